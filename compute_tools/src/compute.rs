@@ -21,7 +21,10 @@ use tracing::{error, info, instrument, warn};
 use utils::id::{TenantId, TimelineId};
 use utils::lsn::Lsn;
 
-use compute_api::responses::{ComputeMetrics, ComputeStatus};
+use compute_api::responses::{
+    ComputeMetrics, ComputeStatus, ConfigurationItem, ConfigurationItemResult,
+    ConfigurationItemStatus,
+};
 use compute_api::spec::{ComputeMode, ComputeSpec};
 use utils::measured_stream::MeasuredReader;
 
@@ -88,6 +91,11 @@ pub struct ComputeState {
     pub last_active: Option<DateTime<Utc>>,
     pub error: Option<String>,
     pub pspec: Option<ParsedSpec>,
+    /// The spec the running Postgres is configured with, while a new one in
+    /// `pspec` is waiting to be applied by [`ComputeNode::reconfigure`].
+    pub applied_spec: Option<ComputeSpec>,
+    /// Outcome of the last reconfiguration, per part of the spec.
+    pub configuration: Vec<ConfigurationItemResult>,
     pub metrics: ComputeMetrics,
 }
 
@@ -99,6 +107,8 @@ impl ComputeState {
             last_active: None,
             error: None,
             pspec: None,
+            applied_spec: None,
+            configuration: Vec::new(),
             metrics: ComputeMetrics::default(),
         }
     }
@@ -123,6 +133,8 @@ pub struct ParsedSpec {
 impl TryFrom<ComputeSpec> for ParsedSpec {
     type Error = String;
     fn try_from(spec: ComputeSpec) -> Result<Self, String> {
+        spec.validate().map_err(|e| e.to_string())?;
+
         // Extract the options from the spec file that are needed to connect to
         // the storage system.
         //
@@ -282,6 +294,39 @@ impl ComputeNode {
         Ok(())
     }
 
+    // Ask the pageserver to read the system catalogs and the relations that the
+    // previous run of this compute read the most, while Postgres is starting up,
+    // so that the first queries don't wait for the layer downloads and the page
+    // reconstruction. The pageserver reads them in the background, and replies
+    // right away. This is best effort, the errors are only logged.
+    fn spawn_pageserver_prewarm(&self, compute_state: &ComputeState) -> Result<()> {
+        let spec = compute_state.pspec.as_ref().expect("spec must be set");
+        if let ComputeMode::Static(_) = spec.spec.mode {
+            // the hottest relations of the branch tip may not be the ones of the LSN
+            return Ok(());
+        }
+
+        let mut config = postgres::Config::from_str(&spec.pageserver_connstr)?;
+        if let Some(storage_auth_token) = &spec.storage_auth_token {
+            config.password(storage_auth_token);
+        }
+        let prewarm_cmd = format!("prewarm {} {}", spec.tenant_id, spec.timeline_id);
+
+        std::thread::Builder::new()
+            .name("pageserver-prewarm".into())
+            .spawn(move || {
+                let start_time = Instant::now();
+                let result = config
+                    .connect(NoTls)
+                    .and_then(|mut client| client.simple_query(&prewarm_cmd));
+                match result {
+                    Ok(_) => info!("pageserver prewarm requested in {:?}", start_time.elapsed()),
+                    Err(e) => warn!("pageserver prewarm failed: {e}"),
+                }
+            })?;
+        Ok(())
+    }
+
     // Get basebackup from the libpq connection to pageserver using `connstr` and
     // unarchive it to `pgdata` directory overriding all its previous content.
     #[instrument(skip_all, fields(%lsn))]
@@ -698,6 +743,7 @@ impl ComputeNode {
         handle_role_deletions(spec, self.connstr.as_str(), &mut client)?;
         handle_grants(spec, self.connstr.as_str())?;
         // handle_extensions(spec, &mut client)?;
+        handle_extension_creation(spec, self.connstr.as_str())?;
 
         // 'Close' connection
         drop(client);
@@ -716,31 +762,70 @@ impl ComputeNode {
 
     /// Similar to `apply_config()`, but does a bit different sequence of operations,
     /// as it's used to reconfigure a previously started and configured Postgres node.
+    ///
+    /// Only the parts of the spec that differ from the applied one are applied, and
+    /// the outcome of each is recorded in [`ComputeState::configuration`]. The parts
+    /// after a failed one are skipped, as they may depend on it.
     #[instrument(skip_all)]
     pub fn reconfigure(&self) -> Result<()> {
-        let spec = self.state.lock().unwrap().pspec.clone().unwrap().spec;
-
-        // Write new config
-        let pgdata_path = Path::new(&self.pgdata);
-        config::write_postgres_conf(&pgdata_path.join("postgresql.conf"), &spec, None)?;
+        let (spec, applied_spec) = {
+            let mut state = self.state.lock().unwrap();
+            (state.pspec.clone().unwrap().spec, state.applied_spec.take())
+        };
 
         let mut client = Client::connect(self.connstr.as_str(), NoTls)?;
-        self.pg_reload_conf(&mut client)?;
-
-        // Proceed with post-startup configuration. Note, that order of operations is important.
         // Disable DDL forwarding because control plane already knows about these roles/databases.
+        client.simple_query("SET neon.forward_ddl = false")?;
+
+        // Note, that order of operations is important.
+        let mut items = vec![ConfigurationItem::Settings];
         if spec.mode == ComputeMode::Primary {
-            client.simple_query("SET neon.forward_ddl = false")?;
-            handle_roles(&spec, &mut client)?;
-            handle_databases(&spec, &mut client)?;
-            handle_role_deletions(&spec, self.connstr.as_str(), &mut client)?;
-            handle_grants(&spec, self.connstr.as_str())?;
-            handle_extensions(&spec, &mut client)?;
+            items.extend([
+                ConfigurationItem::Roles,
+                ConfigurationItem::Databases,
+                ConfigurationItem::RoleDeletions,
+                ConfigurationItem::Grants,
+                ConfigurationItem::Extensions,
+            ]);
         }
+        // For the downloads of the extensions, shared by the items
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut results = Vec::with_capacity(items.len());
+        let mut failed = None;
+        for item in items {
+            let (status, error) = if failed.is_some() {
+                (ConfigurationItemStatus::Skipped, None)
+            } else if !item_changed(item, applied_spec.as_ref(), &spec) {
+                (ConfigurationItemStatus::Unchanged, None)
+            } else {
+                match self.apply_item(item, &spec, &mut client, &rt) {
+                    Ok(()) => (ConfigurationItemStatus::Applied, None),
+                    Err(e) => {
+                        error!("could not apply {item:?}: {e:?}");
+                        let error = format!("{e:#}");
+                        failed = Some(e.context(format!("apply {item:?}")));
+                        (ConfigurationItemStatus::Failed, Some(error))
+                    }
+                }
+            };
+            info!("configuration of {item:?}: {status:?}");
+            results.push(ConfigurationItemResult {
+                item,
+                status,
+                error,
+            });
+        }
+        self.state.lock().unwrap().configuration = results;
 
         // 'Close' connection
         drop(client);
 
+        if let Some(e) = failed {
+            return Err(e);
+        }
+
         let unknown_op = "unknown".to_string();
         let op_id = spec.operation_uuid.as_ref().unwrap_or(&unknown_op);
         info!(
@@ -751,6 +836,34 @@ impl ComputeNode {
         Ok(())
     }
 
+    fn apply_item(
+        &self,
+        item: ConfigurationItem,
+        spec: &ComputeSpec,
+        client: &mut Client,
+        rt: &tokio::runtime::Runtime,
+    ) -> Result<()> {
+        match item {
+            ConfigurationItem::Settings => {
+                let pgdata_path = Path::new(&self.pgdata);
+                config::write_postgres_conf(&pgdata_path.join("postgresql.conf"), spec, None)?;
+                self.pg_reload_conf(client)
+            }
+            ConfigurationItem::Roles => handle_roles(spec, client),
+            ConfigurationItem::Databases => handle_databases(spec, client),
+            ConfigurationItem::RoleDeletions => {
+                handle_role_deletions(spec, self.connstr.as_str(), client)
+            }
+            ConfigurationItem::Grants => handle_grants(spec, self.connstr.as_str()),
+            ConfigurationItem::Extensions => {
+                // The files of the extensions new in the spec aren't there yet
+                rt.block_on(self.download_spec_extensions(spec))?;
+                handle_extensions(spec, client)?;
+                handle_extension_creation(spec, self.connstr.as_str())
+            }
+        }
+    }
+
     #[instrument(skip_all)]
     pub fn start_compute(&self, extension_server_port: u16) -> Result<std::process::Child> {
         let compute_state = self.state.lock().unwrap().clone();
@@ -789,6 +902,8 @@ impl ComputeNode {
 
         self.prepare_pgdata(&compute_state, extension_server_port)?;
 
+        self.spawn_pageserver_prewarm(&compute_state)?;
+
         let start_time = Utc::now();
         let pg = self.start_postgres(pspec.storage_auth_token.clone())?;
 
@@ -942,8 +1057,10 @@ LIMIT 100",
         if let Some(ref ext_remote_storage) = self.ext_remote_storage {
             let pspec = compute_state.pspec.as_ref().expect("spec must be set");
             let spec = &pspec.spec;
-            let custom_ext = spec.custom_extensions.clone().unwrap_or(Vec::new());
+            let mut custom_ext = spec.custom_extensions.clone().unwrap_or(Vec::new());
             info!("custom extensions: {:?}", &custom_ext);
+            // the extensions requested in the spec are enabled too
+            custom_ext.extend(spec.extensions.iter().map(|ext| ext.name.clone()));
 
             let (ext_remote_paths, library_index) = extension_server::get_available_extensions(
                 ext_remote_storage,
@@ -1170,6 +1287,45 @@ LIMIT 100",
                 std::cmp::max(remote_ext_metrics.largest_ext_size, download_size);
             remote_ext_metrics.total_ext_download_size += download_size;
         }
+
+        for download_size in self.download_spec_extensions(spec).await? {
+            remote_ext_metrics.num_ext_downloaded += 1;
+            remote_ext_metrics.largest_ext_size =
+                std::cmp::max(remote_ext_metrics.largest_ext_size, download_size);
+            remote_ext_metrics.total_ext_download_size += download_size;
+        }
         Ok(remote_ext_metrics)
     }
+
+    /// Download the extensions requested in the spec, returning the size of each
+    /// download. Unlike with the libraries, a failed download is an error, as the
+    /// extension was asked for explicitly. The extensions that aren't in the
+    /// remote storage index are assumed to be present locally.
+    pub async fn download_spec_extensions(&self, spec: &ComputeSpec) -> Result<Vec<u64>> {
+        let Some(ext_remote_paths) = self.ext_remote_paths.get() else {
+            return Ok(Vec::new());
+        };
+        let extensions: Vec<&str> = spec
+            .extensions
+            .iter()
+            .map(|ext| ext.name.as_str())
+            .filter(|name| ext_remote_paths.contains_key(*name))
+            .collect();
+        info!(
+            "Downloading extensions requested in the spec: {:?}",
+            &extensions
+        );
+
+        let results = join_all(
+            extensions
+                .iter()
+                .map(|name| self.download_extension(name, false)),
+        )
+        .await;
+        extensions
+            .iter()
+            .zip(results)
+            .map(|(name, result)| result.with_context(|| format!("download extension {name}")))
+            .collect()
+    }
 }
@@ -164,7 +164,11 @@ pub async fn get_available_extensions(
     let mut ext_remote_paths = HashMap::new();
     let mut file_create_tasks = Vec::new();
     for extension in enabled_extensions {
-        let ext_data = &all_extension_data[&extension];
+        let Some(ext_data) = all_extension_data.get(&extension) else {
+            // might be a bundled one
+            warn!("extension {extension} is not in the remote extension index");
+            continue;
+        };
         for (control_file, control_contents) in &ext_data.control_data {
             let extension_name = control_file
                 .strip_suffix(".control")
@@ -270,6 +274,7 @@ pub fn init_remote_storage(remote_ext_config: &str) -> anyhow::Result<GenericRem
         prefix_in_bucket: remote_ext_json.prefix,
         endpoint: remote_ext_json.endpoint,
         concurrency_limit: NonZeroUsize::new(100).expect("100 != 0"),
+        max_requests_per_second: None,
         max_keys_per_list_response: None,
     };
     let config = RemoteStorageConfig {
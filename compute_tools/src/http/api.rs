@@ -30,6 +30,7 @@ fn status_response_from_state(state: &ComputeState) -> ComputeStatusResponse {
         status: state.status,
         last_active: state.last_active,
         error: state.error.clone(),
+        configuration: state.configuration.clone(),
     }
 }
 
@@ -206,7 +207,11 @@ async fn handle_configure_request(
                 );
                 return Err((msg, StatusCode::PRECONDITION_FAILED));
             }
-            state.pspec = Some(parsed_spec);
+            let prev_pspec = state.pspec.replace(parsed_spec);
+            // Only the differences from the spec the compute runs with are applied
+            if state.status == ComputeStatus::Running {
+                state.applied_spec = prev_pspec.map(|pspec| pspec.spec);
+            }
             state.status = ComputeStatus::ConfigurationPending;
             compute.state_changed.notify_all();
             drop(state);
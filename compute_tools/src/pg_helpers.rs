@@ -12,7 +12,7 @@ use notify::{RecursiveMode, Watcher};
 use postgres::{Client, Transaction};
 use tracing::{debug, instrument};
 
-use compute_api::spec::{Database, GenericOption, GenericOptions, PgIdent, Role};
+use compute_api::spec::{Database, Extension, GenericOption, GenericOptions, PgIdent, Role};
 
 const POSTGRES_WAIT_TIMEOUT: Duration = Duration::from_millis(60 * 1000); // milliseconds
 
@@ -173,6 +173,24 @@ impl DatabaseExt for Database {
     }
 }
 
+pub trait ExtensionExt {
+    fn to_create_query(&self) -> String;
+}
+
+impl ExtensionExt for Extension {
+    /// The `CREATE EXTENSION` statement for the extension, in the version
+    /// requested if any.
+    fn to_create_query(&self) -> String {
+        let mut query = format!("CREATE EXTENSION IF NOT EXISTS {}", self.name.pg_quote());
+        if let Some(version) = &self.version {
+            write!(query, " VERSION {}", escape_literal(version))
+                .expect("String is documented to not to error during write operations");
+        }
+
+        query
+    }
+}
+
 /// Generic trait used to provide quoting / encoding for strings used in the
 /// Postgres SQL queries and DATABASE_URL.
 pub trait Escaping {
@@ -2,7 +2,7 @@ use std::fs::File;
 use std::path::Path;
 use std::str::FromStr;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use postgres::config::Config;
 use postgres::{Client, NoTls};
 use reqwest::StatusCode;
@@ -12,7 +12,9 @@ use crate::config;
 use crate::params::PG_HBA_ALL_MD5;
 use crate::pg_helpers::*;
 
-use compute_api::responses::{ControlPlaneComputeStatus, ControlPlaneSpecResponse};
+use compute_api::responses::{
+    ConfigurationItem, ControlPlaneComputeStatus, ControlPlaneSpecResponse,
+};
 use compute_api::spec::{ComputeSpec, Database, PgIdent, Role};
 
 // Do control plane request and return response if any. In case of error it
@@ -131,6 +133,51 @@ pub fn handle_configuration(spec: &ComputeSpec, pgdata_path: &Path) -> Result<()
     Ok(())
 }
 
+/// Whether `item` of `spec` differs from that of `prev`, the spec the running
+/// Postgres was last configured with, if it's known.
+pub fn item_changed(
+    item: ConfigurationItem,
+    prev: Option<&ComputeSpec>,
+    spec: &ComputeSpec,
+) -> bool {
+    let Some(prev) = prev else {
+        return true;
+    };
+    // Renames and deletions of roles and databases come as delta operations,
+    // which have to be applied whatever else changed.
+    let has_delta_ops = spec
+        .delta_operations
+        .as_ref()
+        .is_some_and(|ops| !ops.is_empty());
+    let differ = |part: fn(&ComputeSpec) -> serde_json::Value| part(prev) != part(spec);
+    let settings = |s: &ComputeSpec| {
+        // everything `config::write_postgres_conf` writes
+        serde_json::json!([
+            s.cluster.postgresql_conf,
+            s.cluster.settings,
+            s.pageserver_connstring,
+            s.safekeeper_connstrings,
+            s.tenant_id,
+            s.timeline_id,
+            s.mode,
+        ])
+    };
+    let roles = |s: &ComputeSpec| serde_json::json!(s.cluster.roles);
+    let databases = |s: &ComputeSpec| serde_json::json!(s.cluster.databases);
+
+    match item {
+        ConfigurationItem::Settings => differ(settings),
+        ConfigurationItem::Roles => has_delta_ops || differ(roles),
+        ConfigurationItem::Databases => has_delta_ops || differ(databases),
+        ConfigurationItem::RoleDeletions => has_delta_ops,
+        ConfigurationItem::Grants => has_delta_ops || differ(roles) || differ(databases),
+        // `pg_stat_statements` is created if it's in `shared_preload_libraries`
+        ConfigurationItem::Extensions => {
+            differ(settings) || differ(|s| serde_json::json!(s.extensions))
+        }
+    }
+}
+
 /// Check `pg_hba.conf` and update if needed to allow external connections.
 pub fn update_pg_hba(pgdata_path: &Path) -> Result<()> {
     // XXX: consider making it a part of spec.json
@@ -604,6 +651,27 @@ pub fn handle_grants(spec: &ComputeSpec, connstr: &str) -> Result<()> {
     Ok(())
 }
 
+/// Create the extensions requested in the spec in their databases. Their files
+/// must have been installed with `ComputeNode::download_spec_extensions`.
+#[instrument(skip_all)]
+pub fn handle_extension_creation(spec: &ComputeSpec, connstr: &str) -> Result<()> {
+    for ext in &spec.extensions {
+        let query = ext.to_create_query();
+        for db in &ext.databases {
+            let mut conf = Config::from_str(connstr)?;
+            conf.dbname(db);
+            let mut db_client = conf.connect(NoTls)?;
+
+            info!("creating extension in db {}: {}", db, query);
+            db_client
+                .simple_query(&query)
+                .with_context(|| format!("create extension {} in db {}", ext.name, db))?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Create required system extensions
 #[instrument(skip_all)]
 pub fn handle_extensions(spec: &ComputeSpec, client: &mut Client) -> Result<()> {
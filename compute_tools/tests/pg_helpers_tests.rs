@@ -2,7 +2,7 @@
 mod pg_helpers_tests {
     use std::fs::File;
 
-    use compute_api::spec::{ComputeSpec, GenericOption, GenericOptions, PgIdent};
+    use compute_api::spec::{ComputeSpec, Extension, GenericOption, GenericOptions, PgIdent};
     use compute_tools::pg_helpers::*;
 
     #[test]
@@ -54,6 +54,25 @@ test.escaping = 'here''s a backslash \\ and a quote '' and a double-quote " hoor
         );
     }
 
+    #[test]
+    fn extension_create_query() {
+        let mut ext = Extension {
+            name: "pg_\"trgm".to_string(),
+            databases: vec!["db".to_string()],
+            version: None,
+        };
+        assert_eq!(
+            ext.to_create_query(),
+            "CREATE EXTENSION IF NOT EXISTS \"pg_\"\"trgm\""
+        );
+
+        ext.version = Some("1.6'".to_string());
+        assert_eq!(
+            ext.to_create_query(),
+            "CREATE EXTENSION IF NOT EXISTS \"pg_\"\"trgm\" VERSION '1.6'''"
+        );
+    }
+
     #[test]
     fn ident_pg_quote() {
         let ident: PgIdent = PgIdent::from("\"name\";\\n select 1;");
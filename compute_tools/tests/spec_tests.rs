@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod spec_tests {
+    use std::fs::File;
+
+    use compute_api::responses::ConfigurationItem;
+    use compute_api::spec::{ComputeSpec, Extension, GenericOption};
+    use compute_tools::spec::item_changed;
+
+    fn load_spec() -> ComputeSpec {
+        let file = File::open("../libs/compute_api/tests/cluster_spec.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    fn changed_items(prev: Option<&ComputeSpec>, spec: &ComputeSpec) -> Vec<ConfigurationItem> {
+        [
+            ConfigurationItem::Settings,
+            ConfigurationItem::Roles,
+            ConfigurationItem::Databases,
+            ConfigurationItem::RoleDeletions,
+            ConfigurationItem::Grants,
+            ConfigurationItem::Extensions,
+        ]
+        .into_iter()
+        .filter(|item| item_changed(*item, prev, spec))
+        .collect()
+    }
+
+    #[test]
+    fn spec_delta() {
+        let mut prev = load_spec();
+        prev.delta_operations = None;
+
+        // everything is applied if the applied spec is unknown
+        assert_eq!(changed_items(None, &prev).len(), 6);
+        assert!(changed_items(Some(&prev), &prev).is_empty());
+
+        let mut spec = prev.clone();
+        spec.cluster.settings.as_mut().unwrap().push(GenericOption {
+            name: "work_mem".to_string(),
+            value: Some("64MB".to_string()),
+            vartype: "string".to_string(),
+        });
+        assert_eq!(
+            changed_items(Some(&prev), &spec),
+            [ConfigurationItem::Settings, ConfigurationItem::Extensions]
+        );
+
+        let mut spec = prev.clone();
+        spec.extensions.push(Extension {
+            name: "pg_trgm".to_string(),
+            databases: vec!["db".to_string()],
+            version: None,
+        });
+        assert_eq!(
+            changed_items(Some(&prev), &spec),
+            [ConfigurationItem::Extensions]
+        );
+
+        let mut spec = prev.clone();
+        spec.cluster.roles.pop();
+        assert_eq!(
+            changed_items(Some(&prev), &spec),
+            [ConfigurationItem::Roles, ConfigurationItem::Grants]
+        );
+
+        // delta operations are always applied
+        let spec = load_spec();
+        assert!(spec.delta_operations.is_some());
+        assert_eq!(
+            changed_items(Some(&prev), &spec),
+            [
+                ConfigurationItem::Roles,
+                ConfigurationItem::Databases,
+                ConfigurationItem::RoleDeletions,
+                ConfigurationItem::Grants
+            ]
+        );
+    }
+}
@@ -514,7 +514,8 @@ impl Endpoint {
         // Create spec file
         let spec = ComputeSpec {
             skip_pg_catalog_updates: self.skip_pg_catalog_updates,
-            format_version: 1.0,
+            format_version: 1,
+            format_minor_version: 0,
             operation_uuid: None,
             cluster: Cluster {
                 cluster_id: None, // project ID: not used
@@ -524,6 +525,7 @@ impl Endpoint {
                 databases: vec![],
                 settings: None,
                 postgresql_conf: Some(postgresql_conf),
+                unknown_fields: Default::default(),
             },
             delta_operations: None,
             tenant_id: Some(self.tenant_id),
@@ -533,6 +535,8 @@ impl Endpoint {
             safekeeper_connstrings,
             storage_auth_token: auth_token.clone(),
             custom_extensions: Some(vec![]),
+            extensions: vec![],
+            unknown_fields: Default::default(),
         };
         let spec_path = self.endpoint_path().join("spec.json");
         std::fs::write(spec_path, serde_json::to_string_pretty(&spec)?)?;
@@ -146,6 +146,20 @@ pub struct SafekeeperConf {
     pub remote_storage: Option<String>,
     pub backup_threads: Option<u32>,
     pub auth_enabled: bool,
+    pub max_disk_usage: Option<u64>,
+    /// Push WAL to the pageserver while the broker is unreachable.
+    pub push_wal_to_pageserver: bool,
+    /// Forward the committed WAL to these safekeepers of the environment,
+    /// standing in for the safekeepers of another region.
+    pub wal_forward_to: Vec<NodeId>,
+    /// Verify the WAL of the timelines on startup.
+    pub verify_wal_on_startup: bool,
+    /// Zero out the torn WAL tails found by the verification.
+    pub truncate_torn_wal: bool,
+    /// Offload the in-progress WAL segment at this interval, e.g. "10s".
+    pub partial_backup_interval: Option<String>,
+    /// Fault injection settings as TOML inline table, see `safekeeper --help`.
+    pub chaos: Option<String>,
 }
 
 impl Default for SafekeeperConf {
@@ -159,6 +173,13 @@ impl Default for SafekeeperConf {
             remote_storage: None,
             backup_threads: None,
             auth_enabled: false,
+            max_disk_usage: None,
+            push_wal_to_pageserver: false,
+            wal_forward_to: Vec::new(),
+            verify_wal_on_startup: false,
+            truncate_torn_wal: false,
+            partial_backup_interval: None,
+            chaos: None,
         }
     }
 }
@@ -11,11 +11,16 @@ use std::process::Child;
 use std::{io, result};
 
 use anyhow::Context;
+use postgres_backend::AuthType;
 use postgres_connection::PgConnectionConfig;
 use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::{IntoUrl, Method};
 use thiserror::Error;
-use utils::{http::error::HttpErrorBody, id::NodeId};
+use utils::{
+    auth::{Claims, Scope},
+    http::error::HttpErrorBody,
+    id::NodeId,
+};
 
 use crate::{
     background_process,
@@ -159,6 +164,59 @@ impl SafekeeperNode {
             args.extend(["--remote-storage".to_owned(), remote_storage.clone()]);
         }
 
+        if let Some(ref partial_backup_interval) = self.conf.partial_backup_interval {
+            args.extend([
+                "--partial-backup-interval".to_owned(),
+                partial_backup_interval.clone(),
+            ]);
+        }
+
+        if let Some(ref chaos) = self.conf.chaos {
+            args.extend(["--chaos".to_owned(), chaos.clone()]);
+        }
+
+        if let Some(max_disk_usage) = self.conf.max_disk_usage {
+            args.extend(["--max-disk-usage".to_owned(), max_disk_usage.to_string()]);
+        }
+
+        let mut env_vars = Vec::new();
+        if self.conf.push_wal_to_pageserver {
+            args.push("--wal-push".to_owned());
+            if self.env.pageserver.pg_auth_type == AuthType::NeonJWT {
+                let token = self
+                    .env
+                    .generate_auth_token(&Claims::new(None, Scope::PageServerApi))?;
+                env_vars.push(("PAGESERVER_AUTH_TOKEN".to_owned(), token));
+            }
+        }
+
+        if !self.conf.wal_forward_to.is_empty() {
+            let mut targets = Vec::new();
+            for target_id in &self.conf.wal_forward_to {
+                let target = self
+                    .env
+                    .safekeepers
+                    .iter()
+                    .find(|sk| sk.id == *target_id)
+                    .with_context(|| format!("no safekeeper {target_id} to forward WAL to"))?;
+                targets.push(format!("{}=127.0.0.1:{}", target.id, target.pg_port));
+            }
+            args.extend(["--wal-forward-to".to_owned(), targets.join(",")]);
+            if self.env.safekeepers.iter().any(|sk| sk.auth_enabled) {
+                let token = self
+                    .env
+                    .generate_auth_token(&Claims::new(None, Scope::SafekeeperData))?;
+                env_vars.push(("SAFEKEEPER_AUTH_TOKEN".to_owned(), token));
+            }
+        }
+
+        if self.conf.verify_wal_on_startup {
+            args.push("--verify-wal-on-startup".to_owned());
+        }
+        if self.conf.truncate_torn_wal {
+            args.push("--truncate-torn-wal".to_owned());
+        }
+
         let key_path = self.env.base_data_dir.join("auth_public_key.pem");
         if self.conf.auth_enabled {
             args.extend([
@@ -177,7 +235,7 @@ impl SafekeeperNode {
             &datadir,
             &self.env.safekeeper_bin(),
             &args,
-            [],
+            env_vars,
             background_process::InitialPidFile::Expect(&self.pid_file()),
             || match self.check_status() {
                 Ok(()) => Ok(true),
@@ -0,0 +1,19 @@
+//! Decodes arbitrary bytes as a WAL record, the way WAL ingest does.
+//!
+//! The first byte picks the Postgres version, the rest is the record. Malformed
+//! records must be rejected with an error.
+
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use pageserver::walrecord::{decode_wal_record, DecodedWALRecord};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&version, record)) = data.split_first() else {
+        return;
+    };
+    let pg_version = if version % 2 == 0 { 14 } else { 15 };
+    let mut decoded = DecodedWALRecord::default();
+    let _ = decode_wal_record(Bytes::copy_from_slice(record), &mut decoded, pg_version);
+});
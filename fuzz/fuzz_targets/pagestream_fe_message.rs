@@ -0,0 +1,18 @@
+//! Parses arbitrary bytes as a pagestream request from a compute.
+//!
+//! Malformed requests must be rejected with an error, and the ones which parse
+//! must survive a serialization round trip.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pageserver_api::models::PagestreamFeMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(msg) = PagestreamFeMessage::parse(&mut &data[..]) else {
+        return;
+    };
+    let bytes = msg.serialize();
+    let reparsed = PagestreamFeMessage::parse(&mut &bytes[..]).unwrap();
+    assert_eq!(msg, reparsed);
+});
@@ -21,6 +21,9 @@ pub struct ComputeStatusResponse {
     #[serde(serialize_with = "rfc3339_serialize")]
     pub last_active: Option<DateTime<Utc>>,
     pub error: Option<String>,
+    /// Outcome of the last live reconfiguration, per part of the spec.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub configuration: Vec<ConfigurationItemResult>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -54,6 +57,40 @@ pub enum ComputeStatus {
     Failed,
 }
 
+/// A part of the spec that a live reconfiguration applies to the running
+/// Postgres, in the order they are applied.
+#[derive(Serialize, Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigurationItem {
+    // `postgresql.conf`, reloaded without a restart. The settings that
+    // require one only take effect on the next start.
+    Settings,
+    Roles,
+    Databases,
+    RoleDeletions,
+    Grants,
+    Extensions,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigurationItemStatus {
+    Applied,
+    // Same as in the previous spec, so left as it is.
+    Unchanged,
+    Failed,
+    // Not applied because an earlier item failed.
+    Skipped,
+}
+
+#[derive(Serialize, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ConfigurationItemResult {
+    pub item: ConfigurationItem,
+    pub status: ConfigurationItemStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 fn rfc3339_serialize<S>(x: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -3,7 +3,22 @@
 //! The spec.json file is used to pass information to 'compute_ctl'. It contains
 //! all the information needed to start up the right version of PostgreSQL,
 //! and connect it to the storage nodes.
-use serde::{Deserialize, Serialize};
+//!
+//! The spec has a major version, `format_version`, and a minor version,
+//! `format_minor_version`, both integers. The compute images and the control
+//! plane are rolled out independently, so a spec may come from a control plane
+//! that's older or newer than this `compute_ctl`:
+//! - a new minor version only adds optional fields, which an older `compute_ctl`
+//!   ignores, so any minor version of [`SPEC_FORMAT_MAJOR_VERSION`] is accepted;
+//! - a new major version changes the meaning of existing fields, so the specs of
+//!   other major versions are rejected.
+//!
+//! A field this `compute_ctl` doesn't know in a spec of a minor version it knows
+//! is a mistake, e.g. a typo, and is rejected as well, see [`ComputeSpec::validate`].
+use std::collections::BTreeMap;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use utils::id::{TenantId, TimelineId};
 use utils::lsn::Lsn;
@@ -12,12 +27,39 @@ use utils::lsn::Lsn;
 /// intended to be used for DB / role names.
 pub type PgIdent = String;
 
+/// The major version of the spec format this `compute_ctl` understands.
+pub const SPEC_FORMAT_MAJOR_VERSION: u32 = 1;
+
+/// The latest minor version of the spec format whose fields are all known.
+pub const SPEC_FORMAT_MINOR_VERSION: u32 = 1;
+
+/// Fields that the control plane sends, but `compute_ctl` has no use for.
+const IGNORED_FIELDS: &[&str] = &["timestamp"];
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum SpecValidationError {
+    #[error("incompatible spec format version {0}, expected {SPEC_FORMAT_MAJOR_VERSION}")]
+    IncompatibleVersion(u32),
+
+    #[error(
+        "unknown fields in spec format version {SPEC_FORMAT_MAJOR_VERSION}.{minor_version}: {}",
+        fields.join(", ")
+    )]
+    UnknownFields {
+        minor_version: u32,
+        fields: Vec<String>,
+    },
+}
+
 /// Cluster spec or configuration represented as an optional number of
 /// delta operations + final cluster state description.
 #[serde_as]
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ComputeSpec {
-    pub format_version: f32,
+    #[serde(deserialize_with = "deserialize_format_version")]
+    pub format_version: u32,
+    #[serde(default)]
+    pub format_minor_version: u32,
 
     // The control plane also includes a 'timestamp' field in the JSON document,
     // but we don't use it for anything. Serde will ignore missing fields when
@@ -63,6 +105,60 @@ pub struct ComputeSpec {
 
     // list of prefixes to search for custom extensions in remote extension storage
     pub custom_extensions: Option<Vec<String>>,
+
+    /// Extensions to download from the remote extension storage on startup,
+    /// before Postgres starts, and to create in the databases. Since minor version 1.
+    #[serde(default)]
+    pub extensions: Vec<Extension>,
+
+    /// Fields not known to this `compute_ctl`, see [`ComputeSpec::validate`].
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl ComputeSpec {
+    /// Checks that this `compute_ctl` can apply the spec: that it's of a compatible
+    /// format version, and that it has no unknown fields unless it's of a newer
+    /// minor version.
+    pub fn validate(&self) -> Result<(), SpecValidationError> {
+        if self.format_version != SPEC_FORMAT_MAJOR_VERSION {
+            return Err(SpecValidationError::IncompatibleVersion(
+                self.format_version,
+            ));
+        }
+        if self.format_minor_version > SPEC_FORMAT_MINOR_VERSION {
+            return Ok(());
+        }
+
+        let unknown_fields = self.unknown_fields.keys();
+        let cluster_fields = self.cluster.unknown_fields.keys();
+        let fields: Vec<String> = unknown_fields
+            .filter(|field| !IGNORED_FIELDS.contains(&field.as_str()))
+            .cloned()
+            .chain(cluster_fields.map(|field| format!("cluster.{field}")))
+            .collect();
+        if !fields.is_empty() {
+            return Err(SpecValidationError::UnknownFields {
+                minor_version: self.format_minor_version,
+                fields,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Older control planes send the major version as a float, e.g. `1.0`, which is
+/// accepted as long as it's a whole number.
+fn deserialize_format_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = serde_json::Number::deserialize(deserializer)?;
+    version
+        .as_u64()
+        .or_else(|| {
+            let version = version.as_f64()?;
+            (version.fract() == 0.0 && version >= 0.0).then_some(version as u64)
+        })
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or_else(|| D::Error::custom(format!("invalid spec format version {version}")))
 }
 
 #[serde_as]
@@ -94,6 +190,23 @@ pub struct Cluster {
 
     /// Additional settings that will be appended to the 'postgresql.conf' file.
     pub settings: GenericOptions,
+
+    /// Fields not known to this `compute_ctl`, see [`ComputeSpec::validate`].
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// An extension requested in the spec, see [`ComputeSpec::extensions`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Extension {
+    /// Name of the extension, as in its control file.
+    pub name: String,
+    /// Databases to run `CREATE EXTENSION` in, if any. Otherwise, the extension
+    /// is only installed, for the users to create it.
+    #[serde(default)]
+    pub databases: Vec<PgIdent>,
+    /// Version to create, the default version of the control file if not set.
+    pub version: Option<String>,
 }
 
 /// Single cluster state changing operation that could not be represented as
@@ -161,4 +274,54 @@ mod tests {
         ob.insert("unknown_field_123123123".into(), "hello".into());
         let _spec: ComputeSpec = serde_json::from_value(json).unwrap();
     }
+
+    #[test]
+    fn validate() {
+        let file = File::open("tests/cluster_spec.json").unwrap();
+        let json: serde_json::Value = serde_json::from_reader(file).unwrap();
+        let spec = |f: &dyn Fn(&mut serde_json::Map<String, serde_json::Value>)| {
+            let mut json = json.clone();
+            f(json.as_object_mut().unwrap());
+            serde_json::from_value::<ComputeSpec>(json).unwrap()
+        };
+
+        spec(&|_| {}).validate().unwrap();
+
+        // unknown fields are rejected, unless the spec is of a newer minor version
+        let with_unknown_fields = |ob: &mut serde_json::Map<String, serde_json::Value>| {
+            ob.insert("unknown_field".into(), "hello".into());
+            let cluster = ob["cluster"].as_object_mut().unwrap();
+            cluster.insert("other_field".into(), 1.into());
+        };
+        assert_eq!(
+            spec(&with_unknown_fields).validate(),
+            Err(SpecValidationError::UnknownFields {
+                minor_version: 0,
+                fields: vec!["unknown_field".into(), "cluster.other_field".into()],
+            })
+        );
+        spec(&|ob| {
+            with_unknown_fields(ob);
+            ob.insert("format_minor_version".into(), 3.into());
+        })
+        .validate()
+        .unwrap();
+
+        // other major versions are rejected
+        assert_eq!(
+            spec(&|ob| {
+                ob.insert("format_version".into(), 2.into());
+            })
+            .validate(),
+            Err(SpecValidationError::IncompatibleVersion(2))
+        );
+
+        // the major version is an integer, but older control planes send it as `1.0`
+        assert_eq!(spec(&|_| {}).format_version, 1);
+        for version in [1.5, -1.0] {
+            let mut json = json.clone();
+            json["format_version"] = version.into();
+            serde_json::from_value::<ComputeSpec>(json).unwrap_err();
+        }
+    }
 }
@@ -2,7 +2,7 @@
 //! make sure that we use the same dep version everywhere.
 //! Otherwise, we might not see all metrics registered via
 //! a default registry.
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use prometheus::core::{AtomicU64, Collector, GenericGauge, GenericGaugeVec};
 pub use prometheus::opts;
 pub use prometheus::register;
@@ -56,6 +56,19 @@ pub fn register_internal(c: Box<dyn Collector>) -> Result<()> {
     INTERNAL_REGISTRY.register(c)
 }
 
+/// Labels added to every metric by [`gather`], see [`set_common_labels`].
+static COMMON_LABELS: OnceCell<Vec<(String, String)>> = OnceCell::new();
+
+/// Sets labels added to every metric of the process when it's gathered, e.g. the
+/// region the node runs in, without changing the metrics themselves. A metric that
+/// has a label of the same name keeps its own. This must be called at most once,
+/// at startup.
+pub fn set_common_labels(labels: Vec<(String, String)>) {
+    if COMMON_LABELS.set(labels).is_err() {
+        panic!("common metric labels already set");
+    }
+}
+
 /// Gathers all Prometheus metrics and records the I/O stats just before that.
 ///
 /// Metrics gathering is a relatively simple and standalone operation, so
@@ -65,9 +78,31 @@ pub fn gather() -> Vec<prometheus::proto::MetricFamily> {
     let mut mfs = prometheus::gather();
     let mut internal_mfs = INTERNAL_REGISTRY.gather();
     mfs.append(&mut internal_mfs);
+    if let Some(labels) = COMMON_LABELS.get() {
+        add_labels(&mut mfs, labels);
+    }
     mfs
 }
 
+fn add_labels(mfs: &mut [prometheus::proto::MetricFamily], labels: &[(String, String)]) {
+    for mf in mfs {
+        for metric in mf.mut_metric().iter_mut() {
+            let mut pairs = metric.take_label();
+            for (name, value) in labels {
+                if pairs.iter().any(|pair| pair.get_name() == name) {
+                    continue;
+                }
+                let mut pair = prometheus::proto::LabelPair::default();
+                pair.set_name(name.clone());
+                pair.set_value(value.clone());
+                pairs.push(pair);
+            }
+            pairs.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            metric.set_label(pairs);
+        }
+    }
+}
+
 static DISK_IO_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "libmetrics_disk_io_bytes_total",
@@ -131,3 +166,43 @@ fn get_rusage_stats() -> libc::rusage {
         rusage.assume_init()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_labels() {
+        let registry = Registry::new();
+        let counter = IntCounterVec::new(opts!("requests", "help"), &["region", "kind"]).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.with_label_values(&["7", "get"]).inc();
+        let gauge = IntGauge::new("size", "help").unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+
+        let mut mfs = registry.gather();
+        let labels = [("node_region".to_string(), "1".to_string())];
+        add_labels(&mut mfs, &labels);
+
+        let labels_of = |mf: &proto::MetricFamily| {
+            mf.get_metric()[0]
+                .get_label()
+                .iter()
+                .map(|pair| format!("{}={}", pair.get_name(), pair.get_value()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            labels_of(&mfs[0]),
+            ["kind=get", "node_region=1", "region=7"]
+        );
+        assert_eq!(labels_of(&mfs[1]), ["node_region=1"]);
+
+        // the metric's own label wins
+        let labels = [("kind".to_string(), "put".to_string())];
+        add_labels(&mut mfs, &labels);
+        assert_eq!(
+            labels_of(&mfs[0]),
+            ["kind=get", "node_region=1", "region=7"]
+        );
+    }
+}
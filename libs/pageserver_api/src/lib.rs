@@ -1,6 +1,7 @@
 use const_format::formatcp;
 
 /// Public API types
+pub mod lsn_token;
 pub mod models;
 pub mod reltag;
 
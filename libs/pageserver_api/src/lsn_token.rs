@@ -0,0 +1,116 @@
+//! A token of the LSNs of a compute's writes, per region, for read-your-writes
+//! across regions.
+//!
+//! After a commit, a compute hands out the token of the LSNs its transaction wrote
+//! at, and read at, in each region, with `neon_lsn_token()`. A compute of another
+//! region presents it when it opens its `pagestream` connection, with
+//! `lsn_token=<token>`, and the pageserver doesn't serve it until its timelines have
+//! ingested the WAL up to those LSNs, so that it sees the writes.
+//!
+//! The token is the list of `<region>:<lsn>`, separated by commas, e.g.
+//! `0:0/16B5A50,1:0/1698C48`. The regions are parsed like everywhere else, so the
+//! names of the registry are accepted too.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+use utils::id::RegionId;
+use utils::lsn::Lsn;
+use utils::region;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LsnToken {
+    lsns: BTreeMap<RegionId, Lsn>,
+}
+
+impl LsnToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the LSN of a region, keeping the latest one if it's already there.
+    pub fn add(&mut self, region: RegionId, lsn: Lsn) {
+        let entry = self.lsns.entry(region).or_insert(lsn);
+        *entry = (*entry).max(lsn);
+    }
+
+    /// Adds the LSNs of `other`, keeping the latest one of each region.
+    pub fn merge(&mut self, other: &LsnToken) {
+        for (region, lsn) in other.iter() {
+            self.add(region, lsn);
+        }
+    }
+
+    pub fn get(&self, region: RegionId) -> Option<Lsn> {
+        self.lsns.get(&region).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lsns.is_empty()
+    }
+
+    /// The regions and their LSNs, by region.
+    pub fn iter(&self) -> impl Iterator<Item = (RegionId, Lsn)> + '_ {
+        self.lsns.iter().map(|(region, lsn)| (*region, *lsn))
+    }
+}
+
+impl fmt::Display for LsnToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (region, lsn)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{region}:{lsn}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LsnToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut token = LsnToken::new();
+        for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+            let (region, lsn) = entry.split_once(':').with_context(|| {
+                format!("invalid LSN token entry '{entry}', expected <region>:<lsn>")
+            })?;
+            let region = region::registry().parse(region)?;
+            let lsn =
+                Lsn::from_str(lsn).with_context(|| format!("invalid LSN '{lsn}' in LSN token"))?;
+            token.add(region, lsn);
+        }
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let token: LsnToken = "1:0/1698C48,0:0/16B5A50".parse().unwrap();
+        assert_eq!(token.get(RegionId(0)), Some(Lsn(0x16B5A50)));
+        assert_eq!(token.get(RegionId(1)), Some(Lsn(0x1698C48)));
+        assert_eq!(token.get(RegionId(2)), None);
+        assert_eq!(token.to_string(), "0:0/16B5A50,1:0/1698C48");
+
+        assert!("".parse::<LsnToken>().unwrap().is_empty());
+        "1".parse::<LsnToken>().unwrap_err();
+        "1:16B5A50".parse::<LsnToken>().unwrap_err();
+        "x y:0/16B5A50".parse::<LsnToken>().unwrap_err();
+    }
+
+    #[test]
+    fn keeps_the_latest_lsn() {
+        let mut token: LsnToken = "0:0/2000,0:0/1000,1:0/1000".parse().unwrap();
+        assert_eq!(token.get(RegionId(0)), Some(Lsn(0x2000)));
+
+        token.merge(&"1:0/3000,2:0/500".parse().unwrap());
+        assert_eq!(token.to_string(), "0:0/2000,1:0/3000,2:0/500");
+    }
+}
@@ -1,7 +1,9 @@
 use std::{
-    collections::HashMap,
-    num::{NonZeroU64, NonZeroUsize},
-    time::SystemTime,
+    collections::{BTreeMap, HashMap},
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    ops::AddAssign,
+    str::FromStr,
+    time::{Duration, SystemTime},
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
@@ -17,7 +19,7 @@ use utils::{
 
 use crate::reltag::{RelTag, SlruKind};
 use anyhow::bail;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 /// The state of a tenant in this pageserver.
 ///
@@ -167,13 +169,21 @@ pub enum TimelineState {
     /// The timeline is fully operational.
     /// It can be queried, and the walreceiver connection loop is running.
     Active,
-    /// The timeline was previously Loading or Active but is shutting down.
+    /// The timeline was suspended through the management API, until it's resumed back
+    /// into Active: the walreceiver doesn't run, the background jobs skip it, and it's
+    /// not served to the computes, but its branches can still read through it. The
+    /// state isn't persisted, the timeline is activated when its tenant is loaded.
+    Suspended,
+    /// The timeline was previously Loading, Active or Suspended but is shutting down.
     /// It cannot transition back into any other state.
     Stopping,
     /// The timeline is broken and not operational (previous states: Loading or Active).
     Broken { reason: String, backtrace: String },
 }
 
+mod builder;
+pub use builder::{TenantConfigBuilder, TimelineCreateRequestBuilder};
+
 #[serde_as]
 #[derive(Serialize, Deserialize)]
 pub struct TimelineCreateRequest {
@@ -187,8 +197,47 @@ pub struct TimelineCreateRequest {
     pub ancestor_start_lsn: Option<Lsn>,
     pub pg_version: Option<u32>,
     #[serde(default)]
-    #[serde_as(as = "Option<DisplayFromStr>")]
     pub region_id: Option<RegionId>,
+    #[serde(default)]
+    pub labels: Labels,
+    /// Regions to branch the new timeline into right away, each at the last record
+    /// LSN of the new timeline, besides the region of the timeline itself.
+    #[serde(default)]
+    pub materialize_in_regions: Vec<RegionId>, // Remotexact
+}
+
+/// Key-value labels of a tenant or a timeline. The pageserver doesn't interpret them.
+pub type Labels = BTreeMap<String, String>;
+
+/// Selects tenants or timelines by their labels, in the `label` query parameter of the list endpoints.
+///
+/// Written as comma-separated `key=value` pairs, all of which have to match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSelector(Vec<(String, String)>);
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs = Vec::new();
+        for pair in s.split(',') {
+            match pair.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    pairs.push((key.to_string(), value.to_string()))
+                }
+                _ => bail!("invalid label selector '{pair}', expected key=value"),
+            }
+        }
+        Ok(LabelSelector(pairs))
+    }
 }
 
 #[serde_as]
@@ -219,6 +268,7 @@ pub struct TenantConfig {
     pub gc_horizon: Option<u64>,
     pub gc_period: Option<String>,
     pub image_creation_threshold: Option<usize>,
+    pub slru_image_creation_threshold: Option<usize>,
     pub pitr_interval: Option<String>,
     pub walreceiver_connect_timeout: Option<String>,
     pub lagging_wal_timeout: Option<String>,
@@ -232,6 +282,13 @@ pub struct TenantConfig {
     pub min_resident_size_override: Option<u64>,
     pub evictions_low_residence_duration_metric_threshold: Option<String>,
     pub gc_feedback: Option<bool>,
+    pub layer_compression_level: Option<i32>,
+    pub get_page_weight: Option<NonZeroU32>,
+    pub logical_size_reconcile_period: Option<String>,
+    pub pagestream_rate_limit: Option<NonZeroU32>,
+    pub wait_lsn_timeout: Option<String>,
+    /// Replaces the labels of the tenant. Not a setting, the labels are kept when omitted.
+    pub labels: Option<Labels>,
 }
 
 #[serde_as]
@@ -244,6 +301,59 @@ pub struct StatusResponse {
     pub id: NodeId,
 }
 
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    pub id: NodeId,
+    pub ready: bool,
+    /// What the pageserver is still waiting for, or what is failing, before it is ready.
+    pub pending: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReloadConfigResponse {
+    /// Changed settings that are in effect now.
+    pub applied: Vec<ConfigChange>,
+    /// Changed settings that only take effect after a restart.
+    pub restart_required: Vec<ConfigChange>,
+}
+
+/// The background tasks of a class, as scheduled across the pageserver.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackgroundTaskClassInfo {
+    pub class: String,
+    /// How many tasks of the class run at once.
+    pub limit: usize,
+    pub running: Vec<RunningBackgroundTask>,
+    /// In the order they will start.
+    pub waiting: Vec<WaitingBackgroundTask>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunningBackgroundTask {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    pub running_for_ms: u64,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaitingBackgroundTask {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    /// The tasks with a higher priority start first. For compaction, it's the number
+    /// of L0 delta layers of the tenant.
+    pub priority: u64,
+    pub waiting_for_ms: u64,
+}
+
 impl TenantCreateRequest {
     pub fn new(new_tenant_id: TenantId) -> TenantCreateRequest {
         TenantCreateRequest {
@@ -282,6 +392,7 @@ impl TenantConfigRequest {
             gc_horizon: None,
             gc_period: None,
             image_creation_threshold: None,
+            slru_image_creation_threshold: None,
             pitr_interval: None,
             walreceiver_connect_timeout: None,
             lagging_wal_timeout: None,
@@ -291,11 +402,28 @@ impl TenantConfigRequest {
             min_resident_size_override: None,
             evictions_low_residence_duration_metric_threshold: None,
             gc_feedback: None,
+            layer_compression_level: None,
+            get_page_weight: None,
+            logical_size_reconcile_period: None,
+            pagestream_rate_limit: None,
+            wait_lsn_timeout: None,
+            labels: None,
         };
         TenantConfigRequest { tenant_id, config }
     }
 }
 
+/// Overrides of the tenant config for a single timeline, replacing the previous ones.
+/// The unset fields fall back to the tenant config.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TimelineConfigRequest {
+    #[serde(default)]
+    pub checkpoint_distance: Option<u64>,
+    #[serde(default)]
+    pub checkpoint_timeout: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TenantAttachRequest {
     pub config: TenantAttachConfig,
@@ -338,13 +466,41 @@ pub struct TenantInfo {
     /// If a layer is present in both local FS and S3, it counts only once.
     pub current_physical_size: Option<u64>, // physical size is only included in `tenant_status` endpoint
     pub attachment_status: TenantAttachmentStatus,
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+    /// The region of the root timelines of the tenant, which the timelines of the
+    /// other regions are branched from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_region: Option<RegionId>, // Remotexact
+    /// The regions the tenant has timelines in on this pageserver.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<RegionId>, // Remotexact
+    /// Whether the tenant is being attached, and its layers downloaded.
+    #[serde(default)]
+    pub has_in_progress_downloads: bool,
+    /// The download of the layers at attach, if the pageserver downloads them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attach_download: Option<AttachDownloadProgress>,
+}
+
+/// The progress of the download of the layers of a tenant when it's attached,
+/// see `attach_download_layers` in the pageserver config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachDownloadProgress {
+    pub total_layers: u64,
+    pub total_bytes: u64,
+    /// Including the ones already downloaded by an earlier attempt of the attach,
+    /// which was interrupted by a restart of the pageserver.
+    pub downloaded_layers: u64,
+    pub downloaded_bytes: u64,
+    /// The layers found already downloaded when the attach started.
+    pub resumed_layers: u64,
 }
 
 /// This represents the output of the "timeline_detail" and "timeline_list" API calls.
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TimelineInfo {
-    #[serde_as(as = "DisplayFromStr")]
     pub region_id: RegionId, // Remotexact
     #[serde_as(as = "DisplayFromStr")]
     pub tenant_id: TenantId,
@@ -366,10 +522,23 @@ pub struct TimelineInfo {
     #[serde_as(as = "DisplayFromStr")]
     pub remote_consistent_lsn: Lsn,
     pub current_logical_size: Option<u64>, // is None when timeline is Unloaded
+    /// False until the initial logical size calculation finishes; until then,
+    /// `current_logical_size` only counts the changes since the timeline was loaded.
+    #[serde(default)]
+    pub current_logical_size_is_exact: bool,
+    /// The LSN at which `current_logical_size` was last calculated from the layers.
+    /// It's maintained from the ingested WAL since, so how far this is behind
+    /// `last_record_lsn` tells how much it could have drifted. None if it wasn't
+    /// calculated since the timeline was loaded.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub current_logical_size_calculated_at: Option<Lsn>,
     /// Sum of the size of all layer files.
     /// If a layer is present in both local FS and S3, it counts only once.
     pub current_physical_size: Option<u64>, // is None when timeline is Unloaded
     pub current_logical_size_non_incremental: Option<u64>,
+    /// Total size of the SLRU segments at last_record_lsn.
+    pub current_slru_size: Option<u64>, // is None when timeline is Unloaded
 
     pub timeline_dir_layer_file_size_sum: Option<u64>,
 
@@ -381,6 +550,198 @@ pub struct TimelineInfo {
     pub pg_version: u32,
 
     pub state: TimelineState,
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+    /// The timelines branched from this one into other regions along with its
+    /// creation, see [`TimelineCreateRequest::materialize_in_regions`]. Only set
+    /// in the response of the creation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_timelines: Vec<RegionTimeline>, // Remotexact
+}
+
+/// A timeline of a region.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RegionTimeline {
+    pub region_id: RegionId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: TimelineId,
+}
+
+/// Whether a compute writes to its timeline, or is a hot standby which replays
+/// the WAL of the one that does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComputeMode {
+    ReadWrite,
+    ReadOnly,
+}
+
+/// A compute reading a timeline through the page service, see the `computes`
+/// endpoint.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachedComputeInfo {
+    /// Address of the page service connection of the compute.
+    pub peer_addr: String,
+    pub mode: ComputeMode,
+    #[serde(rename = "connected_at_millis_since_epoch")]
+    #[serde_as(as = "serde_with::TimestampMilliSeconds")]
+    pub connected_at: SystemTime,
+    /// The LSN the last request of the compute was served at, invalid until its
+    /// first request.
+    #[serde_as(as = "DisplayFromStr")]
+    pub read_lsn: Lsn,
+    pub requests: u64,
+}
+
+/// The outcome of a transaction across the regions, from the transaction resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XactOutcome {
+    InProgress,
+    Committed,
+    Aborted,
+}
+
+impl XactOutcome {
+    /// Whether the outcome won't change anymore.
+    pub fn is_final(&self) -> bool {
+        !matches!(self, XactOutcome::InProgress)
+    }
+}
+
+/// A request of the outcomes of transactions of a region of a timeline, to the
+/// `xact_outcomes` endpoint of the transaction resolver.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct XactOutcomesRequest {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: TimelineId,
+    pub region: RegionId,
+    pub xids: Vec<u32>,
+}
+
+/// The response to a [`XactOutcomesRequest`], with the outcomes in the order of the
+/// requested xids.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct XactOutcomesResponse {
+    pub outcomes: Vec<XactOutcome>,
+}
+
+/// A transaction of a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GlobalXid {
+    pub region: RegionId,
+    pub xid: u32,
+}
+
+/// `waiter` waits for a lock held by `holder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitEdge {
+    pub waiter: GlobalXid,
+    pub holder: GlobalXid,
+}
+
+/// The wait-for edges of the transactions of a region, which replace the ones it
+/// reported before, see the `wait_graph` endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaitEdgesReport {
+    pub edges: Vec<WaitEdge>,
+}
+
+/// The wait-for graph of the transactions of a tenant across the regions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaitGraphInfo {
+    pub edges: Vec<WaitEdge>,
+    /// The cycles of the graph, each from its smallest transaction, which are
+    /// deadlocks unless an edge of the cycle went away since it was reported.
+    pub deadlocks: Vec<Vec<GlobalXid>>,
+}
+
+/// The changes made on a timeline between two LSNs, see the `diff` endpoint.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineDiff {
+    /// The changes made after this LSN are included.
+    #[serde_as(as = "DisplayFromStr")]
+    pub from_lsn: Lsn,
+    /// The changes made up to and including this LSN are included.
+    #[serde_as(as = "DisplayFromStr")]
+    pub to_lsn: Lsn,
+    pub relations: Vec<RelationDiff>,
+    /// Changed keys that don't belong to a relation: SLRUs, directories, and other metadata.
+    /// Adjacent keys are merged into a range.
+    pub key_ranges: Vec<KeyRangeDiff>,
+    pub total_records: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelationDiff {
+    pub rel: RelTag,
+    pub changed_blocks: u64,
+    pub size_changed: bool,
+    /// The number of WAL records and page images written to the relation.
+    pub records: u64,
+    /// The on-disk size of the records, an estimate of the size of an incremental backup.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRangeDiff {
+    pub key_start: String,
+    pub key_end: String,
+    pub changed_keys: u64,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+/// What the `top_tenants` debug endpoint ranks the tenants by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopTenantsBy {
+    /// GetPage requests served over the window.
+    Reads,
+    /// WAL bytes ingested over the window.
+    Ingest,
+    /// The current size of the layer files.
+    PhysicalSize,
+}
+
+impl FromStr for TopTenantsBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "reads" => TopTenantsBy::Reads,
+            "ingest" => TopTenantsBy::Ingest,
+            "physical_size" => TopTenantsBy::PhysicalSize,
+            _ => bail!("invalid value '{s}', expected one of reads, ingest, physical_size"),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopTenants {
+    pub by: TopTenantsBy,
+    /// The length of the window the reads and the ingest are counted over.
+    /// Shorter than the full window for a while after startup.
+    pub window_secs: u64,
+    /// The heaviest tenants first.
+    pub tenants: Vec<TopTenantInfo>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopTenantInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: TenantId,
+    pub reads: u64,
+    pub ingested_bytes: u64,
+    pub physical_size: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -550,6 +911,85 @@ pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
 }
 
+///
+/// Result of performing GC
+///
+#[serde_as]
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct GcResult {
+    pub layers_total: u64,
+    pub layers_needed_by_cutoff: u64,
+    pub layers_needed_by_pitr: u64,
+    pub layers_needed_by_branches: u64,
+    pub layers_not_updated: u64,
+    pub layers_removed: u64, // # of layer files removed because they have been made obsolete by newer ondisk files.
+
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub elapsed: Duration,
+}
+
+impl AddAssign for GcResult {
+    fn add_assign(&mut self, other: Self) {
+        self.layers_total += other.layers_total;
+        self.layers_needed_by_pitr += other.layers_needed_by_pitr;
+        self.layers_needed_by_cutoff += other.layers_needed_by_cutoff;
+        self.layers_needed_by_branches += other.layers_needed_by_branches;
+        self.layers_not_updated += other.layers_not_updated;
+        self.layers_removed += other.layers_removed;
+
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// This represents the output of the "layer repair" API call.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LayerRepairInfo {
+    pub outcome: LayerRepairOutcome,
+    /// Blocks of the local layer file that failed checksum verification.
+    pub corrupt_blocks: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum LayerRepairOutcome {
+    /// The local layer file passed verification, nothing was done.
+    Healthy,
+    /// The layer file was not present locally, and was downloaded.
+    Downloaded,
+    /// The local layer file was corrupt, and was replaced by the remote copy.
+    Redownloaded,
+    /// The local layer file was corrupt, and was rebuilt from the other layers.
+    Rematerialized,
+}
+
+/// This represents the output of the "page_cache_info" API call.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PageCacheInfo {
+    /// Number of pages currently in use.
+    pub size: usize,
+    /// Number of pages allocated at startup, the upper limit for resizing.
+    pub capacity: usize,
+    /// Size of a page in bytes.
+    pub page_size: usize,
+    /// Memory budget the cache size is auto-tuned against, if enabled.
+    pub memory_budget: Option<u64>,
+    /// Materialized pages cached per tenant, largest first.
+    pub tenants: Vec<PageCacheTenantUsage>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PageCacheTenantUsage {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    pub materialized_pages: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageCacheResizeRequest {
+    /// New number of pages, clamped to the page cache capacity.
+    pub size: usize,
+}
+
 // Wrapped in libpq CopyData
 #[derive(PartialEq, Eq, Debug)]
 pub enum PagestreamFeMessage {
@@ -562,6 +1002,7 @@ pub enum PagestreamFeMessage {
 }
 
 // Wrapped in libpq CopyData
+#[derive(Debug)]
 pub enum PagestreamBeMessage {
     Exists(PagestreamExistsResponse),
     Nblocks(PagestreamNblocksResponse),
@@ -653,9 +1094,35 @@ pub struct PagestreamGetLatestLsnResponse {
 
 #[derive(Debug)]
 pub struct PagestreamErrorResponse {
+    /// Only sent to the clients that ask for it, with the `error_codes` option of the
+    /// `pagestream` commands: the older ones reject the messages with trailing bytes.
+    pub code: Option<PagestreamErrorCode>,
     pub message: String,
 }
 
+/// The kind of a [`PagestreamErrorResponse`], for the client to tell the errors
+/// worth retrying from the others. Sent after the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PagestreamErrorCode {
+    Other = 0,
+    /// The WAL up to the requested LSN didn't arrive in time.
+    WaitLsnTimeout = 1,
+    /// The pageserver, or the timeline, is shutting down.
+    ShuttingDown = 2,
+}
+
+impl PagestreamErrorCode {
+    fn from_u8(code: u8) -> Self {
+        match code {
+            1 => PagestreamErrorCode::WaitLsnTimeout,
+            2 => PagestreamErrorCode::ShuttingDown,
+            // from a newer pageserver
+            _ => PagestreamErrorCode::Other,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PagestreamDbSizeResponse {
     pub lsn: Lsn,
@@ -663,9 +1130,41 @@ pub struct PagestreamDbSizeResponse {
 }
 
 impl PagestreamFeMessage {
+    /// The region whose timeline the request reads.
+    /// Whether the request is for the latest version, `None` for the requests
+    /// without the flag.
+    pub fn latest(&self) -> Option<bool> {
+        match self {
+            Self::Exists(req) => Some(req.latest),
+            Self::Nblocks(req) => Some(req.latest),
+            Self::GetPage(req) => Some(req.latest),
+            Self::DbSize(req) => Some(req.latest),
+            Self::GetSlruPage(req) => Some(req.latest),
+            Self::GetLatestLsn(_) => None,
+        }
+    }
+
+    pub fn region(&self) -> RegionId {
+        match self {
+            Self::Exists(req) => req.region,
+            Self::Nblocks(req) => req.region,
+            Self::GetPage(req) => req.region,
+            Self::DbSize(req) => req.region,
+            Self::GetSlruPage(req) => req.region,
+            Self::GetLatestLsn(req) => req.region,
+        }
+    }
+
     pub fn serialize(&self) -> Bytes {
         let mut bytes = BytesMut::new();
+        self.serialize_into(&mut bytes);
+        bytes.into()
+    }
 
+    /// Appends the serialized request to `bytes`. Lets a caller sending many
+    /// requests reuse one buffer for all of them, e.g. by sending
+    /// `bytes.split().freeze()` after each.
+    pub fn serialize_into(&self, bytes: &mut BytesMut) {
         match self {
             Self::Exists(req) => {
                 bytes.put_u8(0);
@@ -725,13 +1224,12 @@ impl PagestreamFeMessage {
                 bytes.put_u8(req.region.0);
             }
         }
-
-        bytes.into()
     }
 
     pub fn parse<R: std::io::Read>(body: &mut R) -> anyhow::Result<PagestreamFeMessage> {
-        // TODO these gets can fail
-
+        // Reads past the end of a truncated message fail rather than panic,
+        // which is checked by the pagestream_fe_message fuzz target.
+        //
         // these correspond to the NeonMessageTag enum in pagestore_client.h
         //
         // TODO: consider using protobuf or serde bincode for less error prone
@@ -800,9 +1298,37 @@ impl PagestreamFeMessage {
 }
 
 impl PagestreamBeMessage {
+    /// The LSN the request was served at, `None` for an error.
+    pub fn lsn(&self) -> Option<Lsn> {
+        match self {
+            Self::Exists(resp) => Some(resp.lsn),
+            Self::Nblocks(resp) => Some(resp.lsn),
+            Self::GetPage(resp) => Some(resp.lsn),
+            Self::GetSlruPage(resp) => Some(resp.lsn),
+            Self::GetLatestLsn(resp) => Some(resp.lsn),
+            Self::DbSize(resp) => Some(resp.lsn),
+            Self::Error(_) => None,
+        }
+    }
+
     pub fn serialize(&self) -> Bytes {
         let mut bytes = BytesMut::new();
+        self.serialize_into(&mut bytes);
+        bytes.into()
+    }
 
+    /// Appends the serialized response to `bytes`, see
+    /// [`PagestreamFeMessage::serialize_into`].
+    pub fn serialize_into(&self, bytes: &mut BytesMut) {
+        if let Some(page) = self.serialize_split(bytes) {
+            bytes.extend_from_slice(page);
+        }
+    }
+
+    /// Serializes the response into `bytes`, except for the page it carries, if any,
+    /// which is returned to be sent after it as it is. This saves copying the page
+    /// on the way to the socket, see `PostgresBackend::write_copy_data_noflush`.
+    pub fn serialize_split(&self, bytes: &mut BytesMut) -> Option<&Bytes> {
         match self {
             Self::Exists(resp) => {
                 bytes.put_u8(100); /* tag from pagestore_client.h */
@@ -819,7 +1345,7 @@ impl PagestreamBeMessage {
             Self::GetPage(resp) => {
                 bytes.put_u8(102); /* tag from pagestore_client.h */
                 bytes.put_u64(resp.lsn.0);
-                bytes.put(&resp.page[..]);
+                return Some(&resp.page);
             }
 
             Self::GetSlruPage(resp) => {
@@ -828,7 +1354,7 @@ impl PagestreamBeMessage {
                 bytes.put_u8(resp.seg_exists as u8);
                 if let Some(page) = &resp.page {
                     bytes.put_u8(1); // page exists
-                    bytes.put(&page[..]);
+                    return Some(page);
                 } else {
                     bytes.put_u8(0); // page does not exist
                 }
@@ -843,6 +1369,9 @@ impl PagestreamBeMessage {
                 bytes.put_u8(105); /* tag from pagestore_client.h */
                 bytes.put(resp.message.as_bytes());
                 bytes.put_u8(0); // null terminator
+                if let Some(code) = resp.code {
+                    bytes.put_u8(code as u8);
+                }
             }
             Self::DbSize(resp) => {
                 bytes.put_u8(106); /* tag from pagestore_client.h */
@@ -850,8 +1379,65 @@ impl PagestreamBeMessage {
                 bytes.put_i64(resp.db_size);
             }
         }
+        None
+    }
 
-        bytes.into()
+    /// Parses a response, for the clients in Rust.
+    pub fn deserialize(buf: Bytes) -> anyhow::Result<Self> {
+        let mut buf = buf.reader();
+        let msg_tag = buf.read_u8()?;
+        match msg_tag {
+            100 => Ok(Self::Exists(PagestreamExistsResponse {
+                lsn: Lsn(buf.read_u64::<BigEndian>()?),
+                exists: buf.read_u8()? != 0,
+            })),
+            101 => Ok(Self::Nblocks(PagestreamNblocksResponse {
+                lsn: Lsn(buf.read_u64::<BigEndian>()?),
+                n_blocks: buf.read_u32::<BigEndian>()?,
+            })),
+            102 => {
+                let lsn = Lsn(buf.read_u64::<BigEndian>()?);
+                Ok(Self::GetPage(PagestreamGetPageResponse {
+                    lsn,
+                    page: buf.into_inner(),
+                }))
+            }
+            103 => {
+                let lsn = Lsn(buf.read_u64::<BigEndian>()?);
+                let seg_exists = buf.read_u8()? != 0;
+                let page = match buf.read_u8()? {
+                    0 => None,
+                    _ => Some(buf.into_inner()),
+                };
+                Ok(Self::GetSlruPage(PagestreamGetSlruPageResponse {
+                    lsn,
+                    seg_exists,
+                    page,
+                }))
+            }
+            104 => Ok(Self::GetLatestLsn(PagestreamGetLatestLsnResponse {
+                lsn: Lsn(buf.read_u64::<BigEndian>()?),
+            })),
+            105 => {
+                let rest = buf.into_inner();
+                let Some(len) = rest.iter().position(|&b| b == 0) else {
+                    bail!("error message is not null-terminated");
+                };
+                // not sent unless asked for, and by older pageservers
+                let code = rest
+                    .get(len + 1)
+                    .map(|&code| PagestreamErrorCode::from_u8(code));
+                Ok(Self::Error(PagestreamErrorResponse {
+                    code,
+                    message: String::from_utf8_lossy(&rest[..len]).into_owned(),
+                }))
+            }
+            106 => Ok(Self::DbSize(PagestreamDbSizeResponse {
+                lsn: Lsn(buf.read_u64::<BigEndian>()?),
+                db_size: buf.read_i64::<BigEndian>()?,
+            })),
+            _ => bail!("unknown pagestream response tag: {msg_tag}"),
+        }
     }
 }
 
@@ -907,6 +1493,12 @@ mod tests {
                 region: RegionId(0),
             }),
         ];
+        // a buffer reused across messages produces the same bytes
+        let mut buf = BytesMut::new();
+        for msg in &messages {
+            msg.serialize_into(&mut buf);
+            assert_eq!(buf.split().freeze(), msg.serialize());
+        }
         for msg in messages {
             let bytes = msg.serialize();
             let reconstructed = PagestreamFeMessage::parse(&mut bytes.reader()).unwrap();
@@ -914,6 +1506,74 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pagestream_response() {
+        let page = Bytes::from_static(&[7u8; 8192]);
+        let mut buf = BytesMut::new();
+        let response = PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
+            lsn: Lsn(4),
+            page: page.clone(),
+        });
+        match PagestreamBeMessage::deserialize(response.serialize()).unwrap() {
+            PagestreamBeMessage::GetPage(resp) => {
+                assert_eq!(resp.lsn, Lsn(4));
+                assert_eq!(resp.page, page);
+            }
+            resp => panic!("unexpected response {resp:?}"),
+        }
+        response.serialize_into(&mut buf);
+        assert_eq!(buf.split().freeze(), response.serialize());
+
+        let response = PagestreamBeMessage::Error(PagestreamErrorResponse {
+            code: Some(PagestreamErrorCode::WaitLsnTimeout),
+            message: "timed out waiting for WAL".to_string(),
+        });
+        match PagestreamBeMessage::deserialize(response.serialize()).unwrap() {
+            PagestreamBeMessage::Error(resp) => {
+                assert_eq!(resp.code, Some(PagestreamErrorCode::WaitLsnTimeout));
+                assert_eq!(resp.message, "timed out waiting for WAL");
+            }
+            resp => panic!("unexpected response {resp:?}"),
+        }
+        response.serialize_into(&mut buf);
+        assert_eq!(buf.split().freeze(), response.serialize());
+
+        // without the code, as sent to the clients that don't ask for it
+        let old_error = PagestreamBeMessage::Error(PagestreamErrorResponse {
+            code: None,
+            message: "timeline not found".to_string(),
+        });
+        assert_eq!(&old_error.serialize()[..], b"\x69timeline not found\0");
+        match PagestreamBeMessage::deserialize(old_error.serialize()).unwrap() {
+            PagestreamBeMessage::Error(resp) => {
+                assert_eq!(resp.code, None);
+                assert_eq!(resp.message, "timeline not found");
+            }
+            resp => panic!("unexpected response {resp:?}"),
+        }
+
+        assert!(PagestreamBeMessage::deserialize(Bytes::from_static(&[101, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn test_label_selector() {
+        let labels = Labels::from([
+            ("project".to_string(), "p1".to_string()),
+            ("env".to_string(), "prod".to_string()),
+        ]);
+        let selector: LabelSelector = "project=p1".parse().unwrap();
+        assert!(selector.matches(&labels));
+        let selector: LabelSelector = "project=p1,env=prod".parse().unwrap();
+        assert!(selector.matches(&labels));
+        let selector: LabelSelector = "project=p1,env=staging".parse().unwrap();
+        assert!(!selector.matches(&labels));
+        let selector: LabelSelector = "owner=".parse().unwrap();
+        assert!(!selector.matches(&labels));
+
+        assert!("project".parse::<LabelSelector>().is_err());
+        assert!("=p1".parse::<LabelSelector>().is_err());
+    }
+
     #[test]
     fn test_tenantinfo_serde() {
         // Test serialization/deserialization of TenantInfo
@@ -922,6 +1582,17 @@ mod tests {
             state: TenantState::Active,
             current_physical_size: Some(42),
             attachment_status: TenantAttachmentStatus::Attached,
+            labels: Labels::new(),
+            home_region: Some(RegionId(1)),
+            regions: vec![RegionId(1), RegionId(2)],
+            has_in_progress_downloads: false,
+            attach_download: Some(AttachDownloadProgress {
+                total_layers: 3,
+                total_bytes: 300,
+                downloaded_layers: 3,
+                downloaded_bytes: 300,
+                resumed_layers: 1,
+            }),
         };
         let expected_active = json!({
             "id": original_active.id.to_string(),
@@ -931,7 +1602,17 @@ mod tests {
             "current_physical_size": 42,
             "attachment_status": {
                 "slug":"attached",
-            }
+            },
+            "home_region": 1,
+            "regions": [1, 2],
+            "has_in_progress_downloads": false,
+            "attach_download": {
+                "total_layers": 3,
+                "total_bytes": 300,
+                "downloaded_layers": 3,
+                "downloaded_bytes": 300,
+                "resumed_layers": 1,
+            },
         });
 
         let original_broken = TenantInfo {
@@ -942,6 +1623,11 @@ mod tests {
             },
             current_physical_size: Some(42),
             attachment_status: TenantAttachmentStatus::Attached,
+            labels: Labels::new(),
+            home_region: None,
+            regions: Vec::new(),
+            has_in_progress_downloads: false,
+            attach_download: None,
         };
         let expected_broken = json!({
             "id": original_broken.id.to_string(),
@@ -955,7 +1641,8 @@ mod tests {
             "current_physical_size": 42,
             "attachment_status": {
                 "slug":"attached",
-            }
+            },
+            "has_in_progress_downloads": false,
         });
 
         assert_eq!(
@@ -971,6 +1658,31 @@ mod tests {
         assert!(format!("{:?}", &original_broken.state).contains("backtrace info"));
     }
 
+    #[test]
+    fn test_region_serde() {
+        // Regions are numbers in the responses, numbers or strings in the requests
+        let region_timeline = RegionTimeline {
+            region_id: RegionId(2),
+            timeline_id: TimelineId::generate(),
+        };
+        assert_eq!(
+            serde_json::to_value(region_timeline).unwrap(),
+            json!({
+                "region_id": 2,
+                "timeline_id": region_timeline.timeline_id.to_string(),
+            })
+        );
+
+        let request: TimelineCreateRequest = serde_json::from_value(json!({
+            "new_timeline_id": TimelineId::generate().to_string(),
+            "region_id": 1,
+            "materialize_in_regions": [2, "3"],
+        }))
+        .unwrap();
+        assert_eq!(request.region_id, Some(RegionId(1)));
+        assert_eq!(request.materialize_in_regions, [RegionId(2), RegionId(3)]);
+    }
+
     #[test]
     fn test_reject_unknown_field() {
         let id = TenantId::generate();
@@ -0,0 +1,526 @@
+//! Builders of the tenant and timeline create and config requests.
+//!
+//! The requests are plain structs of optional fields, because that's what the
+//! pageserver's HTTP API accepts, with the durations as strings. The builders set
+//! the fields with their own types, and check the values before the request is
+//! sent, so that a typo in a duration is an error on the caller's side rather than
+//! a 400 response from the pageserver.
+
+use std::num::{NonZeroU32, NonZeroU64};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use utils::id::{RegionId, TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+use super::{
+    Labels, TenantConfig, TenantConfigRequest, TenantCreateRequest, TimelineCreateRequest,
+};
+
+impl TenantConfig {
+    pub fn builder() -> TenantConfigBuilder<TenantConfig> {
+        TenantConfigBuilder::new(|config| config)
+    }
+}
+
+impl TenantCreateRequest {
+    pub fn builder(new_tenant_id: TenantId) -> TenantConfigBuilder<TenantCreateRequest> {
+        TenantConfigBuilder::new(move |config| TenantCreateRequest {
+            new_tenant_id,
+            config,
+        })
+    }
+}
+
+impl TenantConfigRequest {
+    pub fn builder(tenant_id: TenantId) -> TenantConfigBuilder<TenantConfigRequest> {
+        TenantConfigBuilder::new(move |config| TenantConfigRequest { tenant_id, config })
+    }
+}
+
+impl TimelineCreateRequest {
+    pub fn builder(new_timeline_id: TimelineId) -> TimelineCreateRequestBuilder {
+        TimelineCreateRequestBuilder {
+            request: TimelineCreateRequest {
+                new_timeline_id,
+                ancestor_timeline_id: None,
+                ancestor_start_lsn: None,
+                pg_version: None,
+                region_id: None,
+                labels: Labels::new(),
+                materialize_in_regions: Vec::new(),
+            },
+        }
+    }
+
+    /// Checks that the regions to branch the new timeline into are distinct, and
+    /// not the region of the timeline itself.
+    pub fn check_materialize_in_regions(&self) -> anyhow::Result<()> {
+        let region_id = self.region_id.unwrap_or_default();
+        for (i, &region) in self.materialize_in_regions.iter().enumerate() {
+            if region == region_id {
+                bail!("cannot materialize the timeline in its own region {region}");
+            }
+            if self.materialize_in_regions[..i].contains(&region) {
+                bail!("region {region} is listed twice to materialize the timeline in");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [`TenantConfig`], or a request carrying one. The settings that aren't
+/// set are left to the pageserver's defaults, or are kept as they are by a config
+/// request.
+#[must_use]
+pub struct TenantConfigBuilder<T> {
+    config: TenantConfig,
+    make: Box<dyn FnOnce(TenantConfig) -> T + Send>,
+}
+
+impl<T> TenantConfigBuilder<T> {
+    fn new(make: impl FnOnce(TenantConfig) -> T + Send + 'static) -> Self {
+        TenantConfigBuilder {
+            config: TenantConfig::default(),
+            make: Box::new(make),
+        }
+    }
+
+    pub fn checkpoint_distance(mut self, bytes: u64) -> Self {
+        self.config.checkpoint_distance = Some(bytes);
+        self
+    }
+
+    pub fn checkpoint_timeout(mut self, timeout: Duration) -> Self {
+        self.config.checkpoint_timeout = Some(format_duration(timeout));
+        self
+    }
+
+    pub fn compaction_target_size(mut self, bytes: u64) -> Self {
+        self.config.compaction_target_size = Some(bytes);
+        self
+    }
+
+    /// Zero disables the background compaction.
+    pub fn compaction_period(mut self, period: Duration) -> Self {
+        self.config.compaction_period = Some(format_duration(period));
+        self
+    }
+
+    pub fn compaction_threshold(mut self, num_layers: usize) -> Self {
+        self.config.compaction_threshold = Some(num_layers);
+        self
+    }
+
+    pub fn gc_horizon(mut self, bytes: u64) -> Self {
+        self.config.gc_horizon = Some(bytes);
+        self
+    }
+
+    /// Zero disables the background GC.
+    pub fn gc_period(mut self, period: Duration) -> Self {
+        self.config.gc_period = Some(format_duration(period));
+        self
+    }
+
+    pub fn image_creation_threshold(mut self, num_deltas: usize) -> Self {
+        self.config.image_creation_threshold = Some(num_deltas);
+        self
+    }
+
+    pub fn slru_image_creation_threshold(mut self, num_deltas: usize) -> Self {
+        self.config.slru_image_creation_threshold = Some(num_deltas);
+        self
+    }
+
+    pub fn pitr_interval(mut self, interval: Duration) -> Self {
+        self.config.pitr_interval = Some(format_duration(interval));
+        self
+    }
+
+    pub fn walreceiver_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.walreceiver_connect_timeout = Some(format_duration(timeout));
+        self
+    }
+
+    pub fn lagging_wal_timeout(mut self, timeout: Duration) -> Self {
+        self.config.lagging_wal_timeout = Some(format_duration(timeout));
+        self
+    }
+
+    pub fn max_lsn_wal_lag(mut self, bytes: NonZeroU64) -> Self {
+        self.config.max_lsn_wal_lag = Some(bytes);
+        self
+    }
+
+    pub fn trace_read_requests(mut self, enabled: bool) -> Self {
+        self.config.trace_read_requests = Some(enabled);
+        self
+    }
+
+    /// The policy is parsed by the pageserver, see `EvictionPolicy` there.
+    pub fn eviction_policy(mut self, policy: serde_json::Value) -> Self {
+        self.config.eviction_policy = Some(policy);
+        self
+    }
+
+    pub fn min_resident_size_override(mut self, bytes: u64) -> Self {
+        self.config.min_resident_size_override = Some(bytes);
+        self
+    }
+
+    pub fn evictions_low_residence_duration_metric_threshold(
+        mut self,
+        threshold: Duration,
+    ) -> Self {
+        self.config
+            .evictions_low_residence_duration_metric_threshold = Some(format_duration(threshold));
+        self
+    }
+
+    pub fn gc_feedback(mut self, enabled: bool) -> Self {
+        self.config.gc_feedback = Some(enabled);
+        self
+    }
+
+    /// The pageserver checks the level against the range of its zstd.
+    pub fn layer_compression_level(mut self, level: i32) -> Self {
+        self.config.layer_compression_level = Some(level);
+        self
+    }
+
+    pub fn get_page_weight(mut self, weight: NonZeroU32) -> Self {
+        self.config.get_page_weight = Some(weight);
+        self
+    }
+
+    pub fn logical_size_reconcile_period(mut self, period: Duration) -> Self {
+        self.config.logical_size_reconcile_period = Some(format_duration(period));
+        self
+    }
+
+    pub fn pagestream_rate_limit(mut self, requests_per_second: NonZeroU32) -> Self {
+        self.config.pagestream_rate_limit = Some(requests_per_second);
+        self
+    }
+
+    pub fn wait_lsn_timeout(mut self, timeout: Duration) -> Self {
+        self.config.wait_lsn_timeout = Some(format_duration(timeout));
+        self
+    }
+
+    /// Replaces all labels of the tenant with `labels`.
+    pub fn labels(mut self, labels: Labels) -> Self {
+        self.config.labels = Some(labels);
+        self
+    }
+
+    /// Adds a label to the ones the tenant gets. Once a label is set, all labels
+    /// of the tenant are replaced.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config
+            .labels
+            .get_or_insert_with(Labels::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the setting called `name` to `value` in its text form, as in the
+    /// pageserver's config file, e.g. `gc_period` to `1h`.
+    pub fn setting(self, name: &str, value: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "checkpoint_distance" => self.checkpoint_distance(parse(name, value)?),
+            "checkpoint_timeout" => self.checkpoint_timeout(parse_duration(name, value)?),
+            "compaction_target_size" => self.compaction_target_size(parse(name, value)?),
+            "compaction_period" => self.compaction_period(parse_duration(name, value)?),
+            "compaction_threshold" => self.compaction_threshold(parse(name, value)?),
+            "gc_horizon" => self.gc_horizon(parse(name, value)?),
+            "gc_period" => self.gc_period(parse_duration(name, value)?),
+            "image_creation_threshold" => self.image_creation_threshold(parse(name, value)?),
+            "slru_image_creation_threshold" => {
+                self.slru_image_creation_threshold(parse(name, value)?)
+            }
+            "pitr_interval" => self.pitr_interval(parse_duration(name, value)?),
+            "walreceiver_connect_timeout" => {
+                self.walreceiver_connect_timeout(parse_duration(name, value)?)
+            }
+            "lagging_wal_timeout" => self.lagging_wal_timeout(parse_duration(name, value)?),
+            "max_lsn_wal_lag" => self.max_lsn_wal_lag(parse(name, value)?),
+            "trace_read_requests" => self.trace_read_requests(parse(name, value)?),
+            "eviction_policy" => self.eviction_policy(
+                serde_json::from_str(value)
+                    .with_context(|| format!("Failed to parse '{name}' json"))?,
+            ),
+            "min_resident_size_override" => self.min_resident_size_override(parse(name, value)?),
+            "evictions_low_residence_duration_metric_threshold" => {
+                self.evictions_low_residence_duration_metric_threshold(parse_duration(name, value)?)
+            }
+            "gc_feedback" => self.gc_feedback(parse(name, value)?),
+            "layer_compression_level" => self.layer_compression_level(parse(name, value)?),
+            "get_page_weight" => self.get_page_weight(parse(name, value)?),
+            "logical_size_reconcile_period" => {
+                self.logical_size_reconcile_period(parse_duration(name, value)?)
+            }
+            "pagestream_rate_limit" => self.pagestream_rate_limit(parse(name, value)?),
+            "wait_lsn_timeout" => self.wait_lsn_timeout(parse_duration(name, value)?),
+            _ => bail!("Unrecognized tenant setting '{name}'"),
+        })
+    }
+
+    /// Checks the settings and builds the config or request.
+    pub fn build(self) -> anyhow::Result<T> {
+        let config = &self.config;
+        if config.checkpoint_distance == Some(0) {
+            bail!("checkpoint_distance must be positive");
+        }
+        if config.compaction_target_size == Some(0) {
+            bail!("compaction_target_size must be positive");
+        }
+        if config.compaction_threshold == Some(0) {
+            bail!("compaction_threshold must be positive");
+        }
+        if let Some(labels) = &config.labels {
+            check_labels(labels)?;
+        }
+        Ok((self.make)(self.config))
+    }
+}
+
+/// Builds a [`TimelineCreateRequest`].
+#[must_use]
+pub struct TimelineCreateRequestBuilder {
+    request: TimelineCreateRequest,
+}
+
+impl TimelineCreateRequestBuilder {
+    /// Branches the timeline off `ancestor_timeline_id`, at its last record LSN
+    /// unless [`Self::ancestor_start_lsn`] is set.
+    pub fn ancestor(mut self, ancestor_timeline_id: TimelineId) -> Self {
+        self.request.ancestor_timeline_id = Some(ancestor_timeline_id);
+        self
+    }
+
+    pub fn ancestor_start_lsn(mut self, lsn: Lsn) -> Self {
+        self.request.ancestor_start_lsn = Some(lsn);
+        self
+    }
+
+    /// The Postgres version of a timeline without an ancestor, the pageserver's
+    /// default if not set.
+    pub fn pg_version(mut self, pg_version: u32) -> Self {
+        self.request.pg_version = Some(pg_version);
+        self
+    }
+
+    pub fn region_id(mut self, region_id: RegionId) -> Self {
+        self.request.region_id = Some(region_id);
+        self
+    }
+
+    pub fn labels(mut self, labels: Labels) -> Self {
+        self.request.labels = labels;
+        self
+    }
+
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.request.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Also branches the timeline into these regions, as it's created.
+    pub fn materialize_in_regions(mut self, regions: impl IntoIterator<Item = RegionId>) -> Self {
+        self.request.materialize_in_regions.extend(regions);
+        self
+    }
+
+    /// Checks the request and builds it.
+    pub fn build(self) -> anyhow::Result<TimelineCreateRequest> {
+        let request = &self.request;
+        match request.ancestor_timeline_id {
+            Some(ancestor_timeline_id) if ancestor_timeline_id == request.new_timeline_id => {
+                bail!("timeline {ancestor_timeline_id} cannot be its own ancestor")
+            }
+            Some(_) if request.pg_version.is_some() => {
+                bail!("pg_version cannot be set for a branch, it has the version of its ancestor")
+            }
+            None if request.ancestor_start_lsn.is_some() => {
+                bail!("ancestor_start_lsn is set without an ancestor timeline")
+            }
+            _ => {}
+        }
+        check_labels(&request.labels)?;
+        request.check_materialize_in_regions()?;
+        Ok(self.request)
+    }
+}
+
+/// Checks that the labels can be selected with a [`super::LabelSelector`].
+fn check_labels(labels: &Labels) -> anyhow::Result<()> {
+    for (key, value) in labels {
+        if key.is_empty() || key.contains(['=', ',']) {
+            bail!("invalid label key '{key}', it must be non-empty without '=' or ','");
+        }
+        if value.contains(',') {
+            bail!("invalid value '{value}' of label '{key}', it must not contain ','");
+        }
+    }
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    humantime::format_duration(duration).to_string()
+}
+
+fn parse<V>(name: &str, value: &str) -> anyhow::Result<V>
+where
+    V: std::str::FromStr,
+    V::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Failed to parse '{name}' value '{value}'"))
+}
+
+fn parse_duration(name: &str, value: &str) -> anyhow::Result<Duration> {
+    humantime::parse_duration(value)
+        .with_context(|| format!("Failed to parse '{name}' value '{value}' as a duration"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenant_create_request() {
+        let tenant_id = TenantId::generate();
+        let request = TenantCreateRequest::builder(tenant_id)
+            .gc_horizon(64 * 1024 * 1024)
+            .pitr_interval(Duration::from_secs(7 * 24 * 3600))
+            .gc_period(Duration::ZERO)
+            .compaction_period(Duration::from_millis(1500))
+            .label("env", "staging")
+            .build()
+            .unwrap();
+        assert_eq!(request.new_tenant_id, tenant_id);
+        assert_eq!(request.gc_horizon, Some(64 * 1024 * 1024));
+        assert_eq!(request.pitr_interval.as_deref(), Some("7days"));
+        assert_eq!(request.gc_period.as_deref(), Some("0s"));
+        assert_eq!(request.compaction_period.as_deref(), Some("1s 500ms"));
+        assert_eq!(request.checkpoint_distance, None);
+        assert_eq!(
+            request.labels,
+            Some(Labels::from([("env".to_string(), "staging".to_string())]))
+        );
+        // the durations are written in a form the pageserver parses
+        for duration in [&request.pitr_interval, &request.compaction_period] {
+            humantime::parse_duration(duration.as_deref().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn settings() {
+        let config = TenantConfig::builder()
+            .setting("checkpoint_distance", "1000")
+            .and_then(|b| b.setting("gc_period", "1h"))
+            .and_then(|b| b.setting("max_lsn_wal_lag", "10"))
+            .and_then(|b| b.setting("eviction_policy", r#"{"kind": "NoEviction"}"#))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.checkpoint_distance, Some(1000));
+        assert_eq!(config.gc_period.as_deref(), Some("1h"));
+        assert_eq!(config.max_lsn_wal_lag, NonZeroU64::new(10));
+        assert!(config.eviction_policy.is_some());
+
+        let builder = || TenantConfig::builder();
+        assert!(builder().setting("gc_period", "1 fortnight").is_err());
+        assert!(builder().setting("max_lsn_wal_lag", "0").is_err());
+        assert!(builder().setting("gc_feedback", "yes").is_err());
+        assert!(builder().setting("no_such_setting", "1").is_err());
+    }
+
+    #[test]
+    fn invalid_tenant_config() {
+        let tenant_id = TenantId::generate();
+        assert!(TenantConfigRequest::builder(tenant_id)
+            .checkpoint_distance(0)
+            .build()
+            .is_err());
+        assert!(TenantConfigRequest::builder(tenant_id)
+            .compaction_threshold(0)
+            .build()
+            .is_err());
+        assert!(TenantConfigRequest::builder(tenant_id)
+            .label("a=b", "c")
+            .build()
+            .is_err());
+        let request = TenantConfigRequest::builder(tenant_id).build().unwrap();
+        assert_eq!(request.tenant_id, tenant_id);
+        assert!(request.labels.is_none());
+    }
+
+    #[test]
+    fn timeline_create_request() {
+        let (timeline_id, ancestor_id) = (TimelineId::generate(), TimelineId::generate());
+        let request = TimelineCreateRequest::builder(timeline_id)
+            .ancestor(ancestor_id)
+            .ancestor_start_lsn(Lsn(0x100))
+            .build()
+            .unwrap();
+        assert_eq!(request.new_timeline_id, timeline_id);
+        assert_eq!(request.ancestor_timeline_id, Some(ancestor_id));
+        assert_eq!(request.ancestor_start_lsn, Some(Lsn(0x100)));
+
+        let request = TimelineCreateRequest::builder(timeline_id)
+            .pg_version(15)
+            .build()
+            .unwrap();
+        assert_eq!(request.pg_version, Some(15));
+        assert_eq!(request.ancestor_timeline_id, None);
+
+        // an LSN without an ancestor, a branch of itself, a version of a branch
+        assert!(TimelineCreateRequest::builder(timeline_id)
+            .ancestor_start_lsn(Lsn(0x100))
+            .build()
+            .is_err());
+        assert!(TimelineCreateRequest::builder(timeline_id)
+            .ancestor(timeline_id)
+            .build()
+            .is_err());
+        assert!(TimelineCreateRequest::builder(timeline_id)
+            .ancestor(ancestor_id)
+            .pg_version(15)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn timeline_create_request_regions() {
+        let timeline_id = TimelineId::generate();
+        let request = TimelineCreateRequest::builder(timeline_id)
+            .region_id(RegionId(1))
+            .materialize_in_regions([RegionId(2), RegionId(3)])
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.materialize_in_regions,
+            vec![RegionId(2), RegionId(3)]
+        );
+
+        // its own region, the default one included, and a region twice
+        let builder = || TimelineCreateRequest::builder(timeline_id);
+        assert!(builder()
+            .region_id(RegionId(1))
+            .materialize_in_regions([RegionId(1)])
+            .build()
+            .is_err());
+        assert!(builder()
+            .materialize_in_regions([RegionId::default()])
+            .build()
+            .is_err());
+        assert!(builder()
+            .materialize_in_regions([RegionId(2), RegionId(2)])
+            .build()
+            .is_err());
+    }
+}
@@ -30,6 +30,9 @@ pub enum QueryError {
     /// The connection was lost while processing the query.
     #[error(transparent)]
     Disconnected(#[from] ConnectionError),
+    /// The client isn't allowed to run the query.
+    #[error("Unauthorized: {0}")]
+    Unauthorized(std::borrow::Cow<'static, str>),
     /// Some other error
     #[error(transparent)]
     Other(#[from] anyhow::Error),
@@ -45,6 +48,7 @@ impl QueryError {
     pub fn pg_error_code(&self) -> &'static [u8; 5] {
         match self {
             Self::Disconnected(_) => b"08006",         // connection failure
+            Self::Unauthorized(_) => b"28000",         // invalid authorization specification
             Self::Other(_) => SQLSTATE_INTERNAL_ERROR, // internal error
         }
     }
@@ -62,7 +66,7 @@ pub fn is_expected_io_error(e: &io::Error) -> bool {
 }
 
 #[async_trait::async_trait]
-pub trait Handler<IO> {
+pub trait Handler<IO: Send> {
     /// Handle single query.
     /// postgres_backend will issue ReadyForQuery after calling this (this
     /// might be not what we want after CopyData streaming, but currently we don't
@@ -78,7 +82,7 @@ pub trait Handler<IO> {
     /// If Ok(false) is returned postgres_backend will skip auth -- that is needed for new users
     /// creation is the proxy code. That is quite hacky and ad-hoc solution, may be we could allow
     /// to override whole init logic in implementations.
-    fn startup(
+    async fn startup(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         _sm: &FeStartupPacket,
@@ -87,7 +91,7 @@ pub trait Handler<IO> {
     }
 
     /// Check auth jwt
-    fn check_auth_jwt(
+    async fn check_auth_jwt(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         _jwt_response: &[u8],
@@ -134,6 +138,22 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeTlsStream<IO> {
             Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
         }
     }
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Unencrypted(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Unencrypted(stream) => stream.is_write_vectored(),
+            Self::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
     fn poll_flush(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<io::Result<()>> {
         match self.get_mut() {
             Self::Unencrypted(stream) => Pin::new(stream).poll_flush(cx),
@@ -230,6 +250,16 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> MaybeWriteOnly<IO> {
         }
     }
 
+    fn write_copy_data_noflush(&mut self, header: &[u8], payload: Bytes) {
+        match self {
+            MaybeWriteOnly::Full(framed) => framed.write_copy_data(header, payload),
+            MaybeWriteOnly::WriteOnly(framed_writer) => {
+                framed_writer.write_copy_data_noflush(header, payload)
+            }
+            MaybeWriteOnly::Broken => panic!("IO on invalid MaybeWriteOnly"),
+        }
+    }
+
     async fn flush(&mut self) -> io::Result<()> {
         match self {
             MaybeWriteOnly::Full(framed) => framed.flush().await,
@@ -349,6 +379,15 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
         Ok(self)
     }
 
+    /// Write a CopyData message of `header` followed by `payload` into the output,
+    /// doesn't flush it. Unlike with `BeMessage::CopyData`, a large `payload` is
+    /// not copied into the output buffer but written out from where it is.
+    pub fn write_copy_data_noflush(&mut self, header: &[u8], payload: Bytes) -> &mut Self {
+        trace!("wrote CopyData of {} bytes", header.len() + payload.len());
+        self.framed.write_copy_data_noflush(header, payload);
+        self
+    }
+
     /// Flush output buffer into the socket.
     pub async fn flush(&mut self) -> io::Result<()> {
         self.framed.flush().await
@@ -389,6 +428,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
         shutdown_watcher: F,
     ) -> Result<(), QueryError>
     where
+        IO: Send,
         F: Fn() -> S,
         S: Future,
     {
@@ -405,6 +445,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
         shutdown_watcher: F,
     ) -> Result<(), QueryError>
     where
+        IO: Send,
         F: Fn() -> S,
         S: Future,
     {
@@ -538,7 +579,10 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
 
     /// Perform handshake with the client, transitioning to Established.
     /// In case of EOF during handshake logs this, sets state to Closed and returns Ok(()).
-    async fn handshake(&mut self, handler: &mut impl Handler<IO>) -> Result<(), QueryError> {
+    async fn handshake(&mut self, handler: &mut impl Handler<IO>) -> Result<(), QueryError>
+    where
+        IO: Send,
+    {
         while self.state < ProtoState::Authentication {
             match self.framed.read_startup_message().await? {
                 Some(msg) => {
@@ -563,7 +607,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
 
                     let (_, jwt_response) = m.split_last().context("protocol violation")?;
 
-                    if let Err(e) = handler.check_auth_jwt(self, jwt_response) {
+                    if let Err(e) = handler.check_auth_jwt(self, jwt_response).await {
                         self.write_message_noflush(&BeMessage::ErrorResponse(
                             &e.to_string(),
                             Some(e.pg_error_code()),
@@ -606,7 +650,10 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
         &mut self,
         handler: &mut impl Handler<IO>,
         msg: FeStartupPacket,
-    ) -> Result<(), QueryError> {
+    ) -> Result<(), QueryError>
+    where
+        IO: Send,
+    {
         assert!(self.state < ProtoState::Authentication);
         let have_tls = self.tls_config.is_some();
         match msg {
@@ -637,7 +684,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
 
                 // NB: startup() may change self.auth_type -- we are using that in proxy code
                 // to bypass auth for new users.
-                handler.startup(self, &msg)?;
+                handler.startup(self, &msg).await?;
 
                 match self.auth_type {
                     AuthType::Trust => {
@@ -671,7 +718,10 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
         handler: &mut impl Handler<IO>,
         msg: FeMessage,
         unnamed_query_string: &mut Bytes,
-    ) -> Result<ProcessMsgResult, QueryError> {
+    ) -> Result<ProcessMsgResult, QueryError>
+    where
+        IO: Send,
+    {
         // Allow only startup and password messages during auth. Otherwise client would be able to bypass auth
         // TODO: change that to proper top-level match of protocol state with separate message handling for each state
         assert!(self.state == ProtoState::Established);
@@ -913,6 +963,7 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> AsyncWrite for CopyDataWriter<'a, I
 pub fn short_error(e: &QueryError) -> String {
     match e {
         QueryError::Disconnected(connection_error) => connection_error.to_string(),
+        QueryError::Unauthorized(e) => format!("Unauthorized: {e}"),
         QueryError::Other(e) => format!("{e:#}"),
     }
 }
@@ -929,6 +980,9 @@ fn log_query_error(query: &str, e: &QueryError) {
         QueryError::Disconnected(other_connection_error) => {
             error!("query handler for '{query}' failed with connection error: {other_connection_error:?}")
         }
+        QueryError::Unauthorized(e) => {
+            info!("query handler for '{query}' refused: {e}");
+        }
         QueryError::Other(e) => {
             error!("query handler for '{query}' failed: {e:?}");
         }
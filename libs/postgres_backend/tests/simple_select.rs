@@ -1,7 +1,7 @@
 /// Test postgres_backend_async with tokio_postgres
 use once_cell::sync::Lazy;
 use postgres_backend::{AuthType, Handler, PostgresBackend, QueryError};
-use pq_proto::{BeMessage, RowDescriptor};
+use pq_proto::{BeMessage, FeStartupPacket, RowDescriptor};
 use std::io::Cursor;
 use std::{future, sync::Arc};
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -72,6 +72,71 @@ async fn simple_select() {
     }
 }
 
+/// Remembers the application_name of the startup packet, after waiting for something.
+struct StartupHandler {
+    app_name: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl<IO: AsyncRead + AsyncWrite + Unpin + Send> Handler<IO> for StartupHandler {
+    async fn startup(
+        &mut self,
+        _pgb: &mut PostgresBackend<IO>,
+        sm: &FeStartupPacket,
+    ) -> Result<(), QueryError> {
+        tokio::task::yield_now().await;
+        if let FeStartupPacket::StartupMessage { params, .. } = sm {
+            self.app_name = params.get("application_name").map(str::to_owned);
+        }
+        Ok(())
+    }
+
+    // return the application_name for any query
+    async fn process_query(
+        &mut self,
+        pgb: &mut PostgresBackend<IO>,
+        _query_string: &str,
+    ) -> Result<(), QueryError> {
+        let app_name = self.app_name.as_deref().unwrap_or("");
+        pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+            b"application_name",
+        )]))?
+        .write_message_noflush(&BeMessage::DataRow(&[Some(app_name.as_bytes())]))?
+        .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        Ok(())
+    }
+}
+
+// test that the startup handler can await
+#[tokio::test]
+async fn async_startup() {
+    let (client_sock, server_sock) = make_tcp_pair().await;
+
+    let pgbackend =
+        PostgresBackend::new(server_sock, AuthType::Trust, None).expect("pgbackend creation");
+
+    tokio::spawn(async move {
+        let mut handler = StartupHandler { app_name: None };
+        pgbackend.run(&mut handler, future::pending::<()>).await
+    });
+
+    let mut conf = Config::new();
+    conf.application_name("async_startup");
+    let (client, connection) = conf.connect_raw(client_sock, NoTls).await.expect("connect");
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let first_val = &(client.simple_query("SELECT 42;").await.expect("select"))[0];
+    if let SimpleQueryMessage::Row(row) = first_val {
+        assert_eq!(row.get(0), Some("async_startup"));
+    } else {
+        panic!("expected SimpleQueryMessage::Row");
+    }
+}
+
 static KEY: Lazy<rustls::PrivateKey> = Lazy::new(|| {
     let mut cursor = Cursor::new(include_bytes!("key.pem"));
     rustls::PrivateKey(rustls_pemfile::rsa_private_keys(&mut cursor).unwrap()[0].clone())
@@ -58,6 +58,7 @@ for_all_postgres_versions! { postgres_ffi }
 
 pub mod pg_constants;
 pub mod relfile_utils;
+pub mod wal_generator;
 
 // Export some widely used datatypes that are unlikely to change across Postgres versions
 pub use v14::bindings::{uint32, uint64, Oid};
@@ -100,6 +101,15 @@ pub fn bkpimage_is_compressed(bimg_info: u8, version: u32) -> anyhow::Result<boo
     }
 }
 
+/// The magic number in the headers of the WAL pages of the Postgres version.
+pub fn xlog_page_magic(pg_version: u32) -> anyhow::Result<u16> {
+    match pg_version {
+        14 => Ok(v14::bindings::XLOG_PAGE_MAGIC as u16),
+        15 => Ok(v15::bindings::XLOG_PAGE_MAGIC as u16),
+        _ => anyhow::bail!("Unknown version {}", pg_version),
+    }
+}
+
 pub fn generate_wal_segment(
     segno: u64,
     system_id: u64,
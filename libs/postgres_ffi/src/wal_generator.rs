@@ -0,0 +1,773 @@
+//!
+//! Generator of synthetic WAL.
+//!
+//! Produces valid WAL streams without running Postgres, for the benchmarks of
+//! WAL ingestion, the safekeeper tests and fuzzing. The streams are made of
+//! page headers and CRC-checked records that [`WalStreamDecoder`] accepts, and
+//! the records are laid out the way Postgres logs them, so that the pageserver
+//! can decode and ingest them.
+//!
+//! The generator runs a seeded, configurable mix of transactions. Each one does
+//! a few heap inserts and updates, relation creates and drops, and ends with a
+//! commit or abort record. Relation drops are logged in the commit record, and
+//! the relations created by an aborted transaction in its abort record, like
+//! Postgres does.
+//!
+//! Each region has its own WAL stream, and each relation belongs to one region.
+//! A transaction touching relations of several regions logs its changes in the
+//! streams of those regions, and ends with a commit or abort record of the same
+//! XID in each of them.
+//!
+//! [`WalStreamDecoder`]: crate::waldecoder::WalStreamDecoder
+//!
+
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use bytes::{BufMut, Bytes, BytesMut};
+use crc32c::crc32c_append;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use utils::id::RegionId;
+use utils::lsn::Lsn;
+
+use crate::relfile_utils::MAIN_FORKNUM;
+use crate::v14::bindings::{XLogLongPageHeaderData, XLogPageHeaderData};
+use crate::v14::xlog_utils::{XLOG_RECORD_CRC_OFFS, XLP_FIRST_IS_CONTRECORD};
+use crate::BLCKSZ;
+use crate::{
+    pg_constants, xlog_page_magic, BlockNumber, Oid, TimestampTz, TransactionId, XLogRecord,
+    PG_TLI, WAL_SEGMENT_SIZE, XLOG_BLCKSZ,
+};
+
+/// Database of all the generated relations.
+const DB_OID: Oid = 5;
+
+/// Size of the tuple header that precedes the data of a tuple, with t_hoff
+/// aligned.
+const TUPLE_HEADER_SIZE: usize = 24;
+/// Size of an item pointer in the line pointer array of a heap page.
+const ITEM_ID_SIZE: usize = 4;
+/// Space for the tuples and their line pointers on an empty heap page.
+const PAGE_FREE_SPACE: usize = BLCKSZ as usize - pg_constants::SIZE_OF_PAGE_HEADER as usize;
+/// Largest tuple data that fits on an empty heap page.
+const MAX_TUPLE_SIZE: usize = ((PAGE_FREE_SPACE - ITEM_ID_SIZE) & !7) - TUPLE_HEADER_SIZE;
+
+/// Size of the xl_heap_header struct.
+const SIZE_OF_HEAP_HEADER: usize = 5;
+const HEAP_XMAX_INVALID: u16 = 0x0800;
+
+#[derive(Debug, Clone)]
+pub struct WalGeneratorConfig {
+    pub pg_version: u32,
+    pub system_id: u64,
+    /// Where the streams of all regions start. Must be aligned to 8 bytes.
+    pub start_lsn: Lsn,
+    pub regions: Vec<RegionId>,
+
+    /// Relative weights of the operations in a transaction.
+    pub insert_weight: u32,
+    pub update_weight: u32,
+    pub create_relation_weight: u32,
+    pub drop_relation_weight: u32,
+
+    pub max_ops_per_xact: usize,
+    pub max_tuple_size: usize,
+    pub abort_probability: f64,
+    /// Probability that a transaction also touches a second region.
+    pub cross_region_probability: f64,
+}
+
+impl Default for WalGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            pg_version: 14,
+            system_id: 0,
+            start_lsn: Lsn(WAL_SEGMENT_SIZE as u64),
+            regions: vec![RegionId::default()],
+            insert_weight: 60,
+            update_weight: 30,
+            create_relation_weight: 2,
+            drop_relation_weight: 1,
+            max_ops_per_xact: 10,
+            max_tuple_size: 100,
+            abort_probability: 0.1,
+            cross_region_probability: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Insert,
+    Update,
+    CreateRelation,
+    DropRelation,
+}
+
+const OPS: [Op; 4] = [Op::Insert, Op::Update, Op::CreateRelation, Op::DropRelation];
+
+struct Relation {
+    region: usize,
+    relnode: Oid,
+    /// Number of tuples on each block.
+    tuples: Vec<u16>,
+    /// Free space on the last block.
+    free_space: usize,
+    /// Dropped by the running transaction.
+    dropping: bool,
+}
+
+/// A block reference of a record being assembled.
+struct BlockRef {
+    relnode: Oid,
+    blkno: BlockNumber,
+    will_init: bool,
+    data: Vec<u8>,
+}
+
+/// The WAL stream of a region.
+struct Stream {
+    /// Generated WAL that hasn't been taken yet.
+    buf: BytesMut,
+    /// End of the last record.
+    end_lsn: Lsn,
+    /// Start of the last record.
+    prev_lsn: Lsn,
+}
+
+pub struct WalGenerator {
+    config: WalGeneratorConfig,
+    rng: StdRng,
+    op_dist: WeightedIndex<u32>,
+    page_magic: u16,
+
+    streams: Vec<Stream>,
+    region_idx: HashMap<RegionId, usize>,
+    relations: Vec<Relation>,
+    next_relnode: Oid,
+    next_xid: TransactionId,
+    /// Commit timestamps advance by a millisecond per transaction from the
+    /// Postgres epoch, to keep the WAL deterministic.
+    xact_time: TimestampTz,
+}
+
+impl WalGenerator {
+    pub fn new(config: WalGeneratorConfig, seed: u64) -> anyhow::Result<Self> {
+        let page_magic = xlog_page_magic(config.pg_version)?;
+        ensure!(!config.regions.is_empty(), "no regions to generate WAL for");
+        ensure!(config.start_lsn.is_aligned(), "start_lsn must be aligned");
+        ensure!(
+            config.max_ops_per_xact > 0,
+            "max_ops_per_xact must be positive"
+        );
+        ensure!(
+            (1..=MAX_TUPLE_SIZE).contains(&config.max_tuple_size),
+            "max_tuple_size must fit on a page"
+        );
+        let op_dist = WeightedIndex::new([
+            config.insert_weight,
+            config.update_weight,
+            config.create_relation_weight,
+            config.drop_relation_weight,
+        ])?;
+
+        let streams = config
+            .regions
+            .iter()
+            .map(|_| Stream {
+                buf: BytesMut::new(),
+                end_lsn: config.start_lsn,
+                prev_lsn: Lsn(0),
+            })
+            .collect();
+        let region_idx: HashMap<_, _> = config
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(i, region)| (*region, i))
+            .collect();
+        ensure!(
+            region_idx.len() == config.regions.len(),
+            "duplicate regions"
+        );
+        Ok(Self {
+            rng: StdRng::seed_from_u64(seed),
+            op_dist,
+            page_magic,
+            streams,
+            region_idx,
+            relations: Vec::new(),
+            next_relnode: pg_constants::FIRST_NORMAL_OBJECT_ID,
+            next_xid: pg_constants::FIRST_NORMAL_TRANSACTION_ID,
+            xact_time: 0,
+            config,
+        })
+    }
+
+    /// End of the last record generated in the stream of the region.
+    ///
+    /// Panics if the region is not one of the configured ones.
+    pub fn end_lsn(&self, region: RegionId) -> Lsn {
+        self.streams[self.region_idx[&region]].end_lsn
+    }
+
+    /// Takes the WAL of the region generated since the last call, up to the end
+    /// of the last record.
+    ///
+    /// Panics if the region is not one of the configured ones.
+    pub fn take_wal(&mut self, region: RegionId) -> Bytes {
+        let idx = self.region_idx[&region];
+        self.streams[idx].buf.split().freeze()
+    }
+
+    /// Generates transactions until at least `len` bytes of WAL of the region
+    /// are pending, and takes them.
+    pub fn generate(&mut self, region: RegionId, len: usize) -> Bytes {
+        let idx = self.region_idx[&region];
+        while self.streams[idx].buf.len() < len {
+            self.next_xact();
+        }
+        self.take_wal(region)
+    }
+
+    /// Generates a transaction, appending its records to the streams of the
+    /// regions it touches.
+    pub fn next_xact(&mut self) {
+        let xid = self.next_xid;
+        self.next_xid = self.next_xid.wrapping_add(1);
+        if self.next_xid < pg_constants::FIRST_NORMAL_TRANSACTION_ID {
+            self.next_xid = pg_constants::FIRST_NORMAL_TRANSACTION_ID;
+        }
+        self.xact_time += 1000;
+
+        if xid % pg_constants::CLOG_XACTS_PER_PAGE == 0 {
+            // Every region has its own CLOG, covering all the XIDs.
+            let pageno = xid / pg_constants::CLOG_XACTS_PER_PAGE;
+            for idx in 0..self.streams.len() {
+                self.append_record(
+                    idx,
+                    0,
+                    pg_constants::RM_CLOG_ID,
+                    pg_constants::CLOG_ZEROPAGE,
+                    Vec::new(),
+                    &pageno.to_le_bytes(),
+                );
+            }
+        }
+
+        let mut regions = vec![self.rng.gen_range(0..self.streams.len())];
+        if self.streams.len() > 1 && self.rng.gen_bool(self.config.cross_region_probability) {
+            let other =
+                (regions[0] + self.rng.gen_range(1..self.streams.len())) % self.streams.len();
+            regions.push(other);
+        }
+
+        let mut created = Vec::new();
+        for _ in 0..self.rng.gen_range(1..=self.config.max_ops_per_xact) {
+            let region = *regions.choose(&mut self.rng).unwrap();
+            match OPS[self.op_dist.sample(&mut self.rng)] {
+                Op::Insert => {
+                    if let Some(rel) = self.pick_relation(region, false) {
+                        self.insert(xid, rel);
+                        continue;
+                    }
+                }
+                Op::Update => {
+                    if let Some(rel) = self.pick_relation(region, true) {
+                        self.update(xid, rel);
+                        continue;
+                    }
+                }
+                Op::CreateRelation => {}
+                Op::DropRelation => {
+                    if let Some(rel) = self.pick_relation(region, false) {
+                        self.relations[rel].dropping = true;
+                        continue;
+                    }
+                }
+            }
+            // Also the fallback of the operations without a relation to work on.
+            created.push(self.create_relation(xid, region));
+        }
+
+        let commit = !self.rng.gen_bool(self.config.abort_probability);
+        for &region in &regions {
+            // A commit drops the relations dropped by the transaction, and an
+            // abort those it created.
+            let rels: Vec<Oid> = self
+                .relations
+                .iter()
+                .filter(|rel| rel.region == region)
+                .filter(|rel| {
+                    if commit {
+                        rel.dropping
+                    } else {
+                        created.contains(&rel.relnode)
+                    }
+                })
+                .map(|rel| rel.relnode)
+                .collect();
+            self.append_xact_record(region, xid, commit, &rels);
+        }
+        self.relations.retain_mut(|rel| {
+            let dropped = if commit {
+                rel.dropping
+            } else {
+                created.contains(&rel.relnode)
+            };
+            rel.dropping = false;
+            !dropped
+        });
+    }
+
+    fn pick_relation(&mut self, region: usize, with_tuples: bool) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.relations.len())
+            .filter(|&i| {
+                let rel = &self.relations[i];
+                rel.region == region && !rel.dropping && (!with_tuples || !rel.tuples.is_empty())
+            })
+            .collect();
+        candidates.choose(&mut self.rng).copied()
+    }
+
+    fn create_relation(&mut self, xid: TransactionId, region: usize) -> Oid {
+        let relnode = self.next_relnode;
+        self.next_relnode += 1;
+        self.relations.push(Relation {
+            region,
+            relnode,
+            tuples: Vec::new(),
+            free_space: 0,
+            dropping: false,
+        });
+
+        // xl_smgr_create
+        let mut main_data = Vec::with_capacity(16);
+        main_data.put_u32_le(pg_constants::DEFAULTTABLESPACE_OID);
+        main_data.put_u32_le(DB_OID);
+        main_data.put_u32_le(relnode);
+        main_data.put_i32_le(MAIN_FORKNUM as i32);
+        self.append_record(
+            region,
+            xid,
+            pg_constants::RM_SMGR_ID,
+            pg_constants::XLOG_SMGR_CREATE,
+            Vec::new(),
+            &main_data,
+        );
+        relnode
+    }
+
+    /// Places a new tuple on the last block of the relation, or on a new block
+    /// if it doesn't fit. Returns the block data logged for the tuple, and its
+    /// location.
+    fn place_tuple(&mut self, rel: usize) -> (Vec<u8>, BlockNumber, u16, bool) {
+        let len = self.rng.gen_range(1..=self.config.max_tuple_size);
+        let needed = ((TUPLE_HEADER_SIZE + len + 7) & !7) + ITEM_ID_SIZE;
+
+        let rel = &mut self.relations[rel];
+        let new_page = rel.tuples.is_empty() || rel.free_space < needed;
+        if new_page {
+            rel.tuples.push(0);
+            rel.free_space = PAGE_FREE_SPACE;
+        }
+        rel.free_space -= needed;
+        let blkno = rel.tuples.len() as BlockNumber - 1;
+        let tuples = rel.tuples.last_mut().unwrap();
+        *tuples += 1;
+        let offnum = *tuples;
+
+        // xl_heap_header, followed by the tuple past the fixed part of its
+        // header: one byte of padding up to t_hoff, and the attributes.
+        let mut data = Vec::with_capacity(SIZE_OF_HEAP_HEADER + 1 + len);
+        data.put_u16_le(1);
+        data.put_u16_le(HEAP_XMAX_INVALID);
+        data.put_u8(TUPLE_HEADER_SIZE as u8);
+        data.put_u8(0);
+        data.extend((0..len).map(|_| self.rng.gen::<u8>()));
+        (data, blkno, offnum, new_page)
+    }
+
+    fn insert(&mut self, xid: TransactionId, rel: usize) {
+        let (data, blkno, offnum, new_page) = self.place_tuple(rel);
+        let (region, relnode) = (self.relations[rel].region, self.relations[rel].relnode);
+
+        // xl_heap_insert
+        let mut main_data = Vec::with_capacity(3);
+        main_data.put_u16_le(offnum);
+        main_data.put_u8(0);
+        let mut info = pg_constants::XLOG_HEAP_INSERT;
+        if new_page {
+            info |= pg_constants::XLOG_HEAP_INIT_PAGE;
+        }
+        let block = BlockRef {
+            relnode,
+            blkno,
+            will_init: new_page,
+            data,
+        };
+        self.append_record(
+            region,
+            xid,
+            pg_constants::RM_HEAP_ID,
+            info,
+            vec![block],
+            &main_data,
+        );
+    }
+
+    fn update(&mut self, xid: TransactionId, rel: usize) {
+        let (old_blkno, old_offnum) = {
+            let tuples = &self.relations[rel].tuples;
+            let blkno = self.rng.gen_range(0..tuples.len());
+            let offnum = self.rng.gen_range(1..=tuples[blkno]);
+            (blkno as BlockNumber, offnum)
+        };
+        let (data, blkno, offnum, new_page) = self.place_tuple(rel);
+        let (region, relnode) = (self.relations[rel].region, self.relations[rel].relnode);
+
+        // xl_heap_update, as the pageserver decodes it
+        let mut main_data = Vec::with_capacity(18);
+        main_data.put_u32_le(xid);
+        main_data.put_u16_le(old_offnum);
+        main_data.put_u8(0);
+        main_data.put_u8(0);
+        main_data.put_u32_le(0);
+        main_data.put_u32_le(0);
+        main_data.put_u16_le(offnum);
+        let mut info = pg_constants::XLOG_HEAP_UPDATE;
+        if new_page {
+            info |= pg_constants::XLOG_HEAP_INIT_PAGE;
+        }
+        let mut blocks = vec![BlockRef {
+            relnode,
+            blkno,
+            will_init: new_page,
+            data,
+        }];
+        if old_blkno != blkno {
+            blocks.push(BlockRef {
+                relnode,
+                blkno: old_blkno,
+                will_init: false,
+                data: Vec::new(),
+            });
+        }
+        self.append_record(
+            region,
+            xid,
+            pg_constants::RM_HEAP_ID,
+            info,
+            blocks,
+            &main_data,
+        );
+    }
+
+    fn append_xact_record(
+        &mut self,
+        region: usize,
+        xid: TransactionId,
+        commit: bool,
+        rels: &[Oid],
+    ) {
+        let mut info = if commit {
+            pg_constants::XLOG_XACT_COMMIT
+        } else {
+            pg_constants::XLOG_XACT_ABORT
+        };
+        let mut main_data = Vec::new();
+        main_data.put_i64_le(self.xact_time);
+        if !rels.is_empty() {
+            info |= pg_constants::XLOG_XACT_HAS_INFO;
+            main_data.put_u32_le(pg_constants::XACT_XINFO_HAS_RELFILENODES);
+            main_data.put_i32_le(rels.len() as i32);
+            for relnode in rels {
+                main_data.put_u32_le(pg_constants::DEFAULTTABLESPACE_OID);
+                main_data.put_u32_le(DB_OID);
+                main_data.put_u32_le(*relnode);
+            }
+        }
+        self.append_record(
+            region,
+            xid,
+            pg_constants::RM_XACT_ID,
+            info,
+            Vec::new(),
+            &main_data,
+        );
+    }
+
+    /// Assembles a record, and appends it to the stream of the region.
+    fn append_record(
+        &mut self,
+        region: usize,
+        xid: TransactionId,
+        rmid: u8,
+        info: u8,
+        blocks: Vec<BlockRef>,
+        main_data: &[u8],
+    ) {
+        let mut data = Vec::new();
+        let mut prev_relnode = None;
+        for (block_id, block) in blocks.iter().enumerate() {
+            let same_rel = prev_relnode == Some(block.relnode);
+            let mut fork_flags = MAIN_FORKNUM;
+            if !block.data.is_empty() {
+                fork_flags |= pg_constants::BKPBLOCK_HAS_DATA;
+            }
+            if block.will_init {
+                fork_flags |= pg_constants::BKPBLOCK_WILL_INIT;
+            }
+            if same_rel {
+                fork_flags |= pg_constants::BKPBLOCK_SAME_REL;
+            }
+            data.put_u8(block_id as u8);
+            data.put_u8(fork_flags);
+            data.put_u16_le(block.data.len() as u16);
+            if !same_rel {
+                data.put_u32_le(pg_constants::DEFAULTTABLESPACE_OID);
+                data.put_u32_le(DB_OID);
+                data.put_u32_le(block.relnode);
+            }
+            data.put_u32_le(block.blkno);
+            prev_relnode = Some(block.relnode);
+        }
+        if !main_data.is_empty() {
+            if main_data.len() <= u8::MAX as usize {
+                data.put_u8(pg_constants::XLR_BLOCK_ID_DATA_SHORT);
+                data.put_u8(main_data.len() as u8);
+            } else {
+                data.put_u8(pg_constants::XLR_BLOCK_ID_DATA_LONG);
+                data.put_u32_le(main_data.len() as u32);
+            }
+        }
+        for block in &blocks {
+            data.extend_from_slice(&block.data);
+        }
+        data.extend_from_slice(main_data);
+
+        let stream = &mut self.streams[region];
+        let mut lsn = stream.end_lsn.align();
+        // Pad the previous record. A record never starts in a page header.
+        stream.buf.put_bytes(0, (lsn.0 - stream.end_lsn.0) as usize);
+        if lsn.block_offset() == 0 {
+            let page_header = encode_page_header(lsn, 0, self.page_magic, self.config.system_id);
+            stream.buf.extend_from_slice(&page_header);
+            lsn += page_header.len() as u64;
+        }
+        let start_lsn = lsn;
+
+        let mut header = XLogRecord {
+            xl_tot_len: (crate::XLOG_SIZE_OF_XLOG_RECORD + data.len()) as u32,
+            xl_xid: xid,
+            xl_prev: stream.prev_lsn.0,
+            xl_info: info,
+            xl_rmid: rmid,
+            __bindgen_padding_0: [0u8; 2usize],
+            xl_crc: 0, // crc will be calculated later
+        };
+        let header_bytes = header.encode().expect("failed to encode header");
+        let crc = crc32c_append(0, &data);
+        header.xl_crc = crc32c_append(crc, &header_bytes[0..XLOG_RECORD_CRC_OFFS]);
+        let mut record = header.encode().expect("failed to encode header").to_vec();
+        record.extend_from_slice(&data);
+
+        // Split the record across pages.
+        let mut rest = &record[..];
+        while !rest.is_empty() {
+            if lsn.block_offset() == 0 {
+                let page_header =
+                    encode_page_header(lsn, rest.len(), self.page_magic, self.config.system_id);
+                stream.buf.extend_from_slice(&page_header);
+                lsn += page_header.len() as u64;
+            }
+            let n = std::cmp::min(rest.len(), lsn.remaining_in_block() as usize);
+            stream.buf.extend_from_slice(&rest[..n]);
+            lsn += n as u64;
+            rest = &rest[n..];
+        }
+        stream.prev_lsn = start_lsn;
+        stream.end_lsn = lsn;
+    }
+}
+
+/// Encodes the header of the page starting at `lsn`, with `rem_len` bytes of
+/// a record continued from the previous page.
+fn encode_page_header(lsn: Lsn, rem_len: usize, magic: u16, system_id: u64) -> Bytes {
+    let std = XLogPageHeaderData {
+        xlp_magic: magic,
+        xlp_info: if rem_len > 0 {
+            XLP_FIRST_IS_CONTRECORD
+        } else {
+            0
+        },
+        xlp_tli: PG_TLI,
+        xlp_pageaddr: lsn.0,
+        xlp_rem_len: rem_len as u32,
+        ..Default::default() // Put 0 in padding fields.
+    };
+    if lsn.segment_offset(WAL_SEGMENT_SIZE) == 0 {
+        XLogLongPageHeaderData {
+            std: XLogPageHeaderData {
+                xlp_info: std.xlp_info | pg_constants::XLP_LONG_HEADER,
+                ..std
+            },
+            xlp_sysid: system_id,
+            xlp_seg_size: WAL_SEGMENT_SIZE as u32,
+            xlp_xlog_blcksz: XLOG_BLCKSZ as u32,
+        }
+        .encode()
+    } else {
+        std.encode()
+    }
+    .expect("failed to encode page header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v14::xlog_utils::XLOG_SIZE_OF_XLOG_LONG_PHD;
+    use crate::waldecoder::WalStreamDecoder;
+    use crate::XLOG_SIZE_OF_XLOG_SHORT_PHD;
+    use bytes::Buf;
+
+    /// Where a record starting at `lsn` or in the page header there actually
+    /// starts.
+    fn skip_page_header(lsn: Lsn) -> Lsn {
+        if lsn.segment_offset(WAL_SEGMENT_SIZE) == 0 {
+            lsn + XLOG_SIZE_OF_XLOG_LONG_PHD as u64
+        } else if lsn.block_offset() == 0 {
+            lsn + XLOG_SIZE_OF_XLOG_SHORT_PHD as u64
+        } else {
+            lsn
+        }
+    }
+
+    /// Decodes the WAL, checking that the records are chained by xl_prev.
+    /// Returns the headers of the records.
+    fn decode(start_lsn: Lsn, pg_version: u32, wal: &[u8]) -> Vec<XLogRecord> {
+        let mut decoder = WalStreamDecoder::new(start_lsn, pg_version);
+        // feed in small pieces, to exercise the reassembly of records
+        for chunk in wal.chunks(1000) {
+            decoder.feed_bytes(chunk);
+        }
+        let mut records = Vec::new();
+        let mut prev_lsn = Lsn(0);
+        let mut record_lsn = start_lsn;
+        while let Some((next_lsn, record)) = decoder.poll_decode().unwrap() {
+            let header = XLogRecord::from_bytes(&mut record.clone()).unwrap();
+            assert_eq!(header.xl_prev, prev_lsn.0);
+            assert_eq!(header.xl_tot_len as usize, record.remaining());
+            prev_lsn = skip_page_header(record_lsn);
+            record_lsn = next_lsn;
+            records.push(header);
+        }
+        // all the records were decoded
+        assert_eq!(record_lsn, (start_lsn + wal.len() as u64).align());
+        records
+    }
+
+    #[test]
+    fn test_generate_wal() {
+        for pg_version in [14, 15] {
+            let config = WalGeneratorConfig {
+                pg_version,
+                ..Default::default()
+            };
+            let region = config.regions[0];
+            let start_lsn = config.start_lsn;
+            let mut generator = WalGenerator::new(config, 1).unwrap();
+            // more than a segment, to cross a segment boundary
+            let wal = generator.generate(region, WAL_SEGMENT_SIZE + XLOG_BLCKSZ);
+            assert_eq!(generator.end_lsn(region), start_lsn + wal.len() as u64);
+
+            let records = decode(start_lsn, pg_version, &wal);
+            for rmid in [
+                pg_constants::RM_HEAP_ID,
+                pg_constants::RM_SMGR_ID,
+                pg_constants::RM_XACT_ID,
+            ] {
+                assert!(records.iter().any(|r| r.xl_rmid == rmid), "no rmid {rmid}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_wal_deterministic() {
+        let config = WalGeneratorConfig::default();
+        let region = config.regions[0];
+        let wal1 = WalGenerator::new(config.clone(), 42)
+            .unwrap()
+            .generate(region, 100_000);
+        let wal2 = WalGenerator::new(config.clone(), 42)
+            .unwrap()
+            .generate(region, 100_000);
+        let wal3 = WalGenerator::new(config, 43)
+            .unwrap()
+            .generate(region, 100_000);
+        assert_eq!(wal1, wal2);
+        assert_ne!(wal1, wal3);
+    }
+
+    #[test]
+    fn test_generate_wal_regions() {
+        let regions = vec![RegionId(0), RegionId(1), RegionId(2)];
+        let config = WalGeneratorConfig {
+            // start in the middle of a page, with tuples that cross pages
+            start_lsn: Lsn(0x1000_0128),
+            regions: regions.clone(),
+            max_tuple_size: 6000,
+            cross_region_probability: 0.5,
+            ..Default::default()
+        };
+        let start_lsn = config.start_lsn;
+        let mut generator = WalGenerator::new(config, 7).unwrap();
+        for _ in 0..1000 {
+            generator.next_xact();
+        }
+
+        let mut xids = Vec::new();
+        for region in regions {
+            let wal = generator.take_wal(region);
+            assert_eq!(generator.end_lsn(region), start_lsn + wal.len() as u64);
+            let records = decode(start_lsn, 14, &wal);
+            xids.push(
+                records
+                    .iter()
+                    .filter(|r| r.xl_rmid == pg_constants::RM_XACT_ID)
+                    .map(|r| r.xl_xid)
+                    .collect::<Vec<_>>(),
+            );
+            // taking again returns nothing new
+            assert!(generator.take_wal(region).is_empty());
+        }
+        // some transactions ended in several regions
+        assert!(xids[0].iter().any(|xid| xids[1].contains(xid)));
+        assert!(xids[1].iter().any(|xid| xids[2].contains(xid)));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for config in [
+            WalGeneratorConfig {
+                pg_version: 13,
+                ..Default::default()
+            },
+            WalGeneratorConfig {
+                regions: vec![],
+                ..Default::default()
+            },
+            WalGeneratorConfig {
+                start_lsn: Lsn(0x1000_0001),
+                ..Default::default()
+            },
+            WalGeneratorConfig {
+                max_tuple_size: BLCKSZ as usize,
+                ..Default::default()
+            },
+        ] {
+            assert!(WalGenerator::new(config, 0).is_err());
+        }
+    }
+}
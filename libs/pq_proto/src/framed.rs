@@ -10,10 +10,11 @@
 //! calls.
 //!
 //! [Box]: https://docs.rs/futures-util/0.3.26/src/futures_util/lock/bilock.rs.html#107
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::{
+    collections::VecDeque,
     future::Future,
-    io::{self, ErrorKind},
+    io::{self, ErrorKind, IoSlice},
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 
@@ -21,6 +22,13 @@ use crate::{BeMessage, FeMessage, FeStartupPacket, ProtocolError};
 
 const INITIAL_CAPACITY: usize = 8 * 1024;
 
+/// Payloads of CopyData messages shorter than this are copied into the output
+/// buffer; a separate write of them would cost more than the copy.
+const MIN_QUEUED_PAYLOAD: usize = 1024;
+
+/// Max number of buffers passed to one vectored write.
+const MAX_IO_SLICES: usize = 64;
+
 /// Error on postgres connection: either IO (physical transport error) or
 /// protocol violation.
 #[derive(thiserror::Error, Debug)]
@@ -46,7 +54,7 @@ impl ConnectionError {
 pub struct Framed<S> {
     stream: S,
     read_buf: BytesMut,
-    write_buf: BytesMut,
+    write_buf: WriteBuf,
 }
 
 impl<S> Framed<S> {
@@ -54,7 +62,7 @@ impl<S> Framed<S> {
         Self {
             stream,
             read_buf: BytesMut::with_capacity(INITIAL_CAPACITY),
-            write_buf: BytesMut::with_capacity(INITIAL_CAPACITY),
+            write_buf: WriteBuf::new(),
         }
     }
 
@@ -99,7 +107,14 @@ impl<S: AsyncRead + Unpin> Framed<S> {
 impl<S: AsyncWrite + Unpin> Framed<S> {
     /// Write next message to the output buffer; doesn't flush.
     pub fn write_message(&mut self, msg: &BeMessage<'_>) -> Result<(), ProtocolError> {
-        BeMessage::write(&mut self.write_buf, msg)
+        BeMessage::write(&mut self.write_buf.buf, msg)
+    }
+
+    /// Write next CopyData message, consisting of `header` followed by `payload`,
+    /// to the output; doesn't flush. `payload` is sent from where it is rather
+    /// than copied into the output buffer.
+    pub fn write_copy_data(&mut self, header: &[u8], payload: Bytes) {
+        self.write_buf.write_copy_data(header, payload)
     }
 
     /// Flush out the buffer. This function is cancellation safe: it can be
@@ -156,13 +171,18 @@ impl<S: AsyncRead + Unpin> FramedReader<S> {
 /// Write-only version of `Framed`.
 pub struct FramedWriter<S> {
     stream: WriteHalf<S>,
-    write_buf: BytesMut,
+    write_buf: WriteBuf,
 }
 
 impl<S: AsyncWrite + Unpin> FramedWriter<S> {
     /// Write next message to the output buffer; doesn't flush.
     pub fn write_message_noflush(&mut self, msg: &BeMessage<'_>) -> Result<(), ProtocolError> {
-        BeMessage::write(&mut self.write_buf, msg)
+        BeMessage::write(&mut self.write_buf.buf, msg)
+    }
+
+    /// See [`Framed::write_copy_data`].
+    pub fn write_copy_data_noflush(&mut self, header: &[u8], payload: Bytes) {
+        self.write_buf.write_copy_data(header, payload)
     }
 
     /// Flush out the buffer. This function is cancellation safe: it can be
@@ -177,6 +197,80 @@ impl<S: AsyncWrite + Unpin> FramedWriter<S> {
     }
 }
 
+/// Output of a connection. Messages are serialized into `buf`, except for large
+/// CopyData payloads, which are queued as they are, so that e.g. a page read from
+/// the page cache goes to the socket without being copied once more.
+struct WriteBuf {
+    /// Output to write before `buf`: the serialized messages preceding a queued
+    /// payload, and the payload.
+    queued: VecDeque<Bytes>,
+    buf: BytesMut,
+}
+
+impl WriteBuf {
+    fn new() -> Self {
+        WriteBuf {
+            queued: VecDeque::new(),
+            buf: BytesMut::with_capacity(INITIAL_CAPACITY),
+        }
+    }
+
+    fn write_copy_data(&mut self, header: &[u8], payload: Bytes) {
+        let size =
+            i32::try_from(4 + header.len() + payload.len()).expect("message too big to transmit");
+        self.buf.put_u8(b'd');
+        self.buf.put_i32(size);
+        self.buf.put_slice(header);
+        if payload.len() < MIN_QUEUED_PAYLOAD {
+            self.buf.put_slice(&payload);
+        } else {
+            // split() doesn't copy; the memory goes back to `buf` once written out
+            self.queued.push_back(self.buf.split().freeze());
+            self.queued.push_back(payload);
+        }
+    }
+}
+
+impl Buf for WriteBuf {
+    fn remaining(&self) -> usize {
+        self.queued.iter().map(Bytes::len).sum::<usize>() + self.buf.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self.queued.front() {
+            Some(bytes) => bytes,
+            None => &self.buf,
+        }
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let chunks = self
+            .queued
+            .iter()
+            .map(|bytes| &bytes[..])
+            .chain(std::iter::once(&self.buf[..]))
+            .filter(|chunk| !chunk.is_empty());
+        let mut n = 0;
+        for (slice, chunk) in dst.iter_mut().zip(chunks) {
+            *slice = IoSlice::new(chunk);
+            n += 1;
+        }
+        n
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        while let Some(front) = self.queued.front_mut() {
+            if cnt < front.len() {
+                front.advance(cnt);
+                return;
+            }
+            cnt -= front.len();
+            self.queued.pop_front();
+        }
+        self.buf.advance(cnt);
+    }
+}
+
 /// Read next message from the stream. Returns Ok(None), if EOF happened and we
 /// don't have remaining data in the buffer. This function is cancellation safe:
 /// you can drop future which is not yet complete and finalize reading message
@@ -216,10 +310,12 @@ where
 
 async fn flush<S: AsyncWrite + Unpin>(
     stream: &mut S,
-    write_buf: &mut BytesMut,
+    write_buf: &mut WriteBuf,
 ) -> Result<(), io::Error> {
     while write_buf.has_remaining() {
-        let bytes_written = stream.write(write_buf.chunk()).await?;
+        let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+        let n = write_buf.chunks_vectored(&mut slices);
+        let bytes_written = stream.write_vectored(&slices[..n]).await?;
         if bytes_written == 0 {
             return Err(io::Error::new(
                 ErrorKind::WriteZero,
@@ -231,14 +327,53 @@ async fn flush<S: AsyncWrite + Unpin>(
         // enough.
         write_buf.advance(bytes_written);
     }
-    write_buf.clear();
+    write_buf.buf.clear();
     stream.flush().await
 }
 
 async fn shutdown<S: AsyncWrite + Unpin>(
     stream: &mut S,
-    write_buf: &mut BytesMut,
+    write_buf: &mut WriteBuf,
 ) -> Result<(), io::Error> {
     flush(stream, write_buf).await?;
     stream.shutdown().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output with a queued payload is the same as with everything copied.
+    #[test]
+    fn write_buf_queued_payload() {
+        let page = Bytes::from(vec![7u8; 8192]);
+        let mut expected = BytesMut::new();
+        let mut write_buf = WriteBuf::new();
+        for buf in [&mut expected, &mut write_buf.buf] {
+            BeMessage::write(buf, &BeMessage::ReadyForQuery).unwrap();
+        }
+        let mut message = b"header".to_vec();
+        message.extend_from_slice(&page);
+        BeMessage::write(&mut expected, &BeMessage::CopyData(&message)).unwrap();
+        write_buf.write_copy_data(b"header", page.clone());
+        assert_eq!(write_buf.queued.len(), 2);
+        for buf in [&mut expected, &mut write_buf.buf] {
+            BeMessage::write(buf, &BeMessage::CopyDone).unwrap();
+        }
+        assert_eq!(write_buf.remaining(), expected.len());
+
+        let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+        assert_eq!(write_buf.chunks_vectored(&mut slices), 3);
+
+        // consume in odd-sized steps, crossing the chunk boundaries
+        let mut written = Vec::new();
+        while write_buf.has_remaining() {
+            let chunk = write_buf.chunk();
+            let n = chunk.len().min(1000);
+            written.extend_from_slice(&chunk[..n]);
+            write_buf.advance(n);
+        }
+        assert_eq!(written, expected);
+        assert!(write_buf.queued.is_empty());
+    }
+}
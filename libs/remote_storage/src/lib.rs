@@ -299,6 +299,19 @@ impl GenericRemoteStorage {
         Self::Unreliable(Arc::new(UnreliableWrapper::new(s, fail_first)))
     }
 
+    /// Changes the maximum number of concurrent requests to the storage.
+    /// Returns false if the storage has no such limit.
+    pub fn set_concurrency_limit(&self, limit: NonZeroUsize) -> bool {
+        match self {
+            Self::LocalFs(_) => false,
+            Self::AwsS3(s) => {
+                s.set_concurrency_limit(limit);
+                true
+            }
+            Self::Unreliable(s) => s.inner().set_concurrency_limit(limit),
+        }
+    }
+
     /// Takes storage object contents and its size and uploads to remote storage,
     /// mapping `from_path` to the corresponding remote object id in the storage.
     ///
@@ -377,6 +390,9 @@ pub struct S3Config {
     /// AWS S3 has various limits on its API calls, we need not to exceed those.
     /// See [`DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT`] for more details.
     pub concurrency_limit: NonZeroUsize,
+    /// Limits the requests per second, on top of the concurrency limit, e.g. to stay
+    /// under the limits of an S3 flavor, or of a prefix. No limit if unset.
+    pub max_requests_per_second: Option<NonZeroU32>,
     pub max_keys_per_list_response: Option<i32>,
 }
 
@@ -387,6 +403,7 @@ impl Debug for S3Config {
             .field("bucket_region", &self.bucket_region)
             .field("prefix_in_bucket", &self.prefix_in_bucket)
             .field("concurrency_limit", &self.concurrency_limit)
+            .field("max_requests_per_second", &self.max_requests_per_second)
             .field(
                 "max_keys_per_list_response",
                 &self.max_keys_per_list_response,
@@ -419,6 +436,13 @@ impl RemoteStorageConfig {
         )
         .context("Failed to parse 'concurrency_limit' as a positive integer")?;
 
+        let max_requests_per_second = parse_optional_integer("max_requests_per_second", toml)?
+            .map(|limit| {
+                NonZeroU32::new(limit)
+                    .context("Failed to parse 'max_requests_per_second' as a positive integer")
+            })
+            .transpose()?;
+
         let max_keys_per_list_response =
             parse_optional_integer::<i32, _>("max_keys_per_list_response", toml)
                 .context("Failed to parse 'max_keys_per_list_response' as a positive integer")?
@@ -445,6 +469,7 @@ impl RemoteStorageConfig {
                     .map(|endpoint| parse_toml_string("endpoint", endpoint))
                     .transpose()?,
                 concurrency_limit,
+                max_requests_per_second,
                 max_keys_per_list_response,
             }),
             (Some(local_path), None, None) => RemoteStorageKind::LocalFs(PathBuf::from(
@@ -4,7 +4,7 @@
 //! allowing multiple api users to independently work with the same S3 bucket, if
 //! their bucket prefixes are both specified and different.
 
-use std::sync::Arc;
+use std::num::NonZeroUsize;
 
 use anyhow::Context;
 use aws_config::{
@@ -24,12 +24,11 @@ use aws_sdk_s3::{
 use aws_smithy_http::body::SdkBody;
 use hyper::Body;
 use scopeguard::ScopeGuard;
-use tokio::{
-    io::{self, AsyncRead},
-    sync::Semaphore,
-};
+use tokio::io::{self, AsyncRead};
 use tokio_util::io::ReaderStream;
 use tracing::debug;
+use utils::resizable_semaphore::ResizableSemaphore;
+use utils::token_bucket::TokenBucket;
 
 use super::StorageMetadata;
 use crate::{
@@ -51,7 +50,10 @@ pub struct S3Bucket {
     // Every request to S3 can be throttled or cancelled, if a certain number of requests per second is exceeded.
     // Same goes to IAM, which is queried before every S3 request, if enabled. IAM has even lower RPS threshold.
     // The helps to ensure we don't exceed the thresholds.
-    concurrency_limiter: Arc<Semaphore>,
+    // Can be resized at runtime.
+    concurrency_limiter: ResizableSemaphore,
+    // Spreads the requests over time, if a request rate limit is configured.
+    rate_limiter: TokenBucket,
 }
 
 #[derive(Default)]
@@ -118,10 +120,22 @@ impl S3Bucket {
             bucket_name: aws_config.bucket_name.clone(),
             max_keys_per_list_response: aws_config.max_keys_per_list_response,
             prefix_in_bucket,
-            concurrency_limiter: Arc::new(Semaphore::new(aws_config.concurrency_limit.get())),
+            concurrency_limiter: ResizableSemaphore::new(aws_config.concurrency_limit),
+            rate_limiter: match aws_config.max_requests_per_second {
+                // up to a second's worth of requests at once
+                Some(rps) => TokenBucket::new(rps.get(), rps.get()),
+                None => TokenBucket::unlimited(),
+            },
         })
     }
 
+    /// Changes the maximum number of concurrent requests.
+    ///
+    /// A decrease takes effect as the requests in flight complete.
+    pub fn set_concurrency_limit(&self, limit: NonZeroUsize) {
+        self.concurrency_limiter.set_limit(limit);
+    }
+
     fn s3_object_to_relative_path(&self, key: &str) -> RemotePath {
         let relative_path =
             match key.strip_prefix(self.prefix_in_bucket.as_deref().unwrap_or_default()) {
@@ -155,8 +169,10 @@ impl S3Bucket {
 
     async fn permit(&self, kind: RequestKind) -> tokio::sync::SemaphorePermit<'_> {
         let started_at = start_counting_cancelled_wait(kind);
+        self.rate_limiter.acquire(1).await;
         let permit = self
             .concurrency_limiter
+            .semaphore()
             .acquire()
             .await
             .expect("semaphore is never closed");
@@ -171,8 +187,10 @@ impl S3Bucket {
 
     async fn owned_permit(&self, kind: RequestKind) -> tokio::sync::OwnedSemaphorePermit {
         let started_at = start_counting_cancelled_wait(kind);
+        self.rate_limiter.acquire(1).await;
         let permit = self
             .concurrency_limiter
+            .semaphore()
             .clone()
             .acquire_owned()
             .await
@@ -654,6 +672,7 @@ mod tests {
                 prefix_in_bucket: prefix.map(str::to_string),
                 endpoint: None,
                 concurrency_limit: NonZeroUsize::new(100).unwrap(),
+                max_requests_per_second: None,
                 max_keys_per_list_response: Some(5),
             };
             let storage = S3Bucket::new(&config).expect("remote storage init");
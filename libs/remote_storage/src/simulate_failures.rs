@@ -37,6 +37,10 @@ impl UnreliableWrapper {
         }
     }
 
+    pub(crate) fn inner(&self) -> &crate::GenericRemoteStorage {
+        &self.inner
+    }
+
     ///
     /// Common functionality for all operations.
     ///
@@ -395,6 +395,7 @@ fn create_s3_client(
             prefix_in_bucket: Some(format!("pagination_should_work_test_{random_prefix_part}/")),
             endpoint: None,
             concurrency_limit: NonZeroUsize::new(100).unwrap(),
+            max_requests_per_second: None,
             max_keys_per_list_response,
         }),
     };
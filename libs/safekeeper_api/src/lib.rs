@@ -1,3 +1,6 @@
+use std::fmt;
+use std::str::FromStr;
+
 use const_format::formatcp;
 
 /// Public API types
@@ -8,3 +11,57 @@ pub const DEFAULT_PG_LISTEN_ADDR: &str = formatcp!("127.0.0.1:{DEFAULT_PG_LISTEN
 
 pub const DEFAULT_HTTP_LISTEN_PORT: u16 = 7676;
 pub const DEFAULT_HTTP_LISTEN_ADDR: &str = formatcp!("127.0.0.1:{DEFAULT_HTTP_LISTEN_PORT}");
+
+/// Highest version of the replication protocol spoken by the safekeeper's
+/// walsender.
+///
+/// Version 1 is plain Postgres physical replication. Version 2 adds the
+/// `compression` option of START_REPLICATION, see [`WalCompression`].
+///
+/// A safekeeper advertises
+/// the highest version it speaks in the `proto_version` column of its
+/// IDENTIFY_SYSTEM response, and a consumer knowing about the versions passes
+/// the one it picked with [`negotiate_replication_protocol`] in the
+/// `proto_version` option of START_REPLICATION. Safekeepers without the column
+/// and consumers without the option speak version 1, so both sides can be
+/// upgraded independently, and the stream can grow new fields in later
+/// versions.
+pub const REPLICATION_PROTOCOL_VERSION: u32 = 2;
+
+/// Picks the highest replication protocol version both the safekeeper, which
+/// advertised `advertised` in IDENTIFY_SYSTEM, and this binary speak.
+pub fn negotiate_replication_protocol(advertised: Option<u32>) -> u32 {
+    advertised
+        .unwrap_or(1)
+        .clamp(1, REPLICATION_PROTOCOL_VERSION)
+}
+
+/// Compression of the WAL streamed by the safekeeper, requested with the
+/// `compression` option of START_REPLICATION.
+///
+/// The data of each XLogData message is then compressed on its own, while its
+/// `wal_start` and `wal_end` are still positions in the uncompressed WAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalCompression {
+    /// The data is a zstd frame.
+    Zstd,
+}
+
+impl fmt::Display for WalCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for WalCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(WalCompression::Zstd),
+            _ => anyhow::bail!("unknown WAL compression {s}"),
+        }
+    }
+}
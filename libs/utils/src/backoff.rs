@@ -1,6 +1,10 @@
 use std::fmt::{Debug, Display};
+use std::time::Duration;
 
 use futures::Future;
+use rand::Rng;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 pub const DEFAULT_BASE_BACKOFF_SECONDS: f64 = 0.1;
 pub const DEFAULT_MAX_BACKOFF_SECONDS: f64 = 3.0;
@@ -24,6 +28,91 @@ pub fn exponential_backoff_duration_seconds(n: u32, base_increment: f64, max_sec
     }
 }
 
+/// Exponential backoff: the delay before the `n`th retry is `min * multiplier^(n-1)`, up to
+/// `max`, and reduced by a random fraction of up to `jitter` of it, so that the clients
+/// which failed at the same time don't all retry at the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub min: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// Doubles the delay, from `min` to `max`, without jitter.
+    pub const fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max,
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+
+    pub const fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// `jitter` is in `0.0..=1.0`.
+    pub const fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The delay before the `n`th retry, zero for `n == 0`.
+    pub fn delay(&self, n: u32) -> Duration {
+        if n == 0 {
+            return Duration::ZERO;
+        }
+        // Past that, the delay is `max` for any sane `min` and `multiplier` anyway
+        let exp = (n - 1).min(1024) as i32;
+        let secs = (self.min.as_secs_f64() * self.multiplier.powi(exp)).min(self.max.as_secs_f64());
+        let secs = if self.jitter > 0.0 {
+            secs * (1.0 - rand::thread_rng().gen_range(0.0..=self.jitter))
+        } else {
+            secs
+        };
+        Duration::from_secs_f64(secs)
+    }
+}
+
+/// The same delays as [`exponential_backoff`] with the default settings.
+impl Default for Backoff {
+    fn default() -> Self {
+        let multiplier = 1.0 + DEFAULT_BASE_BACKOFF_SECONDS;
+        Backoff::new(
+            Duration::from_secs_f64(multiplier),
+            Duration::from_secs_f64(DEFAULT_MAX_BACKOFF_SECONDS),
+        )
+        .with_multiplier(multiplier)
+    }
+}
+
+/// How [`retry_with_options`] retries an operation.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryOptions {
+    pub backoff: Backoff,
+    /// Failed attempts after which the retries are logged as warnings.
+    pub warn_threshold: u32,
+    /// Retries after the first attempt, [`u32::MAX`] to retry until the deadline.
+    pub max_retries: u32,
+    /// No retry is started after this much time since the first attempt.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        RetryOptions {
+            backoff: Backoff::default(),
+            warn_threshold: 3,
+            max_retries: 10,
+            deadline: None,
+        }
+    }
+}
+
 /// retries passed operation until one of the following conditions are met:
 /// Encountered error is considered as permanent (non-retryable)
 /// Retries have been exhausted.
@@ -44,20 +133,70 @@ where
     O: FnMut() -> F,
     F: Future<Output = Result<T, E>>,
 {
+    let options = RetryOptions {
+        backoff: Backoff::default(),
+        warn_threshold,
+        max_retries,
+        deadline: None,
+    };
+    retry_with_options(
+        op,
+        is_permanent,
+        &options,
+        description,
+        &CancellationToken::new(),
+    )
+    .await
+    .expect("the retries are never cancelled")
+}
+
+/// Like [`retry`], with the backoff, the retry budget and the deadline of `options`.
+/// Returns [`None`] if `cancel` is cancelled before the operation succeeds, or fails
+/// for good, in which case the attempt in progress is dropped.
+pub async fn retry_with_options<T, O, F, E>(
+    mut op: O,
+    is_permanent: impl Fn(&E) -> bool,
+    options: &RetryOptions,
+    description: &str,
+    cancel: &CancellationToken,
+) -> Option<Result<T, E>>
+where
+    E: Display + Debug,
+    O: FnMut() -> F,
+    F: Future<Output = Result<T, E>>,
+{
+    let RetryOptions {
+        backoff,
+        warn_threshold,
+        max_retries,
+        deadline,
+    } = *options;
+    let started_at = Instant::now();
     let mut attempts = 0;
     loop {
-        let result = op().await;
+        let result = tokio::select! {
+            result = op() => result,
+            _ = cancel.cancelled() => return None,
+        };
+        let delay = backoff.delay(attempts);
+        let past_deadline = deadline.map_or(false, |d| started_at.elapsed() + delay > d);
         match result {
             Ok(_) => {
                 if attempts > 0 {
                     tracing::info!("{description} succeeded after {attempts} retries");
                 }
-                return result;
+                return Some(result);
             }
 
             // These are "permanent" errors that should not be retried.
             Err(ref e) if is_permanent(e) => {
-                return result;
+                return Some(result);
+            }
+            Err(ref err) if past_deadline => {
+                tracing::warn!(
+                    "{description} still failed at the deadline after {attempts} retries, giving up: {err:?}"
+                );
+                return Some(result);
             }
             // Assume that any other failure might be transient, and the operation might
             // succeed if we just keep trying.
@@ -72,16 +211,14 @@ where
                 tracing::warn!(
                     "{description} still failed after {attempts} retries, giving up: {err:?}"
                 );
-                return result;
+                return Some(result);
             }
         }
         // sleep and retry
-        exponential_backoff(
-            attempts,
-            DEFAULT_BASE_BACKOFF_SECONDS,
-            DEFAULT_MAX_BACKOFF_SECONDS,
-        )
-        .await;
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel.cancelled() => return None,
+        }
         attempts += 1;
     }
 }
@@ -120,6 +257,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn backoff_delays() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<_> = (0..6).map(|n| backoff.delay(n).as_millis()).collect();
+        assert_eq!(delays, [0, 100, 200, 400, 800, 1000]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        let backoff = backoff.with_multiplier(1.5).with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = backoff.delay(2);
+            assert!(
+                delay >= Duration::from_millis(75) && delay <= Duration::from_millis(150),
+                "{delay:?}"
+            );
+        }
+
+        // the same sequence as exponential_backoff
+        for n in 0..100 {
+            let expected = exponential_backoff_duration_seconds(
+                n,
+                DEFAULT_BASE_BACKOFF_SECONDS,
+                DEFAULT_MAX_BACKOFF_SECONDS,
+            );
+            let delay = Backoff::default().delay(n).as_secs_f64();
+            assert!(
+                (delay - expected).abs() < 1e-6,
+                "{n}: {delay} != {expected}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_until_deadline() {
+        let count = Mutex::new(0);
+        let options = RetryOptions {
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(1)),
+            warn_threshold: 1,
+            max_retries: u32::MAX,
+            deadline: Some(Duration::from_millis(2500)),
+        };
+        let result = retry_with_options(
+            || async {
+                *count.lock().await += 1;
+                Result::<(), io::Error>::Err(io::Error::from(io::ErrorKind::Other))
+            },
+            |_e| false,
+            &options,
+            "work",
+            &CancellationToken::new(),
+        )
+        .await;
+
+        assert!(result.unwrap().is_err());
+        // twice at 0s, as the first retry is immediate, then at 1s and 2s
+        assert_eq!(*count.lock().await, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_cancelled() {
+        let cancel = CancellationToken::new();
+        let options = RetryOptions {
+            max_retries: u32::MAX,
+            ..Default::default()
+        };
+        let result = retry_with_options(
+            || async {
+                cancel.cancel();
+                Result::<(), io::Error>::Err(io::Error::from(io::ErrorKind::Other))
+            },
+            |_e| false,
+            &options,
+            "work",
+            &cancel,
+        )
+        .await;
+
+        assert!(result.is_none());
+    }
+
     #[tokio::test(start_paused = true)]
     async fn retry_always_error() {
         let count = Mutex::new(0);
@@ -0,0 +1,264 @@
+//! Buffered writing of files, with a running CRC32C of the data written.
+//!
+//! [`ChecksummedWriter`] buffers the writes to a file, and keeps the CRC32C of
+//! everything written through it, so that the written data can be checked against
+//! what's read back, with [`verify_checksum`]. When it's finished, the file is made
+//! durable according to a [`SyncPolicy`], which is also what the writers that
+//! don't need the buffering, e.g. the async ones, use to sync their files.
+//!
+//! The files of a known size can be preallocated with [`preallocate_file`], so that
+//! running out of disk space shows when they're created, not halfway through.
+
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+/// Size of the buffer of a [`ChecksummedWriter`] by default.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How a file is made durable after it's written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Don't sync, e.g. in tests, or with the safekeeper's `--no-sync`.
+    NoSync,
+    /// `fdatasync`: the data, and the metadata needed to read it back, e.g. the
+    /// size of the file.
+    Data,
+    /// `fsync`: the data, and all the metadata.
+    All,
+}
+
+impl SyncPolicy {
+    /// [`SyncPolicy::NoSync`] if `no_sync` is set, `self` otherwise.
+    pub fn unless(self, no_sync: bool) -> Self {
+        if no_sync {
+            SyncPolicy::NoSync
+        } else {
+            self
+        }
+    }
+
+    pub fn sync<F: SyncFile + ?Sized>(self, file: &F) -> io::Result<()> {
+        match self {
+            SyncPolicy::NoSync => Ok(()),
+            SyncPolicy::Data => file.sync_data(),
+            SyncPolicy::All => file.sync_all(),
+        }
+    }
+
+    pub async fn sync_async(self, file: &tokio::fs::File) -> io::Result<()> {
+        match self {
+            SyncPolicy::NoSync => Ok(()),
+            SyncPolicy::Data => file.sync_data().await,
+            SyncPolicy::All => file.sync_all().await,
+        }
+    }
+}
+
+/// A file that can be synced to disk.
+pub trait SyncFile {
+    fn sync_data(&self) -> io::Result<()>;
+
+    fn sync_all(&self) -> io::Result<()>;
+
+    /// See [`preallocate_file`].
+    fn preallocate(&self, len: u64) -> io::Result<()>;
+}
+
+impl SyncFile for std::fs::File {
+    fn sync_data(&self) -> io::Result<()> {
+        std::fs::File::sync_data(self)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        std::fs::File::sync_all(self)
+    }
+
+    fn preallocate(&self, len: u64) -> io::Result<()> {
+        preallocate_file(self, len)
+    }
+}
+
+/// Allocates the disk space of the first `len` bytes of the file, without changing
+/// its size or its data, so that the writes up to `len` don't fail with `ENOSPC`,
+/// and the file is less fragmented. Does nothing where the OS or the filesystem
+/// doesn't support it.
+#[cfg(target_os = "linux")]
+pub fn preallocate_file<F: AsRawFd + ?Sized>(file: &F, len: u64) -> io::Result<()> {
+    use nix::fcntl::{fallocate, FallocateFlags};
+
+    if len == 0 {
+        return Ok(());
+    }
+    let len = i64::try_from(len).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    match fallocate(
+        file.as_raw_fd(),
+        FallocateFlags::FALLOC_FL_KEEP_SIZE,
+        0,
+        len,
+    ) {
+        Ok(()) => Ok(()),
+        // e.g. a tmpfs of an old kernel
+        Err(nix::errno::Errno::EOPNOTSUPP) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// See the Linux version.
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate_file<F: AsRawFd + ?Sized>(_file: &F, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// A buffered writer that keeps the CRC32C of the data written through it.
+///
+/// The data is only guaranteed to reach the file when the writer is flushed,
+/// finished, or turned back into the file, like with a [`BufWriter`]. Dropping
+/// the writer flushes it too, ignoring the errors.
+pub struct ChecksummedWriter<W: Write> {
+    inner: BufWriter<W>,
+    crc: u32,
+    written: u64,
+}
+
+impl<W: Write> ChecksummedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        ChecksummedWriter {
+            inner: BufWriter::with_capacity(capacity, inner),
+            crc: 0,
+            written: 0,
+        }
+    }
+
+    /// CRC32C of the data written so far.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Flushes the buffer, and returns the file, and the CRC32C of the data written.
+    pub fn into_inner(self) -> io::Result<(W, u32)> {
+        let crc = self.crc;
+        let inner = self.inner.into_inner().map_err(|e| e.into_error())?;
+        Ok((inner, crc))
+    }
+
+    /// Returns the file without writing out the buffered data, e.g. to remove an
+    /// unfinished file.
+    pub fn discard(self) -> W {
+        self.inner.into_parts().0
+    }
+}
+
+impl<W: Write + SyncFile> ChecksummedWriter<W> {
+    /// Preallocates the first `len` bytes of the file, see [`preallocate_file`].
+    pub fn preallocate(&self, len: u64) -> io::Result<()> {
+        self.get_ref().preallocate(len)
+    }
+
+    /// Flushes the buffer, and syncs the file according to `policy`. Returns the
+    /// file, and the CRC32C of the data written.
+    pub fn finish(self, policy: SyncPolicy) -> io::Result<(W, u32)> {
+        let (inner, crc) = self.into_inner()?;
+        policy.sync(&inner)?;
+        Ok((inner, crc))
+    }
+}
+
+impl<W: Write> Write for ChecksummedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc32c::crc32c_append(self.crc, &buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads `len` bytes, and checks that their CRC32C is `expected`, e.g. the one of a
+/// [`ChecksummedWriter`] which wrote them. Fails with [`io::ErrorKind::InvalidData`]
+/// if it's not.
+pub fn verify_checksum<R: Read>(reader: R, len: u64, expected: u32) -> io::Result<()> {
+    let mut reader = reader.take(len);
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut crc = 0;
+    let mut read = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        crc = crc32c::crc32c_append(crc, &buf[..n]);
+        read += n as u64;
+    }
+    if read != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("read {read} bytes of {len} to verify"),
+        ));
+    }
+    if crc != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum mismatch: expected {expected:08x}, got {crc:08x}"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom};
+
+    use super::*;
+
+    #[test]
+    fn write_and_verify() -> io::Result<()> {
+        let file = tempfile::tempfile()?;
+        let mut writer = ChecksummedWriter::with_capacity(16, file);
+        writer.preallocate(1024)?;
+        for i in 0..100u32 {
+            writer.write_all(&i.to_be_bytes())?;
+        }
+        assert_eq!(writer.written(), 400);
+        let (mut file, crc) = writer.finish(SyncPolicy::Data)?;
+
+        // preallocation doesn't change the size
+        assert_eq!(file.metadata()?.len(), 400);
+        let data: Vec<u8> = (0..100u32).flat_map(u32::to_be_bytes).collect();
+        assert_eq!(crc, crc32c::crc32c(&data));
+
+        file.seek(SeekFrom::Start(0))?;
+        verify_checksum(&mut file, 400, crc)?;
+
+        // a corrupted byte
+        file.seek(SeekFrom::Start(123))?;
+        file.write_all(&[0xff])?;
+        file.seek(SeekFrom::Start(0))?;
+        let err = verify_checksum(&mut file, 400, crc).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a truncated file
+        file.set_len(200)?;
+        file.seek(SeekFrom::Start(0))?;
+        let err = verify_checksum(&mut file, 400, crc).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+}
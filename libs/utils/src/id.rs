@@ -4,24 +4,52 @@ use std::{fmt, str::FromStr};
 use anyhow::Context;
 use hex::FromHex;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+use crate::region::{self, RegionError};
+
+#[derive(Error, Debug, PartialEq, Eq)]
 pub enum IdError {
     #[error("invalid id length {0}")]
     SliceParseError(usize),
+
+    #[error("invalid id: {0}")]
+    Hex(#[from] hex::FromHexError),
+
+    #[error("invalid character {0:?} in short id")]
+    Base62Char(char),
+
+    #[error("short id out of range")]
+    Base62Overflow,
+
+    #[error("invalid id length {0}, expected 32 hex or 22 base62 characters")]
+    StrLength(usize),
 }
 
+/// Digits of the short form of the ids, in ASCII order, so that the short forms
+/// sort like the ids.
+const BASE62_DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Length of the short form: 62^22 > 2^128.
+const BASE62_LEN: usize = 22;
+
 /// Neon ID is a 128-bit random ID.
 /// Used to represent various identifiers. Provides handy utility methods and impls.
 ///
-/// NOTE: It (de)serializes as an array of hex bytes, so the string representation would look
-/// like `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`.
+/// It's printed as 32 hex digits, `ad50847381e248feaac9876cc71ae418`, and has a
+/// short form of 22 base62 digits for the user-facing tools and URLs,
+/// `5H2T5HrfwT5WUbMY7Op5Qm`. Parsing accepts both.
 ///
-/// Use `#[serde_as(as = "DisplayFromStr")]` to (de)serialize it as hex string instead: `ad50847381e248feaac9876cc71ae418`.
-/// Check the `serde_with::serde_as` documentation for options for more complex types.
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
+/// NOTE: Human-readable formats, like JSON, (de)serialize it as a hex string,
+/// accepting the short form too, see [`short`] to serialize it in the short form,
+/// while binary formats, like bincode, as its 16 bytes, with no length prefix,
+/// which is half the size of the hex string. The human-readable formats still
+/// accept the array of bytes, e.g.
+/// `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`, which
+/// is how it used to be serialized.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct Id([u8; 16]);
 
 impl Id {
@@ -60,13 +88,48 @@ impl Id {
         }
         unsafe { String::from_utf8_unchecked(buf) }
     }
+
+    fn base62_encode(&self) -> String {
+        let mut value = u128::from_be_bytes(self.0);
+        let mut buf = [b'0'; BASE62_LEN];
+        for digit in buf.iter_mut().rev() {
+            *digit = BASE62_DIGITS[(value % 62) as usize];
+            value /= 62;
+        }
+        String::from_utf8(buf.to_vec()).expect("base62 digits are ASCII")
+    }
+
+    fn base62_decode(s: &str) -> Result<Id, IdError> {
+        if s.len() != BASE62_LEN {
+            return Err(IdError::StrLength(s.len()));
+        }
+        let mut value: u128 = 0;
+        for c in s.chars() {
+            let digit = match c {
+                '0'..='9' => c as u8 - b'0',
+                'A'..='Z' => c as u8 - b'A' + 10,
+                'a'..='z' => c as u8 - b'a' + 36,
+                _ => return Err(IdError::Base62Char(c)),
+            };
+            value = value
+                .checked_mul(62)
+                .and_then(|v| v.checked_add(digit as u128))
+                .ok_or(IdError::Base62Overflow)?;
+        }
+        Ok(Id(value.to_be_bytes()))
+    }
 }
 
 impl FromStr for Id {
-    type Err = hex::FromHexError;
+    type Err = IdError;
 
+    /// Parses either form, the 32 hex digits or the 22 base62 ones.
     fn from_str(s: &str) -> Result<Id, Self::Err> {
-        Self::from_hex(s)
+        match s.len() {
+            BASE62_LEN => Self::base62_decode(s),
+            32 => Ok(Self::from_hex(s)?),
+            len => Err(IdError::StrLength(len)),
+        }
     }
 }
 
@@ -111,6 +174,54 @@ impl fmt::Debug for Id {
     }
 }
 
+impl Serialize for Id {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            // Same as the derived impl, a tuple of 16 bytes
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(IdVisitor)
+        } else {
+            <[u8; 16]>::deserialize(deserializer).map(Id)
+        }
+    }
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = Id;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a hex or base62 string of 16 bytes, or an array of 16 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Id, E> {
+        Id::from_str(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Id, A::Error> {
+        let mut id = [0u8; 16];
+        for (i, b) in id.iter_mut().enumerate() {
+            *b = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(de::Error::invalid_length(17, &self));
+        }
+        Ok(Id(id))
+    }
+}
+
 macro_rules! id_newtype {
     ($t:ident) => {
         impl $t {
@@ -133,10 +244,19 @@ macro_rules! id_newtype {
             pub const fn from_array(b: [u8; 16]) -> Self {
                 $t(Id(b))
             }
+
+            /// The short form of the id, 22 base62 digits, see [`Id`].
+            pub fn to_base62(&self) -> String {
+                self.0.base62_encode()
+            }
+
+            pub fn from_base62(s: &str) -> Result<$t, IdError> {
+                Ok($t(Id::base62_decode(s)?))
+            }
         }
 
         impl FromStr for $t {
-            type Err = hex::FromHexError;
+            type Err = IdError;
 
             fn from_str(s: &str) -> Result<$t, Self::Err> {
                 let value = Id::from_str(s)?;
@@ -207,9 +327,8 @@ macro_rules! id_newtype {
 /// limitations. A Neon timeline is identified by a 128-bit ID, which
 /// is usually printed out as a hex string.
 ///
-/// NOTE: It (de)serializes as an array of hex bytes, so the string representation would look
-/// like `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`.
-/// See [`Id`] for alternative ways to serialize it.
+/// NOTE: It (de)serializes as a hex string in human-readable formats, and as
+/// 16 bytes in binary ones, see [`Id`].
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct TimelineId(Id);
 
@@ -230,9 +349,8 @@ impl TryFrom<Option<&OsStr>> for TimelineId {
 /// Neon Tenant Id represents identifiar of a particular tenant.
 /// Is used for distinguishing requests and data belonging to different users.
 ///
-/// NOTE: It (de)serializes as an array of hex bytes, so the string representation would look
-/// like `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`.
-/// See [`Id`] for alternative ways to serialize it.
+/// NOTE: It (de)serializes as a hex string in human-readable formats, and as
+/// 16 bytes in binary ones, see [`Id`].
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct TenantId(Id);
 
@@ -241,14 +359,44 @@ id_newtype!(TenantId);
 /// Neon Connection Id identifies long-lived connections (for example a pagestream
 /// connection with the page_service). Is used for better logging and tracing
 ///
-/// NOTE: It (de)serializes as an array of hex bytes, so the string representation would look
-/// like `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`.
-/// See [`Id`] for alternative ways to serialize it.
+/// NOTE: It (de)serializes as a hex string in human-readable formats, and as
+/// 16 bytes in binary ones, see [`Id`].
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct ConnectionId(Id);
 
 id_newtype!(ConnectionId);
 
+/// (De)serializes an id in its short form in human-readable formats, with
+/// `#[serde(with = "utils::id::short")]`, e.g. in the responses of user-facing
+/// APIs. Both forms are accepted when deserializing, and the binary formats are
+/// unaffected.
+pub mod short {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Id;
+
+    pub fn serialize<T, S>(id: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        let id = Id::from_slice(id.as_ref()).map_err(serde::ser::Error::custom)?;
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&id.base62_encode())
+        } else {
+            id.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<[u8; 16]>,
+        D: Deserializer<'de>,
+    {
+        Id::deserialize(deserializer).map(|id| T::from(id.0))
+    }
+}
+
 // A pair uniquely identifying Neon instance.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TenantTimelineId {
@@ -311,22 +459,162 @@ impl fmt::Display for NodeId {
     }
 }
 
-#[derive(
-    Clone, Copy, Eq, Default, Ord, PartialEq, PartialOrd, Hash, Debug, Serialize, Deserialize,
-)]
+/// The number of a region, see [`crate::region`] for their names.
+///
+/// It's printed as the number, or, with `{:#}`, as the name of the region if it has
+/// one. It's parsed from either, and the names are also accepted when it's
+/// deserialized from human-readable formats.
+#[derive(Clone, Copy, Eq, Default, Ord, PartialEq, PartialOrd, Hash, Debug, Serialize)]
 #[serde(transparent)]
 pub struct RegionId(pub u8);
 
 impl FromStr for RegionId {
-    type Err = <u8 as ::core::str::FromStr>::Err;
+    type Err = RegionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(u8::from_str(s)?))
+        region::registry().parse(s)
     }
 }
 
 impl fmt::Display for RegionId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match region::registry().name(*self) {
+            Some(name) if f.alternate() => f.write_str(name),
+            _ => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RegionId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(RegionIdVisitor)
+        } else {
+            u8::deserialize(deserializer).map(RegionId)
+        }
+    }
+}
+
+struct RegionIdVisitor;
+
+impl<'de> Visitor<'de> for RegionIdVisitor {
+    type Value = RegionId;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a region id, or the name of a region")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<RegionId, E> {
+        u8::try_from(v)
+            .map(RegionId)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<RegionId, E> {
+        u8::try_from(v)
+            .map(RegionId)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<RegionId, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_ser::BeSer;
+
+    #[test]
+    fn region_id_serde() {
+        assert_eq!(serde_json::to_string(&RegionId(3)).unwrap(), "3");
+        assert_eq!(serde_json::from_str::<RegionId>("3").unwrap(), RegionId(3));
+        assert_eq!(
+            serde_json::from_str::<RegionId>("\"3\"").unwrap(),
+            RegionId(3)
+        );
+        serde_json::from_str::<RegionId>("256").unwrap_err();
+        // without a registry, there are no names
+        serde_json::from_str::<RegionId>("\"us-east\"").unwrap_err();
+
+        let bytes = RegionId(3).ser().unwrap();
+        assert_eq!(bytes, [3]);
+        assert_eq!(RegionId::des(&bytes).unwrap(), RegionId(3));
+    }
+
+    #[test]
+    fn base62_round_trip() {
+        let id: TenantId = "ad50847381e248feaac9876cc71ae418".parse().unwrap();
+        assert_eq!(id.to_base62(), "5H2T5HrfwT5WUbMY7Op5Qm");
+        assert_eq!(TenantId::from_base62("5H2T5HrfwT5WUbMY7Op5Qm").unwrap(), id);
+        assert_eq!("5H2T5HrfwT5WUbMY7Op5Qm".parse::<TenantId>().unwrap(), id);
+
+        for _ in 0..100 {
+            let id = TimelineId::generate();
+            let short = id.to_base62();
+            assert_eq!(short.len(), 22);
+            assert_eq!(short.parse::<TimelineId>().unwrap(), id);
+        }
+
+        // the short forms sort like the ids
+        let min = TimelineId::from([0; 16]);
+        let max = TimelineId::from([0xff; 16]);
+        assert_eq!(min.to_base62(), "0".repeat(22));
+        assert_eq!(max.to_base62(), "7n42DGM5Tflk9n8mt7Fhc7");
+        assert!(min.to_base62() < id.to_base62() && id.to_base62() < max.to_base62());
+
+        assert_eq!(
+            TimelineId::from_base62("7n42DGM5Tflk9n8mt7Fhc8"),
+            Err(IdError::Base62Overflow)
+        );
+        assert_eq!(
+            TimelineId::from_base62("5H2T5HrfwT5WUbMY7Op5Q-"),
+            Err(IdError::Base62Char('-'))
+        );
+        assert_eq!("5H2T".parse::<TimelineId>(), Err(IdError::StrLength(4)));
+    }
+
+    #[test]
+    fn base62_serde() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Response {
+            #[serde(with = "short")]
+            tenant_id: TenantId,
+        }
+
+        let tenant_id: TenantId = "ad50847381e248feaac9876cc71ae418".parse().unwrap();
+        let json = serde_json::to_string(&Response { tenant_id }).unwrap();
+        assert_eq!(json, r#"{"tenant_id":"5H2T5HrfwT5WUbMY7Op5Qm"}"#);
+        assert_eq!(
+            serde_json::from_str::<Response>(&json).unwrap(),
+            Response { tenant_id }
+        );
+
+        // the default serialization accepts the short form too
+        assert_eq!(
+            serde_json::from_str::<TenantId>("\"5H2T5HrfwT5WUbMY7Op5Qm\"").unwrap(),
+            tenant_id
+        );
+    }
+
+    #[test]
+    fn id_serde() {
+        let hex = "ad50847381e248feaac9876cc71ae418";
+        let id: TimelineId = hex.parse().unwrap();
+
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{hex}\""));
+        assert_eq!(serde_json::from_str::<TimelineId>(&json).unwrap(), id);
+
+        // the former representation is still accepted
+        let array = "[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]";
+        assert_eq!(serde_json::from_str::<TimelineId>(array).unwrap(), id);
+        serde_json::from_str::<TimelineId>("[173,80]").unwrap_err();
+        serde_json::from_str::<TimelineId>("\"ad50\"").unwrap_err();
+
+        let bytes = id.ser().unwrap();
+        assert_eq!(bytes, id.as_arr());
+        assert_eq!(TimelineId::des(&bytes).unwrap(), id);
     }
 }
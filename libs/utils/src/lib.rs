@@ -19,11 +19,16 @@ pub mod bin_ser;
 // helper functions for creating and fsyncing
 pub mod crashsafe;
 
+// buffered file writer with a running checksum, and the fsync policies
+pub mod checksummed_writer;
+
 // common authentication routines
 pub mod auth;
 
 // utility functions and helper traits for unified unique id generation/serialization etc.
 pub mod id;
+// names and metadata of the regions
+pub mod region;
 // http endpoint utils
 pub mod http;
 
@@ -40,6 +45,9 @@ pub mod shutdown;
 // Utility for binding TcpListeners with proper socket options.
 pub mod tcp_listener;
 
+// Utility for binding UnixListeners over stale socket files.
+pub mod unix_listener;
+
 // Utility for putting a raw file descriptor into non-blocking mode
 pub mod nonblock;
 
@@ -62,9 +70,18 @@ pub mod tracing_span_assert;
 
 pub mod rate_limit;
 
+/// Limits the rate of operations, waiting for their turn.
+pub mod token_bucket;
+
+/// A semaphore with a number of permits that can be changed at runtime.
+pub mod resizable_semaphore;
+
 /// Simple once-barrier and a guard which keeps barrier awaiting.
 pub mod completion;
 
+/// Named tasks attached to tenants, timelines etc., cancelled and joined together.
+pub mod task_scope;
+
 /// Reporting utilities
 pub mod error;
 
@@ -103,8 +120,58 @@ mod failpoint_macro_helpers {
         tokio::time::sleep(d).await;
         tracing::info!("failpoint {:?}: sleep done", name);
     }
+
+    /// Failpoint for async code paths that supports both latency injection and
+    /// returning an error:
+    ///
+    /// * `return(sleep=200)` sleeps for 200 milliseconds, then continues.
+    /// * `return` or `return(error=msg)` evaluates `$on_error` with the error
+    ///   message bound to `$msg`.
+    ///
+    /// ```ignore
+    /// utils::failpoint_async!("my-failpoint", |msg| anyhow::bail!(msg));
+    /// ```
+    #[macro_export]
+    macro_rules! failpoint_async {
+        ($name:literal, |$msg:ident| $on_error:expr) => {{
+            let action = (|| {
+                ::fail::fail_point!($name, |x| ::std::option::Option::Some(x));
+                ::std::option::Option::None
+            })();
+
+            if let ::std::option::Option::Some(action) = action {
+                if let ::std::option::Option::Some($msg) =
+                    $crate::failpoint_async_helper($name, action).await
+                {
+                    $on_error
+                }
+            }
+        }};
+    }
+
+    // Helper function used by the `failpoint_async` macro. Returns the error
+    // message if the action asks for an error.
+    pub async fn failpoint_async_helper(
+        name: &'static str,
+        action: Option<String>,
+    ) -> Option<String> {
+        match action.as_deref() {
+            Some(action) if action.starts_with("sleep=") => {
+                let millis = action["sleep=".len()..].to_string();
+                failpoint_sleep_helper(name, millis).await;
+                None
+            }
+            action => {
+                let msg = action
+                    .map(|a| a.strip_prefix("error=").unwrap_or(a))
+                    .unwrap_or("injected error");
+                tracing::info!("failpoint {:?}: returning error {:?}", name, msg);
+                Some(format!("failpoint {name}: {msg}"))
+            }
+        }
+    }
 }
-pub use failpoint_macro_helpers::failpoint_sleep_helper;
+pub use failpoint_macro_helpers::{failpoint_async_helper, failpoint_sleep_helper};
 
 /// This is a shortcut to embed git sha into binaries and avoid copying the same build script to all packages
 ///
@@ -1,7 +1,8 @@
+use std::fmt::Write as _;
 use std::str::FromStr;
 
 use anyhow::Context;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use strum_macros::{EnumString, EnumVariantNames};
 
 #[derive(EnumString, EnumVariantNames, Eq, PartialEq, Debug, Clone, Copy)]
@@ -66,32 +67,115 @@ pub enum TracingErrorLayerEnablement {
     EnableWithRustLogFilter,
 }
 
+/// Fields added to every log line, see [`set_global_fields`].
+static GLOBAL_FIELDS: OnceCell<Vec<(String, String)>> = OnceCell::new();
+
+/// Sets fields added to every log line of the process, e.g. the region the node runs
+/// in, after the fields of the event in the plain format, and first in the JSON one.
+/// This must be called at most once, at startup.
+pub fn set_global_fields(fields: Vec<(String, String)>) -> anyhow::Result<()> {
+    GLOBAL_FIELDS
+        .set(fields)
+        .map_err(|_| anyhow::anyhow!("global log fields are already set"))
+}
+
+/// Formats the events with `F`, and adds the [`GLOBAL_FIELDS`] to the line.
+struct WithGlobalFields<F>(F);
+
+impl<S, N, F> tracing_subscriber::fmt::FormatEvent<S, N> for WithGlobalFields<F>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    N: for<'a> tracing_subscriber::fmt::FormatFields<'a> + 'static,
+    F: tracing_subscriber::fmt::FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &tracing_subscriber::fmt::FmtContext<'_, S, N>,
+        mut writer: tracing_subscriber::fmt::format::Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        let Some(fields) = GLOBAL_FIELDS.get() else {
+            return self.0.format_event(ctx, writer, event);
+        };
+        let mut line = String::new();
+        self.0.format_event(
+            ctx,
+            tracing_subscriber::fmt::format::Writer::new(&mut line),
+            event,
+        )?;
+        writer.write_str(&add_global_fields(&line, fields))
+    }
+}
+
+fn add_global_fields(line: &str, fields: &[(String, String)]) -> String {
+    let mut with_fields = String::with_capacity(line.len() + 32);
+    if let Some(object) = line.strip_prefix('{') {
+        with_fields.push('{');
+        for (name, value) in fields {
+            let _ = write!(
+                with_fields,
+                "{}:{},",
+                serde_json::Value::from(name.as_str()),
+                serde_json::Value::from(value.as_str())
+            );
+        }
+        with_fields.push_str(object);
+    } else {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        with_fields.push_str(content);
+        for (name, value) in fields {
+            let _ = write!(with_fields, " {name}={value}");
+        }
+        with_fields.push_str(newline);
+    }
+    with_fields
+}
+
+/// Handle to replace the filter of the log output at runtime, see [`reload_log_filter`].
+static LOG_FILTER_RELOAD_HANDLE: OnceCell<
+    tracing_subscriber::reload::Handle<tracing_subscriber::EnvFilter, tracing_subscriber::Registry>,
+> = OnceCell::new();
+
+// We fall back to printing all spans at info-level or above if
+// the RUST_LOG environment variable is not set.
+fn rust_log_env_filter() -> tracing_subscriber::EnvFilter {
+    tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+}
+
 pub fn init(
     log_format: LogFormat,
     tracing_error_layer_enablement: TracingErrorLayerEnablement,
 ) -> anyhow::Result<()> {
-    // We fall back to printing all spans at info-level or above if
-    // the RUST_LOG environment variable is not set.
-    let rust_log_env_filter = || {
-        tracing_subscriber::EnvFilter::try_from_default_env()
-            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
-    };
-
     // NB: the order of the with() calls does not matter.
     // See https://docs.rs/tracing-subscriber/0.3.16/tracing_subscriber/layer/index.html#per-layer-filtering
     use tracing_subscriber::prelude::*;
     let r = tracing_subscriber::registry();
     let r = r.with({
-        let log_layer = tracing_subscriber::fmt::layer()
+        let format = tracing_subscriber::fmt::format()
             .with_target(false)
+            .with_ansi(false);
+        let log_layer = tracing_subscriber::fmt::layer()
             .with_ansi(false)
             .with_writer(std::io::stdout);
         let log_layer = match log_format {
-            LogFormat::Json => log_layer.json().boxed(),
-            LogFormat::Plain => log_layer.boxed(),
-            LogFormat::Test => log_layer.with_test_writer().boxed(),
+            LogFormat::Json => log_layer
+                .fmt_fields(tracing_subscriber::fmt::format::JsonFields::new())
+                .event_format(WithGlobalFields(format.json()))
+                .boxed(),
+            LogFormat::Plain => log_layer.event_format(WithGlobalFields(format)).boxed(),
+            LogFormat::Test => log_layer
+                .with_test_writer()
+                .event_format(WithGlobalFields(format))
+                .boxed(),
         };
-        log_layer.with_filter(rust_log_env_filter())
+        let (filter, reload_handle) = tracing_subscriber::reload::Layer::new(rust_log_env_filter());
+        // Only the first call to init() installs the global subscriber
+        let _ = LOG_FILTER_RELOAD_HANDLE.set(reload_handle);
+        log_layer.with_filter(filter)
     });
     let r = r.with(TracingEventCountLayer(&TRACING_EVENT_COUNT).with_filter(rust_log_env_filter()));
     match tracing_error_layer_enablement {
@@ -104,6 +188,32 @@ pub fn init(
     Ok(())
 }
 
+fn parse_log_filter(directives: &str) -> anyhow::Result<tracing_subscriber::EnvFilter> {
+    tracing_subscriber::EnvFilter::try_new(directives)
+        .with_context(|| format!("invalid log filter {directives:?}"))
+}
+
+/// Checks that the log filter directives are valid, for validating configs.
+pub fn check_log_filter(directives: &str) -> anyhow::Result<()> {
+    parse_log_filter(directives).map(|_| ())
+}
+
+/// Replaces the filter of the log output, given as directives in the `RUST_LOG` syntax.
+/// `None` restores the filter from the `RUST_LOG` environment variable.
+///
+/// Only affects which events are logged, the event counting metric still uses `RUST_LOG`.
+pub fn reload_log_filter(directives: Option<&str>) -> anyhow::Result<()> {
+    let filter = match directives {
+        Some(directives) => parse_log_filter(directives)?,
+        None => rust_log_env_filter(),
+    };
+    LOG_FILTER_RELOAD_HANDLE
+        .get()
+        .context("logging is not initialized")?
+        .reload(filter)
+        .context("failed to replace the log filter")
+}
+
 /// Disable the default rust panic hook by using `set_hook`.
 ///
 /// For neon binaries, the assumption is that tracing is configured before with [`init`], after
@@ -220,7 +330,7 @@ impl std::fmt::Debug for PrettyLocation<'_, '_> {
 mod tests {
     use metrics::{core::Opts, IntCounterVec};
 
-    use super::TracingEventCountLayer;
+    use super::{add_global_fields, TracingEventCountLayer};
 
     #[test]
     fn tracing_event_count_metric() {
@@ -244,4 +354,17 @@ mod tests {
         assert_eq!(counter_vec.with_label_values(&["warn"]).get(), 1);
         assert_eq!(counter_vec.with_label_values(&["error"]).get(), 1);
     }
+
+    #[test]
+    fn global_fields() {
+        let fields = [("node_region".to_string(), "us-east".to_string())];
+        assert_eq!(
+            add_global_fields("2023-07-01T00:00:00Z  INFO foo: bar x=1\n", &fields),
+            "2023-07-01T00:00:00Z  INFO foo: bar x=1 node_region=us-east\n"
+        );
+        assert_eq!(
+            add_global_fields("{\"level\":\"INFO\"}\n", &fields),
+            "{\"node_region\":\"us-east\",\"level\":\"INFO\"}\n"
+        );
+    }
 }
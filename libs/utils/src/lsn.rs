@@ -29,6 +29,12 @@ impl Lsn {
     /// Invalid value for InvalidXLogRecPtr, as defined in xlogdefs.h
     pub const INVALID: Lsn = Lsn(0);
 
+    /// Add a number, returning None on overflow.
+    pub fn checked_add<T: Into<u64>>(self, other: T) -> Option<Lsn> {
+        let other: u64 = other.into();
+        self.0.checked_add(other).map(Lsn)
+    }
+
     /// Subtract a number, returning None on overflow.
     pub fn checked_sub<T: Into<u64>>(self, other: T) -> Option<Lsn> {
         let other: u64 = other.into();
@@ -79,6 +85,27 @@ impl Lsn {
         self.0 / seg_sz as u64
     }
 
+    /// Compute LSN of the start of segment `segno`, the inverse of
+    /// [`Lsn::segment_number`]. Mimics postgres XLogSegNoOffsetToRecPtr macro.
+    #[inline]
+    pub fn from_segment_number(segno: u64, seg_sz: usize) -> Lsn {
+        Lsn(segno * seg_sz as u64)
+    }
+
+    /// Compute the bytes remaining in this segment
+    ///
+    /// If the LSN is already at the segment boundary, it will return `seg_sz`.
+    #[inline]
+    pub fn remaining_in_segment(self, seg_sz: usize) -> u64 {
+        seg_sz as u64 - self.segment_offset(seg_sz) as u64
+    }
+
+    /// Return if the LSN is at the start of a segment
+    #[inline]
+    pub fn is_segment_start(self, seg_sz: usize) -> bool {
+        self.segment_offset(seg_sz) == 0
+    }
+
     /// Compute the offset into a block
     #[inline]
     pub fn block_offset(self) -> u64 {
@@ -131,6 +158,26 @@ impl Lsn {
         *self == self.align()
     }
 
+    /// Round the LSN up to a multiple of `sz`, which must be a power of two,
+    /// returning None on overflow.
+    pub fn checked_align_up<T: Into<u64>>(self, sz: T) -> Option<Lsn> {
+        let sz: u64 = sz.into();
+        debug_assert!(sz.is_power_of_two());
+        self.0.checked_add(sz - 1).map(|n| Lsn(n & !(sz - 1)))
+    }
+
+    /// Round the LSN down to a multiple of `sz`, which must be a power of two.
+    pub fn align_down<T: Into<u64>>(self, sz: T) -> Lsn {
+        let sz: u64 = sz.into();
+        debug_assert!(sz.is_power_of_two());
+        Lsn(self.0 & !(sz - 1))
+    }
+
+    /// Return if the LSN is a multiple of `sz`, which must be a power of two.
+    pub fn is_aligned_to<T: Into<u64>>(self, sz: T) -> bool {
+        self.align_down(sz) == self
+    }
+
     /// Return if the LSN is valid
     /// mimics postgres XLogRecPtrIsInvalid macro
     pub fn is_valid(self) -> bool {
@@ -326,6 +373,9 @@ mod tests {
             Lsn(1245)
         );
 
+        assert_eq!(Lsn(1234).checked_add(11u64), Some(Lsn(1245)));
+        assert_eq!(Lsn(u64::MAX).checked_add(1u64), None);
+
         assert_eq!(Lsn(1234).checked_sub(1233u64), Some(Lsn(1)));
         assert_eq!(Lsn(1234).checked_sub(1235u64), None);
 
@@ -337,6 +387,16 @@ mod tests {
         let seg_sz: usize = 16 * 1024 * 1024;
         assert_eq!(Lsn(0x1000007).segment_offset(seg_sz), 7);
         assert_eq!(Lsn(0x1000007).segment_number(seg_sz), 1u64);
+        assert_eq!(Lsn(0x1000007).segment_lsn(seg_sz), Lsn(0x1000000));
+        assert_eq!(Lsn::from_segment_number(1, seg_sz), Lsn(0x1000000));
+        assert_eq!(Lsn(0x1000007).remaining_in_segment(seg_sz), 0xfffff9u64);
+        assert_eq!(Lsn(0x1000000).remaining_in_segment(seg_sz), seg_sz as u64);
+        assert_eq!(Lsn(0xffffff).remaining_in_segment(seg_sz), 1u64);
+        assert!(Lsn(0x1000000).is_segment_start(seg_sz));
+        assert!(!Lsn(0xffffff).is_segment_start(seg_sz));
+        // the last byte of a segment belongs to it, not to the next one
+        assert_eq!(Lsn(0xffffff).segment_number(seg_sz), 0u64);
+        assert_eq!(Lsn(0xffffff).segment_offset(seg_sz), seg_sz - 1);
 
         assert_eq!(Lsn(0x4007).block_offset(), 7u64);
         assert_eq!(Lsn(0x4000).block_offset(), 0u64);
@@ -347,6 +407,23 @@ mod tests {
         assert_eq!(Lsn(0x2000000).calc_padding(seg_sz as u64), 0u64);
         assert_eq!(Lsn(0xffff01).calc_padding(8u32), 7u64);
         assert_eq!(Lsn(0xffff00).calc_padding(8u32), 0u64);
+
+        assert_eq!(Lsn(0x4007).page_lsn(), Lsn(0x4000));
+        assert_eq!(Lsn(0x1006007).page_offset_in_segment(seg_sz), 0x6000u64);
+
+        assert_eq!(Lsn(0x11).align(), Lsn(0x18));
+        assert_eq!(Lsn(0x10).align(), Lsn(0x10));
+        assert!(Lsn(0x10).is_aligned());
+        assert!(!Lsn(0x11).is_aligned());
+
+        assert_eq!(Lsn(0x4001).checked_align_up(8192u32), Some(Lsn(0x6000)));
+        assert_eq!(Lsn(0x4000).checked_align_up(8192u32), Some(Lsn(0x4000)));
+        assert_eq!(Lsn(u64::MAX).checked_align_up(8u32), None);
+        assert_eq!(Lsn(0x5fff).align_down(8192u32), Lsn(0x4000));
+        assert_eq!(Lsn(0x4000).align_down(8192u32), Lsn(0x4000));
+        assert!(Lsn(0x4000).is_aligned_to(8192u32));
+        assert!(!Lsn(0x4008).is_aligned_to(8192u32));
+        assert!(Lsn(0x4008).is_aligned_to(8u32));
     }
 
     #[test]
@@ -0,0 +1,203 @@
+//! Names and metadata of the regions, by [`RegionId`].
+//!
+//! The regions are identified by their number in the storage formats and in the
+//! protocols, and that's how they are printed, too. The registry, configured the
+//! same way for the pageserver, the safekeeper and the proxy, gives them names,
+//! which are accepted wherever a region is parsed, e.g. in the HTTP APIs, and
+//! metadata. With a registry, the regions that aren't in it are rejected; without
+//! one, any region is accepted, as before.
+//!
+//! ```toml
+//! regions = [
+//!     { id = 0, name = "global" },
+//!     { id = 1, name = "us-east-2", metadata = { cloud = "aws" } },
+//! ]
+//! ```
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, ensure};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::id::RegionId;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub id: RegionId,
+    pub name: String,
+    /// Free-form metadata, e.g. the cloud region the region runs in.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RegionError {
+    #[error("invalid region '{0}', expected a region id or name")]
+    Malformed(String),
+
+    #[error("unknown region '{0}'")]
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RegionRegistry {
+    regions: BTreeMap<RegionId, RegionConfig>,
+}
+
+static EMPTY: RegionRegistry = RegionRegistry {
+    regions: BTreeMap::new(),
+};
+
+static REGISTRY: OnceCell<RegionRegistry> = OnceCell::new();
+
+/// Sets the registry of the process, at startup, before any region is parsed.
+pub fn init(registry: RegionRegistry) -> anyhow::Result<()> {
+    REGISTRY
+        .set(registry)
+        .map_err(|_| anyhow::anyhow!("region registry is already initialized"))
+}
+
+/// The registry of the process, empty unless it was [`init`]ialized.
+pub fn registry() -> &'static RegionRegistry {
+    REGISTRY.get().unwrap_or(&EMPTY)
+}
+
+impl RegionRegistry {
+    pub fn new(regions: Vec<RegionConfig>) -> anyhow::Result<Self> {
+        let mut registry = RegionRegistry::default();
+        for region in regions {
+            ensure!(
+                name_valid(&region.name),
+                "invalid name '{}' of region {}: must start with a letter, and contain only \
+                 letters, digits, '-' and '_'",
+                region.name,
+                region.id,
+            );
+            if registry.lookup(&region.name).is_some() {
+                bail!("duplicate region name '{}'", region.name);
+            }
+            if registry.regions.contains_key(&region.id) {
+                bail!("duplicate region id {}", region.id);
+            }
+            registry.regions.insert(region.id, region);
+        }
+        Ok(registry)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn get(&self, id: RegionId) -> Option<&RegionConfig> {
+        self.regions.get(&id)
+    }
+
+    pub fn name(&self, id: RegionId) -> Option<&str> {
+        self.get(id).map(|region| region.name.as_str())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<RegionId> {
+        self.regions
+            .values()
+            .find(|region| region.name == name)
+            .map(|region| region.id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegionConfig> {
+        self.regions.values()
+    }
+
+    /// Parses a region id, or the name of a region in the registry.
+    pub fn parse(&self, s: &str) -> Result<RegionId, RegionError> {
+        match s.parse::<u8>() {
+            Ok(id) => self.check(RegionId(id)),
+            Err(_) if name_valid(s) => self
+                .lookup(s)
+                .ok_or_else(|| RegionError::Unknown(s.to_string())),
+            Err(_) => Err(RegionError::Malformed(s.to_string())),
+        }
+    }
+
+    /// Fails if the region isn't in the registry, unless the registry is empty.
+    pub fn check(&self, id: RegionId) -> Result<RegionId, RegionError> {
+        if self.is_empty() || self.regions.contains_key(&id) {
+            Ok(id)
+        } else {
+            Err(RegionError::Unknown(id.to_string()))
+        }
+    }
+}
+
+/// Tags all the metrics of the process, with the `node_region` label, and all its
+/// log lines, with the `node_region` field, with the region the node runs in. It's
+/// not `region`, which the log lines of the timelines have for their own region.
+/// This must be called at most once, at startup.
+pub fn tag_process(region: RegionId) -> anyhow::Result<()> {
+    let region = region.to_string();
+    crate::logging::set_global_fields(vec![("node_region".to_string(), region.clone())])?;
+    metrics::set_common_labels(vec![("node_region".to_string(), region)]);
+    Ok(())
+}
+
+fn name_valid(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(id: u8, name: &str) -> RegionConfig {
+        RegionConfig {
+            id: RegionId(id),
+            name: name.to_string(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn parse_regions() {
+        // without a registry, any id is accepted
+        let empty = RegionRegistry::default();
+        assert_eq!(empty.parse("7"), Ok(RegionId(7)));
+        assert_eq!(
+            empty.parse("us-east"),
+            Err(RegionError::Unknown("us-east".into()))
+        );
+
+        let registry =
+            RegionRegistry::new(vec![region(0, "global"), region(1, "us-east")]).unwrap();
+        assert_eq!(registry.parse("1"), Ok(RegionId(1)));
+        assert_eq!(registry.parse("us-east"), Ok(RegionId(1)));
+        assert_eq!(registry.name(RegionId(0)), Some("global"));
+        assert_eq!(registry.parse("7"), Err(RegionError::Unknown("7".into())));
+        assert_eq!(
+            registry.parse("300"),
+            Err(RegionError::Malformed("300".into()))
+        );
+        assert_eq!(
+            registry.parse("eu west"),
+            Err(RegionError::Malformed("eu west".into()))
+        );
+    }
+
+    #[test]
+    fn invalid_registry() {
+        RegionRegistry::new(vec![region(0, "a"), region(0, "b")]).unwrap_err();
+        RegionRegistry::new(vec![region(0, "a"), region(1, "a")]).unwrap_err();
+        RegionRegistry::new(vec![region(0, "1a")]).unwrap_err();
+    }
+
+    #[test]
+    fn deserialize_config() {
+        let json = r#"[{"id": 1, "name": "us-east", "metadata": {"cloud": "aws"}}]"#;
+        let regions: Vec<RegionConfig> = serde_json::from_str(json).unwrap();
+        assert_eq!(regions[0].id, RegionId(1));
+        assert_eq!(regions[0].metadata["cloud"], "aws");
+    }
+}
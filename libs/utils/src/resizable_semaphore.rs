@@ -0,0 +1,149 @@
+//! A semaphore whose number of permits can be changed at runtime.
+//!
+//! An increase adds the permits right away. A decrease takes the surplus permits out
+//! of the semaphore as they become available: the ones available at once right away,
+//! the others as their holders release them, by a task that waits for them. The
+//! changes are serialized, and a change made while a decrease is still pending
+//! settles against it, so that the semaphore ends up with the last limit set.
+
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone)]
+pub struct ResizableSemaphore {
+    semaphore: Arc<Semaphore>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: NonZeroUsize,
+    /// Permits still to take out of the semaphore, for a decrease.
+    debt: usize,
+    /// Whether a task is waiting for the permits of `debt`.
+    collecting: bool,
+}
+
+impl ResizableSemaphore {
+    pub fn new(limit: NonZeroUsize) -> Self {
+        ResizableSemaphore {
+            semaphore: Arc::new(Semaphore::new(limit.get())),
+            state: Arc::new(Mutex::new(State {
+                limit,
+                debt: 0,
+                collecting: false,
+            })),
+        }
+    }
+
+    pub fn semaphore(&self) -> &Arc<Semaphore> {
+        &self.semaphore
+    }
+
+    /// The last limit set, which the semaphore may not have settled at yet.
+    pub fn limit(&self) -> NonZeroUsize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Changes the number of permits. When decreasing, and the permits are held, must be
+    /// called in a tokio runtime, to wait for them.
+    pub fn set_limit(&self, limit: NonZeroUsize) {
+        let mut state = self.state.lock().unwrap();
+        let old = state.limit.get();
+        state.limit = limit;
+        if limit.get() >= old {
+            let increase = limit.get() - old;
+            let settled = increase.min(state.debt);
+            state.debt -= settled;
+            self.semaphore.add_permits(increase - settled);
+            return;
+        }
+
+        state.debt += old - limit.get();
+        while state.debt > 0 {
+            match self.semaphore.try_acquire() {
+                Ok(permit) => {
+                    permit.forget();
+                    state.debt -= 1;
+                }
+                Err(_) => break,
+            }
+        }
+        if state.debt > 0 && !state.collecting {
+            state.collecting = true;
+            tokio::spawn(collect_debt(
+                Arc::clone(&self.semaphore),
+                Arc::clone(&self.state),
+            ));
+        }
+    }
+}
+
+/// Takes the permits of the debt out of the semaphore as they're released.
+async fn collect_debt(semaphore: Arc<Semaphore>, state: Arc<Mutex<State>>) {
+    loop {
+        let Ok(permit) = semaphore.acquire().await else {
+            // closed, nothing to limit anymore
+            state.lock().unwrap().collecting = false;
+            return;
+        };
+        let mut locked = state.lock().unwrap();
+        if locked.debt == 0 {
+            // settled by an increase meanwhile, the permit goes back
+            locked.collecting = false;
+            return;
+        }
+        permit.forget();
+        locked.debt -= 1;
+        if locked.debt == 0 {
+            locked.collecting = false;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resize() {
+        let s = ResizableSemaphore::new(limit(4));
+        s.set_limit(limit(6));
+        assert_eq!(s.semaphore().available_permits(), 6);
+        s.set_limit(limit(2));
+        assert_eq!(s.semaphore().available_permits(), 2);
+        assert_eq!(s.limit(), limit(2));
+    }
+
+    #[tokio::test]
+    async fn decrease_waits_for_holders() {
+        let s = ResizableSemaphore::new(limit(3));
+        let held = Arc::clone(s.semaphore())
+            .acquire_many_owned(3)
+            .await
+            .unwrap();
+        s.set_limit(limit(1));
+        // a concurrent change settles against the pending decrease
+        s.set_limit(limit(2));
+        drop(held);
+        for _ in 0..10 {
+            if !s.state.lock().unwrap().collecting {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert!(!s.state.lock().unwrap().collecting);
+        assert_eq!(s.semaphore().available_permits(), 2);
+
+        // the collecting task is done: an increase adds the permits
+        s.set_limit(limit(5));
+        assert_eq!(s.semaphore().available_permits(), 5);
+    }
+}
@@ -8,6 +8,7 @@ use std::sync::Mutex;
 use std::time::Duration;
 use tokio::sync::watch::{channel, Receiver, Sender};
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 
 /// An error happened while waiting for a number
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
@@ -19,6 +20,10 @@ pub enum SeqWaitError {
     /// [`SeqWait::shutdown`] was called
     #[error("SeqWait::shutdown was called")]
     Shutdown,
+
+    /// The cancellation token of the wait was cancelled
+    #[error("seqwait was cancelled")]
+    Cancelled,
 }
 
 /// Monotonically increasing value
@@ -124,6 +129,7 @@ where
             // Prevent new waiters; wake all those that exist.
             // Wake everyone with an error.
             let mut internal = self.internal.lock().unwrap();
+            internal.shutdown = true;
 
             // This will steal the entire waiters map.
             // When we drop it all waiters will be woken.
@@ -179,6 +185,31 @@ where
         }
     }
 
+    /// Wait for a number to arrive, like [`SeqWait::wait_for_timeout`], unless
+    /// `cancel` is cancelled first, in which case [`SeqWaitError::Cancelled`]
+    /// is returned.
+    ///
+    /// This function is async cancellation-safe.
+    pub async fn wait_for_timeout_cancellable(
+        &self,
+        num: V,
+        timeout_duration: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<(), SeqWaitError> {
+        match self.queue_for_wait(num) {
+            Ok(None) => Ok(()),
+            Ok(Some(mut rx)) => tokio::select! {
+                res = timeout(timeout_duration, rx.changed()) => match res {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(_)) => Err(SeqWaitError::Shutdown),
+                    Err(_) => Err(SeqWaitError::Timeout),
+                },
+                _ = cancel.cancelled() => Err(SeqWaitError::Cancelled),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
     /// Register and return a channel that will be notified when a number arrives,
     /// or None, if it has already arrived.
     fn queue_for_wait(&self, num: V) -> Result<Option<Receiver<()>>, SeqWaitError> {
@@ -308,4 +339,36 @@ mod tests {
 
         seq.shutdown();
     }
+
+    #[tokio::test]
+    async fn seqwait_cancellable() {
+        let seq = Arc::new(SeqWait::new(0));
+        let timeout = Duration::from_secs(3600);
+
+        // an already arrived number doesn't wait, even if cancelled
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res = seq.wait_for_timeout_cancellable(0, timeout, &cancel).await;
+        assert_eq!(res, Ok(()));
+
+        let cancel = CancellationToken::new();
+        let jh = tokio::task::spawn({
+            let seq = Arc::clone(&seq);
+            let cancel = cancel.clone();
+            async move { seq.wait_for_timeout_cancellable(42, timeout, &cancel).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cancel.cancel();
+        assert_eq!(jh.await.unwrap(), Err(SeqWaitError::Cancelled));
+
+        let res = seq
+            .wait_for_timeout_cancellable(42, Duration::from_millis(1), &CancellationToken::new())
+            .await;
+        assert_eq!(res, Err(SeqWaitError::Timeout));
+
+        // new waiters fail right away after a shutdown
+        seq.shutdown();
+        let res = seq.wait_for_timeout(42, timeout).await;
+        assert_eq!(res, Err(SeqWaitError::Shutdown));
+    }
 }
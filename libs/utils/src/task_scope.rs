@@ -0,0 +1,210 @@
+//! Tasks attached to the lifetime of an object, e.g. a tenant or a timeline.
+//!
+//! A [`TaskScope`] spawns named tasks, each with a [`CancellationToken`], and
+//! [`TaskScope::shutdown`] cancels them, and waits for them to finish. The scopes
+//! form a tree with [`TaskScope::child`]: cancelling a scope cancels its
+//! children too, and shutting it down waits for their tasks as well, so that
+//! e.g. the shutdown of a tenant stops the tasks of all its timelines.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use futures::Future;
+use tokio::task::{AbortHandle, JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{info_span, warn, Instrument};
+
+/// Dropping the last clone of a scope aborts the tasks that are still running.
+#[derive(Clone)]
+pub struct TaskScope(Arc<ScopeInner>);
+
+struct ScopeInner {
+    name: String,
+    cancel: CancellationToken,
+    tasks: Mutex<JoinSet<()>>,
+    /// Not keeping the children alive, a child removes itself when it's dropped.
+    children: Mutex<Vec<Weak<ScopeInner>>>,
+    parent: Option<Weak<ScopeInner>>,
+}
+
+impl TaskScope {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self::new_inner(name.into(), CancellationToken::new(), None)
+    }
+
+    fn new_inner(
+        name: String,
+        cancel: CancellationToken,
+        parent: Option<Weak<ScopeInner>>,
+    ) -> Self {
+        TaskScope(Arc::new(ScopeInner {
+            name,
+            cancel,
+            tasks: Mutex::new(JoinSet::new()),
+            children: Mutex::new(Vec::new()),
+            parent,
+        }))
+    }
+
+    /// Creates a scope which is cancelled, and shut down, along with this one.
+    /// It stays attached to this scope until it's shut down or dropped itself.
+    pub fn child(&self, name: impl Into<String>) -> TaskScope {
+        let child = Self::new_inner(
+            name.into(),
+            self.0.cancel.child_token(),
+            Some(Arc::downgrade(&self.0)),
+        );
+        self.0
+            .children
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&child.0));
+        child
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The token the tasks of this scope get.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.0.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancel.is_cancelled()
+    }
+
+    /// Asks the tasks of this scope, and of its children, to stop, without waiting
+    /// for them.
+    pub fn cancel(&self) {
+        self.0.cancel.cancel();
+    }
+
+    /// Spawns a task, which is passed the cancellation token of the scope, and should
+    /// return soon after it's cancelled. A task spawned after the scope is cancelled
+    /// gets a cancelled token, and is still waited for by [`TaskScope::shutdown`].
+    pub fn spawn<F, Fut>(&self, name: &str, f: F) -> AbortHandle
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let fut = f(self.0.cancel.clone())
+            .instrument(info_span!("task", scope = %self.0.name, name = %name));
+        let mut tasks = self.0.tasks.lock().unwrap();
+        // Reap the finished tasks, so that the set doesn't grow
+        while let Some(result) = tasks.try_join_next() {
+            self.log_join_error(result);
+        }
+        tasks.spawn(fut)
+    }
+
+    /// Cancels the tasks of this scope and of its children, and waits for all of them
+    /// to finish. The scope is detached from its parent afterwards.
+    pub async fn shutdown(&self) {
+        self.cancel();
+        loop {
+            // Not holding the lock while waiting, so tasks can still be spawned
+            let mut tasks = std::mem::take(&mut *self.0.tasks.lock().unwrap());
+            if tasks.is_empty() {
+                break;
+            }
+            while let Some(result) = tasks.join_next().await {
+                self.log_join_error(result);
+            }
+        }
+
+        let children = std::mem::take(&mut *self.0.children.lock().unwrap());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            // The recursion of async functions needs boxing
+            Box::pin(TaskScope(child).shutdown()).await;
+        }
+
+        if let Some(parent) = self.0.parent.as_ref().and_then(Weak::upgrade) {
+            parent
+                .children
+                .lock()
+                .unwrap()
+                .retain(|child| child.as_ptr() != Arc::as_ptr(&self.0));
+        }
+    }
+
+    fn log_join_error(&self, result: Result<(), JoinError>) {
+        if let Err(e) = result {
+            if e.is_panic() {
+                warn!("task of scope {} panicked: {e}", self.0.name);
+            }
+        }
+    }
+}
+
+impl Drop for ScopeInner {
+    fn drop(&mut self) {
+        if let Some(parent) = self.parent.as_ref().and_then(Weak::upgrade) {
+            // This one can't be upgraded anymore
+            parent
+                .children
+                .lock()
+                .unwrap()
+                .retain(|child| child.strong_count() > 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_waits_for_children() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let tenant = TaskScope::new("tenant");
+        let timelines = [tenant.child("timeline 1"), tenant.child("timeline 2")];
+
+        for scope in [&tenant, &timelines[0], &timelines[1]] {
+            let stopped = Arc::clone(&stopped);
+            scope.spawn("background loop", |cancel| async move {
+                cancel.cancelled().await;
+                // still running after the cancellation
+                tokio::task::yield_now().await;
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        // a child is shut down on its own
+        timelines[0].shutdown().await;
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+        assert!(!tenant.is_cancelled());
+        assert_eq!(tenant.0.children.lock().unwrap().len(), 1);
+
+        tenant.shutdown().await;
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+        assert!(timelines[1].is_cancelled());
+        assert!(tenant.0.children.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_children_are_detached() {
+        let tenant = TaskScope::new("tenant");
+        let timeline = tenant.child("timeline");
+        let _other = tenant.child("other timeline");
+        assert_eq!(tenant.0.children.lock().unwrap().len(), 2);
+
+        drop(timeline);
+        assert_eq!(tenant.0.children.lock().unwrap().len(), 1);
+        tenant.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_are_reaped() {
+        let scope = TaskScope::new("scope");
+        let handle = scope.spawn("quick", |_| async {});
+        while !handle.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        scope.spawn("another", |_| async {});
+        assert_eq!(scope.0.tasks.lock().unwrap().len(), 1);
+        scope.shutdown().await;
+    }
+}
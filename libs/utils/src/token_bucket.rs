@@ -0,0 +1,253 @@
+//! A token bucket, limiting the rate of operations.
+//!
+//! The bucket holds up to `burst` tokens, and is refilled with `per_second` tokens
+//! every second. An operation takes tokens from it: [`TokenBucket::try_acquire`]
+//! fails if there aren't enough, [`TokenBucket::acquire`] waits for them, and
+//! [`TokenBucket::acquire_until`] waits unless they wouldn't be there by a deadline.
+//!
+//! The waiters are served in arrival order: a waiter takes its tokens right away,
+//! leaving the bucket in debt, which the following waiters wait for as well. A
+//! waiter that is dropped before its wait is over gives its tokens back.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("rate limit exceeded, retry in {retry_after:?}")]
+pub struct RateLimited {
+    /// How long until the tokens would have been there.
+    pub retry_after: Duration,
+}
+
+pub struct TokenBucket {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Tokens added per second, 0 for no limit.
+    per_second: f64,
+    burst: f64,
+    /// Negative while there are waiters.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl State {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        self.refilled_at = now;
+    }
+
+    /// How long until `n` tokens are available, after the current waiters.
+    fn wait_for(&self, n: u32) -> Duration {
+        let missing = n as f64 - self.tokens;
+        if self.per_second == 0.0 || missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_second)
+        }
+    }
+}
+
+impl TokenBucket {
+    /// A bucket refilled with `per_second` tokens per second, up to `burst` tokens,
+    /// starting full. With `per_second` 0, there's no limit.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self::new_at(per_second, burst, Instant::now())
+    }
+
+    pub fn new_at(per_second: u32, burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            state: Mutex::new(State {
+                per_second: per_second as f64,
+                burst: burst as f64,
+                tokens: burst as f64,
+                refilled_at: now,
+            }),
+        }
+    }
+
+    /// A bucket that never limits, e.g. until a limit is configured with
+    /// [`TokenBucket::set_limit`].
+    pub fn unlimited() -> Self {
+        Self::new(0, 0)
+    }
+
+    /// Changes the rate and the burst. The current waiters keep their place, and
+    /// are served at the new rate.
+    pub fn set_limit(&self, per_second: u32, burst: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        if state.per_second == 0.0 {
+            // start full, like a new bucket
+            state.tokens = burst as f64;
+        }
+        state.per_second = per_second as f64;
+        state.burst = burst as f64;
+        state.tokens = state.tokens.min(state.burst);
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.state.lock().unwrap().per_second == 0.0
+    }
+
+    /// Whether the bucket is full, i.e. it's in the same state as a new one.
+    pub fn is_full_at(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.refill(now);
+        state.tokens >= state.burst
+    }
+
+    /// Takes `n` tokens if they are available now, without waiting.
+    pub fn try_acquire(&self, n: u32) -> Result<(), RateLimited> {
+        self.try_acquire_at(n, Instant::now())
+    }
+
+    pub fn try_acquire_at(&self, n: u32, now: Instant) -> Result<(), RateLimited> {
+        self.reserve(n, now, Some(now)).map(|_| ())
+    }
+
+    /// Takes `n` tokens, waiting for them as long as needed.
+    pub async fn acquire(&self, n: u32) {
+        let now = Instant::now();
+        let ready_at = self.reserve(n, now, None).expect("no deadline");
+        self.wait(n, ready_at).await
+    }
+
+    /// Takes `n` tokens, waiting for them, unless they wouldn't be available by
+    /// `deadline`, in which case it fails right away, and takes nothing.
+    pub async fn acquire_until(&self, n: u32, deadline: Instant) -> Result<(), RateLimited> {
+        let ready_at = self.reserve(n, Instant::now(), Some(deadline))?;
+        self.wait(n, ready_at).await;
+        Ok(())
+    }
+
+    /// Takes `n` tokens, possibly leaving the bucket in debt, and returns when the
+    /// debt is paid off.
+    fn reserve(
+        &self,
+        n: u32,
+        now: Instant,
+        deadline: Option<Instant>,
+    ) -> Result<Instant, RateLimited> {
+        let mut state = self.state.lock().unwrap();
+        if state.per_second == 0.0 {
+            return Ok(now);
+        }
+        state.refill(now);
+        let ready_at = now + state.wait_for(n);
+        if let Some(deadline) = deadline {
+            if ready_at > deadline {
+                return Err(RateLimited {
+                    retry_after: ready_at - now,
+                });
+            }
+        }
+        state.tokens -= n as f64;
+        Ok(ready_at)
+    }
+
+    async fn wait(&self, n: u32, ready_at: Instant) {
+        if ready_at <= Instant::now() {
+            return;
+        }
+        let refund = Refund { bucket: self, n };
+        tokio::time::sleep_until(ready_at).await;
+        std::mem::forget(refund);
+    }
+}
+
+/// Gives the tokens of a waiter back if it's dropped.
+struct Refund<'a> {
+    bucket: &'a TokenBucket,
+    n: u32,
+}
+
+impl Drop for Refund<'_> {
+    fn drop(&mut self) {
+        let mut state = self.bucket.state.lock().unwrap();
+        state.tokens = (state.tokens + self.n as f64).min(state.burst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_and_burst() {
+        let bucket = TokenBucket::new(4, 2);
+        let start = Instant::now();
+
+        // the burst is available right away
+        bucket.try_acquire(1).unwrap();
+        bucket.try_acquire(1).unwrap();
+        assert_eq!(
+            bucket.try_acquire(1),
+            Err(RateLimited {
+                retry_after: Duration::from_millis(250)
+            })
+        );
+
+        // the waiters are served in turn, at the rate
+        bucket.acquire(1).await;
+        bucket.acquire(2).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(750));
+
+        // the bucket doesn't fill beyond the burst
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(bucket.is_full_at(Instant::now()));
+        bucket.try_acquire(2).unwrap();
+        bucket.try_acquire(1).unwrap_err();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline() {
+        let bucket = TokenBucket::new(1, 1);
+        bucket.acquire(1).await;
+
+        let err = bucket
+            .acquire_until(1, Instant::now() + Duration::from_millis(500))
+            .await
+            .unwrap_err();
+        assert_eq!(err.retry_after, Duration::from_secs(1));
+
+        // the failed attempt took nothing
+        bucket
+            .acquire_until(1, Instant::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_waiter_gives_tokens_back() {
+        let bucket = TokenBucket::new(2, 1);
+        bucket.acquire(1).await;
+
+        let waiter = bucket.acquire(10);
+        tokio::time::timeout(Duration::from_millis(250), waiter)
+            .await
+            .unwrap_err();
+
+        // only waits for the refill of the first token
+        let start = Instant::now();
+        bucket.acquire(1).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited() {
+        let bucket = TokenBucket::unlimited();
+        bucket.try_acquire(1_000_000).unwrap();
+
+        bucket.set_limit(1, 2);
+        bucket.try_acquire(2).unwrap();
+        bucket.try_acquire(1).unwrap_err();
+
+        bucket.set_limit(0, 0);
+        bucket.try_acquire(1_000_000).unwrap();
+    }
+}
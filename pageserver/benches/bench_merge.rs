@@ -0,0 +1,72 @@
+//! Merging the keys of the delta layers being compacted, as compaction does, with
+//! hundreds of input layers.
+//!
+//! * `heap_merge`: the inputs go through [`merge_by_key`].
+//! * `sort`: the inputs are concatenated and sorted, which is how compaction merged
+//!   them before. This is the baseline.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use pageserver::repository::Key;
+use pageserver::tenant::storage_layer::merge_iterator::merge_by_key;
+use rand::prelude::{Rng, SeedableRng, StdRng};
+use utils::lsn::Lsn;
+
+/// The number of keys in each input layer.
+const KEYS_PER_LAYER: usize = 10_000;
+
+/// Builds `num_layers` sorted inputs, each covering the whole key range with the LSNs
+/// of its own slice of WAL, like the L0 delta layers do.
+fn make_layers(num_layers: usize) -> Vec<Vec<(Key, Lsn, u64)>> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..num_layers)
+        .map(|layer_idx| {
+            let start_lsn = 0x1000_0000 * (layer_idx as u64 + 1);
+            let mut layer: Vec<_> = (0..KEYS_PER_LAYER)
+                .map(|_| {
+                    let key = Key::from_i128(rng.gen_range(0..1_000_000));
+                    let lsn = Lsn(start_lsn + rng.gen_range(0..0x1000_0000));
+                    (key, lsn, 8192)
+                })
+                .collect();
+            layer.sort_by_key(|(key, lsn, _size)| (*key, *lsn));
+            layer
+        })
+        .collect()
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    group.sample_size(10);
+    for num_layers in [100, 300, 500] {
+        let layers = make_layers(num_layers);
+        group.bench_with_input(
+            BenchmarkId::new("heap_merge", num_layers),
+            &layers,
+            |b, layers| {
+                b.iter(|| {
+                    let merged: Vec<_> = merge_by_key(
+                        layers.iter().map(|layer| layer.iter().copied()),
+                        |(key, lsn, _size)| (*key, *lsn),
+                    )
+                    .collect();
+                    black_box(merged)
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sort", num_layers),
+            &layers,
+            |b, layers| {
+                b.iter(|| {
+                    let mut merged: Vec<_> = layers.iter().flatten().copied().collect();
+                    merged.sort_by_key(|(key, lsn, _size)| (*key, *lsn));
+                    black_box(merged)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_merge);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generate the gRPC page service from the .proto, into $OUT_DIR, like the
+    // storage broker does. The `bytes` fields are `Bytes`, for the pages to be
+    // sent without copying them.
+    let mut config = prost_build::Config::new();
+    config.bytes(["."]);
+    tonic_build::configure()
+        .compile_with_config(config, &["proto/page_service.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("failed to compile protos {:?}", e));
+    Ok(())
+}
@@ -0,0 +1,6 @@
+//! Clients of the pageserver APIs, for the control planes and the tools that
+//! manage pageservers.
+//!
+//! The request and response types are the ones of [`pageserver_api::models`].
+
+pub mod mgmt_api;
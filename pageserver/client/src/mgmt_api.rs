@@ -0,0 +1,360 @@
+//! Client of the pageserver management API, the HTTP API under `/v1`.
+//!
+//! Requests that fail without a response, or with `503 Service Unavailable`, are
+//! retried with an exponential backoff, up to [`Client::with_max_retries`] times.
+//! Tenants and timelines are created with the IDs the caller chooses, so a retried
+//! create whose earlier attempt went through fails with a conflict, rather than
+//! creating another one. The client takes that conflict for the success of the
+//! earlier attempt.
+
+use std::cell::Cell;
+
+use pageserver_api::models::{
+    FailpointConfig, GcResult, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse,
+    TenantInfo, TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
+};
+use reqwest::{Method, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use utils::backoff;
+use utils::http::error::HttpErrorBody;
+use utils::id::{TenantId, TimelineId};
+
+/// Number of retries of a request by default.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request didn't get a response, e.g. the pageserver isn't running.
+    #[error("send request: {0}")]
+    SendRequest(reqwest::Error),
+
+    /// The response body couldn't be read or parsed.
+    #[error("receive body: {0}")]
+    ReceiveBody(reqwest::Error),
+
+    /// The pageserver responded with an error.
+    #[error("pageserver API: {status}: {msg}")]
+    ApiError { status: StatusCode, msg: String },
+}
+
+impl Error {
+    /// Whether the request may succeed if it's retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::SendRequest(_) => true,
+            Error::ReceiveBody(_) => false,
+            Error::ApiError { status, .. } => *status == StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    /// E.g. `http://localhost:9898`, without the `/v1`.
+    endpoint: String,
+    /// The token of the `Authorization` header, if the pageserver requires one.
+    jwt: Option<String>,
+    max_retries: u32,
+}
+
+impl Client {
+    pub fn new(endpoint: String, jwt: Option<String>) -> Self {
+        Client {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            jwt,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    /// Sets the number of retries of a failed request, 0 to return the first error.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub async fn status(&self) -> Result<()> {
+        self.request(Method::GET, "/v1/status", None::<&()>).await?;
+        Ok(())
+    }
+
+    pub async fn configure_failpoints(&self, failpoints: &[FailpointConfig]) -> Result<()> {
+        self.request(Method::PUT, "/v1/failpoints", Some(failpoints))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn tenant_list(&self) -> Result<Vec<TenantInfo>> {
+        self.get("/v1/tenant").await
+    }
+
+    pub async fn tenant_create(&self, request: &TenantCreateRequest) -> Result<TenantId> {
+        match self.create("/v1/tenant", request).await? {
+            Some(response) => {
+                let TenantCreateResponse(tenant_id) = json(response).await?;
+                Ok(tenant_id)
+            }
+            None => Ok(request.new_tenant_id),
+        }
+    }
+
+    pub async fn tenant_config(&self, request: &TenantConfigRequest) -> Result<()> {
+        self.request(Method::PUT, "/v1/tenant/config", Some(request))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn tenant_status(&self, tenant_id: TenantId) -> Result<TenantInfo> {
+        self.get(&format!("/v1/tenant/{tenant_id}")).await
+    }
+
+    pub async fn tenant_detach(&self, tenant_id: TenantId) -> Result<()> {
+        let path = format!("/v1/tenant/{tenant_id}/detach");
+        self.request(Method::POST, &path, None::<&()>).await?;
+        Ok(())
+    }
+
+    pub async fn timeline_create(
+        &self,
+        tenant_id: TenantId,
+        request: &TimelineCreateRequest,
+    ) -> Result<TimelineInfo> {
+        let path = format!("/v1/tenant/{tenant_id}/timeline");
+        match self.create(&path, request).await? {
+            Some(response) => json(response).await,
+            None => {
+                self.timeline_detail(tenant_id, request.new_timeline_id)
+                    .await
+            }
+        }
+    }
+
+    pub async fn timeline_list(&self, tenant_id: TenantId) -> Result<Vec<TimelineInfo>> {
+        self.get(&format!("/v1/tenant/{tenant_id}/timeline")).await
+    }
+
+    pub async fn timeline_detail(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Result<TimelineInfo> {
+        self.get(&format!("/v1/tenant/{tenant_id}/timeline/{timeline_id}"))
+            .await
+    }
+
+    /// Runs GC on the timeline right away, with the tenant's `gc_horizon` unless
+    /// `gc_horizon` is given.
+    pub async fn timeline_gc(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        gc_horizon: Option<u64>,
+    ) -> Result<GcResult> {
+        let path = format!("/v1/tenant/{tenant_id}/timeline/{timeline_id}/do_gc");
+        let request = TimelineGcRequest { gc_horizon };
+        json(self.request(Method::PUT, &path, Some(&request)).await?).await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        json(self.request(Method::GET, path, None::<&()>).await?).await
+    }
+
+    /// Sends the request, retrying it while it fails with a transient error, and
+    /// turns an error response into an [`Error::ApiError`].
+    async fn request<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<Response> {
+        let response = self.send(method, path, body, false).await?;
+        Ok(response.expect("only a retried create takes a conflict for a success"))
+    }
+
+    /// POSTs a create request. Returns [`None`] if a retry of the request fails with
+    /// `409 Conflict`: an earlier attempt created the object, but its response got
+    /// lost.
+    async fn create<B: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<Option<Response>> {
+        self.send(Method::POST, path, Some(body), true).await
+    }
+
+    async fn send<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+        retried_create_succeeds: bool,
+    ) -> Result<Option<Response>> {
+        let method = &method;
+        let url = &format!("{}{}", self.endpoint, path);
+        let attempts = &Cell::new(0u32);
+        backoff::retry(
+            || async move {
+                let attempt = attempts.get();
+                attempts.set(attempt + 1);
+                let mut request = self.client.request(method.clone(), url);
+                if let Some(jwt) = &self.jwt {
+                    request = request.bearer_auth(jwt);
+                }
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+                let response = request.send().await.map_err(Error::SendRequest)?;
+                if retried_create_succeeds
+                    && attempt > 0
+                    && response.status() == StatusCode::CONFLICT
+                {
+                    return Ok(None);
+                }
+                error_from_body(response).await.map(Some)
+            },
+            |e| !e.is_transient(),
+            // `backoff::retry` retries at least until the warning threshold, whatever
+            // the maximum, so the retries are logged as info and the final failure
+            // as a warning.
+            self.max_retries,
+            self.max_retries,
+            &format!("{method} {path}"),
+        )
+        .await
+    }
+}
+
+async fn error_from_body(response: Response) -> Result<Response> {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return Ok(response);
+    }
+    // Not every error comes from the API handlers, e.g. a proxy's
+    let body = response.text().await.unwrap_or_default();
+    let msg = match serde_json::from_str::<HttpErrorBody>(&body) {
+        Ok(body) => body.msg,
+        Err(_) => body,
+    };
+    Err(Error::ApiError { status, msg })
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    response.json().await.map_err(Error::ReceiveBody)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Server};
+
+    use super::*;
+
+    /// Starts a server that answers every request with `status` until it has
+    /// answered `failures` requests, and with `200 OK` after that.
+    fn serve(status: StatusCode, failures: u32) -> (SocketAddr, Arc<AtomicU32>) {
+        serve_then(status, failures, StatusCode::OK)
+    }
+
+    /// Like [`serve`], answering with `then` after the failures.
+    fn serve_then(
+        status: StatusCode,
+        failures: u32,
+        then: StatusCode,
+    ) -> (SocketAddr, Arc<AtomicU32>) {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&requests);
+        let make_service = make_service_fn(move |_| {
+            let counter = Arc::clone(&counter);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_: Request<Body>| {
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        Ok::<_, Infallible>(if n < failures {
+                            HttpErrorBody::response_from_msg_and_status(
+                                "try again".to_string(),
+                                status,
+                            )
+                        } else if then != StatusCode::OK {
+                            HttpErrorBody::response_from_msg_and_status(
+                                "already exists".to_string(),
+                                then,
+                            )
+                        } else {
+                            hyper::Response::new(Body::empty())
+                        })
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable() {
+        let (addr, requests) = serve(StatusCode::SERVICE_UNAVAILABLE, 1);
+        let client = Client::new(format!("http://{addr}/"), None);
+        client.status().await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // without retries, the first error is returned
+        let (addr, requests) = serve(StatusCode::SERVICE_UNAVAILABLE, 1);
+        let client = Client::new(format!("http://{addr}"), None).with_max_retries(0);
+        let err = client.status().await.unwrap_err();
+        assert!(err.is_transient());
+        assert_eq!(
+            err.to_string(),
+            "pageserver API: 503 Service Unavailable: try again"
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn permanent_errors_not_retried() {
+        let (addr, requests) = serve(StatusCode::CONFLICT, 1);
+        let client = Client::new(format!("http://{addr}"), None);
+        let err = client
+            .tenant_detach(TenantId::generate())
+            .await
+            .unwrap_err();
+        assert!(!err.is_transient());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retried_create_conflict_is_success() {
+        let (addr, requests) = serve_then(StatusCode::SERVICE_UNAVAILABLE, 1, StatusCode::CONFLICT);
+        let client = Client::new(format!("http://{addr}"), None);
+        let request = TenantCreateRequest {
+            new_tenant_id: TenantId::generate(),
+            config: Default::default(),
+        };
+        let tenant_id = client.tenant_create(&request).await.unwrap();
+        assert_eq!(tenant_id, request.new_tenant_id);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // a conflict on the first attempt is an error
+        let (addr, requests) = serve(StatusCode::CONFLICT, 1);
+        let client = Client::new(format!("http://{addr}"), None);
+        let err = client.tenant_create(&request).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ApiError {
+                status: StatusCode::CONFLICT,
+                ..
+            }
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
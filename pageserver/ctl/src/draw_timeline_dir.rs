@@ -23,7 +23,9 @@
 //!      <https://grafana.com/tutorials/build-a-panel-plugin/>
 use anyhow::Result;
 use pageserver::repository::Key;
-use pageserver::METADATA_FILE_NAME;
+use pageserver::{
+    CLEAN_SHUTDOWN_MARKER_FILE_NAME, LABELS_FILE_NAME, METADATA_FILE_NAME, METADATA_LOG_FILE_NAME,
+};
 use std::cmp::Ordering;
 use std::io::{self, BufRead};
 use std::path::PathBuf;
@@ -72,7 +74,11 @@ pub fn main() -> Result<()> {
         let line = PathBuf::from_str(&line).unwrap();
         let filename = line.file_name().unwrap();
         let filename = filename.to_str().unwrap();
-        if filename == METADATA_FILE_NAME {
+        if filename == METADATA_FILE_NAME
+            || filename == METADATA_LOG_FILE_NAME
+            || filename == LABELS_FILE_NAME
+            || filename == CLEAN_SHUTDOWN_MARKER_FILE_NAME
+        {
             // Don't try and parse "metadata" like a key-lsn range
             continue;
         }
@@ -0,0 +1,373 @@
+//! Inspecting and editing the `index_part.json` of a timeline, which lists the
+//! layers of the timeline in remote storage.
+//!
+//! The index is read either from a local file, or from remote storage, given a
+//! `remote_storage` config in the format of the pageserver's. Edits are only
+//! written with `--apply`, after saving a copy of the original index in the
+//! current directory. The pageserver reads the index only when it attaches or
+//! loads the tenant, so detach the tenant before editing its index, and attach it
+//! again afterwards.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use pageserver::tenant::storage_layer::LayerFileName;
+use pageserver::tenant::{
+    IndexPart, LayerFileMetadata, TENANTS_SEGMENT_NAME, TIMELINES_SEGMENT_NAME,
+};
+use remote_storage::{GenericRemoteStorage, RemotePath, RemoteStorageConfig};
+use toml_edit::Document;
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+#[derive(Subcommand)]
+pub(crate) enum IndexPartCmd {
+    /// Print the layers and the metadata of an index
+    ///
+    /// Example: `cargo run --bin pagectl index-part dump --path index_part.json`
+    Dump(IndexSource),
+    /// Check an index for layers missing from remote storage, duplicate or inconsistent
+    /// entries, and layers beyond the disk consistent LSN
+    ///
+    /// The layers next to a local index file are looked up in its directory.
+    Validate(IndexSource),
+    /// Remove layers from an index
+    ///
+    /// Duplicate entries and the metadata of unlisted layers are removed as well.
+    Edit {
+        #[command(flatten)]
+        source: IndexSource,
+        /// Remove the layer with this file name
+        #[arg(long)]
+        remove_layer: Vec<String>,
+        /// Remove the layers which are missing from remote storage
+        #[arg(long)]
+        remove_missing: bool,
+        /// Write the edited index, rather than only printing the changes
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+#[derive(Args)]
+pub(crate) struct IndexSource {
+    /// Local index_part.json file
+    #[arg(
+        long,
+        required_unless_present = "remote_storage",
+        conflicts_with = "remote_storage"
+    )]
+    path: Option<PathBuf>,
+    /// Remote storage config as an inline TOML table, e.g.
+    /// `{bucket_name='bucket', bucket_region='eu-central-1', prefix_in_bucket='pageserver/'}`
+    #[arg(long, requires_all = ["tenant_id", "timeline_id"])]
+    remote_storage: Option<String>,
+    /// Tenant of the index in remote storage
+    #[arg(long)]
+    tenant_id: Option<TenantId>,
+    /// Timeline of the index in remote storage
+    #[arg(long)]
+    timeline_id: Option<TimelineId>,
+}
+
+enum IndexLocation {
+    Local(PathBuf),
+    Remote {
+        storage: GenericRemoteStorage,
+        timeline_path: RemotePath,
+    },
+}
+
+impl IndexSource {
+    fn location(&self) -> Result<IndexLocation> {
+        if let Some(path) = &self.path {
+            return Ok(IndexLocation::Local(path.clone()));
+        }
+        let (Some(storage_conf), Some(tenant_id), Some(timeline_id)) =
+            (&self.remote_storage, self.tenant_id, self.timeline_id)
+        else {
+            bail!(
+                "either --path or --remote-storage with --tenant-id and --timeline-id is required"
+            );
+        };
+        let storage = GenericRemoteStorage::from_config(&parse_remote_storage(storage_conf)?)?;
+        let timeline_path = RemotePath::new(
+            &Path::new(TENANTS_SEGMENT_NAME)
+                .join(tenant_id.to_string())
+                .join(TIMELINES_SEGMENT_NAME)
+                .join(timeline_id.to_string()),
+        )?;
+        Ok(IndexLocation::Remote {
+            storage,
+            timeline_path,
+        })
+    }
+}
+
+fn parse_remote_storage(storage_conf: &str) -> Result<RemoteStorageConfig> {
+    // an inline table is not a valid document, so wrap it in a key
+    let parsed_toml = format!("remote_storage = {storage_conf}").parse::<Document>()?;
+    let (_, storage_conf_parsed_toml) = parsed_toml.iter().next().unwrap();
+    RemoteStorageConfig::from_toml(storage_conf_parsed_toml)?
+        .context("Incorrectly parsed remote storage toml as no remote storage config")
+}
+
+impl IndexLocation {
+    async fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            IndexLocation::Local(path) => {
+                std::fs::read(path).with_context(|| format!("read {}", path.display()))
+            }
+            IndexLocation::Remote {
+                storage,
+                timeline_path,
+            } => {
+                let index_path = timeline_path.join(Path::new(IndexPart::FILE_NAME));
+                let mut download = storage
+                    .download(&index_path)
+                    .await
+                    .with_context(|| format!("download {index_path:?}"))?;
+                let mut bytes = Vec::new();
+                tokio::io::copy(&mut download.download_stream, &mut bytes).await?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    async fn write(&self, bytes: Vec<u8>) -> Result<()> {
+        match self {
+            IndexLocation::Local(path) => {
+                std::fs::write(path, bytes).with_context(|| format!("write {}", path.display()))
+            }
+            IndexLocation::Remote {
+                storage,
+                timeline_path,
+            } => {
+                let index_path = timeline_path.join(Path::new(IndexPart::FILE_NAME));
+                let size = bytes.len();
+                let reader = tokio::io::BufReader::new(std::io::Cursor::new(bytes));
+                storage
+                    .upload_storage_object(reader, size, &index_path)
+                    .await
+                    .with_context(|| format!("upload {index_path:?}"))
+            }
+        }
+    }
+
+    /// The names of the files next to the index.
+    async fn list_files(&self) -> Result<HashSet<String>> {
+        match self {
+            IndexLocation::Local(path) => {
+                let dir = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let mut files = HashSet::new();
+                for entry in std::fs::read_dir(dir)? {
+                    files.insert(entry?.file_name().to_string_lossy().into_owned());
+                }
+                Ok(files)
+            }
+            IndexLocation::Remote {
+                storage,
+                timeline_path,
+            } => Ok(storage
+                .list_files(Some(timeline_path))
+                .await?
+                .iter()
+                .filter_map(|path| path.object_name().map(str::to_string))
+                .collect()),
+        }
+    }
+}
+
+pub(crate) async fn main(cmd: &IndexPartCmd) -> Result<()> {
+    match cmd {
+        IndexPartCmd::Dump(source) => {
+            let location = source.location()?;
+            let index = parse_index(&location.read().await?)?;
+            dump(&index)
+        }
+        IndexPartCmd::Validate(source) => {
+            let location = source.location()?;
+            let bytes = location.read().await?;
+            let index = parse_index(&bytes)?;
+            let problems = validate(&bytes, &index, &location.list_files().await?)?;
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                bail!("found {} problems in the index", problems.len());
+            }
+            println!("no problems found in the index");
+            Ok(())
+        }
+        IndexPartCmd::Edit {
+            source,
+            remove_layer,
+            remove_missing,
+            apply,
+        } => {
+            let location = source.location()?;
+            let bytes = location.read().await?;
+            let mut index = parse_index(&bytes)?;
+
+            let mut to_remove = HashSet::new();
+            for name in remove_layer {
+                let layer = name
+                    .parse::<LayerFileName>()
+                    .map_err(|e| anyhow::anyhow!("invalid layer name: {e}"))?;
+                if !index.timeline_layers.contains(&layer) {
+                    bail!("layer {name} is not in the index");
+                }
+                to_remove.insert(layer);
+            }
+            if *remove_missing {
+                let files = location.list_files().await?;
+                to_remove.extend(
+                    index
+                        .timeline_layers
+                        .iter()
+                        .filter(|layer| !files.contains(&layer.file_name()))
+                        .cloned(),
+                );
+            }
+
+            for layer in &to_remove {
+                println!("removing layer {}", layer.file_name());
+                index.timeline_layers.remove(layer);
+            }
+            let layers = &index.timeline_layers;
+            index.layer_metadata.retain(|layer, _| {
+                let listed = layers.contains(layer);
+                if !listed && !to_remove.contains(layer) {
+                    println!("removing metadata of unlisted layer {}", layer.file_name());
+                }
+                listed
+            });
+
+            let new_bytes = serde_json::to_vec(&index).context("serialize the index")?;
+            if !*apply {
+                println!("dry run, pass --apply to write the edited index");
+                return Ok(());
+            }
+
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs();
+            let backup_path = PathBuf::from(format!("index_part.json.{timestamp}.bak"));
+            std::fs::write(&backup_path, &bytes)
+                .with_context(|| format!("write backup {}", backup_path.display()))?;
+            println!("saved the original index to {}", backup_path.display());
+
+            location.write(new_bytes).await?;
+            println!("wrote the edited index");
+            Ok(())
+        }
+    }
+}
+
+fn parse_index(bytes: &[u8]) -> Result<IndexPart> {
+    serde_json::from_slice(bytes).context("parse index_part.json")
+}
+
+fn sorted_layers(index: &IndexPart) -> Vec<&LayerFileName> {
+    let mut layers = index.timeline_layers.iter().collect::<Vec<_>>();
+    layers.sort_by_key(|layer| layer.file_name());
+    layers
+}
+
+fn dump(index: &IndexPart) -> Result<()> {
+    if let Some(deleted_at) = index.deleted_at {
+        println!("deleted at: {deleted_at}");
+    }
+    println!("disk_consistent_lsn: {}", index.disk_consistent_lsn);
+    match index.parse_metadata() {
+        Ok(metadata) => println!("metadata: {metadata:?}"),
+        Err(e) => println!("metadata: failed to parse: {e:#}"),
+    }
+    println!("{} layers:", index.timeline_layers.len());
+    for layer in sorted_layers(index) {
+        match index.layer_metadata.get(layer) {
+            Some(metadata) => {
+                let size = LayerFileMetadata::from(metadata).file_size();
+                println!("{} {size}", layer.file_name());
+            }
+            None => println!("{} (no metadata)", layer.file_name()),
+        }
+    }
+    Ok(())
+}
+
+/// Lists the problems of the index, read from `bytes`. `files` are the names of
+/// the files next to it.
+fn validate(bytes: &[u8], index: &IndexPart, files: &HashSet<String>) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    match index.parse_metadata() {
+        Ok(metadata) if metadata.disk_consistent_lsn() != index.disk_consistent_lsn => problems
+            .push(format!(
+                "disk_consistent_lsn {} of the metadata differs from {} of the index",
+                metadata.disk_consistent_lsn(),
+                index.disk_consistent_lsn
+            )),
+        Ok(_) => {}
+        Err(e) => problems.push(format!("failed to parse the metadata: {e:#}")),
+    }
+
+    // The layers are deduplicated when parsed, so count them in the raw JSON
+    let raw: serde_json::Value = serde_json::from_slice(bytes)?;
+    let mut counts = HashMap::<&str, usize>::new();
+    for name in raw["timeline_layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.as_str())
+    {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut duplicates = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .collect::<Vec<_>>();
+    duplicates.sort();
+    for (name, count) in duplicates {
+        problems.push(format!("layer {name} is listed {count} times"));
+    }
+
+    for layer in sorted_layers(index) {
+        let name = layer.file_name();
+        if !files.contains(&name) {
+            problems.push(format!("layer {name} is missing from remote storage"));
+        }
+        if !index.layer_metadata.contains_key(layer) {
+            problems.push(format!("layer {name} has no metadata"));
+        }
+        let end_lsn = match layer {
+            LayerFileName::Image(image) => image.lsn,
+            LayerFileName::Delta(delta) => Lsn(delta.lsn_range.end.0 - 1),
+        };
+        if end_lsn > index.disk_consistent_lsn {
+            problems.push(format!(
+                "layer {name} is beyond disk_consistent_lsn {}",
+                index.disk_consistent_lsn
+            ));
+        }
+    }
+
+    let mut unlisted = index
+        .layer_metadata
+        .keys()
+        .filter(|layer| !index.timeline_layers.contains(layer))
+        .map(|layer| layer.file_name())
+        .collect::<Vec<_>>();
+    unlisted.sort();
+    for name in unlisted {
+        problems.push(format!("metadata of layer {name} which is not listed"));
+    }
+
+    Ok(problems)
+}
@@ -99,6 +99,12 @@ async fn get_holes(path: &Path, max_holes: usize) -> Result<Vec<Hole>> {
     let file = FileBlockReader::new(VirtualFile::open(path)?);
     let summary_blk = file.read_blk(0)?;
     let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
+    let file = if actual_summary.compressed_blocks {
+        let file_len = file.file.metadata()?.len();
+        file.with_compressed_blocks(file_len)?
+    } else {
+        file
+    };
     let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
         actual_summary.index_start_blk,
         actual_summary.index_root_blk,
@@ -137,7 +143,7 @@ pub(crate) async fn main(cmd: &AnalyzeLayerMapCmd) -> Result<()> {
 
     // Initialize virtual_file (file desriptor cache) and page cache which are needed to access layer persistent B-Tree.
     pageserver::virtual_file::init(10);
-    pageserver::page_cache::init(100);
+    pageserver::page_cache::init(100, 100);
 
     let mut total_delta_layers = 0usize;
     let mut total_image_layers = 0usize;
@@ -5,6 +5,7 @@ use clap::Subcommand;
 use pageserver::tenant::block_io::BlockCursor;
 use pageserver::tenant::disk_btree::DiskBtreeReader;
 use pageserver::tenant::storage_layer::delta_layer::{BlobRef, Summary};
+use pageserver::tenant::storage_layer::{find_corrupt_blocks, rename_to_backup};
 use pageserver::{page_cache, virtual_file};
 use pageserver::{
     repository::{Key, KEY_SIZE},
@@ -41,6 +42,19 @@ pub(crate) enum LayerCmd {
         /// The id from list-layer command
         id: usize,
     },
+    /// Check all layer files of a given tenant and timeline against their checksums
+    ///
+    /// Run this while the pageserver is stopped. With `--move-aside`, corrupt layer files
+    /// are renamed with an `.old` suffix, so that the pageserver downloads them again from
+    /// remote storage when they are next accessed.
+    VerifyLayers {
+        path: PathBuf,
+        tenant: String,
+        timeline: String,
+        /// Rename corrupt layer files, so that they are downloaded again
+        #[arg(long)]
+        move_aside: bool,
+    },
 }
 
 async fn read_delta_file(path: impl AsRef<Path>) -> Result<()> {
@@ -48,10 +62,16 @@ async fn read_delta_file(path: impl AsRef<Path>) -> Result<()> {
 
     let path = path.as_ref();
     virtual_file::init(10);
-    page_cache::init(100);
+    page_cache::init(100, 100);
     let file = FileBlockReader::new(VirtualFile::open(path)?);
     let summary_blk = file.read_blk(0)?;
     let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
+    let file = if actual_summary.compressed_blocks {
+        let file_len = file.file.metadata()?.len();
+        file.with_compressed_blocks(file_len)?
+    } else {
+        file
+    };
     let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
         actual_summary.index_start_blk,
         actual_summary.index_root_blk,
@@ -165,6 +185,44 @@ pub(crate) async fn main(cmd: &LayerCmd) -> Result<()> {
                 }
             }
         }
+        LayerCmd::VerifyLayers {
+            path,
+            tenant,
+            timeline,
+            move_aside: do_move_aside,
+        } => {
+            let timeline_path = path
+                .join("tenants")
+                .join(tenant)
+                .join("timelines")
+                .join(timeline);
+            let mut corrupt_layers = 0;
+            for layer in fs::read_dir(timeline_path)? {
+                let layer = layer?;
+                if parse_filename(&layer.file_name().into_string().unwrap()).is_none() {
+                    continue;
+                }
+                let layer_path = layer.path();
+                match find_corrupt_blocks(&layer_path) {
+                    Ok(corrupt_blocks) if corrupt_blocks.is_empty() => {}
+                    Ok(corrupt_blocks) => {
+                        corrupt_layers += 1;
+                        println!(
+                            "{}: corrupt blocks {:?}",
+                            layer_path.display(),
+                            corrupt_blocks
+                        );
+                        // the pageserver ignores the renamed files on startup
+                        if *do_move_aside {
+                            let new_path = rename_to_backup(&layer_path)?;
+                            println!("moved to {}", new_path.display());
+                        }
+                    }
+                    Err(e) => println!("{}: cannot verify: {e:#}", layer_path.display()),
+                }
+            }
+            println!("found {corrupt_layers} corrupt layer files");
+        }
     }
     Ok(())
 }
@@ -3,19 +3,25 @@
 //! and prints its interpreted context.
 //!
 //! Separate, `metadata` subcommand allows to print and update pageserver's metadata file.
+//! The `index-part` subcommands inspect and edit the remote index of a timeline.
 
 mod draw_timeline_dir;
+mod index_part;
 mod layer_map_analyzer;
 mod layers;
 
 use clap::{Parser, Subcommand};
+use index_part::IndexPartCmd;
 use layers::LayerCmd;
 use pageserver::{
     context::{DownloadBehavior, RequestContext},
     page_cache,
     task_mgr::TaskKind,
-    tenant::{dump_layerfile_from_path, metadata::TimelineMetadata},
-    virtual_file,
+    tenant::{
+        dump_layerfile_from_path,
+        metadata::{replay_metadata_log, TimelineMetadata},
+    },
+    virtual_file, METADATA_LOG_FILE_NAME,
 };
 use postgres_ffi::ControlFileData;
 use std::path::{Path, PathBuf};
@@ -43,6 +49,8 @@ enum Commands {
     AnalyzeLayerMap(AnalyzeLayerMapCmd),
     #[command(subcommand)]
     Layer(LayerCmd),
+    #[command(subcommand)]
+    IndexPart(IndexPartCmd),
 }
 
 /// Read and update pageserver metadata file
@@ -80,6 +88,9 @@ async fn main() -> anyhow::Result<()> {
         Commands::Layer(cmd) => {
             layers::main(&cmd).await?;
         }
+        Commands::IndexPart(cmd) => {
+            index_part::main(&cmd).await?;
+        }
         Commands::Metadata(cmd) => {
             handle_metadata(&cmd)?;
         }
@@ -117,7 +128,7 @@ fn read_pg_control_file(control_file_path: &Path) -> anyhow::Result<()> {
 async fn print_layerfile(path: &Path) -> anyhow::Result<()> {
     // Basic initialization of things that don't change after startup
     virtual_file::init(10);
-    page_cache::init(100);
+    page_cache::init(100, 100);
     let ctx = RequestContext::new(TaskKind::DebugTool, DownloadBehavior::Error);
     dump_layerfile_from_path(path, true, &ctx).await
 }
@@ -132,6 +143,10 @@ fn handle_metadata(
 ) -> Result<(), anyhow::Error> {
     let metadata_bytes = std::fs::read(path)?;
     let mut meta = TimelineMetadata::from_bytes(&metadata_bytes)?;
+    if let Ok(log_bytes) = std::fs::read(path.with_file_name(METADATA_LOG_FILE_NAME)) {
+        let applied = replay_metadata_log(&mut meta, &log_bytes);
+        println!("Applied {applied} records from the metadata log");
+    }
     println!("Current metadata:\n{meta:?}");
     let mut update_meta = false;
     if let Some(disk_consistent_lsn) = disk_consistent_lsn {
@@ -11,12 +11,19 @@
 //! from data stored in object storage.
 //!
 use anyhow::{anyhow, bail, ensure, Context};
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use fail::fail_point;
+use futures::{Stream, StreamExt, TryStreamExt};
+use std::collections::HashSet;
 use std::fmt::Write as FmtWrite;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::Arc;
 use std::time::SystemTime;
 use tokio::io;
 use tokio::io::AsyncWrite;
+use tokio_util::io::StreamReader;
 use tracing::*;
 
 use tokio_tar::{Builder, EntryType, Header};
@@ -35,6 +42,13 @@ use postgres_ffi::PG_TLI;
 use postgres_ffi::{BLCKSZ, RELSEG_SIZE, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
+/// Number of relation blocks to fetch with one vectored read.
+const VECTORED_READ_BLOCKS: u32 = 256;
+
+/// Number of relation block batches or SLRU segments read at once, each by a task
+/// of its own, ahead of the one being added to the tarball.
+const READ_CONCURRENCY: usize = 8;
+
 /// Create basebackup with non-rel data in it.
 /// Only include relational data if 'full_backup' is true.
 ///
@@ -45,7 +59,7 @@ use utils::lsn::Lsn;
 ///    to start the replication.
 pub async fn send_basebackup_tarball<'a, W>(
     write: &'a mut W,
-    timeline: &'a Timeline,
+    timeline: &'a Arc<Timeline>,
     req_lsn: Option<Lsn>,
     prev_lsn: Option<Lsn>,
     full_backup: bool,
@@ -124,7 +138,7 @@ where
     W: AsyncWrite + Send + Sync + Unpin,
 {
     ar: Builder<&'a mut W>,
-    timeline: &'a Timeline,
+    timeline: &'a Arc<Timeline>,
     lsn: Lsn,
     prev_record_lsn: Lsn,
     full_backup: bool,
@@ -166,6 +180,7 @@ where
         }
 
         // Gather non-relational files from object storage pages.
+        let mut slru_segments = Vec::new();
         for kind in [
             SlruKind::Clog,
             SlruKind::MultiXactOffsets,
@@ -177,22 +192,29 @@ where
                 .list_slru_segments(kind, Version::Lsn(self.lsn), self.ctx)
                 .await?
             {
-                self.add_slru_segment(kind, segno).await?;
+                slru_segments.push((kind, segno));
             }
         }
+        let mut slru_segments = pin!(self.read_slru_segments(slru_segments));
+        while let Some((kind, segno, data)) = slru_segments.try_next().await? {
+            let segname = format!("{}/{:>04X}", kind.to_str(), segno);
+            let header = new_tar_header(&segname, data.len() as u64)?;
+            self.ar.append(&header, &data[..]).await?;
+            trace!("Added to basebackup slru {} size {}", segname, data.len());
+        }
 
-        // Create tablespace directories
+        // Find out the relation files to include, to read them in parallel
+        let mut dbdirs = Vec::new();
         for ((spcnode, dbnode), has_relmap_file) in
             self.timeline.list_dbdirs(self.lsn, self.ctx).await?
         {
-            self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
-
             // If full backup is requested, include all relation files.
             // Otherwise only include init forks of unlogged relations.
             let rels = self
                 .timeline
                 .list_rels(spcnode, dbnode, Version::Lsn(self.lsn), self.ctx)
                 .await?;
+            let mut segments = Vec::new();
             for &rel in rels.iter() {
                 // Send init fork as main fork to provide well formed empty
                 // contents of UNLOGGED relations. Postgres copies it in
@@ -200,8 +222,9 @@ where
                 if rel.forknum == INIT_FORKNUM {
                     // I doubt we need _init fork itself, but having it at least
                     // serves as a marker relation is unlogged.
-                    self.add_rel(rel, rel).await?;
-                    self.add_rel(rel, rel.with_forknum(MAIN_FORKNUM)).await?;
+                    self.plan_rel(rel, rel, &mut segments).await?;
+                    self.plan_rel(rel, rel.with_forknum(MAIN_FORKNUM), &mut segments)
+                        .await?;
                     continue;
                 }
 
@@ -211,9 +234,29 @@ where
                         // skip this, will include it when we reach the init fork
                         continue;
                     }
-                    self.add_rel(rel, rel).await?;
+                    self.plan_rel(rel, rel, &mut segments).await?;
                 }
             }
+            dbdirs.push((spcnode, dbnode, has_relmap_file, segments));
+        }
+
+        // Create tablespace directories, with the relation files in them. The blocks
+        // are read by a stream over all the files, which reads ahead of the file
+        // being added.
+        let batches = dbdirs
+            .iter()
+            .flat_map(|(_, _, _, segments)| segments)
+            .flat_map(|segment| segment.batches())
+            .collect();
+        let mut batches = pin!(self.read_rel_batches(batches));
+        for (spcnode, dbnode, has_relmap_file, segments) in dbdirs {
+            self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
+            for segment in segments {
+                let file_name = segment.dst.to_segfile_name(segment.segno);
+                let header = new_tar_header(&file_name, segment.size())?;
+                let data = StreamReader::new(batches.as_mut().take(segment.batches().count()));
+                self.ar.append(&header, data).await?;
+            }
         }
         for xid in self
             .timeline
@@ -222,6 +265,7 @@ where
         {
             self.add_twophase_file(xid).await?;
         }
+        self.add_aux_files().await?;
 
         fail_point!("basebackup-before-control-file", |_| {
             bail!("failpoint basebackup-before-control-file")
@@ -234,78 +278,104 @@ where
         Ok(())
     }
 
-    /// Add contents of relfilenode `src`, naming it as `dst`.
-    async fn add_rel(&mut self, src: RelTag, dst: RelTag) -> anyhow::Result<()> {
+    /// Plan the files with the contents of relfilenode `src`, naming them as `dst`:
+    /// one for each chunk of blocks (aka segment), or an empty one if the relation
+    /// is empty.
+    async fn plan_rel(
+        &self,
+        src: RelTag,
+        dst: RelTag,
+        segments: &mut Vec<RelSegment>,
+    ) -> anyhow::Result<()> {
         let nblocks = self
             .timeline
             .get_rel_size(src, Version::Lsn(self.lsn), false, self.ctx)
             .await?;
+        // The sizes are ingested without the page versions, don't send zeroes for them
+        anyhow::ensure!(
+            nblocks == 0 || self.timeline.ingests_relation_data(),
+            "the pages of region {} are not ingested by this pageserver",
+            self.timeline.region_id
+        );
 
-        // If the relation is empty, create an empty file
-        if nblocks == 0 {
-            let file_name = dst.to_segfile_name(0);
-            let header = new_tar_header(&file_name, 0)?;
-            self.ar.append(&header, &mut io::empty()).await?;
-            return Ok(());
-        }
-
-        // Add a file for each chunk of blocks (aka segment)
         let mut startblk = 0;
-        let mut seg = 0;
-        while startblk < nblocks {
+        let mut segno = 0;
+        loop {
             let endblk = std::cmp::min(startblk + RELSEG_SIZE, nblocks);
-
-            let mut segment_data: Vec<u8> = vec![];
-            for blknum in startblk..endblk {
-                let img = self
-                    .timeline
-                    .get_rel_page_at_lsn(src, blknum, Version::Lsn(self.lsn), false, self.ctx)
-                    .await?;
-                segment_data.extend_from_slice(&img[..]);
-            }
-
-            let file_name = dst.to_segfile_name(seg as u32);
-            let header = new_tar_header(&file_name, segment_data.len() as u64)?;
-            self.ar.append(&header, segment_data.as_slice()).await?;
-
-            seg += 1;
+            segments.push(RelSegment {
+                src,
+                dst,
+                segno,
+                blocks: startblk..endblk,
+            });
+            segno += 1;
             startblk = endblk;
-        }
-
-        Ok(())
-    }
-
-    //
-    // Generate SLRU segment files from repository.
-    //
-    async fn add_slru_segment(&mut self, slru: SlruKind, segno: u32) -> anyhow::Result<()> {
-        let nblocks = self
-            .timeline
-            .get_slru_segment_size(slru, segno, Version::Lsn(self.lsn), self.ctx)
-            .await?;
-
-        let mut slru_buf: Vec<u8> = Vec::with_capacity(nblocks as usize * BLCKSZ as usize);
-        for blknum in 0..nblocks {
-            let img = self
-                .timeline
-                .get_slru_page_at_lsn(slru, segno, blknum, self.lsn, self.ctx)
-                .await?;
-
-            if slru == SlruKind::Clog {
-                ensure!(img.len() == BLCKSZ as usize || img.len() == BLCKSZ as usize + 8);
-            } else {
-                ensure!(img.len() == BLCKSZ as usize);
+            if startblk >= nblocks {
+                return Ok(());
             }
-
-            slru_buf.extend_from_slice(&img[..BLCKSZ as usize]);
         }
+    }
 
-        let segname = format!("{}/{:>04X}", slru.to_str(), segno);
-        let header = new_tar_header(&segname, slru_buf.len() as u64)?;
-        self.ar.append(&header, slru_buf.as_slice()).await?;
+    /// Reads the given batches of relation blocks, in order, each by a task of its
+    /// own, up to [`READ_CONCURRENCY`] of them at once.
+    fn read_rel_batches(
+        &self,
+        batches: Vec<(RelTag, Range<u32>)>,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let timeline = Arc::clone(self.timeline);
+        let lsn = self.lsn;
+        let ctx = self.ctx.attached_child();
+        futures::stream::iter(batches)
+            .map(move |(rel, blocks)| {
+                let timeline = Arc::clone(&timeline);
+                let ctx = ctx.attached_child();
+                tokio::spawn(
+                    async move {
+                        let imgs = timeline
+                            .get_rel_pages_at_lsn(rel, blocks, lsn, &ctx)
+                            .await?;
+                        let mut batch = BytesMut::with_capacity(imgs.len() * BLCKSZ as usize);
+                        for img in imgs {
+                            // the size of the file is in the tar header already
+                            ensure!(img.len() == BLCKSZ as usize);
+                            batch.extend_from_slice(&img[..]);
+                        }
+                        anyhow::Ok(batch.freeze())
+                    }
+                    .in_current_span(),
+                )
+            })
+            .buffered(READ_CONCURRENCY)
+            .map(|joined| match joined {
+                Ok(Ok(batch)) => Ok(batch),
+                Ok(Err(e)) => Err(io::Error::other(format!("{e:#}"))),
+                Err(e) => Err(io::Error::other(e)),
+            })
+    }
 
-        trace!("Added to basebackup slru {} relsize {}", segname, nblocks);
-        Ok(())
+    /// Reads the given SLRU segments, in order, each by a task of its own, up to
+    /// [`READ_CONCURRENCY`] of them at once.
+    fn read_slru_segments(
+        &self,
+        segments: Vec<(SlruKind, u32)>,
+    ) -> impl Stream<Item = anyhow::Result<(SlruKind, u32, Bytes)>> + Send + 'static {
+        let timeline = Arc::clone(self.timeline);
+        let lsn = self.lsn;
+        let ctx = self.ctx.attached_child();
+        futures::stream::iter(segments)
+            .map(move |(kind, segno)| {
+                let timeline = Arc::clone(&timeline);
+                let ctx = ctx.attached_child();
+                tokio::spawn(
+                    async move {
+                        let data = read_slru_segment(&timeline, kind, segno, lsn, &ctx).await?;
+                        anyhow::Ok((kind, segno, data))
+                    }
+                    .in_current_span(),
+                )
+            })
+            .buffered(READ_CONCURRENCY)
+            .map(|joined| joined.context("SLRU segment read task failed")?)
     }
 
     //
@@ -408,6 +478,40 @@ where
         Ok(())
     }
 
+    //
+    // Add auxiliary files, like replication slot state
+    //
+    async fn add_aux_files(&mut self) -> anyhow::Result<()> {
+        let files = self.timeline.list_aux_files(self.lsn, self.ctx).await?;
+        let mut paths: Vec<&String> = files.keys().collect();
+        paths.sort_unstable();
+
+        // The parent directories of the files, e.g. pg_replslot/<slot name>,
+        // are not part of the standard directory structure.
+        let mut dirs: HashSet<&str> = PGDATA_SUBDIRS.iter().copied().collect();
+        for path in paths {
+            let mut parents = Vec::new();
+            let mut parent = Path::new(path).parent();
+            while let Some(dir) = parent.and_then(|p| p.to_str()) {
+                if dir.is_empty() || dirs.contains(dir) {
+                    break;
+                }
+                parents.push(dir);
+                parent = Path::new(dir).parent();
+            }
+            for dir in parents.into_iter().rev() {
+                let header = new_tar_header_dir(dir)?;
+                self.ar.append(&header, &mut io::empty()).await?;
+                dirs.insert(dir);
+            }
+
+            let content = &files[path];
+            let header = new_tar_header(path, content.len() as u64)?;
+            self.ar.append(&header, &content[..]).await?;
+        }
+        Ok(())
+    }
+
     //
     // Add generated pg_control file and bootstrap WAL segment.
     // Also send zenith.signal file with extra bootstrap data.
@@ -472,6 +576,63 @@ where
     }
 }
 
+/// A relation file of the tarball: the blocks of segment `segno` of relation `src`,
+/// named after `dst`.
+struct RelSegment {
+    src: RelTag,
+    dst: RelTag,
+    segno: u32,
+    blocks: Range<u32>,
+}
+
+impl RelSegment {
+    fn size(&self) -> u64 {
+        self.blocks.len() as u64 * BLCKSZ as u64
+    }
+
+    /// The vectored reads of the blocks of the segment.
+    fn batches(&self) -> impl Iterator<Item = (RelTag, Range<u32>)> + '_ {
+        self.blocks
+            .clone()
+            .step_by(VECTORED_READ_BLOCKS as usize)
+            .map(|startblk| {
+                let endblk = std::cmp::min(startblk + VECTORED_READ_BLOCKS, self.blocks.end);
+                (self.src, startblk..endblk)
+            })
+    }
+}
+
+//
+// Generate SLRU segment files from repository.
+//
+async fn read_slru_segment(
+    timeline: &Timeline,
+    slru: SlruKind,
+    segno: u32,
+    lsn: Lsn,
+    ctx: &RequestContext,
+) -> anyhow::Result<Bytes> {
+    let nblocks = timeline
+        .get_slru_segment_size(slru, segno, Version::Lsn(lsn), ctx)
+        .await?;
+
+    let mut slru_buf = BytesMut::with_capacity(nblocks as usize * BLCKSZ as usize);
+    for blknum in 0..nblocks {
+        let img = timeline
+            .get_slru_page_at_lsn(slru, segno, blknum, lsn, ctx)
+            .await?;
+
+        if slru == SlruKind::Clog {
+            ensure!(img.len() == BLCKSZ as usize || img.len() == BLCKSZ as usize + 8);
+        } else {
+            ensure!(img.len() == BLCKSZ as usize);
+        }
+
+        slru_buf.extend_from_slice(&img[..BLCKSZ as usize]);
+    }
+    Ok(slru_buf.freeze())
+}
+
 //
 // Create new tarball entry header
 //
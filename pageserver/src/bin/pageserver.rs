@@ -17,20 +17,21 @@ use tracing::*;
 
 use metrics::set_build_info_metric;
 use pageserver::{
-    config::{defaults::*, PageServerConf},
+    broker_publisher,
+    config::{defaults::*, reload::ConfigReloader, PageServerConf},
     context::{DownloadBehavior, RequestContext},
-    http, page_cache, page_service, task_mgr,
+    http, page_cache, page_service, shared_page_cache, task_mgr,
     task_mgr::TaskKind,
     task_mgr::{BACKGROUND_RUNTIME, COMPUTE_REQUEST_RUNTIME, MGMT_REQUEST_RUNTIME},
-    tenant::mgr,
-    virtual_file,
+    tenant::{mgr, tasks::scheduler},
+    top_tenants, virtual_file, xact_resolver,
 };
 use postgres_backend::AuthType;
 use utils::logging::TracingErrorLayerEnablement;
 use utils::signals::ShutdownSignals;
 use utils::{
-    auth::JwtAuth, logging, project_git_version, sentry_init::init_sentry, signals::Signal,
-    tcp_listener,
+    auth::JwtAuth, logging, project_git_version, region::RegionRegistry, sentry_init::init_sentry,
+    signals::Signal, tcp_listener, unix_listener,
 };
 
 project_git_version!(GIT_VERSION);
@@ -78,6 +79,12 @@ fn main() -> anyhow::Result<()> {
         )
     })?;
 
+    // Kept for re-reading the config at runtime, see `config::reload`
+    let config_overrides: Vec<String> = arg_matches
+        .get_many::<String>("config-override")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+
     let conf = match initialize_config(&cfg_file_path, arg_matches, &workdir)? {
         ControlFlow::Continue(conf) => conf,
         ControlFlow::Break(()) => {
@@ -99,6 +106,9 @@ fn main() -> anyhow::Result<()> {
         TracingErrorLayerEnablement::Disabled
     };
     logging::init(conf.log_format, tracing_error_layer_enablement)?;
+    if let Some(log_filter) = &conf.log_filter {
+        logging::reload_log_filter(Some(log_filter))?;
+    }
 
     // mind the order required here: 1. logging, 2. panic_hook, 3. sentry.
     // disarming this hook on pageserver, because we never tear down tracing.
@@ -124,10 +134,29 @@ fn main() -> anyhow::Result<()> {
     let scenario = FailScenario::setup();
 
     // Basic initialization of things that don't change after startup
+    utils::region::init(RegionRegistry::new(conf.regions.clone())?)?;
+    if let Some(region) = conf.region {
+        utils::region::tag_process(region)?;
+    }
     virtual_file::init(conf.max_file_descriptors);
-    page_cache::init(conf.page_cache_size);
+    page_cache::init(conf.page_cache_size, conf.max_page_cache_size);
+    shared_page_cache::init(conf.shared_page_cache_size);
+    page_service::fair_limiter::init(conf.get_page_concurrency_limit);
+    scheduler::init(conf.background_task_concurrency);
+    if let Some(endpoint) = &conf.xact_resolver_endpoint {
+        xact_resolver::init(endpoint.clone(), conf.xact_resolver_timeout);
+    }
+    match page_cache::get().load_snapshot(
+        &conf.page_cache_snapshot_path(),
+        conf.page_cache_snapshot_max_pages,
+    ) {
+        Ok(loaded) => info!("Loaded {loaded} pages from the page cache snapshot"),
+        Err(e) => warn!("Failed to load the page cache snapshot: {e:#}"),
+    }
+
+    let config_reloader = ConfigReloader::new(&cfg_file_path, config_overrides, conf);
 
-    start_pageserver(launch_ts, conf).context("Failed to start pageserver")?;
+    start_pageserver(launch_ts, conf, config_reloader).context("Failed to start pageserver")?;
 
     scenario.teardown();
     Ok(())
@@ -225,6 +254,7 @@ fn initialize_config(
 fn start_pageserver(
     launch_ts: &'static LaunchTimestamp,
     conf: &'static PageServerConf,
+    config_reloader: ConfigReloader,
 ) -> anyhow::Result<()> {
     // Monotonic time for later calculating startup duration
     let started_startup_at = Instant::now();
@@ -286,6 +316,32 @@ fn start_pageserver(
     let pg_addr = &conf.listen_pg_addr;
     info!("Starting pageserver pg protocol handler on {pg_addr}");
     let pageserver_listener = tcp_listener::bind(pg_addr)?;
+    let pageserver_unix_listener = match &conf.listen_pg_socket {
+        Some(pg_socket) => {
+            info!(
+                "Starting pageserver pg protocol handler on {}",
+                pg_socket.display()
+            );
+            Some(unix_listener::bind(pg_socket)?)
+        }
+        None => None,
+    };
+    let grpc_listener = match &conf.listen_grpc_addr {
+        Some(grpc_addr) => {
+            info!("Starting pageserver gRPC page service on {grpc_addr}");
+            Some(tcp_listener::bind(grpc_addr)?)
+        }
+        None => None,
+    };
+
+    let http_tls_config = conf
+        .http_listener
+        .load_tls_config()
+        .context("load http_listener TLS config")?;
+    let pg_tls_config = conf
+        .pg_listener
+        .load_tls_config()
+        .context("load pg_listener TLS config")?;
 
     // Launch broker client
     // The storage_broker::connect call needs to happen inside a tokio runtime thread.
@@ -327,6 +383,8 @@ fn start_pageserver(
     }
     info!("Using auth for http API: {:#?}", conf.http_auth_type);
     info!("Using auth for pg connections: {:#?}", conf.pg_auth_type);
+    // The gRPC page service serves the same requests as the libpq one
+    let grpc_auth = pg_auth.clone();
 
     match var("NEON_AUTH_TOKEN") {
         Ok(v) => {
@@ -468,6 +526,38 @@ fn start_pageserver(
         )?;
     }
 
+    if let Some(memory_budget) = conf.page_cache_memory_budget {
+        task_mgr::spawn(
+            BACKGROUND_RUNTIME.handle(),
+            TaskKind::PageCacheAutotune,
+            None,
+            None,
+            "page cache autotune",
+            false,
+            page_cache::autotune_task(memory_budget, task_mgr::shutdown_token()),
+        );
+    }
+
+    task_mgr::spawn(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::TopTenantsSampling,
+        None,
+        None,
+        "top tenants sampling",
+        false,
+        top_tenants::sampling_task(task_mgr::shutdown_token()),
+    );
+
+    task_mgr::spawn(
+        WALRECEIVER_RUNTIME.handle(),
+        TaskKind::BrokerPublisher,
+        None,
+        None,
+        "broker publisher",
+        false,
+        broker_publisher::publish_task(conf, broker_client.clone(), task_mgr::shutdown_token()),
+    );
+
     // Start up the service to handle HTTP mgmt API request. We created the
     // listener earlier already.
     {
@@ -480,13 +570,10 @@ fn start_pageserver(
             broker_client.clone(),
             remote_storage,
             disk_usage_eviction_state,
+            config_reloader,
         )?
         .build()
         .map_err(|err| anyhow!(err))?;
-        let service = utils::http::RouterService::new(router).unwrap();
-        let server = hyper::Server::from_tcp(http_listener)?
-            .serve(service)
-            .with_graceful_shutdown(task_mgr::shutdown_watcher());
 
         task_mgr::spawn(
             MGMT_REQUEST_RUNTIME.handle(),
@@ -495,9 +582,15 @@ fn start_pageserver(
             None,
             "http endpoint listener",
             true,
-            async {
-                server.await?;
-                Ok(())
+            async move {
+                http::server::serve(
+                    http_listener,
+                    router,
+                    &conf.http_listener,
+                    http_tls_config,
+                    task_mgr::shutdown_token(),
+                )
+                .await
             },
         );
     }
@@ -547,7 +640,36 @@ fn start_pageserver(
     }
 
     // Spawn a task to listen for libpq connections. It will spawn further tasks
-    // for each connection. We created the listener earlier already.
+    // for each connection. We created the listeners earlier already.
+    let pg_connection_limit = conf.pg_listener.connection_limit();
+    if let Some(pageserver_unix_listener) = pageserver_unix_listener {
+        let libpq_ctx =
+            RequestContext::todo_child(TaskKind::LibpqEndpointListener, DownloadBehavior::Error);
+        let broker_client = broker_client.clone();
+        let pg_auth = pg_auth.clone();
+        let pg_connection_limit = pg_connection_limit.clone();
+        task_mgr::spawn(
+            COMPUTE_REQUEST_RUNTIME.handle(),
+            TaskKind::LibpqEndpointListener,
+            None,
+            None,
+            "libpq unix socket listener",
+            true,
+            async move {
+                page_service::libpq_listener_main(
+                    conf,
+                    broker_client,
+                    pg_auth,
+                    page_service::Listener::Unix(pageserver_unix_listener),
+                    conf.pg_auth_type,
+                    None,
+                    pg_connection_limit,
+                    libpq_ctx,
+                )
+                .await
+            },
+        );
+    }
     {
         let libpq_ctx = RequestContext::todo_child(
             TaskKind::LibpqEndpointListener,
@@ -569,8 +691,10 @@ fn start_pageserver(
                     conf,
                     broker_client,
                     pg_auth,
-                    pageserver_listener,
+                    page_service::Listener::Tcp(pageserver_listener),
                     conf.pg_auth_type,
+                    pg_tls_config,
+                    pg_connection_limit,
                     libpq_ctx,
                 )
                 .await
@@ -578,6 +702,22 @@ fn start_pageserver(
         );
     }
 
+    if let Some(grpc_listener) = grpc_listener {
+        let grpc_ctx =
+            RequestContext::todo_child(TaskKind::LibpqEndpointListener, DownloadBehavior::Error);
+        task_mgr::spawn(
+            COMPUTE_REQUEST_RUNTIME.handle(),
+            TaskKind::LibpqEndpointListener,
+            None,
+            None,
+            "gRPC page service listener",
+            true,
+            async move {
+                page_service::grpc::grpc_listener_main(grpc_auth, grpc_listener, grpc_ctx).await
+            },
+        );
+    }
+
     let mut shutdown_pageserver = Some(shutdown_pageserver.drop_guard());
 
     // All started up! Now just sit and wait for shutdown signal.
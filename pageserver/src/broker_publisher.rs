@@ -0,0 +1,87 @@
+//! Publishing the timelines the pageserver wants WAL of to the broker.
+//!
+//! The safekeepers learn from these messages where to push the WAL of a
+//! timeline while the broker is unreachable, see the `push_wal` page service
+//! command. [`publish_task`] publishes every active timeline of the active
+//! tenants every [`PUBLISH_INTERVAL`], so that a safekeeper can forget a
+//! pageserver that stopped wanting the WAL of a timeline.
+
+use std::time::Duration;
+
+use storage_broker::proto::{PageserverTimelineInfo, TenantTimelineId as ProtoTenantTimelineId};
+use storage_broker::{BrokerClientChannel, Request};
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+
+use crate::config::PageServerConf;
+use crate::tenant::mgr;
+
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+async fn active_timelines(conf: &PageServerConf) -> Vec<PageserverTimelineInfo> {
+    let tenant_ids = match mgr::list_tenants().await {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            warn!("failed to list tenants: {e:#}");
+            return Vec::new();
+        }
+    };
+    let pg_addr = conf
+        .advertise_pg_addr
+        .as_ref()
+        .unwrap_or(&conf.listen_pg_addr);
+    let pageserver_connstr = format!("postgresql://no_user@{pg_addr}");
+    let mut infos = Vec::new();
+    for (tenant_id, _) in tenant_ids {
+        // The tenant may have been detached or deactivated since it was listed
+        let Ok(tenant) = mgr::get_tenant(tenant_id, true).await else {
+            continue;
+        };
+        for timeline in tenant.list_timelines() {
+            if !timeline.is_active() {
+                continue;
+            }
+            infos.push(PageserverTimelineInfo {
+                pageserver_id: conf.id.0,
+                tenant_timeline_id: Some(ProtoTenantTimelineId {
+                    tenant_id: tenant_id.as_ref().to_owned(),
+                    timeline_id: timeline.timeline_id.as_ref().to_owned(),
+                }),
+                pageserver_connstr: pageserver_connstr.clone(),
+            });
+        }
+    }
+    infos
+}
+
+/// Publishes the active timelines every [`PUBLISH_INTERVAL`], reconnecting to
+/// the broker on errors.
+pub async fn publish_task(
+    conf: &'static PageServerConf,
+    mut broker_client: BrokerClientChannel,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        let outbound = async_stream::stream! {
+            loop {
+                for info in active_timelines(conf).await {
+                    yield info;
+                }
+                tokio::time::sleep(PUBLISH_INTERVAL).await;
+            }
+        };
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            res = broker_client.publish_pageserver_info(Request::new(outbound)) => match res {
+                Ok(_) => warn!("broker closed the publication"),
+                Err(e) => warn!("publication to the broker failed: {e}"),
+            },
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+        }
+    }
+}
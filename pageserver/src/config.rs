@@ -24,21 +24,33 @@ use toml_edit::{Document, Item};
 
 use postgres_backend::AuthType;
 use utils::{
-    id::{NodeId, TenantId, TimelineId},
+    id::{NodeId, RegionId, TenantId, TimelineId},
     logging::LogFormat,
+    region::{RegionConfig, RegionRegistry},
+    resizable_semaphore::ResizableSemaphore,
 };
 
+use self::listener::ListenerConfig;
 use crate::disk_usage_eviction_task::DiskUsageEvictionTaskConfig;
+use crate::tenant::attach_download::AttachDownloadConfig;
+use crate::tenant::config::validate_layer_compression_level;
 use crate::tenant::config::TenantConf;
 use crate::tenant::config::TenantConfOpt;
+use crate::tenant::tasks::scheduler::BackgroundTaskConcurrency;
 use crate::tenant::{
-    TENANT_ATTACHING_MARKER_FILENAME, TENANT_DELETED_MARKER_FILE_NAME, TIMELINES_SEGMENT_NAME,
+    TENANTS_SEGMENT_NAME, TENANT_ATTACHING_MARKER_FILENAME, TENANT_DELETED_MARKER_FILE_NAME,
+    TIMELINES_SEGMENT_NAME,
 };
 use crate::{
-    IGNORED_TENANT_FILE_NAME, METADATA_FILE_NAME, TENANT_CONFIG_NAME, TIMELINE_DELETE_MARK_SUFFIX,
+    CLEAN_SHUTDOWN_MARKER_FILE_NAME, IGNORED_TENANT_FILE_NAME, LABELS_FILE_NAME,
+    METADATA_FILE_NAME, METADATA_LOG_FILE_NAME, RELATION_DATA_SKIPPED_FILE_NAME,
+    TENANT_CONFIG_NAME, TIMELINE_CONFIG_FILE_NAME, TIMELINE_DELETE_MARK_SUFFIX,
     TIMELINE_UNINIT_MARK_SUFFIX,
 };
 
+pub mod listener;
+pub mod reload;
+
 pub mod defaults {
     use crate::tenant::config::defaults::*;
     use const_format::formatcp;
@@ -51,10 +63,13 @@ pub mod defaults {
 
     pub const DEFAULT_WAIT_LSN_TIMEOUT: &str = "60 s";
     pub const DEFAULT_WAL_REDO_TIMEOUT: &str = "60 s";
+    pub const DEFAULT_WAL_REDO_MAX_BATCH_SIZE: usize = 16;
+    pub const DEFAULT_WAL_REDO_NATIVE: bool = false;
 
     pub const DEFAULT_SUPERUSER: &str = "cloud_admin";
 
     pub const DEFAULT_PAGE_CACHE_SIZE: usize = 8192;
+    pub const DEFAULT_PAGE_CACHE_SNAPSHOT_MAX_PAGES: usize = 8192;
     pub const DEFAULT_MAX_FILE_DESCRIPTORS: usize = 100;
 
     pub const DEFAULT_LOG_FORMAT: &str = "plain";
@@ -67,9 +82,24 @@ pub mod defaults {
     pub const DEFAULT_METRIC_COLLECTION_ENDPOINT: Option<reqwest::Url> = None;
     pub const DEFAULT_SYNTHETIC_SIZE_CALCULATION_INTERVAL: &str = "10 min";
     pub const DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY: &str = "10s";
+    pub const DEFAULT_SHUTDOWN_UPLOAD_TIMEOUT: &str = "30s";
 
     pub const DEFAULT_INGEST_BATCH_SIZE: u64 = 100;
 
+    pub const DEFAULT_BASEBACKUP_CACHE_TTL: &str = "10 min";
+
+    pub const DEFAULT_GET_PAGE_CONCURRENCY_LIMIT: usize = 128;
+
+    pub const DEFAULT_PAGE_READAHEAD_BLOCKS: usize = 32;
+
+    pub const DEFAULT_SHARED_PAGE_CACHE_SIZE: usize = 0;
+
+    pub const DEFAULT_COMPACTION_CONCURRENCY: usize = 8;
+    pub const DEFAULT_GC_CONCURRENCY: usize = 8;
+    pub const DEFAULT_LOGICAL_SIZE_RECONCILIATION_CONCURRENCY: usize = 4;
+
+    pub const DEFAULT_XACT_RESOLVER_TIMEOUT: &str = "1 s";
+
     ///
     /// Default built-in configuration file.
     ///
@@ -78,11 +108,21 @@ pub mod defaults {
 # Initial configuration file created by 'pageserver --init'
 #listen_pg_addr = '{DEFAULT_PG_LISTEN_ADDR}'
 #listen_http_addr = '{DEFAULT_HTTP_LISTEN_ADDR}'
+#listen_pg_socket = 'pageserver.sock'
+#listen_grpc_addr = '127.0.0.1:51051'
+#advertise_pg_addr = '127.0.0.1:64000'
+#pg_listener = {{ max_connections = .., tls_cert_file = .., tls_key_file = .. }}
+#http_listener = {{ max_connections = .., tls_cert_file = .., tls_key_file = .. }}
 
 #wait_lsn_timeout = '{DEFAULT_WAIT_LSN_TIMEOUT}'
 #wal_redo_timeout = '{DEFAULT_WAL_REDO_TIMEOUT}'
+#wal_redo_max_batch_size = {DEFAULT_WAL_REDO_MAX_BATCH_SIZE}
+#wal_redo_native = {DEFAULT_WAL_REDO_NATIVE}
 
 #page_cache_size = {DEFAULT_PAGE_CACHE_SIZE}
+#max_page_cache_size = <page_cache_size>
+#page_cache_memory_budget = <unlimited>
+#page_cache_snapshot_max_pages = {DEFAULT_PAGE_CACHE_SNAPSHOT_MAX_PAGES}
 #max_file_descriptors = {DEFAULT_MAX_FILE_DESCRIPTORS}
 
 # initial superuser role name to use when creating a new tenant
@@ -91,6 +131,7 @@ pub mod defaults {
 #broker_endpoint = '{BROKER_DEFAULT_ENDPOINT}'
 
 #log_format = '{DEFAULT_LOG_FORMAT}'
+#log_filter = <RUST_LOG, or 'info' if not set>
 
 #concurrent_tenant_size_logical_size_queries = '{DEFAULT_CONCURRENT_TENANT_SIZE_LOGICAL_SIZE_QUERIES}'
 
@@ -101,8 +142,24 @@ pub mod defaults {
 #disk_usage_based_eviction = {{ max_usage_pct = .., min_avail_bytes = .., period = "10s"}}
 
 #background_task_maximum_delay = '{DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY}'
+#shutdown_upload_timeout = '{DEFAULT_SHUTDOWN_UPLOAD_TIMEOUT}'
 
 #ingest_batch_size = {DEFAULT_INGEST_BATCH_SIZE}
+#walreceiver_compression = false
+#ephemeral_file_compression = false
+#verify_layer_writes = false
+#basebackup_cache_ttl = '{DEFAULT_BASEBACKUP_CACHE_TTL}'
+#attach_download_layers = {{ max_concurrent_downloads = 8, max_bytes_per_second = .. }}
+
+#get_page_concurrency_limit = {DEFAULT_GET_PAGE_CONCURRENCY_LIMIT}
+#page_readahead_blocks = {DEFAULT_PAGE_READAHEAD_BLOCKS}
+#shared_page_cache_size = {DEFAULT_SHARED_PAGE_CACHE_SIZE}
+#background_task_concurrency = {{ compaction = {DEFAULT_COMPACTION_CONCURRENCY}, gc = {DEFAULT_GC_CONCURRENCY}, logical_size_reconciliation = {DEFAULT_LOGICAL_SIZE_RECONCILIATION_CONCURRENCY} }}
+#regions = [{{ id = 0, name = 'global' }}]
+#region = 'global'
+#ingest_regions = ['global']
+#xact_resolver_endpoint = 'http://localhost:8080/v1/xact_outcomes'
+#xact_resolver_timeout = '{DEFAULT_XACT_RESOLVER_TIMEOUT}'
 
 [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
@@ -114,11 +171,17 @@ pub mod defaults {
 #gc_period = '{DEFAULT_GC_PERIOD}'
 #gc_horizon = {DEFAULT_GC_HORIZON}
 #image_creation_threshold = {DEFAULT_IMAGE_CREATION_THRESHOLD}
+#slru_image_creation_threshold = {DEFAULT_SLRU_IMAGE_CREATION_THRESHOLD}
 #pitr_interval = '{DEFAULT_PITR_INTERVAL}'
 
 #min_resident_size_override = .. # in bytes
 #evictions_low_residence_duration_metric_threshold = '{DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD}'
 #gc_feedback = false
+#layer_compression_level = .. # zstd level, layer files are not compressed if unset
+#get_page_weight = {DEFAULT_GET_PAGE_WEIGHT}
+#logical_size_reconcile_period = '{DEFAULT_LOGICAL_SIZE_RECONCILE_PERIOD}'
+#pagestream_rate_limit = .. # requests per second, not limited if unset
+#wait_lsn_timeout = .. # the pageserver-wide wait_lsn_timeout if unset
 
 [remote_storage]
 
@@ -136,6 +199,19 @@ pub struct PageServerConf {
     pub listen_pg_addr: String,
     /// Example (default): 127.0.0.1:9898
     pub listen_http_addr: String,
+    /// Unix domain socket to accept page service connections on, in addition
+    /// to `listen_pg_addr`. For computes running on the same host.
+    pub listen_pg_socket: Option<PathBuf>,
+    /// Address of the experimental gRPC page service, disabled if not set.
+    pub listen_grpc_addr: Option<String>,
+    /// Address the safekeepers reach the page service at, published to the
+    /// broker. `listen_pg_addr` if not set, which only works when the
+    /// pageserver binds to a routable address.
+    pub advertise_pg_addr: Option<String>,
+    /// Connection limit and TLS settings of the page service.
+    pub pg_listener: ListenerConfig,
+    /// Connection limit and TLS settings of the management API.
+    pub http_listener: ListenerConfig,
 
     /// Current availability zone. Used for traffic metrics.
     pub availability_zone: Option<String>,
@@ -144,10 +220,26 @@ pub struct PageServerConf {
     pub wait_lsn_timeout: Duration,
     // How long to wait for WAL redo to complete.
     pub wal_redo_timeout: Duration,
+    /// Maximum number of pages of adjacent blocks of a relation reconstructed with one
+    /// round trip to the WAL redo process, when many pages are read at once. 1 disables
+    /// the batching.
+    pub wal_redo_max_batch_size: NonZeroUsize,
+    /// Whether the simplest Postgres WAL records, like HOT updates, are replayed by the
+    /// pageserver itself instead of the WAL redo process.
+    pub wal_redo_native: bool,
 
     pub superuser: String,
 
     pub page_cache_size: usize,
+    /// Number of page cache pages allocated at startup, the upper limit when
+    /// resizing the page cache at runtime. Never smaller than `page_cache_size`.
+    pub max_page_cache_size: usize,
+    /// If set, the page cache is periodically resized so that the process
+    /// resident memory stays within this many bytes.
+    pub page_cache_memory_budget: Option<u64>,
+    /// Number of the hottest materialized pages saved on shutdown and loaded
+    /// back into the page cache on startup. 0 disables the snapshot.
+    pub page_cache_snapshot_max_pages: usize,
     pub max_file_descriptors: usize,
 
     // Repository directory, relative to current working directory.
@@ -178,6 +270,9 @@ pub struct PageServerConf {
     pub broker_keepalive_interval: Duration,
 
     pub log_format: LogFormat,
+    /// Log filter directives in the `RUST_LOG` syntax, overriding `RUST_LOG` if set.
+    /// Can be changed without a restart, see [`reload`].
+    pub log_filter: Option<String>,
 
     /// Number of concurrent [`Tenant::gather_size_inputs`](crate::tenant::Tenant::gather_size_inputs) allowed.
     pub concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore,
@@ -210,8 +305,72 @@ pub struct PageServerConf {
     /// not terrible.
     pub background_task_maximum_delay: Duration,
 
+    /// How long a graceful shutdown waits for the remote uploads of a timeline to finish.
+    /// The timelines whose uploads don't finish in time are shut down without a
+    /// clean shutdown marker.
+    pub shutdown_upload_timeout: Duration,
+
     /// Maximum number of WAL records to be ingested and committed at the same time
     pub ingest_batch_size: u64,
+
+    /// Ask the safekeepers to compress the WAL streamed to us, trading CPU for bandwidth.
+    /// Safekeepers that don't support compression stream uncompressed WAL.
+    pub walreceiver_compression: bool,
+
+    /// Compress the pages of the open in-memory layers with lz4 when they're spilled
+    /// to disk, trading CPU for less temporary disk usage.
+    pub ephemeral_file_compression: bool,
+
+    /// Read every new layer file back once it's synced, and check it against the
+    /// CRC32C of the data written, to catch the corruption on the write path before
+    /// the layer is used or uploaded. Doubles the IO of the layer writes.
+    pub verify_layer_writes: bool,
+
+    /// How long the last basebackup of a timeline is kept in memory, to be sent again
+    /// to the computes asking for one at the same LSN. Zero disables the caching.
+    pub basebackup_cache_ttl: Duration,
+
+    /// If set, attaching a tenant downloads all of its layers, at the given
+    /// concurrency and bandwidth, before the tenant is activated.
+    pub attach_download_layers: Option<AttachDownloadConfig>,
+
+    /// Maximum number of GetPage requests processed at once, shared between the
+    /// tenants according to their `get_page_weight`.
+    pub get_page_concurrency_limit: NonZeroUsize,
+
+    /// How many blocks past a sequential scan of a relation are read ahead on its
+    /// page service connection. 0 disables the readahead.
+    pub page_readahead_blocks: usize,
+
+    /// Number of distinct page images in the cache shared by all the tenants, which
+    /// stores identical images once. 0 disables the cache.
+    pub shared_page_cache_size: usize,
+
+    /// How many iterations of the per-tenant compaction, GC and logical size
+    /// reconciliation run at once across all the tenants, see
+    /// [`crate::tenant::tasks::scheduler`].
+    pub background_task_concurrency: BackgroundTaskConcurrency,
+
+    /// Names and metadata of the regions, see [`utils::region`]. Empty to accept
+    /// any region.
+    pub regions: Vec<RegionConfig>,
+    /// The region the pageserver runs in, if set, is added to all its metrics, as the
+    /// `node_region` label, and to its log lines.
+    pub region: Option<RegionId>,
+    /// The regions whose relation data the pageserver ingests. The timelines of the
+    /// other regions only get their metadata, SLRUs and relation sizes from the WAL,
+    /// the page versions are skipped, and their pages can't be read. Empty to ingest
+    /// the relation data of all the regions. Changing the list needs a restart, and a
+    /// timeline whose relation data was skipped keeps refusing page reads after its
+    /// region is added back.
+    pub ingest_regions: Vec<RegionId>,
+
+    /// Where to ask for the outcomes of the transactions spanning regions, see
+    /// [`crate::xact_resolver`]. The CLOG pages are served as they are if not set.
+    pub xact_resolver_endpoint: Option<Url>,
+    /// How long to wait for the transaction resolver before serving a CLOG page
+    /// without the outcomes.
+    pub xact_resolver_timeout: Duration,
 }
 
 /// We do not want to store this in a PageServerConf because the latter may be logged
@@ -244,14 +403,27 @@ struct PageServerConfigBuilder {
 
     listen_http_addr: BuilderValue<String>,
 
+    listen_pg_socket: BuilderValue<Option<PathBuf>>,
+    listen_grpc_addr: BuilderValue<Option<String>>,
+    advertise_pg_addr: BuilderValue<Option<String>>,
+
+    pg_listener: BuilderValue<ListenerConfig>,
+
+    http_listener: BuilderValue<ListenerConfig>,
+
     availability_zone: BuilderValue<Option<String>>,
 
     wait_lsn_timeout: BuilderValue<Duration>,
     wal_redo_timeout: BuilderValue<Duration>,
+    wal_redo_max_batch_size: BuilderValue<NonZeroUsize>,
+    wal_redo_native: BuilderValue<bool>,
 
     superuser: BuilderValue<String>,
 
     page_cache_size: BuilderValue<usize>,
+    max_page_cache_size: BuilderValue<Option<usize>>,
+    page_cache_memory_budget: BuilderValue<Option<u64>>,
+    page_cache_snapshot_max_pages: BuilderValue<usize>,
     max_file_descriptors: BuilderValue<usize>,
 
     workdir: BuilderValue<PathBuf>,
@@ -271,6 +443,7 @@ struct PageServerConfigBuilder {
     broker_keepalive_interval: BuilderValue<Duration>,
 
     log_format: BuilderValue<LogFormat>,
+    log_filter: BuilderValue<Option<String>>,
 
     concurrent_tenant_size_logical_size_queries: BuilderValue<NonZeroUsize>,
 
@@ -287,7 +460,33 @@ struct PageServerConfigBuilder {
 
     background_task_maximum_delay: BuilderValue<Duration>,
 
+    shutdown_upload_timeout: BuilderValue<Duration>,
+
     ingest_batch_size: BuilderValue<u64>,
+
+    walreceiver_compression: BuilderValue<bool>,
+    ephemeral_file_compression: BuilderValue<bool>,
+    verify_layer_writes: BuilderValue<bool>,
+
+    basebackup_cache_ttl: BuilderValue<Duration>,
+    attach_download_layers: BuilderValue<Option<AttachDownloadConfig>>,
+
+    get_page_concurrency_limit: BuilderValue<NonZeroUsize>,
+
+    page_readahead_blocks: BuilderValue<usize>,
+
+    shared_page_cache_size: BuilderValue<usize>,
+
+    background_task_concurrency: BuilderValue<BackgroundTaskConcurrency>,
+
+    regions: BuilderValue<Vec<RegionConfig>>,
+    /// Resolved against `regions` when the config is built.
+    region: BuilderValue<Option<String>>,
+    /// Resolved against `regions` when the config is built.
+    ingest_regions: BuilderValue<Vec<String>>,
+
+    xact_resolver_endpoint: BuilderValue<Option<Url>>,
+    xact_resolver_timeout: BuilderValue<Duration>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -297,13 +496,24 @@ impl Default for PageServerConfigBuilder {
         Self {
             listen_pg_addr: Set(DEFAULT_PG_LISTEN_ADDR.to_string()),
             listen_http_addr: Set(DEFAULT_HTTP_LISTEN_ADDR.to_string()),
+            listen_pg_socket: Set(None),
+            listen_grpc_addr: Set(None),
+            advertise_pg_addr: Set(None),
+            pg_listener: Set(ListenerConfig::default()),
+            http_listener: Set(ListenerConfig::default()),
             availability_zone: Set(None),
             wait_lsn_timeout: Set(humantime::parse_duration(DEFAULT_WAIT_LSN_TIMEOUT)
                 .expect("cannot parse default wait lsn timeout")),
             wal_redo_timeout: Set(humantime::parse_duration(DEFAULT_WAL_REDO_TIMEOUT)
                 .expect("cannot parse default wal redo timeout")),
+            wal_redo_max_batch_size: Set(NonZeroUsize::new(DEFAULT_WAL_REDO_MAX_BATCH_SIZE)
+                .expect("Invalid default constant")),
+            wal_redo_native: Set(DEFAULT_WAL_REDO_NATIVE),
             superuser: Set(DEFAULT_SUPERUSER.to_string()),
             page_cache_size: Set(DEFAULT_PAGE_CACHE_SIZE),
+            max_page_cache_size: Set(None),
+            page_cache_memory_budget: Set(None),
+            page_cache_snapshot_max_pages: Set(DEFAULT_PAGE_CACHE_SNAPSHOT_MAX_PAGES),
             max_file_descriptors: Set(DEFAULT_MAX_FILE_DESCRIPTORS),
             workdir: Set(PathBuf::new()),
             pg_distrib_dir: Set(env::current_dir()
@@ -322,6 +532,7 @@ impl Default for PageServerConfigBuilder {
             )
             .expect("cannot parse default keepalive interval")),
             log_format: Set(LogFormat::from_str(DEFAULT_LOG_FORMAT).unwrap()),
+            log_filter: Set(None),
 
             concurrent_tenant_size_logical_size_queries: Set(
                 ConfigurableSemaphore::DEFAULT_INITIAL,
@@ -351,7 +562,37 @@ impl Default for PageServerConfigBuilder {
             )
             .unwrap()),
 
+            shutdown_upload_timeout: Set(humantime::parse_duration(
+                DEFAULT_SHUTDOWN_UPLOAD_TIMEOUT,
+            )
+            .unwrap()),
+
             ingest_batch_size: Set(DEFAULT_INGEST_BATCH_SIZE),
+
+            walreceiver_compression: Set(false),
+            ephemeral_file_compression: Set(false),
+            verify_layer_writes: Set(false),
+
+            basebackup_cache_ttl: Set(humantime::parse_duration(DEFAULT_BASEBACKUP_CACHE_TTL)
+                .expect("cannot parse default basebackup cache ttl")),
+            attach_download_layers: Set(None),
+
+            get_page_concurrency_limit: Set(NonZeroUsize::new(DEFAULT_GET_PAGE_CONCURRENCY_LIMIT)
+                .expect("Invalid default constant")),
+
+            page_readahead_blocks: Set(DEFAULT_PAGE_READAHEAD_BLOCKS),
+
+            shared_page_cache_size: Set(DEFAULT_SHARED_PAGE_CACHE_SIZE),
+
+            background_task_concurrency: Set(BackgroundTaskConcurrency::default()),
+
+            regions: Set(Vec::new()),
+            region: Set(None),
+            ingest_regions: Set(Vec::new()),
+
+            xact_resolver_endpoint: Set(None),
+            xact_resolver_timeout: Set(humantime::parse_duration(DEFAULT_XACT_RESOLVER_TIMEOUT)
+                .expect("cannot parse default xact resolver timeout")),
         }
     }
 }
@@ -365,6 +606,26 @@ impl PageServerConfigBuilder {
         self.listen_http_addr = BuilderValue::Set(listen_http_addr)
     }
 
+    pub fn listen_pg_socket(&mut self, listen_pg_socket: Option<PathBuf>) {
+        self.listen_pg_socket = BuilderValue::Set(listen_pg_socket)
+    }
+
+    pub fn listen_grpc_addr(&mut self, listen_grpc_addr: Option<String>) {
+        self.listen_grpc_addr = BuilderValue::Set(listen_grpc_addr)
+    }
+
+    pub fn advertise_pg_addr(&mut self, advertise_pg_addr: Option<String>) {
+        self.advertise_pg_addr = BuilderValue::Set(advertise_pg_addr)
+    }
+
+    pub fn pg_listener(&mut self, pg_listener: ListenerConfig) {
+        self.pg_listener = BuilderValue::Set(pg_listener)
+    }
+
+    pub fn http_listener(&mut self, http_listener: ListenerConfig) {
+        self.http_listener = BuilderValue::Set(http_listener)
+    }
+
     pub fn availability_zone(&mut self, availability_zone: Option<String>) {
         self.availability_zone = BuilderValue::Set(availability_zone)
     }
@@ -377,6 +638,14 @@ impl PageServerConfigBuilder {
         self.wal_redo_timeout = BuilderValue::Set(wal_redo_timeout)
     }
 
+    pub fn wal_redo_max_batch_size(&mut self, wal_redo_max_batch_size: NonZeroUsize) {
+        self.wal_redo_max_batch_size = BuilderValue::Set(wal_redo_max_batch_size)
+    }
+
+    pub fn wal_redo_native(&mut self, wal_redo_native: bool) {
+        self.wal_redo_native = BuilderValue::Set(wal_redo_native)
+    }
+
     pub fn superuser(&mut self, superuser: String) {
         self.superuser = BuilderValue::Set(superuser)
     }
@@ -385,6 +654,18 @@ impl PageServerConfigBuilder {
         self.page_cache_size = BuilderValue::Set(page_cache_size)
     }
 
+    pub fn max_page_cache_size(&mut self, max_page_cache_size: Option<usize>) {
+        self.max_page_cache_size = BuilderValue::Set(max_page_cache_size)
+    }
+
+    pub fn page_cache_memory_budget(&mut self, page_cache_memory_budget: Option<u64>) {
+        self.page_cache_memory_budget = BuilderValue::Set(page_cache_memory_budget)
+    }
+
+    pub fn page_cache_snapshot_max_pages(&mut self, page_cache_snapshot_max_pages: usize) {
+        self.page_cache_snapshot_max_pages = BuilderValue::Set(page_cache_snapshot_max_pages)
+    }
+
     pub fn max_file_descriptors(&mut self, max_file_descriptors: usize) {
         self.max_file_descriptors = BuilderValue::Set(max_file_descriptors)
     }
@@ -432,6 +713,10 @@ impl PageServerConfigBuilder {
         self.log_format = BuilderValue::Set(log_format)
     }
 
+    pub fn log_filter(&mut self, log_filter: Option<String>) {
+        self.log_filter = BuilderValue::Set(log_filter)
+    }
+
     pub fn concurrent_tenant_size_logical_size_queries(&mut self, u: NonZeroUsize) {
         self.concurrent_tenant_size_logical_size_queries = BuilderValue::Set(u);
     }
@@ -480,16 +765,93 @@ impl PageServerConfigBuilder {
         self.background_task_maximum_delay = BuilderValue::Set(delay);
     }
 
+    pub fn shutdown_upload_timeout(&mut self, timeout: Duration) {
+        self.shutdown_upload_timeout = BuilderValue::Set(timeout);
+    }
+
     pub fn ingest_batch_size(&mut self, ingest_batch_size: u64) {
         self.ingest_batch_size = BuilderValue::Set(ingest_batch_size)
     }
 
+    pub fn walreceiver_compression(&mut self, walreceiver_compression: bool) {
+        self.walreceiver_compression = BuilderValue::Set(walreceiver_compression)
+    }
+
+    pub fn ephemeral_file_compression(&mut self, ephemeral_file_compression: bool) {
+        self.ephemeral_file_compression = BuilderValue::Set(ephemeral_file_compression)
+    }
+
+    pub fn verify_layer_writes(&mut self, verify_layer_writes: bool) {
+        self.verify_layer_writes = BuilderValue::Set(verify_layer_writes)
+    }
+
+    pub fn basebackup_cache_ttl(&mut self, basebackup_cache_ttl: Duration) {
+        self.basebackup_cache_ttl = BuilderValue::Set(basebackup_cache_ttl)
+    }
+
+    pub fn attach_download_layers(&mut self, value: Option<AttachDownloadConfig>) {
+        self.attach_download_layers = BuilderValue::Set(value)
+    }
+
+    pub fn get_page_concurrency_limit(&mut self, get_page_concurrency_limit: NonZeroUsize) {
+        self.get_page_concurrency_limit = BuilderValue::Set(get_page_concurrency_limit)
+    }
+
+    pub fn page_readahead_blocks(&mut self, page_readahead_blocks: usize) {
+        self.page_readahead_blocks = BuilderValue::Set(page_readahead_blocks)
+    }
+
+    pub fn shared_page_cache_size(&mut self, shared_page_cache_size: usize) {
+        self.shared_page_cache_size = BuilderValue::Set(shared_page_cache_size)
+    }
+
+    pub fn regions(&mut self, regions: Vec<RegionConfig>) {
+        self.regions = BuilderValue::Set(regions)
+    }
+
+    pub fn region(&mut self, region: Option<String>) {
+        self.region = BuilderValue::Set(region)
+    }
+
+    pub fn ingest_regions(&mut self, ingest_regions: Vec<String>) {
+        self.ingest_regions = BuilderValue::Set(ingest_regions)
+    }
+
+    pub fn background_task_concurrency(
+        &mut self,
+        background_task_concurrency: BackgroundTaskConcurrency,
+    ) {
+        self.background_task_concurrency = BuilderValue::Set(background_task_concurrency)
+    }
+
+    pub fn xact_resolver_endpoint(&mut self, xact_resolver_endpoint: Option<Url>) {
+        self.xact_resolver_endpoint = BuilderValue::Set(xact_resolver_endpoint)
+    }
+
+    pub fn xact_resolver_timeout(&mut self, xact_resolver_timeout: Duration) {
+        self.xact_resolver_timeout = BuilderValue::Set(xact_resolver_timeout)
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
+        let page_cache_size = self
+            .page_cache_size
+            .ok_or(anyhow!("missing page_cache_size"))?;
+        let max_page_cache_size = self
+            .max_page_cache_size
+            .ok_or(anyhow!("missing max_page_cache_size"))?
+            .unwrap_or(page_cache_size);
+        ensure!(
+            max_page_cache_size >= page_cache_size,
+            "max_page_cache_size ({max_page_cache_size}) must not be smaller than page_cache_size ({page_cache_size})"
+        );
+
         let concurrent_tenant_size_logical_size_queries = self
             .concurrent_tenant_size_logical_size_queries
             .ok_or(anyhow!(
                 "missing concurrent_tenant_size_logical_size_queries"
             ))?;
+        let regions = self.regions.ok_or(anyhow!("missing regions"))?;
+        let region_registry = RegionRegistry::new(regions.clone())?;
         Ok(PageServerConf {
             listen_pg_addr: self
                 .listen_pg_addr
@@ -497,6 +859,17 @@ impl PageServerConfigBuilder {
             listen_http_addr: self
                 .listen_http_addr
                 .ok_or(anyhow!("missing listen_http_addr"))?,
+            listen_pg_socket: self
+                .listen_pg_socket
+                .ok_or(anyhow!("missing listen_pg_socket"))?,
+            listen_grpc_addr: self
+                .listen_grpc_addr
+                .ok_or(anyhow!("missing listen_grpc_addr"))?,
+            advertise_pg_addr: self
+                .advertise_pg_addr
+                .ok_or(anyhow!("missing advertise_pg_addr"))?,
+            pg_listener: self.pg_listener.ok_or(anyhow!("missing pg_listener"))?,
+            http_listener: self.http_listener.ok_or(anyhow!("missing http_listener"))?,
             availability_zone: self
                 .availability_zone
                 .ok_or(anyhow!("missing availability_zone"))?,
@@ -506,10 +879,21 @@ impl PageServerConfigBuilder {
             wal_redo_timeout: self
                 .wal_redo_timeout
                 .ok_or(anyhow!("missing wal_redo_timeout"))?,
+            wal_redo_max_batch_size: self
+                .wal_redo_max_batch_size
+                .ok_or(anyhow!("missing wal_redo_max_batch_size"))?,
+            wal_redo_native: self
+                .wal_redo_native
+                .ok_or(anyhow!("missing wal_redo_native"))?,
             superuser: self.superuser.ok_or(anyhow!("missing superuser"))?,
-            page_cache_size: self
-                .page_cache_size
-                .ok_or(anyhow!("missing page_cache_size"))?,
+            page_cache_size,
+            max_page_cache_size,
+            page_cache_memory_budget: self
+                .page_cache_memory_budget
+                .ok_or(anyhow!("missing page_cache_memory_budget"))?,
+            page_cache_snapshot_max_pages: self
+                .page_cache_snapshot_max_pages
+                .ok_or(anyhow!("missing page_cache_snapshot_max_pages"))?,
             max_file_descriptors: self
                 .max_file_descriptors
                 .ok_or(anyhow!("missing max_file_descriptors"))?,
@@ -537,6 +921,7 @@ impl PageServerConfigBuilder {
                 .broker_keepalive_interval
                 .ok_or(anyhow!("No broker keepalive interval provided"))?,
             log_format: self.log_format.ok_or(anyhow!("missing log_format"))?,
+            log_filter: self.log_filter.ok_or(anyhow!("missing log_filter"))?,
             concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::new(
                 concurrent_tenant_size_logical_size_queries,
             ),
@@ -569,9 +954,56 @@ impl PageServerConfigBuilder {
             background_task_maximum_delay: self
                 .background_task_maximum_delay
                 .ok_or(anyhow!("missing background_task_maximum_delay"))?,
+            shutdown_upload_timeout: self
+                .shutdown_upload_timeout
+                .ok_or(anyhow!("missing shutdown_upload_timeout"))?,
             ingest_batch_size: self
                 .ingest_batch_size
                 .ok_or(anyhow!("missing ingest_batch_size"))?,
+            walreceiver_compression: self
+                .walreceiver_compression
+                .ok_or(anyhow!("missing walreceiver_compression"))?,
+            ephemeral_file_compression: self
+                .ephemeral_file_compression
+                .ok_or(anyhow!("missing ephemeral_file_compression"))?,
+            verify_layer_writes: self
+                .verify_layer_writes
+                .ok_or(anyhow!("missing verify_layer_writes"))?,
+            basebackup_cache_ttl: self
+                .basebackup_cache_ttl
+                .ok_or(anyhow!("missing basebackup_cache_ttl"))?,
+            attach_download_layers: self
+                .attach_download_layers
+                .ok_or(anyhow!("missing attach_download_layers"))?,
+            get_page_concurrency_limit: self
+                .get_page_concurrency_limit
+                .ok_or(anyhow!("missing get_page_concurrency_limit"))?,
+            page_readahead_blocks: self
+                .page_readahead_blocks
+                .ok_or(anyhow!("missing page_readahead_blocks"))?,
+            shared_page_cache_size: self
+                .shared_page_cache_size
+                .ok_or(anyhow!("missing shared_page_cache_size"))?,
+            background_task_concurrency: self
+                .background_task_concurrency
+                .ok_or(anyhow!("missing background_task_concurrency"))?,
+            region: match self.region.ok_or(anyhow!("missing region"))? {
+                Some(region) => Some(region_registry.parse(&region)?),
+                None => None,
+            },
+            ingest_regions: self
+                .ingest_regions
+                .ok_or(anyhow!("missing ingest_regions"))?
+                .iter()
+                .map(|region| region_registry.parse(region))
+                .collect::<Result<_, _>>()?,
+            regions,
+            xact_resolver_endpoint: self
+                .xact_resolver_endpoint
+                .ok_or(anyhow!("missing xact_resolver_endpoint"))?,
+            xact_resolver_timeout: self
+                .xact_resolver_timeout
+                .ok_or(anyhow!("missing xact_resolver_timeout"))?,
         })
     }
 }
@@ -582,7 +1014,11 @@ impl PageServerConf {
     //
 
     pub fn tenants_path(&self) -> PathBuf {
-        self.workdir.join("tenants")
+        self.workdir.join(TENANTS_SEGMENT_NAME)
+    }
+
+    pub fn page_cache_snapshot_path(&self) -> PathBuf {
+        self.workdir.join("page_cache.snapshot")
     }
 
     pub fn tenant_path(&self, tenant_id: &TenantId) -> PathBuf {
@@ -604,6 +1040,10 @@ impl PageServerConf {
         self.tenant_path(tenant_id).join(TENANT_CONFIG_NAME)
     }
 
+    pub fn tenant_labels_path(&self, tenant_id: &TenantId) -> PathBuf {
+        self.tenant_path(tenant_id).join(LABELS_FILE_NAME)
+    }
+
     pub fn timelines_path(&self, tenant_id: &TenantId) -> PathBuf {
         self.tenant_path(tenant_id).join(TIMELINES_SEGMENT_NAME)
     }
@@ -662,6 +1102,40 @@ impl PageServerConf {
             .join(METADATA_FILE_NAME)
     }
 
+    /// Points to the log of metadata updates, next to the metadata file.
+    pub fn metadata_log_path(&self, tenant_id: &TenantId, timeline_id: &TimelineId) -> PathBuf {
+        self.timeline_path(tenant_id, timeline_id)
+            .join(METADATA_LOG_FILE_NAME)
+    }
+
+    pub fn timeline_labels_path(&self, tenant_id: &TenantId, timeline_id: &TimelineId) -> PathBuf {
+        self.timeline_path(tenant_id, timeline_id)
+            .join(LABELS_FILE_NAME)
+    }
+
+    pub fn timeline_config_path(&self, tenant_id: &TenantId, timeline_id: &TimelineId) -> PathBuf {
+        self.timeline_path(tenant_id, timeline_id)
+            .join(TIMELINE_CONFIG_FILE_NAME)
+    }
+
+    pub fn timeline_clean_shutdown_marker_path(
+        &self,
+        tenant_id: &TenantId,
+        timeline_id: &TimelineId,
+    ) -> PathBuf {
+        self.timeline_path(tenant_id, timeline_id)
+            .join(CLEAN_SHUTDOWN_MARKER_FILE_NAME)
+    }
+
+    pub fn timeline_relation_data_skipped_path(
+        &self,
+        tenant_id: &TenantId,
+        timeline_id: &TimelineId,
+    ) -> PathBuf {
+        self.timeline_path(tenant_id, timeline_id)
+            .join(RELATION_DATA_SKIPPED_FILE_NAME)
+    }
+
     /// Files on the remote storage are stored with paths, relative to the workdir.
     /// That path includes in itself both tenant and timeline ids, allowing to have a unique remote storage path.
     ///
@@ -725,12 +1199,41 @@ impl PageServerConf {
         for (key, item) in toml.iter() {
             match key {
                 "listen_pg_addr" => builder.listen_pg_addr(parse_toml_string(key, item)?),
+                "listen_pg_socket" => builder
+                    .listen_pg_socket(Some(PathBuf::from(parse_toml_string(key, item)?))),
+                "listen_grpc_addr" => builder.listen_grpc_addr(Some(parse_toml_string(key, item)?)),
+                "advertise_pg_addr" => {
+                    builder.advertise_pg_addr(Some(parse_toml_string(key, item)?))
+                }
                 "listen_http_addr" => builder.listen_http_addr(parse_toml_string(key, item)?),
+                "pg_listener" | "http_listener" => {
+                    let listener: ListenerConfig = deserialize_from_item(key, item)?;
+                    listener.validate().with_context(|| format!("invalid {key}"))?;
+                    if key == "pg_listener" {
+                        builder.pg_listener(listener)
+                    } else {
+                        builder.http_listener(listener)
+                    }
+                }
                 "availability_zone" => builder.availability_zone(Some(parse_toml_string(key, item)?)),
                 "wait_lsn_timeout" => builder.wait_lsn_timeout(parse_toml_duration(key, item)?),
                 "wal_redo_timeout" => builder.wal_redo_timeout(parse_toml_duration(key, item)?),
+                "wal_redo_max_batch_size" => builder.wal_redo_max_batch_size({
+                    let size = parse_toml_u64(key, item)?;
+                    NonZeroUsize::new(size as usize).context("wal_redo_max_batch_size must be positive")?
+                }),
+                "wal_redo_native" => builder.wal_redo_native(parse_toml_bool(key, item)?),
                 "initial_superuser_name" => builder.superuser(parse_toml_string(key, item)?),
                 "page_cache_size" => builder.page_cache_size(parse_toml_u64(key, item)? as usize),
+                "max_page_cache_size" => {
+                    builder.max_page_cache_size(Some(parse_toml_u64(key, item)? as usize))
+                }
+                "page_cache_memory_budget" => {
+                    builder.page_cache_memory_budget(Some(parse_toml_u64(key, item)?))
+                }
+                "page_cache_snapshot_max_pages" => {
+                    builder.page_cache_snapshot_max_pages(parse_toml_u64(key, item)? as usize)
+                }
                 "max_file_descriptors" => {
                     builder.max_file_descriptors(parse_toml_u64(key, item)? as usize)
                 }
@@ -754,6 +1257,11 @@ impl PageServerConf {
                 "log_format" => builder.log_format(
                     LogFormat::from_config(&parse_toml_string(key, item)?)?
                 ),
+                "log_filter" => builder.log_filter(Some({
+                    let directives = parse_toml_string(key, item)?;
+                    utils::logging::check_log_filter(&directives)?;
+                    directives
+                })),
                 "concurrent_tenant_size_logical_size_queries" => builder.concurrent_tenant_size_logical_size_queries({
                     let input = parse_toml_string(key, item)?;
                     let permits = input.parse::<usize>().context("expected a number of initial permits, not {s:?}")?;
@@ -777,7 +1285,32 @@ impl PageServerConf {
                 },
                 "ondemand_download_behavior_treat_error_as_warn" => builder.ondemand_download_behavior_treat_error_as_warn(parse_toml_bool(key, item)?),
                 "background_task_maximum_delay" => builder.background_task_maximum_delay(parse_toml_duration(key, item)?),
+                "shutdown_upload_timeout" => builder.shutdown_upload_timeout(parse_toml_duration(key, item)?),
                 "ingest_batch_size" => builder.ingest_batch_size(parse_toml_u64(key, item)?),
+                "walreceiver_compression" => builder.walreceiver_compression(parse_toml_bool(key, item)?),
+                "ephemeral_file_compression" => builder.ephemeral_file_compression(parse_toml_bool(key, item)?),
+                "verify_layer_writes" => builder.verify_layer_writes(parse_toml_bool(key, item)?),
+                "basebackup_cache_ttl" => builder.basebackup_cache_ttl(parse_toml_duration(key, item)?),
+                "attach_download_layers" => builder.attach_download_layers(Some(deserialize_from_item(key, item)?)),
+                "get_page_concurrency_limit" => builder.get_page_concurrency_limit({
+                    let limit = parse_toml_u64(key, item)?;
+                    NonZeroUsize::new(limit as usize).context("get_page_concurrency_limit must be positive")?
+                }),
+                "page_readahead_blocks" => builder.page_readahead_blocks(parse_toml_u64(key, item)? as usize),
+                "shared_page_cache_size" => builder.shared_page_cache_size(parse_toml_u64(key, item)? as usize),
+                "background_task_concurrency" => builder.background_task_concurrency(deserialize_from_item(key, item)?),
+                "regions" => {
+                    let regions: Vec<RegionConfig> = deserialize_from_item(key, item)?;
+                    RegionRegistry::new(regions.clone()).context("invalid regions")?;
+                    builder.regions(regions)
+                }
+                "region" => builder.region(Some(parse_toml_string(key, item)?)),
+                "ingest_regions" => builder.ingest_regions(deserialize_from_item(key, item)?),
+                "xact_resolver_endpoint" => {
+                    let endpoint = parse_toml_string(key, item)?.parse().context("failed to parse xact_resolver_endpoint")?;
+                    builder.xact_resolver_endpoint(Some(endpoint));
+                }
+                "xact_resolver_timeout" => builder.xact_resolver_timeout(parse_toml_duration(key, item)?),
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -841,6 +1374,16 @@ impl PageServerConf {
             );
         }
 
+        if let Some(slru_image_creation_threshold) = item.get("slru_image_creation_threshold") {
+            t_conf.slru_image_creation_threshold = Some(
+                parse_toml_u64(
+                    "slru_image_creation_threshold",
+                    slru_image_creation_threshold,
+                )?
+                .try_into()?,
+            );
+        }
+
         if let Some(gc_horizon) = item.get("gc_horizon") {
             t_conf.gc_horizon = Some(parse_toml_u64("gc_horizon", gc_horizon)?);
         }
@@ -904,6 +1447,34 @@ impl PageServerConf {
             );
         }
 
+        if let Some(item) = item.get("layer_compression_level") {
+            let level = deserialize_from_item("layer_compression_level", item)
+                .context("parse layer_compression_level")?;
+            t_conf.layer_compression_level = Some(validate_layer_compression_level(level)?);
+        }
+
+        if let Some(item) = item.get("get_page_weight") {
+            t_conf.get_page_weight = Some(
+                deserialize_from_item("get_page_weight", item).context("parse get_page_weight")?,
+            );
+        }
+
+        if let Some(item) = item.get("logical_size_reconcile_period") {
+            t_conf.logical_size_reconcile_period =
+                Some(parse_toml_duration("logical_size_reconcile_period", item)?);
+        }
+
+        if let Some(item) = item.get("pagestream_rate_limit") {
+            t_conf.pagestream_rate_limit = Some(
+                deserialize_from_item("pagestream_rate_limit", item)
+                    .context("parse pagestream_rate_limit")?,
+            );
+        }
+
+        if let Some(item) = item.get("wait_lsn_timeout") {
+            t_conf.wait_lsn_timeout = Some(parse_toml_duration("wait_lsn_timeout", item)?);
+        }
+
         Ok(t_conf)
     }
 
@@ -919,10 +1490,21 @@ impl PageServerConf {
             id: NodeId(0),
             wait_lsn_timeout: Duration::from_secs(60),
             wal_redo_timeout: Duration::from_secs(60),
+            wal_redo_max_batch_size: NonZeroUsize::new(defaults::DEFAULT_WAL_REDO_MAX_BATCH_SIZE)
+                .unwrap(),
+            wal_redo_native: defaults::DEFAULT_WAL_REDO_NATIVE,
             page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+            max_page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+            page_cache_memory_budget: None,
+            page_cache_snapshot_max_pages: defaults::DEFAULT_PAGE_CACHE_SNAPSHOT_MAX_PAGES,
             max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
+            listen_pg_socket: None,
+            listen_grpc_addr: None,
+            advertise_pg_addr: None,
+            pg_listener: ListenerConfig::default(),
+            http_listener: ListenerConfig::default(),
             availability_zone: None,
             superuser: "cloud_admin".to_string(),
             workdir: repo_dir,
@@ -935,6 +1517,7 @@ impl PageServerConf {
             broker_endpoint: storage_broker::DEFAULT_ENDPOINT.parse().unwrap(),
             broker_keepalive_interval: Duration::from_secs(5000),
             log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+            log_filter: None,
             concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
             eviction_task_immitated_concurrent_logical_size_queries: ConfigurableSemaphore::default(
             ),
@@ -946,7 +1529,29 @@ impl PageServerConf {
             test_remote_failures: 0,
             ondemand_download_behavior_treat_error_as_warn: false,
             background_task_maximum_delay: Duration::ZERO,
+            shutdown_upload_timeout: humantime::parse_duration(
+                defaults::DEFAULT_SHUTDOWN_UPLOAD_TIMEOUT,
+            )
+            .unwrap(),
             ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
+            walreceiver_compression: false,
+            ephemeral_file_compression: false,
+            verify_layer_writes: false,
+            basebackup_cache_ttl: humantime::parse_duration(defaults::DEFAULT_BASEBACKUP_CACHE_TTL)
+                .unwrap(),
+            attach_download_layers: None,
+            get_page_concurrency_limit: NonZeroUsize::new(
+                defaults::DEFAULT_GET_PAGE_CONCURRENCY_LIMIT,
+            )
+            .unwrap(),
+            page_readahead_blocks: defaults::DEFAULT_PAGE_READAHEAD_BLOCKS,
+            shared_page_cache_size: defaults::DEFAULT_SHARED_PAGE_CACHE_SIZE,
+            background_task_concurrency: BackgroundTaskConcurrency::default(),
+            regions: Vec::new(),
+            region: None,
+            ingest_regions: Vec::new(),
+            xact_resolver_endpoint: None,
+            xact_resolver_timeout: Duration::from_secs(1),
         }
     }
 }
@@ -1021,7 +1626,8 @@ where
 #[derive(Debug, Clone)]
 pub struct ConfigurableSemaphore {
     initial_permits: NonZeroUsize,
-    inner: std::sync::Arc<tokio::sync::Semaphore>,
+    /// Currently configured permits, differs from `initial_permits` after [`Self::set_permits`].
+    inner: ResizableSemaphore,
 }
 
 impl ConfigurableSemaphore {
@@ -1040,14 +1646,27 @@ impl ConfigurableSemaphore {
     pub fn new(initial_permits: NonZeroUsize) -> Self {
         ConfigurableSemaphore {
             initial_permits,
-            inner: std::sync::Arc::new(tokio::sync::Semaphore::new(initial_permits.get())),
+            inner: ResizableSemaphore::new(initial_permits),
         }
     }
 
-    /// Returns the configured amount of permits.
+    /// Returns the amount of permits configured at startup.
     pub fn initial_permits(&self) -> NonZeroUsize {
         self.initial_permits
     }
+
+    /// Returns the currently configured amount of permits.
+    pub fn permits(&self) -> NonZeroUsize {
+        self.inner.limit()
+    }
+
+    /// Changes the amount of permits at runtime.
+    ///
+    /// Removed permits are taken away as they are released by their current holders, so
+    /// it can take a while for a decrease to take effect.
+    pub fn set_permits(&self, permits: NonZeroUsize) {
+        self.inner.set_limit(permits);
+    }
 }
 
 impl Default for ConfigurableSemaphore {
@@ -1058,9 +1677,9 @@ impl Default for ConfigurableSemaphore {
 
 impl PartialEq for ConfigurableSemaphore {
     fn eq(&self, other: &Self) -> bool {
-        // the number of permits can be increased at runtime, so we cannot really fulfill the
-        // PartialEq value equality otherwise
-        self.initial_permits == other.initial_permits
+        // the semaphore's available permits change all the time, so we cannot really fulfill
+        // the PartialEq value equality otherwise
+        self.permits() == other.permits()
     }
 }
 
@@ -1068,19 +1687,21 @@ impl Eq for ConfigurableSemaphore {}
 
 impl ConfigurableSemaphore {
     pub fn inner(&self) -> &std::sync::Arc<tokio::sync::Semaphore> {
-        &self.inner
+        self.inner.semaphore()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::BTreeMap,
         fs,
         num::{NonZeroU32, NonZeroUsize},
     };
 
     use remote_storage::{RemoteStorageKind, S3Config};
     use tempfile::{tempdir, TempDir};
+    use utils::id::RegionId;
     use utils::serde_percent::Percent;
 
     use super::*;
@@ -1091,11 +1712,17 @@ mod tests {
 
 listen_pg_addr = '127.0.0.1:64000'
 listen_http_addr = '127.0.0.1:9898'
+http_listener = { max_connections = 10 }
 
 wait_lsn_timeout = '111 s'
 wal_redo_timeout = '111 s'
+wal_redo_max_batch_size = 4
+wal_redo_native = true
 
 page_cache_size = 444
+max_page_cache_size = 555
+page_cache_memory_budget = 1000000
+page_cache_snapshot_max_pages = 222
 max_file_descriptors = 333
 
 # initial superuser role name to use when creating a new tenant
@@ -1109,6 +1736,20 @@ synthetic_size_calculation_interval = '333 s'
 
 log_format = 'json'
 background_task_maximum_delay = '334 s'
+shutdown_upload_timeout = '45 s'
+ephemeral_file_compression = true
+verify_layer_writes = true
+basebackup_cache_ttl = '7 min'
+attach_download_layers = { max_concurrent_downloads = 2 }
+get_page_concurrency_limit = 32
+page_readahead_blocks = 64
+shared_page_cache_size = 1024
+background_task_concurrency = { compaction = 2 }
+regions = [{ id = 1, name = 'us-east' }]
+region = 'us-east'
+ingest_regions = ['us-east']
+xact_resolver_endpoint = 'http://localhost:8080/v1/xact_outcomes'
+xact_resolver_timeout = '500 ms'
 
 "#;
 
@@ -1133,11 +1774,24 @@ background_task_maximum_delay = '334 s'
                 id: NodeId(10),
                 listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
                 listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
+                listen_pg_socket: None,
+                listen_grpc_addr: None,
+                advertise_pg_addr: None,
+                pg_listener: ListenerConfig::default(),
+                http_listener: ListenerConfig::default(),
                 availability_zone: None,
                 wait_lsn_timeout: humantime::parse_duration(defaults::DEFAULT_WAIT_LSN_TIMEOUT)?,
                 wal_redo_timeout: humantime::parse_duration(defaults::DEFAULT_WAL_REDO_TIMEOUT)?,
+                wal_redo_max_batch_size: NonZeroUsize::new(
+                    defaults::DEFAULT_WAL_REDO_MAX_BATCH_SIZE
+                )
+                .unwrap(),
+                wal_redo_native: defaults::DEFAULT_WAL_REDO_NATIVE,
                 superuser: defaults::DEFAULT_SUPERUSER.to_string(),
                 page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+                max_page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+                page_cache_memory_budget: None,
+                page_cache_snapshot_max_pages: defaults::DEFAULT_PAGE_CACHE_SNAPSHOT_MAX_PAGES,
                 max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
                 workdir,
                 pg_distrib_dir,
@@ -1151,6 +1805,7 @@ background_task_maximum_delay = '334 s'
                     storage_broker::DEFAULT_KEEPALIVE_INTERVAL
                 )?,
                 log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+                log_filter: None,
                 concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
                 eviction_task_immitated_concurrent_logical_size_queries:
                     ConfigurableSemaphore::default(),
@@ -1170,7 +1825,31 @@ background_task_maximum_delay = '334 s'
                 background_task_maximum_delay: humantime::parse_duration(
                     defaults::DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY
                 )?,
+                shutdown_upload_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_SHUTDOWN_UPLOAD_TIMEOUT
+                )?,
                 ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
+                walreceiver_compression: false,
+                ephemeral_file_compression: false,
+                verify_layer_writes: false,
+                basebackup_cache_ttl: humantime::parse_duration(
+                    defaults::DEFAULT_BASEBACKUP_CACHE_TTL
+                )?,
+                attach_download_layers: None,
+                get_page_concurrency_limit: NonZeroUsize::new(
+                    defaults::DEFAULT_GET_PAGE_CONCURRENCY_LIMIT
+                )
+                .unwrap(),
+                page_readahead_blocks: defaults::DEFAULT_PAGE_READAHEAD_BLOCKS,
+                shared_page_cache_size: defaults::DEFAULT_SHARED_PAGE_CACHE_SIZE,
+                background_task_concurrency: BackgroundTaskConcurrency::default(),
+                regions: Vec::new(),
+                region: None,
+                ingest_regions: Vec::new(),
+                xact_resolver_endpoint: None,
+                xact_resolver_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_XACT_RESOLVER_TIMEOUT
+                )?,
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -1199,11 +1878,24 @@ background_task_maximum_delay = '334 s'
                 id: NodeId(10),
                 listen_pg_addr: "127.0.0.1:64000".to_string(),
                 listen_http_addr: "127.0.0.1:9898".to_string(),
+                listen_pg_socket: None,
+                listen_grpc_addr: None,
+                advertise_pg_addr: None,
+                pg_listener: ListenerConfig::default(),
+                http_listener: ListenerConfig {
+                    max_connections: Some(10),
+                    ..Default::default()
+                },
                 availability_zone: None,
                 wait_lsn_timeout: Duration::from_secs(111),
                 wal_redo_timeout: Duration::from_secs(111),
+                wal_redo_max_batch_size: NonZeroUsize::new(4).unwrap(),
+                wal_redo_native: true,
                 superuser: "zzzz".to_string(),
                 page_cache_size: 444,
+                max_page_cache_size: 555,
+                page_cache_memory_budget: Some(1000000),
+                page_cache_snapshot_max_pages: 222,
                 max_file_descriptors: 333,
                 workdir,
                 pg_distrib_dir,
@@ -1215,6 +1907,7 @@ background_task_maximum_delay = '334 s'
                 broker_endpoint: storage_broker::DEFAULT_ENDPOINT.parse().unwrap(),
                 broker_keepalive_interval: Duration::from_secs(5),
                 log_format: LogFormat::Json,
+                log_filter: None,
                 concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
                 eviction_task_immitated_concurrent_logical_size_queries:
                     ConfigurableSemaphore::default(),
@@ -1226,7 +1919,32 @@ background_task_maximum_delay = '334 s'
                 test_remote_failures: 0,
                 ondemand_download_behavior_treat_error_as_warn: false,
                 background_task_maximum_delay: Duration::from_secs(334),
+                shutdown_upload_timeout: Duration::from_secs(45),
                 ingest_batch_size: 100,
+                walreceiver_compression: false,
+                ephemeral_file_compression: true,
+                verify_layer_writes: true,
+                basebackup_cache_ttl: Duration::from_secs(7 * 60),
+                attach_download_layers: Some(AttachDownloadConfig {
+                    max_concurrent_downloads: NonZeroUsize::new(2).unwrap(),
+                    max_bytes_per_second: None,
+                }),
+                get_page_concurrency_limit: NonZeroUsize::new(32).unwrap(),
+                page_readahead_blocks: 64,
+                shared_page_cache_size: 1024,
+                background_task_concurrency: BackgroundTaskConcurrency {
+                    compaction: NonZeroUsize::new(2).unwrap(),
+                    ..Default::default()
+                },
+                regions: vec![RegionConfig {
+                    id: RegionId(1),
+                    name: "us-east".to_string(),
+                    metadata: BTreeMap::new(),
+                }],
+                region: Some(RegionId(1)),
+                ingest_regions: vec![RegionId(1)],
+                xact_resolver_endpoint: Some(Url::parse("http://localhost:8080/v1/xact_outcomes")?),
+                xact_resolver_timeout: Duration::from_millis(500),
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -1351,6 +2069,7 @@ broker_endpoint = '{broker_endpoint}'
                         prefix_in_bucket: Some(prefix_in_bucket.clone()),
                         endpoint: Some(endpoint.clone()),
                         concurrency_limit: s3_concurrency_limit,
+                        max_requests_per_second: None,
                         max_keys_per_list_response: None,
                     }),
                 },
@@ -0,0 +1,153 @@
+//! Settings of the page service and management API listeners, other than their addresses.
+//!
+//! ```toml
+//! listen_pg_addr = '0.0.0.0:64000'
+//! pg_listener = { max_connections = 1000, tls_cert_file = 'server.crt', tls_key_file = 'server.key' }
+//!
+//! listen_http_addr = '127.0.0.1:9898'
+//! http_listener = { max_connections = 100 }
+//! ```
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Connections beyond this many are refused. Unlimited if not set.
+    pub max_connections: Option<usize>,
+    /// PEM file with the certificate chain. If set, the clients must connect with TLS.
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM file with the PKCS#8 private key of the certificate.
+    pub tls_key_file: Option<PathBuf>,
+}
+
+impl ListenerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.max_connections != Some(0),
+            "max_connections must be positive"
+        );
+        ensure!(
+            self.tls_cert_file.is_some() == self.tls_key_file.is_some(),
+            "tls_cert_file and tls_key_file must be set together"
+        );
+        Ok(())
+    }
+
+    /// Reads the certificate and the key, if TLS is configured.
+    pub fn load_tls_config(&self) -> anyhow::Result<Option<Arc<rustls::ServerConfig>>> {
+        let (cert_path, key_path) = match (&self.tls_cert_file, &self.tls_key_file) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => bail!("tls_cert_file and tls_key_file must be set together"),
+        };
+
+        let key = {
+            let key_bytes = std::fs::read(key_path)
+                .with_context(|| format!("read TLS key file {}", key_path.display()))?;
+            let mut keys = rustls_pemfile::pkcs8_private_keys(&mut &key_bytes[..])
+                .with_context(|| format!("parse TLS keys in {}", key_path.display()))?;
+            ensure!(
+                keys.len() == 1,
+                "expected one key in {}, found {}",
+                key_path.display(),
+                keys.len()
+            );
+            rustls::PrivateKey(keys.pop().unwrap())
+        };
+        let cert_chain = {
+            let cert_bytes = std::fs::read(cert_path)
+                .with_context(|| format!("read TLS certificate file {}", cert_path.display()))?;
+            rustls_pemfile::certs(&mut &cert_bytes[..])
+                .with_context(|| format!("parse TLS certificates in {}", cert_path.display()))?
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect()
+        };
+
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)
+            .context("invalid TLS certificate or key")?;
+        Ok(Some(Arc::new(config)))
+    }
+
+    pub fn connection_limit(&self) -> ConnectionLimit {
+        ConnectionLimit {
+            semaphore: self
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+}
+
+/// Counts the open connections of a listener against `max_connections`.
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+/// Held for as long as the connection is open.
+pub struct ConnectionSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimit {
+    /// Returns `None` if all the connection slots are taken.
+    pub fn try_acquire(&self) -> Option<ConnectionSlot> {
+        match &self.semaphore {
+            Some(semaphore) => Arc::clone(semaphore)
+                .try_acquire_owned()
+                .ok()
+                .map(|permit| ConnectionSlot {
+                    _permit: Some(permit),
+                }),
+            None => Some(ConnectionSlot { _permit: None }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_limit() {
+        let limited = ListenerConfig {
+            max_connections: Some(2),
+            ..Default::default()
+        }
+        .connection_limit();
+        let first = limited.try_acquire().expect("below the limit");
+        let _second = limited.try_acquire().expect("below the limit");
+        assert!(limited.try_acquire().is_none());
+        drop(first);
+        assert!(limited.try_acquire().is_some());
+
+        let unlimited = ListenerConfig::default().connection_limit();
+        let _slots = (0..100)
+            .map(|_| unlimited.try_acquire().expect("no limit"))
+            .collect::<Vec<_>>();
+    }
+
+    #[test]
+    fn validation() {
+        assert!(ListenerConfig::default().validate().is_ok());
+        let cert_only = ListenerConfig {
+            tls_cert_file: Some(PathBuf::from("server.crt")),
+            ..Default::default()
+        };
+        assert!(cert_only.validate().is_err());
+        let no_connections = ListenerConfig {
+            max_connections: Some(0),
+            ..Default::default()
+        };
+        assert!(no_connections.validate().is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! Applying changes of the pageserver config file at runtime, see `POST /v1/reload_config`.
+//!
+//! Only some settings can be changed without a restart:
+//! - `log_filter`
+//! - `concurrent_tenant_size_logical_size_queries`, the limit of the background
+//!   logical size calculations
+//! - `concurrency_limit` of S3 remote storage
+//! - `get_page_concurrency_limit`
+//! - `background_task_concurrency`
+//!
+//! Changes to any other setting are reported back as requiring a restart.
+//!
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use pageserver_api::models::{ConfigChange, ReloadConfigResponse};
+use remote_storage::{GenericRemoteStorage, RemoteStorageKind};
+use toml_edit::Document;
+use tracing::info;
+
+use super::PageServerConf;
+use crate::page_service;
+use crate::tenant::tasks::scheduler;
+
+pub struct ConfigReloader {
+    cfg_file_path: PathBuf,
+    /// The `-c` overrides given on the command line, applied on top of the file.
+    overrides: Vec<String>,
+    /// The config the pageserver runs with: the one from startup, plus the changes applied since.
+    current: Mutex<PageServerConf>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        cfg_file_path: &Path,
+        overrides: Vec<String>,
+        conf: &'static PageServerConf,
+    ) -> Self {
+        ConfigReloader {
+            cfg_file_path: cfg_file_path.to_owned(),
+            overrides,
+            current: Mutex::new(conf.clone()),
+        }
+    }
+
+    /// Re-reads the config file, and applies the changes that can be applied at runtime.
+    pub fn reload(
+        &self,
+        remote_storage: Option<&GenericRemoteStorage>,
+    ) -> anyhow::Result<ReloadConfigResponse> {
+        let mut current = self.current.lock().unwrap();
+        let new = self.read_config(&current.workdir)?;
+
+        let mut response = ReloadConfigResponse::default();
+        for change in diff(&current, &new) {
+            let applied = match change.name.as_str() {
+                "log_filter" => {
+                    utils::logging::reload_log_filter(new.log_filter.as_deref())?;
+                    current.log_filter = new.log_filter.clone();
+                    true
+                }
+                "concurrent_tenant_size_logical_size_queries" => {
+                    let permits = new.concurrent_tenant_size_logical_size_queries.permits();
+                    // The eviction task's semaphore imitates this one, see its comment
+                    current
+                        .concurrent_tenant_size_logical_size_queries
+                        .set_permits(permits);
+                    current
+                        .eviction_task_immitated_concurrent_logical_size_queries
+                        .set_permits(permits);
+                    true
+                }
+                "get_page_concurrency_limit" => {
+                    page_service::fair_limiter::get().set_limit(new.get_page_concurrency_limit);
+                    current.get_page_concurrency_limit = new.get_page_concurrency_limit;
+                    true
+                }
+                "background_task_concurrency" => {
+                    scheduler::get().set_concurrency(new.background_task_concurrency);
+                    current.background_task_concurrency = new.background_task_concurrency;
+                    true
+                }
+                "remote_storage" => {
+                    match (remote_storage, concurrency_limit_change(&current, &new)) {
+                        (Some(storage), Some(limit)) => {
+                            let applied = storage.set_concurrency_limit(limit);
+                            if applied {
+                                current.remote_storage_config = new.remote_storage_config.clone();
+                            }
+                            applied
+                        }
+                        _ => false,
+                    }
+                }
+                // The WAL ingestion of each timeline reads it once, when it starts
+                "ingest_regions" => false,
+                _ => false,
+            };
+            info!(
+                setting = change.name,
+                old = change.old,
+                new = change.new,
+                applied,
+                "config setting changed"
+            );
+            if applied {
+                response.applied.push(change);
+            } else {
+                response.restart_required.push(change);
+            }
+        }
+        Ok(response)
+    }
+
+    fn read_config(&self, workdir: &Path) -> anyhow::Result<PageServerConf> {
+        let contents = std::fs::read_to_string(&self.cfg_file_path).with_context(|| {
+            format!(
+                "Failed to read pageserver config at '{}'",
+                self.cfg_file_path.display()
+            )
+        })?;
+        let mut toml = contents.parse::<Document>().with_context(|| {
+            format!(
+                "Failed to parse '{}' as pageserver config",
+                self.cfg_file_path.display()
+            )
+        })?;
+        for option_line in &self.overrides {
+            let doc = Document::from_str(option_line).with_context(|| {
+                format!("Option '{option_line}' could not be parsed as a toml document")
+            })?;
+            for (key, item) in doc.iter() {
+                toml.insert(key, item.clone());
+            }
+        }
+        PageServerConf::parse_and_validate(&toml, workdir)
+            .context("Failed to parse pageserver configuration")
+    }
+}
+
+/// If the remote storage configs only differ in the S3 concurrency limit, returns the new limit.
+fn concurrency_limit_change(
+    current: &PageServerConf,
+    new: &PageServerConf,
+) -> Option<NonZeroUsize> {
+    let (current, new) = match (&current.remote_storage_config, &new.remote_storage_config) {
+        (Some(current), Some(new)) => (current, new),
+        _ => return None,
+    };
+    match (&current.storage, &new.storage) {
+        (RemoteStorageKind::AwsS3(current_s3), RemoteStorageKind::AwsS3(new_s3)) => {
+            let mut updated = current.clone();
+            if let RemoteStorageKind::AwsS3(s3) = &mut updated.storage {
+                s3.concurrency_limit = new_s3.concurrency_limit;
+            }
+            (updated == *new && current_s3.concurrency_limit != new_s3.concurrency_limit)
+                .then_some(new_s3.concurrency_limit)
+        }
+        _ => None,
+    }
+}
+
+/// Lists the settings that differ between two configs.
+fn diff(old: &PageServerConf, new: &PageServerConf) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    macro_rules! diff_fields {
+        ($($field:ident),* $(,)?) => {
+            // Fails to compile when a field is added to PageServerConf, but not here
+            let PageServerConf { $($field: _),* } = new;
+            $(
+                if old.$field != new.$field {
+                    changes.push(ConfigChange {
+                        name: stringify!($field).to_string(),
+                        old: format!("{:?}", old.$field),
+                        new: format!("{:?}", new.$field),
+                    });
+                }
+            )*
+        };
+    }
+    diff_fields!(
+        id,
+        listen_pg_addr,
+        listen_http_addr,
+        listen_pg_socket,
+        listen_grpc_addr,
+        advertise_pg_addr,
+        pg_listener,
+        http_listener,
+        availability_zone,
+        wait_lsn_timeout,
+        wal_redo_timeout,
+        wal_redo_max_batch_size,
+        wal_redo_native,
+        superuser,
+        page_cache_size,
+        max_page_cache_size,
+        page_cache_memory_budget,
+        page_cache_snapshot_max_pages,
+        max_file_descriptors,
+        workdir,
+        pg_distrib_dir,
+        http_auth_type,
+        pg_auth_type,
+        auth_validation_public_key_path,
+        remote_storage_config,
+        default_tenant_conf,
+        broker_endpoint,
+        broker_keepalive_interval,
+        log_format,
+        log_filter,
+        concurrent_tenant_size_logical_size_queries,
+        eviction_task_immitated_concurrent_logical_size_queries,
+        metric_collection_interval,
+        cached_metric_collection_interval,
+        metric_collection_endpoint,
+        synthetic_size_calculation_interval,
+        disk_usage_based_eviction,
+        test_remote_failures,
+        ondemand_download_behavior_treat_error_as_warn,
+        background_task_maximum_delay,
+        shutdown_upload_timeout,
+        ingest_batch_size,
+        walreceiver_compression,
+        ephemeral_file_compression,
+        verify_layer_writes,
+        basebackup_cache_ttl,
+        attach_download_layers,
+        get_page_concurrency_limit,
+        page_readahead_blocks,
+        shared_page_cache_size,
+        background_task_concurrency,
+        regions,
+        region,
+        ingest_regions,
+        xact_resolver_endpoint,
+        xact_resolver_timeout,
+    );
+    // The eviction task's limit follows concurrent_tenant_size_logical_size_queries
+    changes.retain(|c| c.name != "eviction_task_immitated_concurrent_logical_size_queries");
+    // Report the remote storage change under its own name
+    for change in &mut changes {
+        if change.name == "remote_storage_config" {
+            change.name = "remote_storage".to_string();
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_storage::{RemoteStorageConfig, S3Config};
+
+    fn s3_conf(concurrency_limit: usize, bucket_name: &str) -> PageServerConf {
+        let mut conf = PageServerConf::dummy_conf(PageServerConf::test_repo_dir("reload_diff"));
+        conf.remote_storage_config = Some(RemoteStorageConfig {
+            max_concurrent_syncs: NonZeroUsize::new(10).unwrap(),
+            max_sync_errors: std::num::NonZeroU32::new(10).unwrap(),
+            storage: RemoteStorageKind::AwsS3(S3Config {
+                bucket_name: bucket_name.to_string(),
+                bucket_region: "eu-north-1".to_string(),
+                prefix_in_bucket: None,
+                endpoint: None,
+                concurrency_limit: NonZeroUsize::new(concurrency_limit).unwrap(),
+                max_requests_per_second: None,
+                max_keys_per_list_response: None,
+            }),
+        });
+        conf
+    }
+
+    #[test]
+    fn config_diff() {
+        let old = s3_conf(100, "bucket");
+        assert!(diff(&old, &old.clone()).is_empty());
+
+        let mut new = s3_conf(50, "bucket");
+        new.log_filter = Some("debug".to_string());
+        new.wait_lsn_timeout = old.wait_lsn_timeout * 2;
+        let names: Vec<_> = diff(&old, &new).into_iter().map(|c| c.name).collect();
+        assert_eq!(names, ["wait_lsn_timeout", "remote_storage", "log_filter"]);
+        assert_eq!(concurrency_limit_change(&old, &new), NonZeroUsize::new(50));
+
+        // Other remote storage changes need a restart
+        let new = s3_conf(50, "other_bucket");
+        assert_eq!(concurrency_limit_change(&old, &new), None);
+    }
+}
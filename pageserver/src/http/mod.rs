@@ -1,4 +1,5 @@
 pub mod routes;
+pub mod server;
 pub use routes::make_router;
 
 pub use pageserver_api::models;
@@ -2,16 +2,23 @@
 //! Management HTTP API
 //!
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
+use futures::{StreamExt, TryStreamExt};
 use hyper::StatusCode;
 use hyper::{Body, Request, Response, Uri};
 use metrics::launch_timestamp::LaunchTimestamp;
-use pageserver_api::models::{DownloadRemoteLayersTaskSpawnRequest, TenantAttachRequest};
-use remote_storage::GenericRemoteStorage;
+use pageserver_api::models::{
+    ActivatingFrom, DownloadRemoteLayersTaskSpawnRequest, LabelSelector, Labels, ReadinessResponse,
+    RegionTimeline, TenantAttachRequest, TenantState, TopTenantsBy, WaitEdgesReport,
+};
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
 use storage_broker::BrokerClientChannel;
 use tenant_size_model::{SizeResult, StorageModel};
+use tokio_util::io::{ReaderStream, StreamReader};
 use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::http::endpoint::request_span;
@@ -19,22 +26,27 @@ use utils::http::json::json_request_or_empty_body;
 use utils::http::request::{get_request_param, must_get_query_param, parse_query_param};
 
 use super::models::{
-    StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
-    TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
+    PageCacheInfo, PageCacheResizeRequest, PageCacheTenantUsage, StatusResponse,
+    TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
+    TimelineConfigRequest, TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
 };
+use crate::config::{reload::ConfigReloader, PageServerConf};
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::metrics::{StorageTimeOperation, STORAGE_TIME_GLOBAL};
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::task_mgr::TaskKind;
-use crate::tenant::config::TenantConfOpt;
+use crate::tenant::config::{TenantConfOpt, TimelineConfOpt};
+use crate::tenant::labels;
+use crate::tenant::mgr;
 use crate::tenant::mgr::{
     GetTenantError, SetNewTenantConfigError, TenantMapInsertError, TenantStateError,
 };
 use crate::tenant::size::ModelInputs;
 use crate::tenant::storage_layer::LayerAccessStatsReset;
-use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError, Timeline};
-use crate::{config::PageServerConf, tenant::mgr};
-use crate::{disk_usage_eviction_task, tenant};
+use crate::tenant::tasks::scheduler::{self, TaskClass};
+use crate::tenant::timeline::diff::DiffError;
+use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError, Tenant, Timeline};
+use crate::{disk_usage_eviction_task, page_cache, tenant, walredo};
 use utils::{
     auth::JwtAuth,
     http::{
@@ -44,7 +56,7 @@ use utils::{
         request::parse_request_param,
         RequestExt, RouterBuilder,
     },
-    id::{TenantId, TimelineId},
+    id::{RegionId, TenantId, TimelineId},
     lsn::Lsn,
 };
 
@@ -58,6 +70,9 @@ struct State {
     remote_storage: Option<GenericRemoteStorage>,
     broker_client: storage_broker::BrokerClientChannel,
     disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
+    /// Set once the readiness probe has seen a WAL redo process start.
+    wal_redo_works: AtomicBool,
+    config_reloader: ConfigReloader,
 }
 
 impl State {
@@ -67,11 +82,18 @@ impl State {
         remote_storage: Option<GenericRemoteStorage>,
         broker_client: storage_broker::BrokerClientChannel,
         disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
+        config_reloader: ConfigReloader,
     ) -> anyhow::Result<Self> {
-        let allowlist_routes = ["/v1/status", "/v1/doc", "/swagger.yml"]
-            .iter()
-            .map(|v| v.parse().unwrap())
-            .collect::<Vec<_>>();
+        let allowlist_routes = [
+            "/v1/status",
+            "/v1/status/live",
+            "/v1/status/ready",
+            "/v1/doc",
+            "/swagger.yml",
+        ]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect::<Vec<_>>();
         Ok(Self {
             conf,
             auth,
@@ -79,6 +101,8 @@ impl State {
             remote_storage,
             broker_client,
             disk_usage_eviction_state,
+            wal_redo_works: AtomicBool::new(false),
+            config_reloader,
         })
     }
 }
@@ -115,7 +139,7 @@ impl From<PageReconstructError> for ApiError {
             PageReconstructError::Cancelled => {
                 ApiError::InternalServerError(anyhow::anyhow!("request was cancelled"))
             }
-            PageReconstructError::AncestorStopping(_) => {
+            PageReconstructError::AncestorStopping(_) | PageReconstructError::MissingKey(_) => {
                 ApiError::InternalServerError(anyhow::Error::new(pre))
             }
             PageReconstructError::WalRedo(pre) => {
@@ -271,14 +295,22 @@ async fn build_timeline_info_common(
         Lsn(0) => None,
         lsn @ Lsn(_) => Some(lsn),
     };
-    let current_logical_size = match timeline.get_current_logical_size(ctx) {
-        Ok((size, _)) => Some(size),
+    let (current_logical_size, current_logical_size_is_exact) =
+        match timeline.get_current_logical_size(ctx) {
+            Ok((size, is_exact)) => (Some(size), is_exact),
+            Err(err) => {
+                error!("Timeline info creation failed to get current logical size: {err:?}");
+                (None, false)
+            }
+        };
+    let current_physical_size = Some(timeline.layer_size_sum().await);
+    let current_slru_size = match timeline.get_slru_size(last_record_lsn, ctx).await {
+        Ok(size) => Some(size),
         Err(err) => {
-            error!("Timeline info creation failed to get current logical size: {err:?}");
+            error!("Timeline info creation failed to get SLRU size: {err:?}");
             None
         }
     };
-    let current_physical_size = Some(timeline.layer_size_sum().await);
     let state = timeline.current_state();
     let remote_consistent_lsn = timeline.get_remote_consistent_lsn().unwrap_or(Lsn(0));
 
@@ -294,20 +326,25 @@ async fn build_timeline_info_common(
         prev_record_lsn: Some(timeline.get_prev_record_lsn()),
         latest_gc_cutoff_lsn: *timeline.get_latest_gc_cutoff_lsn(),
         current_logical_size,
+        current_logical_size_is_exact,
+        current_logical_size_calculated_at: timeline.get_logical_size_calculated_at(),
         current_physical_size,
         current_logical_size_non_incremental: None,
+        current_slru_size,
         timeline_dir_layer_file_size_sum: None,
         wal_source_connstr,
         last_received_msg_lsn,
         last_received_msg_ts,
         pg_version: timeline.pg_version,
+        labels: timeline.labels(),
+        region_timelines: Vec::new(),
 
         state,
     };
     Ok(info)
 }
 
-// healthcheck handler
+// liveness probe handler, also served at the old healthcheck path
 async fn status_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -317,6 +354,119 @@ async fn status_handler(
     json_response(StatusCode::OK, StatusResponse { id: config.id })
 }
 
+/// How long the readiness probe waits for remote storage to respond
+const READINESS_REMOTE_STORAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// readiness probe handler: responds with 503 and the list of pending checks until
+// the initial tenant load has finished, remote storage is reachable and WAL redo works
+async fn status_ready_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let state = get_state(&request);
+    let mut pending = Vec::new();
+
+    if crate::is_shutting_down() {
+        pending.push("shutting down".to_string());
+    }
+
+    match mgr::list_tenants().await {
+        Ok(tenants) => {
+            let loading = tenants
+                .iter()
+                .filter(|(_, state)| {
+                    matches!(
+                        state,
+                        TenantState::Loading | TenantState::Activating(ActivatingFrom::Loading)
+                    )
+                })
+                .count();
+            if loading > 0 {
+                pending.push(format!(
+                    "initial tenant load: {loading} tenants are still loading"
+                ));
+            }
+        }
+        Err(_) => pending.push("initial tenant load: tenant manager is initializing".to_string()),
+    }
+
+    if let Some(storage) = &state.remote_storage {
+        // Any response, including "not found" for an object that doesn't exist, means
+        // that remote storage is reachable.
+        let probe = RemotePath::from_string("readiness_probe").expect("valid remote path");
+        match tokio::time::timeout(READINESS_REMOTE_STORAGE_TIMEOUT, storage.download(&probe)).await
+        {
+            Ok(Ok(_)) | Ok(Err(DownloadError::NotFound)) => {}
+            Ok(Err(e)) => pending.push(format!("remote storage: {e}")),
+            Err(_) => pending.push("remote storage: request timed out".to_string()),
+        }
+    }
+
+    if !state.wal_redo_works.load(Ordering::Relaxed) {
+        let conf = state.conf;
+        match tokio::task::spawn_blocking(move || walredo::check_wal_redo(conf)).await {
+            Ok(Ok(())) => state.wal_redo_works.store(true, Ordering::Relaxed),
+            Ok(Err(e)) => pending.push(format!("wal redo: {e:#}")),
+            Err(e) => pending.push(format!("wal redo: check panicked: {e}")),
+        }
+    }
+
+    let ready = pending.is_empty();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(
+        status,
+        ReadinessResponse {
+            id: state.conf.id,
+            ready,
+            pending,
+        },
+    )
+}
+
+/// Starts a graceful shutdown, like SIGTERM does. Responds before the shutdown is done.
+async fn shutdown_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    info!("shutdown requested through the management API");
+
+    // The signal handler on the main thread runs the shutdown, which stops the
+    // HTTP endpoint last, so this response still gets out.
+    nix::sys::signal::kill(nix::unistd::Pid::this(), nix::sys::signal::Signal::SIGTERM)
+        .context("send SIGTERM to the pageserver process")
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::ACCEPTED, ())
+}
+
+async fn reload_config_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let state = get_state(&request);
+    let response = state
+        .config_reloader
+        .reload(state.remote_storage.as_ref())
+        .map_err(ApiError::BadRequest)?;
+    json_response(StatusCode::OK, response)
+}
+
+/// The background tasks running and waiting for their turn, see [`scheduler`].
+async fn background_tasks_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    json_response(StatusCode::OK, scheduler::get().info())
+}
+
 async fn timeline_create_handler(
     mut request: Request<Body>,
     _cancel: CancellationToken,
@@ -326,6 +476,16 @@ async fn timeline_create_handler(
     check_permission(&request, Some(tenant_id))?;
 
     let new_timeline_id = request_data.new_timeline_id;
+    labels::validate(&request_data.labels).map_err(ApiError::BadRequest)?;
+    let region_id = request_data.region_id.unwrap_or_default();
+    for region in std::iter::once(&region_id).chain(&request_data.materialize_in_regions) {
+        utils::region::registry()
+            .check(*region)
+            .map_err(|e| ApiError::BadRequest(e.into()))?;
+    }
+    request_data
+        .check_materialize_in_regions()
+        .map_err(ApiError::BadRequest)?;
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Error);
 
@@ -333,38 +493,141 @@ async fn timeline_create_handler(
 
     async {
         let tenant = mgr::get_tenant(tenant_id, true).await?;
-        match tenant.create_timeline(
+        let new_timeline = match tenant.create_timeline(
             new_timeline_id,
             request_data.ancestor_timeline_id.map(TimelineId::from),
             request_data.ancestor_start_lsn,
             request_data.pg_version.unwrap_or(crate::DEFAULT_PG_VERSION),
             state.broker_client.clone(),
-            request_data.region_id.unwrap_or_default(),
+            region_id,
             &ctx,
         )
         .await {
-            Ok(new_timeline) => {
-                // Created. Construct a TimelineInfo for it.
-                let timeline_info = build_timeline_info_common(&new_timeline, &ctx)
-                    .await
-                    .map_err(ApiError::InternalServerError)?;
-                json_response(StatusCode::CREATED, timeline_info)
-            }
+            Ok(new_timeline) => new_timeline,
             Err(tenant::CreateTimelineError::AlreadyExists) => {
-                json_response(StatusCode::CONFLICT, ())
+                // A retry of a creation that failed while materializing the timeline
+                // in the other regions picks up where it stopped
+                match tenant.get_timeline(new_timeline_id, false) {
+                    Ok(timeline)
+                        if !request_data.materialize_in_regions.is_empty()
+                            && timeline.region_id == region_id
+                            && timeline.get_ancestor_timeline_id()
+                                == request_data.ancestor_timeline_id =>
+                    {
+                        timeline
+                    }
+                    _ => return json_response(StatusCode::CONFLICT, ()),
+                }
             }
             Err(tenant::CreateTimelineError::AncestorLsn(err)) => {
-                json_response(StatusCode::NOT_ACCEPTABLE, HttpErrorBody::from_msg(
+                return json_response(StatusCode::NOT_ACCEPTABLE, HttpErrorBody::from_msg(
                     format!("{err:#}")
                 ))
             }
-            Err(tenant::CreateTimelineError::Other(err)) => Err(ApiError::InternalServerError(err)),
+            Err(tenant::CreateTimelineError::Other(err)) => {
+                return Err(ApiError::InternalServerError(err))
+            }
+        };
+        if !request_data.labels.is_empty() {
+            new_timeline
+                .set_labels(request_data.labels.clone())
+                .context("set timeline labels")
+                .map_err(ApiError::InternalServerError)?;
         }
+        let region_timelines = materialize_in_regions(
+            &tenant,
+            &new_timeline,
+            &request_data,
+            state.broker_client.clone(),
+            &ctx,
+        )
+        .await?;
+        // Created. Construct a TimelineInfo for it.
+        let mut timeline_info = build_timeline_info_common(&new_timeline, &ctx)
+            .await
+            .map_err(ApiError::InternalServerError)?;
+        timeline_info.region_timelines = region_timelines;
+        json_response(StatusCode::CREATED, timeline_info)
     }
     .instrument(info_span!("timeline_create", %tenant_id, timeline_id = %new_timeline_id, lsn=?request_data.ancestor_start_lsn, pg_version=?request_data.pg_version))
     .await
 }
 
+/// Branches a new timeline into the regions of its creation request, at its last
+/// record LSN, with the same labels.
+///
+/// The regions the timeline has been branched into already, by an attempt of the
+/// creation that failed midway, are kept, and the others are branched at the same
+/// LSN. That way a retry of the creation completes it, instead of failing.
+async fn materialize_in_regions(
+    tenant: &Tenant,
+    timeline: &Timeline,
+    request_data: &TimelineCreateRequest,
+    broker_client: BrokerClientChannel,
+    ctx: &RequestContext,
+) -> Result<Vec<RegionTimeline>, ApiError> {
+    let materialized: Vec<_> = tenant
+        .list_timelines()
+        .into_iter()
+        .filter(|branch| {
+            branch.get_ancestor_timeline_id() == Some(timeline.timeline_id)
+                && request_data
+                    .materialize_in_regions
+                    .contains(&branch.region_id)
+        })
+        .collect();
+    let start_lsn = match materialized.first() {
+        Some(branch) => branch.get_ancestor_lsn(),
+        None => timeline.get_last_record_lsn(),
+    };
+    let mut region_timelines = Vec::with_capacity(request_data.materialize_in_regions.len());
+    for &region_id in &request_data.materialize_in_regions {
+        let region_timeline = match materialized.iter().find(|t| t.region_id == region_id) {
+            Some(region_timeline) => Arc::clone(region_timeline),
+            None => {
+                fail::fail_point!("timeline-materialize-in-region", |_| {
+                    Err(ApiError::InternalServerError(anyhow!(
+                        "failpoint: timeline-materialize-in-region"
+                    )))
+                });
+                let region_timeline = tenant
+                    .create_timeline(
+                        TimelineId::generate(),
+                        Some(timeline.timeline_id),
+                        Some(start_lsn),
+                        timeline.pg_version,
+                        broker_client.clone(),
+                        region_id,
+                        ctx,
+                    )
+                    .await
+                    .map_err(|e| {
+                        ApiError::InternalServerError(anyhow!(
+                            "failed to materialize timeline {} in region {region_id}: {e:#}",
+                            timeline.timeline_id
+                        ))
+                    })?;
+                info!(
+                    "materialized timeline in region {region_id} as {}",
+                    region_timeline.timeline_id
+                );
+                region_timeline
+            }
+        };
+        if !request_data.labels.is_empty() {
+            region_timeline
+                .set_labels(request_data.labels.clone())
+                .context("set timeline labels")
+                .map_err(ApiError::InternalServerError)?;
+        }
+        region_timelines.push(RegionTimeline {
+            region_id,
+            timeline_id: region_timeline.timeline_id,
+        });
+    }
+    Ok(region_timelines)
+}
+
 async fn timeline_list_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -372,13 +635,17 @@ async fn timeline_list_handler(
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     let include_non_incremental_logical_size: Option<bool> =
         parse_query_param(&request, "include-non-incremental-logical-size")?;
+    let label_selector: Option<LabelSelector> = parse_query_param(&request, "label")?;
     check_permission(&request, Some(tenant_id))?;
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
 
     let response_data = async {
         let tenant = mgr::get_tenant(tenant_id, true).await?;
-        let timelines = tenant.list_timelines();
+        let mut timelines = tenant.list_timelines();
+        if let Some(selector) = &label_selector {
+            timelines.retain(|timeline| selector.matches(&timeline.labels()));
+        }
 
         let mut response_data = Vec::with_capacity(timelines.len());
         for timeline in timelines {
@@ -439,6 +706,136 @@ async fn timeline_detail_handler(
     json_response(StatusCode::OK, timeline_info)
 }
 
+async fn timeline_labels_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let new_labels: Labels = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+    labels::validate(&new_labels).map_err(ApiError::BadRequest)?;
+
+    async {
+        let tenant = mgr::get_tenant(tenant_id, true).await?;
+        let timeline = tenant
+            .get_timeline(timeline_id, false)
+            .map_err(|e| ApiError::NotFound(e.into()))?;
+        timeline
+            .set_labels(new_labels)
+            .context("set timeline labels")
+            .map_err(ApiError::InternalServerError)
+    }
+    .instrument(info_span!("timeline_labels", %tenant_id, %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn get_timeline_config_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+    let timeline = tenant
+        .get_timeline(timeline_id, false)
+        .map_err(|e| ApiError::NotFound(e.into()))?;
+
+    let response = HashMap::from([
+        (
+            "timeline_specific_overrides",
+            serde_json::to_value(timeline.timeline_specific_overrides())
+                .context("serializing timeline specific overrides")
+                .map_err(ApiError::InternalServerError)?,
+        ),
+        (
+            "effective_config",
+            serde_json::to_value(timeline.effective_timeline_config())
+                .context("serializing effective config")
+                .map_err(ApiError::InternalServerError)?,
+        ),
+    ]);
+
+    json_response(StatusCode::OK, response)
+}
+
+async fn update_timeline_config_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let request_data: TimelineConfigRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_conf = TimelineConfOpt::try_from(&request_data).map_err(ApiError::BadRequest)?;
+
+    async {
+        let tenant = mgr::get_tenant(tenant_id, true).await?;
+        let timeline = tenant
+            .get_timeline(timeline_id, false)
+            .map_err(|e| ApiError::NotFound(e.into()))?;
+        timeline
+            .set_timeline_conf(timeline_conf)
+            .context("set timeline config")
+            .map_err(ApiError::InternalServerError)
+    }
+    .instrument(info_span!("timeline_config", %tenant_id, %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn timeline_suspend_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    async {
+        let tenant = mgr::get_tenant(tenant_id, true).await?;
+        let timeline = tenant
+            .get_timeline(timeline_id, false)
+            .map_err(|e| ApiError::NotFound(e.into()))?;
+        timeline.suspend().map_err(|state| {
+            ApiError::Conflict(format!("Cannot suspend a timeline in state {state:?}"))
+        })
+    }
+    .instrument(info_span!("timeline_suspend", %tenant_id, %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn timeline_resume_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    async {
+        let tenant = mgr::get_tenant(tenant_id, true).await?;
+        let timeline = tenant
+            .get_timeline(timeline_id, false)
+            .map_err(|e| ApiError::NotFound(e.into()))?;
+        timeline.resume().map_err(|state| {
+            ApiError::Conflict(format!("Cannot resume a timeline in state {state:?}"))
+        })
+    }
+    .instrument(info_span!("timeline_resume", %tenant_id, %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn get_lsn_by_timestamp_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -474,10 +871,16 @@ async fn tenant_attach_handler(
     check_permission(&request, Some(tenant_id))?;
 
     let maybe_body: Option<TenantAttachRequest> = json_request_or_empty_body(&mut request).await?;
-    let tenant_conf = match maybe_body {
-        Some(request) => TenantConfOpt::try_from(&*request.config).map_err(ApiError::BadRequest)?,
-        None => TenantConfOpt::default(),
+    let (tenant_conf, tenant_labels) = match maybe_body {
+        Some(request) => (
+            TenantConfOpt::try_from(&*request.config).map_err(ApiError::BadRequest)?,
+            request.config.labels.clone(),
+        ),
+        None => (TenantConfOpt::default(), None),
     };
+    if let Some(tenant_labels) = &tenant_labels {
+        labels::validate(tenant_labels).map_err(ApiError::BadRequest)?;
+    }
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
 
@@ -496,6 +899,14 @@ async fn tenant_attach_handler(
         )
         .instrument(info_span!("tenant_attach", %tenant_id))
         .await?;
+
+        if let Some(tenant_labels) = tenant_labels {
+            mgr::get_tenant(tenant_id, false)
+                .await?
+                .set_labels(tenant_labels)
+                .context("set tenant labels")
+                .map_err(ApiError::InternalServerError)?;
+        }
     } else {
         return Err(ApiError::BadRequest(anyhow!(
             "attach_tenant is not possible because pageserver was configured without remote storage"
@@ -579,24 +990,42 @@ async fn tenant_ignore_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn tenant_list_ignored_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let state = get_state(&request);
+    let ignored = mgr::list_ignored_tenants(state.conf)
+        .instrument(info_span!("tenant_list_ignored"))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, ignored)
+}
+
 async fn tenant_list_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
+    let label_selector: Option<LabelSelector> = parse_query_param(&request, "label")?;
+    let region_id: Option<RegionId> = parse_query_param(&request, "region_id")?;
     check_permission(&request, None)?;
 
-    let response_data = mgr::list_tenants()
+    let response_data = mgr::list_tenants_detailed()
         .instrument(info_span!("tenant_list"))
         .await
         .map_err(anyhow::Error::new)
         .map_err(ApiError::InternalServerError)?
         .iter()
-        .map(|(id, state)| TenantInfo {
-            id: *id,
-            state: state.clone(),
-            current_physical_size: None,
-            attachment_status: state.attachment_status(),
+        .map(|tenant| tenant_info_of(tenant, None))
+        .filter(|info| {
+            label_selector
+                .as_ref()
+                .map_or(true, |selector| selector.matches(&info.labels))
         })
+        .filter(|info| region_id.map_or(true, |region_id| info.home_region == Some(region_id)))
         .collect::<Vec<TenantInfo>>();
 
     json_response(StatusCode::OK, response_data)
@@ -618,13 +1047,7 @@ async fn tenant_status(
             current_physical_size += timeline.layer_size_sum().await;
         }
 
-        let state = tenant.current_state();
-        Result::<_, ApiError>::Ok(TenantInfo {
-            id: tenant_id,
-            state: state.clone(),
-            current_physical_size: Some(current_physical_size),
-            attachment_status: state.attachment_status(),
-        })
+        Result::<_, ApiError>::Ok(tenant_info_of(&tenant, Some(current_physical_size)))
     }
     .instrument(info_span!("tenant_status_handler", %tenant_id))
     .await?;
@@ -632,6 +1055,21 @@ async fn tenant_status(
     json_response(StatusCode::OK, tenant_info)
 }
 
+fn tenant_info_of(tenant: &Tenant, current_physical_size: Option<u64>) -> TenantInfo {
+    let state = tenant.current_state();
+    TenantInfo {
+        id: tenant.tenant_id(),
+        attachment_status: state.attachment_status(),
+        state,
+        current_physical_size,
+        labels: tenant.labels(),
+        home_region: tenant.home_region(),
+        regions: tenant.regions(),
+        has_in_progress_downloads: tenant.has_in_progress_downloads(),
+        attach_download: tenant.attach_download_progress(),
+    }
+}
+
 async fn tenant_delete_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -747,6 +1185,68 @@ async fn layer_map_info_handler(
     json_response(StatusCode::OK, layer_map_info)
 }
 
+/// Replaces the wait-for edges of the transactions of a region, reported by the
+/// transaction coordination layer of the region.
+async fn tenant_wait_edges_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let region: RegionId = parse_request_param(&request, "region")?;
+    let report: WaitEdgesReport = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, true).await?;
+    tenant
+        .wait_graph
+        .report(region, report.edges)
+        .map_err(ApiError::BadRequest)?;
+
+    json_response(StatusCode::OK, ())
+}
+
+/// The wait-for graph of the transactions of the tenant across the regions, with
+/// its cycles, for the deadlock detector.
+async fn tenant_wait_graph_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, true).await?;
+    json_response(StatusCode::OK, tenant.wait_graph.info())
+}
+
+async fn timeline_computes_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    json_response(StatusCode::OK, timeline.attached_computes())
+}
+
+async fn timeline_check_layer_map_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    timeline
+        .check_layer_map()
+        .await
+        .map_err(|e| ApiError::InternalServerError(e.context("layer map check failed")))?;
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn layer_download_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -798,6 +1298,31 @@ async fn evict_timeline_layer_handler(
     }
 }
 
+async fn layer_repair_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let layer_file_name = get_request_param(&request, "layer_file_name")?;
+
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let repaired = timeline
+        .repair_layer(layer_file_name, &ctx)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    match repaired {
+        Some(info) => json_response(StatusCode::OK, info),
+        None => json_response(
+            StatusCode::BAD_REQUEST,
+            format!("Layer {tenant_id}/{timeline_id}/{layer_file_name} not found"),
+        ),
+    }
+}
+
 /// Get tenant_size SVG graph along with the JSON data.
 fn synthetic_size_html_response(
     inputs: ModelInputs,
@@ -869,6 +1394,9 @@ async fn tenant_create_handler(
 
     let tenant_conf =
         TenantConfOpt::try_from(&request_data.config).map_err(ApiError::BadRequest)?;
+    if let Some(tenant_labels) = &request_data.config.labels {
+        labels::validate(tenant_labels).map_err(ApiError::BadRequest)?;
+    }
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
 
@@ -885,6 +1413,13 @@ async fn tenant_create_handler(
     .instrument(info_span!("tenant_create", tenant_id = %target_tenant_id))
     .await?;
 
+    if let Some(tenant_labels) = request_data.config.labels {
+        new_tenant
+            .set_labels(tenant_labels)
+            .context("set tenant labels")
+            .map_err(ApiError::InternalServerError)?;
+    }
+
     // We created the tenant. Existing API semantics are that the tenant
     // is Active when this function returns.
     if let res @ Err(_) = new_tenant.wait_to_become_active().await {
@@ -941,9 +1476,13 @@ async fn update_tenant_config_handler(
 
     let tenant_conf =
         TenantConfOpt::try_from(&request_data.config).map_err(ApiError::BadRequest)?;
+    let tenant_labels = request_data.config.labels;
+    if let Some(tenant_labels) = &tenant_labels {
+        labels::validate(tenant_labels).map_err(ApiError::BadRequest)?;
+    }
 
     let state = get_state(&request);
-    mgr::set_new_tenant_config(state.conf, tenant_conf, tenant_id)
+    mgr::set_new_tenant_config(state.conf, tenant_conf, tenant_labels, tenant_id)
         .instrument(info_span!("tenant_config", %tenant_id))
         .await?;
 
@@ -1001,6 +1540,133 @@ async fn failpoints_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn timeline_diff_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+    let from_lsn: Lsn = parse_query_param(&request, "from_lsn")?
+        .ok_or_else(|| ApiError::BadRequest(anyhow!("missing 'from_lsn' query parameter")))?;
+    let to_lsn: Option<Lsn> = parse_query_param(&request, "to_lsn")?;
+
+    async {
+        let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+        let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+        let to_lsn = to_lsn.unwrap_or_else(|| timeline.get_disk_consistent_lsn());
+        let diff = timeline
+            .diff(from_lsn, to_lsn, &ctx)
+            .await
+            .map_err(|e| match e {
+                DiffError::InvalidLsnRange(msg) => ApiError::BadRequest(anyhow!(msg)),
+                DiffError::Other(e) => ApiError::InternalServerError(e),
+            })?;
+        json_response(StatusCode::OK, diff)
+    }
+    .instrument(info_span!("timeline_diff", %tenant_id, %timeline_id, %from_lsn, ?to_lsn))
+    .await
+}
+
+/// Streams the timeline's metadata and layer files as a tar archive, see
+/// [`Timeline::export_snapshot`].
+async fn timeline_snapshot_export_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, true).await?;
+    let timeline = tenant
+        .get_timeline(timeline_id, true)
+        .map_err(|e| ApiError::NotFound(e.into()))?;
+    if let Some(ancestor_id) = timeline.get_ancestor_timeline_id() {
+        return Err(ApiError::BadRequest(anyhow!(
+            "cannot export timeline {timeline_id}, it has ancestor {ancestor_id}"
+        )));
+    }
+
+    // The export outlives the handler, so it runs in the tenant's scope, to be
+    // stopped when the tenant shuts down.
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+    tenant.tasks.spawn("snapshot export", |cancel| {
+        async move {
+            let result = tokio::select! {
+                result = timeline.export_snapshot(writer) => result,
+                _ = cancel.cancelled() => Err(anyhow!("tenant is shutting down")),
+            };
+            let _ = result_tx.send(result);
+        }
+        .instrument(info_span!("timeline_snapshot_export", %tenant_id, %timeline_id))
+    });
+    // Fail the response body if the export fails, rather than ending it early
+    // with a truncated archive.
+    let export_result = futures::stream::once(async move {
+        let error = match result_rx.await {
+            Ok(Ok(())) => return None,
+            Ok(Err(e)) => format!("{e:#}"),
+            Err(_) => "snapshot export task panicked".to_string(),
+        };
+        error!("snapshot export failed: {error}");
+        Some(Err(std::io::Error::new(std::io::ErrorKind::Other, error)))
+    })
+    .filter_map(std::future::ready);
+    let body = Body::wrap_stream(ReaderStream::new(reader).chain(export_result));
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/x-tar")
+        .body(body)
+        .unwrap())
+}
+
+/// Creates a new tenant from an archive written by [`timeline_snapshot_export_handler`].
+async fn timeline_snapshot_import_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
+    let state = get_state(&request);
+    let (conf, broker_client, remote_storage) = (
+        state.conf,
+        state.broker_client.clone(),
+        state.remote_storage.clone(),
+    );
+    let snapshot = StreamReader::new(
+        request
+            .into_body()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+    );
+
+    let tenant = mgr::import_tenant_snapshot(
+        conf,
+        TenantConfOpt::default(),
+        tenant_id,
+        timeline_id,
+        snapshot,
+        broker_client,
+        remote_storage,
+        &ctx,
+    )
+    .instrument(info_span!("timeline_snapshot_import", %tenant_id, %timeline_id))
+    .await?;
+
+    tenant
+        .wait_to_become_active()
+        .await
+        .context("imported tenant failed to become active")
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::CREATED, ())
+}
+
 // Run GC immediately on given timeline.
 async fn timeline_gc_handler(
     mut request: Request<Body>,
@@ -1035,6 +1701,7 @@ async fn timeline_compact_handler(
     async {
         let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
         let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+        let _permit = manual_compaction_turn(tenant_id, &cancel).await?;
         timeline
             .compact(&cancel, &ctx)
             .await
@@ -1045,6 +1712,18 @@ async fn timeline_compact_handler(
     .await
 }
 
+/// Waits for a compaction turn in the background task scheduler, ahead of the
+/// automatic compactions.
+async fn manual_compaction_turn(
+    tenant_id: TenantId,
+    cancel: &CancellationToken,
+) -> Result<scheduler::Permit, ApiError> {
+    scheduler::get()
+        .acquire_cancellable(TaskClass::Compaction, tenant_id, u64::MAX, cancel)
+        .await
+        .map_err(|_| ApiError::InternalServerError(anyhow!("request was cancelled")))
+}
+
 // Run checkpoint immediately on given timeline.
 async fn timeline_checkpoint_handler(
     request: Request<Body>,
@@ -1060,6 +1739,7 @@ async fn timeline_checkpoint_handler(
             .freeze_and_flush()
             .await
             .map_err(ApiError::InternalServerError)?;
+        let _permit = manual_compaction_turn(tenant_id, &cancel).await?;
         timeline
             .compact(&cancel, &ctx)
             .await
@@ -1211,6 +1891,61 @@ async fn disk_usage_eviction_run(
     json_response(StatusCode::OK, response)
 }
 
+fn page_cache_info(conf: &PageServerConf) -> PageCacheInfo {
+    let cache = page_cache::get();
+    PageCacheInfo {
+        size: cache.size(),
+        capacity: cache.capacity(),
+        page_size: page_cache::PAGE_SZ,
+        memory_budget: conf.page_cache_memory_budget,
+        tenants: cache
+            .materialized_pages_by_tenant()
+            .into_iter()
+            .map(|(tenant_id, materialized_pages)| PageCacheTenantUsage {
+                tenant_id,
+                materialized_pages,
+            })
+            .collect(),
+    }
+}
+
+async fn page_cache_info_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    json_response(StatusCode::OK, page_cache_info(get_config(&request)))
+}
+
+async fn top_tenants_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let by: TopTenantsBy = parse_query_param(&request, "by")?.unwrap_or(TopTenantsBy::Reads);
+    let k: usize = parse_query_param(&request, "k")?.unwrap_or(20);
+
+    json_response(StatusCode::OK, crate::top_tenants::top_tenants(by, k).await)
+}
+
+async fn page_cache_resize_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let resize_request: PageCacheResizeRequest = json_request(&mut request).await?;
+
+    let new_size = page_cache::get()
+        .resize(resize_request.size)
+        .map_err(ApiError::InternalServerError)?;
+    info!(
+        requested = resize_request.size,
+        new_size, "resized page cache"
+    );
+
+    json_response(StatusCode::OK, page_cache_info(get_config(&request)))
+}
+
 async fn handler_404(_: Request<Body>) -> Result<Response<Body>, ApiError> {
     json_response(
         StatusCode::NOT_FOUND,
@@ -1337,6 +2072,7 @@ pub fn make_router(
     broker_client: BrokerClientChannel,
     remote_storage: Option<GenericRemoteStorage>,
     disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
+    config_reloader: ConfigReloader,
 ) -> anyhow::Result<RouterBuilder<hyper::Body, ApiError>> {
     let spec = include_bytes!("openapi_spec.yml");
     let mut router = attach_openapi_ui(endpoint::make_router(), spec, "/swagger.yml", "/v1/doc");
@@ -1367,15 +2103,29 @@ pub fn make_router(
                 remote_storage,
                 broker_client,
                 disk_usage_eviction_state,
+                config_reloader,
             )
             .context("Failed to initialize router state")?,
         ))
         .get("/v1/status", |r| api_handler(r, status_handler))
+        .get("/v1/status/live", |r| api_handler(r, status_handler))
+        .get("/v1/status/ready", |r| api_handler(r, status_ready_handler))
+        .post("/v1/reload_config", |r| {
+            api_handler(r, reload_config_handler)
+        })
+        .post("/v1/shutdown", |r| api_handler(r, shutdown_handler))
+        .get("/v1/background_tasks", |r| {
+            api_handler(r, background_tasks_handler)
+        })
         .put("/v1/failpoints", |r| {
             testing_api_handler("manage failpoints", r, failpoints_handler)
         })
         .get("/v1/tenant", |r| api_handler(r, tenant_list_handler))
         .post("/v1/tenant", |r| api_handler(r, tenant_create_handler))
+        // before the tenant status, which would take `ignored` for a tenant id
+        .get("/v1/tenant/ignored", |r| {
+            api_handler(r, tenant_list_ignored_handler)
+        })
         .get("/v1/tenant/:tenant_id", |r| api_handler(r, tenant_status))
         .delete("/v1/tenant/:tenant_id", |r| {
             api_handler(r, tenant_delete_handler)
@@ -1407,13 +2157,49 @@ pub fn make_router(
         .post("/v1/tenant/:tenant_id/ignore", |r| {
             api_handler(r, tenant_ignore_handler)
         })
+        .get("/v1/tenant/:tenant_id/wait_graph", |r| {
+            api_handler(r, tenant_wait_graph_handler)
+        })
+        .put("/v1/tenant/:tenant_id/wait_graph/:region", |r| {
+            api_handler(r, tenant_wait_edges_handler)
+        })
         .get("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             api_handler(r, timeline_detail_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/computes",
+            |r| api_handler(r, timeline_computes_handler),
+        )
+        .put("/v1/tenant/:tenant_id/timeline/:timeline_id/labels", |r| {
+            api_handler(r, timeline_labels_handler)
+        })
+        .get("/v1/tenant/:tenant_id/timeline/:timeline_id/config", |r| {
+            api_handler(r, get_timeline_config_handler)
+        })
+        .put("/v1/tenant/:tenant_id/timeline/:timeline_id/config", |r| {
+            api_handler(r, update_timeline_config_handler)
+        })
+        .post("/v1/tenant/:tenant_id/timeline/:timeline_id/suspend", |r| {
+            api_handler(r, timeline_suspend_handler)
+        })
+        .post("/v1/tenant/:tenant_id/timeline/:timeline_id/resume", |r| {
+            api_handler(r, timeline_resume_handler)
+        })
         .get(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/get_lsn_by_timestamp",
             |r| api_handler(r, get_lsn_by_timestamp_handler),
         )
+        .get("/v1/tenant/:tenant_id/timeline/:timeline_id/diff", |r| {
+            api_handler(r, timeline_diff_handler)
+        })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/snapshot",
+            |r| api_handler(r, timeline_snapshot_export_handler),
+        )
+        .put(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/snapshot",
+            |r| api_handler(r, timeline_snapshot_import_handler),
+        )
         .put("/v1/tenant/:tenant_id/timeline/:timeline_id/do_gc", |r| {
             api_handler(r, timeline_gc_handler)
         })
@@ -1446,9 +2232,26 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/layer/:layer_file_name",
             |r| api_handler(r, evict_timeline_layer_handler),
         )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/layer/:layer_file_name/repair",
+            |r| api_handler(r, layer_repair_handler),
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/check_layer_map",
+            |r| api_handler(r, timeline_check_layer_map_handler),
+        )
         .put("/v1/disk_usage_eviction/run", |r| {
             api_handler(r, disk_usage_eviction_run)
         })
+        .get("/v1/page_cache", |r| {
+            api_handler(r, page_cache_info_handler)
+        })
+        .put("/v1/page_cache", |r| {
+            api_handler(r, page_cache_resize_handler)
+        })
+        .get("/v1/debug/top_tenants", |r| {
+            api_handler(r, top_tenants_handler)
+        })
         .put("/v1/tenant/:tenant_id/break", |r| {
             testing_api_handler("set tenant state to broken", r, handle_tenant_break)
         })
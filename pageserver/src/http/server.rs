@@ -0,0 +1,97 @@
+//! Serves the management API with the connection limit and the TLS settings of
+//! [`PageServerConf::http_listener`](crate::config::PageServerConf::http_listener).
+
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use hyper::server::conn::Http;
+use hyper::Body;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+use utils::http::error::ApiError;
+use utils::http::{RequestServiceBuilder, Router};
+
+use crate::config::listener::ListenerConfig;
+
+/// Accepts connections until `cancel` is cancelled, then waits for the requests
+/// in progress to finish.
+pub async fn serve(
+    listener: TcpListener,
+    router: Router<Body, ApiError>,
+    listener_conf: &ListenerConfig,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let connection_limit = listener_conf.connection_limit();
+    let tls_acceptor = tls_config.map(TlsAcceptor::from);
+    let mut service_builder = RequestServiceBuilder::new(router).map_err(|err| anyhow!(err))?;
+
+    let mut connections = JoinSet::new();
+    loop {
+        let (socket, peer_addr) = tokio::select! {
+            _ = cancel.cancelled() => break,
+            res = listener.accept() => match res {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!("accept() failed: {err:?}");
+                    continue;
+                }
+            },
+        };
+        // Forget about the connections that are done
+        while connections.try_join_next().is_some() {}
+
+        let Some(connection_slot) = connection_limit.try_acquire() else {
+            warn!("refusing http connection from {peer_addr}, max_connections reached");
+            continue;
+        };
+        let service = service_builder.build(peer_addr);
+        let tls_acceptor = tls_acceptor.clone();
+        let cancel = cancel.clone();
+        connections.spawn(async move {
+            let _connection_slot = connection_slot;
+            let res = match tls_acceptor {
+                Some(tls_acceptor) => match tls_acceptor.accept(socket).await {
+                    Ok(stream) => serve_connection(stream, service, cancel).await,
+                    Err(err) => {
+                        debug!("TLS handshake with {peer_addr} failed: {err}");
+                        return;
+                    }
+                },
+                None => serve_connection(socket, service, cancel).await,
+            };
+            if let Err(err) = res {
+                debug!("http connection from {peer_addr} failed: {err}");
+            }
+        });
+    }
+
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn serve_connection<IO>(
+    io: IO,
+    service: utils::http::RequestService<Body, ApiError>,
+    cancel: CancellationToken,
+) -> hyper::Result<()>
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = Http::new().serve_connection(io, service);
+    tokio::pin!(connection);
+    tokio::select! {
+        res = connection.as_mut() => res,
+        _ = cancel.cancelled() => {
+            // Like hyper::Server::with_graceful_shutdown: finish the request in
+            // progress, and then close the connection.
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    }
+}
@@ -62,6 +62,26 @@ impl KeySpace {
         KeyPartitioning { parts }
     }
 
+    ///
+    /// Split the key space in two: the keys below `at`, and the keys at or
+    /// above it.
+    ///
+    pub fn split_at(&self, at: Key) -> (KeySpace, KeySpace) {
+        let mut below = Vec::new();
+        let mut above = Vec::new();
+        for range in &self.ranges {
+            if range.end <= at {
+                below.push(range.clone());
+            } else if range.start >= at {
+                above.push(range.clone());
+            } else {
+                below.push(range.start..at);
+                above.push(at..range.end);
+            }
+        }
+        (KeySpace { ranges: below }, KeySpace { ranges: above })
+    }
+
     ///
     /// Check if key space contains overlapping range
     ///
@@ -363,4 +383,23 @@ mod tests {
         //        xxxxxxxxxxx
         assert!(ks.overlaps(&kr(0..30))); // XXXXX This fails currently!
     }
+
+    #[test]
+    fn keyspace_split_at() {
+        let ks = KeySpace {
+            ranges: vec![kr(10..20), kr(30..40)],
+        };
+
+        let (below, above) = ks.split_at(Key::from_i128(15));
+        assert_ks_eq(&below, vec![kr(10..15)]);
+        assert_ks_eq(&above, vec![kr(15..20), kr(30..40)]);
+
+        let (below, above) = ks.split_at(Key::from_i128(20));
+        assert_ks_eq(&below, vec![kr(10..20)]);
+        assert_ks_eq(&above, vec![kr(30..40)]);
+
+        let (below, above) = ks.split_at(Key::from_i128(5));
+        assert_ks_eq(&below, vec![]);
+        assert_ks_eq(&above, vec![kr(10..20), kr(30..40)]);
+    }
 }
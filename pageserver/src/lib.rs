@@ -1,5 +1,6 @@
 mod auth;
 pub mod basebackup;
+pub mod broker_publisher;
 pub mod config;
 pub mod consumption_metrics;
 pub mod context;
@@ -12,19 +13,23 @@ pub mod page_cache;
 pub mod page_service;
 pub mod pgdatadir_mapping;
 pub mod repository;
+pub mod shared_page_cache;
 pub(crate) mod statvfs;
 pub mod task_mgr;
 pub mod tenant;
+pub mod top_tenants;
 pub mod trace;
 pub mod virtual_file;
 pub mod walingest;
 pub mod walrecord;
 pub mod walredo;
+pub mod xact_resolver;
 
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::task_mgr::TaskKind;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Current storage format version
 ///
@@ -33,7 +38,28 @@ use tracing::info;
 /// format, bump this!
 /// Note that TimelineMetadata uses its own version number to track
 /// backwards-compatible changes to the metadata format.
-pub const STORAGE_FORMAT_VERSION: u16 = 3;
+///
+/// Version 4 allows the blocks of layer files to be compressed, see
+/// [`tenant::block_io::CompressedBlockWriter`]. Version 5 adds a bloom filter
+/// over the keys to delta layer files. Version 6 adds a table of per-block
+/// checksums to all layer files. Version 7 adds sparse image layer files, which have a
+/// presence map. Version 3 files can still be read.
+pub const STORAGE_FORMAT_VERSION: u16 = 7;
+
+/// Oldest layer file format version that can still be read.
+pub const MIN_STORAGE_FORMAT_VERSION: u16 = 3;
+
+/// First storage format version whose layer files may have compressed blocks.
+pub const COMPRESSED_BLOCKS_FORMAT_VERSION: u16 = 4;
+
+/// First storage format version whose delta layer files have a bloom filter.
+pub const BLOOM_FILTER_FORMAT_VERSION: u16 = 5;
+
+/// First storage format version whose layer files have per-block checksums.
+pub const CHECKSUMS_FORMAT_VERSION: u16 = 6;
+
+/// First storage format version that can have sparse image layer files.
+pub const SPARSE_IMAGES_FORMAT_VERSION: u16 = 7;
 
 pub const DEFAULT_PG_VERSION: u32 = 15;
 
@@ -45,9 +71,18 @@ static ZERO_PAGE: bytes::Bytes = bytes::Bytes::from_static(&[0u8; 8192]);
 
 pub use crate::metrics::preinitialize_metrics;
 
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Has [`shutdown_pageserver`] been called?
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
 #[tracing::instrument]
 pub async fn shutdown_pageserver(exit_code: i32) {
     use std::time::Duration;
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+
     // Shut down the libpq endpoint task. This prevents new connections from
     // being accepted.
     timed(
@@ -74,6 +109,13 @@ pub async fn shutdown_pageserver(exit_code: i32) {
     )
     .await;
 
+    // Save the hottest materialized pages, so that the page cache is warm after a restart.
+    match tokio::task::spawn_blocking(|| page_cache::get().save_snapshot()).await {
+        Ok(Ok(saved)) => info!("Saved {saved} pages to the page cache snapshot"),
+        Ok(Err(e)) => warn!("Failed to save the page cache snapshot: {e:#}"),
+        Err(e) => warn!("Failed to save the page cache snapshot: {e}"),
+    }
+
     // Shut down the HTTP endpoint last, so that you can still check the server's
     // status while it's shutting down.
     // FIXME: We should probably stop accepting commands like attach/detach earlier.
@@ -99,6 +141,27 @@ pub async fn shutdown_pageserver(exit_code: i32) {
 /// Full path: `tenants/<tenant_id>/timelines/<timeline_id>/metadata`.
 pub const METADATA_FILE_NAME: &str = "metadata";
 
+/// The name of the log of metadata updates, applied on top of the metadata file.
+/// Full path: `tenants/<tenant_id>/timelines/<timeline_id>/metadata.log`.
+pub const METADATA_LOG_FILE_NAME: &str = "metadata.log";
+
+/// Labels of a tenant or a timeline.
+/// Full path: `tenants/<tenant_id>/labels` and `tenants/<tenant_id>/timelines/<timeline_id>/labels`.
+pub const LABELS_FILE_NAME: &str = "labels";
+
+/// Overrides of the tenant config for a timeline, see [`tenant::config::TimelineConfOpt`].
+/// Full path: `tenants/<tenant_id>/timelines/<timeline_id>/config`.
+pub const TIMELINE_CONFIG_FILE_NAME: &str = "config";
+
+/// Written by a graceful shutdown, see [`tenant::timeline::clean_shutdown`].
+/// Full path: `tenants/<tenant_id>/timelines/<timeline_id>/clean_shutdown`.
+pub const CLEAN_SHUTDOWN_MARKER_FILE_NAME: &str = "clean_shutdown";
+
+/// Written when the relation data of a timeline is first skipped, see
+/// [`tenant::timeline::skipped_relation_data`].
+/// Full path: `tenants/<tenant_id>/timelines/<timeline_id>/relation_data_skipped`.
+pub const RELATION_DATA_SKIPPED_FILE_NAME: &str = "relation_data_skipped";
+
 /// Per-tenant configuration file.
 /// Full path: `tenants/<tenant_id>/config`.
 pub const TENANT_CONFIG_NAME: &str = "config";
@@ -102,6 +102,15 @@ pub(crate) static RECONSTRUCT_TIME: Lazy<Histogram> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+pub(crate) static RECONSTRUCT_BATCH_TIME: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "pageserver_getpage_reconstruct_batch_seconds",
+        "Time spent in reconstruct_values (reconstruct the pages read together from deltas)",
+        CRITICAL_OP_BUCKETS.into(),
+    )
+    .expect("failed to define a metric")
+});
+
 pub(crate) static MATERIALIZED_PAGE_CACHE_HIT_DIRECT: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "pageserver_materialized_cache_hits_direct_total",
@@ -127,6 +136,14 @@ pub(crate) static MATERIALIZED_PAGE_CACHE_HIT: Lazy<IntCounter> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+pub(crate) static LAYERS_SKIPPED_BY_BLOOM_FILTER: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_layers_skipped_by_bloom_filter_total",
+        "Number of delta layer lookups skipped because the layer's bloom filter ruled out the key",
+    )
+    .expect("failed to define a metric")
+});
+
 pub struct PageCacheMetrics {
     pub read_accesses_materialized_page: IntCounter,
     pub read_accesses_ephemeral: IntCounter,
@@ -243,6 +260,15 @@ pub static PAGE_CACHE_SIZE: Lazy<PageCacheSizeMetrics> = Lazy::new(|| PageCacheS
     },
 });
 
+pub(crate) static EPHEMERAL_FILE_SPILLED_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_ephemeral_file_spilled_bytes_total",
+        "Bytes of ephemeral file pages spilled to disk, before and after compression",
+        &["kind"]
+    )
+    .expect("failed to define a metric")
+});
+
 pub(crate) static WAIT_LSN_TIME: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "pageserver_wait_lsn_seconds",
@@ -252,6 +278,25 @@ pub(crate) static WAIT_LSN_TIME: Lazy<Histogram> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+static WAIT_LSN_TIME_PER_TIMELINE: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "pageserver_timeline_wait_lsn_seconds",
+        "Time spent waiting for WAL to arrive, grouped by timeline",
+        &["tenant_id", "timeline_id"],
+        CRITICAL_OP_BUCKETS.into(),
+    )
+    .expect("failed to define a metric")
+});
+
+static WAIT_LSN_TIMEOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_wait_lsn_timeouts_total",
+        "Number of waits for WAL to arrive that timed out",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
 static LAST_RECORD_LSN: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "pageserver_last_record_lsn",
@@ -404,6 +449,15 @@ static EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+static LAYER_CORRUPTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_layer_corruptions_total",
+        "Number of times a local layer file failed checksum verification on read",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
 static EVICTIONS_WITH_LOW_RESIDENCE_DURATION: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "pageserver_evictions_with_low_residence_duration",
@@ -576,6 +630,7 @@ const STORAGE_IO_TIME_BUCKETS: &[f64] = &[
 /// - write (`write_at`)
 /// - seek (modify internal position or file length query)
 /// - fsync ([`std::fs::File::sync_all`])
+/// - fdatasync ([`std::fs::File::sync_data`])
 /// - metadata ([`std::fs::File::metadata`])
 pub(crate) static STORAGE_IO_TIME: Lazy<HistogramVec> = Lazy::new(|| {
     register_histogram_vec!(
@@ -653,6 +708,15 @@ impl DurationResultObserver for BasebackupQueryTime {
     }
 }
 
+pub(crate) static BASEBACKUP_CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_basebackup_cache_lookups_total",
+        "Number of basebackup requests looked up in the basebackup cache, by result",
+        &["result"]
+    )
+    .expect("failed to define a metric")
+});
+
 pub static LIVE_CONNECTIONS_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "pageserver_live_connections",
@@ -781,6 +845,109 @@ pub(crate) static WALRECEIVER_STARTED_CONNECTIONS: Lazy<IntCounter> = Lazy::new(
     .expect("failed to define a metric")
 });
 
+pub(crate) static WAL_INGEST_SKIPPED_BLOCKS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_ingest_skipped_blocks_total",
+        "Number of block references of WAL records not ingested because of ingest_regions"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static GET_PAGE_LIMITER_WAITING: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_getpage_limiter_waiting_requests",
+        "Number of GetPage requests waiting for their turn in the concurrency limiter"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static BACKGROUND_TASKS_WAITING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_background_tasks_waiting",
+        "Number of per-tenant background tasks waiting for their turn in the scheduler",
+        &["task_class"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGESTREAM_THROTTLED_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_pagestream_throttled_requests_total",
+        "Number of page service requests delayed by the pagestream_rate_limit of their tenant"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGE_SERVICE_UNAUTHORIZED_COMMANDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_page_service_unauthorized_commands_total",
+        "Number of page service commands refused by the authorization of their connection",
+        &["command"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static XACT_RESOLVER_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_xact_resolver_requests_total",
+        "Number of requests to the transaction resolver, by result: ok, error or timeout",
+        &["result"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static XACT_RESOLVER_CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_xact_resolver_cache_hits_total",
+        "Number of transaction outcomes found in the cache of the transaction resolver client"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static SHARED_PAGE_CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_shared_page_cache_hits_total",
+        "Number of page lookups that missed the materialized page cache, and found an \
+         image in the shared page cache",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static SHARED_PAGE_CACHE_IMAGES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_shared_page_cache_images",
+        "Number of distinct page images in the shared page cache"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static SHARED_PAGE_CACHE_VERSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_shared_page_cache_versions",
+        "Number of page versions of all the tenants in the shared page cache, each \
+         referring to one of its images"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGE_READAHEAD_BLOCKS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_page_readahead_blocks_total",
+        "Number of relation blocks read ahead of the sequential scans of the page service \
+         connections",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static LOGICAL_SIZE_RECONCILE_CORRECTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_logical_size_reconcile_corrections_total",
+        "Number of times the incrementally maintained logical size of a timeline differed from \
+         its calculation from the layers, and was corrected",
+    )
+    .expect("failed to define a metric")
+});
+
 pub(crate) static WALRECEIVER_ACTIVE_MANAGERS: Lazy<IntGauge> = Lazy::new(|| {
     register_int_gauge!(
         "pageserver_walreceiver_active_managers",
@@ -901,6 +1068,24 @@ pub(crate) static WAL_REDO_BYTES_HISTOGRAM: Lazy<Histogram> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+pub(crate) static WAL_REDO_NATIVE_RECORDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_wal_redo_native_records_total",
+        "Number of Postgres WAL records replayed by the pageserver itself, by record type",
+        &["record_type"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static WAL_REDO_BATCH_SIZE_HISTOGRAM: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "pageserver_wal_redo_batch_size_histogram",
+        "Histogram of number of pages reconstructed per round trip to the WAL redo process",
+        vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0],
+    )
+    .expect("failed to define a metric")
+});
+
 // FIXME: isn't this already included by WAL_REDO_RECORDS_HISTOGRAM which has _count?
 pub(crate) static WAL_REDO_RECORD_COUNTER: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
@@ -989,12 +1174,15 @@ pub struct TimelineMetrics {
     pub last_receive_gauge: IntGauge,
     pub wal_receive_time: Histogram,
     pub wal_replication_msg_records: Histogram,
+    pub wait_lsn_time: Histogram,
+    pub wait_lsn_timeouts: IntCounter,
     pub resident_physical_size_gauge: UIntGauge,
     /// copy of LayeredTimeline.current_logical_size
     pub current_logical_size_gauge: UIntGauge,
     pub num_persistent_files_created: IntCounter,
     pub persistent_bytes_written: IntCounter,
     pub evictions: IntCounter,
+    pub layer_corruptions: IntCounter,
     pub evictions_with_low_residence_duration: std::sync::RwLock<EvictionsWithLowResidenceDuration>,
 }
 
@@ -1037,6 +1225,12 @@ impl TimelineMetrics {
         let wal_replication_msg_records = WAL_REPLICATION_MSG_RECORDS
             .get_metric_with_label_values(&[&tenant_id, &timeline_id, &region_id])
             .unwrap();
+        let wait_lsn_time = WAIT_LSN_TIME_PER_TIMELINE
+            .get_metric_with_label_values(&[&tenant_id, &timeline_id])
+            .unwrap();
+        let wait_lsn_timeouts = WAIT_LSN_TIMEOUTS
+            .get_metric_with_label_values(&[&tenant_id, &timeline_id])
+            .unwrap();
         let resident_physical_size_gauge = RESIDENT_PHYSICAL_SIZE
             .get_metric_with_label_values(&[&tenant_id, &timeline_id])
             .unwrap();
@@ -1052,6 +1246,9 @@ impl TimelineMetrics {
         let evictions = EVICTIONS
             .get_metric_with_label_values(&[&tenant_id, &timeline_id])
             .unwrap();
+        let layer_corruptions = LAYER_CORRUPTIONS
+            .get_metric_with_label_values(&[&tenant_id, &timeline_id])
+            .unwrap();
         let evictions_with_low_residence_duration =
             evictions_with_low_residence_duration_builder.build(&tenant_id, &timeline_id);
 
@@ -1069,11 +1266,14 @@ impl TimelineMetrics {
             last_receive_gauge,
             wal_receive_time,
             wal_replication_msg_records,
+            wait_lsn_time,
+            wait_lsn_timeouts,
             resident_physical_size_gauge,
             current_logical_size_gauge,
             num_persistent_files_created,
             persistent_bytes_written,
             evictions,
+            layer_corruptions,
             evictions_with_low_residence_duration: std::sync::RwLock::new(
                 evictions_with_low_residence_duration,
             ),
@@ -1086,11 +1286,14 @@ impl Drop for TimelineMetrics {
         let tenant_id = &self.tenant_id;
         let timeline_id = &self.timeline_id;
         let _ = LAST_RECORD_LSN.remove_label_values(&[tenant_id, timeline_id]);
+        let _ = WAIT_LSN_TIME_PER_TIMELINE.remove_label_values(&[tenant_id, timeline_id]);
+        let _ = WAIT_LSN_TIMEOUTS.remove_label_values(&[tenant_id, timeline_id]);
         let _ = RESIDENT_PHYSICAL_SIZE.remove_label_values(&[tenant_id, timeline_id]);
         let _ = CURRENT_LOGICAL_SIZE.remove_label_values(&[tenant_id, timeline_id]);
         let _ = NUM_PERSISTENT_FILES_CREATED.remove_label_values(&[tenant_id, timeline_id]);
         let _ = PERSISTENT_BYTES_WRITTEN.remove_label_values(&[tenant_id, timeline_id]);
         let _ = EVICTIONS.remove_label_values(&[tenant_id, timeline_id]);
+        let _ = LAYER_CORRUPTIONS.remove_label_values(&[tenant_id, timeline_id]);
 
         self.evictions_with_low_residence_duration
             .write()
@@ -1487,6 +1690,7 @@ pub fn preinitialize_metrics() {
     [
         &MATERIALIZED_PAGE_CACHE_HIT,
         &MATERIALIZED_PAGE_CACHE_HIT_DIRECT,
+        &LAYERS_SKIPPED_BY_BLOOM_FILTER,
         &UNEXPECTED_ONDEMAND_DOWNLOADS,
         &WALRECEIVER_STARTED_CONNECTIONS,
         &WALRECEIVER_BROKER_UPDATES,
@@ -1512,11 +1716,13 @@ pub fn preinitialize_metrics() {
     [
         &READ_NUM_FS_LAYERS,
         &RECONSTRUCT_TIME,
+        &RECONSTRUCT_BATCH_TIME,
         &WAIT_LSN_TIME,
         &WAL_REDO_TIME,
         &WAL_REDO_WAIT_TIME,
         &WAL_REDO_RECORDS_HISTOGRAM,
         &WAL_REDO_BYTES_HISTOGRAM,
+        &WAL_REDO_BATCH_SIZE_HISTOGRAM,
     ]
     .into_iter()
     .for_each(|h| {
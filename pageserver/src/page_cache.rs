@@ -35,26 +35,58 @@
 //! initialized it. If the guard is dropped without calling mark_valid(), the
 //! mapping is automatically removed and the slot is marked free.
 //!
+//! # Sizing
+//!
+//! The buffers for the maximum cache size are allocated at startup, but only
+//! the first `size` slots are in use at any time. The clock sweep only visits
+//! the active slots, so the cache can be shrunk or grown at runtime with
+//! [`PageCache::resize`], either through the management API or by the
+//! memory-budget driven [`autotune_task`]. Shrinking evicts (and writes back,
+//! if dirty) the pages in the slots that become inactive, and returns their
+//! memory to the OS with `madvise(MADV_DONTNEED)`. The buffers are mapped
+//! anonymously, so a released slot reads as zeroes and takes memory again only
+//! once it's written to after a grow.
+//!
+//! # Snapshot
+//!
+//! To avoid starting with a cold cache after a restart, the hottest materialized
+//! pages are saved to a snapshot file on clean shutdown, see
+//! [`PageCache::save_snapshot`], and loaded back on startup with
+//! [`PageCache::load_snapshot`]. The file starts with a header of a 4-byte magic
+//! and a big-endian u32 format version, followed by one entry per page: tenant
+//! ID, timeline ID, key, big-endian LSN, the page image, and a CRC32C of all that.
+//!
 
 use std::{
     collections::{hash_map::Entry, HashMap},
     convert::TryInto,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicU8, AtomicUsize, Ordering},
-        RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+        Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
     },
+    time::Duration,
 };
 
-use anyhow::Context;
+use anyhow::{ensure, Context};
+use nix::sys::mman::{self, MapFlags, MmapAdvise, ProtFlags};
 use once_cell::sync::OnceCell;
-use tracing::error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use utils::{
+    crashsafe,
     id::{TenantId, TimelineId},
     lsn::Lsn,
 };
 
 use crate::tenant::writeback_ephemeral_file;
-use crate::{metrics::PageCacheSizeMetrics, repository::Key};
+use crate::{
+    metrics::PageCacheSizeMetrics,
+    repository::{Key, KEY_SIZE},
+};
 
 static PAGE_CACHE: OnceCell<PageCache> = OnceCell::new();
 const TEST_PAGE_CACHE_SIZE: usize = 50;
@@ -62,8 +94,11 @@ const TEST_PAGE_CACHE_SIZE: usize = 50;
 ///
 /// Initialize the page cache. This must be called once at page server startup.
 ///
-pub fn init(size: usize) {
-    if PAGE_CACHE.set(PageCache::new(size)).is_err() {
+/// `size` is the initial number of pages in use, `max_size` is the number of
+/// pages allocated up front, which is the upper limit for [`PageCache::resize`].
+///
+pub fn init(size: usize, max_size: usize) {
+    if PAGE_CACHE.set(PageCache::new(size, max_size)).is_err() {
         panic!("page cache already initialized");
     }
 }
@@ -78,7 +113,7 @@ pub fn get() -> &'static PageCache {
     // page cache is usable in unit tests.
     //
     if cfg!(test) {
-        PAGE_CACHE.get_or_init(|| PageCache::new(TEST_PAGE_CACHE_SIZE))
+        PAGE_CACHE.get_or_init(|| PageCache::new(TEST_PAGE_CACHE_SIZE, TEST_PAGE_CACHE_SIZE))
     } else {
         PAGE_CACHE.get().expect("page cache not initialized")
     }
@@ -87,6 +122,12 @@ pub fn get() -> &'static PageCache {
 pub const PAGE_SZ: usize = postgres_ffi::BLCKSZ as usize;
 const MAX_USAGE_COUNT: u8 = 5;
 
+const SNAPSHOT_MAGIC: [u8; 4] = *b"PCSN";
+const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+const SNAPSHOT_HEADER_SIZE: usize = 8;
+/// Tenant ID, timeline ID, key, LSN, page image and checksum.
+const SNAPSHOT_ENTRY_SIZE: usize = 16 + 16 + KEY_SIZE + 8 + PAGE_SZ + 4;
+
 ///
 /// CacheKey uniquely identifies a "thing" to cache in the page cache.
 ///
@@ -181,9 +222,35 @@ pub struct PageCache {
 
     immutable_page_map: RwLock<HashMap<(u64, u32), usize>>,
 
+    /// Number of materialized pages currently cached for each tenant.
+    ///
+    /// Only modified while holding the `materialized_page_map` write lock.
+    materialized_pages_per_tenant: Mutex<HashMap<TenantId, usize>>,
+
     /// The actual buffers with their metadata.
+    ///
+    /// Only the first `size` slots are in use, the rest are allocated so that the
+    /// cache can be grown at runtime.
     slots: Box<[Slot]>,
 
+    /// Number of slots currently in use, always between 1 and `slots.len()`.
+    size: AtomicUsize,
+
+    /// Number of slots that may hold memory: the slots in use, and the inactive
+    /// ones whose memory couldn't be released, see [`Self::releases_memory`].
+    resident_size: AtomicUsize,
+
+    /// Whether a shrink can give the memory of a slot back to the OS, which needs
+    /// the slots to be made of whole OS pages.
+    releases_memory: bool,
+
+    /// Serializes [`PageCache::resize`] calls.
+    resize_lock: Mutex<()>,
+
+    /// Path of the snapshot file and the maximum number of pages to save in it,
+    /// set by [`PageCache::load_snapshot`].
+    snapshot_config: Mutex<Option<(PathBuf, usize)>>,
+
     /// Index of the next candidate to evict, for the Clock replacement algorithm.
     /// This is interpreted modulo the page cache size.
     next_evict_slot: AtomicUsize,
@@ -452,6 +519,207 @@ impl PageCache {
         }
     }
 
+    // Section 1.4: Public interface functions for sizing.
+
+    /// Number of pages currently in use.
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Number of pages that may hold memory, including the inactive slots whose
+    /// memory couldn't be released by a shrink.
+    pub fn resident_size(&self) -> usize {
+        self.resident_size.load(Ordering::Relaxed)
+    }
+
+    /// Number of pages allocated at startup, the upper limit for [`Self::resize`].
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Change the number of pages in use. The requested size is clamped to
+    /// `1..=capacity()`; returns the new size.
+    ///
+    /// When shrinking, the pages in the slots that become unused are evicted,
+    /// writing back dirty ephemeral pages first, and the memory of the slots is
+    /// released. If a writeback fails, the page stays in its (now inactive) slot
+    /// and is retried by the next resize.
+    pub fn resize(&self, new_size: usize) -> anyhow::Result<usize> {
+        let _guard = self.resize_lock.lock().unwrap();
+
+        let new_size = new_size.clamp(1, self.capacity());
+        let old_size = self.size.swap(new_size, Ordering::Relaxed);
+        self.resident_size.fetch_max(new_size, Ordering::Relaxed);
+        self.size_metrics.max_bytes.set_page_sz(new_size);
+
+        if new_size < old_size {
+            info!("shrinking page cache from {old_size} to {new_size} pages");
+            // Also look past `old_size`: a previous shrink may have left pages behind.
+            for slot_idx in new_size..self.capacity() {
+                let slot = &self.slots[slot_idx];
+                let mut inner = slot.inner.write().unwrap();
+                let evicted = if let Some(old_key) = &inner.key {
+                    if inner.dirty {
+                        Self::writeback(old_key, inner.buf).with_context(|| {
+                            format!("writeback of buffer {old_key:?} failed during resize")
+                        })?;
+                    }
+                    self.remove_mapping(old_key);
+                    inner.dirty = false;
+                    inner.key = None;
+                    true
+                } else {
+                    false
+                };
+                slot.usage_count.store(0, Ordering::Relaxed);
+                // The slots past `old_size` without a page were released already.
+                // Release under the slot lock, a racing eviction may be about to
+                // reuse the slot.
+                if self.releases_memory && (evicted || slot_idx < old_size) {
+                    release_buf(inner.buf).context("release page cache slot memory")?;
+                }
+            }
+            if self.releases_memory {
+                self.resident_size.store(new_size, Ordering::Relaxed);
+            }
+        } else if new_size > old_size {
+            info!("growing page cache from {old_size} to {new_size} pages");
+        }
+
+        Ok(new_size)
+    }
+
+    /// Number of materialized pages cached per tenant, largest first.
+    pub fn materialized_pages_by_tenant(&self) -> Vec<(TenantId, usize)> {
+        let mut result: Vec<_> = self
+            .materialized_pages_per_tenant
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tenant_id, pages)| (*tenant_id, *pages))
+            .collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1));
+        result
+    }
+
+    // Section 1.5: Public interface functions for persisting materialized pages across restarts.
+
+    /// Loads the materialized pages from the snapshot file at `path`, written by
+    /// [`Self::save_snapshot`] at the previous shutdown, and remembers `path` and
+    /// `max_pages` for the next save. Returns the number of pages loaded.
+    ///
+    /// The snapshot file is removed after loading, so that it's used at most once.
+    pub fn load_snapshot(&self, path: &Path, max_pages: usize) -> anyhow::Result<usize> {
+        *self.snapshot_config.lock().unwrap() = Some((path.to_owned(), max_pages));
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context("open page cache snapshot"),
+        };
+        let result = self.load_snapshot_from(BufReader::new(file));
+        std::fs::remove_file(path).context("remove page cache snapshot")?;
+        result
+    }
+
+    fn load_snapshot_from(&self, mut reader: impl Read) -> anyhow::Result<usize> {
+        let mut header = [0u8; SNAPSHOT_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        ensure!(
+            header[..4] == SNAPSHOT_MAGIC,
+            "bad page cache snapshot magic"
+        );
+        let format_version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        ensure!(
+            format_version == SNAPSHOT_FORMAT_VERSION,
+            "unsupported page cache snapshot format version {format_version}"
+        );
+
+        // The hottest pages come first. Stop when the cache is full, rather than
+        // evicting them to make room for colder ones.
+        let mut entry = vec![0u8; SNAPSHOT_ENTRY_SIZE];
+        let mut loaded = 0;
+        while loaded < self.size() {
+            match reader.read_exact(&mut entry) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let (data, checksum) = entry.split_at(SNAPSHOT_ENTRY_SIZE - 4);
+            if crc32c::crc32c(data) != u32::from_be_bytes(checksum.try_into().unwrap()) {
+                warn!("checksum mismatch in page cache snapshot entry {loaded}, ignoring the rest");
+                break;
+            }
+            let (tenant_id, data) = data.split_at(16);
+            let (timeline_id, data) = data.split_at(16);
+            let (key, data) = data.split_at(KEY_SIZE);
+            let (lsn, img) = data.split_at(8);
+            self.memorize_materialized_page(
+                TenantId::from_slice(tenant_id)?,
+                TimelineId::from_slice(timeline_id)?,
+                Key::from_slice(key),
+                Lsn(u64::from_be_bytes(lsn.try_into().unwrap())),
+                img,
+            )?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Saves the hottest materialized pages to the snapshot file set up by
+    /// [`Self::load_snapshot`], to be loaded again on the next startup. Returns
+    /// the number of pages saved.
+    pub fn save_snapshot(&self) -> anyhow::Result<usize> {
+        let Some((path, max_pages)) = self.snapshot_config.lock().unwrap().clone() else {
+            return Ok(0);
+        };
+        if max_pages == 0 {
+            return Ok(0);
+        }
+
+        // Visit the slots in order of decreasing usage count.
+        let mut slot_indexes: Vec<usize> = (0..self.size()).collect();
+        slot_indexes.sort_by_key(|slot_idx| {
+            std::cmp::Reverse(self.slots[*slot_idx].usage_count.load(Ordering::Relaxed))
+        });
+
+        let temp_path = crashsafe::path_with_suffix_extension(&path, "temp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_FORMAT_VERSION.to_be_bytes())?;
+
+        let mut entry = Vec::with_capacity(SNAPSHOT_ENTRY_SIZE);
+        let mut saved = 0;
+        for slot_idx in slot_indexes {
+            if saved == max_pages {
+                break;
+            }
+            let inner = self.slots[slot_idx].inner.read().unwrap();
+            let Some(CacheKey::MaterializedPage { hash_key, lsn }) = &inner.key else {
+                continue;
+            };
+            entry.clear();
+            entry.extend_from_slice(&hash_key.tenant_id.as_arr());
+            entry.extend_from_slice(&hash_key.timeline_id.as_arr());
+            let mut key = [0u8; KEY_SIZE];
+            hash_key.key.write_to_byte_slice(&mut key);
+            entry.extend_from_slice(&key);
+            entry.extend_from_slice(&lsn.0.to_be_bytes());
+            entry.extend_from_slice(inner.buf.as_slice());
+            drop(inner);
+            entry.extend_from_slice(&crc32c::crc32c(&entry).to_be_bytes());
+            writer.write_all(&entry)?;
+            saved += 1;
+        }
+
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&temp_path, &path)?;
+        crashsafe::fsync_file_and_parent(&path)?;
+        Ok(saved)
+    }
+
     //
     // Section 2: Internal interface functions for lookup/update.
     //
@@ -723,6 +991,7 @@ impl PageCache {
                         self.size_metrics
                             .current_bytes_materialized_page
                             .sub_page_sz(1);
+                        self.account_materialized_page(old_hash_key.tenant_id, false);
                         if versions.is_empty() {
                             old_entry.remove_entry();
                         }
@@ -772,6 +1041,7 @@ impl PageCache {
                         self.size_metrics
                             .current_bytes_materialized_page
                             .add_page_sz(1);
+                        self.account_materialized_page(new_key.tenant_id, true);
                         None
                     }
                 }
@@ -801,6 +1071,20 @@ impl PageCache {
         }
     }
 
+    /// Update the per-tenant count of materialized pages. Must be called with the
+    /// `materialized_page_map` write lock held.
+    fn account_materialized_page(&self, tenant_id: TenantId, added: bool) {
+        let mut per_tenant = self.materialized_pages_per_tenant.lock().unwrap();
+        if added {
+            *per_tenant.entry(tenant_id).or_default() += 1;
+        } else if let Entry::Occupied(mut entry) = per_tenant.entry(tenant_id) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+
     //
     // Section 4: Misc internal helpers
     //
@@ -813,7 +1097,7 @@ impl PageCache {
         let mut iters = 0;
         loop {
             iters += 1;
-            let slot_idx = self.next_evict_slot.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+            let slot_idx = self.next_evict_slot.fetch_add(1, Ordering::Relaxed) % self.size();
 
             let slot = &self.slots[slot_idx];
 
@@ -836,6 +1120,10 @@ impl PageCache {
                         continue;
                     }
                 };
+                if slot_idx >= self.size() {
+                    // The cache was shrunk concurrently, this slot is no longer in use.
+                    continue;
+                }
                 if let Some(old_key) = &inner.key {
                     if inner.dirty {
                         if let Err(err) = Self::writeback(old_key, inner.buf) {
@@ -888,10 +1176,21 @@ impl PageCache {
     /// Initialize a new page cache
     ///
     /// This should be called only once at page server startup.
-    fn new(num_pages: usize) -> Self {
+    fn new(num_pages: usize, max_pages: usize) -> Self {
         assert!(num_pages > 0, "page cache size must be > 0");
-
-        let page_buffer = Box::leak(vec![0u8; num_pages * PAGE_SZ].into_boxed_slice());
+        let max_pages = max_pages.max(num_pages);
+
+        // An anonymous mapping is zero-filled on first touch, so the memory for the
+        // slots that are not in use yet is not committed, and it's aligned to the OS
+        // pages for `release_buf`.
+        let page_buffer = alloc_page_buffer(max_pages * PAGE_SZ);
+        let releases_memory = match os_page_size() {
+            Ok(os_page_size) => PAGE_SZ % os_page_size == 0,
+            Err(e) => {
+                warn!("not releasing the memory of inactive page cache slots: {e:#}");
+                false
+            }
+        };
 
         let size_metrics = &crate::metrics::PAGE_CACHE_SIZE;
         size_metrics.max_bytes.set_page_sz(num_pages);
@@ -919,13 +1218,120 @@ impl PageCache {
             materialized_page_map: Default::default(),
             ephemeral_page_map: Default::default(),
             immutable_page_map: Default::default(),
+            materialized_pages_per_tenant: Default::default(),
             slots,
+            size: AtomicUsize::new(num_pages),
+            resident_size: AtomicUsize::new(num_pages),
+            releases_memory,
+            resize_lock: Mutex::new(()),
+            snapshot_config: Mutex::new(None),
             next_evict_slot: AtomicUsize::new(0),
             size_metrics,
         }
     }
 }
 
+/// Maps `len` bytes of anonymous memory for the page buffers, never unmapped.
+fn alloc_page_buffer(len: usize) -> &'static mut [u8] {
+    let len = NonZeroUsize::new(len).expect("page cache size must be > 0");
+    // SAFETY: a new private anonymous mapping doesn't alias any other memory, and
+    // stays valid for the rest of the process as it's never unmapped.
+    unsafe {
+        let ptr = mman::mmap(
+            None,
+            len,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+        .expect("failed to allocate the page cache buffers");
+        std::slice::from_raw_parts_mut(ptr.cast::<u8>(), len.get())
+    }
+}
+
+/// Gives the memory of a page buffer back to the OS. It reads as zeroes after.
+fn release_buf(buf: &mut [u8; PAGE_SZ]) -> nix::Result<()> {
+    // SAFETY: the buffer is a whole number of OS pages of the anonymous mapping,
+    // and the exclusive borrow keeps anyone else from seeing it being zeroed.
+    unsafe { mman::madvise(buf.as_mut_ptr().cast(), PAGE_SZ, MmapAdvise::MADV_DONTNEED) }
+}
+
+fn os_page_size() -> anyhow::Result<usize> {
+    let os_page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)?
+        .context("unknown OS page size")?;
+    Ok(usize::try_from(os_page_size)?)
+}
+
+/// How often [`autotune_task`] re-evaluates the page cache size.
+const AUTOTUNE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Don't bother resizing for changes smaller than this fraction of the current size.
+const AUTOTUNE_MIN_CHANGE_DIVISOR: usize = 20;
+
+/// Periodically resize the page cache so that the resident memory of the whole
+/// process stays within `memory_budget` bytes.
+///
+/// Everything that is not the page cache (tenants, layer maps, in-memory layers,
+/// connection buffers) gets to use memory first; the page cache is sized to the
+/// remainder, between one page and its capacity.
+pub async fn autotune_task(memory_budget: u64, cancel: CancellationToken) -> anyhow::Result<()> {
+    let cache = get();
+    info!(
+        memory_budget,
+        capacity = cache.capacity(),
+        "starting page cache autotuning"
+    );
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(AUTOTUNE_PERIOD) => {}
+        }
+
+        let rss = match process_resident_bytes() {
+            Ok(rss) => rss,
+            Err(e) => {
+                error!("failed to read process resident memory, skipping autotuning: {e:#}");
+                continue;
+            }
+        };
+
+        let current = cache.size();
+        let target = autotune_target(memory_budget, rss, cache.resident_size(), cache.capacity());
+
+        if target.abs_diff(current) > current / AUTOTUNE_MIN_CHANGE_DIVISOR {
+            if let Err(e) = cache.resize(target) {
+                error!("page cache autotuning failed to resize to {target} pages: {e:#}");
+            }
+        }
+    }
+}
+
+/// The page cache size that fits the process in `memory_budget` bytes, given its
+/// resident memory `rss`.
+///
+/// The inactive slots whose memory a shrink couldn't release are part of `rss` too,
+/// so all the `resident_size` slots are subtracted to get the memory used by the
+/// rest of the process, not only the slots currently in use.
+fn autotune_target(memory_budget: u64, rss: u64, resident_size: usize, capacity: usize) -> usize {
+    let other_bytes = rss.saturating_sub(count_times_page_sz(resident_size));
+    usize::try_from(memory_budget.saturating_sub(other_bytes) / PAGE_SZ as u64)
+        .unwrap_or(usize::MAX)
+        .clamp(1, capacity)
+}
+
+/// Resident set size of this process, in bytes.
+fn process_resident_bytes() -> anyhow::Result<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").context("read /proc/self/statm")?;
+    let resident_pages: u64 = statm
+        .split_whitespace()
+        .nth(1)
+        .context("missing resident field in /proc/self/statm")?
+        .parse()
+        .context("parse resident field in /proc/self/statm")?;
+    Ok(resident_pages * os_page_size()? as u64)
+}
+
 trait PageSzBytesMetric {
     fn set_page_sz(&self, count: usize);
     fn add_page_sz(&self, count: usize);
@@ -948,3 +1354,78 @@ impl PageSzBytesMetric for metrics::UIntGauge {
         self.sub(count_times_page_sz(count));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autotune_converges() {
+        let capacity = 1000;
+        let other_bytes = count_times_page_sz(300);
+        let memory_budget = count_times_page_sz(1000);
+
+        // All the slots were in use, the rest of the process then grew past the budget
+        let rss = other_bytes + count_times_page_sz(capacity);
+        let first = autotune_target(memory_budget, rss, capacity, capacity);
+        assert_eq!(first, 700);
+
+        // The shrink released the memory of the inactive slots
+        let second = autotune_target(
+            memory_budget,
+            rss - count_times_page_sz(capacity - first),
+            first,
+            capacity,
+        );
+        assert_eq!(second, first);
+
+        // Or it couldn't, the next computation must not take them for memory used
+        // by the rest of the process
+        let second = autotune_target(memory_budget, rss, capacity, capacity);
+        assert_eq!(second, first);
+
+        // Memory freed elsewhere lets the cache grow back
+        let rss = rss - count_times_page_sz(200);
+        assert_eq!(autotune_target(memory_budget, rss, capacity, capacity), 900);
+        assert_eq!(
+            autotune_target(memory_budget, 0, capacity, capacity),
+            capacity
+        );
+        assert_eq!(autotune_target(0, rss, capacity, capacity), 1);
+    }
+
+    #[test]
+    fn shrink_releases_memory() -> anyhow::Result<()> {
+        let capacity = 8192; // 64 MiB
+        let cache = PageCache::new(capacity, capacity);
+        if !cache.releases_memory {
+            return Ok(());
+        }
+        for slot in cache.slots.iter() {
+            slot.inner.write().unwrap().buf.fill(1);
+        }
+
+        let before = process_resident_bytes()?;
+        assert_eq!(cache.resize(capacity / 4)?, capacity / 4);
+        let after = process_resident_bytes()?;
+        assert_eq!(cache.resident_size(), capacity / 4);
+
+        // Other tests run concurrently, leave them some slack
+        let released = count_times_page_sz(capacity - capacity / 4);
+        assert!(
+            before.saturating_sub(after) >= released / 2,
+            "resident memory went from {before} to {after}, expected to release {released} bytes"
+        );
+
+        // The released slots are zeroes once in use again
+        assert_eq!(cache.resize(capacity)?, capacity);
+        assert!(cache.slots[capacity - 1]
+            .inner
+            .read()
+            .unwrap()
+            .buf
+            .iter()
+            .all(|b| *b == 0));
+        Ok(())
+    }
+}
@@ -7,28 +7,35 @@
 //     *status* -- show actual info about this pageserver,
 //     *pagestream* -- enter mode where smgr and pageserver talk with their
 //  custom protocol.
+//     *push_wal* -- ingest WAL pushed by a safekeeper while the broker is
+//  unreachable.
 //
 
 use anyhow::Context;
 use async_compression::tokio::write::GzipEncoder;
 use bytes::Buf;
 use bytes::Bytes;
+use bytes::BytesMut;
 use futures::Stream;
-use pageserver_api::models::TenantState;
+use futures::StreamExt;
+use pageserver_api::lsn_token::LsnToken;
+use pageserver_api::models::{ComputeMode, TenantState};
 use pageserver_api::models::{
-    PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamDbSizeResponse,
+    PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamDbSizeResponse, PagestreamErrorCode,
     PagestreamErrorResponse, PagestreamExistsRequest, PagestreamExistsResponse,
     PagestreamFeMessage, PagestreamGetLatestLsnResponse, PagestreamGetPageRequest,
     PagestreamGetPageResponse, PagestreamGetSlruPageRequest, PagestreamGetSlruPageResponse,
     PagestreamNblocksRequest, PagestreamNblocksResponse,
 };
+use pageserver_api::reltag::SlruKind;
 use postgres_backend::{self, is_expected_io_error, AuthType, PostgresBackend, QueryError};
 use pq_proto::framed::ConnectionError;
 use pq_proto::FeStartupPacket;
 use pq_proto::{BeMessage, FeMessage, RowDescriptor};
 use std::collections::HashMap;
 use std::io;
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::net::UnixListener;
 use std::pin::pin;
 use std::str;
 use std::str::FromStr;
@@ -36,6 +43,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::either::Either;
 use tokio_util::io::StreamReader;
 use tracing::field;
 use tracing::*;
@@ -45,14 +53,19 @@ use utils::{
     id::{RegionId, TenantId, TimelineId},
     lsn::Lsn,
     simple_rcu::RcuReadGuard,
+    unix_listener::UNIX_PEER_ADDR,
 };
 
 use crate::auth::check_permission;
 use crate::basebackup;
+use crate::config::listener::{ConnectionLimit, ConnectionSlot};
 use crate::config::PageServerConf;
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::import_datadir::import_wal_from_tar;
-use crate::metrics::{LIVE_CONNECTIONS_COUNT, SMGR_QUERY_TIME};
+use crate::metrics::{
+    BASEBACKUP_CACHE_LOOKUPS, LIVE_CONNECTIONS_COUNT, PAGESTREAM_THROTTLED_REQUESTS,
+    PAGE_SERVICE_UNAUTHORIZED_COMMANDS, SMGR_QUERY_TIME,
+};
 use crate::pgdatadir_mapping::Version;
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
@@ -60,11 +73,25 @@ use crate::tenant;
 use crate::tenant::debug_assert_current_span_has_tenant_and_timeline_id;
 use crate::tenant::mgr;
 use crate::tenant::mgr::GetTenantError;
+use crate::tenant::timeline::basebackup_cache::{BasebackupCacheKey, CachingWriter};
+use crate::tenant::timeline::prewarm::DEFAULT_PREWARM_MAX_RELATIONS;
+use crate::tenant::timeline::{PageReconstructError, WaitLsnError};
 use crate::tenant::{Tenant, Timeline};
 use crate::trace::Tracer;
+use crate::walingest::WalIngest;
+use crate::walrecord::DecodedWALRecord;
+use crate::xact_resolver;
 
 use postgres_ffi::pg_constants::DEFAULTTABLESPACE_OID;
-use postgres_ffi::BLCKSZ;
+use postgres_ffi::v14::xlog_utils::normalize_lsn;
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::{BLCKSZ, WAL_SEGMENT_SIZE};
+
+pub mod fair_limiter;
+pub mod grpc;
+mod readahead;
+
+use readahead::Readahead;
 
 fn copyin_stream<IO>(pgb: &mut PostgresBackend<IO>) -> impl Stream<Item = io::Result<Bytes>> + '_
 where
@@ -174,21 +201,69 @@ async fn read_tar_eof(mut reader: (impl AsyncRead + Unpin)) -> anyhow::Result<()
 
 ///////////////////////////////////////////////////////////////////////////////
 
+/// Socket the page service accepts connections on, TCP for the computes elsewhere,
+/// and optionally a Unix domain socket for the ones on the same host.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// For the computes on the same host. The connections skip TLS, like the
+    /// ones to a local Postgres do.
+    Unix(UnixListener),
+}
+
+enum TokioListener {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+type Socket = Either<tokio::net::TcpStream, tokio::net::UnixStream>;
+
+impl TokioListener {
+    fn from_std(listener: Listener) -> io::Result<Self> {
+        // Tokio's from_std won't set the non-blocking mode for us
+        Ok(match listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                Self::Tcp(tokio::net::TcpListener::from_std(listener)?)
+            }
+            Listener::Unix(listener) => {
+                listener.set_nonblocking(true)?;
+                Self::Unix(tokio::net::UnixListener::from_std(listener)?)
+            }
+        })
+    }
+
+    /// Unix domain socket peers are reported with [`UNIX_PEER_ADDR`].
+    async fn accept(&self) -> io::Result<(Socket, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (socket, peer_addr) = listener.accept().await?;
+                Ok((Either::Left(socket), peer_addr))
+            }
+            Self::Unix(listener) => {
+                let (socket, _) = listener.accept().await?;
+                Ok((Either::Right(socket), UNIX_PEER_ADDR))
+            }
+        }
+    }
+}
+
 ///
 /// Main loop of the page service.
 ///
 /// Listens for connections, and launches a new handler task for each.
 ///
+#[allow(clippy::too_many_arguments)]
 pub async fn libpq_listener_main(
     conf: &'static PageServerConf,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<JwtAuth>>,
-    listener: TcpListener,
+    listener: Listener,
     auth_type: AuthType,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    connection_limit: ConnectionLimit,
     listener_ctx: RequestContext,
 ) -> anyhow::Result<()> {
-    listener.set_nonblocking(true)?;
-    let tokio_listener = tokio::net::TcpListener::from_std(listener)?;
+    let tokio_listener = TokioListener::from_std(listener)?;
 
     // Wait for a new connection to arrive, or for server shutdown.
     while let Some(res) = tokio::select! {
@@ -205,6 +280,11 @@ pub async fn libpq_listener_main(
     } {
         match res {
             Ok((socket, peer_addr)) => {
+                let Some(connection_slot) = connection_limit.try_acquire() else {
+                    warn!("refusing connection from {peer_addr}, max_connections reached");
+                    continue;
+                };
+
                 // Connection established. Spawn a new task to handle it.
                 debug!("accepted connection from {}", peer_addr);
                 let local_auth = auth.clone();
@@ -228,7 +308,10 @@ pub async fn libpq_listener_main(
                         broker_client.clone(),
                         local_auth,
                         socket,
+                        peer_addr,
                         auth_type,
+                        tls_config.clone(),
+                        connection_slot,
                         connection_ctx,
                     ),
                 );
@@ -245,13 +328,17 @@ pub async fn libpq_listener_main(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip_all, fields(peer_addr))]
 async fn page_service_conn_main(
     conf: &'static PageServerConf,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<JwtAuth>>,
-    socket: tokio::net::TcpStream,
+    socket: Socket,
+    peer_addr: SocketAddr,
     auth_type: AuthType,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    _connection_slot: ConnectionSlot,
     connection_ctx: RequestContext,
 ) -> anyhow::Result<()> {
     // Immediately increment the gauge, then create a job to decrement it on task exit.
@@ -263,12 +350,17 @@ async fn page_service_conn_main(
         gauge.dec();
     }
 
-    socket
-        .set_nodelay(true)
-        .context("could not set TCP_NODELAY")?;
-
-    let peer_addr = socket.peer_addr().context("get peer address")?;
-    tracing::Span::current().record("peer_addr", field::display(peer_addr));
+    match &socket {
+        Either::Left(tcp_socket) => {
+            tcp_socket
+                .set_nodelay(true)
+                .context("could not set TCP_NODELAY")?;
+            tracing::Span::current().record("peer_addr", field::display(peer_addr));
+        }
+        Either::Right(_) => {
+            tracing::Span::current().record("peer_addr", "unix");
+        }
+    }
 
     // setup read timeout of 10 minutes. the timeout is rather arbitrary for requirements:
     // - long enough for most valid compute connections
@@ -287,7 +379,7 @@ async fn page_service_conn_main(
     // But it's in a shared crate, so, we store connection_ctx inside PageServerHandler
     // and create the per-query context in process_query ourselves.
     let mut conn_handler = PageServerHandler::new(conf, broker_client, auth, connection_ctx);
-    let pgbackend = PostgresBackend::new_from_io(socket, peer_addr, auth_type, None)?;
+    let pgbackend = PostgresBackend::new_from_io(socket, peer_addr, auth_type, tls_config)?;
 
     match pgbackend
         .run(&mut conn_handler, task_mgr::shutdown_watcher)
@@ -377,8 +469,19 @@ impl PageRequestMetrics {
     }
 }
 
+/// What a command needs the token of the connection to allow, see
+/// [`PageServerHandler::authorize`].
+#[derive(Debug, Clone, Copy)]
+enum CommandAccess {
+    /// Reads the pages or the metadata of the tenant.
+    Read,
+    /// Changes the data of the tenant, which only the control plane and the
+    /// safekeepers do, with pageserver-wide tokens.
+    Write,
+}
+
 struct PageServerHandler {
-    _conf: &'static PageServerConf,
+    conf: &'static PageServerConf,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<JwtAuth>>,
     claims: Option<Claims>,
@@ -398,7 +501,7 @@ impl PageServerHandler {
         connection_ctx: RequestContext,
     ) -> Self {
         PageServerHandler {
-            _conf: conf,
+            conf,
             broker_client,
             auth,
             claims: None,
@@ -406,12 +509,35 @@ impl PageServerHandler {
         }
     }
 
+    /// Waits until the timelines have ingested the WAL up to the LSNs of the token.
+    async fn wait_for_lsn_token(
+        timelines: &HashMap<RegionId, Arc<Timeline>>,
+        lsn_token: &LsnToken,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        for (region_id, lsn) in lsn_token.iter() {
+            let timeline = timelines
+                .get(&region_id)
+                .with_context(|| format!("no timeline of region {region_id} in the LSN token"))?;
+            let started_at = std::time::Instant::now();
+            timeline.wait_lsn(lsn, ctx).await.with_context(|| {
+                format!("waiting for LSN {lsn} of region {region_id} of the LSN token")
+            })?;
+            debug!(
+                "waited {:?} for LSN {lsn} of region {region_id} of the LSN token",
+                started_at.elapsed()
+            );
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     async fn handle_pagerequests<IO>(
         &self,
         pgb: &mut PostgresBackend<IO>,
         tenant_id: TenantId,
         timeline_id: Option<TimelineId>,
+        options: PagestreamOptions,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -438,18 +564,34 @@ impl PageServerHandler {
         };
 
         // Check that the timeline exists
-        let timelines = if let Some(id) = timeline_id {
-            HashMap::from([(
-                RegionId::default(),
-                tenant
-                    .get_timeline(id, true)
-                    .map_err(|e| anyhow::anyhow!(e))?,
-            )])
+        let (timelines, main_region) = if let Some(id) = timeline_id {
+            let timeline = tenant
+                .get_timeline(id, true)
+                .map_err(|e| anyhow::anyhow!(e))?;
+            let region_id = timeline.region_id;
+            (HashMap::from([(region_id, timeline)]), region_id)
         } else {
             // Remotexact
-            get_timelines_indexed_by_region_id(&tenant)?
+            (get_timelines_indexed_by_region_id(&tenant)?, RegionId(0))
         };
 
+        // Attached to the timelines until the connection is closed
+        let attached_computes = timelines
+            .iter()
+            .map(|(region_id, timeline)| {
+                (
+                    *region_id,
+                    timeline.attach_compute(*pgb.get_peer_addr(), options.mode),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        // Read-your-writes across regions: the compute isn't served until the
+        // writes of its token are in
+        if let Some(lsn_token) = &options.lsn_token {
+            Self::wait_for_lsn_token(&timelines, lsn_token, &ctx).await?;
+        }
+
         // switch client to COPYBOTH
         pgb.write_message_noflush(&BeMessage::CopyBothResponse)?;
         pgb.flush().await?;
@@ -466,8 +608,12 @@ impl PageServerHandler {
 
         // Remotexact
         let (main_timeline, main_metrics) =
-            get_timeline_and_metrics_by_region_id(&timelines, &metrics, RegionId(0)).unwrap();
+            get_timeline_and_metrics_by_region_id(&timelines, &metrics, main_region).unwrap();
 
+        // Reused for the headers of all the responses; the pages are sent without
+        // copying them
+        let mut response_header = BytesMut::new();
+        let mut readahead = Readahead::new(self.conf.page_readahead_blocks, tenant.tasks.clone());
         loop {
             let msg = tokio::select! {
                 biased;
@@ -500,6 +646,28 @@ impl PageServerHandler {
             }
 
             let neon_fe_msg = PagestreamFeMessage::parse(&mut copy_data_bytes.reader())?;
+            // The connection to a single timeline serves that timeline, whatever region
+            // the requests name
+            let region = match timeline_id {
+                Some(_) => main_region,
+                None => neon_fe_msg.region(),
+            };
+            let attached_compute = attached_computes.get(&region);
+            let latest = neon_fe_msg.latest();
+
+            if tenant.pagestream_throttle.try_acquire(1).is_err() {
+                PAGESTREAM_THROTTLED_REQUESTS.inc();
+                tokio::select! {
+                    biased;
+
+                    _ = task_mgr::shutdown_watcher() => {
+                        info!("shutdown request received in page handler");
+                        break;
+                    }
+
+                    _ = tenant.pagestream_throttle.acquire(1) => {}
+                }
+            }
 
             // TODO: We could create a new per-request context here, with unique ID.
             // Currently we use the same per-timeline context for all requests
@@ -514,11 +682,10 @@ impl PageServerHandler {
             // the data added to the relation prior to the move.
             let response = match neon_fe_msg {
                 PagestreamFeMessage::Exists(mut req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
                             let timer = metrics.get_rel_exists.start_timer();
-                            match self
-                                .handle_get_rel_exists_request(timeline.as_ref(), &req, &ctx)
+                            match Self::handle_get_rel_exists_request(timeline.as_ref(), &req, &ctx)
                                 .await
                             {
                                 res @ Ok(_) => res,
@@ -528,7 +695,7 @@ impl PageServerHandler {
                                     let _timer = main_metrics.get_rel_exists.start_timer();
                                     req.latest = true;
                                     req.lsn = Lsn(0);
-                                    self.handle_get_rel_exists_request(&main_timeline, &req, &ctx)
+                                    Self::handle_get_rel_exists_request(&main_timeline, &req, &ctx)
                                         .await
                                 }
                             }
@@ -537,10 +704,10 @@ impl PageServerHandler {
                     }
                 }
                 PagestreamFeMessage::Nblocks(mut req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
                             let timer = metrics.get_rel_size.start_timer();
-                            match self.handle_get_nblocks_request(&timeline, &req, &ctx).await {
+                            match Self::handle_get_nblocks_request(&timeline, &req, &ctx).await {
                                 res @ Ok(_) => res,
                                 Err(_) => {
                                     timer.stop_and_record();
@@ -548,7 +715,7 @@ impl PageServerHandler {
                                     let _timer = main_metrics.get_rel_size.start_timer();
                                     req.latest = true;
                                     req.lsn = Lsn(0);
-                                    self.handle_get_nblocks_request(&main_timeline, &req, &ctx)
+                                    Self::handle_get_nblocks_request(&main_timeline, &req, &ctx)
                                         .await
                                 }
                             }
@@ -557,68 +724,96 @@ impl PageServerHandler {
                     }
                 }
                 PagestreamFeMessage::GetPage(mut req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
+                            timeline.usage_counters.record_read();
                             let timer = metrics.get_page_at_lsn.start_timer();
-                            match self
-                                .handle_get_page_at_lsn_request(&timeline, &req, &ctx)
-                                .await
-                            {
-                                res @ Ok(_) => res,
-                                Err(_) => {
-                                    timer.stop_and_record();
-                                    // Start a new timer for the main timeline
-                                    let _timer = main_metrics.get_page_at_lsn.start_timer();
-                                    req.latest = true;
-                                    req.lsn = Lsn(0);
-                                    self.handle_get_page_at_lsn_request(&main_timeline, &req, &ctx)
-                                        .await
-                                }
+                            let (timeline, res) =
+                                match Self::handle_get_page_at_lsn_request(&timeline, &req, &ctx)
+                                    .await
+                                {
+                                    res @ Ok(_) => (timeline, res),
+                                    Err(_) => {
+                                        timer.stop_and_record();
+                                        // Start a new timer for the main timeline
+                                        let _timer = main_metrics.get_page_at_lsn.start_timer();
+                                        req.latest = true;
+                                        req.lsn = Lsn(0);
+                                        let res = Self::handle_get_page_at_lsn_request(
+                                            &main_timeline,
+                                            &req,
+                                            &ctx,
+                                        )
+                                        .await;
+                                        (Arc::clone(&main_timeline), res)
+                                    }
+                                };
+                            if let Ok(PagestreamBeMessage::GetPage(response)) = &res {
+                                readahead.on_get_page(
+                                    &timeline,
+                                    req.rel,
+                                    req.blkno,
+                                    response.lsn,
+                                    req.latest,
+                                    &ctx,
+                                );
                             }
+                            res
                         }
                         Err(e) => Err(e),
                     }
                 }
                 PagestreamFeMessage::DbSize(req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
                             let _timer = metrics.get_db_size.start_timer();
-                            self.handle_db_size_request(&timeline, &req, &ctx).await
+                            Self::handle_db_size_request(&timeline, &req, &ctx).await
                         }
                         Err(e) => Err(e),
                     }
                 }
                 PagestreamFeMessage::GetSlruPage(req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
                             let _timer = metrics.get_slru_page.start_timer();
-                            self.handle_get_slru_page_at_lsn_request(&timeline, &req, &ctx)
-                                .await
+                            Self::handle_get_slru_page_at_lsn_request(&timeline, &req, &ctx).await
                         }
                         Err(e) => Err(e),
                     }
                 }
-                PagestreamFeMessage::GetLatestLsn(req) => {
-                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, req.region) {
+                PagestreamFeMessage::GetLatestLsn(_) => {
+                    match get_timeline_and_metrics_by_region_id(&timelines, &metrics, region) {
                         Ok((timeline, metrics)) => {
                             let _timer = metrics.get_latest_lsn.start_timer();
-                            self.handle_get_latest_lsn_request(&timeline, &ctx).await
+                            Self::handle_get_latest_lsn_request(&timeline, &ctx).await
                         }
                         Err(e) => Err(e),
                     }
                 }
             };
 
+            if let (Some(compute), Some(lsn)) = (
+                attached_compute,
+                response.as_ref().ok().and_then(PagestreamBeMessage::lsn),
+            ) {
+                compute.record_read(lsn, latest);
+            }
+
             let response = response.unwrap_or_else(|e| {
                 // print the all details to the log with {:#}, but for the client the
                 // error message is enough
                 error!("error reading relation or page version: {:?}", e);
                 PagestreamBeMessage::Error(PagestreamErrorResponse {
+                    code: options.error_codes.then(|| pagestream_error_code(&e)),
                     message: e.to_string(),
                 })
             });
 
-            pgb.write_message_noflush(&BeMessage::CopyData(&response.serialize()))?;
+            response_header.clear();
+            match response.serialize_split(&mut response_header) {
+                Some(page) => pgb.write_copy_data_noflush(&response_header, page.clone()),
+                None => pgb.write_message_noflush(&BeMessage::CopyData(&response_header))?,
+            };
             pgb.flush().await?;
         }
         Ok(())
@@ -746,6 +941,86 @@ impl PageServerHandler {
         Ok(())
     }
 
+    /// Ingests the WAL a safekeeper sends as CopyData, starting at `start_lsn`,
+    /// until CopyDone. Used by the safekeepers while the broker is unreachable
+    /// and the WAL receiver doesn't know where to stream WAL from.
+    #[instrument(skip_all, fields(%start_lsn))]
+    async fn handle_push_wal<IO>(
+        &self,
+        pgb: &mut PostgresBackend<IO>,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        start_lsn: Lsn,
+        ctx: RequestContext,
+    ) -> Result<(), QueryError>
+    where
+        IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
+    {
+        debug_assert_current_span_has_tenant_and_timeline_id();
+        task_mgr::associate_with(Some(tenant_id), Some(timeline_id));
+
+        let timeline = get_active_tenant_timeline(tenant_id, timeline_id, &ctx).await?;
+        let Ok(_ingest_guard) = timeline.wal_ingest_lock.try_lock() else {
+            return Err(QueryError::Other(anyhow::anyhow!(
+                "timeline is already receiving WAL"
+            )));
+        };
+
+        // Same as the starting point of the WAL receiver: the end of the last
+        // record, skipping the padding and the page header.
+        let last_record_lsn = timeline.get_last_record_lsn();
+        let expected_start_lsn = normalize_lsn(
+            last_record_lsn + last_record_lsn.calc_padding(8u32),
+            WAL_SEGMENT_SIZE,
+        );
+        if start_lsn != expected_start_lsn {
+            return Err(QueryError::Other(anyhow::anyhow!(
+                "cannot ingest WAL from {start_lsn}, last record of the timeline ends at {last_record_lsn}"
+            )));
+        }
+
+        info!("receiving pushed WAL");
+        pgb.write_message_noflush(&BeMessage::CopyInResponse)?;
+        pgb.flush().await?;
+
+        let mut waldecoder = WalStreamDecoder::new(start_lsn, timeline.pg_version);
+        let mut walingest = WalIngest::new(timeline.as_ref(), start_lsn, &ctx).await?;
+        let mut decoded = DecodedWALRecord::default();
+        let mut last_rec_lsn = last_record_lsn;
+        let mut copyin = pin!(copyin_stream(pgb));
+        while let Some(data) = copyin.next().await {
+            let data = data?;
+            timeline.usage_counters.record_ingest(data.len() as u64);
+            waldecoder.feed_bytes(&data);
+
+            let mut modification = timeline.begin_modification(last_rec_lsn);
+            let mut uncommitted_records = 0;
+            while let Some((lsn, recdata)) = waldecoder.poll_decode().map_err(anyhow::Error::new)? {
+                if !lsn.is_aligned() {
+                    return Err(QueryError::Other(anyhow::anyhow!("LSN not aligned")));
+                }
+                walingest
+                    .ingest_record(recdata, lsn, &mut modification, &mut decoded, &ctx)
+                    .await
+                    .with_context(|| format!("could not ingest record at {lsn}"))?;
+                last_rec_lsn = lsn;
+
+                uncommitted_records += 1;
+                if uncommitted_records >= self.conf.ingest_batch_size {
+                    modification.commit().await?;
+                    uncommitted_records = 0;
+                }
+            }
+            if uncommitted_records > 0 {
+                modification.commit().await?;
+            }
+
+            timeline.check_checkpoint_distance().await?;
+        }
+        info!("pushed WAL received up to {last_rec_lsn}");
+        Ok(())
+    }
+
     /// Helper function to handle the LSN from client request.
     ///
     /// Each GetPage (and Exists and Nblocks) request includes information about
@@ -807,9 +1082,8 @@ impl PageServerHandler {
         Ok(lsn)
     }
 
-    #[instrument(skip(self, timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, req_lsn = %req.lsn))]
+    #[instrument(skip(timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, req_lsn = %req.lsn))]
     async fn handle_get_rel_exists_request(
-        &self,
         timeline: &Timeline,
         req: &PagestreamExistsRequest,
         ctx: &RequestContext,
@@ -829,9 +1103,8 @@ impl PageServerHandler {
         }))
     }
 
-    #[instrument(skip(self, timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, req_lsn = %req.lsn))]
+    #[instrument(skip(timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, req_lsn = %req.lsn))]
     async fn handle_get_nblocks_request(
-        &self,
         timeline: &Timeline,
         req: &PagestreamNblocksRequest,
         ctx: &RequestContext,
@@ -851,9 +1124,8 @@ impl PageServerHandler {
         }))
     }
 
-    #[instrument(skip(self, timeline, req, ctx), fields(region = %timeline.region_id, dbnode = %req.dbnode, req_lsn = %req.lsn))]
+    #[instrument(skip(timeline, req, ctx), fields(region = %timeline.region_id, dbnode = %req.dbnode, req_lsn = %req.lsn))]
     async fn handle_db_size_request(
-        &self,
         timeline: &Timeline,
         req: &PagestreamDbSizeRequest,
         ctx: &RequestContext,
@@ -880,29 +1152,34 @@ impl PageServerHandler {
         }))
     }
 
-    #[instrument(skip(self, timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn))]
+    #[instrument(skip(timeline, req, ctx), fields(region = %timeline.region_id, rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn))]
     async fn handle_get_page_at_lsn_request(
-        &self,
         timeline: &Timeline,
         req: &PagestreamGetPageRequest,
         ctx: &RequestContext,
     ) -> anyhow::Result<PagestreamBeMessage> {
+        anyhow::ensure!(
+            timeline.ingests_relation_data(),
+            "the pages of region {} are not ingested by this pageserver",
+            timeline.region_id
+        );
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
         let lsn =
             Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
                 .await?;
-        /*
-        // Add a 1s delay to some requests. The delay helps the requests to
-        // hit the race condition from github issue #1047 more easily.
-        use rand::Rng;
-        if rand::thread_rng().gen::<u8>() < 5 {
-            std::thread::sleep(std::time::Duration::from_millis(1000));
-        }
-        */
+        // Waiting for the LSN doesn't occupy an executor thread, so only the
+        // reconstruction of the page counts against the limit
+        let _permit = fair_limiter::get()
+            .acquire(timeline.tenant_id, timeline.get_get_page_weight())
+            .await;
+        // A delay on some requests, e.g. "2%return(sleep=1000)", helps them hit
+        // the race condition from github issue #1047 more easily.
+        utils::failpoint_async!("page-service-get-page", |msg| anyhow::bail!(msg));
 
         let page = timeline
             .get_rel_page_at_lsn(req.rel, req.blkno, Version::Lsn(lsn), req.latest, ctx)
             .await?;
+        timeline.record_rel_access(req.rel);
 
         Ok(PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
             lsn,
@@ -910,10 +1187,9 @@ impl PageServerHandler {
         }))
     }
 
-    #[instrument(skip(self, timeline, req, ctx), fields(region = %timeline.region_id, slru_kind = %req.kind.to_str(), segno = %req.segno,
+    #[instrument(skip(timeline, req, ctx), fields(region = %timeline.region_id, slru_kind = %req.kind.to_str(), segno = %req.segno,
                  check_blkno = %req.blkno, req_lsn = %req.lsn, check_exists_only = %req.check_exists_only))]
     async fn handle_get_slru_page_at_lsn_request(
-        &self,
         timeline: &Timeline,
         req: &PagestreamGetSlruPageRequest,
         ctx: &RequestContext,
@@ -940,12 +1216,18 @@ impl PageServerHandler {
             if req.check_exists_only {
                 page = page_res.and(Ok(Bytes::default())).ok();
             } else {
-                page = Some(page_res.map(|mut buf| {
-                    // Neon appends an 8-byte timestamp to the page so need to ensure that the
-                    // page has postgres page size
-                    buf.truncate(BLCKSZ as usize);
-                    buf
-                })?);
+                let mut buf = page_res?;
+                // Neon appends an 8-byte timestamp to the page so need to ensure that the
+                // page has postgres page size
+                buf.truncate(BLCKSZ as usize);
+                // Remotexact: the outcomes of the transactions spanning regions may not
+                // be in the WAL of the region yet
+                if let (SlruKind::Clog, Some(resolver)) = (req.kind, xact_resolver::get()) {
+                    buf = resolver
+                        .resolve_clog_page(timeline, req.segno, req.blkno, lsn, buf, ctx)
+                        .await;
+                }
+                page = Some(buf);
             }
         }
 
@@ -958,9 +1240,8 @@ impl PageServerHandler {
         ))
     }
 
-    #[instrument(skip(self, timeline, ctx), fields(region = %timeline.region_id))]
+    #[instrument(skip(timeline, ctx), fields(region = %timeline.region_id))]
     async fn handle_get_latest_lsn_request(
-        &self,
         timeline: &Timeline,
         ctx: &RequestContext,
     ) -> anyhow::Result<PagestreamBeMessage> {
@@ -1013,6 +1294,7 @@ impl PageServerHandler {
 
         // Send a tarball of the latest layer on the timeline. Compress if not
         // fullbackup. TODO Compress in that case too (tests need to be updated)
+        let mut cached = false;
         if full_backup {
             let mut writer = pgb.copyout_writer();
             basebackup::send_basebackup_tarball(
@@ -1026,37 +1308,41 @@ impl PageServerHandler {
             .await?;
         } else {
             let mut writer = pgb.copyout_writer();
-            if gzip {
-                let mut encoder = GzipEncoder::with_quality(
-                    writer,
-                    // NOTE using fast compression because it's on the critical path
-                    //      for compute startup. For an empty database, we get
-                    //      <100KB with this method. The Level::Best compression method
-                    //      gives us <20KB, but maybe we should add basebackup caching
-                    //      on compute shutdown first.
-                    async_compression::Level::Fastest,
-                );
-                basebackup::send_basebackup_tarball(
-                    &mut encoder,
-                    &timeline,
+            // Only the backups at a given LSN can be cached, not the ones at the
+            // end of the timeline
+            let cache_key = lsn
+                .filter(|_| timeline.basebackup_cache.is_enabled())
+                .map(|lsn| BasebackupCacheKey {
                     lsn,
                     prev_lsn,
-                    full_backup,
-                    &ctx,
-                )
-                .await?;
-                // shutdown the encoder to ensure the gzip footer is written
-                encoder.shutdown().await?;
+                    gzip,
+                });
+            if let Some(cache_key) = cache_key {
+                match timeline.basebackup_cache.get(&cache_key) {
+                    Some(tarball) => {
+                        BASEBACKUP_CACHE_LOOKUPS.with_label_values(&["hit"]).inc();
+                        cached = true;
+                        writer.write_all(&tarball).await?;
+                    }
+                    None => {
+                        BASEBACKUP_CACHE_LOOKUPS.with_label_values(&["miss"]).inc();
+                        let mut caching_writer = CachingWriter::new(&mut writer);
+                        send_basebackup_tarball(
+                            &mut caching_writer,
+                            &timeline,
+                            lsn,
+                            prev_lsn,
+                            gzip,
+                            &ctx,
+                        )
+                        .await?;
+                        if let Some(tarball) = caching_writer.into_tarball() {
+                            timeline.basebackup_cache.insert(cache_key, tarball);
+                        }
+                    }
+                }
             } else {
-                basebackup::send_basebackup_tarball(
-                    &mut writer,
-                    &timeline,
-                    lsn,
-                    prev_lsn,
-                    full_backup,
-                    &ctx,
-                )
-                .await?;
+                send_basebackup_tarball(&mut writer, &timeline, lsn, prev_lsn, gzip, &ctx).await?;
             }
         }
 
@@ -1071,15 +1357,22 @@ impl PageServerHandler {
         info!(
             lsn_await_millis = lsn_awaited_after.as_millis(),
             basebackup_millis = basebackup_after.as_millis(),
+            cached,
             "basebackup complete"
         );
 
         Ok(())
     }
 
-    // when accessing management api supply None as an argument
-    // when using to authorize tenant pass corresponding tenant id
-    fn check_permission(&self, tenant_id: Option<TenantId>) -> anyhow::Result<()> {
+    /// Checks that the token the connection authenticated with allows `command` on
+    /// `tenant_id`: a tenant token only gives access to its own tenant, and only to
+    /// the commands which read it, which is all its computes need.
+    fn authorize(
+        &self,
+        command: &'static str,
+        tenant_id: TenantId,
+        access: CommandAccess,
+    ) -> Result<(), QueryError> {
         if self.auth.is_none() {
             // auth is set to Trust, nothing to check so just return ok
             return Ok(());
@@ -1091,7 +1384,23 @@ impl PageServerHandler {
             .claims
             .as_ref()
             .expect("claims presence already checked");
-        check_permission(claims, tenant_id)
+        let result = check_permission(claims, Some(tenant_id)).and_then(|()| match access {
+            CommandAccess::Read => Ok(()),
+            CommandAccess::Write if claims.scope == Scope::PageServerApi => Ok(()),
+            CommandAccess::Write => {
+                anyhow::bail!("{command} requires a pageserver token. Permission denied")
+            }
+        });
+        result.map_err(|e| {
+            PAGE_SERVICE_UNAUTHORIZED_COMMANDS
+                .with_label_values(&[command])
+                .inc();
+            warn!(
+                "refusing {command} on tenant {tenant_id} to a {:?} token of tenant {:?}: {e}",
+                claims.scope, claims.tenant_id
+            );
+            QueryError::Unauthorized(e.to_string().into())
+        })
     }
 }
 
@@ -1100,7 +1409,7 @@ impl<IO> postgres_backend::Handler<IO> for PageServerHandler
 where
     IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
 {
-    fn check_auth_jwt(
+    async fn check_auth_jwt(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         jwt_response: &[u8],
@@ -1128,7 +1437,7 @@ where
         Ok(())
     }
 
-    fn startup(
+    async fn startup(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         _sm: &FeStartupPacket,
@@ -1148,7 +1457,7 @@ where
         if query_string.starts_with("pagestream ") {
             let (_, params_raw) = query_string.split_at("pagestream ".len());
             let params = params_raw.split(' ').collect::<Vec<_>>();
-            if params.len() != 2 {
+            if !(2..=5).contains(&params.len()) {
                 return Err(QueryError::Other(anyhow::anyhow!(
                     "invalid param number for pagestream command"
                 )));
@@ -1157,19 +1466,20 @@ where
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
                 .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let options = parse_pagestream_options(&params[2..])?;
 
             tracing::Span::current()
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("pagestream", tenant_id, CommandAccess::Read)?;
 
-            self.handle_pagerequests(pgb, tenant_id, Some(timeline_id), ctx)
+            self.handle_pagerequests(pgb, tenant_id, Some(timeline_id), options, ctx)
                 .await?;
         } else if query_string.starts_with("multipagestream ") {
             let (_, params_raw) = query_string.split_at("multipagestream ".len());
             let params = params_raw.split(' ').collect::<Vec<_>>();
-            if params.len() != 1 {
+            if !(1..=4).contains(&params.len()) {
                 return Err(QueryError::Other(anyhow::anyhow!(
                     "invalid param number for multipagestream command"
                 )));
@@ -1177,10 +1487,12 @@ where
 
             let tenant_id = TenantId::from_str(params[0])
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let options = parse_pagestream_options(&params[1..])?;
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("multipagestream", tenant_id, CommandAccess::Read)?;
 
-            self.handle_pagerequests(pgb, tenant_id, None, ctx).await?;
+            self.handle_pagerequests(pgb, tenant_id, None, options, ctx)
+                .await?;
         } else if query_string.starts_with("basebackup ") {
             let (_, params_raw) = query_string.split_at("basebackup ".len());
             let params = params_raw.split_whitespace().collect::<Vec<_>>();
@@ -1200,7 +1512,7 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("basebackup", tenant_id, CommandAccess::Read)?;
 
             let lsn = if params.len() >= 3 {
                 Some(
@@ -1264,7 +1576,7 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("get_last_record_rlsn", tenant_id, CommandAccess::Read)?;
             let timeline = get_active_tenant_timeline(tenant_id, timeline_id, &ctx).await?;
 
             let end_of_timeline = timeline.get_last_record_rlsn();
@@ -1279,6 +1591,48 @@ where
             ]))?
             .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
         }
+        // read the catalogs and the hottest relations ahead of the queries of a starting compute
+        else if query_string.starts_with("prewarm ") {
+            let (_, params_raw) = query_string.split_at("prewarm ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() < 2 || params.len() > 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for prewarm command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let max_relations = match params.get(2) {
+                Some(max_relations) => max_relations.parse().with_context(|| {
+                    format!("Failed to parse max relations from {max_relations}")
+                })?,
+                None => DEFAULT_PREWARM_MAX_RELATIONS,
+            };
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.authorize("prewarm", tenant_id, CommandAccess::Read)?;
+            let timeline = get_active_tenant_timeline(tenant_id, timeline_id, &ctx).await?;
+
+            // the pages are read in the background, not to hold the connection meanwhile
+            let started = if timeline.spawn_prewarm(max_relations) {
+                "t"
+            } else {
+                "f"
+            };
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+                b"started",
+            )]))?
+            .write_message_noflush(&BeMessage::DataRow(&[Some(started.as_bytes())]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        }
         // same as basebackup, but result includes relational data as well
         else if query_string.starts_with("fullbackup ") {
             let (_, params_raw) = query_string.split_at("fullbackup ".len());
@@ -1317,7 +1671,7 @@ where
                 None
             };
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("fullbackup", tenant_id, CommandAccess::Read)?;
 
             // Check that the timeline exists
             self.handle_basebackup_request(
@@ -1366,7 +1720,7 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("import basebackup", tenant_id, CommandAccess::Write)?;
 
             match self
                 .handle_import_basebackup(
@@ -1414,7 +1768,7 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("import wal", tenant_id, CommandAccess::Write)?;
 
             match self
                 .handle_import_wal(pgb, tenant_id, timeline_id, start_lsn, end_lsn, ctx)
@@ -1429,6 +1783,43 @@ where
                     ))?
                 }
             };
+        } else if query_string.starts_with("push_wal ") {
+            // Receive WAL from a safekeeper instead of streaming it from one,
+            // while the broker is unreachable. The start LSN must be where the
+            // WAL receiver would start streaming, see `get_last_record_rlsn`.
+            let (_, params_raw) = query_string.split_at("push_wal ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() != 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for push_wal command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let start_lsn = Lsn::from_str(params[2])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.authorize("push_wal", tenant_id, CommandAccess::Write)?;
+
+            match self
+                .handle_push_wal(pgb, tenant_id, timeline_id, start_lsn, ctx)
+                .await
+            {
+                Ok(()) => pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?,
+                Err(e) => {
+                    error!("error ingesting WAL pushed from {start_lsn}: {e:?}");
+                    pgb.write_message_noflush(&BeMessage::ErrorResponse(
+                        &e.to_string(),
+                        Some(e.pg_error_code()),
+                    ))?
+                }
+            };
         } else if query_string.to_ascii_lowercase().starts_with("set ") {
             // important because psycopg2 executes "SET datestyle TO 'ISO'"
             // on connect
@@ -1447,7 +1838,7 @@ where
 
             tracing::Span::current().record("tenant_id", field::display(tenant_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.authorize("show", tenant_id, CommandAccess::Read)?;
 
             let tenant = get_active_tenant_with_timeout(tenant_id, &ctx).await?;
             pgb.write_message_noflush(&BeMessage::RowDescription(&[
@@ -1522,6 +1913,39 @@ impl From<GetActiveTenantError> for QueryError {
     }
 }
 
+/// Writes the tarball of a basebackup without relational data to `write`,
+/// gzip-compressed if `gzip` is set.
+async fn send_basebackup_tarball<W>(
+    write: &mut W,
+    timeline: &Arc<Timeline>,
+    lsn: Option<Lsn>,
+    prev_lsn: Option<Lsn>,
+    gzip: bool,
+    ctx: &RequestContext,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    if gzip {
+        let mut encoder = GzipEncoder::with_quality(
+            write,
+            // NOTE using fast compression because it's on the critical path
+            //      for compute startup. For an empty database, we get
+            //      <100KB with this method. The Level::Best compression method
+            //      gives us <20KB, but the tarballs are cached instead, see
+            //      basebackup_cache_ttl.
+            async_compression::Level::Fastest,
+        );
+        basebackup::send_basebackup_tarball(&mut encoder, timeline, lsn, prev_lsn, false, ctx)
+            .await?;
+        // shutdown the encoder to ensure the gzip footer is written
+        encoder.shutdown().await?;
+    } else {
+        basebackup::send_basebackup_tarball(write, timeline, lsn, prev_lsn, false, ctx).await?;
+    }
+    Ok(())
+}
+
 /// Get active tenant.
 ///
 /// If the tenant is Loading, waits for it to become Active, for up to 30 s. That
@@ -1616,3 +2040,55 @@ fn get_timeline_and_metrics_by_region_id<'a>(
                 .map(|metrics| (timeline, metrics))
         })
 }
+
+/// The options of the `pagestream` commands, after the ids.
+#[derive(Debug)]
+struct PagestreamOptions {
+    /// `readonly` for a hot standby, see [`crate::tenant::timeline::computes`].
+    mode: ComputeMode,
+    /// `lsn_token=<token>`, to wait for the writes of another compute, see
+    /// [`pageserver_api::lsn_token`].
+    lsn_token: Option<LsnToken>,
+    /// `error_codes`, for the client to get the [`PagestreamErrorCode`] of the errors.
+    error_codes: bool,
+}
+
+fn parse_pagestream_options(params: &[&str]) -> anyhow::Result<PagestreamOptions> {
+    let mut options = PagestreamOptions {
+        mode: ComputeMode::ReadWrite,
+        lsn_token: None,
+        error_codes: false,
+    };
+    for param in params {
+        if *param == "readonly" {
+            options.mode = ComputeMode::ReadOnly;
+        } else if let Some(token) = param.strip_prefix("lsn_token=") {
+            options.lsn_token = Some(token.parse()?);
+        } else if *param == "error_codes" {
+            options.error_codes = true;
+        } else {
+            anyhow::bail!(
+                "invalid pagestream option '{param}', \
+                 expected 'readonly', 'lsn_token=<token>' or 'error_codes'"
+            );
+        }
+    }
+    Ok(options)
+}
+
+/// The [`PagestreamErrorCode`] of the error of a pagestream request.
+fn pagestream_error_code(e: &anyhow::Error) -> PagestreamErrorCode {
+    if let Some(e) = e.downcast_ref::<WaitLsnError>() {
+        return match e {
+            WaitLsnError::Timeout(_) => PagestreamErrorCode::WaitLsnTimeout,
+            WaitLsnError::Shutdown(_) => PagestreamErrorCode::ShuttingDown,
+            WaitLsnError::BadState(_) => PagestreamErrorCode::Other,
+        };
+    }
+    match e.downcast_ref::<PageReconstructError>() {
+        Some(PageReconstructError::Cancelled | PageReconstructError::AncestorStopping(_)) => {
+            PagestreamErrorCode::ShuttingDown
+        }
+        _ => PagestreamErrorCode::Other,
+    }
+}
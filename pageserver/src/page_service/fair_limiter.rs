@@ -0,0 +1,302 @@
+//! Limits the number of GetPage requests processed at once across the whole
+//! pageserver, see `get_page_concurrency_limit`.
+//!
+//! The executor threads serving the page service are shared by all the tenants, so
+//! a tenant with many outstanding requests could otherwise keep them busy, and delay
+//! the requests of everybody else. When the limit is reached, the waiting requests
+//! are admitted in weighted fair queueing order: each request gets a virtual finish
+//! tag, which advances by the inverse of its tenant's `get_page_weight` for every
+//! request of that tenant, and the waiting request with the smallest tag is admitted
+//! first. A tenant that was idle starts from the current virtual time, so it gets no
+//! credit for the time it didn't use its share.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::OnceCell;
+use tokio::sync::oneshot;
+use utils::id::TenantId;
+
+use crate::metrics::GET_PAGE_LIMITER_WAITING;
+
+/// The virtual time a request of a tenant with weight 1 takes.
+const REQUEST_COST: u64 = 1 << 20;
+
+const MIN_PRUNE_AT: usize = 1024;
+
+static LIMITER: OnceCell<Arc<FairLimiter>> = OnceCell::new();
+
+/// Initializes the GetPage limiter. Must be called once at pageserver startup.
+pub fn init(limit: NonZeroUsize) {
+    if LIMITER.set(Arc::new(FairLimiter::new(limit))).is_err() {
+        panic!("GetPage limiter already initialized");
+    }
+}
+
+/// Gets the GetPage limiter.
+pub fn get() -> &'static Arc<FairLimiter> {
+    if cfg!(test) {
+        LIMITER.get_or_init(|| Arc::new(FairLimiter::new(NonZeroUsize::new(64).unwrap())))
+    } else {
+        LIMITER.get().expect("GetPage limiter not initialized")
+    }
+}
+
+pub struct FairLimiter {
+    state: Mutex<State>,
+}
+
+struct State {
+    limit: usize,
+    in_flight: usize,
+    /// The largest finish tag of the admitted requests.
+    virtual_time: u64,
+    /// The finish tag of the last request of each tenant with requests newer than
+    /// `virtual_time`. The others start from `virtual_time` anyway.
+    tenant_finish_tags: HashMap<TenantId, u64>,
+    /// Size of `tenant_finish_tags` at which the stale tags are removed.
+    prune_at: usize,
+    waiting: BinaryHeap<Waiter>,
+    /// Orders the waiters with the same finish tag by arrival.
+    next_seq: u64,
+}
+
+struct Waiter {
+    finish_tag: u64,
+    seq: u64,
+    admit: oneshot::Sender<Permit>,
+}
+
+/// Held while processing a request. Dropping it admits the next waiting request.
+pub struct Permit {
+    limiter: Option<Arc<FairLimiter>>,
+}
+
+impl FairLimiter {
+    pub fn new(limit: NonZeroUsize) -> Self {
+        FairLimiter {
+            state: Mutex::new(State {
+                limit: limit.get(),
+                in_flight: 0,
+                virtual_time: 0,
+                tenant_finish_tags: HashMap::new(),
+                prune_at: MIN_PRUNE_AT,
+                waiting: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    /// Waits for the turn of a request of `tenant_id`.
+    ///
+    /// Cancel safe: a request dropped while waiting gives up its turn.
+    pub async fn acquire(self: &Arc<Self>, tenant_id: TenantId, weight: NonZeroU32) -> Permit {
+        let admitted = {
+            let mut state = self.state.lock().unwrap();
+            let finish_tag = state.next_finish_tag(tenant_id, weight);
+            if state.in_flight < state.limit && state.waiting.is_empty() {
+                state.in_flight += 1;
+                state.virtual_time = state.virtual_time.max(finish_tag);
+                return Permit {
+                    limiter: Some(Arc::clone(self)),
+                };
+            }
+            let (admit, admitted) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter {
+                finish_tag,
+                seq,
+                admit,
+            });
+            GET_PAGE_LIMITER_WAITING.inc();
+            admitted
+        };
+        // The sender is only dropped after sending, the limiter is never dropped
+        // with waiters in it
+        admitted.await.expect("GetPage limiter dropped a waiter")
+    }
+
+    /// Changes the number of requests processed at once. A decrease takes effect as
+    /// the requests in progress finish.
+    pub fn set_limit(self: &Arc<Self>, limit: NonZeroUsize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit.get();
+        while state.in_flight < state.limit && self.admit_next(&mut state) {
+            state.in_flight += 1;
+        }
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight > state.limit || !self.admit_next(&mut state) {
+            state.in_flight -= 1;
+        }
+        if state.in_flight == 0 && state.waiting.is_empty() {
+            state.tenant_finish_tags.clear();
+        }
+    }
+
+    /// Hands a permit to the waiter with the smallest finish tag, skipping the ones
+    /// that stopped waiting. Returns false if nobody is waiting.
+    fn admit_next(self: &Arc<Self>, state: &mut State) -> bool {
+        while let Some(waiter) = state.waiting.pop() {
+            GET_PAGE_LIMITER_WAITING.dec();
+            let permit = Permit {
+                limiter: Some(Arc::clone(self)),
+            };
+            match waiter.admit.send(permit) {
+                Ok(()) => {
+                    state.virtual_time = state.virtual_time.max(waiter.finish_tag);
+                    return true;
+                }
+                Err(mut permit) => {
+                    // not admitted, so there's nothing to release
+                    permit.limiter = None;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl State {
+    fn next_finish_tag(&mut self, tenant_id: TenantId, weight: NonZeroU32) -> u64 {
+        let cost = REQUEST_COST / weight.get() as u64;
+        let virtual_time = self.virtual_time;
+        if self.tenant_finish_tags.len() >= self.prune_at {
+            self.tenant_finish_tags
+                .retain(|_, finish_tag| *finish_tag > virtual_time);
+            self.prune_at = MIN_PRUNE_AT.max(self.tenant_finish_tags.len() * 2);
+        }
+        let last = self.tenant_finish_tags.entry(tenant_id).or_insert(0);
+        *last = (*last).max(virtual_time) + cost.max(1);
+        *last
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release();
+        }
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap, the smallest tag goes first
+        (other.finish_tag, other.seq).cmp(&(self.finish_tag, self.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn weight(w: u32) -> NonZeroU32 {
+        NonZeroU32::new(w).unwrap()
+    }
+
+    #[tokio::test]
+    async fn limit() {
+        let limiter = Arc::new(FairLimiter::new(NonZeroUsize::new(2).unwrap()));
+        let tenant = TenantId::generate();
+        let first = limiter.acquire(tenant, weight(1)).await;
+        let _second = limiter.acquire(tenant, weight(1)).await;
+
+        let mut third = Box::pin(limiter.acquire(tenant, weight(1)));
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut third)
+            .await
+            .is_err());
+        drop(first);
+        let _third = third.await;
+
+        limiter.set_limit(NonZeroUsize::new(3).unwrap());
+        let _fourth = limiter.acquire(tenant, weight(1)).await;
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter() {
+        let limiter = Arc::new(FairLimiter::new(NonZeroUsize::new(1).unwrap()));
+        let tenant = TenantId::generate();
+        let first = limiter.acquire(tenant, weight(1)).await;
+        let waiting = limiter.acquire(tenant, weight(1));
+        {
+            let mut waiting = Box::pin(waiting);
+            assert!(
+                tokio::time::timeout(Duration::from_millis(10), &mut waiting)
+                    .await
+                    .is_err()
+            );
+        }
+        drop(first);
+        // the cancelled waiter didn't take the permit with it
+        let _second = limiter.acquire(tenant, weight(1)).await;
+    }
+
+    /// A tenant with a deep queue of requests doesn't delay the requests of another
+    /// tenant behind all of its own.
+    #[tokio::test]
+    async fn fair_order() {
+        let limiter = Arc::new(FairLimiter::new(NonZeroUsize::new(1).unwrap()));
+        let busy = TenantId::generate();
+        let quiet = TenantId::generate();
+        let heavy = TenantId::generate();
+
+        let held = limiter.acquire(busy, weight(1)).await;
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        let mut spawn = |tenant: TenantId, w: u32, name: &'static str| {
+            let limiter = Arc::clone(&limiter);
+            let order_tx = order_tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = limiter.acquire(tenant, weight(w)).await;
+                order_tx.send(name).unwrap();
+            }));
+        };
+        for _ in 0..10 {
+            spawn(busy, 1, "busy");
+        }
+        spawn(quiet, 1, "quiet");
+        for _ in 0..4 {
+            spawn(heavy, 4, "heavy");
+        }
+        // let all the tasks queue up
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        drop(order_tx);
+
+        let mut order = Vec::new();
+        while let Some(name) = order_rx.recv().await {
+            order.push(name);
+        }
+        let busy_before = |end: usize| order[..end].iter().filter(|n| **n == "busy").count();
+        let quiet = order.iter().position(|n| *n == "quiet").unwrap();
+        assert!(busy_before(quiet) <= 1, "{order:?}");
+        // with four times the weight, all four requests get in before the second busy one
+        let last_heavy = order.iter().rposition(|n| *n == "heavy").unwrap();
+        assert!(busy_before(last_heavy) <= 1, "{order:?}");
+    }
+}
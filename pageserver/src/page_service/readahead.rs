@@ -0,0 +1,233 @@
+//! Readahead for the sequential scans of the computes, see `page_readahead_blocks`.
+//!
+//! The compute requests the blocks of a relation one at a time, also when it scans
+//! the whole relation, so every block it requests waits for its layers to be read,
+//! or even downloaded. When the GetPage requests of a connection are for consecutive
+//! blocks of a relation, the blocks past the requested one are read in the
+//! background with one vectored read. That downloads the layers they are in, and
+//! leaves the blocks of the layer files in the page cache, as well as the pages that
+//! needed WAL redo in the materialized page cache, for when the compute gets to them.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use pageserver_api::reltag::RelTag;
+use tokio::task::AbortHandle;
+use tracing::{debug, Instrument};
+use utils::id::TimelineId;
+use utils::lsn::Lsn;
+use utils::task_scope::TaskScope;
+
+use super::fair_limiter;
+use crate::context::RequestContext;
+use crate::metrics::PAGE_READAHEAD_BLOCKS;
+use crate::pgdatadir_mapping::{BlockNumber, Version};
+use crate::tenant::Timeline;
+
+/// Number of consecutive blocks requested before the requests are taken for a
+/// sequential scan. Index scans often read a few neighbouring blocks too.
+const SCAN_MIN_BLOCKS: u32 = 4;
+
+/// Readahead state of a page service connection.
+pub struct Readahead {
+    /// Number of blocks read past the requested one, 0 if disabled.
+    distance: u32,
+    /// The last requested block.
+    last: Option<(TimelineId, RelTag, BlockNumber)>,
+    /// Number of consecutive blocks requested, up to the last one.
+    run_length: u32,
+    /// The blocks of the scanned relation before this one have been read ahead.
+    read_ahead_until: BlockNumber,
+    /// The readahead in progress, at most one per connection.
+    task: Option<AbortHandle>,
+    /// The scope of the tenant, the readaheads may outlive the connection, but
+    /// not the tenant.
+    tasks: TaskScope,
+}
+
+impl Readahead {
+    pub fn new(distance: usize, tasks: TaskScope) -> Self {
+        Readahead {
+            distance: u32::try_from(distance).unwrap_or(u32::MAX),
+            last: None,
+            run_length: 0,
+            read_ahead_until: 0,
+            task: None,
+            tasks,
+        }
+    }
+
+    /// Called after a GetPage request was answered with the page at `lsn`. Starts
+    /// reading ahead if the request continues a sequential scan, and the blocks
+    /// read ahead so far are running out.
+    pub fn on_get_page(
+        &mut self,
+        timeline: &Arc<Timeline>,
+        rel: RelTag,
+        blkno: BlockNumber,
+        lsn: Lsn,
+        latest: bool,
+        ctx: &RequestContext,
+    ) {
+        let idle = self.task.as_ref().map_or(true, |task| task.is_finished());
+        let Some(blocks) = self.plan(timeline.timeline_id, rel, blkno, idle) else {
+            return;
+        };
+
+        let timeline = Arc::clone(timeline);
+        let ctx = ctx.attached_child();
+        self.task = Some(self.tasks.spawn("readahead", |cancel| {
+            async move {
+                let read = read_ahead(&timeline, rel, blocks.clone(), lsn, latest, &ctx);
+                let res = tokio::select! {
+                    res = read => res,
+                    _ = cancel.cancelled() => return,
+                };
+                if let Err(e) = res {
+                    // The compute will get the error when it requests the block
+                    debug!("readahead of {rel} blocks {blocks:?} failed: {e:#}");
+                }
+            }
+            .in_current_span()
+        }));
+    }
+
+    /// Returns the blocks to read ahead after a request for `blkno` of `rel`, if the
+    /// readahead is `idle`.
+    fn plan(
+        &mut self,
+        timeline_id: TimelineId,
+        rel: RelTag,
+        blkno: BlockNumber,
+        idle: bool,
+    ) -> Option<Range<BlockNumber>> {
+        if self.distance == 0 {
+            return None;
+        }
+        let sequential = matches!(
+            self.last,
+            Some((last_timeline_id, last_rel, last_blkno))
+                if last_timeline_id == timeline_id
+                    && last_rel == rel
+                    && last_blkno.checked_add(1) == Some(blkno)
+        );
+        self.last = Some((timeline_id, rel, blkno));
+        if !sequential {
+            self.run_length = 1;
+            self.read_ahead_until = 0;
+            return None;
+        }
+        self.run_length = self.run_length.saturating_add(1);
+        if self.run_length < SCAN_MIN_BLOCKS || !idle {
+            return None;
+        }
+
+        // Read the next blocks once the scan is halfway through the ones read ahead,
+        // so that it doesn't catch up with the readahead
+        let start = self.read_ahead_until.max(blkno.saturating_add(1));
+        if start > blkno.saturating_add(self.distance / 2) {
+            return None;
+        }
+        let end = blkno.saturating_add(1).saturating_add(self.distance);
+        if start >= end {
+            return None;
+        }
+        self.read_ahead_until = end;
+        Some(start..end)
+    }
+}
+
+impl Drop for Readahead {
+    fn drop(&mut self) {
+        // Nobody is going to request the blocks once the connection is closed
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn read_ahead(
+    timeline: &Timeline,
+    rel: RelTag,
+    blocks: Range<BlockNumber>,
+    lsn: Lsn,
+    latest: bool,
+    ctx: &RequestContext,
+) -> anyhow::Result<()> {
+    let nblocks = timeline
+        .get_rel_size(rel, Version::Lsn(lsn), latest, ctx)
+        .await?;
+    let blocks = blocks.start..blocks.end.min(nblocks);
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    // The readahead takes its turn like one GetPage request of the tenant
+    let _permit = fair_limiter::get()
+        .acquire(timeline.tenant_id, timeline.get_get_page_weight())
+        .await;
+    let num_blocks = blocks.len();
+    timeline.get_rel_pages_at_lsn(rel, blocks, lsn, ctx).await?;
+    PAGE_READAHEAD_BLOCKS.inc_by(num_blocks as u64);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rel(relnode: u32) -> RelTag {
+        RelTag {
+            forknum: 0,
+            spcnode: 1663,
+            dbnode: 5,
+            relnode,
+        }
+    }
+
+    #[test]
+    fn sequential_scan() {
+        let timeline_id = TimelineId::generate();
+        let mut readahead = Readahead::new(8, TaskScope::new("test"));
+        let mut windows = Vec::new();
+        for blkno in 0..20 {
+            if let Some(blocks) = readahead.plan(timeline_id, rel(1), blkno, true) {
+                windows.push((blkno, blocks));
+            }
+        }
+        // starts after SCAN_MIN_BLOCKS, then continues halfway through each window
+        assert_eq!(
+            windows,
+            vec![(3, 4..12), (8, 12..17), (13, 17..22), (18, 22..27)]
+        );
+    }
+
+    #[test]
+    fn random_access() {
+        let timeline_id = TimelineId::generate();
+        let mut readahead = Readahead::new(8, TaskScope::new("test"));
+        for blkno in [5, 6, 7, 20, 21, 22, 3, 100, 101] {
+            assert_eq!(readahead.plan(timeline_id, rel(1), blkno, true), None);
+        }
+        // interleaved scans of two relations aren't detected either
+        for blkno in 0..10 {
+            assert_eq!(readahead.plan(timeline_id, rel(1), blkno, true), None);
+            assert_eq!(readahead.plan(timeline_id, rel(2), blkno, true), None);
+        }
+    }
+
+    #[test]
+    fn busy_or_disabled() {
+        let timeline_id = TimelineId::generate();
+        let mut readahead = Readahead::new(8, TaskScope::new("test"));
+        for blkno in 0..10 {
+            assert_eq!(readahead.plan(timeline_id, rel(1), blkno, false), None);
+        }
+        // picks up where the scan is once the previous readahead finished
+        assert_eq!(readahead.plan(timeline_id, rel(1), 10, true), Some(11..19));
+
+        let mut disabled = Readahead::new(0);
+        for blkno in 0..10 {
+            assert_eq!(disabled.plan(timeline_id, rel(1), blkno, true), None);
+        }
+    }
+}
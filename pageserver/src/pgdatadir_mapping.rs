@@ -94,6 +94,7 @@ impl Timeline {
             pending_updates: HashMap::new(),
             pending_deletions: Vec::new(),
             pending_nblocks: 0,
+            pending_slru_change: false,
             lsn,
             prev_lsn: Lsn::INVALID,
         }
@@ -134,6 +135,30 @@ impl Timeline {
         version.get(self, key, ctx).await
     }
 
+    /// Look up a range of blocks of a relation at given LSN, with one vectored
+    /// read. All blocks must be below the relation size.
+    pub async fn get_rel_pages_at_lsn(
+        &self,
+        tag: RelTag,
+        blknums: Range<BlockNumber>,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<Vec<Bytes>, PageReconstructError> {
+        if tag.relnode == 0 {
+            return Err(PageReconstructError::Other(
+                RelationError::InvalidRelnode.into(),
+            ));
+        }
+
+        let keys: Vec<Key> = blknums
+            .map(|blknum| rel_block_to_key(tag, blknum))
+            .collect();
+        self.get_vectored(&keys, lsn, ctx)
+            .await?
+            .into_iter()
+            .collect()
+    }
+
     // Get size of a database in blocks
     pub async fn get_db_size(
         &self,
@@ -488,6 +513,33 @@ impl Timeline {
         }
     }
 
+    /// Returns the auxiliary files, like replication slot state, keyed by their
+    /// path relative to the data directory.
+    pub async fn list_aux_files(
+        &self,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<HashMap<String, Bytes>, PageReconstructError> {
+        let buf = match self.get(AUX_FILES_KEY, lsn, ctx).await {
+            Ok(buf) => buf,
+            // Timelines created before aux files were stored don't have the key
+            Err(PageReconstructError::MissingKey(e)) => {
+                debug!("no aux files directory at {lsn}: {e}");
+                return Ok(HashMap::new());
+            }
+            Err(e) => return Err(e),
+        };
+
+        match AuxFilesDirectory::des(&buf).context("deserialization failure") {
+            Ok(dir) => Ok(dir
+                .files
+                .into_iter()
+                .map(|(path, content)| (path, Bytes::from(content)))
+                .collect()),
+            Err(e) => Err(PageReconstructError::from(e)),
+        }
+    }
+
     pub async fn get_control_file(
         &self,
         lsn: Lsn,
@@ -544,10 +596,57 @@ impl Timeline {
         Ok(total_size * BLCKSZ as u64)
     }
 
+    /// Returns the total size of the SLRU segments, in bytes.
+    ///
+    /// Walking the SLRU directories is expensive, so the size is cached, and
+    /// reused at a later LSN as long as no segment was created, extended or
+    /// dropped in between.
+    pub async fn get_slru_size(
+        &self,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<u64, PageReconstructError> {
+        // The changes up to `lsn` are committed by now, see DatadirModification::commit
+        let changed_at = self.slru_changed_at.load();
+        if let Some((cached_lsn, size)) = *self.slru_size_cache.lock().unwrap() {
+            if cached_lsn <= lsn && changed_at <= cached_lsn {
+                return Ok(size);
+            }
+        }
+
+        let mut total_blocks: u64 = 0;
+        for kind in [
+            SlruKind::Clog,
+            SlruKind::MultiXactMembers,
+            SlruKind::MultiXactOffsets,
+            SlruKind::Csn,
+        ] {
+            for segno in self
+                .list_slru_segments(kind, Version::Lsn(lsn), ctx)
+                .await?
+            {
+                total_blocks += self
+                    .get_slru_segment_size(kind, segno, Version::Lsn(lsn), ctx)
+                    .await? as u64;
+            }
+        }
+        let size = total_blocks * BLCKSZ as u64;
+
+        let mut cache = self.slru_size_cache.lock().unwrap();
+        if cache.map_or(true, |(cached_lsn, _)| cached_lsn < lsn) {
+            *cache = Some((lsn, size));
+        }
+        Ok(size)
+    }
+
     ///
     /// Get a KeySpace that covers all the Keys that are in use at the given LSN.
     /// Anything that's not listed maybe removed from the underlying storage (from
     /// that LSN forwards).
+    ///
+    /// The relation blocks are left out if the timeline doesn't ingest its relation
+    /// data, see [`Timeline::ingests_relation_data`]: the relation sizes cover blocks
+    /// whose page versions were skipped.
     pub async fn collect_keyspace(
         &self,
         lsn: Lsn,
@@ -563,6 +662,7 @@ impl Timeline {
         let buf = self.get(DBDIR_KEY, lsn, ctx).await?;
         let dbdir = DbDirectory::des(&buf).context("deserialization failure")?;
 
+        let ingests_relation_data = self.ingests_relation_data();
         let mut dbs: Vec<(Oid, Oid)> = dbdir.dbdirs.keys().cloned().collect();
         dbs.sort_unstable();
         for (spcnode, dbnode) in dbs {
@@ -580,7 +680,9 @@ impl Timeline {
                 let mut buf = self.get(relsize_key, lsn, ctx).await?;
                 let relsize = buf.get_u32_le();
 
-                result.add_range(rel_block_to_key(rel, 0)..rel_block_to_key(rel, relsize));
+                if ingests_relation_data {
+                    result.add_range(rel_block_to_key(rel, 0)..rel_block_to_key(rel, relsize));
+                }
                 result.add_key(relsize_key);
             }
         }
@@ -622,6 +724,11 @@ impl Timeline {
 
         result.add_key(CONTROLFILE_KEY);
         result.add_key(CHECKPOINT_KEY);
+        match self.get(AUX_FILES_KEY, lsn, ctx).await {
+            Ok(_) => result.add_key(AUX_FILES_KEY),
+            Err(PageReconstructError::MissingKey(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
 
         Ok(result.to_keyspace())
     }
@@ -686,6 +793,8 @@ pub struct DatadirModification<'a> {
     pending_updates: HashMap<Key, Vec<(Lsn, Value)>>,
     pending_deletions: Vec<(Range<Key>, Lsn)>,
     pending_nblocks: i64,
+    /// Whether an SLRU segment was created, extended or dropped.
+    pending_slru_change: bool,
 }
 
 impl<'a> DatadirModification<'a> {
@@ -735,6 +844,9 @@ impl<'a> DatadirModification<'a> {
         );
         self.put(slru_dir_to_key(SlruKind::Csn), empty_dir);
 
+        let buf = AuxFilesDirectory::ser(&AuxFilesDirectory::default())?;
+        self.put(AUX_FILES_KEY, Value::Image(buf.into()));
+
         Ok(())
     }
 
@@ -862,6 +974,34 @@ impl<'a> DatadirModification<'a> {
         Ok(())
     }
 
+    /// Stores an auxiliary file. Empty content removes the file.
+    pub async fn put_aux_file(
+        &mut self,
+        path: &str,
+        content: &[u8],
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let mut dir = match self.get(AUX_FILES_KEY, ctx).await {
+            Ok(buf) => AuxFilesDirectory::des(&buf)?,
+            // Timelines created before aux files were stored don't have the key
+            Err(PageReconstructError::MissingKey(e)) => {
+                debug!("no aux files directory, creating it: {e}");
+                AuxFilesDirectory::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if content.is_empty() {
+            dir.files.remove(path);
+        } else {
+            dir.files.insert(path.to_string(), content.to_vec());
+        }
+        self.put(
+            AUX_FILES_KEY,
+            Value::Image(Bytes::from(AuxFilesDirectory::ser(&dir)?)),
+        );
+        Ok(())
+    }
+
     pub fn put_control_file(&mut self, img: Bytes) -> anyhow::Result<()> {
         self.put(CONTROLFILE_KEY, Value::Image(img));
         Ok(())
@@ -1074,6 +1214,7 @@ impl<'a> DatadirModification<'a> {
         let size_key = slru_segment_size_to_key(kind, segno);
         let buf = nblocks.to_le_bytes();
         self.put(size_key, Value::Image(Bytes::from(buf.to_vec())));
+        self.pending_slru_change = true;
 
         // even if nblocks > 0, we don't insert any actual blocks here
 
@@ -1091,6 +1232,7 @@ impl<'a> DatadirModification<'a> {
         let size_key = slru_segment_size_to_key(kind, segno);
         let buf = nblocks.to_le_bytes();
         self.put(size_key, Value::Image(Bytes::from(buf.to_vec())));
+        self.pending_slru_change = true;
         Ok(())
     }
 
@@ -1116,6 +1258,7 @@ impl<'a> DatadirModification<'a> {
 
         // Delete size entry, as well as all blocks
         self.delete(slru_segment_key_range(kind, segno));
+        self.pending_slru_change = true;
 
         Ok(())
     }
@@ -1179,24 +1322,16 @@ impl<'a> DatadirModification<'a> {
         let writer = self.tline.writer().await;
 
         // Flush relation and  SLRU data blocks, keep metadata.
-        let mut retained_pending_updates = HashMap::<_, Vec<_>>::new();
-        for (key, values) in self.pending_updates.drain() {
-            for (lsn, value) in values {
-                if is_rel_block_key(key) || is_slru_block_key(key) {
-                    // This bails out on first error without modifying pending_updates.
-                    // That's Ok, cf this function's doc comment.
-                    writer.put(key, lsn, &value).await?;
-                } else {
-                    retained_pending_updates
-                        .entry(key)
-                        .or_default()
-                        .push((lsn, value));
-                }
-            }
-        }
-
+        let (data_updates, retained_pending_updates): (HashMap<_, _>, HashMap<_, _>) = self
+            .pending_updates
+            .drain()
+            .partition(|(key, _)| is_rel_block_key(*key) || is_slru_block_key(*key));
         self.pending_updates = retained_pending_updates;
 
+        // This bails out on error with the data blocks dropped from pending_updates.
+        // That's Ok, cf this function's doc comment.
+        writer.put_batch(&data_updates).await?;
+
         if pending_nblocks != 0 {
             writer.update_current_logical_size(pending_nblocks * i64::from(BLCKSZ));
             self.pending_nblocks = 0;
@@ -1222,6 +1357,12 @@ impl<'a> DatadirModification<'a> {
         writer.delete_batch(&self.pending_deletions).await?;
         self.pending_deletions.clear();
 
+        if self.pending_slru_change {
+            // Before the LSN becomes readable, for the cache of the SLRU size
+            self.tline.slru_changed_at.fetch_max(self.lsn);
+            self.pending_slru_change = false;
+        }
+
         writer.finish_write(RecordLsn {
             last: self.lsn,
             prev: self.prev_lsn,
@@ -1326,6 +1467,11 @@ struct TwoPhaseDirectory {
     xids: HashSet<TransactionId>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct AuxFilesDirectory {
+    files: HashMap<String, Vec<u8>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 struct RelDirectory {
     // Set of relations that exist. (relfilenode, forknum)
@@ -1385,6 +1531,7 @@ static ZERO_PAGE: Bytes = Bytes::from_static(&[0u8; BLCKSZ as usize]);
 // 03 misc
 //    controlfile
 //    checkpoint
+//    aux files
 //    pg_version
 //
 // Below is a full list of the keyspace allocation:
@@ -1424,6 +1571,9 @@ static ZERO_PAGE: Bytes = Bytes::from_static(&[0u8; BLCKSZ as usize]);
 //
 // Checkpoint:
 // 03 00000000 00000000 00000000 00   00000001
+//
+// AuxFiles:
+// 03 00000000 00000000 00000000 00   00000002
 //-- Section 01: relation data and metadata
 
 const DBDIR_KEY: Key = Key {
@@ -1517,6 +1667,25 @@ fn rel_key_range(rel: RelTag) -> Range<Key> {
 
 //-- Section 02: SLRUs
 
+/// The range of keys that hold SLRU data and metadata.
+pub fn slru_key_range() -> Range<Key> {
+    Key {
+        field1: 0x01,
+        field2: 0,
+        field3: 0,
+        field4: 0,
+        field5: 0,
+        field6: 0,
+    }..Key {
+        field1: 0x02,
+        field2: 0,
+        field3: 0,
+        field4: 0,
+        field5: 0,
+        field6: 0,
+    }
+}
+
 fn slru_dir_to_key(kind: SlruKind) -> Key {
     Key {
         field1: 0x01,
@@ -1651,6 +1820,26 @@ const CHECKPOINT_KEY: Key = Key {
     field6: 1,
 };
 
+const AUX_FILES_KEY: Key = Key {
+    field1: 0x03,
+    field2: 0,
+    field3: 0,
+    field4: 0,
+    field5: 0,
+    field6: 2,
+};
+
+/// Returns true if `path` names an auxiliary file that we store on behalf of
+/// the compute. Only replication slot and logical decoding state is accepted.
+pub fn is_valid_aux_file_path(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    (path.starts_with("pg_replslot") || path.starts_with("pg_logical"))
+        && path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        && path.components().count() > 1
+}
+
 // Reverse mappings for a few Keys.
 // These are needed by WAL redo manager.
 
@@ -1669,7 +1858,7 @@ pub fn key_to_rel_block(key: Key) -> anyhow::Result<(RelTag, BlockNumber)> {
     })
 }
 
-fn is_rel_block_key(key: Key) -> bool {
+pub fn is_rel_block_key(key: Key) -> bool {
     key.field1 == 0x00 && key.field4 != 0
 }
 
@@ -1836,4 +2025,17 @@ mod tests {
         Ok(())
     }
      */
+
+    #[test]
+    fn test_aux_file_paths() {
+        use super::is_valid_aux_file_path;
+
+        assert!(is_valid_aux_file_path("pg_replslot/slot1/state"));
+        assert!(is_valid_aux_file_path("pg_logical/replorigin_checkpoint"));
+
+        assert!(!is_valid_aux_file_path("pg_replslot"));
+        assert!(!is_valid_aux_file_path("pg_replslot/../global/pg_control"));
+        assert!(!is_valid_aux_file_path("/pg_replslot/slot1/state"));
+        assert!(!is_valid_aux_file_path("base/1/1259"));
+    }
 }
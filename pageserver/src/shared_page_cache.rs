@@ -0,0 +1,328 @@
+//!
+//! Cache of page images shared by all tenants, storing identical images once
+//!
+//! Right after they are created, the timelines of all tenants hold the same
+//! template database and catalog pages, written by initdb, and the small tenants
+//! hardly change them. The materialized page cache keeps a copy of each page for
+//! every timeline that reads it, so on a pageserver hosting thousands of such
+//! tenants, most of it is taken by copies of the same few thousand pages.
+//!
+//! This cache stores each distinct page image once, addressed by its SHA-256, and
+//! the versions of a page of a timeline only refer to the images. It's consulted
+//! when the materialized page cache misses, and is filled with the page versions
+//! that are read without WAL redo, mostly the images in the layers.
+//!
+//! The cache holds at most `shared_page_cache_size` images, and
+//! [`VERSIONS_PER_IMAGE`] times as many versions. The versions of the least
+//! recently used page are evicted first, and with them the images nothing else
+//! refers to.
+//!
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use hashlink::LruCache;
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::metrics::{
+    SHARED_PAGE_CACHE_HITS, SHARED_PAGE_CACHE_IMAGES, SHARED_PAGE_CACHE_VERSIONS,
+};
+use crate::page_cache::PAGE_SZ;
+use crate::repository::Key;
+
+/// Number of page versions the cache holds per image it can hold. A version costs
+/// about 100 bytes, a hundredth of an image.
+const VERSIONS_PER_IMAGE: usize = 32;
+
+/// Number of versions of a page kept in the cache, the most recent ones.
+const MAX_VERSIONS_PER_PAGE: usize = 4;
+
+static SHARED_PAGE_CACHE: OnceCell<SharedPageCache> = OnceCell::new();
+
+///
+/// Initialize the shared page cache, if enabled with a non-zero `size`. This must be
+/// called once at page server startup.
+///
+pub fn init(size: usize) {
+    if size == 0 {
+        return;
+    }
+    if SHARED_PAGE_CACHE.set(SharedPageCache::new(size)).is_err() {
+        panic!("shared page cache already initialized");
+    }
+}
+
+///
+/// Get a handle to the shared page cache, if it's enabled.
+///
+pub fn get() -> Option<&'static SharedPageCache> {
+    SHARED_PAGE_CACHE.get()
+}
+
+type ContentHash = [u8; 32];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PageKey {
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    key: Key,
+}
+
+pub struct SharedPageCache {
+    max_images: usize,
+    max_versions: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// The cached versions of each page, ordered by LSN, in the order the pages
+    /// were used.
+    pages: LruCache<PageKey, Vec<(Lsn, ContentHash)>>,
+    /// Total number of versions in `pages`.
+    num_versions: usize,
+    /// The distinct images, with the number of versions that refer to each.
+    images: HashMap<ContentHash, (Bytes, usize)>,
+}
+
+impl SharedPageCache {
+    fn new(max_images: usize) -> Self {
+        SharedPageCache {
+            max_images,
+            max_versions: max_images.saturating_mul(VERSIONS_PER_IMAGE),
+            inner: Mutex::new(Inner {
+                pages: LruCache::new_unbounded(),
+                num_versions: 0,
+                images: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Look up the most recent version of a page at or before `lsn`, like
+    /// [`crate::page_cache::PageCache::lookup_materialized_page`].
+    pub fn lookup(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        key: &Key,
+        lsn: Lsn,
+    ) -> Option<(Lsn, Bytes)> {
+        let page_key = PageKey {
+            tenant_id,
+            timeline_id,
+            key: *key,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let versions = inner.pages.get(&page_key)?;
+        let (version_lsn, hash) = versions.iter().rev().find(|(v, _)| *v <= lsn)?;
+        let (img, _) = inner
+            .images
+            .get(hash)
+            .expect("a cached version refers to a missing image");
+        SHARED_PAGE_CACHE_HITS.inc();
+        Some((*version_lsn, img.clone()))
+    }
+
+    /// Store the image of a page at `lsn`, as read from a layer.
+    pub fn memorize(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        key: &Key,
+        lsn: Lsn,
+        img: &Bytes,
+    ) {
+        // Only whole pages are worth it, not the metadata
+        if img.len() != PAGE_SZ {
+            return;
+        }
+        let page_key = PageKey {
+            tenant_id,
+            timeline_id,
+            key: *key,
+        };
+        if self.contains(&page_key, lsn) {
+            return;
+        }
+        // Hash without holding the lock
+        let hash: ContentHash = Sha256::digest(img).into();
+
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        if !inner.pages.contains_key(&page_key) {
+            inner.pages.insert(page_key, Vec::new());
+        }
+        let versions = inner.pages.get_mut(&page_key).unwrap();
+        let pos = match versions.binary_search_by_key(&lsn, |(v, _)| *v) {
+            // somebody else got here first
+            Ok(_) => return,
+            Err(pos) => pos,
+        };
+        versions.insert(pos, (lsn, hash));
+        inner.num_versions += 1;
+        inner
+            .images
+            .entry(hash)
+            .or_insert_with(|| (img.clone(), 0))
+            .1 += 1;
+        if versions.len() > MAX_VERSIONS_PER_PAGE {
+            let (_, oldest) = versions.remove(0);
+            inner.num_versions -= 1;
+            release_image(&mut inner.images, &oldest);
+        }
+
+        while inner.images.len() > self.max_images || inner.num_versions > self.max_versions {
+            let Some((_, versions)) = inner.pages.remove_lru() else {
+                break;
+            };
+            inner.num_versions -= versions.len();
+            for (_, hash) in versions {
+                release_image(&mut inner.images, &hash);
+            }
+        }
+        SHARED_PAGE_CACHE_IMAGES.set(inner.images.len() as i64);
+        SHARED_PAGE_CACHE_VERSIONS.set(inner.num_versions as i64);
+    }
+
+    fn contains(&self, page_key: &PageKey, lsn: Lsn) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .pages
+            .peek(page_key)
+            .is_some_and(|versions| versions.iter().any(|(v, _)| *v == lsn))
+    }
+}
+
+fn release_image(images: &mut HashMap<ContentHash, (Bytes, usize)>, hash: &ContentHash) {
+    let (_, refs) = images
+        .get_mut(hash)
+        .expect("a cached version refers to a missing image");
+    *refs -= 1;
+    if *refs == 0 {
+        images.remove(hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(byte: u8) -> Bytes {
+        Bytes::from(vec![byte; PAGE_SZ])
+    }
+
+    #[test]
+    fn identical_images_stored_once() {
+        let cache = SharedPageCache::new(10);
+        let key = Key::from_i128(1);
+        let timeline_id = TimelineId::generate();
+        let tenants: Vec<TenantId> = (0..5).map(|_| TenantId::generate()).collect();
+        for tenant_id in &tenants {
+            cache.memorize(*tenant_id, timeline_id, &key, Lsn(0x10), &page(1));
+        }
+        assert_eq!(cache.inner.lock().unwrap().images.len(), 1);
+        for tenant_id in &tenants {
+            let (lsn, img) = cache
+                .lookup(*tenant_id, timeline_id, &key, Lsn(0x20))
+                .unwrap();
+            assert_eq!(lsn, Lsn(0x10));
+            assert_eq!(img, page(1));
+        }
+        // other tenants, other keys and older LSNs miss
+        assert!(cache
+            .lookup(TenantId::generate(), timeline_id, &key, Lsn(0x20))
+            .is_none());
+        assert!(cache
+            .lookup(tenants[0], timeline_id, &Key::from_i128(2), Lsn(0x20))
+            .is_none());
+        assert!(cache
+            .lookup(tenants[0], timeline_id, &key, Lsn(0x8))
+            .is_none());
+        // not a page
+        cache.memorize(
+            tenants[0],
+            timeline_id,
+            &Key::from_i128(2),
+            Lsn(0x10),
+            &Bytes::from_static(b"x"),
+        );
+        assert!(cache
+            .lookup(tenants[0], timeline_id, &Key::from_i128(2), Lsn(0x20))
+            .is_none());
+    }
+
+    #[test]
+    fn versions() {
+        let cache = SharedPageCache::new(10);
+        let key = Key::from_i128(1);
+        let (tenant_id, timeline_id) = (TenantId::generate(), TimelineId::generate());
+        for i in 1..=(MAX_VERSIONS_PER_PAGE as u8 + 2) {
+            cache.memorize(tenant_id, timeline_id, &key, Lsn(0x10 * i as u64), &page(i));
+        }
+        let lookup = |lsn| cache.lookup(tenant_id, timeline_id, &key, Lsn(lsn));
+        assert_eq!(lookup(0x35), Some((Lsn(0x30), page(3))));
+        assert_eq!(lookup(0x60), Some((Lsn(0x60), page(6))));
+        assert_eq!(lookup(0x1000), Some((Lsn(0x60), page(6))));
+        // the oldest versions are gone, and so are their images
+        assert_eq!(lookup(0x25), None);
+        let inner = cache.inner.lock().unwrap();
+        assert_eq!(inner.num_versions, MAX_VERSIONS_PER_PAGE);
+        assert_eq!(inner.images.len(), MAX_VERSIONS_PER_PAGE);
+    }
+
+    #[test]
+    fn eviction() {
+        let cache = SharedPageCache::new(3);
+        let timeline_id = TimelineId::generate();
+        let tenant_id = TenantId::generate();
+        for i in 0..3 {
+            cache.memorize(
+                tenant_id,
+                timeline_id,
+                &Key::from_i128(i),
+                Lsn(0x10),
+                &page(i as u8),
+            );
+        }
+        // use the first page, so that the second one is evicted for a new image
+        assert!(cache
+            .lookup(tenant_id, timeline_id, &Key::from_i128(0), Lsn(0x10))
+            .is_some());
+        cache.memorize(
+            tenant_id,
+            timeline_id,
+            &Key::from_i128(3),
+            Lsn(0x10),
+            &page(3),
+        );
+        for (i, cached) in [(0, true), (1, false), (2, true), (3, true)] {
+            assert_eq!(
+                cache
+                    .lookup(tenant_id, timeline_id, &Key::from_i128(i), Lsn(0x10))
+                    .is_some(),
+                cached,
+                "key {i}"
+            );
+        }
+        assert_eq!(cache.inner.lock().unwrap().images.len(), 3);
+
+        // pages with an image that's already cached only take a version
+        let tenants: Vec<TenantId> = (0..50).map(|_| TenantId::generate()).collect();
+        for tenant_id in &tenants {
+            cache.memorize(
+                *tenant_id,
+                timeline_id,
+                &Key::from_i128(0),
+                Lsn(0x10),
+                &page(0),
+            );
+        }
+        let inner = cache.inner.lock().unwrap();
+        assert_eq!(inner.num_versions, 3 + tenants.len());
+        assert_eq!(inner.images.len(), 3);
+    }
+}
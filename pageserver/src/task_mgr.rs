@@ -250,12 +250,27 @@ pub enum TaskKind {
     // Compaction. One per tenant.
     Compaction,
 
+    // Logical size reconciliation. One per tenant.
+    LogicalSizeReconciliation,
+
     // Eviction. One per timeline.
     Eviction,
 
     /// See [`crate::disk_usage_eviction_task`].
     DiskUsageEviction,
 
+    /// See [`crate::page_cache::autotune_task`].
+    PageCacheAutotune,
+
+    /// See [`crate::top_tenants::sampling_task`].
+    TopTenantsSampling,
+
+    /// See [`crate::broker_publisher::publish_task`].
+    BrokerPublisher,
+
+    /// See [`crate::tenant::timeline::prewarm`].
+    Prewarm,
+
     // Initial logical size calculation
     InitialLogicalSizeCalculation,
 
@@ -575,6 +590,12 @@ pub fn shutdown_token() -> CancellationToken {
         .expect("shutdown_token() called in an unexpected task or thread")
 }
 
+/// Like [`shutdown_token`], but outside of the tasks of task_mgr, e.g. in the HTTP
+/// handlers, returns a token that is never cancelled.
+pub fn shutdown_token_or_default() -> CancellationToken {
+    SHUTDOWN_TOKEN.try_with(|t| t.clone()).unwrap_or_default()
+}
+
 /// Has the current task been requested to shut down?
 pub fn is_shutdown_requested() -> bool {
     if let Ok(cancel) = SHUTDOWN_TOKEN.try_with(|t| t.clone()) {
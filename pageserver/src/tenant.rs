@@ -13,7 +13,7 @@
 
 use anyhow::{bail, Context};
 use futures::FutureExt;
-use pageserver_api::models::TimelineState;
+use pageserver_api::models::{AttachDownloadProgress, Labels, TimelineState};
 use remote_storage::DownloadError;
 use remote_storage::GenericRemoteStorage;
 use storage_broker::BrokerClientChannel;
@@ -23,6 +23,8 @@ use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::completion;
 use utils::crashsafe::path_with_suffix_extension;
+use utils::task_scope::TaskScope;
+use utils::token_bucket::TokenBucket;
 
 use std::cmp::min;
 use std::collections::hash_map::Entry;
@@ -34,6 +36,7 @@ use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
 use std::io::Write;
+use std::num::NonZeroU32;
 use std::ops::Bound::Included;
 use std::path::Path;
 use std::path::PathBuf;
@@ -61,12 +64,12 @@ use crate::import_datadir;
 use crate::is_uninit_mark;
 use crate::metrics::TENANT_ACTIVATION;
 use crate::metrics::{remove_tenant_metrics, TENANT_STATE_METRIC, TENANT_SYNTHETIC_SIZE_METRIC};
+use crate::pgdatadir_mapping::CalculateLogicalSizeError;
 use crate::repository::GcResult;
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
-use crate::tenant::config::TenantConfOpt;
+use crate::tenant::config::{TenantConfOpt, TimelineConfOpt};
 use crate::tenant::metadata::load_metadata;
-use crate::tenant::remote_timeline_client::index::IndexPart;
 use crate::tenant::remote_timeline_client::MaybeDeletedIndexPart;
 use crate::tenant::storage_layer::DeltaLayer;
 use crate::tenant::storage_layer::ImageLayer;
@@ -78,6 +81,7 @@ use crate::tenant::timeline::uninit::cleanup_timeline_directory;
 use crate::virtual_file::VirtualFile;
 use crate::walredo::PostgresRedoManager;
 use crate::walredo::WalRedoManager;
+use crate::xact_resolver::wait_graph::WaitGraph;
 use crate::TEMP_FILE_SUFFIX;
 pub use pageserver_api::models::TenantState;
 
@@ -109,11 +113,13 @@ macro_rules! pausable_failpoint {
 
 pub mod blob_io;
 pub mod block_io;
+pub mod bloom_filter;
 
 pub mod disk_btree;
 pub(crate) mod ephemeral_file;
 pub mod layer_map;
 pub mod manifest;
+pub mod presence_map;
 mod span;
 
 pub mod metadata;
@@ -121,8 +127,10 @@ mod par_fsync;
 mod remote_timeline_client;
 pub mod storage_layer;
 
+pub mod attach_download;
 pub mod config;
 pub mod delete;
+pub mod labels;
 pub mod mgr;
 pub mod tasks;
 pub mod upload_queue;
@@ -145,7 +153,11 @@ pub use crate::tenant::metadata::save_metadata;
 // re-export for use in walreceiver
 pub use crate::tenant::timeline::WalReceiverInfo;
 
+// re-export for use in pagectl
+pub use crate::tenant::remote_timeline_client::index::{IndexPart, LayerFileMetadata};
+
 /// Parts of the `.neon/tenants/<tenant_id>/timelines/<timeline_id>` directory prefix.
+pub const TENANTS_SEGMENT_NAME: &str = "tenants";
 pub const TIMELINES_SEGMENT_NAME: &str = "timelines";
 
 pub const TENANT_ATTACHING_MARKER_FILENAME: &str = "attaching";
@@ -170,6 +182,12 @@ pub struct Tenant {
     // about parameters that are not set.
     // This is necessary to allow global config updates.
     tenant_conf: Arc<RwLock<TenantConfOpt>>,
+    /// Bumped on every change of `tenant_conf`, so that the background loops
+    /// apply new periods to the sleeps they are in.
+    tenant_conf_updates: watch::Sender<()>,
+
+    /// See [`labels`]. Persisted in the tenant directory.
+    labels: RwLock<Labels>,
 
     tenant_id: TenantId,
     timelines: Mutex<HashMap<TimelineId, Arc<Timeline>>>,
@@ -192,6 +210,22 @@ pub struct Tenant {
     eviction_task_tenant_state: tokio::sync::Mutex<EvictionTaskTenantState>,
 
     pub(crate) delete_progress: Arc<tokio::sync::Mutex<DeleteTenantFlow>>,
+
+    /// Tasks of the tenant which aren't managed by [`task_mgr`], e.g. the ones of
+    /// HTTP requests which outlive the request, or the readaheads of the page
+    /// service connections, joined by [`Tenant::shutdown`].
+    pub(crate) tasks: TaskScope,
+
+    /// Limits the rate of the page service requests of the tenant, following
+    /// `pagestream_rate_limit`.
+    pub(crate) pagestream_throttle: TokenBucket,
+
+    /// The wait-for edges of the transactions of the tenant reported by the regions,
+    /// for the detection of the deadlocks across regions.
+    pub(crate) wait_graph: WaitGraph,
+
+    /// The download of the layers at attach, see [`attach_download`].
+    attach_download: Mutex<Option<AttachDownloadProgress>>,
 }
 
 // We should not blindly overwrite local metadata with remote one.
@@ -421,6 +455,7 @@ impl Tenant {
             new_disk_consistent_lsn.is_valid(),
             "Timeline {tenant_id}/{timeline_id} has invalid disk_consistent_lsn"
         );
+        timeline.consume_clean_shutdown_marker();
         timeline
             .load_layer_map(new_disk_consistent_lsn)
             .await
@@ -662,6 +697,14 @@ impl Tenant {
                 })?;
         }
 
+        // With the marker file still there, a restart in the middle of the download
+        // attaches the tenant again, and only downloads the layers not local yet.
+        if let Some(config) = &self.conf.attach_download_layers {
+            self.download_layers_at_attach(config)
+                .await
+                .context("download the layers of the tenant")?;
+        }
+
         std::fs::remove_file(&marker_file)
             .with_context(|| format!("unlink attach marker file {}", marker_file.display()))?;
         crashsafe::fsync(marker_file.parent().expect("marker file has parent dir"))
@@ -775,7 +818,7 @@ impl Tenant {
         broker_client: storage_broker::BrokerClientChannel,
         remote_storage: Option<GenericRemoteStorage>,
         init_order: Option<InitializationOrder>,
-        tenants: &'static tokio::sync::RwLock<TenantsMap>,
+        tenants: &'static TenantsMap,
         ctx: &RequestContext,
     ) -> Arc<Tenant> {
         span::debug_assert_current_span_has_tenant_id();
@@ -787,9 +830,16 @@ impl Tenant {
                 return Tenant::create_broken_tenant(conf, tenant_id, format!("{e:#}"));
             }
         };
+        let tenant_labels = match labels::load(&conf.tenant_labels_path(&tenant_id)) {
+            Ok(labels) => labels,
+            Err(e) => {
+                error!("load tenant labels failed: {:?}", e);
+                return Tenant::create_broken_tenant(conf, tenant_id, format!("{e:#}"));
+            }
+        };
 
         let wal_redo_manager = Arc::new(PostgresRedoManager::new(conf, tenant_id));
-        let tenant = Tenant::new(
+        let mut tenant = Tenant::new(
             TenantState::Loading,
             conf,
             tenant_conf,
@@ -797,6 +847,7 @@ impl Tenant {
             tenant_id,
             remote_storage.clone(),
         );
+        *tenant.labels.get_mut().unwrap() = tenant_labels;
         let tenant = Arc::new(tenant);
 
         // Do all the hard work in a background task
@@ -1336,6 +1387,29 @@ impl Tenant {
             .collect()
     }
 
+    /// The home region of the tenant: the region of its root timelines, the ones
+    /// without an ancestor, which the timelines of the other regions are branched
+    /// from. If there are root timelines in several regions, the lowest one.
+    pub fn home_region(&self) -> Option<RegionId> {
+        self.timelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|timeline| timeline.get_ancestor_timeline_id().is_none())
+            .map(|timeline| timeline.region_id)
+            .min()
+    }
+
+    /// The regions the tenant has timelines in, in order.
+    pub fn regions(&self) -> Vec<RegionId> {
+        let timelines = self.timelines.lock().unwrap();
+        let regions: BTreeSet<RegionId> = timelines
+            .values()
+            .map(|timeline| timeline.region_id)
+            .collect();
+        regions.into_iter().collect()
+    }
+
     /// This is used to create the initial 'main' timeline during bootstrapping,
     /// or when importing a new base backup. The caller is expected to load an
     /// initial image of the datadir to the new timeline after this.
@@ -1498,7 +1572,10 @@ impl Tenant {
                     // decoding the new WAL might need to look up previous pages, relation
                     // sizes etc. and that would get confused if the previous page versions
                     // are not in the repository yet.
-                    ancestor_timeline.wait_lsn(*lsn, ctx).await?;
+                    ancestor_timeline
+                        .wait_lsn(*lsn, ctx)
+                        .await
+                        .map_err(|e| CreateTimelineError::Other(e.into()))?;
                 }
 
                 self.branch_timeline(
@@ -1606,14 +1683,64 @@ impl Tenant {
         Ok(())
     }
 
-    /// Flush all in-memory data to disk and remote storage, if any.
+    /// The L0 debt of the tenant: the L0 delta layers of its active timelines, which
+    /// compaction is yet to turn into L1 layers. The tenants with the most debt are
+    /// compacted first, see [`tasks::scheduler`].
+    pub async fn level0_debt(&self) -> u64 {
+        let timelines: Vec<_> = {
+            let timelines = self.timelines.lock().unwrap();
+            timelines
+                .values()
+                .filter(|timeline| timeline.is_active())
+                .cloned()
+                .collect()
+        };
+        let mut debt = 0;
+        for timeline in timelines {
+            debt += timeline.level0_deltas_count().await as u64;
+        }
+        debt
+    }
+
+    /// Check the logical size of each active timeline against a calculation from the
+    /// layers, see [`Timeline::reconcile_logical_size`].
+    /// This function is periodically called by the logical size reconciliation task.
+    pub async fn reconcile_logical_sizes(&self, ctx: &RequestContext) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.is_active(),
+            "Cannot reconcile logical sizes of inactive tenant"
+        );
+
+        let timelines = self.list_timelines();
+        for timeline in timelines.iter().filter(|timeline| timeline.is_active()) {
+            let timeline_id = timeline.timeline_id;
+            match timeline
+                .reconcile_logical_size(ctx)
+                .instrument(info_span!("reconcile_logical_size", %timeline_id))
+                .await
+            {
+                Ok(()) => {}
+                Err(CalculateLogicalSizeError::Cancelled) => break,
+                // Don't let one timeline keep the others from being checked
+                Err(CalculateLogicalSizeError::Other(e)) => {
+                    warn!("failed to reconcile logical size of timeline {timeline_id}: {e:#}")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush all in-memory data to disk and remote storage, if any, and write the
+    /// clean shutdown marker of the timelines where both succeeded.
     ///
     /// Used at graceful shutdown.
     async fn freeze_and_flush_on_shutdown(&self) {
         let mut js = tokio::task::JoinSet::new();
 
+        let upload_timeout = self.conf.shutdown_upload_timeout;
+
         // execute on each timeline on the JoinSet, join after.
-        let per_timeline = |timeline_id: TimelineId, timeline: Arc<Timeline>| {
+        let per_timeline = move |timeline_id: TimelineId, timeline: Arc<Timeline>| {
             async move {
                 debug_assert_current_span_has_tenant_and_timeline_id();
 
@@ -1632,14 +1759,24 @@ impl Tenant {
                     //
                     // what is problematic is the shutting down of RemoteTimelineClient, because
                     // obviously it does not make sense to stop while we wait for it, but what
-                    // about corner cases like s3 suddenly hanging up?
-                    client.wait_completion().await
+                    // about corner cases like s3 suddenly hanging up? that's what the timeout is for.
+                    match tokio::time::timeout(upload_timeout, client.wait_completion()).await {
+                        Ok(res) => res,
+                        Err(_) => Err(anyhow::anyhow!(
+                            "uploads did not finish in {upload_timeout:?}"
+                        )),
+                    }
                 } else {
                     Ok(())
                 };
 
                 if let Err(e) = res {
                     warn!("failed to await for frozen and flushed uploads: {e:#}");
+                    return;
+                }
+
+                if let Err(e) = timeline.write_clean_shutdown_marker() {
+                    warn!("failed to write the clean shutdown marker: {e:#}");
                 }
             }
             .instrument(tracing::info_span!("freeze_and_flush_on_shutdown", %timeline_id))
@@ -1673,6 +1810,12 @@ impl Tenant {
         self.current_state() == TenantState::Active
     }
 
+    /// Whether the attach of the tenant is still downloading its layers.
+    pub fn has_in_progress_downloads(&self) -> bool {
+        matches!(self.current_state(), TenantState::Attaching)
+            && self.attach_download.lock().unwrap().is_some()
+    }
+
     /// Changes tenant status to active, unless shutdown was already requested.
     ///
     /// `background_jobs_can_start` is an optional barrier set to a value during pageserver startup
@@ -1815,6 +1958,7 @@ impl Tenant {
         //
         // this will additionally shutdown and await all timeline tasks.
         task_mgr::shutdown_tasks(None, Some(self.tenant_id), None).await;
+        self.tasks.shutdown().await;
 
         Ok(())
     }
@@ -1965,6 +2109,10 @@ impl Tenant {
         self.state.subscribe()
     }
 
+    pub(crate) fn subscribe_for_conf_updates(&self) -> watch::Receiver<()> {
+        self.tenant_conf_updates.subscribe()
+    }
+
     pub(crate) async fn wait_to_become_active(&self) -> Result<(), WaitToBecomeActiveError> {
         let mut receiver = self.state.subscribe();
         loop {
@@ -2103,6 +2251,13 @@ impl Tenant {
             .unwrap_or(self.conf.default_tenant_conf.gc_period)
     }
 
+    pub fn get_logical_size_reconcile_period(&self) -> Duration {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .logical_size_reconcile_period
+            .unwrap_or(self.conf.default_tenant_conf.logical_size_reconcile_period)
+    }
+
     pub fn get_image_creation_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -2131,8 +2286,37 @@ impl Tenant {
             .or(self.conf.default_tenant_conf.min_resident_size_override)
     }
 
+    pub fn get_pagestream_rate_limit(&self) -> Option<NonZeroU32> {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .pagestream_rate_limit
+            .or(self.conf.default_tenant_conf.pagestream_rate_limit)
+    }
+
+    fn update_pagestream_throttle(&self) {
+        let per_second = self.get_pagestream_rate_limit().map_or(0, NonZeroU32::get);
+        // up to a second's worth of requests at once
+        self.pagestream_throttle.set_limit(per_second, per_second);
+    }
+
+    pub fn labels(&self) -> Labels {
+        self.labels.read().unwrap().clone()
+    }
+
+    /// Replaces the labels of the tenant, and persists them.
+    pub fn set_labels(&self, new_labels: Labels) -> anyhow::Result<()> {
+        labels::validate(&new_labels)?;
+        let mut guard = self.labels.write().unwrap();
+        labels::persist(&self.conf.tenant_labels_path(&self.tenant_id), &new_labels)?;
+        *guard = new_labels;
+        Ok(())
+    }
+
     pub fn set_new_tenant_config(&self, new_tenant_conf: TenantConfOpt) {
         *self.tenant_conf.write().unwrap() = new_tenant_conf;
+        // send_replace, unlike send, works without receivers
+        self.tenant_conf_updates.send_replace(());
+        self.update_pagestream_throttle();
         // Don't hold self.timelines.lock() during the notifies.
         // There's no risk of deadlock right now, but there could be if we consolidate
         // mutexes in struct Timeline in the future.
@@ -2191,6 +2375,14 @@ impl Tenant {
             initial_logical_size_attempt.cloned().flatten(),
             state,
         );
+        let labels_path = self
+            .conf
+            .timeline_labels_path(&self.tenant_id, &new_timeline_id);
+        *timeline.labels.write().unwrap() = labels::load(&labels_path)?;
+        let config_path = self
+            .conf
+            .timeline_config_path(&self.tenant_id, &new_timeline_id);
+        *timeline.timeline_conf.write().unwrap() = TimelineConfOpt::load(&config_path)?;
 
         Ok(timeline)
     }
@@ -2257,13 +2449,15 @@ impl Tenant {
             }
         });
 
-        Tenant {
+        let tenant = Tenant {
             tenant_id,
             conf,
             // using now here is good enough approximation to catch tenants with really long
             // activation times.
             loading_started_at: Instant::now(),
             tenant_conf: Arc::new(RwLock::new(tenant_conf)),
+            tenant_conf_updates: watch::channel(()).0,
+            labels: RwLock::new(Labels::new()),
             timelines: Mutex::new(HashMap::new()),
             gc_cs: tokio::sync::Mutex::new(()),
             walredo_mgr,
@@ -2273,7 +2467,13 @@ impl Tenant {
             cached_synthetic_tenant_size: Arc::new(AtomicU64::new(0)),
             eviction_task_tenant_state: tokio::sync::Mutex::new(EvictionTaskTenantState::default()),
             delete_progress: Arc::new(tokio::sync::Mutex::new(DeleteTenantFlow::default())),
-        }
+            tasks: TaskScope::new(format!("tenant {tenant_id}")),
+            pagestream_throttle: TokenBucket::unlimited(),
+            wait_graph: WaitGraph::default(),
+            attach_download: Mutex::new(None),
+        };
+        tenant.update_pagestream_throttle();
+        tenant
     }
 
     /// Locate and load config
@@ -2549,6 +2749,8 @@ impl Tenant {
                 if timeline_id != target_timeline_id {
                     continue;
                 }
+            } else if timeline.is_suspended() {
+                continue;
             }
 
             if let Some(cutoff) = timeline.get_last_record_lsn().checked_sub(horizon) {
@@ -3340,6 +3542,7 @@ pub mod harness {
                 gc_horizon: Some(tenant_conf.gc_horizon),
                 gc_period: Some(tenant_conf.gc_period),
                 image_creation_threshold: Some(tenant_conf.image_creation_threshold),
+                slru_image_creation_threshold: Some(tenant_conf.slru_image_creation_threshold),
                 pitr_interval: Some(tenant_conf.pitr_interval),
                 walreceiver_connect_timeout: Some(tenant_conf.walreceiver_connect_timeout),
                 lagging_wal_timeout: Some(tenant_conf.lagging_wal_timeout),
@@ -3351,6 +3554,11 @@ pub mod harness {
                     tenant_conf.evictions_low_residence_duration_metric_threshold,
                 ),
                 gc_feedback: Some(tenant_conf.gc_feedback),
+                layer_compression_level: tenant_conf.layer_compression_level,
+                get_page_weight: Some(tenant_conf.get_page_weight),
+                logical_size_reconcile_period: Some(tenant_conf.logical_size_reconcile_period),
+                pagestream_rate_limit: tenant_conf.pagestream_rate_limit,
+                wait_lsn_timeout: tenant_conf.wait_lsn_timeout,
             }
         }
     }
@@ -4568,6 +4776,84 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_vectored_get() -> anyhow::Result<()> {
+        let (tenant, ctx) = TenantHarness::create("test_vectored_get")?.load().await;
+        let mut tline = tenant
+            .create_test_timeline(
+                TIMELINE_ID,
+                Lsn(0x10),
+                DEFAULT_PG_VERSION,
+                RegionId(0),
+                &ctx,
+            )
+            .await?;
+
+        const NUM_KEYS: usize = 200;
+        const NUM_TLINES: usize = 3;
+
+        let mut test_key = Key::from_hex("012222222233333333444444445500000000").unwrap();
+        let keys: Vec<Key> = (0..NUM_KEYS)
+            .map(|blknum| {
+                test_key.field6 = blknum as u32;
+                test_key
+            })
+            .collect();
+
+        // Spread the page versions over several branches, and over on-disk and
+        // in-memory layers, so that the keys take different paths.
+        let mut lsn = Lsn(0x10);
+        for idx in 0..NUM_TLINES {
+            if idx > 0 {
+                let new_tline_id = TimelineId::generate();
+                tenant
+                    .branch_timeline_test(&tline, new_tline_id, Some(lsn), RegionId(0), &ctx)
+                    .await?;
+                tline = tenant
+                    .get_timeline(new_tline_id, true)
+                    .expect("Should have the branched timeline");
+            }
+
+            for round in 0..2 {
+                for blknum in (idx..NUM_KEYS).step_by(idx + 1) {
+                    lsn = Lsn(lsn.0 + 0x10);
+                    let writer = tline.writer().await;
+                    writer
+                        .put(
+                            keys[blknum],
+                            lsn,
+                            &Value::Image(TEST_IMG(&format!("{idx} {blknum} at {lsn}"))),
+                        )
+                        .await?;
+                    writer.finish_write(RecordLsn {
+                        last: lsn,
+                        prev: Lsn::INVALID,
+                    });
+                }
+                if round == 0 {
+                    tline.freeze_and_flush().await?;
+                }
+            }
+        }
+
+        let results = tline.get_vectored(&keys, lsn, &ctx).await?;
+        assert_eq!(results.len(), NUM_KEYS);
+        for (key, result) in keys.iter().zip(results) {
+            assert_eq!(result?, tline.get(*key, lsn, &ctx).await?);
+        }
+
+        // A key that doesn't exist fails on its own.
+        let mut missing_key = test_key;
+        missing_key.field6 = NUM_KEYS as u32 + 1;
+        let results = tline
+            .get_vectored(&[keys[0], missing_key], lsn, &ctx)
+            .await?;
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_write_at_initdb_lsn_takes_optimization_code_path() -> anyhow::Result<()> {
         let (tenant, ctx) = TenantHarness::create("test_empty_test_timeline_is_usable")?
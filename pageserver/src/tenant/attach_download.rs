@@ -0,0 +1,134 @@
+//! Download of all the layers of a tenant when it's attached, see
+//! `attach_download_layers` in the pageserver config.
+//!
+//! By default, the layers of an attached tenant are only downloaded on demand,
+//! when they're read. Before it serves a very large tenant, e.g. one moved from
+//! another pageserver, a pageserver may rather have all of its layers locally:
+//! the attach then downloads them, at a limited bandwidth so that the tenants
+//! already served by the pageserver don't suffer from it.
+//!
+//! The download is resumable. Each layer file is downloaded to a temporary file,
+//! which is renamed into place, durably, once complete; the attach marker file is
+//! only removed once all the layers are downloaded. If the pageserver restarts in
+//! the middle, the attach starts over, finds the layer files downloaded before in
+//! the timeline directories, and only downloads the remaining ones.
+
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::sync::Arc;
+
+use futures::StreamExt;
+use pageserver_api::models::AttachDownloadProgress;
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span, Instrument};
+use utils::token_bucket::TokenBucket;
+
+use super::storage_layer::downcast_remote_layer;
+use super::Tenant;
+use crate::task_mgr;
+
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 8;
+
+/// The bandwidth limit counts KiB, so that the rate fits the u32 of the token bucket.
+const TOKEN_BYTES: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachDownloadConfig {
+    /// Number of layer files of the tenant downloaded at once.
+    pub max_concurrent_downloads: NonZeroUsize,
+    /// Limit on the download bandwidth of each attach, not limited if unset.
+    pub max_bytes_per_second: Option<NonZeroU64>,
+}
+
+impl Default for AttachDownloadConfig {
+    fn default() -> Self {
+        AttachDownloadConfig {
+            max_concurrent_downloads: NonZeroUsize::new(DEFAULT_MAX_CONCURRENT_DOWNLOADS)
+                .expect("Invalid default constant"),
+            max_bytes_per_second: None,
+        }
+    }
+}
+
+fn tokens(bytes: u64) -> u32 {
+    u32::try_from(bytes.div_ceil(TOKEN_BYTES)).unwrap_or(u32::MAX)
+}
+
+impl Tenant {
+    /// Downloads the layers of all the timelines which aren't local yet, as the
+    /// last step of [`Tenant::attach`].
+    pub(super) async fn download_layers_at_attach(
+        &self,
+        config: &AttachDownloadConfig,
+    ) -> anyhow::Result<()> {
+        let mut progress = AttachDownloadProgress::default();
+        let mut downloads = Vec::new();
+        for timeline in self.list_timelines() {
+            let guard = timeline.layers.read().await;
+            for desc in guard.layer_map().iter_historic_layers() {
+                let size = desc.file_size();
+                progress.total_layers += 1;
+                progress.total_bytes += size;
+                match downcast_remote_layer(&guard.get_from_desc(&desc)) {
+                    Some(remote_layer) => {
+                        downloads.push((Arc::clone(&timeline), remote_layer, size));
+                    }
+                    None => {
+                        progress.downloaded_layers += 1;
+                        progress.downloaded_bytes += size;
+                        progress.resumed_layers += 1;
+                    }
+                }
+            }
+        }
+        info!(
+            "downloading {} of {} layers, {} bytes",
+            downloads.len(),
+            progress.total_layers,
+            progress.total_bytes - progress.downloaded_bytes,
+        );
+        *self.attach_download.lock().unwrap() = Some(progress);
+
+        // up to a second's worth of bytes at once
+        let throttle = match config.max_bytes_per_second {
+            Some(bytes) => TokenBucket::new(tokens(bytes.get()), tokens(bytes.get())),
+            None => TokenBucket::unlimited(),
+        };
+        let throttle = &throttle;
+        let mut downloads = futures::stream::iter(downloads)
+            .map(|(timeline, remote_layer, size)| async move {
+                throttle.acquire(tokens(size)).await;
+                let span = info_span!("download_layer", timeline_id = %timeline.timeline_id);
+                timeline
+                    .download_remote_layer(remote_layer)
+                    .instrument(span)
+                    .await?;
+                anyhow::Ok(size)
+            })
+            .buffer_unordered(config.max_concurrent_downloads.get());
+
+        loop {
+            tokio::select! {
+                downloaded = downloads.next() => {
+                    let Some(size) = downloaded.transpose()? else {
+                        break;
+                    };
+                    let mut progress = self.attach_download.lock().unwrap();
+                    let progress = progress.as_mut().expect("set above");
+                    progress.downloaded_layers += 1;
+                    progress.downloaded_bytes += size;
+                }
+                _ = task_mgr::shutdown_watcher() => {
+                    anyhow::bail!("shutting down in the middle of the layer download");
+                }
+            }
+        }
+        info!("all layers downloaded");
+        Ok(())
+    }
+
+    /// The download of the layers at attach, if the pageserver downloads them.
+    pub fn attach_download_progress(&self) -> Option<AttachDownloadProgress> {
+        self.attach_download.lock().unwrap().clone()
+    }
+}
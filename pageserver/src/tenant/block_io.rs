@@ -4,8 +4,11 @@
 
 use crate::page_cache::{self, PageReadGuard, ReadBufResult, PAGE_SZ};
 use bytes::Bytes;
+use std::cmp::min;
+use std::io::{Error, ErrorKind, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
 use std::os::unix::fs::FileExt;
+use std::path::Path;
 use std::sync::atomic::AtomicU64;
 
 /// This is implemented by anything that can read 8 kB (PAGE_SZ)
@@ -127,6 +130,12 @@ pub struct FileBlockReader<F> {
 
     /// Unique ID of this file, used as key in the page cache.
     file_id: u64,
+
+    /// Expected checksums of the blocks of the file, see [`Self::with_checksums`].
+    checksums: Option<Vec<u32>>,
+
+    /// Where the blocks are, if they are compressed, see [`Self::with_compressed_blocks`].
+    block_map: Option<BlockMap>,
 }
 
 impl<F> FileBlockReader<F>
@@ -136,13 +145,62 @@ where
     pub fn new(file: F) -> Self {
         let file_id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-        FileBlockReader { file_id, file }
+        FileBlockReader {
+            file_id,
+            file,
+            checksums: None,
+            block_map: None,
+        }
+    }
+
+    /// Loads the map at the end of the file, `file_len` bytes long, that was
+    /// written through a [`CompressedBlockWriter`]. From then on, blocks are
+    /// read from where the map says, and decompressed.
+    ///
+    /// Block 0 is stored as is, so it can be read before. Other blocks must not
+    /// have been read before, and the checksums must be loaded after this.
+    pub fn with_compressed_blocks(mut self, file_len: u64) -> Result<Self, Error> {
+        self.block_map = Some(BlockMap::read(&self.file, file_len)?);
+        Ok(self)
+    }
+
+    /// Loads the checksum table stored at `checksums_blk`, which covers all the
+    /// blocks before it, see [`BlockChecksumWriter`]. From then on, blocks read
+    /// from disk are verified against it.
+    ///
+    /// Block 0, which holds the summary of a layer file and has usually been
+    /// read already to find the table, is verified right away. Other blocks
+    /// must not have been read before.
+    pub fn with_checksums(mut self, checksums_blk: u32) -> Result<Self, Error> {
+        if checksums_blk == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "checksum table cannot start at block 0",
+            ));
+        }
+        let checksums = read_checksums(&self, checksums_blk, checksums_blk)?;
+        verify_block_checksum(&self.read_blk(0)?, 0, checksums[0])?;
+        self.checksums = Some(checksums);
+        Ok(self)
     }
 
     /// Read a page from the underlying file into given buffer.
     fn fill_buffer(&self, buf: &mut [u8], blkno: u32) -> Result<(), std::io::Error> {
         assert!(buf.len() == PAGE_SZ);
-        self.file.read_exact_at(buf, blkno as u64 * PAGE_SZ as u64)
+        match &self.block_map {
+            Some(block_map) => block_map.read_blk(&self.file, blkno, buf)?,
+            None => self
+                .file
+                .read_exact_at(buf, blkno as u64 * PAGE_SZ as u64)?,
+        }
+        if let Some(expected) = self
+            .checksums
+            .as_ref()
+            .and_then(|checksums| checksums.get(blkno as usize))
+        {
+            verify_block_checksum(buf, blkno, *expected)?;
+        }
+        Ok(())
     }
 }
 
@@ -176,6 +234,379 @@ where
     }
 }
 
+/// Error for a block whose contents don't match its checksum.
+#[derive(Debug, thiserror::Error)]
+#[error("checksum mismatch in block {blknum}: expected {expected:#010x}, got {actual:#010x}")]
+pub struct BlockChecksumError {
+    pub blknum: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+/// Checks a block against its expected CRC32C checksum.
+pub fn verify_block_checksum(buf: &[u8], blknum: u32, expected: u32) -> Result<(), Error> {
+    let actual = crc32c::crc32c(buf);
+    if actual != expected {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            BlockChecksumError {
+                blknum,
+                expected,
+                actual,
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// Error for a compressed block that cannot be decompressed.
+#[derive(Debug, thiserror::Error)]
+#[error("block {blknum} cannot be decompressed")]
+pub struct BlockDecompressError {
+    pub blknum: u32,
+}
+
+/// Returns true if `err` was caused by a block that failed checksum verification,
+/// or that cannot be decompressed.
+pub fn is_checksum_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<BlockChecksumError>()
+            || cause.is::<BlockDecompressError>()
+            || cause
+                .downcast_ref::<Error>()
+                .map_or(false, is_corrupt_block)
+    })
+}
+
+fn is_corrupt_block(err: &Error) -> bool {
+    err.get_ref().map_or(false, |e| {
+        e.is::<BlockChecksumError>() || e.is::<BlockDecompressError>()
+    })
+}
+
+/// Serializes a checksum table for [`FileBlockReader::with_checksums`],
+/// padded to whole blocks.
+pub fn checksums_to_blocks(checksums: &[u32]) -> Vec<u8> {
+    let padded_len = (checksums.len() * 4 + PAGE_SZ - 1) / PAGE_SZ * PAGE_SZ;
+    let mut buf = Vec::with_capacity(padded_len);
+    for checksum in checksums {
+        buf.extend_from_slice(&checksum.to_be_bytes());
+    }
+    buf.resize(padded_len, 0);
+    buf
+}
+
+/// Reads the blocks of a new layer file after its summary block back, `len` bytes,
+/// and checks them against `crc`, the CRC32C of the [`ChecksummedWriter`] that
+/// wrote them. See [`PageServerConf::verify_layer_writes`].
+///
+/// [`ChecksummedWriter`]: utils::checksummed_writer::ChecksummedWriter
+/// [`PageServerConf::verify_layer_writes`]: crate::config::PageServerConf::verify_layer_writes
+pub fn verify_written_blocks(path: &Path, len: u64, crc: u32) -> Result<(), Error> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(PAGE_SZ as u64))?;
+    utils::checksummed_writer::verify_checksum(file, len, crc)
+}
+
+/// Reads `count` checksums written by [`checksums_to_blocks`] at `start_blk`.
+pub fn read_checksums<R: BlockReader>(
+    reader: &R,
+    start_blk: u32,
+    count: u32,
+) -> Result<Vec<u32>, Error> {
+    let mut checksums = Vec::with_capacity(count as usize);
+    let mut blknum = start_blk;
+    while checksums.len() < count as usize {
+        let buf = reader.read_blk(blknum)?;
+        let n = min(count as usize - checksums.len(), PAGE_SZ / 4);
+        checksums.extend(
+            buf[..n * 4]
+                .chunks_exact(4)
+                .map(|c| u32::from_be_bytes(c.try_into().unwrap())),
+        );
+        blknum += 1;
+    }
+    Ok(checksums)
+}
+
+/// Reads all the blocks of a file that has a checksum table at `checksums_blk`
+/// with `read_blk`, and returns the numbers of the blocks that don't match their
+/// checksums, or cannot be decompressed.
+///
+/// Unlike [`FileBlockReader::with_checksums`], this doesn't go through the page
+/// cache, so it also checks blocks that are currently cached.
+pub fn find_corrupt_blocks<F>(mut read_blk: F, checksums_blk: u32) -> Result<Vec<u32>, Error>
+where
+    F: FnMut(u32, &mut [u8]) -> Result<(), Error>,
+{
+    let mut buf = [0u8; PAGE_SZ];
+    let mut actual = Vec::with_capacity(checksums_blk as usize);
+    for blknum in 0..checksums_blk {
+        actual.push(match read_blk(blknum, &mut buf) {
+            Ok(()) => Some(crc32c::crc32c(&buf)),
+            Err(e) if is_corrupt_block(&e) => None,
+            Err(e) => return Err(e),
+        });
+    }
+    let mut expected = Vec::with_capacity(checksums_blk as usize);
+    let mut blknum = checksums_blk;
+    while expected.len() < checksums_blk as usize {
+        read_blk(blknum, &mut buf)?;
+        let n = min(checksums_blk as usize - expected.len(), PAGE_SZ / 4);
+        expected.extend(
+            buf[..n * 4]
+                .chunks_exact(4)
+                .map(|c| u32::from_be_bytes(c.try_into().unwrap())),
+        );
+        blknum += 1;
+    }
+    Ok(expected
+        .into_iter()
+        .zip(actual)
+        .enumerate()
+        .filter(|(_, (expected, actual))| Some(*expected) != *actual)
+        .map(|(blknum, _)| blknum as u32)
+        .collect())
+}
+
+/// Like [`find_corrupt_blocks`], for a file of `file_len` bytes, whose blocks
+/// have been written through a [`CompressedBlockWriter`] if `compressed`.
+pub fn find_corrupt_file_blocks<F: FileExt>(
+    file: &F,
+    file_len: u64,
+    checksums_blk: u32,
+    compressed: bool,
+) -> Result<Vec<u32>, Error> {
+    if compressed {
+        let block_map = BlockMap::read(file, file_len)?;
+        find_corrupt_blocks(
+            |blknum, buf| block_map.read_blk(file, blknum, buf),
+            checksums_blk,
+        )
+    } else {
+        find_corrupt_blocks(
+            |blknum, buf| file.read_exact_at(buf, blknum as u64 * PAGE_SZ as u64),
+            checksums_blk,
+        )
+    }
+}
+
+/// The locations of the blocks of a file written through a [`CompressedBlockWriter`].
+///
+/// The map is stored at the end of the file: the offset of each block, and of
+/// the end of the last one, followed by the offset of the map itself, all as
+/// big-endian u64. A block that takes a whole [`PAGE_SZ`] is stored as is.
+pub struct BlockMap {
+    offsets: Vec<u64>,
+}
+
+impl BlockMap {
+    /// Reads the map at the end of a file of `file_len` bytes.
+    pub fn read<F: FileExt>(file: &F, file_len: u64) -> Result<Self, Error> {
+        let corrupt = || Error::new(ErrorKind::InvalidData, "corrupt block map");
+        let map_end = file_len.checked_sub(8).ok_or_else(corrupt)?;
+        let mut trailer = [0u8; 8];
+        file.read_exact_at(&mut trailer, map_end)?;
+        let map_start = u64::from_be_bytes(trailer);
+        if map_start >= map_end || (map_end - map_start) % 8 != 0 {
+            return Err(corrupt());
+        }
+        let mut buf = vec![0u8; (map_end - map_start) as usize];
+        file.read_exact_at(&mut buf, map_start)?;
+        let offsets: Vec<u64> = buf
+            .chunks_exact(8)
+            .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        if offsets.windows(2).any(|w| w[0] > w[1]) || offsets[offsets.len() - 1] > map_start {
+            return Err(corrupt());
+        }
+        Ok(BlockMap { offsets })
+    }
+
+    /// Reads block `blknum` of `file` into `buf`, decompressing it if needed.
+    pub fn read_blk<F: FileExt>(&self, file: &F, blknum: u32, buf: &mut [u8]) -> Result<(), Error> {
+        assert!(buf.len() == PAGE_SZ);
+        let Some(&[start, end]) = self.offsets.get(blknum as usize..blknum as usize + 2) else {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("block {blknum} is past the end of the file"),
+            ));
+        };
+        if end - start == PAGE_SZ as u64 {
+            return file.read_exact_at(buf, start);
+        }
+        let mut compressed = vec![0u8; (end - start) as usize];
+        file.read_exact_at(&mut compressed, start)?;
+        match zstd::bulk::decompress_to_buffer(&compressed, buf) {
+            Ok(PAGE_SZ) => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                BlockDecompressError { blknum },
+            )),
+        }
+    }
+}
+
+/// A writer that compresses each block written through it with zstd, on its own,
+/// so that the blocks can still be read one at a time.
+///
+/// Writing starts at block `start_blk` of the file, the blocks before it are
+/// stored as is. So is a block that doesn't get smaller. The blocks keep their
+/// numbers, [`Self::finish`] appends the [`BlockMap`] that locates them in the
+/// file. Without a compressor, blocks are written through as is, without a map.
+pub struct CompressedBlockWriter<W> {
+    inner: W,
+    compressor: Option<zstd::bulk::Compressor<'static>>,
+    /// The current, incomplete block.
+    buf: Vec<u8>,
+    /// Offsets of the blocks written so far, and of the end of the last one.
+    offsets: Vec<u64>,
+}
+
+impl<W: Write> CompressedBlockWriter<W> {
+    /// Compresses the blocks at `level`, if set.
+    pub fn new(inner: W, start_blk: u32, level: Option<i32>) -> Result<Self, Error> {
+        Ok(CompressedBlockWriter {
+            inner,
+            compressor: level.map(zstd::bulk::Compressor::new).transpose()?,
+            buf: Vec::with_capacity(PAGE_SZ),
+            offsets: (0..=start_blk as u64)
+                .map(|blknum| blknum * PAGE_SZ as u64)
+                .collect(),
+        })
+    }
+
+    /// Whether the blocks are compressed, and the file needs a [`BlockMap`] to be read.
+    pub fn is_compressed(&self) -> bool {
+        self.compressor.is_some()
+    }
+
+    /// Appends the map of the blocks, if compressed, and returns the underlying
+    /// writer. Must be called at a block boundary.
+    pub fn finish(mut self) -> Result<W, Error> {
+        assert!(self.buf.is_empty(), "not at a block boundary");
+        if self.is_compressed() {
+            let map_start = self.offsets[self.offsets.len() - 1];
+            let mut map = Vec::with_capacity((self.offsets.len() + 1) * 8);
+            for offset in self.offsets.iter().chain([&map_start]) {
+                map.extend_from_slice(&offset.to_be_bytes());
+            }
+            self.inner.write_all(&map)?;
+        }
+        Ok(self.inner)
+    }
+
+    /// Returns the underlying writer, without the map, e.g. to remove an
+    /// unfinished file.
+    pub fn into_writer(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CompressedBlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let Some(compressor) = &mut self.compressor else {
+            return self.inner.write(buf);
+        };
+        let n = min(PAGE_SZ - self.buf.len(), buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == PAGE_SZ {
+            let compressed = compressor.compress(&self.buf)?;
+            let stored = if compressed.len() < PAGE_SZ {
+                &compressed[..]
+            } else {
+                &self.buf[..]
+            };
+            self.inner.write_all(stored)?;
+            let end = self.offsets[self.offsets.len() - 1] + stored.len() as u64;
+            self.offsets.push(end);
+            self.buf.clear();
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that computes the CRC32C checksum of each block written through it.
+///
+/// Writing starts at block `start_blk` of the file. To skip to the next block,
+/// use [`Self::pad_to_block`] rather than seeking, so that the skipped bytes are
+/// part of the checksum.
+pub struct BlockChecksumWriter<W> {
+    inner: W,
+    start_blk: u32,
+    /// Checksums of the completed blocks, starting from `start_blk`.
+    checksums: Vec<u32>,
+    /// Running checksum of the current, incomplete block.
+    crc: u32,
+    pos_in_blk: usize,
+}
+
+impl<W: Write> BlockChecksumWriter<W> {
+    pub fn new(inner: W, start_blk: u32) -> Self {
+        BlockChecksumWriter {
+            inner,
+            start_blk,
+            checksums: Vec::new(),
+            crc: 0,
+            pos_in_blk: 0,
+        }
+    }
+
+    /// Fills the rest of the current block with zeros.
+    pub fn pad_to_block(&mut self) -> Result<(), Error> {
+        if self.pos_in_blk > 0 {
+            let zeros = [0u8; PAGE_SZ];
+            self.write_all(&zeros[..PAGE_SZ - self.pos_in_blk])?;
+        }
+        Ok(())
+    }
+
+    /// Number of the next block to be written. Only meaningful at a block boundary.
+    pub fn next_blk(&self) -> u32 {
+        self.start_blk + self.checksums.len() as u32
+    }
+
+    /// Returns the underlying writer, and the checksums of the blocks written,
+    /// starting from `start_blk`. Must be called at a block boundary.
+    pub fn into_inner(self) -> (W, Vec<u32>) {
+        assert_eq!(self.pos_in_blk, 0, "not at a block boundary");
+        (self.inner, self.checksums)
+    }
+
+    /// Returns the underlying writer, without the checksums, e.g. to remove an
+    /// unfinished file.
+    pub fn into_writer(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for BlockChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write(buf)?;
+        let mut written = &buf[..n];
+        while !written.is_empty() {
+            let len = min(PAGE_SZ - self.pos_in_blk, written.len());
+            self.crc = crc32c::crc32c_append(self.crc, &written[..len]);
+            self.pos_in_blk += len;
+            written = &written[len..];
+            if self.pos_in_blk == PAGE_SZ {
+                self.checksums.push(self.crc);
+                self.crc = 0;
+                self.pos_in_blk = 0;
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
 ///
 /// Trait for block-oriented output
 ///
@@ -218,3 +649,133 @@ impl Default for BlockBuf {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestFile(Vec<u8>);
+
+    impl TestFile {
+        fn read_into(&self, blknum: u32, buf: &mut [u8]) -> Result<(), Error> {
+            let start = blknum as usize * PAGE_SZ;
+            buf.copy_from_slice(&self.0[start..start + PAGE_SZ]);
+            Ok(())
+        }
+    }
+
+    impl BlockReader for TestFile {
+        fn read_blk(&self, blknum: u32) -> Result<BlockLease, Error> {
+            let start = blknum as usize * PAGE_SZ;
+            let mut buf = [0u8; PAGE_SZ];
+            buf.copy_from_slice(&self.0[start..start + PAGE_SZ]);
+            Ok(std::rc::Rc::new(buf).into())
+        }
+    }
+
+    #[test]
+    fn test_block_checksums() -> anyhow::Result<()> {
+        let mut writer = BlockChecksumWriter::new(vec![0u8; PAGE_SZ], 1);
+        for i in 0..10000u32 {
+            writer.write_all(format!("data{i}").as_bytes())?;
+        }
+        writer.pad_to_block()?;
+        let checksums_blk = writer.next_blk();
+        let (mut data, block_checksums) = writer.into_inner();
+        assert_eq!(data.len(), checksums_blk as usize * PAGE_SZ);
+
+        let mut checksums = vec![crc32c::crc32c(&data[..PAGE_SZ])];
+        checksums.extend(block_checksums);
+        for (blknum, chunk) in data.chunks_exact(PAGE_SZ).enumerate() {
+            assert_eq!(crc32c::crc32c(chunk), checksums[blknum]);
+        }
+        data.extend(checksums_to_blocks(&checksums));
+
+        let file = TestFile(data);
+        assert_eq!(
+            read_checksums(&file, checksums_blk, checksums_blk)?,
+            checksums
+        );
+
+        assert!(
+            find_corrupt_blocks(|blknum, buf| file.read_into(blknum, buf), checksums_blk)?
+                .is_empty()
+        );
+
+        // A corrupt block is detected
+        let mut corrupt = file.read_blk(2)?.to_vec();
+        corrupt[100] ^= 1;
+        let err = verify_block_checksum(&corrupt, 2, checksums[2]).unwrap_err();
+        assert!(is_checksum_error(
+            &anyhow::Error::new(err).context("read block")
+        ));
+        let mut file = file;
+        file.0[2 * PAGE_SZ + 100] ^= 1;
+        assert_eq!(
+            find_corrupt_blocks(|blknum, buf| file.read_into(blknum, buf), checksums_blk)?,
+            vec![2]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_blocks() -> anyhow::Result<()> {
+        let mut writer = CompressedBlockWriter::new(vec![0u8; PAGE_SZ], 1, Some(3))?;
+        assert!(writer.is_compressed());
+        // Compressible blocks, then one that isn't
+        let mut blocks = Vec::new();
+        for i in 0..4u8 {
+            blocks.push([i; PAGE_SZ]);
+        }
+        let mut random = [0u8; PAGE_SZ];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut random[..]);
+        blocks.push(random);
+        for block in &blocks {
+            writer.write_all(block)?;
+        }
+        let mut data = writer.finish()?;
+        assert!(data.len() < 3 * PAGE_SZ);
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&data)?;
+
+        let map = BlockMap::read(&file, data.len() as u64)?;
+        let mut buf = [0u8; PAGE_SZ];
+        map.read_blk(&file, 0, &mut buf)?;
+        assert_eq!(buf, [0u8; PAGE_SZ]);
+        for (i, block) in blocks.iter().enumerate() {
+            map.read_blk(&file, i as u32 + 1, &mut buf)?;
+            assert_eq!(&buf, block);
+        }
+        assert!(map
+            .read_blk(&file, blocks.len() as u32 + 1, &mut buf)
+            .is_err());
+
+        // A block that cannot be decompressed counts as corrupt
+        data[PAGE_SZ] ^= 0xff;
+        file.write_all_at(&data, 0)?;
+        let err = map.read_blk(&file, 1, &mut buf).unwrap_err();
+        assert!(is_checksum_error(&anyhow::Error::new(err)));
+        Ok(())
+    }
+    #[test]
+    fn test_verify_written_blocks() -> anyhow::Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        // Like the layer writers, write the blocks after the summary block first
+        let mut inner = file.reopen()?;
+        inner.seek(SeekFrom::Start(PAGE_SZ as u64))?;
+        let mut writer = utils::checksummed_writer::ChecksummedWriter::new(inner);
+        for i in 0..1000u32 {
+            writer.write_all(format!("data{i}").as_bytes())?;
+        }
+        let written = writer.written();
+        let (inner, crc) = writer.into_inner()?;
+        inner.write_all_at(&[1u8; PAGE_SZ], 0)?;
+
+        verify_written_blocks(file.path(), written, crc)?;
+
+        inner.write_all_at(&[0xff], PAGE_SZ as u64 + 10)?;
+        let err = verify_written_blocks(file.path(), written, crc).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//!
+//! A bloom filter over the keys stored in a delta layer file.
+//!
+//! The filter lets layer map traversal skip delta layers that definitely
+//! don't contain a key, without searching the layer's B-tree index.
+//!
+//! On disk, the filter starts at a block boundary. It begins with a header
+//! of two big-endian u32 fields: the number of hash functions, and the
+//! number of 64-bit words in the bit array. The words follow, also in
+//! big-endian. The last block is zero-padded.
+//!
+use crate::page_cache::PAGE_SZ;
+use crate::tenant::block_io::BlockReader;
+use std::cmp::min;
+use std::io::{Error, ErrorKind};
+
+/// Number of bits in the filter per key. With `NUM_HASHES` hash functions,
+/// this gives a false positive rate of about 1%.
+const BITS_PER_KEY: usize = 10;
+const NUM_HASHES: u32 = 7;
+
+/// Seed of the second CRC used to derive the key hash.
+const HASH_SEED: u32 = 0x9e37_79b9;
+
+const HEADER_SIZE: usize = 8;
+
+/// Computes the hash of a key, as used by [`BloomFilter`].
+///
+/// The hash is part of the on-disk format, so it must not change.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let hash = (crc32c::crc32c(key) as u64) << 32 | crc32c::crc32c_append(HASH_SEED, key) as u64;
+    mix64(hash)
+}
+
+/// Finalizer of the splitmix64 generator, to spread the bits of a hash.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub struct BloomFilter {
+    num_hashes: u32,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Builds a filter over the keys with the given hashes, see [`key_hash`].
+    pub fn from_hashes(hashes: &[u64]) -> Self {
+        let num_words = (hashes.len() * BITS_PER_KEY + 63) / 64;
+        let mut filter = BloomFilter {
+            num_hashes: NUM_HASHES,
+            words: vec![0; num_words.max(1)],
+        };
+        for hash in hashes {
+            for bit in filter.bit_positions(*hash) {
+                filter.words[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    /// Returns false if the key with the given hash is definitely not in the filter.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.bit_positions(hash)
+            .all(|bit| self.words[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Positions of the bits for a key hash, using double hashing.
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.words.len() as u64 * 64;
+        let h1 = hash;
+        let h2 = mix64(hash) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    /// Serializes the filter, padded to a whole number of blocks.
+    pub fn to_blocks(&self) -> Vec<u8> {
+        let len = HEADER_SIZE + self.words.len() * 8;
+        let padded_len = (len + PAGE_SZ - 1) / PAGE_SZ * PAGE_SZ;
+        let mut buf = Vec::with_capacity(padded_len);
+        buf.extend_from_slice(&self.num_hashes.to_be_bytes());
+        buf.extend_from_slice(&(self.words.len() as u32).to_be_bytes());
+        for word in &self.words {
+            buf.extend_from_slice(&word.to_be_bytes());
+        }
+        buf.resize(padded_len, 0);
+        buf
+    }
+
+    /// Reads a filter stored at the given block.
+    pub fn read<R: BlockReader>(reader: &R, start_blk: u32) -> Result<Self, Error> {
+        let mut blknum = start_blk;
+        let blk = reader.read_blk(blknum)?;
+        let num_hashes = u32::from_be_bytes(blk[0..4].try_into().unwrap());
+        let num_words = u32::from_be_bytes(blk[4..8].try_into().unwrap()) as usize;
+        if num_hashes == 0 || num_words == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid bloom filter header at block {start_blk}"),
+            ));
+        }
+
+        let len = HEADER_SIZE + num_words * 8;
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&blk[..min(len, PAGE_SZ)]);
+        drop(blk);
+        while buf.len() < len {
+            blknum += 1;
+            let blk = reader.read_blk(blknum)?;
+            buf.extend_from_slice(&blk[..min(len - buf.len(), PAGE_SZ)]);
+        }
+
+        let words = buf[HEADER_SIZE..]
+            .chunks_exact(8)
+            .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+            .collect();
+        Ok(BloomFilter { num_hashes, words })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::block_io::BlockLease;
+
+    struct TestFile(Vec<u8>);
+
+    impl BlockReader for TestFile {
+        fn read_blk(&self, blknum: u32) -> Result<BlockLease, Error> {
+            let start = blknum as usize * PAGE_SZ;
+            let mut buf = [0u8; PAGE_SZ];
+            buf.copy_from_slice(&self.0[start..start + PAGE_SZ]);
+            Ok(std::rc::Rc::new(buf).into())
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter() -> Result<(), Error> {
+        let hashes: Vec<u64> = (0..10000u32).map(|i| key_hash(&i.to_be_bytes())).collect();
+        let filter = BloomFilter::from_hashes(&hashes);
+
+        // Round-trip through the on-disk format, starting at a block other than 0
+        let mut file = TestFile(vec![0xff; PAGE_SZ]);
+        file.0.extend(filter.to_blocks());
+        assert_eq!(file.0.len() % PAGE_SZ, 0);
+        let filter = BloomFilter::read(&file, 1)?;
+
+        // No false negatives
+        assert!(hashes.iter().all(|hash| filter.may_contain(*hash)));
+
+        // Few false positives
+        let false_positives = (10000..20000u32)
+            .filter(|i| filter.may_contain(key_hash(&i.to_be_bytes())))
+            .count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_bloom_filter() -> Result<(), Error> {
+        let filter = BloomFilter::from_hashes(&[]);
+        let file = TestFile(filter.to_blocks());
+        let filter = BloomFilter::read(&file, 0)?;
+        assert!(!filter.may_contain(key_hash(b"foo")));
+        Ok(())
+    }
+}
@@ -8,11 +8,17 @@
 //! We cannot use global or default config instead, because wrong settings
 //! may lead to a data loss.
 //!
-use anyhow::Context;
+use anyhow::{bail, Context};
 use pageserver_api::models;
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU64;
+use std::fs;
+use std::io::Write;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::Path;
 use std::time::Duration;
+use utils::crashsafe::{self, path_with_suffix_extension};
+
+use crate::TEMP_FILE_SUFFIX;
 
 pub mod defaults {
     // FIXME: This current value is very low. I would imagine something like 1 GB or 10 GB
@@ -37,11 +43,14 @@ pub mod defaults {
     // Relevant: https://github.com/neondatabase/neon/issues/3394
     pub const DEFAULT_GC_PERIOD: &str = "1 hr";
     pub const DEFAULT_IMAGE_CREATION_THRESHOLD: usize = 3;
+    pub const DEFAULT_SLRU_IMAGE_CREATION_THRESHOLD: usize = 2;
     pub const DEFAULT_PITR_INTERVAL: &str = "7 days";
     pub const DEFAULT_WALRECEIVER_CONNECT_TIMEOUT: &str = "10 seconds";
     pub const DEFAULT_WALRECEIVER_LAGGING_WAL_TIMEOUT: &str = "10 seconds";
     pub const DEFAULT_MAX_WALRECEIVER_LSN_WAL_LAG: u64 = 10 * 1024 * 1024;
     pub const DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD: &str = "24 hour";
+    pub const DEFAULT_GET_PAGE_WEIGHT: u32 = 1;
+    pub const DEFAULT_LOGICAL_SIZE_RECONCILE_PERIOD: &str = "24 h";
 
     pub const DEFAULT_INGEST_BATCH_SIZE: u64 = 100;
 }
@@ -78,6 +87,8 @@ pub struct TenantConf {
     pub gc_period: Duration,
     // Delta layer churn threshold to create L1 image layers.
     pub image_creation_threshold: usize,
+    // Same as image_creation_threshold, for the partitions holding SLRU data.
+    pub slru_image_creation_threshold: usize,
     // Determines how much history is retained, to allow
     // branching and read replicas at an older point in time.
     // The unit is time.
@@ -102,6 +113,24 @@ pub struct TenantConf {
     #[serde(with = "humantime_serde")]
     pub evictions_low_residence_duration_metric_threshold: Duration,
     pub gc_feedback: bool,
+    /// If set, the blocks of newly written layer files are compressed with zstd
+    /// at this level. Existing layer files are not rewritten.
+    pub layer_compression_level: Option<i32>,
+    /// Share of the GetPage concurrency limit the tenant gets when the pageserver
+    /// is busy, relative to the weights of the other tenants.
+    pub get_page_weight: NonZeroU32,
+    /// How often the logical size maintained during WAL ingest is checked against
+    /// a calculation from the layers, and corrected if they differ.
+    /// Duration::ZERO means the check is disabled.
+    #[serde(with = "humantime_serde")]
+    pub logical_size_reconcile_period: Duration,
+    /// If set, the page service requests of the tenant are limited to this many
+    /// per second, with bursts of up to a second's worth of requests.
+    pub pagestream_rate_limit: Option<NonZeroU32>,
+    /// How long the requests of the tenant wait for the WAL up to their LSN to
+    /// arrive before failing. The pageserver-wide `wait_lsn_timeout` if unset.
+    #[serde(with = "humantime_serde")]
+    pub wait_lsn_timeout: Option<Duration>,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -143,6 +172,10 @@ pub struct TenantConfOpt {
     #[serde(default)]
     pub image_creation_threshold: Option<usize>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub slru_image_creation_threshold: Option<usize>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(with = "humantime_serde")]
     #[serde(default)]
@@ -182,6 +215,28 @@ pub struct TenantConfOpt {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub gc_feedback: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub layer_compression_level: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub get_page_weight: Option<NonZeroU32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub logical_size_reconcile_period: Option<Duration>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub pagestream_rate_limit: Option<NonZeroU32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub wait_lsn_timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -231,6 +286,9 @@ impl TenantConfOpt {
             image_creation_threshold: self
                 .image_creation_threshold
                 .unwrap_or(global_conf.image_creation_threshold),
+            slru_image_creation_threshold: self
+                .slru_image_creation_threshold
+                .unwrap_or(global_conf.slru_image_creation_threshold),
             pitr_interval: self.pitr_interval.unwrap_or(global_conf.pitr_interval),
             walreceiver_connect_timeout: self
                 .walreceiver_connect_timeout
@@ -250,6 +308,17 @@ impl TenantConfOpt {
                 .evictions_low_residence_duration_metric_threshold
                 .unwrap_or(global_conf.evictions_low_residence_duration_metric_threshold),
             gc_feedback: self.gc_feedback.unwrap_or(global_conf.gc_feedback),
+            layer_compression_level: self
+                .layer_compression_level
+                .or(global_conf.layer_compression_level),
+            get_page_weight: self.get_page_weight.unwrap_or(global_conf.get_page_weight),
+            logical_size_reconcile_period: self
+                .logical_size_reconcile_period
+                .unwrap_or(global_conf.logical_size_reconcile_period),
+            pagestream_rate_limit: self
+                .pagestream_rate_limit
+                .or(global_conf.pagestream_rate_limit),
+            wait_lsn_timeout: self.wait_lsn_timeout.or(global_conf.wait_lsn_timeout),
         }
     }
 }
@@ -269,6 +338,7 @@ impl Default for TenantConf {
             gc_period: humantime::parse_duration(DEFAULT_GC_PERIOD)
                 .expect("cannot parse default gc period"),
             image_creation_threshold: DEFAULT_IMAGE_CREATION_THRESHOLD,
+            slru_image_creation_threshold: DEFAULT_SLRU_IMAGE_CREATION_THRESHOLD,
             pitr_interval: humantime::parse_duration(DEFAULT_PITR_INTERVAL)
                 .expect("cannot parse default PITR interval"),
             walreceiver_connect_timeout: humantime::parse_duration(
@@ -287,10 +357,32 @@ impl Default for TenantConf {
             )
             .expect("cannot parse default evictions_low_residence_duration_metric_threshold"),
             gc_feedback: false,
+            layer_compression_level: None,
+            get_page_weight: NonZeroU32::new(DEFAULT_GET_PAGE_WEIGHT)
+                .expect("cannot parse default get_page_weight"),
+            logical_size_reconcile_period: humantime::parse_duration(
+                DEFAULT_LOGICAL_SIZE_RECONCILE_PERIOD,
+            )
+            .expect("cannot parse default logical size reconcile period"),
+            pagestream_rate_limit: None,
+            wait_lsn_timeout: None,
         }
     }
 }
 
+/// Checks that `level` is a compression level supported by zstd.
+pub fn validate_layer_compression_level(level: i32) -> anyhow::Result<i32> {
+    let range = zstd::compression_level_range();
+    if !range.contains(&level) {
+        bail!(
+            "layer_compression_level {level} is out of range {}..={}",
+            range.start(),
+            range.end()
+        );
+    }
+    Ok(level)
+}
+
 // Helper function to standardize the error messages we produce on bad durations
 //
 // Intended to be used with anyhow's `with_context`, e.g.:
@@ -315,6 +407,7 @@ impl TryFrom<&'_ models::TenantConfig> for TenantConfOpt {
         }
         tenant_conf.gc_horizon = request_data.gc_horizon;
         tenant_conf.image_creation_threshold = request_data.image_creation_threshold;
+        tenant_conf.slru_image_creation_threshold = request_data.slru_image_creation_threshold;
 
         if let Some(pitr_interval) = &request_data.pitr_interval {
             tenant_conf.pitr_interval = Some(
@@ -382,11 +475,95 @@ impl TryFrom<&'_ models::TenantConfig> for TenantConfOpt {
             );
         }
         tenant_conf.gc_feedback = request_data.gc_feedback;
+        tenant_conf.layer_compression_level = request_data
+            .layer_compression_level
+            .map(validate_layer_compression_level)
+            .transpose()?;
+        tenant_conf.get_page_weight = request_data.get_page_weight;
+        if let Some(period) = &request_data.logical_size_reconcile_period {
+            tenant_conf.logical_size_reconcile_period = Some(
+                humantime::parse_duration(period)
+                    .with_context(bad_duration("logical_size_reconcile_period", period))?,
+            );
+        }
+        tenant_conf.pagestream_rate_limit = request_data.pagestream_rate_limit;
+        if let Some(timeout) = &request_data.wait_lsn_timeout {
+            tenant_conf.wait_lsn_timeout = Some(
+                humantime::parse_duration(timeout)
+                    .with_context(bad_duration("wait_lsn_timeout", timeout))?,
+            );
+        }
 
         Ok(tenant_conf)
     }
 }
 
+/// Overrides of the tenant config for a single timeline, e.g. to flush the idle
+/// branches of a tenant less often than its main branch. They are kept in the
+/// timeline directory, and, like the labels, not uploaded to the remote storage
+/// nor inherited by the branches of the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct TimelineConfOpt {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub checkpoint_distance: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub checkpoint_timeout: Option<Duration>,
+}
+
+impl TimelineConfOpt {
+    /// Reads the config file of a timeline. A missing file means no overrides.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("read timeline config {}", path.display()));
+            }
+        };
+        toml_edit::de::from_str(&contents)
+            .with_context(|| format!("parse timeline config {}", path.display()))
+    }
+
+    /// Replaces the config file of a timeline, atomically.
+    pub fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = toml_edit::ser::to_string(self)?;
+        let temp_path = path_with_suffix_extension(path, TEMP_FILE_SUFFIX);
+        let mut file = fs::File::create(&temp_path)
+            .with_context(|| format!("create timeline config {}", temp_path.display()))?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("rename timeline config to {}", path.display()))?;
+        crashsafe::fsync_file_and_parent(path)?;
+        Ok(())
+    }
+}
+
+impl TryFrom<&'_ models::TimelineConfigRequest> for TimelineConfOpt {
+    type Error = anyhow::Error;
+
+    fn try_from(request_data: &'_ models::TimelineConfigRequest) -> Result<Self, Self::Error> {
+        let checkpoint_timeout = match &request_data.checkpoint_timeout {
+            Some(timeout) => Some(
+                humantime::parse_duration(timeout)
+                    .with_context(bad_duration("checkpoint_timeout", timeout))?,
+            ),
+            None => None,
+        };
+        if request_data.checkpoint_distance == Some(0) {
+            bail!("checkpoint_distance must be positive");
+        }
+        Ok(TimelineConfOpt {
+            checkpoint_distance: request_data.checkpoint_distance,
+            checkpoint_timeout,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -406,4 +583,27 @@ mod tests {
         assert_eq!(json_form, "{\"gc_horizon\":42}");
         assert_eq!(small_conf, serde_json::from_str(&json_form).unwrap());
     }
+
+    #[test]
+    fn timeline_conf_roundtrip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(crate::TIMELINE_CONFIG_FILE_NAME);
+        assert_eq!(TimelineConfOpt::load(&path)?, TimelineConfOpt::default());
+
+        let request = models::TimelineConfigRequest {
+            checkpoint_distance: None,
+            checkpoint_timeout: Some("1h".to_string()),
+        };
+        let conf = TimelineConfOpt::try_from(&request)?;
+        assert_eq!(conf.checkpoint_timeout, Some(Duration::from_secs(3600)));
+        conf.persist(&path)?;
+        assert_eq!(TimelineConfOpt::load(&path)?, conf);
+
+        let request = models::TimelineConfigRequest {
+            checkpoint_distance: Some(0),
+            checkpoint_timeout: None,
+        };
+        assert!(TimelineConfOpt::try_from(&request).is_err());
+        Ok(())
+    }
 }
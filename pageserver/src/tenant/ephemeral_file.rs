@@ -1,7 +1,14 @@
 //! Implementation of append-only file data structure
 //! used to keep in-memory layers spilled on disk.
+//!
+//! The pages of the file live in the page cache, and are written to disk when
+//! the page cache evicts them. With `ephemeral_file_compression`, they are
+//! compressed with lz4 when they're written, and decompressed when they're read
+//! back. The compressed pages are appended to the file, and found through an
+//! in-memory map of the spilled pages.
 
 use crate::config::PageServerConf;
+use crate::metrics::EPHEMERAL_FILE_SPILLED_BYTES;
 use crate::page_cache::{self, ReadBufResult, WriteBufResult, PAGE_SZ};
 use crate::tenant::blob_io::BlobWriter;
 use crate::tenant::block_io::{BlockLease, BlockReader};
@@ -13,7 +20,7 @@ use std::fs::OpenOptions;
 use std::io::{self, ErrorKind};
 use std::ops::DerefMut;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use tracing::*;
 use utils::id::{TenantId, TimelineId};
 
@@ -32,18 +39,150 @@ static EPHEMERAL_FILES: Lazy<RwLock<EphemeralFiles>> = Lazy::new(|| {
 pub struct EphemeralFiles {
     next_file_id: u64,
 
-    files: HashMap<u64, Arc<VirtualFile>>,
+    files: HashMap<u64, Arc<SpillFile>>,
 }
 
 pub struct EphemeralFile {
     file_id: u64,
     _tenant_id: TenantId,
     _timeline_id: TimelineId,
-    file: Arc<VirtualFile>,
+    file: Arc<SpillFile>,
 
     pub size: u64,
 }
 
+/// The file on disk of an [`EphemeralFile`], which the page cache writes the
+/// evicted pages to.
+///
+/// The page cache doesn't read a page from the file while it's writing it back,
+/// nor writes it twice at once, so the I/O on a page is done outside of the
+/// lock on the map of the compressed pages.
+struct SpillFile {
+    file: VirtualFile,
+    /// Where the pages are in the file, if they're compressed. Otherwise, page
+    /// `blkno` is at offset `blkno * PAGE_SZ`.
+    compressed: Option<Mutex<CompressedPages>>,
+}
+
+#[derive(Default)]
+struct CompressedPages {
+    slots: HashMap<u32, Slot>,
+    /// End of the file, where the pages are appended.
+    end: u64,
+}
+
+/// The place of a page in a file of compressed pages.
+#[derive(Clone, Copy)]
+struct Slot {
+    offset: u64,
+    /// Length of the compressed page, or `PAGE_SZ` if the page is stored as is
+    /// because it doesn't compress.
+    len: usize,
+    /// Space available for the page, when it's written again.
+    capacity: usize,
+}
+
+impl SpillFile {
+    /// Reads page `blkno` into `buf`. The pages that were never written are zeros.
+    fn read_page(&self, buf: &mut [u8], blkno: u32) -> Result<(), io::Error> {
+        let Some(compressed) = &self.compressed else {
+            return self.read_raw_page(buf, blkno);
+        };
+        let slot = compressed.lock().unwrap().slots.get(&blkno).copied();
+        let Some(slot) = slot else {
+            buf.fill(0);
+            return Ok(());
+        };
+        if slot.len == PAGE_SZ {
+            return self.file.read_exact_at(buf, slot.offset);
+        }
+        let mut page = vec![0u8; slot.len];
+        self.file.read_exact_at(&mut page, slot.offset)?;
+        match lz4_flex::block::decompress_into(&page, buf) {
+            Ok(PAGE_SZ) => Ok(()),
+            Ok(len) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("page {blkno} decompressed to {len} bytes"),
+            )),
+            Err(e) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to decompress page {blkno}: {e}"),
+            )),
+        }
+    }
+
+    fn read_raw_page(&self, buf: &mut [u8], blkno: u32) -> Result<(), io::Error> {
+        let mut off = 0;
+        while off < PAGE_SZ {
+            let n = self
+                .file
+                .read_at(&mut buf[off..], blkno as u64 * PAGE_SZ as u64 + off as u64)?;
+
+            if n == 0 {
+                // Reached EOF. Fill the rest of the buffer with zeros.
+                const ZERO_BUF: [u8; PAGE_SZ] = [0u8; PAGE_SZ];
+
+                buf[off..].copy_from_slice(&ZERO_BUF[off..]);
+                break;
+            }
+
+            off += n;
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, buf: &[u8], blkno: u32) -> Result<(), io::Error> {
+        let Some(compressed) = &self.compressed else {
+            self.file.write_all_at(buf, blkno as u64 * PAGE_SZ as u64)?;
+            EPHEMERAL_FILE_SPILLED_BYTES
+                .with_label_values(&["logical"])
+                .inc_by(PAGE_SZ as u64);
+            EPHEMERAL_FILE_SPILLED_BYTES
+                .with_label_values(&["physical"])
+                .inc_by(PAGE_SZ as u64);
+            return Ok(());
+        };
+
+        let mut page = vec![0u8; lz4_flex::block::get_maximum_output_size(PAGE_SZ)];
+        let page = match lz4_flex::block::compress_into(buf, &mut page) {
+            Ok(len) if len < PAGE_SZ => &page[..len],
+            _ => buf,
+        };
+
+        // Rewrite the page in place if it still fits, or else append it. The
+        // space of the previous version is lost, but it's rare that a page is
+        // written again, and the file is short-lived anyway.
+        let slot = {
+            let mut compressed = compressed.lock().unwrap();
+            match compressed.slots.get(&blkno) {
+                Some(slot) if slot.capacity >= page.len() => Slot {
+                    len: page.len(),
+                    ..*slot
+                },
+                _ => {
+                    let slot = Slot {
+                        offset: compressed.end,
+                        len: page.len(),
+                        capacity: page.len(),
+                    };
+                    compressed.end += page.len() as u64;
+                    slot
+                }
+            }
+        };
+        self.file.write_all_at(page, slot.offset)?;
+        compressed.lock().unwrap().slots.insert(blkno, slot);
+
+        EPHEMERAL_FILE_SPILLED_BYTES
+            .with_label_values(&["logical"])
+            .inc_by(PAGE_SZ as u64);
+        EPHEMERAL_FILE_SPILLED_BYTES
+            .with_label_values(&["physical"])
+            .inc_by(page.len() as u64);
+        Ok(())
+    }
+}
+
 impl EphemeralFile {
     pub fn create(
         conf: &PageServerConf,
@@ -62,7 +201,10 @@ impl EphemeralFile {
             &filename,
             OpenOptions::new().read(true).write(true).create(true),
         )?;
-        let file_rc = Arc::new(file);
+        let file_rc = Arc::new(SpillFile {
+            file,
+            compressed: conf.ephemeral_file_compression.then(Default::default),
+        });
         l.files.insert(file_id, file_rc.clone());
 
         Ok(EphemeralFile {
@@ -75,23 +217,7 @@ impl EphemeralFile {
     }
 
     fn fill_buffer(&self, buf: &mut [u8], blkno: u32) -> Result<(), io::Error> {
-        let mut off = 0;
-        while off < PAGE_SZ {
-            let n = self
-                .file
-                .read_at(&mut buf[off..], blkno as u64 * PAGE_SZ as u64 + off as u64)?;
-
-            if n == 0 {
-                // Reached EOF. Fill the rest of the buffer with zeros.
-                const ZERO_BUF: [u8; PAGE_SZ] = [0u8; PAGE_SZ];
-
-                buf[off..].copy_from_slice(&ZERO_BUF[off..]);
-                break;
-            }
-
-            off += n;
-        }
-        Ok(())
+        self.file.read_page(buf, blkno)
     }
 
     fn get_buf_for_write(&self, blkno: u32) -> Result<page_cache::PageWriteGuard, io::Error> {
@@ -116,6 +242,82 @@ impl EphemeralFile {
 
         Ok(write_guard)
     }
+
+    /// Writes the blobs of `batch` one after another, in the same format as
+    /// [`BlobWriter::write_blob`], and returns their offsets in the file.
+    ///
+    /// Each page of the file is looked up in the page cache once, rather than once
+    /// per blob written to it.
+    pub fn write_blob_batch(&mut self, batch: &BlobBatch) -> Result<Vec<u64>, io::Error> {
+        let offsets = batch
+            .starts
+            .iter()
+            .map(|start| self.size + *start as u64)
+            .collect();
+
+        let mut blknum = (self.size / PAGE_SZ as u64) as u32;
+        let mut off = (self.size % PAGE_SZ as u64) as usize;
+        let mut buf_remain = &batch.buf[..];
+        while !buf_remain.is_empty() {
+            let mut buf = self.get_buf_for_write(blknum)?;
+            let this_blk_len = min(PAGE_SZ - off, buf_remain.len());
+            buf[off..(off + this_blk_len)].copy_from_slice(&buf_remain[..this_blk_len]);
+            buf_remain = &buf_remain[this_blk_len..];
+            blknum += 1;
+            off = 0;
+        }
+        self.size += batch.buf.len() as u64;
+
+        Ok(offsets)
+    }
+}
+
+/// Blobs to write to an [`EphemeralFile`] at once, with
+/// [`EphemeralFile::write_blob_batch`].
+#[derive(Default)]
+pub struct BlobBatch {
+    /// The blobs with their length headers.
+    buf: Vec<u8>,
+    /// Where each blob's header starts in `buf`.
+    starts: Vec<usize>,
+}
+
+impl BlobBatch {
+    /// Adds a blob, the bytes that `write` appends to the given buffer.
+    pub fn push_with<E>(
+        &mut self,
+        write: impl FnOnce(&mut Vec<u8>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let start = self.buf.len();
+        // Leave room for a 4-byte length header, and move a short blob back once
+        // it's known to need only 1 byte. That's cheaper than copying every blob
+        // from a buffer of its own.
+        self.buf.extend_from_slice(&[0; 4]);
+        if let Err(e) = write(&mut self.buf) {
+            self.buf.truncate(start);
+            return Err(e);
+        }
+        let len = self.buf.len() - start - 4;
+        if len < 0x80 {
+            self.buf[start] = len as u8;
+            self.buf.copy_within(start + 4.., start + 1);
+            self.buf.truncate(start + 1 + len);
+        } else {
+            let mut len_buf = u32::to_be_bytes(len as u32);
+            len_buf[0] |= 0x80;
+            self.buf[start..start + 4].copy_from_slice(&len_buf);
+        }
+        self.starts.push(start);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
 }
 
 /// Does the given filename look like an ephemeral file?
@@ -264,7 +466,7 @@ impl Drop for EphemeralFile {
         EPHEMERAL_FILES.write().unwrap().files.remove(&self.file_id);
 
         // unlink the file
-        let res = std::fs::remove_file(&self.file.path);
+        let res = std::fs::remove_file(&self.file.file.path);
         if let Err(e) = res {
             if e.kind() != std::io::ErrorKind::NotFound {
                 // just never log the not found errors, we cannot do anything for them; on detach
@@ -273,7 +475,7 @@ impl Drop for EphemeralFile {
                 // not found files might also be related to https://github.com/neondatabase/neon/issues/2442
                 error!(
                     "could not remove ephemeral file '{}': {}",
-                    self.file.path.display(),
+                    self.file.file.path.display(),
                     e
                 );
             }
@@ -283,13 +485,13 @@ impl Drop for EphemeralFile {
 
 pub fn writeback(file_id: u64, blkno: u32, buf: &[u8]) -> Result<(), io::Error> {
     if let Some(file) = EPHEMERAL_FILES.read().unwrap().files.get(&file_id) {
-        match file.write_all_at(buf, blkno as u64 * PAGE_SZ as u64) {
+        match file.write_page(buf, blkno) {
             Ok(_) => Ok(()),
             Err(e) => Err(io::Error::new(
                 ErrorKind::Other,
                 format!(
                     "failed to write back to ephemeral file at {} error: {}",
-                    file.path.display(),
+                    file.file.path.display(),
                     e
                 ),
             )),
@@ -449,4 +651,99 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_ephemeral_blob_batches() -> Result<(), io::Error> {
+        let (conf, tenant_id, timeline_id) = harness("ephemeral_blob_batches")?;
+
+        let mut file = EphemeralFile::create(conf, tenant_id, timeline_id)?;
+
+        // start in the middle of a page
+        let pos_foo = file.write_blob(b"foo")?;
+
+        let mut blobs = Vec::new();
+        for batch_no in 0..10 {
+            let mut batch = BlobBatch::default();
+            let mut batch_blobs = Vec::new();
+            for i in 0..1000 {
+                // short, long and multi-page blobs, with their headers at all kinds of
+                // offsets within the pages
+                let mut data = format!("blob{batch_no}-{i}").into_bytes();
+                if i % 10 == 0 {
+                    data = data.repeat(100);
+                } else if i % 333 == 0 {
+                    data.resize(20000, 0);
+                    thread_rng().fill_bytes(&mut data);
+                }
+                batch.push_with(|buf| {
+                    buf.extend_from_slice(&data);
+                    Ok::<_, io::Error>(())
+                })?;
+                batch_blobs.push(data);
+            }
+            // a failed write leaves nothing behind
+            assert!(batch
+                .push_with(|buf| {
+                    buf.extend_from_slice(b"partial");
+                    Err(io::Error::new(ErrorKind::Other, "failed"))
+                })
+                .is_err());
+            assert_eq!(batch.len(), batch_blobs.len());
+
+            let positions = file.write_blob_batch(&batch)?;
+            blobs.extend(positions.into_iter().zip(batch_blobs));
+        }
+        let pos_bar = file.write_blob(b"bar")?;
+
+        let cursor = BlockCursor::new(&file);
+        assert_eq!(b"foo", cursor.read_blob(pos_foo).await?.as_slice());
+        assert_eq!(b"bar", cursor.read_blob(pos_bar).await?.as_slice());
+        for (pos, expected) in blobs {
+            let actual = cursor.read_blob(pos).await?;
+            assert_eq!(actual, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_pages() -> Result<(), io::Error> {
+        let (conf, tenant_id, timeline_id) = harness("compressed_pages")?;
+        let mut conf = conf.clone();
+        conf.ephemeral_file_compression = true;
+        let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+
+        let file = EphemeralFile::create(conf, tenant_id, timeline_id)?;
+        let spill = &file.file;
+        let compressible = b"compressible".repeat(PAGE_SZ / 12 + 1)[..PAGE_SZ].to_vec();
+        let mut incompressible = vec![0u8; PAGE_SZ];
+        thread_rng().fill_bytes(&mut incompressible);
+        let file_len = || fs::metadata(&spill.file.path).map(|m| m.len());
+
+        let mut buf = vec![1u8; PAGE_SZ];
+        spill.write_page(&compressible, 0)?;
+        spill.write_page(&incompressible, 2)?;
+        spill.read_page(&mut buf, 0)?;
+        assert_eq!(buf, compressible);
+        spill.read_page(&mut buf, 2)?;
+        assert_eq!(buf, incompressible);
+        // never written
+        spill.read_page(&mut buf, 1)?;
+        assert_eq!(buf, vec![0u8; PAGE_SZ]);
+        let len = file_len()?;
+        assert!(len < PAGE_SZ as u64 + 1024, "{len}");
+
+        // a page that doesn't fit in its previous place is appended
+        spill.write_page(&incompressible, 0)?;
+        assert_eq!(file_len()?, len + PAGE_SZ as u64);
+        // and one that fits is written in place
+        spill.write_page(&compressible, 2)?;
+        assert_eq!(file_len()?, len + PAGE_SZ as u64);
+        spill.read_page(&mut buf, 0)?;
+        assert_eq!(buf, incompressible);
+        spill.read_page(&mut buf, 2)?;
+        assert_eq!(buf, compressible);
+
+        Ok(())
+    }
 }
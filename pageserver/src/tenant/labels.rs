@@ -0,0 +1,109 @@
+//!
+//! Key-value labels of tenants and timelines.
+//!
+//! The control plane uses them to stash things like project ids and environment
+//! tags next to the data. The pageserver doesn't interpret them, it only stores
+//! them in a `labels` file in the tenant or timeline directory, returns them in
+//! the tenant and timeline info, and filters the list endpoints by them.
+//!
+//! The labels are not uploaded to the remote storage, so they have to be set
+//! again after an attach, like the tenant config.
+//!
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use pageserver_api::models::Labels;
+use utils::crashsafe::{self, path_with_suffix_extension};
+
+use crate::TEMP_FILE_SUFFIX;
+
+const MAX_LABELS: usize = 64;
+const MAX_KEY_LEN: usize = 63;
+const MAX_VALUE_LEN: usize = 255;
+
+pub fn validate(labels: &Labels) -> anyhow::Result<()> {
+    ensure!(
+        labels.len() <= MAX_LABELS,
+        "too many labels: {}, at most {MAX_LABELS} are allowed",
+        labels.len()
+    );
+    for (key, value) in labels {
+        ensure!(
+            !key.is_empty() && key.len() <= MAX_KEY_LEN,
+            "label key '{key}' must be 1 to {MAX_KEY_LEN} bytes long"
+        );
+        ensure!(
+            key.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')),
+            "label key '{key}' may only contain ASCII letters, digits and '-', '_', '.', '/'"
+        );
+        ensure!(
+            value.len() <= MAX_VALUE_LEN,
+            "value of label '{key}' is longer than {MAX_VALUE_LEN} bytes"
+        );
+    }
+    Ok(())
+}
+
+/// Reads the labels file. A missing file means no labels.
+pub fn load(path: &Path) -> anyhow::Result<Labels> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Labels::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("read labels file {}", path.display()));
+        }
+    };
+    toml_edit::de::from_str(&contents)
+        .with_context(|| format!("parse labels file {}", path.display()))
+}
+
+/// Replaces the labels file, atomically.
+pub fn persist(path: &Path, labels: &Labels) -> anyhow::Result<()> {
+    let contents = toml_edit::ser::to_string(labels)?;
+    let temp_path = path_with_suffix_extension(path, TEMP_FILE_SUFFIX);
+    let mut file = fs::File::create(&temp_path)
+        .with_context(|| format!("create labels file {}", temp_path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("rename labels file to {}", path.display()))?;
+    crashsafe::fsync_file_and_parent(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_roundtrip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(crate::LABELS_FILE_NAME);
+        assert_eq!(load(&path)?, Labels::new());
+
+        let labels = Labels::from([
+            ("project".to_string(), "cold-moon-123".to_string()),
+            ("env".to_string(), "a value with \"quotes\"".to_string()),
+        ]);
+        validate(&labels)?;
+        persist(&path, &labels)?;
+        assert_eq!(load(&path)?, labels);
+
+        persist(&path, &Labels::new())?;
+        assert_eq!(load(&path)?, Labels::new());
+        Ok(())
+    }
+
+    #[test]
+    fn labels_validation() {
+        let label = |key: &str, value: &str| Labels::from([(key.to_string(), value.to_string())]);
+        assert!(validate(&label("neon/project-id", "")).is_ok());
+        assert!(validate(&label("", "value")).is_err());
+        assert!(validate(&label("with space", "value")).is_err());
+        assert!(validate(&label(&"k".repeat(64), "value")).is_err());
+        assert!(validate(&label("key", &"v".repeat(256))).is_err());
+    }
+}
@@ -51,7 +51,7 @@ use crate::keyspace::KeyPartitioning;
 use crate::repository::Key;
 use crate::tenant::storage_layer::InMemoryLayer;
 use crate::tenant::storage_layer::Layer;
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use std::collections::VecDeque;
 use std::ops::Range;
 use std::sync::Arc;
@@ -624,6 +624,95 @@ impl LayerMap {
         Ok(self.l0_delta_layers.to_vec())
     }
 
+    /// Check the consistency of the layer map, returning the first violated
+    /// invariant.
+    ///
+    /// Takes O(N log N) for N historic layers. Used by the tests, and by the
+    /// `check_layer_map` debug endpoint on production nodes.
+    pub fn check_invariants(&self) -> Result<()> {
+        let historic = self.historic.get()?;
+
+        let mut num_l0 = 0;
+        for layer in self.iter_historic_layers() {
+            let key_range = layer.get_key_range();
+            let lsn_range = layer.get_lsn_range();
+            ensure!(
+                !key_range.is_empty() && !lsn_range.is_empty(),
+                "layer {} has an empty range",
+                layer.short_id()
+            );
+            ensure!(
+                layer.is_delta() || lsn_range.end == lsn_range.start + 1,
+                "image layer {} covers more than one LSN",
+                layer.short_id()
+            );
+            if Self::is_l0(&layer) {
+                num_l0 += 1;
+                ensure!(
+                    self.l0_delta_layers
+                        .iter()
+                        .any(|l0| l0.key() == layer.key()),
+                    "L0 layer {} is missing from the L0 layers",
+                    layer.short_id()
+                );
+            }
+
+            // The layer, or one at least as recent, must cover its own start
+            // from the version it was added in.
+            let version = historic.get_version(lsn_range.start.0);
+            let coverage = version.map(|version| {
+                if layer.is_incremental() {
+                    &version.delta_coverage
+                } else {
+                    &version.image_coverage
+                }
+            });
+            let covering = coverage.and_then(|c| c.query(key_range.start.to_i128()));
+            ensure!(
+                covering.map_or(false, |c| c.get_lsn_range().end >= lsn_range.end),
+                "layer {} is not covered by the search index",
+                layer.short_id()
+            );
+        }
+        ensure!(
+            self.l0_delta_layers.len() == num_l0,
+            "{} L0 layers, but {} L0 layers in the map",
+            self.l0_delta_layers.len(),
+            num_l0
+        );
+
+        // The in-memory layers follow each other without gaps.
+        ensure!(
+            self.open_layer.is_none() || self.next_open_layer_at.is_none(),
+            "both the open layer and next_open_layer_at are set"
+        );
+        let mut prev_end = None;
+        for frozen in &self.frozen_layers {
+            let lsn_range = frozen.get_lsn_range();
+            ensure!(
+                lsn_range.end != Lsn::MAX,
+                "frozen layer {frozen} has no end"
+            );
+            ensure!(
+                prev_end.map_or(true, |end| end == lsn_range.start),
+                "frozen layer {frozen} doesn't follow the previous one"
+            );
+            prev_end = Some(lsn_range.end);
+        }
+        let next_start = self
+            .open_layer
+            .as_ref()
+            .map(|open| open.get_lsn_range().start)
+            .or(self.next_open_layer_at);
+        if let (Some(prev_end), Some(next_start)) = (prev_end, next_start) {
+            ensure!(
+                prev_end == next_start,
+                "the open layer starts at {next_start}, but the frozen layers end at {prev_end}"
+            );
+        }
+        Ok(())
+    }
+
     /// debugging function to print out the contents of the layer map
     #[allow(unused)]
     pub async fn dump(&self, verbose: bool, ctx: &RequestContext) -> Result<()> {
@@ -783,4 +872,207 @@ mod tests {
             (historic, l0)
         }
     }
+
+    /// Cross-checks the layer map against a naive model, a list of the layers,
+    /// on random sequences of batched insertions and removals.
+    mod reference_model {
+        use std::cmp::Reverse;
+        use std::ops::Range;
+
+        use proptest::prelude::*;
+        use utils::id::{TenantId, TimelineId};
+        use utils::lsn::Lsn;
+
+        use super::LayerMap;
+        use crate::repository::Key;
+        use crate::tenant::layer_map::LayerKey;
+        use crate::tenant::storage_layer::PersistentLayerDesc;
+
+        /// The layers cover small ranges of keys and LSNs, to overlap a lot.
+        const KEYS: i128 = 16;
+        const LSNS: u64 = 32;
+
+        #[derive(Debug, Clone)]
+        enum Update {
+            Insert(PersistentLayerDesc),
+            /// Removes the current layer at this index, modulo their number.
+            Remove(usize),
+        }
+
+        fn key_range() -> impl Strategy<Value = Range<i128>> {
+            (0..KEYS, 0..KEYS).prop_map(|(a, b)| a.min(b)..a.max(b) + 1)
+        }
+
+        fn layer() -> impl Strategy<Value = PersistentLayerDesc> {
+            let tenant_id = TenantId::from([0; 16]);
+            let timeline_id = TimelineId::from([0; 16]);
+            prop_oneof![
+                (key_range(), 0..LSNS).prop_map(move |(keys, lsn)| {
+                    PersistentLayerDesc::new_img(
+                        tenant_id,
+                        timeline_id,
+                        Key::from_i128(keys.start)..Key::from_i128(keys.end),
+                        Lsn(lsn),
+                        false,
+                        0,
+                    )
+                }),
+                (key_range(), 0..LSNS, 0..LSNS, any::<bool>()).prop_map(move |(keys, a, b, l0)| {
+                    let keys = if l0 {
+                        Key::MIN..Key::MAX
+                    } else {
+                        Key::from_i128(keys.start)..Key::from_i128(keys.end)
+                    };
+                    PersistentLayerDesc::new_delta(
+                        tenant_id,
+                        timeline_id,
+                        keys,
+                        Lsn(a.min(b))..Lsn(a.max(b) + 1),
+                        0,
+                    )
+                }),
+            ]
+        }
+
+        fn update() -> impl Strategy<Value = Update> {
+            prop_oneof![
+                3 => layer().prop_map(Update::Insert),
+                1 => any::<usize>().prop_map(Update::Remove),
+            ]
+        }
+
+        /// The latest layer of the kind covering the key below `end_lsn`, the
+        /// first inserted of the latest ones by `lsn.end` like the search index.
+        fn latest(
+            layers: &[PersistentLayerDesc],
+            key: Key,
+            end_lsn: Lsn,
+            image: bool,
+        ) -> Option<&PersistentLayerDesc> {
+            layers
+                .iter()
+                .filter(|l| l.is_incremental() != image)
+                .filter(|l| l.key_range.contains(&key) && l.lsn_range.start < end_lsn)
+                .min_by_key(|l| (Reverse(l.lsn_range.end), LayerKey::from(*l)))
+        }
+
+        /// Expected result of [`LayerMap::search`]: the layer, and lsn_floor.
+        fn search(
+            layers: &[PersistentLayerDesc],
+            key: Key,
+            end_lsn: Lsn,
+        ) -> Option<(LayerKey, Lsn)> {
+            let delta = latest(layers, key, end_lsn, false);
+            let image = latest(layers, key, end_lsn, true);
+            match (delta, image) {
+                (None, None) => None,
+                (Some(layer), None) | (None, Some(layer)) => {
+                    Some((LayerKey::from(layer), layer.lsn_range.start))
+                }
+                (Some(delta), Some(image)) => {
+                    if image.lsn_range.end >= delta.lsn_range.end
+                        || image.lsn_range.start + 1 == end_lsn
+                    {
+                        Some((LayerKey::from(image), image.lsn_range.start))
+                    } else {
+                        let lsn_floor =
+                            std::cmp::max(delta.lsn_range.start, image.lsn_range.start + 1);
+                        Some((LayerKey::from(delta), lsn_floor))
+                    }
+                }
+            }
+        }
+
+        /// Expected result of [`LayerMap::image_layer_exists`]: every key is
+        /// covered by an image within the LSN range.
+        fn image_layer_exists(
+            layers: &[PersistentLayerDesc],
+            keys: Range<i128>,
+            lsn: Range<Lsn>,
+        ) -> bool {
+            keys.map(Key::from_i128).all(|key| {
+                layers.iter().any(|l| {
+                    !l.is_incremental()
+                        && l.key_range.contains(&key)
+                        && lsn.contains(&l.lsn_range.start)
+                })
+            })
+        }
+
+        fn layer_keys<'a>(layers: impl Iterator<Item = &'a PersistentLayerDesc>) -> Vec<LayerKey> {
+            let mut keys: Vec<LayerKey> = layers.map(LayerKey::from).collect();
+            keys.sort();
+            keys
+        }
+
+        fn check(map: &LayerMap, layers: &[PersistentLayerDesc]) {
+            map.check_invariants().unwrap();
+
+            let historic: Vec<_> = map.iter_historic_layers().collect();
+            assert_eq!(
+                layer_keys(historic.iter().map(|l| l.as_ref())),
+                layer_keys(layers.iter())
+            );
+            let l0: Vec<_> = map.get_level0_deltas().unwrap();
+            assert_eq!(
+                layer_keys(l0.iter().map(|l| l.as_ref())),
+                layer_keys(layers.iter().filter(|l| LayerMap::is_l0(l)))
+            );
+
+            for key in (0..KEYS).map(Key::from_i128) {
+                for end_lsn in (1..=LSNS + 1).map(Lsn) {
+                    let result = map
+                        .search(key, end_lsn)
+                        .map(|r| (LayerKey::from(r.layer.as_ref()), r.lsn_floor));
+                    assert_eq!(
+                        result,
+                        search(layers, key, end_lsn),
+                        "search({key}, {end_lsn})"
+                    );
+                }
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn layer_map_matches_model(
+                batches in prop::collection::vec(prop::collection::vec(update(), 1..8), 1..10),
+                queries in prop::collection::vec((key_range(), 0..LSNS, 0..LSNS), 10),
+            ) {
+                let mut map = LayerMap::default();
+                let mut layers: Vec<PersistentLayerDesc> = Vec::new();
+                for batch in batches {
+                    let mut updates = map.batch_update();
+                    for update in batch {
+                        match update {
+                            Update::Insert(layer) => {
+                                // a layer is only ever added once
+                                if layers.iter().all(|l| l.key() != layer.key()) {
+                                    updates.insert_historic(layer.clone());
+                                    layers.push(layer);
+                                }
+                            }
+                            Update::Remove(i) => {
+                                if !layers.is_empty() {
+                                    let layer = layers.swap_remove(i % layers.len());
+                                    updates.remove_historic(&layer);
+                                }
+                            }
+                        }
+                    }
+                    updates.flush();
+
+                    check(&map, &layers);
+                    for (keys, a, b) in &queries {
+                        let lsn = Lsn(*a.min(b))..Lsn(*a.max(b) + 1);
+                        let key_range = Key::from_i128(keys.start)..Key::from_i128(keys.end);
+                        prop_assert_eq!(
+                            map.image_layer_exists(&key_range, &lsn).unwrap(),
+                            image_layer_exists(&layers, keys.clone(), lsn)
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
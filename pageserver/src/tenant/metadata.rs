@@ -6,6 +6,11 @@
 //!
 //! The module contains all structs and related helper methods related to timeline metadata.
 //!
+//! While a timeline is running, its metadata changes on every checkpoint. Instead of
+//! rewriting the metadata file each time, the changed fields are appended to a metadata
+//! log next to it, see [`MetadataLog`]. The log is folded back into the metadata file
+//! every [`METADATA_LOG_MAX_RECORDS`] records.
+//!
 //! [`remote_timeline_client`]: super::remote_timeline_client
 
 use std::fs::{File, OpenOptions};
@@ -37,6 +42,12 @@ const METADATA_OLD_FORMAT_VERSION: u16 = 3;
 /// see PG_CONTROL_MAX_SAFE_SIZE
 const METADATA_MAX_SIZE: usize = 512;
 
+/// Magic number at the start of a metadata log file.
+const METADATA_LOG_MAGIC: u32 = 0x4d4c_4f47; // "MLOG"
+
+/// After this many records, the metadata log is folded into the metadata file.
+const METADATA_LOG_MAX_RECORDS: usize = 256;
+
 /// Metadata stored on disk for each timeline
 ///
 /// The fields correspond to the values we hold in memory, in Timeline.
@@ -97,6 +108,29 @@ struct TimelineMetadataBodyV1 {
     region_id: RegionId,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct MetadataLogHeader {
+    magic: u32,
+    base_checksum: u32, // checksum of the metadata file body that the log applies to
+}
+const METADATA_LOG_HDR_SIZE: usize = std::mem::size_of::<MetadataLogHeader>();
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct MetadataLogRecordHeader {
+    checksum: u32, // CRC of serialized record body
+    size: u32,     // size of serialized record body
+}
+const METADATA_LOG_RECORD_HDR_SIZE: usize = std::mem::size_of::<MetadataLogRecordHeader>();
+
+/// The metadata fields that change during the lifetime of a timeline.
+/// Each record in the metadata log holds their new values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TimelineMetadataDelta {
+    disk_consistent_lsn: Lsn,
+    prev_record_lsn: Option<Lsn>,
+    latest_gc_cutoff_lsn: Lsn,
+}
+
 impl TimelineMetadata {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -240,6 +274,67 @@ impl TimelineMetadata {
     pub fn region_id(&self) -> RegionId {
         self.body.region_id
     }
+
+    fn delta(&self) -> TimelineMetadataDelta {
+        TimelineMetadataDelta {
+            disk_consistent_lsn: self.body.disk_consistent_lsn,
+            prev_record_lsn: self.body.prev_record_lsn,
+            latest_gc_cutoff_lsn: self.body.latest_gc_cutoff_lsn,
+        }
+    }
+
+    fn apply_delta(&mut self, delta: TimelineMetadataDelta) {
+        self.body.disk_consistent_lsn = delta.disk_consistent_lsn;
+        self.body.prev_record_lsn = delta.prev_record_lsn;
+        self.body.latest_gc_cutoff_lsn = delta.latest_gc_cutoff_lsn;
+    }
+
+    /// Whether `other` only differs from `self` in the fields that the metadata log records.
+    fn differs_only_in_delta(&self, other: &TimelineMetadata) -> bool {
+        let mut updated = self.clone();
+        updated.apply_delta(other.delta());
+        updated.body == other.body
+    }
+}
+
+/// Applies the records of a metadata log on top of the metadata file contents.
+/// Returns the number of records applied.
+///
+/// A log that was started for a different version of the metadata file is
+/// ignored. Replay stops at the first torn or corrupt record, which is what a
+/// crash in the middle of an append leaves behind.
+pub fn replay_metadata_log(metadata: &mut TimelineMetadata, log_bytes: &[u8]) -> usize {
+    let hdr = match log_bytes.get(..METADATA_LOG_HDR_SIZE) {
+        Some(hdr_bytes) => MetadataLogHeader::des(hdr_bytes).ok(),
+        None => None,
+    };
+    match hdr {
+        Some(hdr)
+            if hdr.magic == METADATA_LOG_MAGIC && hdr.base_checksum == metadata.hdr.checksum => {}
+        _ => return 0,
+    }
+
+    let mut applied = 0;
+    let mut rest = &log_bytes[METADATA_LOG_HDR_SIZE..];
+    while let Some(hdr_bytes) = rest.get(..METADATA_LOG_RECORD_HDR_SIZE) {
+        let Ok(hdr) = MetadataLogRecordHeader::des(hdr_bytes) else {
+            break;
+        };
+        let record_end = METADATA_LOG_RECORD_HDR_SIZE + hdr.size as usize;
+        let Some(body) = rest.get(METADATA_LOG_RECORD_HDR_SIZE..record_end) else {
+            break;
+        };
+        if crc32c::crc32c(body) != hdr.checksum {
+            break;
+        }
+        let Ok(delta) = TimelineMetadataDelta::des(body) else {
+            break;
+        };
+        metadata.apply_delta(delta);
+        applied += 1;
+        rest = &rest[record_end..];
+    }
+    applied
 }
 
 /// Save timeline metadata to file
@@ -278,6 +373,117 @@ pub fn save_metadata(
     Ok(())
 }
 
+/// Persists the metadata updates of a running timeline.
+///
+/// Updates that only change the fields in [`TimelineMetadataDelta`] are
+/// appended to the metadata log, which costs a small write and an fdatasync
+/// instead of a rewrite of the metadata file. Anything else, a full log, or a
+/// failed append rewrites the metadata file and starts a new log.
+///
+/// The new log is bound to the new metadata file contents by the checksum in
+/// its header, so the old log is ignored if we crash before the new one has
+/// been started.
+pub struct MetadataLog {
+    conf: &'static PageServerConf,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    /// None until the first save: we don't know what the log on disk contains.
+    state: Option<MetadataLogState>,
+}
+
+struct MetadataLogState {
+    file: VirtualFile,
+    last_saved: TimelineMetadata,
+    num_records: usize,
+}
+
+impl MetadataLog {
+    pub fn new(
+        conf: &'static PageServerConf,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Self {
+        MetadataLog {
+            conf,
+            tenant_id,
+            timeline_id,
+            state: None,
+        }
+    }
+
+    /// Save timeline metadata, by appending to the log if possible
+    pub fn save(&mut self, data: &TimelineMetadata) -> anyhow::Result<()> {
+        match self.state.take() {
+            Some(mut state)
+                if state.num_records < METADATA_LOG_MAX_RECORDS
+                    && state.last_saved.differs_only_in_delta(data) =>
+            {
+                if state.last_saved.body != data.body {
+                    let _enter = info_span!("appending to metadata log").entered();
+                    append_metadata_log_record(&mut state.file, &data.delta())?;
+                    state.last_saved = data.clone();
+                    state.num_records += 1;
+                }
+                self.state = Some(state);
+                Ok(())
+            }
+            _ => self.compact(data),
+        }
+    }
+
+    /// Rewrite the metadata file, and start a new, empty log for it
+    fn compact(&mut self, data: &TimelineMetadata) -> anyhow::Result<()> {
+        save_metadata(self.conf, &self.tenant_id, &self.timeline_id, data, false)?;
+
+        let _enter = info_span!("starting metadata log").entered();
+        let path = self
+            .conf
+            .metadata_log_path(&self.tenant_id, &self.timeline_id);
+        let mut file = VirtualFile::open_with_options(
+            &path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+        .context("open_with_options")?;
+
+        let hdr = MetadataLogHeader {
+            magic: METADATA_LOG_MAGIC,
+            base_checksum: crc32c::crc32c(&data.body.ser()?),
+        };
+        file.write_all(&hdr.ser()?)?;
+        file.sync_all()?;
+
+        // fsync the parent directory, in case the log file was just created
+        let timeline_dir = File::open(
+            path.parent()
+                .expect("Metadata log should always have a parent dir"),
+        )?;
+        timeline_dir.sync_all()?;
+
+        self.state = Some(MetadataLogState {
+            file,
+            last_saved: data.clone(),
+            num_records: 0,
+        });
+        Ok(())
+    }
+}
+
+fn append_metadata_log_record(
+    file: &mut VirtualFile,
+    delta: &TimelineMetadataDelta,
+) -> anyhow::Result<()> {
+    let body = delta.ser()?;
+    let hdr = MetadataLogRecordHeader {
+        checksum: crc32c::crc32c(&body),
+        size: body.len() as u32,
+    };
+    let mut record = hdr.ser()?;
+    record.extend_from_slice(&body);
+    file.write_all(&record)?;
+    file.sync_data()?;
+    Ok(())
+}
+
 #[derive(Error, Debug)]
 pub enum LoadMetadataError {
     #[error(transparent)]
@@ -294,14 +500,23 @@ pub fn load_metadata(
 ) -> Result<TimelineMetadata, LoadMetadataError> {
     let metadata_path = conf.metadata_path(tenant_id, timeline_id);
     let metadata_bytes = std::fs::read(metadata_path)?;
+    let mut metadata = TimelineMetadata::from_bytes(&metadata_bytes)?;
 
-    Ok(TimelineMetadata::from_bytes(&metadata_bytes)?)
+    match std::fs::read(conf.metadata_log_path(tenant_id, timeline_id)) {
+        Ok(log_bytes) => {
+            replay_metadata_log(&mut metadata, &log_bytes);
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(metadata)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tenant::harness::TIMELINE_ID;
+    use crate::tenant::harness::{TenantHarness, TIMELINE_ID};
 
     #[test]
     fn metadata_serializes_correctly() {
@@ -330,6 +545,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn metadata_log_replay() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("metadata_log_replay")?;
+        let (conf, tenant_id) = (harness.conf, harness.tenant_id);
+        std::fs::create_dir_all(harness.timeline_path(&TIMELINE_ID))?;
+
+        let metadata_at = |lsn: u64| {
+            TimelineMetadata::new(
+                Lsn(lsn),
+                Some(Lsn(lsn - 0x8)),
+                None,
+                Lsn(0),
+                Lsn(lsn / 2),
+                Lsn(0),
+                crate::DEFAULT_PG_VERSION,
+                RegionId(0),
+            )
+        };
+        let load = || load_metadata(conf, &tenant_id, &TIMELINE_ID).map(|m| m.body);
+        let log_path = conf.metadata_log_path(&tenant_id, &TIMELINE_ID);
+
+        save_metadata(conf, &tenant_id, &TIMELINE_ID, &metadata_at(0x100), true)?;
+        assert_eq!(load()?, metadata_at(0x100).body);
+
+        // The first save starts the log, the rest are appended to it
+        let mut log = MetadataLog::new(conf, tenant_id, TIMELINE_ID);
+        for i in 2..=10 {
+            log.save(&metadata_at(0x100 * i))?;
+        }
+        assert_eq!(load()?, metadata_at(0xa00).body);
+
+        // A torn record at the end of the log is ignored
+        let log_bytes = std::fs::read(&log_path)?;
+        std::fs::write(&log_path, &log_bytes[..log_bytes.len() - 3])?;
+        assert_eq!(load()?, metadata_at(0x900).body);
+
+        // A log that doesn't belong to the metadata file is ignored
+        save_metadata(conf, &tenant_id, &TIMELINE_ID, &metadata_at(0x100), false)?;
+        assert_eq!(load()?, metadata_at(0x100).body);
+
+        // A full log is folded into the metadata file
+        let mut log = MetadataLog::new(conf, tenant_id, TIMELINE_ID);
+        for i in 1..=(METADATA_LOG_MAX_RECORDS as u64 + 2) {
+            log.save(&metadata_at(0x100 * i))?;
+        }
+        assert_eq!(
+            std::fs::metadata(&log_path)?.len(),
+            METADATA_LOG_HDR_SIZE as u64
+        );
+        assert_eq!(
+            load()?,
+            metadata_at(0x100 * (METADATA_LOG_MAX_RECORDS as u64 + 2)).body
+        );
+
+        Ok(())
+    }
+
     // Generate old version metadata and read it with current code.
     // Ensure that it is upgraded correctly
     #[test]
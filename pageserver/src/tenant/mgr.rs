@@ -1,28 +1,33 @@
 //! This module acts as a switchboard to access different repositories managed by this
 //! page server.
 
-use std::collections::{hash_map, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::sync::Arc;
 use tokio::fs;
 
 use anyhow::Context;
 use once_cell::sync::Lazy;
-use tokio::sync::RwLock;
+use tokio::io::AsyncRead;
+use tokio::sync::{OwnedRwLockReadGuard, RwLock};
 use tokio::task::JoinSet;
 use tracing::*;
 
+use pageserver_api::models::Labels;
 use remote_storage::GenericRemoteStorage;
-use utils::crashsafe;
+use utils::crashsafe::{self, path_with_suffix_extension};
 
 use crate::config::PageServerConf;
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::task_mgr::{self, TaskKind};
 use crate::tenant::config::TenantConfOpt;
 use crate::tenant::delete::DeleteTenantFlow;
+use crate::tenant::timeline::snapshot::unpack_snapshot;
 use crate::tenant::{create_tenant_files, CreateTenantFilesMode, Tenant, TenantState};
-use crate::{InitializationOrder, IGNORED_TENANT_FILE_NAME};
+use crate::{InitializationOrder, IGNORED_TENANT_FILE_NAME, TEMP_FILE_SUFFIX};
 
 use utils::fs_ext::PathExt;
 use utils::id::{TenantId, TimelineId};
@@ -30,35 +35,140 @@ use utils::id::{TenantId, TimelineId};
 use super::delete::DeleteTenantError;
 use super::timeline::delete::DeleteTimelineFlow;
 
-/// The tenants known to the pageserver.
-/// The enum variants are used to distinguish the different states that the pageserver can be in.
-pub(crate) enum TenantsMap {
+/// The states that the pageserver's tenants map can be in.
+enum TenantsMapPhase {
     /// [`init_tenant_mgr`] is not done yet.
     Initializing,
     /// [`init_tenant_mgr`] is done, all on-disk tenants have been loaded.
     /// New tenants can be added using [`tenant_map_insert`].
-    Open(HashMap<TenantId, Arc<Tenant>>),
+    Open,
     /// The pageserver has entered shutdown mode via [`shutdown_all_tenants`].
     /// Existing tenants are still accessible, but no new tenants can be created.
-    ShuttingDown(HashMap<TenantId, Arc<Tenant>>),
+    ShuttingDown,
+}
+
+const TENANTS_MAP_SHARDS: usize = 64;
+
+/// The entry of a tenant in [`TenantsMap`]. The lock is held for writing while the
+/// tenant is being inserted or removed, so that lookups of the tenant wait for that,
+/// while the lookups of the other tenants don't.
+type TenantSlot = Arc<RwLock<Option<Arc<Tenant>>>>;
+
+/// The tenants known to the pageserver.
+///
+/// Lookups are on the hot path of every management API call and page service
+/// connection, so the map is split into shards by tenant id, each behind a
+/// synchronous lock that is only held to find the slot of a tenant. Slow operations
+/// on a tenant, like creating its files, only hold the lock of its own slot.
+pub(crate) struct TenantsMap {
+    /// Held for reading while tenants are inserted, so that shutdown sees them all.
+    phase: RwLock<TenantsMapPhase>,
+    shards: Vec<std::sync::RwLock<HashMap<TenantId, TenantSlot>>>,
+}
+
+/// A tenant of [`TenantsMap`], locked against its removal.
+pub(crate) struct TenantReadGuard(Option<OwnedRwLockReadGuard<Option<Arc<Tenant>>>>);
+
+impl TenantReadGuard {
+    pub(crate) fn get(&self) -> Option<&Arc<Tenant>> {
+        self.0.as_ref().and_then(|slot| slot.as_ref())
+    }
 }
 
 impl TenantsMap {
-    pub(crate) fn get(&self, tenant_id: &TenantId) -> Option<&Arc<Tenant>> {
-        match self {
-            TenantsMap::Initializing => None,
-            TenantsMap::Open(m) | TenantsMap::ShuttingDown(m) => m.get(tenant_id),
+    pub(crate) fn new() -> Self {
+        TenantsMap {
+            phase: RwLock::new(TenantsMapPhase::Initializing),
+            shards: (0..TENANTS_MAP_SHARDS)
+                .map(|_| std::sync::RwLock::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, tenant_id: &TenantId) -> &std::sync::RwLock<HashMap<TenantId, TenantSlot>> {
+        let mut hasher = DefaultHasher::new();
+        tenant_id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    fn slot(&self, tenant_id: &TenantId) -> Option<TenantSlot> {
+        self.shard(tenant_id)
+            .read()
+            .unwrap()
+            .get(tenant_id)
+            .map(Arc::clone)
+    }
+
+    /// Whether `slot` is still the slot of the tenant, and not one removed from the
+    /// map while we waited for its lock.
+    fn is_current(&self, tenant_id: &TenantId, slot: &TenantSlot) -> bool {
+        self.slot(tenant_id)
+            .map_or(false, |current| Arc::ptr_eq(&current, slot))
+    }
+
+    /// Removes the slot of a tenant, if it's empty. Called with the slot locked for writing.
+    fn prune(&self, tenant_id: &TenantId, slot: &TenantSlot) {
+        let mut shard = self.shard(tenant_id).write().unwrap();
+        if shard
+            .get(tenant_id)
+            .map_or(false, |current| Arc::ptr_eq(current, slot))
+        {
+            shard.remove(tenant_id);
+        }
+    }
+
+    pub(crate) async fn get(&self, tenant_id: &TenantId) -> Option<Arc<Tenant>> {
+        self.slot(tenant_id)?.read().await.clone()
+    }
+
+    /// Gets the tenant, and keeps it from being inserted or removed until the guard
+    /// is dropped.
+    pub(crate) async fn read(&self, tenant_id: &TenantId) -> TenantReadGuard {
+        match self.slot(tenant_id) {
+            Some(slot) => TenantReadGuard(Some(slot.read_owned().await)),
+            None => TenantReadGuard(None),
+        }
+    }
+
+    pub(crate) async fn remove(&self, tenant_id: &TenantId) -> Option<Arc<Tenant>> {
+        let slot = self.slot(tenant_id)?;
+        let mut tenant = slot.write().await;
+        let removed = tenant.take();
+        self.prune(tenant_id, &slot);
+        removed
+    }
+
+    /// All the tenants, in no particular order.
+    async fn all(&self) -> Vec<Arc<Tenant>> {
+        let slots = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.read().unwrap().values().cloned().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut tenants = Vec::with_capacity(slots.len());
+        for slot in slots {
+            if let Some(tenant) = slot.read().await.as_ref() {
+                tenants.push(Arc::clone(tenant));
+            }
         }
+        tenants
     }
-    pub(crate) fn remove(&mut self, tenant_id: &TenantId) -> Option<Arc<Tenant>> {
-        match self {
-            TenantsMap::Initializing => None,
-            TenantsMap::Open(m) | TenantsMap::ShuttingDown(m) => m.remove(tenant_id),
+
+    /// Adds the tenants found at startup, and opens the map for new tenants.
+    async fn open(&self, tenants: HashMap<TenantId, Arc<Tenant>>) {
+        let mut phase = self.phase.write().await;
+        assert!(matches!(&*phase, TenantsMapPhase::Initializing));
+        for (tenant_id, tenant) in tenants {
+            self.shard(&tenant_id)
+                .write()
+                .unwrap()
+                .insert(tenant_id, Arc::new(RwLock::new(Some(tenant))));
         }
+        *phase = TenantsMapPhase::Open;
     }
 }
 
-static TENANTS: Lazy<RwLock<TenantsMap>> = Lazy::new(|| RwLock::new(TenantsMap::Initializing));
+static TENANTS: Lazy<TenantsMap> = Lazy::new(TenantsMap::new);
 
 /// Initialize repositories with locally available timelines.
 /// Timelines that are only partially available locally (remote storage has more data than this pageserver)
@@ -153,9 +263,7 @@ pub async fn init_tenant_mgr(
 
     info!("Processed {} local tenants at startup", tenants.len());
 
-    let mut tenants_map = TENANTS.write().await;
-    assert!(matches!(&*tenants_map, &TenantsMap::Initializing));
-    *tenants_map = TenantsMap::Open(tenants);
+    TENANTS.open(tenants).await;
     Ok(())
 }
 
@@ -165,7 +273,7 @@ pub(crate) fn schedule_local_tenant_processing(
     broker_client: storage_broker::BrokerClientChannel,
     remote_storage: Option<GenericRemoteStorage>,
     init_order: Option<InitializationOrder>,
-    tenants: &'static tokio::sync::RwLock<TenantsMap>,
+    tenants: &'static TenantsMap,
     ctx: &RequestContext,
 ) -> anyhow::Result<Arc<Tenant>> {
     anyhow::ensure!(
@@ -247,24 +355,23 @@ pub async fn shutdown_all_tenants() {
     shutdown_all_tenants0(&TENANTS).await
 }
 
-async fn shutdown_all_tenants0(tenants: &tokio::sync::RwLock<TenantsMap>) {
+async fn shutdown_all_tenants0(tenants: &TenantsMap) {
     use utils::completion;
 
     // Prevent new tenants from being created.
     let tenants_to_shut_down = {
-        let mut m = tenants.write().await;
-        match &mut *m {
-            TenantsMap::Initializing => {
-                *m = TenantsMap::ShuttingDown(HashMap::default());
+        let mut phase = tenants.phase.write().await;
+        match &*phase {
+            TenantsMapPhase::Initializing => {
+                *phase = TenantsMapPhase::ShuttingDown;
                 info!("tenants map is empty");
                 return;
             }
-            TenantsMap::Open(tenants) => {
-                let tenants_clone = tenants.clone();
-                *m = TenantsMap::ShuttingDown(std::mem::take(tenants));
-                tenants_clone
+            TenantsMapPhase::Open => {
+                *phase = TenantsMapPhase::ShuttingDown;
+                tenants.all().await
             }
-            TenantsMap::ShuttingDown(_) => {
+            TenantsMapPhase::ShuttingDown => {
                 // TODO: it is possible that detach and shutdown happen at the same time. as a
                 // result, during shutdown we do not wait for detach.
                 error!("already shutting down, this function isn't supposed to be called more than once");
@@ -275,7 +382,8 @@ async fn shutdown_all_tenants0(tenants: &tokio::sync::RwLock<TenantsMap>) {
 
     let started_at = std::time::Instant::now();
     let mut join_set = JoinSet::new();
-    for (tenant_id, tenant) in tenants_to_shut_down {
+    for tenant in tenants_to_shut_down {
+        let tenant_id = tenant.tenant_id();
         join_set.spawn(
             async move {
                 let freeze_and_flush = true;
@@ -376,6 +484,71 @@ pub async fn create_tenant(
     }).await
 }
 
+/// Creates a tenant with a single timeline from an archive written by
+/// [`Timeline::export_snapshot`](super::Timeline::export_snapshot).
+///
+/// The archive is unpacked into a temporary directory first, which is moved into
+/// the new tenant's directory once complete.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_tenant_snapshot(
+    conf: &'static PageServerConf,
+    tenant_conf: TenantConfOpt,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    snapshot: impl AsyncRead + Unpin,
+    broker_client: storage_broker::BrokerClientChannel,
+    remote_storage: Option<GenericRemoteStorage>,
+    ctx: &RequestContext,
+) -> Result<Arc<Tenant>, TenantMapInsertError> {
+    // Check early, to not unpack the whole archive for nothing
+    if let Ok(tenant) = get_tenant(tenant_id, false).await {
+        return Err(TenantMapInsertError::TenantAlreadyExists(
+            tenant_id,
+            tenant.current_state(),
+        ));
+    }
+
+    let staging_dir = path_with_suffix_extension(
+        conf.tenants_path().join(format!("{tenant_id}-snapshot")),
+        TEMP_FILE_SUFFIX,
+    );
+    crashsafe::create_dir_all(&staging_dir)
+        .with_context(|| format!("create snapshot staging directory {staging_dir:?}"))?;
+
+    let result = async {
+        unpack_snapshot(snapshot, &staging_dir).await?;
+
+        tenant_map_insert(tenant_id, || {
+            let tenant_directory =
+                create_tenant_files(conf, tenant_conf, &tenant_id, CreateTenantFilesMode::Create)?;
+
+            let timeline_path = conf.timeline_path(&tenant_id, &timeline_id);
+            std::fs::rename(&staging_dir, &timeline_path)
+                .with_context(|| format!("move snapshot into {timeline_path:?}"))?;
+            crashsafe::fsync(&conf.timelines_path(&tenant_id))?;
+
+            schedule_local_tenant_processing(
+                conf,
+                &tenant_directory,
+                broker_client,
+                remote_storage,
+                None,
+                &TENANTS,
+                ctx,
+            )
+        })
+        .await
+    }
+    .await;
+
+    if result.is_err() && staging_dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&staging_dir) {
+            error!("Failed to remove snapshot staging directory {staging_dir:?}: {e}");
+        }
+    }
+    result
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SetNewTenantConfigError {
     #[error(transparent)]
@@ -387,6 +560,7 @@ pub enum SetNewTenantConfigError {
 pub async fn set_new_tenant_config(
     conf: &'static PageServerConf,
     new_tenant_conf: TenantConfOpt,
+    new_labels: Option<Labels>,
     tenant_id: TenantId,
 ) -> Result<(), SetNewTenantConfigError> {
     info!("configuring tenant {tenant_id}");
@@ -396,6 +570,11 @@ pub async fn set_new_tenant_config(
     Tenant::persist_tenant_config(&tenant_id, &tenant_config_path, new_tenant_conf, false)
         .map_err(SetNewTenantConfigError::Persist)?;
     tenant.set_new_tenant_config(new_tenant_conf);
+    if let Some(new_labels) = new_labels {
+        tenant
+            .set_labels(new_labels)
+            .map_err(SetNewTenantConfigError::Persist)?;
+    }
     Ok(())
 }
 
@@ -413,14 +592,14 @@ pub async fn get_tenant(
     tenant_id: TenantId,
     active_only: bool,
 ) -> Result<Arc<Tenant>, GetTenantError> {
-    let m = TENANTS.read().await;
-    let tenant = m
+    let tenant = TENANTS
         .get(&tenant_id)
+        .await
         .ok_or(GetTenantError::NotFound(tenant_id))?;
     if active_only && !tenant.is_active() {
         Err(GetTenantError::NotActive(tenant_id))
     } else {
-        Ok(Arc::clone(tenant))
+        Ok(tenant)
     }
 }
 
@@ -473,7 +652,7 @@ pub async fn detach_tenant(
 
 async fn detach_tenant0(
     conf: &'static PageServerConf,
-    tenants: &tokio::sync::RwLock<TenantsMap>,
+    tenants: &TenantsMap,
     tenant_id: TenantId,
     detach_ignored: bool,
 ) -> Result<(), TenantStateError> {
@@ -541,7 +720,7 @@ pub async fn ignore_tenant(
 
 async fn ignore_tenant0(
     conf: &'static PageServerConf,
-    tenants: &tokio::sync::RwLock<TenantsMap>,
+    tenants: &TenantsMap,
     tenant_id: TenantId,
 ) -> Result<(), TenantStateError> {
     remove_tenant_from_memory(tenants, tenant_id, async {
@@ -569,16 +748,56 @@ pub enum TenantMapListError {
 /// Get list of tenants, for the mgmt API
 ///
 pub async fn list_tenants() -> Result<Vec<(TenantId, TenantState)>, TenantMapListError> {
-    let tenants = TENANTS.read().await;
-    let m = match &*tenants {
-        TenantsMap::Initializing => return Err(TenantMapListError::Initializing),
-        TenantsMap::Open(m) | TenantsMap::ShuttingDown(m) => m,
-    };
-    Ok(m.iter()
-        .map(|(id, tenant)| (*id, tenant.current_state()))
+    if matches!(&*TENANTS.phase.read().await, TenantsMapPhase::Initializing) {
+        return Err(TenantMapListError::Initializing);
+    }
+    Ok(TENANTS
+        .all()
+        .await
+        .iter()
+        .map(|tenant| (tenant.tenant_id(), tenant.current_state()))
         .collect())
 }
 
+///
+/// Get list of the tenants with an ignore mark on the local disk, for the mgmt API.
+/// These are not loaded on pageserver restarts, until `load` is called on them.
+///
+pub async fn list_ignored_tenants(conf: &'static PageServerConf) -> anyhow::Result<Vec<TenantId>> {
+    let tenants_dir = conf.tenants_path();
+    let mut dir_entries = fs::read_dir(&tenants_dir)
+        .await
+        .with_context(|| format!("Failed to list tenants dir {tenants_dir:?}"))?;
+
+    let mut ignored = Vec::new();
+    while let Some(dir_entry) = dir_entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Failed to list tenants dir {tenants_dir:?}"))?
+    {
+        let Some(tenant_id) = dir_entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<TenantId>().ok())
+        else {
+            continue;
+        };
+        if conf.tenant_ignore_mark_file_path(&tenant_id).exists() {
+            ignored.push(tenant_id);
+        }
+    }
+    ignored.sort();
+    Ok(ignored)
+}
+
+/// Like [`list_tenants`], but the tenants themselves, for the details of each.
+pub async fn list_tenants_detailed() -> Result<Vec<Arc<Tenant>>, TenantMapListError> {
+    if matches!(&*TENANTS.phase.read().await, TenantsMapPhase::Initializing) {
+        return Err(TenantMapListError::Initializing);
+    }
+    Ok(TENANTS.all().await)
+}
+
 /// Execute Attach mgmt API command.
 ///
 /// Downloading all the tenant data is performed in the background, this merely
@@ -630,12 +849,11 @@ pub enum TenantMapInsertError {
     Closure(#[from] anyhow::Error),
 }
 
-/// Give the given closure access to the tenants map entry for the given `tenant_id`, iff that
-/// entry is vacant. The closure is responsible for creating the tenant object and inserting
-/// it into the tenants map through the vacnt entry that it receives as argument.
+/// Runs the given closure to create the tenant object for `tenant_id`, iff the tenant is not
+/// in the tenants map yet, and inserts the created tenant into the map.
 ///
-/// NB: the closure should return quickly because the current implementation of tenants map
-/// serializes access through an `RwLock`.
+/// The closure runs with the slot of the tenant locked, so lookups of this tenant wait for
+/// it, but the other tenants are not affected.
 async fn tenant_map_insert<F>(
     tenant_id: TenantId,
     insert_fn: F,
@@ -643,24 +861,94 @@ async fn tenant_map_insert<F>(
 where
     F: FnOnce() -> anyhow::Result<Arc<Tenant>>,
 {
-    let mut guard = TENANTS.write().await;
-    let m = match &mut *guard {
-        TenantsMap::Initializing => return Err(TenantMapInsertError::StillInitializing),
-        TenantsMap::ShuttingDown(_) => return Err(TenantMapInsertError::ShuttingDown),
-        TenantsMap::Open(m) => m,
-    };
-    match m.entry(tenant_id) {
-        hash_map::Entry::Occupied(e) => Err(TenantMapInsertError::TenantAlreadyExists(
-            tenant_id,
-            e.get().current_state(),
-        )),
-        hash_map::Entry::Vacant(v) => match insert_fn() {
+    TENANTS.insert_with(tenant_id, insert_fn).await
+}
+
+impl TenantsMap {
+    /// See [`tenant_map_insert`].
+    async fn insert_with<F>(
+        &'static self,
+        tenant_id: TenantId,
+        insert_fn: F,
+    ) -> Result<Arc<Tenant>, TenantMapInsertError>
+    where
+        F: FnOnce() -> anyhow::Result<Arc<Tenant>>,
+    {
+        let phase = self.phase.read().await;
+        match &*phase {
+            TenantsMapPhase::Initializing => return Err(TenantMapInsertError::StillInitializing),
+            TenantsMapPhase::ShuttingDown => return Err(TenantMapInsertError::ShuttingDown),
+            TenantsMapPhase::Open => {}
+        }
+        let (_reservation, mut guard) = loop {
+            let reservation = SlotReservation {
+                tenants: self,
+                tenant_id,
+                slot: Arc::clone(
+                    self.shard(&tenant_id)
+                        .write()
+                        .unwrap()
+                        .entry(tenant_id)
+                        .or_default(),
+                ),
+            };
+            let guard = Arc::clone(&reservation.slot).write_owned().await;
+            // a concurrent removal of the tenant may have pruned the slot while we waited
+            if self.is_current(&tenant_id, &reservation.slot) {
+                break (reservation, guard);
+            }
+        };
+        if let Some(tenant) = &*guard {
+            return Err(TenantMapInsertError::TenantAlreadyExists(
+                tenant_id,
+                tenant.current_state(),
+            ));
+        }
+        match insert_fn() {
             Ok(tenant) => {
-                v.insert(tenant.clone());
+                *guard = Some(Arc::clone(&tenant));
                 Ok(tenant)
             }
-            Err(e) => Err(TenantMapInsertError::Closure(e)),
-        },
+            Err(e) => {
+                // the reservation prunes the slot
+                drop(guard);
+                Err(TenantMapInsertError::Closure(e))
+            }
+        }
+    }
+}
+
+/// A slot of [`TenantsMap`] used by an insertion, pruned when it's left empty because the
+/// insertion fails or is cancelled, e.g. while it waits for the lock of the slot.
+struct SlotReservation {
+    tenants: &'static TenantsMap,
+    tenant_id: TenantId,
+    slot: TenantSlot,
+}
+
+impl Drop for SlotReservation {
+    fn drop(&mut self) {
+        let (tenants, tenant_id) = (self.tenants, self.tenant_id);
+        match self.slot.try_write() {
+            Ok(tenant) => {
+                if tenant.is_none() {
+                    tenants.prune(&tenant_id, &self.slot);
+                }
+            }
+            // Locked by another insertion, which prunes it if needed, or by a lookup,
+            // to wait for
+            Err(_) => {
+                let slot = Arc::clone(&self.slot);
+                if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                    handle.spawn(async move {
+                        let tenant = slot.write().await;
+                        if tenant.is_none() {
+                            tenants.prune(&tenant_id, &slot);
+                        }
+                    });
+                }
+            }
+        }
     }
 }
 
@@ -669,7 +957,7 @@ where
 /// If the cleanup fails, tenant will stay in memory in [`TenantState::Broken`] state, and another removal
 /// operation would be needed to remove it.
 async fn remove_tenant_from_memory<V, F>(
-    tenants: &tokio::sync::RwLock<TenantsMap>,
+    tenants: &TenantsMap,
     tenant_id: TenantId,
     tenant_cleanup: F,
 ) -> Result<V, TenantStateError>
@@ -679,17 +967,12 @@ where
     use utils::completion;
 
     // It's important to keep the tenant in memory after the final cleanup, to avoid cleanup races.
-    // The exclusive lock here ensures we don't miss the tenant state updates before trying another removal.
     // tenant-wde cleanup operations may take some time (removing the entire tenant directory), we want to
     // avoid holding the lock for the entire process.
-    let tenant = {
-        tenants
-            .write()
-            .await
-            .get(&tenant_id)
-            .cloned()
-            .ok_or(TenantStateError::NotFound(tenant_id))?
-    };
+    let tenant = tenants
+        .get(&tenant_id)
+        .await
+        .ok_or(TenantStateError::NotFound(tenant_id))?;
 
     // allow pageserver shutdown to await for our completion
     let (_guard, progress) = completion::channel();
@@ -713,15 +996,14 @@ where
         .with_context(|| format!("Failed to run cleanup for tenant {tenant_id}"))
     {
         Ok(hook_value) => {
-            let mut tenants_accessor = tenants.write().await;
-            if tenants_accessor.remove(&tenant_id).is_none() {
+            if tenants.remove(&tenant_id).await.is_none() {
                 warn!("Tenant {tenant_id} got removed from memory before operation finished");
             }
             Ok(hook_value)
         }
         Err(e) => {
-            let tenants_accessor = tenants.read().await;
-            match tenants_accessor.get(&tenant_id) {
+            let tenant = tenants.read(&tenant_id).await;
+            match tenant.get() {
                 Some(tenant) => {
                     tenant.set_broken(e.to_string()).await;
                 }
@@ -746,9 +1028,9 @@ pub async fn immediate_gc(
     gc_req: TimelineGcRequest,
     ctx: &RequestContext,
 ) -> Result<tokio::sync::oneshot::Receiver<Result<GcResult, anyhow::Error>>, ApiError> {
-    let guard = TENANTS.read().await;
+    let guard = TENANTS.read(&tenant_id).await;
     let tenant = guard
-        .get(&tenant_id)
+        .get()
         .map(Arc::clone)
         .with_context(|| format!("tenant {tenant_id}"))
         .map_err(|e| ApiError::NotFound(e.into()))?;
@@ -794,9 +1076,38 @@ mod tests {
     use std::collections::HashMap;
     use std::sync::Arc;
     use tracing::{info_span, Instrument};
+    use utils::id::TenantId;
 
     use super::{super::harness::TenantHarness, TenantsMap};
 
+    #[tokio::test]
+    async fn failed_or_cancelled_insert_releases_slot() {
+        let tenants: &'static TenantsMap = Box::leak(Box::new(TenantsMap::new()));
+        tenants.open(HashMap::new()).await;
+        let id = TenantId::generate();
+
+        let res = tenants
+            .insert_with(id, || Err(anyhow::anyhow!("failed")))
+            .await;
+        assert!(res.is_err());
+        assert!(tenants.slot(&id).is_none());
+
+        // a lookup holds the slot of the insertion, which is cancelled while it waits
+        let slot = Arc::clone(tenants.shard(&id).write().unwrap().entry(id).or_default());
+        let lookup = Arc::clone(&slot).read_owned().await;
+        let insert = tenants.insert_with(id, || unreachable!("the insertion is cancelled"));
+        assert!(futures::future::poll_immediate(insert).await.is_none());
+
+        drop(lookup);
+        for _ in 0..10 {
+            if tenants.slot(&id).is_none() {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert!(tenants.slot(&id).is_none());
+    }
+
     #[tokio::test(start_paused = true)]
     async fn shutdown_joins_remove_tenant_from_memory() {
         // the test is a bit ugly with the lockstep together with spawned tasks. the aim is to make
@@ -816,8 +1127,8 @@ mod tests {
         // tenant harness configures the logging and we cannot escape it
         let _e = info_span!("testing", tenant_id = %id).entered();
 
-        let tenants = HashMap::from([(id, t.clone())]);
-        let tenants = Arc::new(tokio::sync::RwLock::new(TenantsMap::Open(tenants)));
+        let tenants = Arc::new(TenantsMap::new());
+        tenants.open(HashMap::from([(id, t.clone())])).await;
 
         let (until_cleanup_completed, can_complete_cleanup) = utils::completion::channel();
         let (until_cleanup_started, cleanup_started) = utils::completion::channel();
@@ -0,0 +1,153 @@
+//!
+//! The presence map of a sparse image layer file.
+//!
+//! A sparse image layer holds images of only some of the keys in its key
+//! range. The presence map lists the key ranges that the layer covers. Keys
+//! outside of them have to be looked up in older layers.
+//!
+//! On disk, the map starts at a block boundary. It begins with the number of
+//! ranges as a big-endian u32, followed by the start and end key of each
+//! range. The last block is zero-padded.
+//!
+use crate::keyspace::KeySpace;
+use crate::page_cache::PAGE_SZ;
+use crate::repository::{Key, KEY_SIZE};
+use crate::tenant::block_io::BlockReader;
+use std::cmp::min;
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+
+const HEADER_SIZE: usize = 4;
+const RANGE_SIZE: usize = 2 * KEY_SIZE;
+
+pub struct PresenceMap {
+    /// In key order, and with no overlap.
+    ranges: Vec<Range<Key>>,
+}
+
+impl PresenceMap {
+    pub fn new(keyspace: KeySpace) -> Self {
+        PresenceMap {
+            ranges: keyspace.ranges,
+        }
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        // Find the last range that starts at or before the key
+        match self.ranges.partition_point(|r| r.start <= *key) {
+            0 => false,
+            i => self.ranges[i - 1].end > *key,
+        }
+    }
+
+    /// Number of keys covered by the map.
+    pub fn num_keys(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|r| crate::repository::key_range_size(r) as u64)
+            .sum()
+    }
+
+    /// Serializes the map, padded to a whole number of blocks.
+    pub fn to_blocks(&self) -> Vec<u8> {
+        let len = HEADER_SIZE + self.ranges.len() * RANGE_SIZE;
+        let padded_len = (len + PAGE_SZ - 1) / PAGE_SZ * PAGE_SZ;
+        let mut buf = Vec::with_capacity(padded_len);
+        buf.extend_from_slice(&(self.ranges.len() as u32).to_be_bytes());
+        let mut keybuf = [0u8; KEY_SIZE];
+        for range in &self.ranges {
+            range.start.write_to_byte_slice(&mut keybuf);
+            buf.extend_from_slice(&keybuf);
+            range.end.write_to_byte_slice(&mut keybuf);
+            buf.extend_from_slice(&keybuf);
+        }
+        buf.resize(padded_len, 0);
+        buf
+    }
+
+    /// Reads a map stored at the given block.
+    pub fn read<R: BlockReader>(reader: &R, start_blk: u32) -> Result<Self, Error> {
+        let mut blknum = start_blk;
+        let blk = reader.read_blk(blknum)?;
+        let num_ranges = u32::from_be_bytes(blk[0..4].try_into().unwrap()) as usize;
+
+        let len = HEADER_SIZE + num_ranges * RANGE_SIZE;
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&blk[..min(len, PAGE_SZ)]);
+        drop(blk);
+        while buf.len() < len {
+            blknum += 1;
+            let blk = reader.read_blk(blknum)?;
+            buf.extend_from_slice(&blk[..min(len - buf.len(), PAGE_SZ)]);
+        }
+
+        let ranges: Vec<Range<Key>> = buf[HEADER_SIZE..]
+            .chunks_exact(RANGE_SIZE)
+            .map(|range| {
+                let (start, end) = range.split_at(KEY_SIZE);
+                Key::from_slice(start)..Key::from_slice(end)
+            })
+            .collect();
+        let sorted = ranges.windows(2).all(|pair| pair[0].end <= pair[1].start);
+        if !sorted || ranges.iter().any(|r| r.is_empty()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid presence map at block {start_blk}"),
+            ));
+        }
+        Ok(PresenceMap { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::block_io::BlockLease;
+
+    struct TestFile(Vec<u8>);
+
+    impl BlockReader for TestFile {
+        fn read_blk(&self, blknum: u32) -> Result<BlockLease, Error> {
+            let start = blknum as usize * PAGE_SZ;
+            let mut buf = [0u8; PAGE_SZ];
+            buf.copy_from_slice(&self.0[start..start + PAGE_SZ]);
+            Ok(std::rc::Rc::new(buf).into())
+        }
+    }
+
+    fn kr(irange: Range<i128>) -> Range<Key> {
+        Key::from_i128(irange.start)..Key::from_i128(irange.end)
+    }
+
+    #[test]
+    fn test_presence_map() -> Result<(), Error> {
+        // Enough ranges to span several blocks
+        let ranges: Vec<Range<Key>> = (0..1000).map(|i| kr(i * 10..i * 10 + 5)).collect();
+        let map = PresenceMap::new(KeySpace { ranges });
+        assert_eq!(map.num_keys(), 5000);
+
+        let mut file = TestFile(vec![0xff; PAGE_SZ]);
+        file.0.extend(map.to_blocks());
+        assert_eq!(file.0.len() % PAGE_SZ, 0);
+        let map = PresenceMap::read(&file, 1)?;
+
+        assert!(map.contains(&Key::from_i128(0)));
+        assert!(map.contains(&Key::from_i128(4)));
+        assert!(!map.contains(&Key::from_i128(5)));
+        assert!(!map.contains(&Key::from_i128(9)));
+        assert!(map.contains(&Key::from_i128(9994)));
+        assert!(!map.contains(&Key::from_i128(9995)));
+        assert!(!map.contains(&Key::from_i128(20000)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_presence_map() -> Result<(), Error> {
+        let map = PresenceMap::new(KeySpace::default());
+        let file = TestFile(map.to_blocks());
+        let map = PresenceMap::read(&file, 0)?;
+        assert!(!map.contains(&Key::from_i128(0)));
+        Ok(())
+    }
+}
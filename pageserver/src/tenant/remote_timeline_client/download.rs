@@ -66,6 +66,10 @@ pub async fn download_layer_file<'a>(
 
     let (mut destination_file, bytes_amount) = download_retry(
         || async {
+            utils::failpoint_async!("download-layer-file", |msg| {
+                return Err(DownloadError::Other(anyhow::anyhow!(msg)));
+            });
+
             // TODO: this doesn't use the cached fd for some reason?
             let mut destination_file = fs::File::create(&temp_file_path).await.with_context(|| {
                 format!(
@@ -5,6 +5,7 @@ mod filename;
 mod image_layer;
 mod inmemory_layer;
 mod layer_desc;
+pub mod merge_iterator;
 mod remote_layer;
 
 use crate::config::PageServerConf;
@@ -12,7 +13,7 @@ use crate::context::{AccessStatsBehavior, RequestContext};
 use crate::repository::Key;
 use crate::task_mgr::TaskKind;
 use crate::walrecord::NeonWalRecord;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bytes::Bytes;
 use enum_map::EnumMap;
 use enumset::EnumSet;
@@ -22,7 +23,8 @@ use pageserver_api::models::{
     HistoricLayerInfo, LayerResidenceEvent, LayerResidenceEventReason, LayerResidenceStatus,
 };
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::warn;
@@ -43,6 +45,41 @@ pub use remote_layer::RemoteLayer;
 
 use super::timeline::layer_manager::LayerManager;
 
+/// Checks every block of the layer file at `path` against its checksum,
+/// bypassing the page cache. Returns the numbers of the corrupt blocks.
+///
+/// Fails for files written before block checksums were added to the format.
+pub fn find_corrupt_blocks(path: &Path) -> Result<Vec<u32>> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("path {} has no file name", path.display()))?
+        .to_string_lossy();
+    match LayerFileName::from_str(&file_name).map_err(|e| anyhow::anyhow!(e))? {
+        LayerFileName::Image(_) => image_layer::ImageLayerInner::find_corrupt_blocks(path),
+        LayerFileName::Delta(_) => delta_layer::DeltaLayerInner::find_corrupt_blocks(path),
+    }
+}
+
+/// Add a suffix to a layer file's name: .{num}.old
+/// Uses the first available num (starts at 0)
+pub fn rename_to_backup(path: &Path) -> Result<PathBuf> {
+    let filename = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Path {} don't have a file name", path.display()))?
+        .to_string_lossy();
+    let mut new_path = path.to_owned();
+
+    for i in 0u32.. {
+        new_path.set_file_name(format!("{filename}.{i}.old"));
+        if !new_path.exists() {
+            std::fs::rename(path, &new_path)?;
+            return Ok(new_path);
+        }
+    }
+
+    anyhow::bail!("couldn't find an unused backup number for {:?}", path)
+}
+
 pub fn range_overlaps<T>(a: &Range<T>, b: &Range<T>) -> bool
 where
     T: PartialOrd<T>,
@@ -91,6 +128,19 @@ pub enum ValueReconstructResult {
     Missing,
 }
 
+/// One key of a vectored read, see [`Layer::get_values_reconstruct_data`].
+///
+/// `state` is carried over from layer to layer just like the
+/// [`ValueReconstructState`] of a single-key read, and `result` holds the
+/// outcome of the last layer visited.
+#[derive(Debug)]
+pub struct VectoredValueRead {
+    pub key: Key,
+    pub lsn_range: Range<Lsn>,
+    pub state: ValueReconstructState,
+    pub result: ValueReconstructResult,
+}
+
 #[derive(Debug)]
 pub struct LayerAccessStats(Mutex<LayerAccessStatsLocked>);
 
@@ -381,6 +431,36 @@ pub trait Layer: std::fmt::Debug + std::fmt::Display + Send + Sync + 'static {
         ctx: &RequestContext,
     ) -> Result<ValueReconstructResult>;
 
+    ///
+    /// Vectored version of [`Layer::get_value_reconstruct_data`]: collect the
+    /// data for all `reads` from this layer, storing the outcome for each key in
+    /// its `result` field.
+    ///
+    /// The default implementation does one lookup per key. On-disk layers
+    /// override it to read all the values in file order, so that each block is
+    /// read only once.
+    async fn get_values_reconstruct_data(
+        &self,
+        reads: &mut [VectoredValueRead],
+        ctx: &RequestContext,
+    ) -> Result<()> {
+        for read in reads.iter_mut() {
+            read.result = self
+                .get_value_reconstruct_data(read.key, read.lsn_range.clone(), &mut read.state, ctx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns false if the layer definitely doesn't contain any values for
+    /// `key`, so that layer map traversal can skip it without searching it.
+    ///
+    /// This must not do any I/O. The default implementation can't tell, and
+    /// returns true.
+    fn may_contain_key(&self, _key: Key) -> bool {
+        true
+    }
+
     /// Dump summary of the contents of the layer to stdout
     async fn dump(&self, verbose: bool, ctx: &RequestContext) -> Result<()>;
 }
@@ -25,27 +25,43 @@
 //! and it contains basic information about the layer, and offsets to the other
 //! parts. The "index" is a B-tree, mapping from Key and LSN to an offset in the
 //! "values" part.  The actual page images and WAL records are stored in the
-//! "values" part.
+//! "values" part. Since format version 5, the index is followed by a bloom
+//! filter over the keys in the file, see [`crate::tenant::bloom_filter`].
+//! Since format version 6, the file ends with a table of the checksums of all
+//! the blocks before it, which are verified when the blocks are read.
+//!
+//! The blocks after the summary may be compressed, each on its own, in which
+//! case the file ends with a map of where they are, see
+//! [`block_io::CompressedBlockWriter`]. The checksums are those of the
+//! uncompressed blocks.
 //!
 use crate::config::PageServerConf;
 use crate::context::RequestContext;
+use crate::metrics::LAYERS_SKIPPED_BY_BLOOM_FILTER;
 use crate::page_cache::PAGE_SZ;
 use crate::repository::{Key, Value, KEY_SIZE};
 use crate::tenant::blob_io::{BlobWriter, WriteBlobWriter};
-use crate::tenant::block_io::{BlockBuf, BlockCursor, BlockLease, BlockReader, FileBlockReader};
+use crate::tenant::block_io::{
+    self, checksums_to_blocks, BlockBuf, BlockChecksumWriter, BlockCursor, BlockLease, BlockReader,
+    CompressedBlockWriter, FileBlockReader,
+};
+use crate::tenant::bloom_filter::{self, BloomFilter};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
 use crate::tenant::storage_layer::{
-    PersistentLayer, ValueReconstructResult, ValueReconstructState,
+    PersistentLayer, ValueReconstructResult, ValueReconstructState, VectoredValueRead,
 };
 use crate::virtual_file::VirtualFile;
 use crate::{walrecord, TEMP_FILE_SUFFIX};
-use crate::{DELTA_FILE_MAGIC, STORAGE_FORMAT_VERSION};
+use crate::{
+    BLOOM_FILTER_FORMAT_VERSION, CHECKSUMS_FORMAT_VERSION, COMPRESSED_BLOCKS_FORMAT_VERSION,
+    DELTA_FILE_MAGIC, MIN_STORAGE_FORMAT_VERSION, STORAGE_FORMAT_VERSION,
+};
 use anyhow::{bail, ensure, Context, Result};
 use pageserver_api::models::{HistoricLayerInfo, LayerAccessKind};
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::io::{Read, Write};
 use std::io::{Seek, SeekFrom};
 use std::ops::Range;
 use std::os::unix::fs::FileExt;
@@ -57,6 +73,7 @@ use tracing::*;
 
 use utils::{
     bin_ser::BeSer,
+    checksummed_writer::{ChecksummedWriter, SyncPolicy},
     id::{TenantId, TimelineId},
     lsn::Lsn,
 };
@@ -76,7 +93,8 @@ use super::{
 pub struct Summary {
     /// Magic value to identify this as a neon delta file. Always DELTA_FILE_MAGIC.
     magic: u16,
-    format_version: u16,
+    /// Storage format version the file was written with, see [`crate::STORAGE_FORMAT_VERSION`].
+    pub format_version: u16,
 
     tenant_id: TenantId,
     timeline_id: TimelineId,
@@ -87,6 +105,15 @@ pub struct Summary {
     pub index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     pub index_root_blk: u32,
+    /// Block where the bloom filter over the keys begins. Files written
+    /// before format version 5 don't have this field, it reads as 0 there.
+    pub bloom_filter_blk: u32,
+    /// Block where the checksum table begins. It covers all blocks before it.
+    /// 0 in files written before format version 6.
+    pub checksums_blk: u32,
+    /// Whether the blocks after the summary are compressed, and the file ends
+    /// with a map of them. Always false in files written before format version 4.
+    pub compressed_blocks: bool,
 }
 
 impl From<&DeltaLayer> for Summary {
@@ -118,6 +145,9 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            bloom_filter_blk: 0,
+            checksums_blk: 0,
+            compressed_blocks: false,
         }
     }
 }
@@ -223,6 +253,10 @@ pub struct DeltaLayerInner {
     index_start_blk: u32,
     index_root_blk: u32,
 
+    /// Filter over the keys in the file. None for files written before
+    /// format version 5.
+    bloom_filter: Option<BloomFilter>,
+
     /// Reader object for reading blocks from the file.
     file: FileBlockReader<VirtualFile>,
 }
@@ -342,6 +376,29 @@ impl Layer for DeltaLayer {
             .await
     }
 
+    async fn get_values_reconstruct_data(
+        &self,
+        reads: &mut [VectoredValueRead],
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        for read in reads.iter() {
+            ensure!(read.lsn_range.start >= self.desc.lsn_range.start);
+            ensure!(self.desc.key_range.contains(&read.key));
+        }
+
+        let inner = self
+            .load(LayerAccessKind::GetValueReconstructData, ctx)
+            .await?;
+        inner.get_values_reconstruct_data(reads).await
+    }
+
+    fn may_contain_key(&self, key: Key) -> bool {
+        // The bloom filter is only available once the file has been loaded.
+        self.inner
+            .get()
+            .map_or(true, |inner| inner.may_contain_key(&key))
+    }
+
     /// Boilerplate to implement the Layer trait, always use layer_desc for persistent layers.
     fn get_key_range(&self) -> Range<Key> {
         self.layer_desc().key_range.clone()
@@ -576,6 +633,23 @@ impl DeltaLayer {
             .await
             .context("Layer index is corrupted")
     }
+
+    /// Counts the records of each key that fall into the given LSN range.
+    /// Returns key, number of records and their approximate size on disk.
+    pub async fn load_key_changes(
+        &self,
+        lsn_range: Range<Lsn>,
+        ctx: &RequestContext,
+    ) -> Result<Vec<(Key, u64, u64)>> {
+        let inner = self
+            .load(LayerAccessKind::KeyIter, ctx)
+            .await
+            .context("load delta layer keys")?;
+        inner
+            .load_key_changes(lsn_range)
+            .await
+            .context("Layer index is corrupted")
+    }
 }
 
 /// A builder object for constructing a new delta layer.
@@ -600,7 +674,12 @@ struct DeltaLayerWriterInner {
 
     tree: DiskBtreeBuilder<BlockBuf, DELTA_KEY_SIZE>,
 
-    blob_writer: WriteBlobWriter<BufWriter<VirtualFile>>,
+    blob_writer:
+        WriteBlobWriter<BlockChecksumWriter<CompressedBlockWriter<ChecksummedWriter<VirtualFile>>>>,
+
+    /// Hashes of the distinct keys written so far, for the bloom filter.
+    key_hashes: Vec<u64>,
+    last_key: Option<Key>,
 }
 
 impl DeltaLayerWriterInner {
@@ -613,6 +692,7 @@ impl DeltaLayerWriterInner {
         tenant_id: TenantId,
         key_start: Key,
         lsn_range: Range<Lsn>,
+        compression_level: Option<i32>,
     ) -> anyhow::Result<Self> {
         // Create the file initially with a temporary filename. We don't know
         // the end key yet, so we cannot form the final filename yet. We will
@@ -625,8 +705,10 @@ impl DeltaLayerWriterInner {
         let mut file = VirtualFile::create(&path)?;
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64))?;
-        let buf_writer = BufWriter::new(file);
-        let blob_writer = WriteBlobWriter::new(buf_writer, PAGE_SZ as u64);
+        let buf_writer =
+            CompressedBlockWriter::new(ChecksummedWriter::new(file), 1, compression_level)?;
+        let blob_writer =
+            WriteBlobWriter::new(BlockChecksumWriter::new(buf_writer, 1), PAGE_SZ as u64);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -641,6 +723,8 @@ impl DeltaLayerWriterInner {
             lsn_range,
             tree: tree_builder,
             blob_writer,
+            key_hashes: Vec::new(),
+            last_key: None,
         })
     }
 
@@ -669,6 +753,13 @@ impl DeltaLayerWriterInner {
         let delta_key = DeltaKey::from_key_lsn(&key, lsn);
         self.tree.append(&delta_key.0, blob_ref.0)?;
 
+        // Values are appended in key order, so each key is seen in one run
+        if self.last_key != Some(key) {
+            self.key_hashes
+                .push(bloom_filter::key_hash(&delta_key.0[..KEY_SIZE]));
+            self.last_key = Some(key);
+        }
+
         Ok(())
     }
 
@@ -683,15 +774,23 @@ impl DeltaLayerWriterInner {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
-        let buf_writer = self.blob_writer.into_inner();
-        let mut file = buf_writer.into_inner()?;
+        let mut writer = self.blob_writer.into_inner();
 
         // Write out the index
         let (index_root_blk, block_buf) = self.tree.finish()?;
-        file.seek(SeekFrom::Start(index_start_blk as u64 * PAGE_SZ as u64))?;
+        writer.pad_to_block()?;
+        assert_eq!(writer.next_blk(), index_start_blk);
+        let bloom_filter_blk = index_start_blk + block_buf.blocks.len() as u32;
         for buf in block_buf.blocks {
-            file.write_all(buf.as_ref())?;
+            writer.write_all(buf.as_ref())?;
         }
+
+        // Write out the bloom filter, right after the index
+        writer.write_all(&BloomFilter::from_hashes(&self.key_hashes).to_blocks())?;
+
+        let checksums_blk = writer.next_blk();
+        let (mut writer, block_checksums) = writer.into_inner();
+
         assert!(self.lsn_range.start < self.lsn_range.end);
         // Fill in the summary on blk 0
         let summary = Summary {
@@ -703,9 +802,23 @@ impl DeltaLayerWriterInner {
             lsn_range: self.lsn_range.clone(),
             index_start_blk,
             index_root_blk,
+            bloom_filter_blk,
+            checksums_blk,
+            compressed_blocks: writer.is_compressed(),
         };
+        let mut summary_buf = Summary::ser(&summary)?;
+        summary_buf.resize(PAGE_SZ, 0);
+
+        // Write out the checksums of all the blocks, including the summary
+        let mut checksums = vec![crc32c::crc32c(&summary_buf)];
+        checksums.extend(block_checksums);
+        writer.write_all(&checksums_to_blocks(&checksums))?;
+        let writer = writer.finish()?;
+        let written = writer.written();
+        let (mut file, crc) = writer.into_inner()?;
+
         file.seek(SeekFrom::Start(0))?;
-        Summary::ser_into(&summary, &mut file)?;
+        file.write_all(&summary_buf)?;
 
         let metadata = file
             .metadata()
@@ -728,7 +841,12 @@ impl DeltaLayerWriterInner {
         };
 
         // fsync the file
-        file.sync_all()?;
+        SyncPolicy::All.sync(&file)?;
+        if self.conf.verify_layer_writes {
+            block_io::verify_written_blocks(&self.path, written, crc).with_context(|| {
+                format!("verify the written delta layer {}", self.path.display())
+            })?;
+        }
         // Rename the file to its final name
         //
         // Note: This overwrites any existing file. There shouldn't be any.
@@ -780,12 +898,16 @@ impl DeltaLayerWriter {
     ///
     /// Start building a new delta layer.
     ///
+    /// If `compression_level` is set, the blocks of the file are compressed with
+    /// zstd at that level.
+    ///
     pub fn new(
         conf: &'static PageServerConf,
         timeline_id: TimelineId,
         tenant_id: TenantId,
         key_start: Key,
         lsn_range: Range<Lsn>,
+        compression_level: Option<i32>,
     ) -> anyhow::Result<Self> {
         Ok(Self {
             inner: Some(DeltaLayerWriterInner::new(
@@ -794,6 +916,7 @@ impl DeltaLayerWriter {
                 tenant_id,
                 key_start,
                 lsn_range,
+                compression_level,
             )?),
         })
     }
@@ -835,13 +958,13 @@ impl DeltaLayerWriter {
 impl Drop for DeltaLayerWriter {
     fn drop(&mut self) {
         if let Some(inner) = self.inner.take() {
-            match inner.blob_writer.into_inner().into_inner() {
-                Ok(vfile) => vfile.remove(),
-                Err(err) => warn!(
-                    "error while flushing buffer of image layer temporary file: {}",
-                    err
-                ),
-            }
+            inner
+                .blob_writer
+                .into_inner()
+                .into_writer()
+                .into_writer()
+                .discard()
+                .remove();
         }
     }
 }
@@ -855,10 +978,39 @@ impl DeltaLayerInner {
         let summary_blk = file.read_blk(0)?;
         let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
 
+        if !(MIN_STORAGE_FORMAT_VERSION..=STORAGE_FORMAT_VERSION)
+            .contains(&actual_summary.format_version)
+        {
+            bail!(
+                "unsupported layer file format version {}",
+                actual_summary.format_version
+            );
+        }
+
+        let file = if actual_summary.format_version >= COMPRESSED_BLOCKS_FORMAT_VERSION
+            && actual_summary.compressed_blocks
+        {
+            let file_len = file.file.metadata()?.len();
+            file.with_compressed_blocks(file_len)
+                .context("load block map")?
+        } else {
+            file
+        };
+        let file = if actual_summary.format_version >= CHECKSUMS_FORMAT_VERSION {
+            file.with_checksums(actual_summary.checksums_blk)
+                .context("load block checksums")?
+        } else {
+            file
+        };
+
         if let Some(mut expected_summary) = summary {
             // production code path
+            expected_summary.format_version = actual_summary.format_version;
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.bloom_filter_blk = actual_summary.bloom_filter_blk;
+            expected_summary.checksums_blk = actual_summary.checksums_blk;
+            expected_summary.compressed_blocks = actual_summary.compressed_blocks;
             if actual_summary != expected_summary {
                 bail!(
                     "in-file summary does not match expected summary. actual = {:?} expected = {:?}",
@@ -868,19 +1020,74 @@ impl DeltaLayerInner {
             }
         }
 
+        let bloom_filter = if actual_summary.format_version >= BLOOM_FILTER_FORMAT_VERSION {
+            Some(
+                BloomFilter::read(&file, actual_summary.bloom_filter_blk)
+                    .context("read bloom filter")?,
+            )
+        } else {
+            None
+        };
+
         Ok(DeltaLayerInner {
+            bloom_filter,
             file,
             index_start_blk: actual_summary.index_start_blk,
             index_root_blk: actual_summary.index_root_blk,
         })
     }
 
+    /// Checks every block of the layer file at `path` against its checksum,
+    /// and returns the numbers of the blocks that don't match.
+    pub(super) fn find_corrupt_blocks(path: &Path) -> anyhow::Result<Vec<u32>> {
+        let mut file = File::open(path)
+            .with_context(|| format!("Failed to open file '{}'", path.display()))?;
+        let mut summary_buf = [0u8; PAGE_SZ];
+        file.read_exact(&mut summary_buf)?;
+        let summary = Summary::des_prefix(&summary_buf)?;
+        ensure!(
+            summary.magic == DELTA_FILE_MAGIC,
+            "bad magic in layer file summary"
+        );
+        if !(CHECKSUMS_FORMAT_VERSION..=STORAGE_FORMAT_VERSION).contains(&summary.format_version) {
+            bail!(
+                "layer file format version {} has no block checksums",
+                summary.format_version
+            );
+        }
+        let file_len = file.metadata()?.len();
+        Ok(block_io::find_corrupt_file_blocks(
+            &file,
+            file_len,
+            summary.checksums_blk,
+            summary.compressed_blocks,
+        )?)
+    }
+
+    /// Returns false if the file definitely doesn't contain any values for `key`.
+    pub(super) fn may_contain_key(&self, key: &Key) -> bool {
+        let Some(bloom_filter) = &self.bloom_filter else {
+            return true;
+        };
+        let mut keybuf = [0u8; KEY_SIZE];
+        key.write_to_byte_slice(&mut keybuf);
+        let may_contain = bloom_filter.may_contain(bloom_filter::key_hash(&keybuf));
+        if !may_contain {
+            LAYERS_SKIPPED_BY_BLOOM_FILTER.inc();
+        }
+        may_contain
+    }
+
     pub(super) async fn get_value_reconstruct_data(
         &self,
         key: Key,
         lsn_range: Range<Lsn>,
         reconstruct_state: &mut ValueReconstructState,
     ) -> anyhow::Result<ValueReconstructResult> {
+        if !self.may_contain_key(&key) {
+            return Ok(ValueReconstructResult::Continue);
+        }
+
         let mut need_image = true;
         // Scan the page versions backwards, starting from `lsn`.
         let file = &self.file;
@@ -955,6 +1162,114 @@ impl DeltaLayerInner {
         }
     }
 
+    pub(super) async fn get_values_reconstruct_data(
+        &self,
+        reads: &mut [VectoredValueRead],
+    ) -> anyhow::Result<()> {
+        let file = &self.file;
+        let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
+            self.index_start_blk,
+            self.index_root_blk,
+            file,
+        );
+
+        // Collect the offsets of the entries needed for each key, newest first.
+        let mut offsets: Vec<Vec<(Lsn, u64)>> = Vec::with_capacity(reads.len());
+        for read in reads.iter() {
+            if !self.may_contain_key(&read.key) {
+                offsets.push(Vec::new());
+                continue;
+            }
+            let search_key = DeltaKey::from_key_lsn(&read.key, Lsn(read.lsn_range.end.0 - 1));
+            let lsn_start = read.lsn_range.start;
+            let mut key_offsets = Vec::new();
+            tree_reader
+                .visit(&search_key.0, VisitDirection::Backwards, |key, value| {
+                    let blob_ref = BlobRef(value);
+                    if key[..KEY_SIZE] != search_key.0[..KEY_SIZE] {
+                        return false;
+                    }
+                    let entry_lsn = DeltaKey::extract_lsn_from_buf(key);
+                    if entry_lsn < lsn_start {
+                        return false;
+                    }
+                    key_offsets.push((entry_lsn, blob_ref.pos()));
+
+                    !blob_ref.will_init()
+                })
+                .await?;
+            offsets.push(key_offsets);
+        }
+
+        // Read the values of all keys in file order, so that each block of the
+        // values part is read at most once.
+        let mut reads_by_pos: Vec<(u64, usize, usize)> = offsets
+            .iter()
+            .enumerate()
+            .flat_map(|(read_idx, key_offsets)| {
+                key_offsets
+                    .iter()
+                    .enumerate()
+                    .map(move |(entry_idx, (_, pos))| (*pos, read_idx, entry_idx))
+            })
+            .collect();
+        reads_by_pos.sort_unstable();
+
+        let mut values: Vec<Vec<Option<Value>>> = offsets
+            .iter()
+            .map(|key_offsets| key_offsets.iter().map(|_| None).collect())
+            .collect();
+        let cursor = file.block_cursor();
+        let mut buf = Vec::new();
+        for (pos, read_idx, entry_idx) in reads_by_pos {
+            cursor
+                .read_blob_into_buf(pos, &mut buf)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to read blob from virtual file {}",
+                        file.file.path.display()
+                    )
+                })?;
+            let val = Value::des(&buf).with_context(|| {
+                format!(
+                    "Failed to deserialize file blob from virtual file {}",
+                    file.file.path.display()
+                )
+            })?;
+            values[read_idx][entry_idx] = Some(val);
+        }
+
+        // Apply the values to each key, newest first, like get_value_reconstruct_data does.
+        for ((read, key_offsets), key_values) in reads.iter_mut().zip(offsets).zip(values) {
+            let mut need_image = true;
+            for ((entry_lsn, _), val) in key_offsets.into_iter().zip(key_values) {
+                match val.expect("all collected values were read") {
+                    Value::Image(img) => {
+                        read.state.img = Some((entry_lsn, img));
+                        need_image = false;
+                        break;
+                    }
+                    Value::WalRecord(rec) => {
+                        let will_init = rec.will_init();
+                        read.state.records.push((entry_lsn, rec));
+                        if will_init {
+                            need_image = false;
+                            break;
+                        }
+                    }
+                }
+            }
+            read.result = if need_image {
+                ValueReconstructResult::Continue
+            } else {
+                ValueReconstructResult::Complete
+            };
+        }
+
+        Ok(())
+    }
+
     pub(super) async fn load_val_refs<T: AsRef<DeltaLayerInner> + Clone>(
         this: &T,
     ) -> Result<Vec<(Key, Lsn, ValueRef<T>)>> {
@@ -1020,6 +1335,56 @@ impl DeltaLayerInner {
         }
         Ok(all_keys)
     }
+
+    pub(super) async fn load_key_changes(
+        &self,
+        lsn_range: Range<Lsn>,
+    ) -> Result<Vec<(Key, u64, u64)>> {
+        let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
+            self.index_start_blk,
+            self.index_root_blk,
+            &self.file,
+        );
+
+        // The values are stored in the order of the index, so the size of a value
+        // is the distance to the next one.
+        fn add(changes: &mut Vec<(Key, u64, u64)>, key: Key, size: u64) {
+            match changes.last_mut() {
+                Some(last) if last.0 == key => {
+                    last.1 += 1;
+                    last.2 += size;
+                }
+                _ => changes.push((key, 1, size)),
+            }
+        }
+        let mut changes = Vec::new();
+        let mut prev: Option<(Key, Lsn, u64)> = None;
+        tree_reader
+            .visit(
+                &[0u8; DELTA_KEY_SIZE],
+                VisitDirection::Forwards,
+                |key, value| {
+                    let delta_key = DeltaKey::from_slice(key);
+                    let pos = BlobRef(value).pos();
+                    if let Some((prev_key, prev_lsn, prev_pos)) = prev {
+                        if lsn_range.contains(&prev_lsn) {
+                            add(&mut changes, prev_key, pos.saturating_sub(prev_pos));
+                        }
+                    }
+                    prev = Some((delta_key.key(), delta_key.lsn(), pos));
+                    true
+                },
+            )
+            .await?;
+        if let Some((prev_key, prev_lsn, prev_pos)) = prev {
+            // The last value extends to the index
+            if lsn_range.contains(&prev_lsn) {
+                let values_end = self.index_start_blk as u64 * PAGE_SZ as u64;
+                add(&mut changes, prev_key, values_end.saturating_sub(prev_pos));
+            }
+        }
+        Ok(changes)
+    }
 }
 
 /// Reference to an on-disk value
@@ -11,6 +11,8 @@ use utils::lsn::Lsn;
 
 use super::PersistentLayerDesc;
 
+const SPARSE_SUFFIX: &str = "-sparse";
+
 // Note: Timeline::load_layer_map() relies on this sort order
 #[derive(PartialEq, Eq, Clone, Hash)]
 pub struct DeltaFileName {
@@ -118,6 +120,9 @@ impl fmt::Display for DeltaFileName {
 pub struct ImageFileName {
     pub key_range: Range<Key>,
     pub lsn: Lsn,
+    /// Whether the layer holds images of only some of the keys in the range,
+    /// see [`crate::tenant::presence_map`].
+    pub sparse: bool,
 }
 
 impl std::fmt::Debug for ImageFileName {
@@ -127,6 +132,7 @@ impl std::fmt::Debug for ImageFileName {
         f.debug_struct("ImageFileName")
             .field("key_range", &RangeDisplayDebug(&self.key_range))
             .field("lsn", &self.lsn)
+            .field("sparse", &self.sparse)
             .finish()
     }
 }
@@ -148,6 +154,10 @@ impl Ord for ImageFileName {
             return cmp;
         }
         cmp = self.lsn.cmp(&other.lsn);
+        if cmp != Ordering::Equal {
+            return cmp;
+        }
+        cmp = self.sparse.cmp(&other.sparse);
 
         cmp
     }
@@ -166,6 +176,8 @@ impl ImageFileName {
 /// ```text
 ///    <key start>-<key end>__<LSN>
 /// ```
+///
+/// Sparse image layers have a `-sparse` suffix.
 impl ImageFileName {
     ///
     /// Parse a string as an image file name. Returns None if the filename does not
@@ -181,6 +193,10 @@ impl ImageFileName {
         if parts.next().is_some() || key_parts.next().is_some() {
             return None;
         }
+        let (lsn_str, sparse) = match lsn_str.strip_suffix(SPARSE_SUFFIX) {
+            Some(lsn_str) => (lsn_str, true),
+            None => (lsn_str, false),
+        };
 
         let key_start = Key::from_hex(key_start_str).ok()?;
         let key_end = Key::from_hex(key_end_str).ok()?;
@@ -190,6 +206,7 @@ impl ImageFileName {
         Some(ImageFileName {
             key_range: key_start..key_end,
             lsn,
+            sparse,
         })
     }
 }
@@ -198,10 +215,11 @@ impl fmt::Display for ImageFileName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}-{}__{:016X}",
+            "{}-{}__{:016X}{}",
             self.key_range.start,
             self.key_range.end,
             u64::from(self.lsn),
+            if self.sparse { SPARSE_SUFFIX } else { "" },
         )
     }
 }
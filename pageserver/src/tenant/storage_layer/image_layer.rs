@@ -3,6 +3,11 @@
 //! in its key-range. Any key that falls into the image layer's range
 //! but does not exist in the layer, does not exist.
 //!
+//! A sparse image layer is the exception: it only contains images of the
+//! keys listed in its presence map, see [`crate::tenant::presence_map`].
+//! Other keys in its key-range must be looked up in older layers. Sparse
+//! image layers are incremental, like delta layers.
+//!
 //! An image layer is stored in a file on disk. The file is stored in
 //! timelines/<timeline_id> directory.  Currently, there are no
 //! subdirectories, and each image layer file is named like this:
@@ -22,19 +27,37 @@
 //! beginning of the file, and it contains basic information about the
 //! layer, and offsets to the other parts. The "index" is a B-tree,
 //! mapping from Key to an offset in the "values" part.  The
-//! actual page images are stored in the "values" part.
+//! actual page images are stored in the "values" part. Since format
+//! version 6, the file ends with a table of the checksums of all the
+//! blocks before it, which are verified when the blocks are read. In a
+//! sparse image layer, the presence map is stored between the index and the
+//! checksums.
+//!
+//! The blocks after the summary may be compressed, each on its own, in which
+//! case the file ends with a map of where they are, see
+//! [`block_io::CompressedBlockWriter`].
 use crate::config::PageServerConf;
 use crate::context::RequestContext;
+use crate::keyspace::KeySpaceAccum;
 use crate::page_cache::PAGE_SZ;
 use crate::repository::{Key, KEY_SIZE};
 use crate::tenant::blob_io::{BlobWriter, WriteBlobWriter};
-use crate::tenant::block_io::{BlockBuf, BlockReader, FileBlockReader};
+use crate::tenant::block_io::{
+    self, checksums_to_blocks, BlockBuf, BlockChecksumWriter, BlockReader, CompressedBlockWriter,
+    FileBlockReader,
+};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
+use crate::tenant::presence_map::PresenceMap;
 use crate::tenant::storage_layer::{
     LayerAccessStats, PersistentLayer, ValueReconstructResult, ValueReconstructState,
+    VectoredValueRead,
 };
 use crate::virtual_file::VirtualFile;
-use crate::{IMAGE_FILE_MAGIC, STORAGE_FORMAT_VERSION, TEMP_FILE_SUFFIX};
+use crate::{
+    CHECKSUMS_FORMAT_VERSION, COMPRESSED_BLOCKS_FORMAT_VERSION, IMAGE_FILE_MAGIC,
+    MIN_STORAGE_FORMAT_VERSION, SPARSE_IMAGES_FORMAT_VERSION, STORAGE_FORMAT_VERSION,
+    TEMP_FILE_SUFFIX,
+};
 use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
 use hex;
@@ -42,7 +65,7 @@ use pageserver_api::models::{HistoricLayerInfo, LayerAccessKind};
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::io::{Seek, SeekFrom};
 use std::ops::Range;
 use std::os::unix::prelude::FileExt;
@@ -52,6 +75,7 @@ use tracing::*;
 
 use utils::{
     bin_ser::BeSer,
+    checksummed_writer::{ChecksummedWriter, SyncPolicy},
     id::{TenantId, TimelineId},
     lsn::Lsn,
 };
@@ -80,6 +104,15 @@ pub(super) struct Summary {
     index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     index_root_blk: u32,
+    /// Block where the checksum table begins. It covers all blocks before it.
+    /// 0 in files written before format version 6.
+    checksums_blk: u32,
+    /// Block where the presence map begins, in a sparse image layer. 0 in
+    /// dense image layers.
+    presence_blk: u32,
+    /// Whether the blocks after the summary are compressed, and the file ends
+    /// with a map of them. Always false in files written before format version 4.
+    compressed_blocks: bool,
     // the 'values' part starts after the summary header, on block 1.
 }
 
@@ -111,6 +144,9 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            checksums_blk: 0,
+            presence_blk: 0,
+            compressed_blocks: false,
         }
     }
 }
@@ -154,6 +190,9 @@ pub struct ImageLayerInner {
 
     lsn: Lsn,
 
+    /// The keys this layer holds images of, if it's a sparse image layer.
+    presence_map: Option<PresenceMap>,
+
     /// Reader object for reading blocks from the file.
     file: FileBlockReader<VirtualFile>,
 }
@@ -163,6 +202,7 @@ impl std::fmt::Debug for ImageLayerInner {
         f.debug_struct("ImageLayerInner")
             .field("index_start_blk", &self.index_start_blk)
             .field("index_root_blk", &self.index_root_blk)
+            .field("sparse", &self.presence_map.is_some())
             .finish()
     }
 }
@@ -225,6 +265,33 @@ impl Layer for ImageLayer {
             .with_context(|| format!("read {}", self.path().display()))
     }
 
+    async fn get_values_reconstruct_data(
+        &self,
+        reads: &mut [VectoredValueRead],
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        for read in reads.iter() {
+            assert!(self.desc.key_range.contains(&read.key));
+            assert!(read.lsn_range.start >= self.lsn);
+            assert!(read.lsn_range.end >= self.lsn);
+        }
+
+        let inner = self
+            .load(LayerAccessKind::GetValueReconstructData, ctx)
+            .await?;
+        inner
+            .get_values_reconstruct_data(reads)
+            .await
+            .with_context(|| format!("read {}", self.path().display()))
+    }
+
+    fn may_contain_key(&self, key: Key) -> bool {
+        // The presence map is only available once the file has been loaded.
+        self.inner
+            .get()
+            .map_or(true, |inner| inner.may_contain_key(&key))
+    }
+
     /// Boilerplate to implement the Layer trait, always use layer_desc for persistent layers.
     fn get_key_range(&self) -> Range<Key> {
         self.layer_desc().key_range.clone()
@@ -371,9 +438,9 @@ impl ImageLayer {
                 timeline_id,
                 filename.key_range.clone(),
                 filename.lsn,
-                false,
+                filename.sparse,
                 file_size,
-            ), // Now we assume image layer ALWAYS covers the full range. This may change in the future.
+            ),
             lsn: filename.lsn,
             access_stats,
             inner: OnceCell::new(),
@@ -398,9 +465,9 @@ impl ImageLayer {
                 summary.timeline_id,
                 summary.key_range,
                 summary.lsn,
-                false,
+                summary.presence_blk != 0,
                 metadata.len(),
-            ), // Now we assume image layer ALWAYS covers the full range. This may change in the future.
+            ),
             lsn: summary.lsn,
             access_stats: LayerAccessStats::empty_will_record_residence_event_later(),
             inner: OnceCell::new(),
@@ -434,10 +501,39 @@ impl ImageLayerInner {
         let summary_blk = file.read_blk(0)?;
         let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
 
+        if !(MIN_STORAGE_FORMAT_VERSION..=STORAGE_FORMAT_VERSION)
+            .contains(&actual_summary.format_version)
+        {
+            bail!(
+                "unsupported layer file format version {}",
+                actual_summary.format_version
+            );
+        }
+
+        let file = if actual_summary.format_version >= COMPRESSED_BLOCKS_FORMAT_VERSION
+            && actual_summary.compressed_blocks
+        {
+            let file_len = file.file.metadata()?.len();
+            file.with_compressed_blocks(file_len)
+                .context("load block map")?
+        } else {
+            file
+        };
+        let file = if actual_summary.format_version >= CHECKSUMS_FORMAT_VERSION {
+            file.with_checksums(actual_summary.checksums_blk)
+                .context("load block checksums")?
+        } else {
+            file
+        };
+
         if let Some(mut expected_summary) = summary {
             // production code path
+            expected_summary.format_version = actual_summary.format_version;
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.checksums_blk = actual_summary.checksums_blk;
+            expected_summary.presence_blk = actual_summary.presence_blk;
+            expected_summary.compressed_blocks = actual_summary.compressed_blocks;
 
             if actual_summary != expected_summary {
                 bail!(
@@ -448,19 +544,69 @@ impl ImageLayerInner {
             }
         }
 
+        let presence_map = if actual_summary.format_version >= SPARSE_IMAGES_FORMAT_VERSION
+            && actual_summary.presence_blk != 0
+        {
+            Some(
+                PresenceMap::read(&file, actual_summary.presence_blk)
+                    .context("load presence map")?,
+            )
+        } else {
+            None
+        };
+
         Ok(ImageLayerInner {
             index_start_blk: actual_summary.index_start_blk,
             index_root_blk: actual_summary.index_root_blk,
             lsn,
+            presence_map,
             file,
         })
     }
 
+    /// Checks every block of the layer file at `path` against its checksum,
+    /// and returns the numbers of the blocks that don't match.
+    pub(super) fn find_corrupt_blocks(path: &Path) -> anyhow::Result<Vec<u32>> {
+        let mut file = File::open(path)
+            .with_context(|| format!("Failed to open file '{}'", path.display()))?;
+        let mut summary_buf = [0u8; PAGE_SZ];
+        file.read_exact(&mut summary_buf)?;
+        let summary = Summary::des_prefix(&summary_buf)?;
+        ensure!(
+            summary.magic == IMAGE_FILE_MAGIC,
+            "bad magic in layer file summary"
+        );
+        if !(CHECKSUMS_FORMAT_VERSION..=STORAGE_FORMAT_VERSION).contains(&summary.format_version) {
+            bail!(
+                "layer file format version {} has no block checksums",
+                summary.format_version
+            );
+        }
+        let file_len = file.metadata()?.len();
+        Ok(block_io::find_corrupt_file_blocks(
+            &file,
+            file_len,
+            summary.checksums_blk,
+            summary.compressed_blocks,
+        )?)
+    }
+
+    /// Returns false if the layer definitely doesn't hold an image of the key.
+    pub(super) fn may_contain_key(&self, key: &Key) -> bool {
+        self.presence_map
+            .as_ref()
+            .map_or(true, |presence_map| presence_map.contains(key))
+    }
+
     pub(super) async fn get_value_reconstruct_data(
         &self,
         key: Key,
         reconstruct_state: &mut ValueReconstructState,
     ) -> anyhow::Result<ValueReconstructResult> {
+        if !self.may_contain_key(&key) {
+            // Not covered by this sparse image layer, look in older layers
+            return Ok(ValueReconstructResult::Continue);
+        }
         let file = &self.file;
         let tree_reader = DiskBtreeReader::new(self.index_start_blk, self.index_root_blk, file);
 
@@ -480,6 +626,42 @@ impl ImageLayerInner {
             Ok(ValueReconstructResult::Missing)
         }
     }
+
+    pub(super) async fn get_values_reconstruct_data(
+        &self,
+        reads: &mut [VectoredValueRead],
+    ) -> anyhow::Result<()> {
+        let file = &self.file;
+        let tree_reader = DiskBtreeReader::new(self.index_start_blk, self.index_root_blk, file);
+
+        let mut offsets = Vec::with_capacity(reads.len());
+        let mut keybuf: [u8; KEY_SIZE] = [0u8; KEY_SIZE];
+        for (read_idx, read) in reads.iter_mut().enumerate() {
+            if !self.may_contain_key(&read.key) {
+                read.result = ValueReconstructResult::Continue;
+                continue;
+            }
+            read.key.write_to_byte_slice(&mut keybuf);
+            match tree_reader.get(&keybuf).await? {
+                Some(offset) => offsets.push((offset, read_idx)),
+                None => read.result = ValueReconstructResult::Missing,
+            }
+        }
+
+        // Read the images in file order, so that each block is read at most once.
+        offsets.sort_unstable();
+        let cursor = file.block_cursor();
+        for (offset, read_idx) in offsets {
+            let blob = cursor
+                .read_blob(offset)
+                .await
+                .with_context(|| format!("failed to read value from offset {}", offset))?;
+            let read = &mut reads[read_idx];
+            read.state.img = Some((self.lsn, Bytes::from(blob)));
+            read.result = ValueReconstructResult::Complete;
+        }
+        Ok(())
+    }
 }
 
 /// A builder object for constructing a new image layer.
@@ -502,8 +684,11 @@ struct ImageLayerWriterInner {
     lsn: Lsn,
     is_incremental: bool,
 
-    blob_writer: WriteBlobWriter<VirtualFile>,
+    blob_writer:
+        WriteBlobWriter<BlockChecksumWriter<CompressedBlockWriter<ChecksummedWriter<VirtualFile>>>>,
     tree: DiskBtreeBuilder<BlockBuf, KEY_SIZE>,
+    /// The keys written so far, if this is a sparse image layer.
+    presence: Option<KeySpaceAccum>,
 }
 
 impl ImageLayerWriterInner {
@@ -517,6 +702,7 @@ impl ImageLayerWriterInner {
         key_range: &Range<Key>,
         lsn: Lsn,
         is_incremental: bool,
+        compression_level: Option<i32>,
     ) -> anyhow::Result<Self> {
         // Create the file initially with a temporary filename.
         // We'll atomically rename it to the final name when we're done.
@@ -527,6 +713,7 @@ impl ImageLayerWriterInner {
             &ImageFileName {
                 key_range: key_range.clone(),
                 lsn,
+                sparse: is_incremental,
             },
         );
         info!("new image layer {}", path.display());
@@ -536,7 +723,10 @@ impl ImageLayerWriterInner {
         )?;
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64))?;
-        let blob_writer = WriteBlobWriter::new(file, PAGE_SZ as u64);
+        let buf_writer =
+            CompressedBlockWriter::new(ChecksummedWriter::new(file), 1, compression_level)?;
+        let blob_writer =
+            WriteBlobWriter::new(BlockChecksumWriter::new(buf_writer, 1), PAGE_SZ as u64);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -552,6 +742,7 @@ impl ImageLayerWriterInner {
             tree: tree_builder,
             blob_writer,
             is_incremental,
+            presence: is_incremental.then(KeySpaceAccum::new),
         };
 
         Ok(writer)
@@ -569,6 +760,9 @@ impl ImageLayerWriterInner {
         let mut keybuf: [u8; KEY_SIZE] = [0u8; KEY_SIZE];
         key.write_to_byte_slice(&mut keybuf);
         self.tree.append(&keybuf, off)?;
+        if let Some(presence) = &mut self.presence {
+            presence.add_key(key);
+        }
 
         Ok(())
     }
@@ -580,15 +774,29 @@ impl ImageLayerWriterInner {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
-        let mut file = self.blob_writer.into_inner();
+        let mut writer = self.blob_writer.into_inner();
 
         // Write out the index
-        file.seek(SeekFrom::Start(index_start_blk as u64 * PAGE_SZ as u64))?;
+        writer.pad_to_block()?;
+        assert_eq!(writer.next_blk(), index_start_blk);
         let (index_root_blk, block_buf) = self.tree.finish()?;
         for buf in block_buf.blocks {
-            file.write_all(buf.as_ref())?;
+            writer.write_all(buf.as_ref())?;
         }
 
+        // Write out the presence map of a sparse image layer
+        let presence_blk = match self.presence {
+            Some(presence) => {
+                let presence_blk = writer.next_blk();
+                writer.write_all(&PresenceMap::new(presence.to_keyspace()).to_blocks())?;
+                presence_blk
+            }
+            None => 0,
+        };
+
+        let checksums_blk = writer.next_blk();
+        let (mut writer, block_checksums) = writer.into_inner();
+
         // Fill in the summary on blk 0
         let summary = Summary {
             magic: IMAGE_FILE_MAGIC,
@@ -599,9 +807,23 @@ impl ImageLayerWriterInner {
             lsn: self.lsn,
             index_start_blk,
             index_root_blk,
+            checksums_blk,
+            presence_blk,
+            compressed_blocks: writer.is_compressed(),
         };
+        let mut summary_buf = Summary::ser(&summary)?;
+        summary_buf.resize(PAGE_SZ, 0);
+
+        // Write out the checksums of all the blocks, including the summary
+        let mut checksums = vec![crc32c::crc32c(&summary_buf)];
+        checksums.extend(block_checksums);
+        writer.write_all(&checksums_to_blocks(&checksums))?;
+        let writer = writer.finish()?;
+        let written = writer.written();
+        let (mut file, crc) = writer.into_inner()?;
+
         file.seek(SeekFrom::Start(0))?;
-        Summary::ser_into(&summary, &mut file)?;
+        file.write_all(&summary_buf)?;
 
         let metadata = file
             .metadata()
@@ -612,7 +834,7 @@ impl ImageLayerWriterInner {
             self.timeline_id,
             self.key_range.clone(),
             self.lsn,
-            self.is_incremental, // for now, image layer ALWAYS covers the full range
+            self.is_incremental,
             metadata.len(),
         );
 
@@ -628,7 +850,12 @@ impl ImageLayerWriterInner {
         };
 
         // fsync the file
-        file.sync_all()?;
+        SyncPolicy::All.sync(&file)?;
+        if self.conf.verify_layer_writes {
+            block_io::verify_written_blocks(&self.path, written, crc).with_context(|| {
+                format!("verify the written image layer {}", self.path.display())
+            })?;
+        }
 
         // Rename the file to its final name
         //
@@ -641,6 +868,7 @@ impl ImageLayerWriterInner {
             &ImageFileName {
                 key_range: self.key_range.clone(),
                 lsn: self.lsn,
+                sparse: self.is_incremental,
             },
         );
         std::fs::rename(self.path, final_path)?;
@@ -681,6 +909,10 @@ impl ImageLayerWriter {
     ///
     /// Start building a new image layer.
     ///
+    /// If `is_incremental` is set, the layer is a sparse image layer that only
+    /// covers the keys that are written to it. If `compression_level` is set,
+    /// the blocks of the file are compressed with zstd at that level.
+    ///
     pub fn new(
         conf: &'static PageServerConf,
         timeline_id: TimelineId,
@@ -688,6 +920,7 @@ impl ImageLayerWriter {
         key_range: &Range<Key>,
         lsn: Lsn,
         is_incremental: bool,
+        compression_level: Option<i32>,
     ) -> anyhow::Result<ImageLayerWriter> {
         Ok(Self {
             inner: Some(ImageLayerWriterInner::new(
@@ -697,6 +930,7 @@ impl ImageLayerWriter {
                 key_range,
                 lsn,
                 is_incremental,
+                compression_level,
             )?),
         })
     }
@@ -721,7 +955,13 @@ impl ImageLayerWriter {
 impl Drop for ImageLayerWriter {
     fn drop(&mut self) {
         if let Some(inner) = self.inner.take() {
-            inner.blob_writer.into_inner().remove();
+            inner
+                .blob_writer
+                .into_inner()
+                .into_writer()
+                .into_writer()
+                .discard()
+                .remove();
         }
     }
 }
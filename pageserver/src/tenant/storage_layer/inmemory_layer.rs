@@ -9,7 +9,7 @@ use crate::context::RequestContext;
 use crate::repository::{Key, Value};
 use crate::tenant::blob_io::BlobWriter;
 use crate::tenant::block_io::BlockReader;
-use crate::tenant::ephemeral_file::EphemeralFile;
+use crate::tenant::ephemeral_file::{BlobBatch, EphemeralFile};
 use crate::tenant::storage_layer::{ValueReconstructResult, ValueReconstructState};
 use crate::walrecord;
 use anyhow::{ensure, Result};
@@ -273,12 +273,33 @@ impl InMemoryLayer {
         self.put_value_locked(&mut inner, key, lsn, val).await
     }
 
+    /// Adds a batch of values, e.g. the WAL records of an ingest batch.
+    ///
+    /// The values are serialized before taking the lock, and written to the
+    /// ephemeral file in one go, rather than one by one with [`Self::put_value`].
     pub async fn put_values(&self, values: &HashMap<Key, Vec<(Lsn, Value)>>) -> Result<()> {
-        let mut inner = self.inner.write().await;
-        self.assert_writable();
+        let mut batch = BlobBatch::default();
+        let mut keys = Vec::new();
         for (key, vals) in values {
             for (lsn, val) in vals {
-                self.put_value_locked(&mut inner, *key, *lsn, val).await?;
+                batch.push_with(|buf| val.ser_into(buf))?;
+                keys.push((*key, *lsn));
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        trace!("put_values {} values at {}", batch.len(), self.timeline_id);
+
+        let mut inner = self.inner.write().await;
+        self.assert_writable();
+        let offsets = inner.file.write_blob_batch(&batch)?;
+        for ((key, lsn), off) in keys.into_iter().zip(offsets) {
+            let vec_map = inner.index.entry(key).or_default();
+            let old = vec_map.append_or_update_last(lsn, off).unwrap().0;
+            if old.is_some() {
+                // We already had an entry for this LSN. That's odd..
+                warn!("Key {} at {} already exists", key, lsn);
             }
         }
         Ok(())
@@ -341,8 +362,9 @@ impl InMemoryLayer {
 
     /// Write this frozen in-memory layer to disk.
     ///
-    /// Returns a new delta layer with all the same data as this in-memory layer
-    pub async fn write_to_disk(&self) -> Result<DeltaLayer> {
+    /// Returns a new delta layer with all the same data as this in-memory layer.
+    /// Values are compressed at `compression_level`, if set.
+    pub async fn write_to_disk(&self, compression_level: Option<i32>) -> Result<DeltaLayer> {
         // Grab the lock in read-mode. We hold it over the I/O, but because this
         // layer is not writeable anymore, no one should be trying to acquire the
         // write lock on it, so we shouldn't block anyone. There's one exception
@@ -362,6 +384,7 @@ impl InMemoryLayer {
             self.tenant_id,
             Key::MIN,
             self.start_lsn..end_lsn,
+            compression_level,
         )?;
 
         let mut buf = Vec::new();
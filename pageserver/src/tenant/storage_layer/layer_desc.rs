@@ -24,9 +24,8 @@ pub struct PersistentLayerDesc {
     pub lsn_range: Range<Lsn>,
     /// Whether this is a delta layer.
     pub is_delta: bool,
-    /// Whether this layer only contains page images for part of the keys in the range. In the current implementation, this should
-    /// always be equal to `is_delta`. If we land the partial image layer PR someday, image layer could also be
-    /// incremental.
+    /// Whether this layer only contains page images for part of the keys in the range. This is true for delta
+    /// layers, and for sparse image layers.
     pub is_incremental: bool,
     /// File size
     pub file_size: u64,
@@ -135,6 +134,7 @@ impl PersistentLayerDesc {
         ImageFileName {
             key_range: self.key_range.clone(),
             lsn: self.lsn_range.start,
+            sparse: self.is_incremental,
         }
     }
 
@@ -0,0 +1,167 @@
+//! Merges iterators that are sorted by a key into one sorted iterator, like the
+//! keys and values of the delta layers being compacted.
+//!
+//! The inputs are merged through a binary heap holding the next item of each
+//! input, so with `k` inputs, each item takes `O(log k)` comparisons, and the
+//! merged items are produced one at a time. Sorting the concatenated inputs
+//! instead needs them all in memory, and although the standard library's sort
+//! detects the sorted runs, it merges them pairwise, which gets slow with
+//! hundreds of inputs.
+
+use std::cmp::Ordering;
+use std::collections::binary_heap::PeekMut;
+use std::collections::BinaryHeap;
+
+/// Merges `inputs`, each sorted by `key`, into one iterator sorted by `key`.
+/// Items with equal keys are returned in the order of their inputs, the same as
+/// a stable sort of the concatenated inputs.
+pub fn merge_by_key<I, K, F>(
+    inputs: impl IntoIterator<Item = I>,
+    mut key: F,
+) -> MergeIterator<I, K, F>
+where
+    I: Iterator,
+    K: Ord,
+    F: FnMut(&I::Item) -> K,
+{
+    let mut heap = BinaryHeap::new();
+    let mut all_inputs = Vec::new();
+    for (input_idx, mut input) in inputs.into_iter().enumerate() {
+        if let Some(item) = input.next() {
+            heap.push(HeapEntry {
+                key: key(&item),
+                input_idx,
+                item,
+            });
+        }
+        all_inputs.push(input);
+    }
+    MergeIterator {
+        inputs: all_inputs,
+        heap,
+        key,
+    }
+}
+
+/// See [`merge_by_key`].
+pub struct MergeIterator<I: Iterator, K, F> {
+    inputs: Vec<I>,
+    /// The next item of each input that has one left.
+    heap: BinaryHeap<HeapEntry<I::Item, K>>,
+    key: F,
+}
+
+struct HeapEntry<T, K> {
+    key: K,
+    input_idx: usize,
+    item: T,
+}
+
+impl<I, K, F> Iterator for MergeIterator<I, K, F>
+where
+    I: Iterator,
+    K: Ord,
+    F: FnMut(&I::Item) -> K,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let mut top = self.heap.peek_mut()?;
+        match self.inputs[top.input_idx].next() {
+            Some(item) => {
+                // Replace the top in place, which saves a sift compared to a pop
+                // followed by a push
+                let key = (self.key)(&item);
+                debug_assert!(
+                    key >= top.key,
+                    "merge input {} is not sorted",
+                    top.input_idx
+                );
+                let input_idx = top.input_idx;
+                let entry = std::mem::replace(
+                    &mut *top,
+                    HeapEntry {
+                        key,
+                        input_idx,
+                        item,
+                    },
+                );
+                Some(entry.item)
+            }
+            None => Some(PeekMut::pop(top).item),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inputs.iter().fold(
+            (self.heap.len(), Some(self.heap.len())),
+            |(lower, upper), input| {
+                let (input_lower, input_upper) = input.size_hint();
+                (
+                    lower.saturating_add(input_lower),
+                    upper.zip(input_upper).and_then(|(a, b)| a.checked_add(b)),
+                )
+            },
+        )
+    }
+}
+
+impl<T, K: Ord> PartialEq for HeapEntry<T, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, K: Ord> Eq for HeapEntry<T, K> {}
+
+impl<T, K: Ord> PartialOrd for HeapEntry<T, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, K: Ord> Ord for HeapEntry<T, K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap, the smallest key goes first, and of equal keys,
+        // the one of the first input
+        (&other.key, other.input_idx).cmp(&(&self.key, self.input_idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn merge_matches_stable_sort() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for num_inputs in [0, 1, 2, 7, 100] {
+            let inputs: Vec<Vec<(u32, usize)>> = (0..num_inputs)
+                .map(|input_idx| {
+                    let len = rng.gen_range(0..50);
+                    let mut keys: Vec<u32> = (0..len).map(|_| rng.gen_range(0..100)).collect();
+                    keys.sort_unstable();
+                    keys.into_iter().map(|key| (key, input_idx)).collect()
+                })
+                .collect();
+
+            let mut expected: Vec<(u32, usize)> = inputs.iter().flatten().copied().collect();
+            expected.sort_by_key(|(key, _)| *key);
+
+            let merged = merge_by_key(inputs.into_iter().map(Vec::into_iter), |(key, _)| *key);
+            assert_eq!(merged.size_hint(), (expected.len(), Some(expected.len())));
+            assert_eq!(merged.collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn empty_inputs() {
+        let inputs: Vec<Vec<u32>> = vec![vec![], vec![3], vec![], vec![1, 2]];
+        let merged: Vec<u32> =
+            merge_by_key(inputs.into_iter().map(Vec::into_iter), |key| *key).collect();
+        assert_eq!(merged, vec![1, 2, 3]);
+    }
+}
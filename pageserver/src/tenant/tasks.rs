@@ -1,5 +1,8 @@
 //! This module contains functions to serve per-tenant background processes,
 //! such as compaction and GC
+//!
+//! Each loop decides when an iteration of its tenant is due, and then waits for its
+//! turn in the node-wide [`scheduler`].
 
 use std::ops::ControlFlow;
 use std::sync::Arc;
@@ -10,11 +13,16 @@ use crate::metrics::TENANT_TASK_EVENTS;
 use crate::task_mgr;
 use crate::task_mgr::{TaskKind, BACKGROUND_RUNTIME};
 use crate::tenant::{Tenant, TenantState};
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::completion;
 
-/// Start per tenant background loops: compaction and gc.
+use self::scheduler::TaskClass;
+
+pub mod scheduler;
+
+/// Start per tenant background loops: compaction, gc and logical size reconciliation.
 pub fn start_background_loops(
     tenant: &Arc<Tenant>,
     background_jobs_can_start: Option<&completion::Barrier>,
@@ -66,6 +74,29 @@ pub fn start_background_loops(
             }
         },
     );
+    task_mgr::spawn(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::LogicalSizeReconciliation,
+        Some(tenant_id),
+        None,
+        &format!("logical size reconciliation for tenant {tenant_id}"),
+        false,
+        {
+            let tenant = Arc::clone(tenant);
+            let background_jobs_can_start = background_jobs_can_start.cloned();
+            async move {
+                let cancel = task_mgr::shutdown_token();
+                tokio::select! {
+                    _ = cancel.cancelled() => { return Ok(()) },
+                    _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                };
+                logical_size_reconcile_loop(tenant, cancel)
+                    .instrument(info_span!("logical_size_reconcile_loop", tenant_id = %tenant_id))
+                    .await;
+                Ok(())
+            }
+        },
+    );
 }
 
 ///
@@ -76,6 +107,7 @@ async fn compaction_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
     TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
     async {
         let ctx = RequestContext::todo_child(TaskKind::Compaction, DownloadBehavior::Download);
+        let mut conf_updates = tenant.subscribe_for_conf_updates();
         let mut first = true;
         loop {
             tokio::select! {
@@ -88,39 +120,69 @@ async fn compaction_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
                 },
             }
 
-            let period = tenant.get_compaction_period();
-
             // TODO: we shouldn't need to await to find tenant and this could be moved outside of
             // loop, #3501. There are also additional "allowed_errors" in tests.
             if first {
                 first = false;
-                if random_init_delay(period, &cancel).await.is_err() {
+                if random_init_delay_following_conf_updates(
+                    &tenant,
+                    Tenant::get_compaction_period,
+                    &mut conf_updates,
+                    &cancel,
+                )
+                .await
+                .is_err()
+                {
                     break;
                 }
             }
 
-            let started_at = Instant::now();
+            // Read after the initial delay, which may have seen a config change
+            let period = tenant.get_compaction_period();
 
-            let sleep_duration = if period == Duration::ZERO {
+            let failed = if period == Duration::ZERO {
                 info!("automatic compaction is disabled");
-                // check again in 10 seconds, in case it's been enabled again.
-                Duration::from_secs(10)
+                false
             } else {
+                // The tenants with the most L0 layers go first
+                let priority = tenant.level0_debt().await;
+                let Ok(_permit) = scheduler::get()
+                    .acquire_cancellable(TaskClass::Compaction, tenant.tenant_id, priority, &cancel)
+                    .await
+                else {
+                    break;
+                };
+
+                let started_at = Instant::now();
+
                 // Run compaction
-                if let Err(e) = tenant.compaction_iteration(&cancel, &ctx).await {
+                let res = tenant.compaction_iteration(&cancel, &ctx).await;
+
+                warn_when_period_overrun(started_at.elapsed(), period, "compaction");
+
+                if let Err(e) = res {
                     error!("Compaction failed, retrying in {:?}: {e:?}", wait_duration);
-                    wait_duration
+                    true
                 } else {
-                    period
+                    false
                 }
             };
 
-            warn_when_period_overrun(started_at.elapsed(), period, "compaction");
-
             // Sleep
-            if tokio::time::timeout(sleep_duration, cancel.cancelled())
+            let sleep_duration = |tenant: &Tenant| {
+                let period = tenant.get_compaction_period();
+                if period == Duration::ZERO {
+                    // check again in 10 seconds, in case it's been enabled again.
+                    Duration::from_secs(10)
+                } else if failed {
+                    wait_duration
+                } else {
+                    period
+                }
+            };
+            if sleep_following_conf_updates(&tenant, sleep_duration, &mut conf_updates, &cancel)
                 .await
-                .is_ok()
+                .is_err()
             {
                 break;
             }
@@ -141,6 +203,7 @@ async fn gc_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
         // cutoff specified as time.
         let ctx =
             RequestContext::todo_child(TaskKind::GarbageCollector, DownloadBehavior::Download);
+        let mut conf_updates = tenant.subscribe_for_conf_updates();
         let mut first = true;
         loop {
             tokio::select! {
@@ -153,41 +216,158 @@ async fn gc_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
                 },
             }
 
-            let period = tenant.get_gc_period();
-
             if first {
                 first = false;
-                if random_init_delay(period, &cancel).await.is_err() {
+                if random_init_delay_following_conf_updates(
+                    &tenant,
+                    Tenant::get_gc_period,
+                    &mut conf_updates,
+                    &cancel,
+                )
+                .await
+                .is_err()
+                {
                     break;
                 }
             }
 
-            let started_at = Instant::now();
+            // Read after the initial delay, which may have seen a config change
+            let period = tenant.get_gc_period();
 
             let gc_horizon = tenant.get_gc_horizon();
-            let sleep_duration = if period == Duration::ZERO || gc_horizon == 0 {
+            let failed = if period == Duration::ZERO || gc_horizon == 0 {
                 info!("automatic GC is disabled");
-                // check again in 10 seconds, in case it's been enabled again.
-                Duration::from_secs(10)
+                false
             } else {
+                let Ok(_permit) = scheduler::get()
+                    .acquire_cancellable(TaskClass::Gc, tenant.tenant_id, 0, &cancel)
+                    .await
+                else {
+                    break;
+                };
+
+                let started_at = Instant::now();
+
                 // Run gc
                 let res = tenant
                     .gc_iteration(None, gc_horizon, tenant.get_pitr_interval(), &ctx)
                     .await;
+
+                warn_when_period_overrun(started_at.elapsed(), period, "gc");
+
                 if let Err(e) = res {
                     error!("Gc failed, retrying in {:?}: {e:?}", wait_duration);
+                    true
+                } else {
+                    false
+                }
+            };
+
+            // Sleep
+            let sleep_duration = |tenant: &Tenant| {
+                let period = tenant.get_gc_period();
+                if period == Duration::ZERO || tenant.get_gc_horizon() == 0 {
+                    // check again in 10 seconds, in case it's been enabled again.
+                    Duration::from_secs(10)
+                } else if failed {
                     wait_duration
                 } else {
                     period
                 }
             };
+            if sleep_following_conf_updates(&tenant, sleep_duration, &mut conf_updates, &cancel)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+    .await;
+    TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
+}
 
-            warn_when_period_overrun(started_at.elapsed(), period, "gc");
+///
+/// Logical size reconciliation task's main loop
+///
+async fn logical_size_reconcile_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
+    TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
+    async {
+        // The calculation may need to download layers
+        let ctx = RequestContext::todo_child(
+            TaskKind::LogicalSizeReconciliation,
+            DownloadBehavior::Download,
+        );
+        let mut conf_updates = tenant.subscribe_for_conf_updates();
+        let mut first = true;
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => {
+                    return;
+                },
+                tenant_wait_result = wait_for_active_tenant(&tenant) => match tenant_wait_result {
+                    ControlFlow::Break(()) => return,
+                    ControlFlow::Continue(()) => (),
+                },
+            }
 
-            // Sleep
-            if tokio::time::timeout(sleep_duration, cancel.cancelled())
+            // The initial logical size calculation has just been done on startup,
+            // no need to repeat it right away
+            if first {
+                first = false;
+                if sleep_following_conf_updates(
+                    &tenant,
+                    Tenant::get_logical_size_reconcile_period,
+                    &mut conf_updates,
+                    &cancel,
+                )
                 .await
-                .is_ok()
+                .is_err()
+                {
+                    break;
+                }
+            }
+
+            let period = tenant.get_logical_size_reconcile_period();
+
+            if period != Duration::ZERO {
+                let Ok(_permit) = scheduler::get()
+                    .acquire_cancellable(
+                        TaskClass::LogicalSizeReconciliation,
+                        tenant.tenant_id,
+                        0,
+                        &cancel,
+                    )
+                    .await
+                else {
+                    break;
+                };
+
+                let started_at = Instant::now();
+
+                if let Err(e) = tenant.reconcile_logical_sizes(&ctx).await {
+                    error!("Logical size reconciliation failed: {e:?}");
+                }
+
+                warn_when_period_overrun(
+                    started_at.elapsed(),
+                    period,
+                    "logical size reconciliation",
+                );
+            }
+
+            let sleep_duration = |tenant: &Tenant| {
+                let period = tenant.get_logical_size_reconcile_period();
+                if period == Duration::ZERO {
+                    // check again in 10 seconds, in case it's been enabled again.
+                    Duration::from_secs(10)
+                } else {
+                    period
+                }
+            };
+            if sleep_following_conf_updates(&tenant, sleep_duration, &mut conf_updates, &cancel)
+                .await
+                .is_err()
             {
                 break;
             }
@@ -230,6 +410,49 @@ async fn wait_for_active_tenant(tenant: &Arc<Tenant>) -> ControlFlow<()> {
 #[error("cancelled")]
 pub(crate) struct Cancelled;
 
+/// Sleeps for `sleep_duration(tenant)`. The duration is recomputed whenever the
+/// tenant config changes, so that e.g. a shorter `gc_period` cuts the ongoing
+/// sleep short instead of applying only after it.
+async fn sleep_following_conf_updates(
+    tenant: &Tenant,
+    sleep_duration: impl Fn(&Tenant) -> Duration,
+    conf_updates: &mut watch::Receiver<()>,
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    let started_at = Instant::now();
+    loop {
+        let remaining = sleep_duration(tenant).saturating_sub(started_at.elapsed());
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(Cancelled),
+            _ = tokio::time::sleep(remaining) => return Ok(()),
+            // The sender lives in the tenant we hold, so changed() doesn't fail
+            Ok(()) = conf_updates.changed() => {
+                debug!("tenant config changed, recomputing the sleep");
+            }
+        }
+    }
+}
+
+/// Like [`random_init_delay`], but the delay is a random fraction of the period,
+/// which follows the tenant config changes like [`sleep_following_conf_updates`].
+async fn random_init_delay_following_conf_updates(
+    tenant: &Tenant,
+    period: impl Fn(&Tenant) -> Duration,
+    conf_updates: &mut watch::Receiver<()>,
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    use rand::Rng;
+
+    let fraction = rand::thread_rng().gen_range(0.0..=1.0);
+    sleep_following_conf_updates(
+        tenant,
+        |tenant| period(tenant).mul_f64(fraction),
+        conf_updates,
+        cancel,
+    )
+    .await
+}
+
 /// Provide a random delay for background task initialization.
 ///
 /// This delay prevents a thundering herd of background tasks and will likely keep them running on
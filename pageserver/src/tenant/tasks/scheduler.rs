@@ -0,0 +1,516 @@
+//! Schedules the per-tenant background tasks across the whole pageserver, see
+//! `background_task_concurrency`.
+//!
+//! Every tenant has its own compaction, GC and logical size reconciliation loops,
+//! which decide when an iteration is due, but the iteration only starts once the
+//! scheduler lets it. Each class of task has a budget of iterations running at once,
+//! so when the periods of thousands of tenants line up, e.g. after a restart, the
+//! iterations queue up instead of all hitting the disks and the remote storage at
+//! the same time. The waiting compactions start in the order of the L0 debt of their
+//! tenants, the L0 delta layers waiting to be compacted, so that the tenants whose
+//! reads suffer the most go first. The other classes start in arrival order, and so
+//! do the compactions which have waited for [`MAX_PRIORITIZED_WAIT`], ahead of the
+//! others, so that a steady stream of tenants with more debt doesn't starve the rest.
+//! The queues are listed by `GET /v1/background_tasks`.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use enum_map::EnumMap;
+use once_cell::sync::OnceCell;
+use pageserver_api::models::{
+    BackgroundTaskClassInfo, RunningBackgroundTask, WaitingBackgroundTask,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use utils::id::TenantId;
+
+use super::Cancelled;
+use crate::config::defaults::{
+    DEFAULT_COMPACTION_CONCURRENCY, DEFAULT_GC_CONCURRENCY,
+    DEFAULT_LOGICAL_SIZE_RECONCILIATION_CONCURRENCY,
+};
+use crate::metrics::BACKGROUND_TASKS_WAITING;
+use crate::task_mgr;
+
+static SCHEDULER: OnceCell<Arc<Scheduler>> = OnceCell::new();
+
+/// How long a task waits at most before it's started in arrival order, whatever its
+/// priority.
+pub const MAX_PRIORITIZED_WAIT: Duration = Duration::from_secs(10 * 60);
+
+/// Initializes the background task scheduler. Must be called once at pageserver startup.
+pub fn init(concurrency: BackgroundTaskConcurrency) {
+    if SCHEDULER
+        .set(Arc::new(Scheduler::new(concurrency)))
+        .is_err()
+    {
+        panic!("background task scheduler already initialized");
+    }
+}
+
+/// Gets the background task scheduler.
+pub fn get() -> &'static Arc<Scheduler> {
+    if cfg!(test) {
+        SCHEDULER.get_or_init(|| Arc::new(Scheduler::new(BackgroundTaskConcurrency::default())))
+    } else {
+        SCHEDULER
+            .get()
+            .expect("background task scheduler not initialized")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_map::Enum, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum TaskClass {
+    Compaction,
+    Gc,
+    LogicalSizeReconciliation,
+}
+
+/// How many iterations of each class of background task run at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundTaskConcurrency {
+    pub compaction: NonZeroUsize,
+    pub gc: NonZeroUsize,
+    pub logical_size_reconciliation: NonZeroUsize,
+}
+
+impl Default for BackgroundTaskConcurrency {
+    fn default() -> Self {
+        let limit = |l| NonZeroUsize::new(l).expect("Invalid default constant");
+        BackgroundTaskConcurrency {
+            compaction: default_compaction_concurrency(),
+            gc: limit(DEFAULT_GC_CONCURRENCY),
+            logical_size_reconciliation: limit(DEFAULT_LOGICAL_SIZE_RECONCILIATION_CONCURRENCY),
+        }
+    }
+}
+
+/// [`DEFAULT_COMPACTION_CONCURRENCY`], but at most 3/4 of the workers of the background
+/// runtime. While a lot of the work of a compaction is done on `spawn_blocking`, the
+/// repartitioning is done in the async context, and this leaves some workers for the
+/// shorter tasks.
+fn default_compaction_concurrency() -> NonZeroUsize {
+    let total_threads = *task_mgr::BACKGROUND_RUNTIME_WORKER_THREADS;
+    let limit =
+        NonZeroUsize::new(DEFAULT_COMPACTION_CONCURRENCY).expect("Invalid default constant");
+    limit.min(NonZeroUsize::new(total_threads * 3 / 4).unwrap_or(NonZeroUsize::MIN))
+}
+
+impl BackgroundTaskConcurrency {
+    fn limit(&self, class: TaskClass) -> NonZeroUsize {
+        match class {
+            TaskClass::Compaction => self.compaction,
+            TaskClass::Gc => self.gc,
+            TaskClass::LogicalSizeReconciliation => self.logical_size_reconciliation,
+        }
+    }
+}
+
+pub struct Scheduler {
+    classes: EnumMap<TaskClass, Mutex<ClassState>>,
+    max_prioritized_wait: Duration,
+}
+
+struct ClassState {
+    limit: usize,
+    /// By the sequence number the task got when it asked for its turn.
+    running: HashMap<u64, Running>,
+    /// By the sequence number too, so in arrival order.
+    waiting: BTreeMap<u64, Waiter>,
+    /// Identifies the tasks, and orders the waiters with the same priority by arrival.
+    next_seq: u64,
+}
+
+struct Running {
+    tenant_id: TenantId,
+    started_at: Instant,
+}
+
+struct Waiter {
+    priority: u64,
+    tenant_id: TenantId,
+    queued_at: Instant,
+    admit: oneshot::Sender<Permit>,
+}
+
+/// Held while a background task runs. Dropping it starts the next waiting task of the
+/// same class.
+pub struct Permit {
+    scheduler: Option<Arc<Scheduler>>,
+    class: TaskClass,
+    seq: u64,
+}
+
+/// Takes a task out of the queue if it stops waiting before its turn.
+struct Queued<'a> {
+    scheduler: &'a Scheduler,
+    class: TaskClass,
+    seq: u64,
+}
+
+impl Scheduler {
+    pub fn new(concurrency: BackgroundTaskConcurrency) -> Self {
+        Scheduler {
+            classes: EnumMap::from_fn(|class| {
+                Mutex::new(ClassState {
+                    limit: concurrency.limit(class).get(),
+                    running: HashMap::new(),
+                    waiting: BTreeMap::new(),
+                    next_seq: 0,
+                })
+            }),
+            max_prioritized_wait: MAX_PRIORITIZED_WAIT,
+        }
+    }
+
+    /// Waits for the turn of a task of `class` of `tenant_id`. Of the waiting tasks,
+    /// the ones with the highest `priority` start first, unless some have waited for
+    /// [`MAX_PRIORITIZED_WAIT`].
+    ///
+    /// Cancel safe: a task dropped while waiting gives up its turn.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        class: TaskClass,
+        tenant_id: TenantId,
+        priority: u64,
+    ) -> Permit {
+        let (admitted, _queued) = {
+            let mut state = self.classes[class].lock().unwrap();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            if state.running.len() < state.limit && state.waiting.is_empty() {
+                let running = Running {
+                    tenant_id,
+                    started_at: Instant::now(),
+                };
+                state.running.insert(seq, running);
+                return Permit {
+                    scheduler: Some(Arc::clone(self)),
+                    class,
+                    seq,
+                };
+            }
+            let (admit, admitted) = oneshot::channel();
+            state.waiting.insert(
+                seq,
+                Waiter {
+                    priority,
+                    tenant_id,
+                    queued_at: Instant::now(),
+                    admit,
+                },
+            );
+            BACKGROUND_TASKS_WAITING
+                .with_label_values(&[class.into()])
+                .inc();
+            let queued = Queued {
+                scheduler: self,
+                class,
+                seq,
+            };
+            (admitted, queued)
+        };
+        // The sender is only dropped after sending, the scheduler is never dropped
+        // with waiters in it
+        admitted
+            .await
+            .expect("background task scheduler dropped a waiter")
+    }
+
+    /// Like [`Scheduler::acquire`], but gives up when `cancel` is cancelled.
+    pub(crate) async fn acquire_cancellable(
+        self: &Arc<Self>,
+        class: TaskClass,
+        tenant_id: TenantId,
+        priority: u64,
+        cancel: &CancellationToken,
+    ) -> Result<Permit, Cancelled> {
+        tokio::select! {
+            _ = cancel.cancelled() => Err(Cancelled),
+            permit = self.acquire(class, tenant_id, priority) => Ok(permit),
+        }
+    }
+
+    /// Changes the number of tasks of each class that run at once. A decrease takes
+    /// effect as the running tasks finish.
+    pub fn set_concurrency(self: &Arc<Self>, concurrency: BackgroundTaskConcurrency) {
+        for (class, state) in &self.classes {
+            let mut state = state.lock().unwrap();
+            state.limit = concurrency.limit(class).get();
+            while state.running.len() < state.limit && self.admit_next(class, &mut state) {}
+        }
+    }
+
+    /// The running and the waiting tasks of each class, the waiting ones in the order
+    /// they will start.
+    pub fn info(&self) -> Vec<BackgroundTaskClassInfo> {
+        let now = Instant::now();
+        let millis_since = |at: Instant| now.duration_since(at).as_millis() as u64;
+        self.classes
+            .iter()
+            .map(|(class, state)| {
+                let state = state.lock().unwrap();
+                let mut running: Vec<_> = state.running.iter().collect();
+                running.sort_by_key(|(seq, _)| **seq);
+                let mut waiting: Vec<_> = state
+                    .waiting
+                    .iter()
+                    .filter(|(_, waiter)| !waiter.admit.is_closed())
+                    .collect();
+                waiting.sort_by_key(|(seq, waiter)| Reverse(self.start_order(**seq, waiter, now)));
+                BackgroundTaskClassInfo {
+                    class: <&str>::from(class).to_string(),
+                    limit: state.limit,
+                    running: running
+                        .into_iter()
+                        .map(|(_, running)| RunningBackgroundTask {
+                            tenant_id: running.tenant_id,
+                            running_for_ms: millis_since(running.started_at),
+                        })
+                        .collect(),
+                    waiting: waiting
+                        .into_iter()
+                        .map(|(_, waiter)| WaitingBackgroundTask {
+                            tenant_id: waiter.tenant_id,
+                            priority: waiter.priority,
+                            waiting_for_ms: millis_since(waiter.queued_at),
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn release(self: &Arc<Self>, class: TaskClass, seq: u64) {
+        let mut state = self.classes[class].lock().unwrap();
+        state.running.remove(&seq);
+        if state.running.len() < state.limit {
+            self.admit_next(class, &mut state);
+        }
+    }
+
+    /// The waiters which have waited too long go first in arrival order, then the
+    /// others by priority and arrival. The greatest goes first.
+    fn start_order(&self, seq: u64, waiter: &Waiter, now: Instant) -> (bool, u64, Reverse<u64>) {
+        let overdue = now.duration_since(waiter.queued_at) >= self.max_prioritized_wait;
+        let priority = if overdue { 0 } else { waiter.priority };
+        (overdue, priority, Reverse(seq))
+    }
+
+    /// Hands a permit to the waiter that goes first, skipping the ones that stopped
+    /// waiting. Returns false if nobody is waiting.
+    fn admit_next(self: &Arc<Self>, class: TaskClass, state: &mut ClassState) -> bool {
+        let now = Instant::now();
+        while let Some(seq) = state
+            .waiting
+            .iter()
+            .max_by_key(|(seq, waiter)| self.start_order(**seq, waiter, now))
+            .map(|(seq, _)| *seq)
+        {
+            let waiter = state.waiting.remove(&seq).expect("just found");
+            BACKGROUND_TASKS_WAITING
+                .with_label_values(&[class.into()])
+                .dec();
+            let running = Running {
+                tenant_id: waiter.tenant_id,
+                started_at: Instant::now(),
+            };
+            state.running.insert(seq, running);
+            let permit = Permit {
+                scheduler: Some(Arc::clone(self)),
+                class,
+                seq,
+            };
+            match waiter.admit.send(permit) {
+                Ok(()) => return true,
+                Err(mut permit) => {
+                    // not admitted, so there's nothing to release
+                    permit.scheduler = None;
+                    state.running.remove(&seq);
+                }
+            }
+        }
+        false
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release(self.class, self.seq);
+        }
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.classes[self.class].lock().unwrap();
+        if state.waiting.remove(&self.seq).is_some() {
+            BACKGROUND_TASKS_WAITING
+                .with_label_values(&[self.class.into()])
+                .dec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn concurrency(compaction: usize) -> BackgroundTaskConcurrency {
+        BackgroundTaskConcurrency {
+            compaction: NonZeroUsize::new(compaction).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn class_info(scheduler: &Scheduler, class: TaskClass) -> BackgroundTaskClassInfo {
+        let name: &str = class.into();
+        scheduler
+            .info()
+            .into_iter()
+            .find(|info| info.class == name)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn limit_per_class() {
+        let scheduler = Arc::new(Scheduler::new(concurrency(1)));
+        let tenant = TenantId::generate();
+        let first = scheduler.acquire(TaskClass::Compaction, tenant, 0).await;
+        // the other classes have their own budgets
+        let _gc = scheduler.acquire(TaskClass::Gc, tenant, 0).await;
+
+        let mut second = Box::pin(scheduler.acquire(TaskClass::Compaction, tenant, 0));
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut second)
+            .await
+            .is_err());
+        let info = class_info(&scheduler, TaskClass::Compaction);
+        assert_eq!(info.running.len(), 1);
+        assert_eq!(info.waiting.len(), 1);
+
+        drop(first);
+        let _second = second.await;
+
+        scheduler.set_concurrency(concurrency(2));
+        let _third = scheduler.acquire(TaskClass::Compaction, tenant, 0).await;
+        assert_eq!(
+            class_info(&scheduler, TaskClass::Compaction).running.len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter() {
+        let scheduler = Arc::new(Scheduler::new(concurrency(1)));
+        let tenant = TenantId::generate();
+        let first = scheduler.acquire(TaskClass::Compaction, tenant, 0).await;
+        let cancel = CancellationToken::new();
+        let waiting = scheduler.acquire_cancellable(TaskClass::Compaction, tenant, 0, &cancel);
+        cancel.cancel();
+        assert!(waiting.await.is_err());
+        // taken out of the queue right away, not when its turn comes
+        assert!(scheduler.classes[TaskClass::Compaction]
+            .lock()
+            .unwrap()
+            .waiting
+            .is_empty());
+
+        drop(first);
+        // the cancelled waiter didn't take the permit with it
+        let _second = scheduler.acquire(TaskClass::Compaction, tenant, 0).await;
+    }
+
+    /// The waiting tasks with the most L0 debt go first, the others in arrival order.
+    #[tokio::test]
+    async fn priority_order() {
+        let scheduler = Arc::new(Scheduler::new(concurrency(1)));
+        let held = scheduler
+            .acquire(TaskClass::Compaction, TenantId::generate(), 0)
+            .await;
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for (name, priority) in [("a", 1), ("b", 10), ("c", 1), ("d", 5)] {
+            let scheduler = Arc::clone(&scheduler);
+            let order_tx = order_tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler
+                    .acquire(TaskClass::Compaction, TenantId::generate(), priority)
+                    .await;
+                order_tx.send(name).unwrap();
+            }));
+            // let the task queue up before the next one
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let priorities: Vec<_> = class_info(&scheduler, TaskClass::Compaction)
+            .waiting
+            .iter()
+            .map(|waiter| waiter.priority)
+            .collect();
+        assert_eq!(priorities, [10, 5, 1, 1]);
+
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        drop(order_tx);
+        let mut order = Vec::new();
+        while let Some(name) = order_rx.recv().await {
+            order.push(name);
+        }
+        assert_eq!(order, ["b", "d", "a", "c"]);
+    }
+
+    /// The tasks which have waited too long go first, in arrival order.
+    #[tokio::test]
+    async fn bounded_wait() {
+        let mut scheduler = Scheduler::new(concurrency(1));
+        scheduler.max_prioritized_wait = Duration::from_millis(50);
+        let scheduler = Arc::new(scheduler);
+        let held = scheduler
+            .acquire(TaskClass::Compaction, TenantId::generate(), 0)
+            .await;
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for (name, priority, delay) in [("a", 1, 0), ("b", 2, 100), ("c", 10, 0)] {
+            let scheduler = Arc::clone(&scheduler);
+            let order_tx = order_tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = scheduler
+                    .acquire(TaskClass::Compaction, TenantId::generate(), priority)
+                    .await;
+                order_tx.send(name).unwrap();
+            }));
+            tokio::time::sleep(Duration::from_millis(10 + delay)).await;
+        }
+        // "a" and "b" are overdue, "c" is not yet
+        let priorities: Vec<_> = class_info(&scheduler, TaskClass::Compaction)
+            .waiting
+            .iter()
+            .map(|waiter| waiter.priority)
+            .collect();
+        assert_eq!(priorities, [1, 2, 10]);
+
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        drop(order_tx);
+        let mut order = Vec::new();
+        while let Some(name) = order_rx.recv().await {
+            order.push(name);
+        }
+        assert_eq!(order, ["a", "b", "c"]);
+    }
+}
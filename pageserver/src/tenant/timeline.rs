@@ -1,7 +1,14 @@
+pub(crate) mod basebackup_cache;
+pub mod clean_shutdown;
+pub(crate) mod computes;
 pub mod delete;
+pub mod diff;
 mod eviction_task;
 pub mod layer_manager;
 mod logical_size;
+pub mod prewarm;
+pub mod skipped_relation_data;
+pub mod snapshot;
 pub mod span;
 pub mod uninit;
 mod walreceiver;
@@ -13,8 +20,8 @@ use futures::StreamExt;
 use itertools::Itertools;
 use pageserver_api::models::{
     DownloadRemoteLayersTaskInfo, DownloadRemoteLayersTaskSpawnRequest,
-    DownloadRemoteLayersTaskState, LayerMapInfo, LayerResidenceEventReason, LayerResidenceStatus,
-    TimelineState,
+    DownloadRemoteLayersTaskState, Labels, LayerMapInfo, LayerRepairInfo, LayerRepairOutcome,
+    LayerResidenceEventReason, LayerResidenceStatus, TimelineState,
 };
 use remote_storage::GenericRemoteStorage;
 use serde_with::serde_as;
@@ -28,6 +35,7 @@ use utils::id::TenantTimelineId;
 use std::cmp::{max, min, Ordering};
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs;
+use std::num::NonZeroU32;
 use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::pin::pin;
@@ -39,29 +47,37 @@ use crate::context::{
     AccessStatsBehavior, DownloadBehavior, RequestContext, RequestContextBuilder,
 };
 use crate::tenant::remote_timeline_client::{self, index::LayerFileMetadata};
+use crate::tenant::storage_layer::merge_iterator::merge_by_key;
 use crate::tenant::storage_layer::{
     DeltaFileName, DeltaLayerWriter, ImageFileName, ImageLayerWriter, InMemoryLayer,
     LayerAccessStats, LayerFileName, RemoteLayer,
 };
 use crate::tenant::timeline::logical_size::CurrentLogicalSize;
 use crate::tenant::{
+    block_io::is_checksum_error,
     ephemeral_file::is_ephemeral_file,
+    labels,
     layer_map::{LayerMap, SearchResult},
-    metadata::{save_metadata, TimelineMetadata},
+    metadata::{MetadataLog, TimelineMetadata},
     par_fsync,
-    storage_layer::{PersistentLayer, ValueReconstructResult, ValueReconstructState},
+    storage_layer::{
+        find_corrupt_blocks, rename_to_backup, PersistentLayer, PersistentLayerKey,
+        ValueReconstructResult, ValueReconstructState, VectoredValueRead,
+    },
 };
 
 use crate::config::PageServerConf;
 use crate::keyspace::{KeyPartitioning, KeySpace, KeySpaceRandomAccum};
 use crate::metrics::{
-    TimelineMetrics, MATERIALIZED_PAGE_CACHE_HIT, MATERIALIZED_PAGE_CACHE_HIT_DIRECT,
-    RECONSTRUCT_TIME, UNEXPECTED_ONDEMAND_DOWNLOADS,
+    TimelineMetrics, LOGICAL_SIZE_RECONCILE_CORRECTIONS, MATERIALIZED_PAGE_CACHE_HIT,
+    MATERIALIZED_PAGE_CACHE_HIT_DIRECT, RECONSTRUCT_BATCH_TIME, RECONSTRUCT_TIME,
+    UNEXPECTED_ONDEMAND_DOWNLOADS,
 };
 use crate::pgdatadir_mapping::LsnForTimestamp;
-use crate::pgdatadir_mapping::{is_rel_fsm_block_key, is_rel_vm_block_key};
+use crate::pgdatadir_mapping::{is_rel_fsm_block_key, is_rel_vm_block_key, slru_key_range};
 use crate::pgdatadir_mapping::{BlockNumber, CalculateLogicalSizeError};
-use crate::tenant::config::{EvictionPolicy, TenantConfOpt};
+use crate::tenant::config::{EvictionPolicy, TenantConfOpt, TimelineConfOpt};
+use crate::top_tenants::UsageCounters;
 use pageserver_api::reltag::RelTag;
 
 use postgres_connection::PgConnectionConfig;
@@ -70,19 +86,24 @@ use utils::{
     completion,
     id::{RegionId, TenantId, TimelineId},
     lsn::{AtomicLsn, Lsn, RecordLsn},
-    seqwait::SeqWait,
+    seqwait::{SeqWait, SeqWaitError},
     simple_rcu::{Rcu, RcuReadGuard},
 };
 
 use crate::page_cache;
 use crate::repository::GcResult;
-use crate::repository::{Key, Value};
+use crate::repository::{key_range_size, singleton_range, Key, Value};
+use crate::shared_page_cache;
 use crate::task_mgr::TaskKind;
-use crate::walredo::WalRedoManager;
-use crate::METADATA_FILE_NAME;
+use crate::walredo::{WalRedoError, WalRedoManager, WalRedoRequest};
 use crate::ZERO_PAGE;
 use crate::{is_temporary, task_mgr};
+use crate::{
+    CLEAN_SHUTDOWN_MARKER_FILE_NAME, LABELS_FILE_NAME, METADATA_FILE_NAME, METADATA_LOG_FILE_NAME,
+    RELATION_DATA_SKIPPED_FILE_NAME, TIMELINE_CONFIG_FILE_NAME,
+};
 
+use self::basebackup_cache::BasebackupCache;
 use self::delete::DeleteTimelineFlow;
 pub(super) use self::eviction_task::EvictionTaskTenantState;
 use self::eviction_task::EvictionTaskTimelineState;
@@ -232,6 +253,18 @@ pub struct Timeline {
     /// Used to avoid multiple `flush_loop` tasks running
     pub(super) flush_loop_state: Mutex<FlushLoopState>,
 
+    /// Persists the metadata updates made by the flush loop.
+    metadata_log: Mutex<MetadataLog>,
+
+    /// See [`labels`]. Persisted in the timeline directory.
+    pub(super) labels: RwLock<Labels>,
+
+    /// Overrides of `tenant_conf` for this timeline. Persisted in the timeline directory.
+    pub(super) timeline_conf: RwLock<TimelineConfOpt>,
+
+    /// See [`crate::top_tenants`].
+    pub(crate) usage_counters: UsageCounters,
+
     /// layer_flush_start_tx can be used to wake up the layer-flushing task.
     /// The value is a counter, incremented every time a new flush cycle is requested.
     /// The flush cycle counter is sent back on the layer_flush_done channel when
@@ -255,6 +288,11 @@ pub struct Timeline {
     // garbage collecting data that is still needed by the child timelines.
     pub gc_info: std::sync::RwLock<GcInfo>,
 
+    /// The oldest LSN the read-only replicas of the timeline are at, as reported
+    /// by the safekeepers, or [`Lsn::INVALID`]. GC keeps the page versions from it
+    /// on, which the replicas still read.
+    pub(crate) standby_horizon: AtomicLsn,
+
     // It may change across major versions so for simplicity
     // keep it after running initdb for a timeline.
     // It is needed in checks when we want to error on some operations
@@ -278,9 +316,28 @@ pub struct Timeline {
     pub last_received_wal: Mutex<Option<WalReceiverInfo>>,
     pub walreceiver: Mutex<Option<WalReceiver>>,
 
+    /// Held while WAL is being ingested into the timeline, by the WAL receiver
+    /// or by a safekeeper pushing WAL with the `push_wal` page service command.
+    pub(crate) wal_ingest_lock: tokio::sync::Mutex<()>,
+
     /// Relation size cache
     pub rel_size_cache: RwLock<HashMap<RelTag, (Lsn, BlockNumber)>>,
 
+    /// Total size of the SLRU segments at an LSN, see [`Timeline::get_slru_size`].
+    pub(crate) slru_size_cache: Mutex<Option<(Lsn, u64)>>,
+    /// LSN of the last creation, extension or drop of an SLRU segment, until
+    /// which the cached SLRU size holds.
+    pub(crate) slru_changed_at: AtomicLsn,
+
+    /// What to prewarm, and whether it is being prewarmed, see [`prewarm`].
+    prewarm_state: prewarm::PrewarmState,
+
+    /// The computes reading the timeline through the page service.
+    attached_computes: Arc<computes::AttachedComputes>,
+
+    /// The last basebackup sent for the timeline, see [`basebackup_cache`].
+    pub(crate) basebackup_cache: BasebackupCache,
+
     download_all_remote_layers_task_info: RwLock<Option<DownloadRemoteLayersTaskInfo>>,
 
     state: watch::Sender<TimelineState>,
@@ -304,6 +361,10 @@ pub struct Timeline {
 
     /// Region id
     pub region_id: RegionId,
+
+    /// The LSN the relation data of the timeline was first skipped at, see
+    /// [`skipped_relation_data`].
+    relation_data_skipped_since: Mutex<Option<Lsn>>,
 }
 
 pub struct WalReceiverInfo {
@@ -356,6 +417,9 @@ pub enum PageReconstructError {
     /// The ancestor of this is being stopped
     AncestorStopping(TimelineId),
 
+    /// There is no value for the key at the LSN, in this timeline or its ancestors.
+    MissingKey(anyhow::Error),
+
     /// An error happened replaying WAL records
     #[error(transparent)]
     WalRedo(#[from] crate::walredo::WalRedoError),
@@ -377,6 +441,7 @@ impl std::fmt::Debug for PageReconstructError {
             Self::AncestorStopping(timeline_id) => {
                 write!(f, "ancestor timeline {timeline_id} is being stopped")
             }
+            Self::MissingKey(err) => err.fmt(f),
             Self::WalRedo(err) => err.fmt(f),
         }
     }
@@ -398,17 +463,44 @@ impl std::fmt::Display for PageReconstructError {
             Self::AncestorStopping(timeline_id) => {
                 write!(f, "ancestor timeline {timeline_id} is being stopped")
             }
+            Self::MissingKey(err) => err.fmt(f),
             Self::WalRedo(err) => err.fmt(f),
         }
     }
 }
 
+/// An error of [`Timeline::wait_lsn`].
+#[derive(Debug, thiserror::Error)]
+pub enum WaitLsnError {
+    /// The timeline isn't active, or the caller must not wait for WAL.
+    #[error(transparent)]
+    BadState(anyhow::Error),
+
+    /// The WAL didn't arrive in time, e.g. because the walreceiver is stuck.
+    #[error("{0}")]
+    Timeout(String),
+
+    /// The waiting task, or the timeline, is shutting down.
+    #[error("shutting down while waiting for WAL record at LSN {0} to arrive")]
+    Shutdown(Lsn),
+}
+
+impl From<WaitLsnError> for PageReconstructError {
+    fn from(e: WaitLsnError) -> Self {
+        match e {
+            WaitLsnError::Shutdown(_) => PageReconstructError::Cancelled,
+            e => PageReconstructError::Other(e.into()),
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum LogicalSizeCalculationCause {
     Initial,
     ConsumptionMetricsSyntheticSize,
     EvictionTaskImitation,
     TenantSizeHandler,
+    Reconciliation,
 }
 
 /// Public interface functions
@@ -425,6 +517,19 @@ impl Timeline {
             .map(|ancestor| ancestor.timeline_id)
     }
 
+    /// Whether the page versions of the relations are ingested from the WAL of the
+    /// timeline, see [`PageServerConf::ingest_regions`].
+    /// A timeline whose relation data was skipped once stays without it, even if
+    /// its region is ingested again.
+    pub fn ingests_relation_data(&self) -> bool {
+        self.region_ingested() && !self.relation_data_skipped()
+    }
+
+    /// Whether the region of the timeline is in [`PageServerConf::ingest_regions`].
+    pub(crate) fn region_ingested(&self) -> bool {
+        self.conf.ingest_regions.is_empty() || self.conf.ingest_regions.contains(&self.region_id)
+    }
+
     /// Lock and get timeline's GC cuttof
     pub fn get_latest_gc_cutoff_lsn(&self) -> RcuReadGuard<Lsn> {
         self.latest_gc_cutoff_lsn.read()
@@ -495,6 +600,95 @@ impl Timeline {
             .observe_closure_duration(|| self.reconstruct_value(key, lsn, reconstruct_state))
     }
 
+    /// Look up the page versions of many keys at the same LSN.
+    ///
+    /// This is the vectored version of [`Self::get`]. The keys are resolved against
+    /// the layer map together, and all keys that need data from the same layer are
+    /// read from it with one [`Layer::get_values_reconstruct_data`] call, which lets
+    /// the on-disk layers coalesce their reads.
+    ///
+    /// Keys whose traversal needs an on-demand download, or runs into anything
+    /// unexpected, are retried with [`Self::get`], which also produces the detailed
+    /// error messages.
+    ///
+    /// The results are in the same order as `keys`; a failure to reconstruct one
+    /// key doesn't fail the others.
+    pub async fn get_vectored(
+        &self,
+        keys: &[Key],
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<Vec<Result<Bytes, PageReconstructError>>, PageReconstructError> {
+        if !lsn.is_valid() {
+            return Err(PageReconstructError::Other(anyhow::anyhow!("Invalid LSN")));
+        }
+
+        trace!(
+            "vectored get page request for {} keys @{} from task kind {:?}",
+            keys.len(),
+            lsn,
+            ctx.task_kind()
+        );
+
+        let mut results: Vec<Option<Result<Bytes, PageReconstructError>>> =
+            keys.iter().map(|_| None).collect();
+
+        let mut traversals = Vec::with_capacity(keys.len());
+        for (idx, key) in keys.iter().enumerate() {
+            let cached_page_img = self.lookup_cached_page(key, lsn);
+            let cached_lsn = match &cached_page_img {
+                Some((cached_lsn, cached_img)) if *cached_lsn == lsn => {
+                    MATERIALIZED_PAGE_CACHE_HIT_DIRECT.inc();
+                    results[idx] = Some(Ok(cached_img.clone()));
+                    continue;
+                }
+                Some((cached_lsn, _)) => *cached_lsn,
+                None => Lsn(0),
+            };
+            traversals.push((
+                VectoredTraversal {
+                    idx,
+                    cached_lsn,
+                    cont_lsn: Lsn(lsn.0 + 1),
+                    prev_lsn: Lsn(u64::MAX),
+                    read_count: 0,
+                },
+                VectoredValueRead {
+                    key: *key,
+                    lsn_range: Lsn(0)..Lsn(0),
+                    state: ValueReconstructState {
+                        records: Vec::new(),
+                        img: cached_page_img,
+                    },
+                    result: ValueReconstructResult::Continue,
+                },
+            ));
+        }
+
+        let timer = crate::metrics::GET_RECONSTRUCT_DATA_TIME.start_timer();
+        let (complete, fallback) = self.get_vectored_reconstruct_data(traversals, ctx).await;
+        timer.stop_and_record();
+
+        let values = complete
+            .into_iter()
+            .map(|(idx, read)| (idx, read.key, read.state))
+            .collect();
+        // Observed per batch, RECONSTRUCT_TIME stays per page
+        let reconstructed = RECONSTRUCT_BATCH_TIME
+            .observe_closure_duration(|| self.reconstruct_values(lsn, values));
+        for (idx, result) in reconstructed {
+            results[idx] = Some(result);
+        }
+        for (idx, key) in fallback {
+            results[idx] = Some(self.get(key, lsn, ctx).await);
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every key is either complete or retried"))
+            .collect())
+    }
+
     /// Get last or prev record separately. Same as get_last_record_rlsn().last/prev.
     pub fn get_last_record_lsn(&self) -> Lsn {
         self.last_record_lsn.load().last
@@ -513,6 +707,46 @@ impl Timeline {
         self.disk_consistent_lsn.load()
     }
 
+    pub fn labels(&self) -> Labels {
+        self.labels.read().unwrap().clone()
+    }
+
+    /// Replaces the labels of the timeline, and persists them.
+    pub fn set_labels(&self, new_labels: Labels) -> anyhow::Result<()> {
+        labels::validate(&new_labels)?;
+        let path = self
+            .conf
+            .timeline_labels_path(&self.tenant_id, &self.timeline_id);
+        let mut guard = self.labels.write().unwrap();
+        labels::persist(&path, &new_labels)?;
+        *guard = new_labels;
+        Ok(())
+    }
+
+    pub fn timeline_specific_overrides(&self) -> TimelineConfOpt {
+        *self.timeline_conf.read().unwrap()
+    }
+
+    /// The config the timeline runs with, with the values of the tenant config where
+    /// the timeline has no overrides.
+    pub fn effective_timeline_config(&self) -> TimelineConfOpt {
+        TimelineConfOpt {
+            checkpoint_distance: Some(self.get_checkpoint_distance()),
+            checkpoint_timeout: Some(self.get_checkpoint_timeout()),
+        }
+    }
+
+    /// Replaces the overrides of the tenant config for the timeline, and persists them.
+    pub fn set_timeline_conf(&self, new_conf: TimelineConfOpt) -> anyhow::Result<()> {
+        let path = self
+            .conf
+            .timeline_config_path(&self.tenant_id, &self.timeline_id);
+        let mut guard = self.timeline_conf.write().unwrap();
+        new_conf.persist(&path)?;
+        *guard = new_conf;
+        Ok(())
+    }
+
     pub fn get_remote_consistent_lsn(&self) -> Option<Lsn> {
         if let Some(remote_client) = &self.remote_client {
             remote_client.last_uploaded_consistent_lsn()
@@ -534,6 +768,16 @@ impl Timeline {
         size
     }
 
+    /// Number of L0 delta layers, which compaction is yet to turn into L1 layers.
+    pub async fn level0_deltas_count(&self) -> usize {
+        let guard = self.layers.read().await;
+        guard
+            .layer_map()
+            .get_level0_deltas()
+            .map(|deltas| deltas.len())
+            .unwrap_or(0)
+    }
+
     pub fn resident_physical_size(&self) -> u64 {
         self.metrics.resident_physical_size_gauge.get()
     }
@@ -544,45 +788,66 @@ impl Timeline {
     /// You should call this before any of the other get_* or list_* functions. Calling
     /// those functions with an LSN that has been processed yet is an error.
     ///
-    pub async fn wait_lsn(
+    /// Waits up to the `wait_lsn_timeout` of the tenant, and stops waiting when the
+    /// current task is requested to shut down.
+    ///
+    pub async fn wait_lsn(&self, lsn: Lsn, ctx: &RequestContext) -> Result<(), WaitLsnError> {
+        let cancel = task_mgr::shutdown_token_or_default();
+        self.wait_lsn_cancellable(lsn, self.get_wait_lsn_timeout(), &cancel, ctx)
+            .await
+    }
+
+    /// Like [`Timeline::wait_lsn`], with the given timeout and cancellation token.
+    pub async fn wait_lsn_cancellable(
         &self,
         lsn: Lsn,
+        timeout: Duration,
+        cancel: &CancellationToken,
         _ctx: &RequestContext, /* Prepare for use by cancellation */
-    ) -> anyhow::Result<()> {
-        anyhow::ensure!(self.is_active(), "Cannot wait for Lsn on inactive timeline");
+    ) -> Result<(), WaitLsnError> {
+        let bad_state = |msg: &str| Err(WaitLsnError::BadState(anyhow::anyhow!("{msg}")));
+        // the branches of a suspended timeline can still read through it
+        if !(self.is_active() || self.is_suspended()) {
+            return bad_state("Cannot wait for Lsn on inactive timeline");
+        }
 
         // This should never be called from the WAL receiver, because that could lead
         // to a deadlock.
-        anyhow::ensure!(
-            task_mgr::current_task_kind() != Some(TaskKind::WalReceiverManager),
-            "wait_lsn cannot be called in WAL receiver"
-        );
-        anyhow::ensure!(
-            task_mgr::current_task_kind() != Some(TaskKind::WalReceiverConnectionHandler),
-            "wait_lsn cannot be called in WAL receiver"
-        );
-        anyhow::ensure!(
-            task_mgr::current_task_kind() != Some(TaskKind::WalReceiverConnectionPoller),
-            "wait_lsn cannot be called in WAL receiver"
-        );
+        if matches!(
+            task_mgr::current_task_kind(),
+            Some(
+                TaskKind::WalReceiverManager
+                    | TaskKind::WalReceiverConnectionHandler
+                    | TaskKind::WalReceiverConnectionPoller
+            )
+        ) {
+            return bad_state("wait_lsn cannot be called in WAL receiver");
+        }
 
         let _timer = crate::metrics::WAIT_LSN_TIME.start_timer();
+        let _timeline_timer = self.metrics.wait_lsn_time.start_timer();
 
         match self
             .last_record_lsn
-            .wait_for_timeout(
+            .wait_for_timeout_cancellable(
                 RecordLsn {
                     last: lsn,
                     prev: Lsn::INVALID, // We only use the last value so it does not matter what we put here
                 },
-                self.conf.wait_lsn_timeout,
+                timeout,
+                cancel,
             )
             .await
         {
             Ok(()) => Ok(()),
-            Err(e) => {
+            Err(SeqWaitError::Shutdown | SeqWaitError::Cancelled) => {
+                Err(WaitLsnError::Shutdown(lsn))
+            }
+            Err(SeqWaitError::Timeout) => {
                 // don't count the time spent waiting for lock below, and also in walreceiver.status(), towards the wait_lsn_time_histo
                 drop(_timer);
+                drop(_timeline_timer);
+                self.metrics.wait_lsn_timeouts.inc();
                 let walreceiver_status = {
                     match &*self.walreceiver.lock().unwrap() {
                         None => "stopping or stopped".to_string(),
@@ -592,15 +857,13 @@ impl Timeline {
                         },
                     }
                 };
-                Err(anyhow::Error::new(e).context({
-                    format!(
-                        "Timed out while waiting for WAL record at LSN {} to arrive, last_record_lsn {} disk consistent LSN={}, WalReceiver status: {}",
-                        lsn,
-                        self.get_last_record_lsn(),
-                        self.get_disk_consistent_lsn(),
-                        walreceiver_status,
-                    )
-                }))
+                Err(WaitLsnError::Timeout(format!(
+                    "Timed out while waiting for WAL record at LSN {} to arrive, last_record_lsn {} disk consistent LSN={}, WalReceiver status: {}",
+                    lsn,
+                    self.get_last_record_lsn(),
+                    self.get_disk_consistent_lsn(),
+                    walreceiver_status,
+                )))
             }
         }
     }
@@ -628,6 +891,11 @@ impl Timeline {
     }
 
     /// Outermost timeline compaction operation; downloads needed layers.
+    ///
+    /// The callers wait for their turn in the background task [`scheduler`] first,
+    /// which limits the compactions running at once.
+    ///
+    /// [`scheduler`]: crate::tenant::tasks::scheduler
     pub async fn compact(
         self: &Arc<Self>,
         cancel: &CancellationToken,
@@ -635,39 +903,6 @@ impl Timeline {
     ) -> anyhow::Result<()> {
         const ROUNDS: usize = 2;
 
-        static CONCURRENT_COMPACTIONS: once_cell::sync::Lazy<tokio::sync::Semaphore> =
-            once_cell::sync::Lazy::new(|| {
-                let total_threads = *task_mgr::BACKGROUND_RUNTIME_WORKER_THREADS;
-                let permits = usize::max(
-                    1,
-                    // while a lot of the work is done on spawn_blocking, we still do
-                    // repartitioning in the async context. this should give leave us some workers
-                    // unblocked to be blocked on other work, hopefully easing any outside visible
-                    // effects of restarts.
-                    //
-                    // 6/8 is a guess; previously we ran with unlimited 8 and more from
-                    // spawn_blocking.
-                    (total_threads * 3).checked_div(4).unwrap_or(0),
-                );
-                assert_ne!(permits, 0, "we will not be adding in permits later");
-                assert!(
-                    permits < total_threads,
-                    "need threads avail for shorter work"
-                );
-                tokio::sync::Semaphore::new(permits)
-            });
-
-        // this wait probably never needs any "long time spent" logging, because we already nag if
-        // compaction task goes over it's period (20s) which is quite often in production.
-        let _permit = tokio::select! {
-            permit = CONCURRENT_COMPACTIONS.acquire() => {
-                permit
-            },
-            _ = cancel.cancelled() => {
-                return Ok(());
-            }
-        };
-
         let last_record_lsn = self.get_last_record_lsn();
 
         // Last record Lsn could be zero in case the timeline was just created
@@ -878,6 +1113,53 @@ impl Timeline {
         Ok((size, is_exact))
     }
 
+    /// The LSN at which the logical size was last calculated from the layers, rather
+    /// than maintained from the ingested WAL. None if it wasn't since the timeline
+    /// was loaded.
+    pub fn get_logical_size_calculated_at(&self) -> Option<Lsn> {
+        let lsn = self.current_logical_size.calculated_at.load();
+        lsn.is_valid().then_some(lsn)
+    }
+
+    /// Checks the logical size maintained from the ingested WAL against a calculation
+    /// from the layers at the last record LSN, and corrects it if they differ. Does
+    /// nothing until the initial size calculation has finished.
+    pub(crate) async fn reconcile_logical_size(
+        self: &Arc<Self>,
+        ctx: &RequestContext,
+    ) -> Result<(), CalculateLogicalSizeError> {
+        // The last record LSN and the size are updated together under the writer
+        // lock, see `DatadirModification::commit`, so this is the size at `lsn`.
+        let (lsn, incremental_size) = {
+            let _writer = self.writer().await;
+            match self.current_logical_size.current_size()? {
+                CurrentLogicalSize::Exact(size) => (self.get_last_record_lsn(), size),
+                CurrentLogicalSize::Approximate(_) => return Ok(()),
+            }
+        };
+
+        let calculated_size = self
+            .logical_size_calculation_task(
+                lsn,
+                LogicalSizeCalculationCause::Reconciliation,
+                ctx,
+                task_mgr::shutdown_token(),
+            )
+            .await?;
+
+        // The WAL ingested since `lsn` changed the size by the same amount either way
+        let drift = calculated_size as i64 - incremental_size as i64;
+        if drift != 0 {
+            warn!(
+                "logical size maintained from WAL was {incremental_size} at {lsn}, but calculated as {calculated_size}, correcting it by {drift}"
+            );
+            LOGICAL_SIZE_RECONCILE_CORRECTIONS.inc();
+            self.update_current_logical_size(drift);
+        }
+        self.current_logical_size.calculated_at.store(lsn);
+        Ok(())
+    }
+
     /// Check if more than 'checkpoint_distance' of WAL has been accumulated in
     /// the in-memory layer, and initiate flushing it if so.
     ///
@@ -943,8 +1225,11 @@ impl Timeline {
             (TimelineState::Broken { .. }, new_state) => {
                 error!("Ignoring state update {new_state:?} for broken timeline");
             }
-            (TimelineState::Stopping, TimelineState::Active) => {
-                error!("Not activating a Stopping timeline");
+            (
+                TimelineState::Stopping,
+                new_state @ (TimelineState::Active | TimelineState::Suspended),
+            ) => {
+                error!("Not moving a Stopping timeline into {new_state:?} state");
             }
             (_, new_state) => {
                 if matches!(
@@ -988,6 +1273,40 @@ impl Timeline {
         self.current_state() == TimelineState::Stopping
     }
 
+    pub fn is_suspended(&self) -> bool {
+        self.current_state() == TimelineState::Suspended
+    }
+
+    /// Moves an Active timeline into [`TimelineState::Suspended`], which stops its
+    /// walreceiver. Fails with the current state if the timeline is in another state
+    /// than these two.
+    pub fn suspend(&self) -> Result<(), TimelineState> {
+        self.transition(TimelineState::Active, TimelineState::Suspended)
+    }
+
+    /// Moves a Suspended timeline back into [`TimelineState::Active`]. Fails with the
+    /// current state if the timeline is in another state than these two.
+    pub fn resume(&self) -> Result<(), TimelineState> {
+        self.transition(TimelineState::Suspended, TimelineState::Active)
+    }
+
+    fn transition(&self, from: TimelineState, to: TimelineState) -> Result<(), TimelineState> {
+        let mut result = Ok(());
+        self.state.send_if_modified(|state| {
+            if *state == from {
+                info!("moving timeline from {from:?} into {to:?} state");
+                *state = to.clone();
+                true
+            } else {
+                if *state != to {
+                    result = Err(state.clone());
+                }
+                false
+            }
+        });
+        result
+    }
+
     pub fn subscribe_for_state_updates(&self) -> watch::Receiver<TimelineState> {
         self.state.subscribe()
     }
@@ -1000,7 +1319,7 @@ impl Timeline {
         loop {
             let current_state = receiver.borrow().clone();
             match current_state {
-                TimelineState::Loading => {
+                TimelineState::Loading | TimelineState::Suspended => {
                     receiver
                         .changed()
                         .await
@@ -1040,6 +1359,11 @@ impl Timeline {
         }
     }
 
+    /// See [`LayerMap::check_invariants`].
+    pub async fn check_layer_map(&self) -> anyhow::Result<()> {
+        self.layers.read().await.layer_map().check_invariants()
+    }
+
     #[instrument(skip_all, fields(tenant_id = %self.tenant_id, timeline_id = %self.timeline_id))]
     pub async fn download_layer(&self, layer_file_name: &str) -> anyhow::Result<Option<bool>> {
         let Some(layer) = self.find_layer(layer_file_name).await else {
@@ -1080,6 +1404,236 @@ impl Timeline {
         }
     }
 
+    /// Handles a local layer file that failed checksum verification with `err`.
+    ///
+    /// The layer is evicted, so that the next access downloads it again from
+    /// remote storage, rather than serving corrupt pages from it. Returns `err`
+    /// if that's not possible.
+    async fn evict_corrupt_layer(
+        &self,
+        layer: Arc<dyn PersistentLayer>,
+        err: anyhow::Error,
+    ) -> Result<(), PageReconstructError> {
+        self.metrics.layer_corruptions.inc();
+        let layer_name = layer.filename().file_name();
+        error!("layer {layer_name} is corrupt, evicting it to re-download: {err:#}");
+
+        let Some(remote_client) = self.remote_client.as_ref() else {
+            return Err(PageReconstructError::from(err.context(
+                "remote storage not configured, cannot re-download corrupt layer",
+            )));
+        };
+        let results = match self
+            .evict_layer_batch(remote_client, &[layer], CancellationToken::new())
+            .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                return Err(PageReconstructError::from(err.context(format!(
+                    "failed to evict corrupt layer {layer_name}: {e:#}"
+                ))))
+            }
+        };
+        match results.into_iter().next() {
+            Some(Some(Ok(()))) => Ok(()),
+            Some(Some(Err(e))) => Err(PageReconstructError::from(
+                err.context(format!("failed to evict corrupt layer {layer_name}: {e}")),
+            )),
+            _ => Err(PageReconstructError::from(err)),
+        }
+    }
+
+    /// Checks a layer file, and repairs it if it's missing or corrupt.
+    ///
+    /// A missing layer is downloaded from remote storage. A corrupt layer is replaced by its
+    /// remote copy if remote storage is configured. Otherwise, image layers are rebuilt by
+    /// reconstructing their pages from the other layers of the timeline. Delta layers can't
+    /// be rebuilt that way, because the WAL they contain is not stored anywhere else.
+    ///
+    /// Returns `Ok(None)` if the layer could not be found by its `layer_file_name`.
+    #[instrument(skip_all, fields(tenant_id = %self.tenant_id, timeline_id = %self.timeline_id, layer = %layer_file_name))]
+    pub async fn repair_layer(
+        &self,
+        layer_file_name: &str,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<LayerRepairInfo>> {
+        let Some(layer) = self.find_layer(layer_file_name).await else {
+            return Ok(None);
+        };
+
+        if let Some(remote_layer) = layer.downcast_remote_layer() {
+            anyhow::ensure!(
+                self.remote_client.is_some(),
+                "remote storage not configured; cannot download layer"
+            );
+            self.download_remote_layer(remote_layer).await?;
+            return Ok(Some(LayerRepairInfo {
+                outcome: LayerRepairOutcome::Downloaded,
+                corrupt_blocks: Vec::new(),
+            }));
+        }
+
+        let corrupt_blocks = find_corrupt_blocks_of_layer(&layer).await?;
+        if corrupt_blocks.is_empty() {
+            return Ok(Some(LayerRepairInfo {
+                outcome: LayerRepairOutcome::Healthy,
+                corrupt_blocks,
+            }));
+        }
+        self.metrics.layer_corruptions.inc();
+        warn!(
+            "layer file has {} corrupt blocks: {:?}",
+            corrupt_blocks.len(),
+            corrupt_blocks
+        );
+
+        let outcome = if let Some(remote_client) = self.remote_client.as_ref() {
+            let results = self
+                .evict_layer_batch(remote_client, &[layer], CancellationToken::new())
+                .await?;
+            match results.into_iter().next().unwrap() {
+                None => anyhow::bail!("task_mgr shutdown requested"),
+                Some(Ok(())) => {}
+                Some(Err(e)) => return Err(anyhow::Error::new(e).context("evict corrupt layer")),
+            }
+
+            let remote_layer = self
+                .find_layer(layer_file_name)
+                .await
+                .and_then(|layer| layer.downcast_remote_layer())
+                .context("layer was removed while repairing it")?;
+            self.download_remote_layer(remote_layer).await?;
+
+            let layer = self
+                .find_layer(layer_file_name)
+                .await
+                .context("layer was removed while repairing it")?;
+            let still_corrupt = find_corrupt_blocks_of_layer(&layer).await?;
+            anyhow::ensure!(
+                still_corrupt.is_empty(),
+                "remote copy of the layer is corrupt as well, corrupt blocks: {still_corrupt:?}"
+            );
+            LayerRepairOutcome::Redownloaded
+        } else if !layer.layer_desc().is_delta() {
+            self.rematerialize_image_layer(layer, ctx).await?;
+            LayerRepairOutcome::Rematerialized
+        } else {
+            anyhow::bail!(
+                "delta layer file is corrupt, and remote storage is not configured to repair it from"
+            );
+        };
+
+        info!("repaired corrupt layer: {outcome:?}");
+        Ok(Some(LayerRepairInfo {
+            outcome,
+            corrupt_blocks,
+        }))
+    }
+
+    /// Rebuilds a corrupt image layer by reconstructing all its pages from the layers below it.
+    ///
+    /// The corrupt file is kept in the timeline directory, renamed with an `.old` suffix.
+    async fn rematerialize_image_layer(
+        &self,
+        layer: Arc<dyn PersistentLayer>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let desc = layer.layer_desc().clone();
+        let key_range = desc.get_key_range();
+        let lsn = desc.image_layer_lsn();
+
+        // Keep compaction and GC from removing the layers we reconstruct the pages from.
+        let _layer_removal_guard = self.layer_removal_cs.lock().await;
+
+        // Take the corrupt layer out of the layer map, so that page reconstruction at its
+        // LSN doesn't read from it.
+        self.layers
+            .write()
+            .await
+            .detach_corrupt_layer(Arc::clone(&layer));
+
+        let new_layer = match self.write_image_layer(&key_range, lsn, ctx).await {
+            Ok(new_layer) => new_layer,
+            Err(e) => {
+                self.layers.write().await.attach_repaired_layer(layer);
+                return Err(e);
+            }
+        };
+
+        let mut guard = self.layers.write().await;
+        let new_size = new_layer.layer_desc().file_size;
+        self.metrics
+            .resident_physical_size_gauge
+            .sub(desc.file_size);
+        self.metrics.resident_physical_size_gauge.add(new_size);
+        let new_layer: Arc<dyn PersistentLayer> = Arc::new(new_layer);
+        new_layer.access_stats().record_residence_event(
+            &guard,
+            LayerResidenceStatus::Resident,
+            LayerResidenceEventReason::LayerCreate,
+        );
+        guard.attach_repaired_layer(new_layer);
+        drop_wlock(guard);
+
+        Ok(())
+    }
+
+    /// Writes an image layer with the pages in `key_range` at `lsn`, reconstructed from the
+    /// current layer map. The existing layer file with the same name, if any, is renamed
+    /// with an `.old` suffix.
+    async fn write_image_layer(
+        &self,
+        key_range: &Range<Key>,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<ImageLayer> {
+        let keyspace = self.collect_keyspace(lsn, ctx).await?;
+        let mut writer = ImageLayerWriter::new(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            key_range,
+            lsn,
+            false,
+            self.get_layer_compression_level(),
+        )?;
+        for range in &keyspace.ranges {
+            let mut key = max(range.start, key_range.start);
+            let end = min(range.end, key_range.end);
+            while key < end {
+                let img = self
+                    .get(key, lsn, ctx)
+                    .await
+                    .with_context(|| format!("reconstruct key {key} at {lsn}"))?;
+                writer.put_image(key, &img)?;
+                key = key.next();
+            }
+        }
+
+        let path = self
+            .conf
+            .timeline_path(&self.tenant_id, &self.timeline_id)
+            .join(
+                ImageFileName {
+                    key_range: key_range.clone(),
+                    lsn,
+                    sparse: false,
+                }
+                .to_string(),
+            );
+        if path.exists() {
+            rename_to_backup(&path)?;
+        }
+        let new_layer = writer.finish()?;
+        par_fsync::par_fsync_async(&[
+            new_layer.path(),
+            self.conf.timeline_path(&self.tenant_id, &self.timeline_id),
+        ])
+        .await
+        .context("fsync of rebuilt layer file")?;
+        Ok(new_layer)
+    }
+
     /// Evict a batch of layers.
     ///
     /// GenericRemoteStorage reference is required as a (witness)[witness_article] for "remote storage is configured."
@@ -1293,16 +1847,20 @@ const REPARTITION_FREQ_IN_CHECKPOINT_DISTANCE: u64 = 10;
 // Private functions
 impl Timeline {
     fn get_checkpoint_distance(&self) -> u64 {
+        let timeline_conf = self.timeline_specific_overrides();
         let tenant_conf = self.tenant_conf.read().unwrap();
-        tenant_conf
+        timeline_conf
             .checkpoint_distance
+            .or(tenant_conf.checkpoint_distance)
             .unwrap_or(self.conf.default_tenant_conf.checkpoint_distance)
     }
 
     fn get_checkpoint_timeout(&self) -> Duration {
+        let timeline_conf = self.timeline_specific_overrides();
         let tenant_conf = self.tenant_conf.read().unwrap();
-        tenant_conf
+        timeline_conf
             .checkpoint_timeout
+            .or(tenant_conf.checkpoint_timeout)
             .unwrap_or(self.conf.default_tenant_conf.checkpoint_timeout)
     }
 
@@ -1327,6 +1885,13 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.image_creation_threshold)
     }
 
+    fn get_slru_image_creation_threshold(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .slru_image_creation_threshold
+            .unwrap_or(self.conf.default_tenant_conf.slru_image_creation_threshold)
+    }
+
     fn get_eviction_policy(&self) -> EvictionPolicy {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -1350,6 +1915,28 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.gc_feedback)
     }
 
+    fn get_layer_compression_level(&self) -> Option<i32> {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .layer_compression_level
+            .or(self.conf.default_tenant_conf.layer_compression_level)
+    }
+
+    pub fn get_get_page_weight(&self) -> NonZeroU32 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .get_page_weight
+            .unwrap_or(self.conf.default_tenant_conf.get_page_weight)
+    }
+
+    fn get_wait_lsn_timeout(&self) -> Duration {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .wait_lsn_timeout
+            .or(self.conf.default_tenant_conf.wait_lsn_timeout)
+            .unwrap_or(self.conf.wait_lsn_timeout)
+    }
+
     pub(super) fn tenant_conf_updated(&self) {
         // NB: Most tenant conf options are read by background loops, so,
         // changes will automatically be picked up.
@@ -1403,6 +1990,21 @@ impl Timeline {
             );
         drop(tenant_conf_guard);
 
+        let relation_data_skipped_since = skipped_relation_data::load_relation_data_skipped_marker(
+            conf,
+            &tenant_id,
+            &timeline_id,
+        );
+        if let Some(since) = relation_data_skipped_since {
+            if conf.ingest_regions.is_empty() || conf.ingest_regions.contains(&metadata.region_id())
+            {
+                warn!(
+                    "region {} is ingested, but the relation data of timeline {tenant_id}/{timeline_id} was skipped from {since}, its pages stay unreadable",
+                    metadata.region_id()
+                );
+            }
+        }
+
         Arc::new_cyclic(|myself| {
             let mut result = Timeline {
                 conf,
@@ -1416,6 +2018,7 @@ impl Timeline {
 
                 walredo_mgr,
                 walreceiver: Mutex::new(None),
+                wal_ingest_lock: tokio::sync::Mutex::new(()),
 
                 remote_client: remote_client.map(Arc::new),
 
@@ -1445,6 +2048,10 @@ impl Timeline {
                 ),
 
                 flush_loop_state: Mutex::new(FlushLoopState::NotStarted),
+                metadata_log: Mutex::new(MetadataLog::new(conf, tenant_id, timeline_id)),
+                labels: RwLock::new(Labels::new()),
+                timeline_conf: RwLock::new(TimelineConfOpt::default()),
+                usage_counters: UsageCounters::default(),
 
                 layer_flush_start_tx,
                 layer_flush_done_tx,
@@ -1457,6 +2064,7 @@ impl Timeline {
                     horizon_cutoff: Lsn(0),
                     pitr_cutoff: Lsn(0),
                 }),
+                standby_horizon: AtomicLsn::new(0),
 
                 latest_gc_cutoff_lsn: Rcu::new(metadata.latest_gc_cutoff_lsn()),
                 initdb_lsn: metadata.initdb_lsn(),
@@ -1475,6 +2083,11 @@ impl Timeline {
 
                 last_received_wal: Mutex::new(None),
                 rel_size_cache: RwLock::new(HashMap::new()),
+                slru_size_cache: Mutex::new(None),
+                slru_changed_at: AtomicLsn::new(0),
+                prewarm_state: Default::default(),
+                attached_computes: Default::default(),
+                basebackup_cache: BasebackupCache::new(conf.basebackup_cache_ttl),
 
                 download_all_remote_layers_task_info: RwLock::new(None),
 
@@ -1489,6 +2102,7 @@ impl Timeline {
                 initial_logical_size_attempt: Mutex::new(initial_logical_size_attempt),
 
                 region_id: metadata.region_id(),
+                relation_data_skipped_since: Mutex::new(relation_data_skipped_since),
             };
             result.repartition_threshold =
                 result.get_checkpoint_distance() / REPARTITION_FREQ_IN_CHECKPOINT_DISTANCE;
@@ -1589,6 +2203,7 @@ impl Timeline {
                 auth_token: crate::config::SAFEKEEPER_AUTH_TOKEN.get().cloned(),
                 availability_zone: self.conf.availability_zone.clone(),
                 ingest_batch_size: self.conf.ingest_batch_size,
+                compression: self.conf.walreceiver_compression,
             },
             broker_client,
             ctx,
@@ -1686,7 +2301,14 @@ impl Timeline {
 
                 total_physical_size += file_size;
                 loaded_layers.push(Arc::new(layer));
-            } else if fname == METADATA_FILE_NAME || fname.ends_with(".old") {
+            } else if fname == METADATA_FILE_NAME
+                || fname == METADATA_LOG_FILE_NAME
+                || fname == LABELS_FILE_NAME
+                || fname == TIMELINE_CONFIG_FILE_NAME
+                || fname == CLEAN_SHUTDOWN_MARKER_FILE_NAME
+                || fname == RELATION_DATA_SKIPPED_FILE_NAME
+                || fname.ends_with(".old")
+            {
                 // ignore these
             } else if remote_timeline_client::is_temp_download_file(&direntry_path) {
                 info!(
@@ -2070,7 +2692,7 @@ impl Timeline {
                     .initial_logical_size
                     .set(calculated_size)
                 {
-                    Ok(()) => (),
+                    Ok(()) => self_clone.current_logical_size.calculated_at.store(lsn),
                     Err(_what_we_just_attempted_to_set) => {
                         let existing_size = self_clone
                             .current_logical_size
@@ -2156,7 +2778,8 @@ impl Timeline {
                             TimelineState::Active => continue,
                             TimelineState::Broken { .. }
                             | TimelineState::Stopping
-                            | TimelineState::Loading => {
+                            | TimelineState::Loading
+                            | TimelineState::Suspended => {
                                 break format!("aborted because timeline became inactive (new state: {new_state:?})")
                             }
                         }
@@ -2235,7 +2858,8 @@ impl Timeline {
         let storage_time_metrics = match cause {
             LogicalSizeCalculationCause::Initial
             | LogicalSizeCalculationCause::ConsumptionMetricsSyntheticSize
-            | LogicalSizeCalculationCause::TenantSizeHandler => &self.metrics.logical_size_histo,
+            | LogicalSizeCalculationCause::TenantSizeHandler
+            | LogicalSizeCalculationCause::Reconciliation => &self.metrics.logical_size_histo,
             LogicalSizeCalculationCause::EvictionTaskImitation => {
                 &self.metrics.imitate_logical_size_histo
             }
@@ -2343,6 +2967,9 @@ impl Timeline {
         // through. It's included in the error message if we fail to find the key.
         let mut traversal_path = Vec::<TraversalPathItem>::new();
 
+        // Set once a corrupt layer file has been evicted for re-download.
+        let mut evicted_corrupt_layer = false;
+
         let cached_lsn = if let Some((cached_lsn, _)) = &reconstruct_state.img {
             *cached_lsn
         } else {
@@ -2371,18 +2998,18 @@ impl Timeline {
                     if prev_lsn <= cont_lsn {
                         // Didn't make any progress in last iteration. Error out to avoid
                         // getting stuck in the loop.
-                        return Err(layer_traversal_error(format!(
+                        return Err(PageReconstructError::from(layer_traversal_error(format!(
                             "could not find layer with more data for key {} at LSN {}, request LSN {}, ancestor {}",
                             key,
                             Lsn(cont_lsn.0 - 1),
                             request_lsn,
                             timeline.ancestor_lsn
-                        ), traversal_path));
+                        ), traversal_path)));
                     }
                     prev_lsn = cont_lsn;
                 }
                 ValueReconstructResult::Missing => {
-                    return Err(layer_traversal_error(
+                    return Err(PageReconstructError::MissingKey(layer_traversal_error(
                         if cfg!(test) {
                             format!(
                                 "could not find data for key {} at LSN {}, for request at LSN {}\n{}",
@@ -2395,7 +3022,7 @@ impl Timeline {
                             )
                         },
                         traversal_path,
-                    ));
+                    )));
                 }
             }
 
@@ -2435,7 +3062,12 @@ impl Timeline {
                 // NB: this could be avoided by requiring
                 //   branch_lsn >= remote_consistent_lsn
                 // during branch creation.
-                match ancestor.wait_to_become_active(ctx).await {
+                let ancestor_ready = if ancestor.is_suspended() {
+                    Ok(())
+                } else {
+                    ancestor.wait_to_become_active(ctx).await
+                };
+                match ancestor_ready {
                     Ok(()) => {}
                     Err(state) if state == TimelineState::Stopping => {
                         return Err(PageReconstructError::AncestorStopping(ancestor.timeline_id));
@@ -2458,7 +3090,7 @@ impl Timeline {
 
             #[allow(clippy::never_loop)] // see comment at bottom of this loop
             'layer_map_search: loop {
-                let remote_layer = {
+                let layer_to_fetch = 'fetch: {
                     let guard = timeline.layers.read().await;
                     let layers = guard.layer_map();
 
@@ -2535,11 +3167,19 @@ impl Timeline {
                         {
                             // TODO: push a breadcrumb to 'traversal_path' to record the fact that
                             // we downloaded / would need to download this layer.
-                            remote_layer // download happens outside the scope of `layers` guard object
+                            LayerToFetch::Remote(remote_layer) // download happens outside the scope of `layers` guard object
                         } else {
                             // Get all the data needed to reconstruct the page version from this layer.
                             // But if we have an older cached page image, no need to go past that.
                             let lsn_floor = max(cached_lsn + 1, lsn_floor);
+                            if !layer.may_contain_key(key) {
+                                // The layer's bloom filter rules out the key, so there
+                                // is nothing to collect from it. Continue below it.
+                                result = ValueReconstructResult::Continue;
+                                cont_lsn = lsn_floor;
+                                continue 'outer;
+                            }
+                            let num_records = reconstruct_state.records.len();
                             result = match layer
                                 .get_value_reconstruct_data(
                                     key,
@@ -2550,6 +3190,12 @@ impl Timeline {
                                 .await
                             {
                                 Ok(result) => result,
+                                Err(e) if is_checksum_error(&e) && !evicted_corrupt_layer => {
+                                    // Forget whatever was collected from the corrupt file, and
+                                    // re-download it outside the scope of `layers` guard object.
+                                    reconstruct_state.records.truncate(num_records);
+                                    break 'fetch LayerToFetch::Corrupt(layer, e);
+                                }
                                 Err(e) => return Err(PageReconstructError::from(e)),
                             };
                             cont_lsn = lsn_floor;
@@ -2575,6 +3221,16 @@ impl Timeline {
                         continue 'outer;
                     }
                 };
+                let remote_layer = match layer_to_fetch {
+                    LayerToFetch::Remote(remote_layer) => remote_layer,
+                    LayerToFetch::Corrupt(layer, err) => {
+                        // Only try this once per request, in case the remote copy is
+                        // corrupt as well.
+                        evicted_corrupt_layer = true;
+                        timeline.evict_corrupt_layer(layer, err).await?;
+                        continue 'layer_map_search;
+                    }
+                };
                 // Download the remote_layer and replace it in the layer map.
                 // For that, we need to release the mutex. Otherwise, we'd deadlock.
                 //
@@ -2627,15 +3283,212 @@ impl Timeline {
         }
     }
 
+    /// Vectored version of [`Self::get_reconstruct_data`], see [`Self::get_vectored`].
+    ///
+    /// Walks all traversals down the layer map in lockstep: in each round, every
+    /// pending key visits its next layer, with the keys grouped by layer. Returns
+    /// the reads that collected all their data, and the keys that must be retried
+    /// with the single-key path, both along with their index in the request.
+    async fn get_vectored_reconstruct_data(
+        &self,
+        mut pending: Vec<(VectoredTraversal, VectoredValueRead)>,
+        ctx: &RequestContext,
+    ) -> (Vec<(usize, VectoredValueRead)>, Vec<(usize, Key)>) {
+        let mut timeline_owned;
+        let mut timeline = self;
+
+        let mut complete = Vec::new();
+        let mut fallback = Vec::new();
+        // Keys that are done with the current timeline, and continue in its ancestor.
+        let mut to_ancestor = Vec::new();
+
+        loop {
+            // Check the outcome of the previous round, like get_reconstruct_data does.
+            let mut on_this_timeline = Vec::new();
+            for (mut traversal, read) in pending.drain(..) {
+                match read.result {
+                    ValueReconstructResult::Complete => {
+                        crate::metrics::READ_NUM_FS_LAYERS.observe(traversal.read_count as f64);
+                        complete.push((traversal.idx, read));
+                        continue;
+                    }
+                    ValueReconstructResult::Continue => {
+                        if traversal.cont_lsn == traversal.cached_lsn + 1 {
+                            MATERIALIZED_PAGE_CACHE_HIT.inc_by(1);
+                            crate::metrics::READ_NUM_FS_LAYERS.observe(traversal.read_count as f64);
+                            complete.push((traversal.idx, read));
+                            continue;
+                        }
+                        if traversal.prev_lsn <= traversal.cont_lsn {
+                            // No progress; let the single-key path report the error.
+                            fallback.push((traversal.idx, read.key));
+                            continue;
+                        }
+                        traversal.prev_lsn = traversal.cont_lsn;
+                    }
+                    ValueReconstructResult::Missing => {
+                        fallback.push((traversal.idx, read.key));
+                        continue;
+                    }
+                }
+
+                if Lsn(traversal.cont_lsn.0 - 1) <= timeline.ancestor_lsn {
+                    to_ancestor.push((traversal, read));
+                } else {
+                    on_this_timeline.push((traversal, read));
+                }
+            }
+
+            if on_this_timeline.is_empty() {
+                if to_ancestor.is_empty() {
+                    break;
+                }
+
+                // All remaining keys continue in the ancestor. See get_reconstruct_data
+                // for why we might need to wait for it.
+                let ancestor = match timeline.get_ancestor_timeline() {
+                    Ok(ancestor) => ancestor,
+                    Err(_) => {
+                        fallback.extend(to_ancestor.iter().map(|(t, r)| (t.idx, r.key)));
+                        break;
+                    }
+                };
+                if (!ancestor.is_suspended() && ancestor.wait_to_become_active(ctx).await.is_err())
+                    || ancestor.wait_lsn(timeline.ancestor_lsn, ctx).await.is_err()
+                {
+                    fallback.extend(to_ancestor.iter().map(|(t, r)| (t.idx, r.key)));
+                    break;
+                }
+
+                for (traversal, _) in to_ancestor.iter_mut() {
+                    traversal.prev_lsn = Lsn(u64::MAX);
+                }
+                pending = std::mem::take(&mut to_ancestor);
+                timeline_owned = ancestor;
+                timeline = &*timeline_owned;
+                continue;
+            }
+
+            let guard = timeline.layers.read().await;
+            let layers = guard.layer_map();
+
+            // Find the next layer for every key, grouping the keys by layer.
+            let mut groups: HashMap<VectoredReadLayerId, VectoredReadGroup> = HashMap::new();
+            for (mut traversal, mut read) in on_this_timeline {
+                let cont_lsn = traversal.cont_lsn;
+
+                let mut next_layer = None;
+                if let Some(open_layer) = &layers.open_layer {
+                    let start_lsn = open_layer.get_lsn_range().start;
+                    if cont_lsn > start_lsn {
+                        next_layer = Some((
+                            VectoredReadLayerId::Open,
+                            VectoredReadLayer::InMemory(Arc::clone(open_layer)),
+                            start_lsn,
+                        ));
+                    }
+                }
+                if next_layer.is_none() {
+                    for (i, frozen_layer) in layers.frozen_layers.iter().enumerate().rev() {
+                        let start_lsn = frozen_layer.get_lsn_range().start;
+                        if cont_lsn > start_lsn {
+                            next_layer = Some((
+                                VectoredReadLayerId::Frozen(i),
+                                VectoredReadLayer::InMemory(Arc::clone(frozen_layer)),
+                                start_lsn,
+                            ));
+                            break;
+                        }
+                    }
+                }
+                if next_layer.is_none() {
+                    if let Some(SearchResult { lsn_floor, layer }) =
+                        layers.search(read.key, cont_lsn)
+                    {
+                        let layer = guard.get_from_desc(&layer);
+                        if super::storage_layer::downcast_remote_layer(&layer).is_some() {
+                            // Needs an on-demand download, which the single-key path handles.
+                            fallback.push((traversal.idx, read.key));
+                            continue;
+                        }
+                        traversal.read_count += 1;
+                        next_layer = Some((
+                            VectoredReadLayerId::Historic(layer.layer_desc().key()),
+                            VectoredReadLayer::Persistent(layer),
+                            lsn_floor,
+                        ));
+                    } else if timeline.ancestor_timeline.is_some() {
+                        // Nothing on this timeline. Traverse to parent
+                        read.result = ValueReconstructResult::Continue;
+                        traversal.cont_lsn = Lsn(timeline.ancestor_lsn.0 + 1);
+                        pending.push((traversal, read));
+                        continue;
+                    } else {
+                        read.result = ValueReconstructResult::Missing;
+                        pending.push((traversal, read));
+                        continue;
+                    }
+                }
+
+                let (id, layer, start_lsn) = next_layer.expect("found a layer above");
+                // If we have an older cached page image, no need to go past that.
+                let lsn_floor = max(traversal.cached_lsn + 1, start_lsn);
+                read.lsn_range = lsn_floor..cont_lsn;
+                traversal.cont_lsn = lsn_floor;
+
+                let group = groups.entry(id).or_insert_with(|| VectoredReadGroup {
+                    layer,
+                    traversals: Vec::new(),
+                    reads: Vec::new(),
+                });
+                group.traversals.push(traversal);
+                group.reads.push(read);
+            }
+
+            // Read from each layer once, for all of its keys.
+            for (_, mut group) in groups {
+                let res = match &group.layer {
+                    VectoredReadLayer::InMemory(layer) => {
+                        layer
+                            .get_values_reconstruct_data(&mut group.reads, ctx)
+                            .await
+                    }
+                    VectoredReadLayer::Persistent(layer) => {
+                        layer
+                            .get_values_reconstruct_data(&mut group.reads, ctx)
+                            .await
+                    }
+                };
+                if let Err(e) = res {
+                    debug!("vectored read failed, retrying keys one by one: {e:#}");
+                    fallback.extend(
+                        group
+                            .traversals
+                            .iter()
+                            .zip(&group.reads)
+                            .map(|(t, r)| (t.idx, r.key)),
+                    );
+                    continue;
+                }
+                pending.extend(group.traversals.into_iter().zip(group.reads));
+            }
+        }
+
+        (complete, fallback)
+    }
+
     fn lookup_cached_page(&self, key: &Key, lsn: Lsn) -> Option<(Lsn, Bytes)> {
         let cache = page_cache::get();
 
         // FIXME: It's pointless to check the cache for things that are not 8kB pages.
         // We should look at the key to determine if it's a cacheable object
-        let (lsn, read_guard) =
-            cache.lookup_materialized_page(self.tenant_id, self.timeline_id, key, lsn)?;
-        let img = Bytes::from(read_guard.to_vec());
-        Some((lsn, img))
+        match cache.lookup_materialized_page(self.tenant_id, self.timeline_id, key, lsn) {
+            Some((lsn, read_guard)) => {
+                let img = Bytes::from(read_guard.to_vec());
+                Some((lsn, img))
+            }
+            None => shared_page_cache::get()?.lookup(self.tenant_id, self.timeline_id, key, lsn),
+        }
     }
 
     fn get_ancestor_timeline(&self) -> anyhow::Result<Arc<Timeline>> {
@@ -2813,6 +3666,8 @@ impl Timeline {
         frozen_layer: Arc<InMemoryLayer>,
         ctx: &RequestContext,
     ) -> anyhow::Result<()> {
+        utils::failpoint_async!("flush-frozen-layer", |msg| anyhow::bail!(msg));
+
         // As a special case, when we have just imported an image into the repository,
         // instead of writing out a L0 delta layer, we directly write out image layer
         // files instead. This is possible as long as *all* the data imported into the
@@ -2966,14 +3821,11 @@ impl Timeline {
             x.unwrap()
         ));
 
-        save_metadata(
-            self.conf,
-            &self.tenant_id,
-            &self.timeline_id,
-            &metadata,
-            false,
-        )
-        .context("save_metadata")?;
+        self.metadata_log
+            .lock()
+            .unwrap()
+            .save(&metadata)
+            .context("save_metadata")?;
 
         if let Some(remote_client) = &self.remote_client {
             for (path, layer_metadata) in layer_paths_to_upload {
@@ -3002,7 +3854,9 @@ impl Timeline {
                 // as long as the write path is still sync and the read impl
                 // is still not fully async. Otherwise executor threads would
                 // be blocked.
-                let new_delta = Handle::current().block_on(frozen_layer.write_to_disk())?;
+                let new_delta = Handle::current().block_on(
+                    frozen_layer.write_to_disk(self_clone.get_layer_compression_level()),
+                )?;
                 let new_delta_path = new_delta.path();
 
                 // Sync it to disk.
@@ -3054,7 +3908,18 @@ impl Timeline {
             }
         }
         let keyspace = self.collect_keyspace(lsn, ctx).await?;
-        let partitioning = keyspace.partition(partition_size);
+
+        // Keep the SLRUs in partitions of their own, so that their image layers
+        // are created on their own schedule, see time_for_new_image_layer.
+        let slru_range = slru_key_range();
+        let (rel_keyspace, rest) = keyspace.split_at(slru_range.start);
+        let (slru_keyspace, misc_keyspace) = rest.split_at(slru_range.end);
+        let mut partitioning = rel_keyspace.partition(partition_size);
+        for keyspace in [slru_keyspace, misc_keyspace] {
+            partitioning
+                .parts
+                .extend(keyspace.partition(partition_size).parts);
+        }
 
         let mut partitioning_guard = self.partitioning.lock().unwrap();
         if lsn > partitioning_guard.1 {
@@ -3071,7 +3936,21 @@ impl Timeline {
         partition: &KeySpace,
         lsn: Lsn,
     ) -> anyhow::Result<bool> {
-        let threshold = self.get_image_creation_threshold();
+        // SLRUs are appended to and truncated from the front, so nearly all
+        // of their deltas pile up on a few pages at the end. Their partitions
+        // are small, so we can afford to create images for them more often,
+        // which keeps GetSlruPage reconstruction short and lets GC drop the
+        // history of truncated segments.
+        let slru_range = slru_key_range();
+        let is_slru_partition = partition
+            .ranges
+            .iter()
+            .all(|r| r.start >= slru_range.start && r.end <= slru_range.end);
+        let threshold = if is_slru_partition {
+            self.get_slru_image_creation_threshold()
+        } else {
+            self.get_image_creation_threshold()
+        };
 
         let guard = self.layers.read().await;
         let layers = guard.layer_map();
@@ -3102,6 +3981,24 @@ impl Timeline {
             }
         }
 
+        // A sparse image layer over the whole partition holds the latest images
+        // of all the keys modified below it, so only count the deltas above it.
+        // Sparse image layers are incremental, so image_coverage ignores them.
+        let partition_range =
+            partition.ranges.first().unwrap().start..partition.ranges.last().unwrap().end;
+        let sparse_img_lsn = layers
+            .iter_historic_layers()
+            .filter(|layer| {
+                !layer.is_delta()
+                    && layer.is_incremental()
+                    && layer.key_range.start <= partition_range.start
+                    && layer.key_range.end >= partition_range.end
+                    && layer.lsn_range.end <= lsn
+            })
+            .map(|layer| layer.lsn_range.end)
+            .max()
+            .unwrap_or(Lsn(0));
+
         for part_range in &partition.ranges {
             let image_coverage = layers.image_coverage(part_range, lsn)?;
             for (img_range, last_img) in image_coverage {
@@ -3110,6 +4007,7 @@ impl Timeline {
                 } else {
                     Lsn(0)
                 };
+                let img_lsn = max(img_lsn, sparse_img_lsn);
                 // Let's consider an example:
                 //
                 // delta layer with LSN range 71-81
@@ -3145,6 +4043,94 @@ impl Timeline {
         Ok(false)
     }
 
+    /// Decides whether the image layer for `partition` should be a sparse
+    /// one, and if so, returns the LSN to create it at and the keys it should
+    /// hold.
+    ///
+    /// On a branch, the keys that haven't been modified since the branch point
+    /// are only stored in the ancestor timeline. If they make up most of the
+    /// partition, copying them into a dense image layer wastes space and I/O.
+    /// Instead, we create a sparse image layer of the keys that were modified
+    /// on this timeline, and let reads of the other keys continue into the
+    /// ancestor.
+    ///
+    /// The layer map treats sparse image layers like delta layers, and
+    /// expects delta layers not to overlap in LSN. So a sparse image layer is
+    /// created at disk_consistent_lsn, where all the older WAL is in delta
+    /// layers on disk already, and all the newer WAL will be above it.
+    async fn sparse_image_keyspace(
+        &self,
+        partition: &KeySpace,
+        img_range: &Range<Key>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<(Lsn, KeySpace)>> {
+        let lsn = self.get_disk_consistent_lsn();
+        if self.ancestor_timeline.is_none()
+            || lsn <= self.ancestor_lsn
+            || lsn < *self.get_latest_gc_cutoff_lsn()
+        {
+            return Ok(None);
+        }
+        if let Some((_, wanted)) = &*self.wanted_image_layers.lock().unwrap() {
+            if wanted.overlaps(img_range) {
+                // GC can only remove the layers below a dense image layer
+                return Ok(None);
+            }
+        }
+
+        let deltas = {
+            let guard = self.layers.read().await;
+            let layers = guard.layer_map();
+            if layers.image_layer_exists(img_range, &(Lsn(0)..lsn + 1))? {
+                // All the keys are stored on this timeline already
+                return Ok(None);
+            }
+            let mut deltas = Vec::new();
+            for desc in layers.iter_historic_layers() {
+                if desc.key_range.end <= img_range.start || desc.key_range.start >= img_range.end {
+                    continue;
+                }
+                if !desc.is_delta() {
+                    if desc.key_range == *img_range && desc.lsn_range.start == lsn {
+                        // Already created, nothing to add
+                        return Ok(Some((lsn, KeySpace::default())));
+                    }
+                    continue;
+                }
+                match guard.get_from_desc(&desc).downcast_delta_layer() {
+                    Some(delta) => deltas.push(delta),
+                    // Don't download layers just to find out
+                    None => return Ok(None),
+                }
+            }
+            deltas
+        };
+
+        let mut modified = KeySpaceRandomAccum::new();
+        for delta in deltas {
+            let mut prev_key = None;
+            for (key, _, _) in delta.load_keys(ctx).await? {
+                if prev_key != Some(key) && partition.overlaps(&singleton_range(key)) {
+                    modified.add_key(key);
+                }
+                prev_key = Some(key);
+            }
+        }
+        let modified = modified.to_keyspace();
+
+        let num_keys = |keyspace: &KeySpace| -> u64 {
+            keyspace
+                .ranges
+                .iter()
+                .map(|r| key_range_size(r) as u64)
+                .sum()
+        };
+        if num_keys(&modified) * 2 > num_keys(partition) {
+            return Ok(None);
+        }
+        Ok(Some((lsn, modified)))
+    }
+
     async fn create_image_layers(
         &self,
         partitioning: &KeyPartitioning,
@@ -3170,13 +4156,28 @@ impl Timeline {
             let img_range = start..partition.ranges.last().unwrap().end;
             start = img_range.end;
             if force || self.time_for_new_image_layer(partition, lsn).await? {
+                let sparse = if force {
+                    None
+                } else {
+                    self.sparse_image_keyspace(partition, &img_range, ctx)
+                        .await?
+                };
+                let (img_lsn, keyspace) = match &sparse {
+                    Some((_, keyspace)) if keyspace.ranges.is_empty() => {
+                        // Nothing in the partition has been modified on this timeline
+                        continue;
+                    }
+                    Some((sparse_lsn, keyspace)) => (*sparse_lsn, keyspace),
+                    None => (lsn, partition),
+                };
                 let mut image_layer_writer = ImageLayerWriter::new(
                     self.conf,
                     self.timeline_id,
                     self.tenant_id,
                     &img_range,
-                    lsn,
-                    false, // image layer always covers the full range
+                    img_lsn,
+                    sparse.is_some(),
+                    self.get_layer_compression_level(),
                 )?;
 
                 fail_point!("image-layer-writer-fail-before-finish", |_| {
@@ -3184,10 +4185,10 @@ impl Timeline {
                         "failpoint image-layer-writer-fail-before-finish"
                     )))
                 });
-                for range in &partition.ranges {
+                for range in &keyspace.ranges {
                     let mut key = range.start;
                     while key < range.end {
-                        let img = match self.get(key, lsn, ctx).await {
+                        let img = match self.get(key, img_lsn, ctx).await {
                             Ok(img) => img,
                             Err(err) => {
                                 // If we fail to reconstruct a VM or FSM page, we can zero the
@@ -3581,26 +4582,27 @@ impl Timeline {
         let mut heap: BinaryHeap<Hole> = BinaryHeap::with_capacity(max_holes + 1);
         let mut prev: Option<Key> = None;
 
-        let mut all_value_refs = Vec::new();
-        let mut all_keys = Vec::new();
+        let mut value_refs_per_layer = Vec::with_capacity(deltas_to_compact.len());
+        let mut keys_per_layer = Vec::with_capacity(deltas_to_compact.len());
 
         for l in deltas_to_compact.iter() {
             // TODO: replace this with an await once we fully go async
             let delta = l.clone().downcast_delta_layer().expect("delta layer");
             Handle::current().block_on(async {
-                all_value_refs.extend(delta.load_val_refs(ctx).await?);
-                all_keys.extend(delta.load_keys(ctx).await?);
+                value_refs_per_layer.push(delta.load_val_refs(ctx).await?);
+                keys_per_layer.push(delta.load_keys(ctx).await?);
                 anyhow::Ok(())
             })?;
         }
 
-        // The current stdlib sorting implementation is designed in a way where it is
-        // particularly fast where the slice is made up of sorted sub-ranges.
-        all_value_refs.sort_by_key(|(key, lsn, _value_ref)| (*key, *lsn));
-
-        // The current stdlib sorting implementation is designed in a way where it is
-        // particularly fast where the slice is made up of sorted sub-ranges.
-        all_keys.sort_by_key(|(key, lsn, _size)| (*key, *lsn));
+        // The contents of each layer are sorted by key and LSN already, so a merge
+        // sorts them all. The keys are needed twice, the values are merged as they
+        // are written out.
+        let all_keys: Vec<_> = merge_by_key(
+            keys_per_layer.into_iter().map(Vec::into_iter),
+            |(key, lsn, _size)| (*key, *lsn),
+        )
+        .collect();
 
         for (next_key, _next_lsn, _size) in all_keys.iter() {
             let next_key = *next_key;
@@ -3636,7 +4638,10 @@ impl Timeline {
 
         // This iterator walks through all key-value pairs from all the layers
         // we're compacting, in key, LSN order.
-        let all_values_iter = all_value_refs.into_iter();
+        let all_values_iter = merge_by_key(
+            value_refs_per_layer.into_iter().map(Vec::into_iter),
+            |(key, lsn, _value_ref)| (*key, *lsn),
+        );
 
         // This iterator walks through all keys and is needed to calculate size used by each key
         let mut all_keys_iter = all_keys.into_iter();
@@ -3778,6 +4783,7 @@ impl Timeline {
                             debug!("Create new layer {}..{}", lsn_range.start, lsn_range.end);
                             lsn_range.clone()
                         },
+                        self.get_layer_compression_level(),
                     )?);
                 }
 
@@ -4065,6 +5071,16 @@ impl Timeline {
             cutoff_horizon
         };
 
+        // Keep the page versions the replicas read. GC removes what's older than
+        // both cutoffs, so lowering the horizon one is enough.
+        let standby_horizon = self.read_only_horizon();
+        let cutoff_horizon = if standby_horizon.is_valid() && standby_horizon < cutoff_horizon {
+            debug!("gc horizon cutoff {cutoff_horizon} held back by replicas at {standby_horizon}");
+            standby_horizon
+        } else {
+            cutoff_horizon
+        };
+
         // Grab the lock and update the values
         *self.gc_info.write().unwrap() = GcInfo {
             retain_lsns,
@@ -4329,8 +5345,68 @@ impl Timeline {
         &self,
         key: Key,
         request_lsn: Lsn,
-        mut data: ValueReconstructState,
+        data: ValueReconstructState,
     ) -> Result<Bytes, PageReconstructError> {
+        match self.prepare_reconstruction(key, request_lsn, data)? {
+            Reconstruction::Done(img) => Ok(img),
+            Reconstruction::Redo(request) => {
+                let last_rec_lsn = request.records.last().unwrap().0;
+                let img = self.walredo_mgr.request_redo(
+                    key,
+                    request_lsn,
+                    request.base_img,
+                    request.records,
+                    self.pg_version,
+                );
+                self.finish_redo(key, last_rec_lsn, img)
+            }
+        }
+    }
+
+    /// Reconstruct the values of many keys at the same LSN, see
+    /// [`Self::reconstruct_value`]. The WAL redo of all the keys that need it is
+    /// requested together, which lets the WAL redo manager batch the adjacent blocks.
+    /// The results are tagged with the index that comes with each value.
+    fn reconstruct_values(
+        &self,
+        request_lsn: Lsn,
+        values: Vec<(usize, Key, ValueReconstructState)>,
+    ) -> Vec<(usize, Result<Bytes, PageReconstructError>)> {
+        let mut results = Vec::with_capacity(values.len());
+        let mut redo = Vec::new();
+        for (idx, key, data) in values {
+            match self.prepare_reconstruction(key, request_lsn, data) {
+                Ok(Reconstruction::Done(img)) => results.push((idx, Ok(img))),
+                Ok(Reconstruction::Redo(request)) => redo.push((idx, request)),
+                Err(e) => results.push((idx, Err(e))),
+            }
+        }
+        if redo.is_empty() {
+            return results;
+        }
+
+        let redone: Vec<_> = redo
+            .iter()
+            .map(|(idx, request)| (*idx, request.key, request.records.last().unwrap().0))
+            .collect();
+        let imgs = self.walredo_mgr.request_redo_batch(
+            redo.into_iter().map(|(_, request)| request).collect(),
+            self.pg_version,
+        );
+        for ((idx, key, last_rec_lsn), img) in redone.into_iter().zip(imgs) {
+            results.push((idx, self.finish_redo(key, last_rec_lsn, img)));
+        }
+        results
+    }
+
+    /// Returns the page image if no WAL redo is needed to reconstruct the value,
+    /// otherwise the WAL redo request for it.
+    fn prepare_reconstruction(
+        &self,
+        key: Key,
+        request_lsn: Lsn,
+        mut data: ValueReconstructState,
+    ) -> Result<Reconstruction, PageReconstructError> {
         // Perform WAL redo if needed
         data.records.reverse();
 
@@ -4343,7 +5419,10 @@ impl Timeline {
                     img_lsn,
                     request_lsn,
                 );
-                Ok(img.clone())
+                if let Some(cache) = shared_page_cache::get() {
+                    cache.memorize(self.tenant_id, self.timeline_id, &key, *img_lsn, img);
+                }
+                Ok(Reconstruction::Done(img.clone()))
             } else {
                 Err(PageReconstructError::from(anyhow!(
                     "base image for {key} at {request_lsn} not found"
@@ -4373,36 +5452,45 @@ impl Timeline {
                     trace!("found {} WAL records that will init the page for {} at {}, performing WAL redo", data.records.len(), key, request_lsn);
                 };
 
-                let last_rec_lsn = data.records.last().unwrap().0;
-
-                let img = match self
-                    .walredo_mgr
-                    .request_redo(key, request_lsn, data.img, data.records, self.pg_version)
-                    .context("Failed to reconstruct a page image:")
-                {
-                    Ok(img) => img,
-                    Err(e) => return Err(PageReconstructError::from(e)),
-                };
+                Ok(Reconstruction::Redo(WalRedoRequest {
+                    key,
+                    lsn: request_lsn,
+                    base_img: data.img,
+                    records: data.records,
+                }))
+            }
+        }
+    }
 
-                if img.len() == page_cache::PAGE_SZ {
-                    let cache = page_cache::get();
-                    if let Err(e) = cache
-                        .memorize_materialized_page(
-                            self.tenant_id,
-                            self.timeline_id,
-                            key,
-                            last_rec_lsn,
-                            &img,
-                        )
-                        .context("Materialized page memoization failed")
-                    {
-                        return Err(PageReconstructError::from(e));
-                    }
-                }
+    /// Memoizes the page image reconstructed by WAL redo.
+    fn finish_redo(
+        &self,
+        key: Key,
+        last_rec_lsn: Lsn,
+        img: Result<Bytes, WalRedoError>,
+    ) -> Result<Bytes, PageReconstructError> {
+        let img = match img.context("Failed to reconstruct a page image:") {
+            Ok(img) => img,
+            Err(e) => return Err(PageReconstructError::from(e)),
+        };
 
-                Ok(img)
+        if img.len() == page_cache::PAGE_SZ {
+            let cache = page_cache::get();
+            if let Err(e) = cache
+                .memorize_materialized_page(
+                    self.tenant_id,
+                    self.timeline_id,
+                    key,
+                    last_rec_lsn,
+                    &img,
+                )
+                .context("Materialized page memoization failed")
+            {
+                return Err(PageReconstructError::from(e));
             }
         }
+
+        Ok(img)
     }
 
     /// Download a layer file from remote storage and insert it into the layer map.
@@ -4756,6 +5844,56 @@ impl Timeline {
     }
 }
 
+/// What's left to do to reconstruct a value, see [`Timeline::prepare_reconstruction`].
+enum Reconstruction {
+    /// The page image is there, no WAL redo is required.
+    Done(Bytes),
+    Redo(WalRedoRequest),
+}
+
+/// Where one key of [`Timeline::get_vectored`] is in its layer traversal.
+///
+/// The collected data lives in the accompanying [`VectoredValueRead`], which is
+/// kept apart so that the reads for one layer can be passed to it as a slice.
+struct VectoredTraversal {
+    /// Index of the key in the request.
+    idx: usize,
+    cached_lsn: Lsn,
+    cont_lsn: Lsn,
+    prev_lsn: Lsn,
+    /// Number of on-disk layers visited, for metrics.
+    read_count: usize,
+}
+
+/// Identifies a layer of one timeline within a round of a vectored read.
+#[derive(PartialEq, Eq, Hash)]
+enum VectoredReadLayerId {
+    Open,
+    Frozen(usize),
+    Historic(PersistentLayerKey),
+}
+
+enum VectoredReadLayer {
+    InMemory(Arc<InMemoryLayer>),
+    Persistent(Arc<dyn PersistentLayer>),
+}
+
+/// The keys that read from one layer in a round of a vectored read.
+struct VectoredReadGroup {
+    layer: VectoredReadLayer,
+    traversals: Vec<VectoredTraversal>,
+    reads: Vec<VectoredValueRead>,
+}
+
+/// A layer that [`Timeline::get_reconstruct_data`] needs to fetch from remote
+/// storage before it can continue the traversal.
+enum LayerToFetch {
+    /// The layer is not present locally.
+    Remote(Arc<RemoteLayer>),
+    /// The local layer file failed checksum verification.
+    Corrupt(Arc<dyn PersistentLayer>, anyhow::Error),
+}
+
 type TraversalPathItem = (
     ValueReconstructResult,
     Lsn,
@@ -4764,7 +5902,7 @@ type TraversalPathItem = (
 
 /// Helper function for get_reconstruct_data() to add the path of layers traversed
 /// to an error, as anyhow context information.
-fn layer_traversal_error(msg: String, path: Vec<TraversalPathItem>) -> PageReconstructError {
+fn layer_traversal_error(msg: String, path: Vec<TraversalPathItem>) -> anyhow::Error {
     // We want the original 'msg' to be the outermost context. The outermost context
     // is the most high-level information, which also gets propagated to the client.
     let mut msg_iter = path
@@ -4782,8 +5920,7 @@ fn layer_traversal_error(msg: String, path: Vec<TraversalPathItem>) -> PageRecon
     let err = anyhow!(msg_iter.next().unwrap());
 
     // Append all subsequent traversals, and the error message 'msg', as contexts.
-    let msg = msg_iter.fold(err, |err, msg| err.context(msg));
-    PageReconstructError::from(msg)
+    msg_iter.fold(err, |err, msg| err.context(msg))
 }
 
 /// Various functions to mutate the timeline.
@@ -4849,24 +5986,16 @@ fn is_send() {
     _assert_send::<TimelineWriter<'_>>();
 }
 
-/// Add a suffix to a layer file's name: .{num}.old
-/// Uses the first available num (starts at 0)
-fn rename_to_backup(path: &Path) -> anyhow::Result<()> {
-    let filename = path
-        .file_name()
-        .ok_or_else(|| anyhow!("Path {} don't have a file name", path.display()))?
-        .to_string_lossy();
-    let mut new_path = path.to_owned();
-
-    for i in 0u32.. {
-        new_path.set_file_name(format!("{filename}.{i}.old"));
-        if !new_path.exists() {
-            std::fs::rename(path, &new_path)?;
-            return Ok(());
-        }
-    }
-
-    bail!("couldn't find an unused backup number for {:?}", path)
+/// Checks every block of a resident layer file against its checksum.
+async fn find_corrupt_blocks_of_layer(
+    layer: &Arc<dyn PersistentLayer>,
+) -> anyhow::Result<Vec<u32>> {
+    let path = layer
+        .local_path()
+        .context("layer file is not present locally")?;
+    tokio::task::spawn_blocking(move || find_corrupt_blocks(&path))
+        .await
+        .context("spawn_blocking")?
 }
 
 /// Similar to `Arc::ptr_eq`, but only compares the object pointers, not vtables.
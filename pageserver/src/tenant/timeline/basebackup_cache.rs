@@ -0,0 +1,289 @@
+//! The last basebackup tarball sent for a timeline, kept for a short while.
+//!
+//! A compute asks for a basebackup every time it starts, and the ones suspended
+//! when idle are started again on the next connection, every few minutes for
+//! some. Generating the tarball scans the SLRUs, the relation maps and the rest of
+//! the non-relational keyspace, while the tarball at a given LSN is always the
+//! same: the page service keeps the last one of each timeline for
+//! `basebackup_cache_ttl`, keyed by the LSN and the options of the request.
+//!
+//! A checkpoint record ingested into the timeline drops the cached tarball, since
+//! the computes start from a later LSN after a checkpoint anyway.
+//!
+//! The tarball is streamed to the compute as it's generated, and copied aside by a
+//! [`CachingWriter`] for the cache, as long as it's small enough. The cached
+//! tarballs of all the timelines together are limited to [`CACHE_BUDGET_BYTES`]:
+//! a tarball that doesn't fit in the budget isn't cached.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::io::AsyncWrite;
+use utils::lsn::Lsn;
+
+/// Larger tarballs are not cached, to bound the memory used per timeline.
+const MAX_CACHED_TARBALL_SIZE: usize = 16 * 1024 * 1024;
+
+/// Bytes of the tarballs cached by all the timelines together.
+const CACHE_BUDGET_BYTES: usize = 256 * 1024 * 1024;
+
+static CACHE_BUDGET: CacheBudget = CacheBudget::new(CACHE_BUDGET_BYTES);
+
+/// Bytes that the cached tarballs may take in total.
+struct CacheBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl CacheBudget {
+    const fn new(limit: usize) -> Self {
+        CacheBudget {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Charges `bytes` to the budget, until the returned charge is dropped. Returns
+    /// `None` if they don't fit.
+    fn try_charge(&'static self, bytes: usize) -> Option<BudgetCharge> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|used| *used <= self.limit)
+            })
+            .ok()?;
+        Some(BudgetCharge {
+            budget: self,
+            bytes,
+        })
+    }
+}
+
+struct BudgetCharge {
+    budget: &'static CacheBudget,
+    bytes: usize,
+}
+
+impl Drop for BudgetCharge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// The parameters of a basebackup request that determine the tarball.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BasebackupCacheKey {
+    pub lsn: Lsn,
+    pub prev_lsn: Option<Lsn>,
+    pub gzip: bool,
+}
+
+struct CachedTarball {
+    key: BasebackupCacheKey,
+    tarball: Bytes,
+    cached_at: Instant,
+    _charge: BudgetCharge,
+}
+
+pub(crate) struct BasebackupCache {
+    ttl: Duration,
+    budget: &'static CacheBudget,
+    cached: Mutex<Option<CachedTarball>>,
+}
+
+impl BasebackupCache {
+    /// A zero `ttl` disables the cache.
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self::with_budget(ttl, &CACHE_BUDGET)
+    }
+
+    fn with_budget(ttl: Duration, budget: &'static CacheBudget) -> Self {
+        BasebackupCache {
+            ttl,
+            budget,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub(crate) fn get(&self, key: &BasebackupCacheKey) -> Option<Bytes> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &BasebackupCacheKey, now: Instant) -> Option<Bytes> {
+        let mut cached = self.cached.lock().unwrap();
+        match &*cached {
+            Some(entry) if now.duration_since(entry.cached_at) >= self.ttl => {
+                *cached = None;
+                None
+            }
+            Some(entry) if entry.key == *key => Some(entry.tarball.clone()),
+            _ => None,
+        }
+    }
+
+    /// Replaces the cached tarball, if the cache is enabled and the tarball fits in
+    /// the budget.
+    pub(crate) fn insert(&self, key: BasebackupCacheKey, tarball: Bytes) {
+        if !self.is_enabled() || tarball.len() > MAX_CACHED_TARBALL_SIZE {
+            return;
+        }
+        let mut cached = self.cached.lock().unwrap();
+        // Release the budget of the replaced tarball first
+        *cached = None;
+        if let Some(charge) = self.budget.try_charge(tarball.len()) {
+            *cached = Some(CachedTarball {
+                key,
+                tarball,
+                cached_at: Instant::now(),
+                _charge: charge,
+            });
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+/// Writes through to `inner`, keeping a copy of what was written, as long as it's
+/// small enough to be cached.
+pub(crate) struct CachingWriter<W> {
+    inner: W,
+    copy: Option<Vec<u8>>,
+}
+
+impl<W> CachingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        CachingWriter {
+            inner,
+            copy: Some(Vec::new()),
+        }
+    }
+
+    /// The bytes written, if they are few enough to be cached.
+    pub(crate) fn into_tarball(self) -> Option<Bytes> {
+        self.copy.map(Bytes::from)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CachingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if let Some(copy) = &mut this.copy {
+                if copy.len() + n > MAX_CACHED_TARBALL_SIZE {
+                    this.copy = None;
+                } else {
+                    copy.extend_from_slice(&buf[..n]);
+                }
+            }
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(lsn: u64) -> BasebackupCacheKey {
+        BasebackupCacheKey {
+            lsn: Lsn(lsn),
+            prev_lsn: None,
+            gzip: true,
+        }
+    }
+
+    #[test]
+    fn cached_tarball() {
+        let cache = BasebackupCache::new(Duration::from_secs(60));
+        let tarball = Bytes::from_static(b"tarball");
+        cache.insert(key(0x100), tarball.clone());
+
+        assert_eq!(cache.get(&key(0x100)), Some(tarball.clone()));
+        assert_eq!(cache.get(&key(0x200)), None);
+        let uncompressed = BasebackupCacheKey {
+            gzip: false,
+            ..key(0x100)
+        };
+        assert_eq!(cache.get(&uncompressed), None);
+
+        // expired
+        let later = Instant::now() + Duration::from_secs(61);
+        assert_eq!(cache.get_at(&key(0x100), later), None);
+        assert_eq!(cache.get(&key(0x100)), None);
+
+        cache.insert(key(0x100), tarball);
+        cache.clear();
+        assert_eq!(cache.get(&key(0x100)), None);
+
+        let disabled = BasebackupCache::new(Duration::ZERO);
+        disabled.insert(key(0x100), Bytes::from_static(b"tarball"));
+        assert_eq!(disabled.get(&key(0x100)), None);
+    }
+
+    #[test]
+    fn budget_is_shared() {
+        static BUDGET: CacheBudget = CacheBudget::new(10);
+        let cache1 = BasebackupCache::with_budget(Duration::from_secs(60), &BUDGET);
+        let cache2 = BasebackupCache::with_budget(Duration::from_secs(60), &BUDGET);
+
+        cache1.insert(key(0x100), Bytes::from_static(b"123456"));
+        cache2.insert(key(0x100), Bytes::from_static(b"123456"));
+        assert!(cache1.get(&key(0x100)).is_some());
+        assert_eq!(cache2.get(&key(0x100)), None);
+
+        // replacing a tarball releases its bytes first
+        cache1.insert(key(0x200), Bytes::from_static(b"1234567890"));
+        assert!(cache1.get(&key(0x200)).is_some());
+
+        cache1.clear();
+        cache2.insert(key(0x100), Bytes::from_static(b"123456"));
+        assert!(cache2.get(&key(0x100)).is_some());
+        drop(cache2);
+        assert_eq!(BUDGET.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn caching_writer() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut out = Vec::new();
+        let mut writer = CachingWriter::new(&mut out);
+        writer.write_all(b"tar").await.unwrap();
+        writer.write_all(b"ball").await.unwrap();
+        assert_eq!(writer.into_tarball(), Some(Bytes::from_static(b"tarball")));
+        assert_eq!(out, b"tarball");
+
+        // too large to be cached, but written all the same
+        let mut out = Vec::new();
+        let mut writer = CachingWriter::new(&mut out);
+        let chunk = vec![1u8; 1024 * 1024];
+        for _ in 0..=MAX_CACHED_TARBALL_SIZE / chunk.len() {
+            writer.write_all(&chunk).await.unwrap();
+        }
+        assert_eq!(writer.into_tarball(), None);
+        assert_eq!(out.len(), MAX_CACHED_TARBALL_SIZE + chunk.len());
+    }
+}
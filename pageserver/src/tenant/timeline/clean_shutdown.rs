@@ -0,0 +1,130 @@
+//! Marker left in the timeline directory by a graceful shutdown.
+//!
+//! A graceful shutdown flushes the in-memory layers of each timeline and waits
+//! for its remote uploads. If both succeed, it writes the marker with the disk
+//! consistent LSN and the logical size at that LSN. When the timeline is loaded
+//! again at the same disk consistent LSN, the initial logical size is taken from
+//! the marker instead of being calculated from the layers, which is the most
+//! expensive part of starting up a timeline with many relations.
+//!
+//! The marker is removed when the timeline is loaded, so that it can't outlive
+//! the state it describes.
+
+use std::fs;
+use std::io::Write;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::{info, warn};
+use utils::crashsafe::{self, path_with_suffix_extension};
+use utils::lsn::Lsn;
+
+use crate::TEMP_FILE_SUFFIX;
+
+use super::logical_size::CurrentLogicalSize;
+use super::Timeline;
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+struct CleanShutdownMarker {
+    #[serde_as(as = "DisplayFromStr")]
+    disk_consistent_lsn: Lsn,
+    logical_size: u64,
+}
+
+impl Timeline {
+    /// Writes the clean shutdown marker. Must be called after the timeline was
+    /// flushed and the WAL receiver was stopped.
+    pub(crate) fn write_clean_shutdown_marker(&self) -> anyhow::Result<()> {
+        let disk_consistent_lsn = self.get_disk_consistent_lsn();
+        let last_record_lsn = self.get_last_record_lsn();
+        anyhow::ensure!(
+            disk_consistent_lsn == last_record_lsn,
+            "timeline is not fully flushed, disk_consistent_lsn {disk_consistent_lsn} is behind last_record_lsn {last_record_lsn}"
+        );
+        let logical_size = match self.current_logical_size.current_size()? {
+            CurrentLogicalSize::Exact(size) => size,
+            CurrentLogicalSize::Approximate(_) => {
+                // Nothing to save the next startup from
+                info!("logical size is not calculated yet, not writing a clean shutdown marker");
+                return Ok(());
+            }
+        };
+
+        let marker = CleanShutdownMarker {
+            disk_consistent_lsn,
+            logical_size,
+        };
+        let path = self
+            .conf
+            .timeline_clean_shutdown_marker_path(&self.tenant_id, &self.timeline_id);
+        let temp_path = path_with_suffix_extension(&path, TEMP_FILE_SUFFIX);
+        let mut file = fs::File::create(&temp_path)
+            .with_context(|| format!("create clean shutdown marker {}", temp_path.display()))?;
+        file.write_all(&serde_json::to_vec(&marker)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &path)
+            .with_context(|| format!("rename clean shutdown marker to {}", path.display()))?;
+        crashsafe::fsync_file_and_parent(&path)?;
+        Ok(())
+    }
+
+    /// Removes the clean shutdown marker, and takes the initial logical size from
+    /// it if it was written at the disk consistent LSN the timeline was loaded at.
+    ///
+    /// The marker is only an optimization, so failures are logged and ignored.
+    pub(crate) fn consume_clean_shutdown_marker(&self) {
+        let path = self
+            .conf
+            .timeline_clean_shutdown_marker_path(&self.tenant_id, &self.timeline_id);
+        let marker = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice::<CleanShutdownMarker>(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!("failed to read clean shutdown marker: {e}");
+                return;
+            }
+        };
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("failed to remove clean shutdown marker: {e}");
+        }
+        let marker = match marker {
+            Ok(marker) => marker,
+            Err(e) => {
+                warn!("failed to parse clean shutdown marker: {e}");
+                return;
+            }
+        };
+
+        if self.current_logical_size.initial_part_end != Some(marker.disk_consistent_lsn) {
+            info!(
+                "ignoring clean shutdown marker written at {}, the timeline was loaded at {:?}",
+                marker.disk_consistent_lsn, self.current_logical_size.initial_part_end
+            );
+            return;
+        }
+        if self
+            .current_logical_size
+            .initial_logical_size
+            .set(marker.logical_size)
+            .is_ok()
+        {
+            // Like a finished calculation, don't admit any more
+            if let Ok(permit) = self
+                .current_logical_size
+                .initial_size_computation
+                .try_acquire()
+            {
+                permit.forget();
+            }
+            self.metrics
+                .current_logical_size_gauge
+                .set(marker.logical_size);
+            info!(
+                "took the initial logical size {} at {} from the clean shutdown marker",
+                marker.logical_size, marker.disk_consistent_lsn
+            );
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! The computes reading a timeline through the page service, see
+//! [`Timeline::attach_compute`].
+//!
+//! A timeline is read by one read-write compute, the primary, and by any number
+//! of read-only ones: hot standbys, which replay the WAL of the primary from the
+//! safekeepers. The primary reads the latest version of the pages, but a standby
+//! reads them at the LSN it has replayed up to, which can be well behind. Each
+//! `pagestream` connection registers itself with the timelines it reads, and
+//! records the LSN of each request, so that GC keeps the page versions from the
+//! oldest LSN the standbys read on, in addition to the horizon the safekeepers
+//! report for the replicas streaming from them.
+//!
+//! The LSN a standby read at is a lease: it holds GC back for [`READ_LSN_LEASE`]
+//! after the request, so that an idle standby doesn't hold it back forever. A
+//! standby promoted to primary keeps its connections, and is taken for the primary
+//! from its first request for the latest page versions.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use pageserver_api::models::{AttachedComputeInfo, ComputeMode};
+use tracing::info;
+use utils::lsn::{AtomicLsn, Lsn};
+
+use super::Timeline;
+
+/// How long the LSN of a request of a standby holds GC back.
+const READ_LSN_LEASE: Duration = Duration::from_secs(10 * 60);
+
+struct AttachedCompute {
+    peer_addr: SocketAddr,
+    read_only: AtomicBool,
+    connected_at: SystemTime,
+    /// `connected_at`, for the lease of `read_lsn`.
+    connected_instant: Instant,
+    read_lsn: AtomicLsn,
+    /// When `read_lsn` was recorded, in milliseconds since `connected_instant`.
+    read_at_millis: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl AttachedCompute {
+    fn mode(&self) -> ComputeMode {
+        if self.read_only.load(Ordering::Relaxed) {
+            ComputeMode::ReadOnly
+        } else {
+            ComputeMode::ReadWrite
+        }
+    }
+
+    /// The LSN of the last request, if it was recorded within the lease.
+    fn leased_read_lsn(&self, now: Instant) -> Option<Lsn> {
+        let lsn = self.read_lsn.load();
+        let read_at = self.connected_instant
+            + Duration::from_millis(self.read_at_millis.load(Ordering::Relaxed));
+        (lsn.is_valid() && now.saturating_duration_since(read_at) < READ_LSN_LEASE).then_some(lsn)
+    }
+}
+
+/// The computes attached to a timeline.
+#[derive(Default)]
+pub(crate) struct AttachedComputes {
+    next_id: AtomicU64,
+    computes: Mutex<HashMap<u64, Arc<AttachedCompute>>>,
+}
+
+impl AttachedComputes {
+    fn attach(self: &Arc<Self>, peer_addr: SocketAddr, mode: ComputeMode) -> AttachedComputeGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let compute = Arc::new(AttachedCompute {
+            peer_addr,
+            read_only: AtomicBool::new(mode == ComputeMode::ReadOnly),
+            connected_at: SystemTime::now(),
+            connected_instant: Instant::now(),
+            read_lsn: AtomicLsn::new(Lsn::INVALID.0),
+            read_at_millis: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+        });
+        self.computes
+            .lock()
+            .unwrap()
+            .insert(id, Arc::clone(&compute));
+        AttachedComputeGuard {
+            computes: Arc::clone(self),
+            id,
+            compute,
+        }
+    }
+
+    /// The oldest LSN the read-only computes read at within the lease,
+    /// [`Lsn::INVALID`] if there are none, or they haven't read anything lately.
+    fn standby_read_horizon(&self, now: Instant) -> Lsn {
+        self.computes
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|compute| compute.mode() == ComputeMode::ReadOnly)
+            .filter_map(|compute| compute.leased_read_lsn(now))
+            .min()
+            .unwrap_or(Lsn::INVALID)
+    }
+}
+
+/// A compute attached to a timeline, until this is dropped.
+pub(crate) struct AttachedComputeGuard {
+    computes: Arc<AttachedComputes>,
+    id: u64,
+    compute: Arc<AttachedCompute>,
+}
+
+impl AttachedComputeGuard {
+    /// Records a request of the compute, served at `lsn`. `latest` is the flag of
+    /// the request, if it has one: a standby asking for the latest page versions
+    /// has been promoted.
+    pub(crate) fn record_read(&self, lsn: Lsn, latest: Option<bool>) {
+        let compute = &self.compute;
+        if latest == Some(true) && compute.read_only.swap(false, Ordering::Relaxed) {
+            info!(peer_addr = %compute.peer_addr, "read-only compute was promoted");
+        }
+        let read_at = compute.connected_instant.elapsed().as_millis() as u64;
+        compute.read_at_millis.store(read_at, Ordering::Relaxed);
+        compute.read_lsn.store(lsn);
+        compute.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for AttachedComputeGuard {
+    fn drop(&mut self) {
+        self.computes.computes.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Timeline {
+    pub(crate) fn attach_compute(
+        &self,
+        peer_addr: SocketAddr,
+        mode: ComputeMode,
+    ) -> AttachedComputeGuard {
+        self.attached_computes.attach(peer_addr, mode)
+    }
+
+    /// The computes attached to the timeline, the earliest connected first. There's
+    /// an entry per page service connection, and a compute opens one per backend.
+    pub fn attached_computes(&self) -> Vec<AttachedComputeInfo> {
+        let computes = self.attached_computes.computes.lock().unwrap();
+        let mut computes: Vec<(u64, AttachedComputeInfo)> = computes
+            .iter()
+            .map(|(id, compute)| {
+                let info = AttachedComputeInfo {
+                    peer_addr: compute.peer_addr.to_string(),
+                    mode: compute.mode(),
+                    connected_at: compute.connected_at,
+                    read_lsn: compute.read_lsn.load(),
+                    requests: compute.requests.load(Ordering::Relaxed),
+                };
+                (*id, info)
+            })
+            .collect();
+        computes.sort_unstable_by_key(|(id, _)| *id);
+        computes.into_iter().map(|(_, info)| info).collect()
+    }
+
+    /// The oldest LSN the read-only computes of the timeline read at, from the
+    /// safekeepers and from the standbys attached to this pageserver, or
+    /// [`Lsn::INVALID`] if there are none.
+    pub(crate) fn read_only_horizon(&self) -> Lsn {
+        [
+            self.standby_horizon.load(),
+            self.attached_computes.standby_read_horizon(Instant::now()),
+        ]
+        .into_iter()
+        .filter(|lsn| lsn.is_valid())
+        .min()
+        .unwrap_or(Lsn::INVALID)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standby_read_horizon() {
+        let computes = Arc::new(AttachedComputes::default());
+        let attach = |mode| computes.attach("127.0.0.1:5432".parse().unwrap(), mode);
+
+        let now = Instant::now();
+
+        let primary = attach(ComputeMode::ReadWrite);
+        primary.record_read(Lsn(0x1000), Some(true));
+        assert_eq!(computes.standby_read_horizon(now), Lsn::INVALID);
+
+        let standby1 = attach(ComputeMode::ReadOnly);
+        let standby2 = attach(ComputeMode::ReadOnly);
+        standby1.record_read(Lsn(0x3000), Some(false));
+        standby2.record_read(Lsn(0x2000), Some(false));
+        assert_eq!(computes.standby_read_horizon(now), Lsn(0x2000));
+
+        drop(standby2);
+        assert_eq!(computes.standby_read_horizon(now), Lsn(0x3000));
+        assert_eq!(computes.computes.lock().unwrap().len(), 2);
+
+        // the lease expires
+        let later = Instant::now() + READ_LSN_LEASE;
+        assert_eq!(computes.standby_read_horizon(later), Lsn::INVALID);
+
+        // a promoted standby doesn't hold GC back anymore
+        standby1.record_read(Lsn(0x4000), None);
+        assert_eq!(computes.standby_read_horizon(now), Lsn(0x4000));
+        standby1.record_read(Lsn(0x5000), Some(true));
+        assert_eq!(computes.standby_read_horizon(now), Lsn::INVALID);
+        assert_eq!(standby1.compute.mode(), ComputeMode::ReadWrite);
+    }
+}
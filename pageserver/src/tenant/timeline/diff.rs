@@ -0,0 +1,269 @@
+//! Reporting which keys changed on a timeline between two LSNs, see [`Timeline::diff`].
+//!
+//! Only the indexes of the delta layers are read, not the records themselves,
+//! so the byte counts are the on-disk sizes of the records.
+
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::Context;
+use pageserver_api::models::{KeyRangeDiff, RelationDiff, TimelineDiff};
+use utils::lsn::Lsn;
+
+use crate::context::RequestContext;
+use crate::pgdatadir_mapping::{is_rel_block_key, key_to_rel_block};
+use crate::repository::Key;
+use crate::tenant::storage_layer::downcast_remote_layer;
+
+use super::Timeline;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error("{0}")]
+    InvalidLsnRange(String),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Default, Clone, Copy)]
+struct KeyChanges {
+    records: u64,
+    bytes: u64,
+}
+
+impl Timeline {
+    /// Reports the relations and key ranges that changed after `from` up to and including `to`.
+    ///
+    /// If `from` is before the branch point, the changes made on the ancestors are included.
+    /// Only the layers are read: `to` must not be past the disk consistent LSN of the
+    /// timelines.
+    pub async fn diff(
+        self: &Arc<Self>,
+        from: Lsn,
+        to: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<TimelineDiff, DiffError> {
+        if from >= to {
+            return Err(DiffError::InvalidLsnRange(format!(
+                "from_lsn {from} must be smaller than to_lsn {to}"
+            )));
+        }
+        let last_record_lsn = self.get_last_record_lsn();
+        if to > last_record_lsn {
+            return Err(DiffError::InvalidLsnRange(format!(
+                "to_lsn {to} is ahead of last_record_lsn {last_record_lsn}"
+            )));
+        }
+
+        let mut changes = BTreeMap::new();
+        let mut timeline = Arc::clone(self);
+        let mut end = to;
+        loop {
+            let ancestor_lsn = timeline.get_ancestor_lsn();
+            let start = max(from, ancestor_lsn);
+            if start < end {
+                // Records below the GC cutoff may have been removed already
+                let gc_cutoff = *timeline.get_latest_gc_cutoff_lsn();
+                if start < gc_cutoff {
+                    return Err(DiffError::InvalidLsnRange(format!(
+                        "from_lsn {from} is below the GC cutoff {gc_cutoff} of timeline {}",
+                        timeline.timeline_id
+                    )));
+                }
+                // The changes have to be flushed to the layers first
+                let disk_consistent_lsn = timeline.get_disk_consistent_lsn();
+                if end > disk_consistent_lsn {
+                    return Err(DiffError::InvalidLsnRange(format!(
+                        "to_lsn {end} is not flushed on timeline {} yet, \
+                         its disk consistent LSN is {disk_consistent_lsn}",
+                        timeline.timeline_id
+                    )));
+                }
+                timeline
+                    .collect_changes(start + 1..end + 1, &mut changes, ctx)
+                    .await?;
+            }
+            if from >= ancestor_lsn {
+                break;
+            }
+            match timeline.ancestor_timeline.clone() {
+                Some(ancestor) => {
+                    end = min(end, ancestor_lsn);
+                    timeline = ancestor;
+                }
+                None => break,
+            }
+        }
+
+        summarize(from, to, changes).map_err(DiffError::Other)
+    }
+
+    /// Adds up the records in the given LSN range stored in the delta layers of this timeline.
+    async fn collect_changes(
+        &self,
+        lsn_range: Range<Lsn>,
+        changes: &mut BTreeMap<Key, KeyChanges>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let layers = {
+            let guard = self.layers.read().await;
+            guard
+                .layer_map()
+                .iter_historic_layers()
+                .filter(|desc| {
+                    desc.is_delta()
+                        && desc.lsn_range.start < lsn_range.end
+                        && lsn_range.start < desc.lsn_range.end
+                })
+                .map(|desc| guard.get_from_desc(&desc))
+                .collect::<Vec<_>>()
+        };
+
+        for layer in layers {
+            let delta = match downcast_remote_layer(&layer) {
+                Some(remote_layer) => {
+                    let layer_file_name = layer.filename().file_name();
+                    self.download_remote_layer(remote_layer).await?;
+                    self.find_layer(&layer_file_name)
+                        .await
+                        .and_then(|layer| layer.downcast_delta_layer())
+                        .with_context(|| {
+                            format!("layer {layer_file_name} was evicted while computing the diff")
+                        })?
+                }
+                None => layer
+                    .downcast_delta_layer()
+                    .context("expected a delta layer")?,
+            };
+            for (key, records, bytes) in delta.load_key_changes(lsn_range.clone(), ctx).await? {
+                let entry: &mut KeyChanges = changes.entry(key).or_default();
+                entry.records += records;
+                entry.bytes += bytes;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Groups the changed keys by relation, and merges the other keys into ranges.
+fn summarize(
+    from_lsn: Lsn,
+    to_lsn: Lsn,
+    changes: BTreeMap<Key, KeyChanges>,
+) -> anyhow::Result<TimelineDiff> {
+    let mut relations: Vec<RelationDiff> = Vec::new();
+    let mut key_ranges: Vec<(Range<Key>, u64, KeyChanges)> = Vec::new();
+    let mut total = KeyChanges::default();
+
+    for (key, key_changes) in changes {
+        total.records += key_changes.records;
+        total.bytes += key_changes.bytes;
+
+        if is_rel_block_key(key) {
+            let (rel, blknum) = key_to_rel_block(key)?;
+            if relations.last().map(|r| r.rel) != Some(rel) {
+                relations.push(RelationDiff {
+                    rel,
+                    changed_blocks: 0,
+                    size_changed: false,
+                    records: 0,
+                    bytes: 0,
+                });
+            }
+            let relation = relations.last_mut().unwrap();
+            // The relation size is stored in the last key of the relation
+            if blknum == 0xffffffff {
+                relation.size_changed = true;
+            } else {
+                relation.changed_blocks += 1;
+            }
+            relation.records += key_changes.records;
+            relation.bytes += key_changes.bytes;
+        } else {
+            match key_ranges.last_mut() {
+                Some((range, keys, range_changes)) if range.end == key => {
+                    range.end = key.next();
+                    *keys += 1;
+                    range_changes.records += key_changes.records;
+                    range_changes.bytes += key_changes.bytes;
+                }
+                _ => key_ranges.push((key..key.next(), 1, key_changes)),
+            }
+        }
+    }
+
+    Ok(TimelineDiff {
+        from_lsn,
+        to_lsn,
+        relations,
+        key_ranges: key_ranges
+            .into_iter()
+            .map(|(range, changed_keys, range_changes)| KeyRangeDiff {
+                key_start: range.start.to_string(),
+                key_end: range.end.to_string(),
+                changed_keys,
+                records: range_changes.records,
+                bytes: range_changes.bytes,
+            })
+            .collect(),
+        total_records: total.records,
+        total_bytes: total.bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_changes() -> anyhow::Result<()> {
+        let changed = |records, bytes| KeyChanges { records, bytes };
+        let rel_key = |relnode, blknum| Key {
+            field1: 0x00,
+            field2: 1663,
+            field3: 5,
+            field4: relnode,
+            field5: 0,
+            field6: blknum,
+        };
+        let slru_key = |blknum| Key {
+            field1: 0x01,
+            field2: 0,
+            field3: 1,
+            field4: 0,
+            field5: 0,
+            field6: blknum,
+        };
+        let changes = BTreeMap::from([
+            (rel_key(1000, 0), changed(3, 300)),
+            (rel_key(1000, 7), changed(1, 100)),
+            (rel_key(1000, 0xffffffff), changed(1, 10)),
+            (rel_key(1001, 2), changed(2, 200)),
+            (slru_key(0), changed(1, 50)),
+            (slru_key(1), changed(1, 50)),
+            (slru_key(5), changed(4, 80)),
+        ]);
+
+        let diff = summarize(Lsn(0x10), Lsn(0x20), changes)?;
+        assert_eq!(diff.total_records, 13);
+        assert_eq!(diff.total_bytes, 790);
+
+        assert_eq!(diff.relations.len(), 2);
+        assert_eq!(diff.relations[0].rel.relnode, 1000);
+        assert_eq!(diff.relations[0].changed_blocks, 2);
+        assert!(diff.relations[0].size_changed);
+        assert_eq!(diff.relations[0].records, 5);
+        assert_eq!(diff.relations[0].bytes, 410);
+        assert_eq!(diff.relations[1].rel.relnode, 1001);
+        assert!(!diff.relations[1].size_changed);
+
+        assert_eq!(diff.key_ranges.len(), 2);
+        assert_eq!(diff.key_ranges[0].key_start, slru_key(0).to_string());
+        assert_eq!(diff.key_ranges[0].key_end, slru_key(2).to_string());
+        assert_eq!(diff.key_ranges[0].changed_keys, 2);
+        assert_eq!(diff.key_ranges[1].records, 4);
+        Ok(())
+    }
+}
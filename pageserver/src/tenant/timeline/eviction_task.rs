@@ -117,6 +117,10 @@ impl Timeline {
         ctx: &RequestContext,
     ) -> ControlFlow<(), Instant> {
         debug!("eviction iteration: {policy:?}");
+        if self.is_suspended() {
+            // check again in 10 seconds, the layers are left alone until it's resumed
+            return ControlFlow::Continue(Instant::now() + Duration::from_secs(10));
+        }
         match policy {
             EvictionPolicy::NoEviction => {
                 // check again in 10 seconds; XXX config watch mechanism
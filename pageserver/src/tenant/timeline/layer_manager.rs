@@ -193,6 +193,22 @@ impl LayerManager {
         updates.flush();
     }
 
+    /// Remove a corrupt layer from the layer map while it's being rebuilt, called from
+    /// `rematerialize_image_layer`. The layer file is not deleted.
+    pub fn detach_corrupt_layer(&mut self, layer: Arc<dyn PersistentLayer>) {
+        let mut updates = self.layer_map.batch_update();
+        Self::remove_historic_layer(layer, &mut updates, &mut self.layer_fmgr);
+        updates.flush();
+    }
+
+    /// Add back a layer removed by `detach_corrupt_layer`: either the rebuilt layer, or the
+    /// corrupt one if rebuilding it failed.
+    pub fn attach_repaired_layer(&mut self, layer: Arc<dyn PersistentLayer>) {
+        let mut updates = self.layer_map.batch_update();
+        Self::insert_historic_layer(layer, &mut updates, &mut self.layer_fmgr);
+        updates.flush();
+    }
+
     /// Flush a frozen layer and add the written delta layer to the layer map.
     pub fn finish_flush_l0_layer(
         &mut self,
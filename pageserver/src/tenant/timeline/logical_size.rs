@@ -2,7 +2,7 @@ use anyhow::Context;
 use once_cell::sync::OnceCell;
 
 use tokio::sync::Semaphore;
-use utils::lsn::Lsn;
+use utils::lsn::{AtomicLsn, Lsn};
 
 use std::sync::atomic::{AtomicI64, Ordering as AtomicOrdering};
 use std::sync::Arc;
@@ -52,6 +52,11 @@ pub(super) struct LogicalSize {
     /// see `current_logical_size_gauge`. Use the `update_current_logical_size`
     /// to modify this, it will also keep the prometheus metric in sync.
     pub size_added_after_initial: AtomicI64,
+
+    /// The LSN at which the size was last calculated from the layers, by the initial
+    /// calculation or by `Timeline::reconcile_logical_size`. Zero if it never was,
+    /// e.g. on a timeline created since the pageserver started.
+    pub calculated_at: AtomicLsn,
 }
 
 /// Normalized current size, that the data in pageserver occupies.
@@ -83,6 +88,7 @@ impl LogicalSize {
             initial_size_computation: Arc::new(Semaphore::new(0)),
             initial_part_end: None,
             size_added_after_initial: AtomicI64::new(0),
+            calculated_at: AtomicLsn::new(0),
         }
     }
 
@@ -92,6 +98,7 @@ impl LogicalSize {
             initial_size_computation: Arc::new(Semaphore::new(1)),
             initial_part_end: Some(compute_to),
             size_added_after_initial: AtomicI64::new(0),
+            calculated_at: AtomicLsn::new(0),
         }
     }
 
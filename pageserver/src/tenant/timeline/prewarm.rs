@@ -0,0 +1,240 @@
+//! Warming up a timeline for a starting compute, see [`Timeline::prewarm`].
+//!
+//! After a scale-to-zero, the first queries of a compute wait for the pageserver to
+//! download the layers they need and to reconstruct their pages. The page service
+//! counts the `GetPage` requests per relation of each timeline, and a starting
+//! compute asks, with the `prewarm` page service command, to read the system
+//! catalogs and the relations its previous run read the most, while Postgres is
+//! still starting up. The pages are read by a background task, at most one per
+//! timeline, and the command returns as soon as it's spawned.
+//!
+//! The counts are only kept in memory, so after the tenant is attached again, or
+//! the pageserver restarts, only the catalogs are warmed up.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use pageserver_api::reltag::RelTag;
+use postgres_ffi::pg_constants::{FIRST_NORMAL_OBJECT_ID, GLOBALTABLESPACE_OID};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::pgdatadir_mapping::{BlockNumber, Version};
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+
+use super::Timeline;
+
+/// Number of the hottest relations prewarmed, unless the compute asks for another.
+pub const DEFAULT_PREWARM_MAX_RELATIONS: usize = 100;
+
+/// Upper limit of the relations whose reads are counted, per timeline.
+const MAX_TRACKED_RELATIONS: usize = 10_000;
+
+/// Upper limit of the blocks read of each relation, from the start of it.
+const MAX_BLOCKS_PER_RELATION: BlockNumber = 16384;
+
+/// Number of the shards of [`RelAccessCounts`].
+const RELATION_SHARDS: usize = 16;
+
+/// What to prewarm on a timeline, and whether it's being prewarmed.
+#[derive(Default)]
+pub(crate) struct PrewarmState {
+    access_counts: RelAccessCounts,
+    running: AtomicBool,
+}
+
+/// The number of `GetPage` requests per relation of a timeline.
+///
+/// Recorded on every `GetPage` request, so the relations are spread over shards, and
+/// the counts of the known relations are bumped under a shared lock.
+#[derive(Default)]
+struct RelAccessCounts {
+    shards: [RwLock<HashMap<RelTag, AtomicU64>>; RELATION_SHARDS],
+    tracked: AtomicUsize,
+}
+
+impl RelAccessCounts {
+    fn record(&self, rel: RelTag) {
+        let shard = &self.shards[shard_of(&rel)];
+        if let Some(count) = shard.read().unwrap().get(&rel) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut shard = shard.write().unwrap();
+        if let Some(count) = shard.get(&rel) {
+            count.fetch_add(1, Ordering::Relaxed);
+        } else if self.tracked.fetch_add(1, Ordering::Relaxed) < MAX_TRACKED_RELATIONS {
+            shard.insert(rel, AtomicU64::new(1));
+        } else {
+            self.tracked.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The `n` relations read the most, the most read first.
+    fn hottest(&self, n: usize) -> Vec<RelTag> {
+        let mut rels: Vec<(RelTag, u64)> = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            rels.extend(
+                shard
+                    .iter()
+                    .map(|(rel, count)| (*rel, count.load(Ordering::Relaxed))),
+            );
+        }
+        rels.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        rels.into_iter().take(n).map(|(rel, _)| rel).collect()
+    }
+}
+
+fn shard_of(rel: &RelTag) -> usize {
+    let mut hasher = DefaultHasher::new();
+    rel.hash(&mut hasher);
+    hasher.finish() as usize % RELATION_SHARDS
+}
+
+#[derive(Debug, Default)]
+pub struct PrewarmStats {
+    pub relations: usize,
+    pub pages: u64,
+}
+
+impl Timeline {
+    pub(crate) fn record_rel_access(&self, rel: RelTag) {
+        self.prewarm_state.access_counts.record(rel);
+    }
+
+    /// Spawns a background task running [`Self::prewarm`], unless one is already
+    /// running on the timeline. Returns whether it was spawned.
+    pub fn spawn_prewarm(self: &Arc<Self>, max_relations: usize) -> bool {
+        if self.prewarm_state.running.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let timeline = Arc::clone(self);
+        let span = info_span!(parent: None, "prewarm",
+            tenant_id = %self.tenant_id, timeline_id = %self.timeline_id);
+        task_mgr::spawn(
+            BACKGROUND_RUNTIME.handle(),
+            TaskKind::Prewarm,
+            Some(self.tenant_id),
+            Some(self.timeline_id),
+            "prewarm",
+            false,
+            async move {
+                let ctx = RequestContext::new(TaskKind::Prewarm, DownloadBehavior::Download);
+                let cancel = task_mgr::shutdown_token();
+                tokio::select! {
+                    _ = cancel.cancelled() => {}
+                    res = timeline.prewarm(max_relations, &ctx) => {
+                        if let Err(e) = res {
+                            warn!("prewarm failed: {e:#}");
+                        }
+                    }
+                }
+                timeline
+                    .prewarm_state
+                    .running
+                    .store(false, Ordering::Release);
+                Ok(())
+            }
+            .instrument(span),
+        );
+        true
+    }
+
+    /// Reads the pages of the system catalogs, and of up to `max_relations` of the
+    /// relations read the most, at the last record LSN, so that the layers they
+    /// need are downloaded, and the pages are in the page caches.
+    pub async fn prewarm(
+        &self,
+        max_relations: usize,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<PrewarmStats> {
+        let lsn = self.get_last_record_lsn();
+        let version = Version::Lsn(lsn);
+
+        let mut dbs: Vec<(u32, u32)> = self.list_dbdirs(lsn, ctx).await?.into_keys().collect();
+        dbs.push((GLOBALTABLESPACE_OID, 0));
+        let mut rels = Vec::new();
+        for (spcnode, dbnode) in dbs {
+            let mut catalog: Vec<RelTag> = self
+                .list_rels(spcnode, dbnode, version, ctx)
+                .await?
+                .into_iter()
+                .filter(|rel| rel.relnode < FIRST_NORMAL_OBJECT_ID)
+                .collect();
+            catalog.sort();
+            rels.extend(catalog);
+        }
+        let mut seen: HashSet<RelTag> = rels.iter().copied().collect();
+        for rel in self.prewarm_state.access_counts.hottest(max_relations) {
+            if seen.insert(rel) {
+                rels.push(rel);
+            }
+        }
+
+        let mut stats = PrewarmStats::default();
+        for rel in rels {
+            // dropped since it was read
+            if !self.get_rel_exists(rel, version, true, ctx).await? {
+                continue;
+            }
+            let nblocks = self.get_rel_size(rel, version, true, ctx).await?;
+            for blkno in 0..nblocks.min(MAX_BLOCKS_PER_RELATION) {
+                self.get_rel_page_at_lsn(rel, blkno, version, true, ctx)
+                    .await?;
+                stats.pages += 1;
+            }
+            stats.relations += 1;
+        }
+        info!(
+            "prewarmed {} relations, {} pages at {lsn}",
+            stats.relations, stats.pages
+        );
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rel(relnode: u32) -> RelTag {
+        RelTag {
+            spcnode: 1663,
+            dbnode: 5,
+            relnode,
+            forknum: 0,
+        }
+    }
+
+    #[test]
+    fn hottest_relations() {
+        let counts = RelAccessCounts::default();
+        for (relnode, n) in [(20000, 3), (20001, 10), (20002, 1), (20003, 5)] {
+            for _ in 0..n {
+                counts.record(rel(relnode));
+            }
+        }
+        assert_eq!(counts.hottest(2), vec![rel(20001), rel(20003)]);
+        assert_eq!(counts.hottest(10).len(), 4);
+    }
+
+    #[test]
+    fn tracked_relations_limit() {
+        let counts = RelAccessCounts::default();
+        for relnode in 0..(MAX_TRACKED_RELATIONS as u32 + 10) {
+            counts.record(rel(relnode));
+        }
+        // the known relations are still counted
+        counts.record(rel(0));
+        assert_eq!(
+            counts.tracked.load(Ordering::Relaxed),
+            MAX_TRACKED_RELATIONS
+        );
+        assert_eq!(counts.hottest(usize::MAX).len(), MAX_TRACKED_RELATIONS);
+        assert_eq!(counts.hottest(1), vec![rel(0)]);
+    }
+}
@@ -0,0 +1,113 @@
+//! Marker left in the timeline directory when the WAL ingestion starts skipping
+//! the relation data of the timeline, see [`PageServerConf::ingest_regions`].
+//!
+//! The page versions skipped while the region of the timeline wasn't ingested
+//! are never ingested again, so adding the region back to `ingest_regions` later
+//! doesn't make the pages of the timeline readable. The marker keeps the timeline,
+//! and the branches created from it after the LSN the data was first skipped at,
+//! refusing the page reads across restarts.
+//!
+//! [`PageServerConf::ingest_regions`]: crate::config::PageServerConf::ingest_regions
+
+use std::fs;
+use std::io::Write;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tracing::{info, warn};
+use utils::crashsafe::{self, path_with_suffix_extension};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::config::PageServerConf;
+use crate::TEMP_FILE_SUFFIX;
+
+use super::Timeline;
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+struct RelationDataSkippedMarker {
+    #[serde_as(as = "DisplayFromStr")]
+    since: Lsn,
+}
+
+/// Reads the marker of a timeline being loaded. A marker that can't be read is
+/// taken as skipped from the start, so that the pages are refused rather than
+/// served incomplete.
+pub(super) fn load_relation_data_skipped_marker(
+    conf: &PageServerConf,
+    tenant_id: &TenantId,
+    timeline_id: &TimelineId,
+) -> Option<Lsn> {
+    let path = conf.timeline_relation_data_skipped_path(tenant_id, timeline_id);
+    let marker = match fs::read(&path) {
+        Ok(contents) => serde_json::from_slice::<RelationDataSkippedMarker>(&contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("failed to read relation data skipped marker: {e}");
+            return Some(Lsn(0));
+        }
+    };
+    match marker {
+        Ok(marker) => Some(marker.since),
+        Err(e) => {
+            warn!("failed to parse relation data skipped marker: {e}");
+            Some(Lsn(0))
+        }
+    }
+}
+
+impl Timeline {
+    /// Records that the relation data of the timeline is skipped from `lsn` on. Only
+    /// the first call writes the marker.
+    pub(crate) fn mark_relation_data_skipped(&self, lsn: Lsn) -> anyhow::Result<()> {
+        let mut skipped_since = self.relation_data_skipped_since.lock().unwrap();
+        if skipped_since.is_some() {
+            return Ok(());
+        }
+
+        let marker = RelationDataSkippedMarker { since: lsn };
+        let path = self
+            .conf
+            .timeline_relation_data_skipped_path(&self.tenant_id, &self.timeline_id);
+        let temp_path = path_with_suffix_extension(&path, TEMP_FILE_SUFFIX);
+        let mut file = fs::File::create(&temp_path).with_context(|| {
+            format!(
+                "create relation data skipped marker {}",
+                temp_path.display()
+            )
+        })?;
+        file.write_all(&serde_json::to_vec(&marker)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &path).with_context(|| {
+            format!("rename relation data skipped marker to {}", path.display())
+        })?;
+        crashsafe::fsync_file_and_parent(&path)?;
+
+        info!("skipping the relation data of the timeline from {lsn}");
+        *skipped_since = Some(lsn);
+        Ok(())
+    }
+
+    /// Whether any relation data of the timeline was skipped, or of its ancestors
+    /// up to the branch point.
+    pub(super) fn relation_data_skipped(&self) -> bool {
+        if self.relation_data_skipped_since.lock().unwrap().is_some() {
+            return true;
+        }
+        self.ancestor_relation_data_skipped()
+    }
+
+    fn ancestor_relation_data_skipped(&self) -> bool {
+        let Some(ancestor) = &self.ancestor_timeline else {
+            return false;
+        };
+        let skipped_before_branch = ancestor
+            .relation_data_skipped_since
+            .lock()
+            .unwrap()
+            .is_some_and(|since| since <= self.ancestor_lsn);
+        skipped_before_branch || ancestor.ancestor_relation_data_skipped()
+    }
+}
@@ -0,0 +1,182 @@
+//! Exporting the local state of a timeline as a tar archive, see
+//! [`Timeline::export_snapshot`], and unpacking it for a fresh tenant, see
+//! [`crate::tenant::mgr::import_tenant_snapshot`].
+//!
+//! The archive contains the timeline's `metadata` file and its layer files, all
+//! at the top level. Only timelines without an ancestor can be exported, because
+//! the layers of the ancestors aren't included.
+
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::{bail, ensure, Context};
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_tar::{Archive, Builder, Header};
+use tracing::*;
+use utils::crashsafe;
+
+use crate::tenant::metadata::{load_metadata, TimelineMetadata};
+use crate::tenant::storage_layer::LayerFileName;
+use crate::METADATA_FILE_NAME;
+
+use super::Timeline;
+
+impl Timeline {
+    /// Writes the metadata and all layer files of the timeline to `writer` as a tar archive.
+    ///
+    /// The in-memory layers are flushed first, and the layers that are only in remote
+    /// storage are downloaded.
+    #[instrument(skip_all, fields(tenant_id = %self.tenant_id, timeline_id = %self.timeline_id))]
+    pub async fn export_snapshot<W>(&self, writer: W) -> anyhow::Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        ensure!(
+            self.get_ancestor_timeline_id().is_none(),
+            "cannot export a timeline with an ancestor, the ancestor's layers would be missing"
+        );
+
+        self.freeze_and_flush().await?;
+
+        loop {
+            let remote_layers = {
+                let guard = self.layers.read().await;
+                guard
+                    .layer_map()
+                    .iter_historic_layers()
+                    .filter_map(|l| guard.get_from_desc(&l).downcast_remote_layer())
+                    .collect::<Vec<_>>()
+            };
+            if remote_layers.is_empty() {
+                break;
+            }
+            for remote_layer in remote_layers {
+                self.download_remote_layer(remote_layer).await?;
+            }
+        }
+
+        // Open the layer files while holding the layer map lock, so that the layers match
+        // the metadata. A layer that compaction, GC or eviction removes afterwards can
+        // still be read through its open file.
+        let (metadata, layer_files) = {
+            let guard = self.layers.read().await;
+            let metadata = load_metadata(self.conf, &self.tenant_id, &self.timeline_id)
+                .context("load timeline metadata")?;
+            let mut layer_files = Vec::new();
+            for desc in guard.layer_map().iter_historic_layers() {
+                let layer = guard.get_from_desc(&desc);
+                let file_name = layer.filename().file_name();
+                let Some(path) = layer.local_path() else {
+                    bail!("layer {file_name} was evicted during the export");
+                };
+                let file = std::fs::File::open(&path)
+                    .with_context(|| format!("open layer file {}", path.display()))?;
+                layer_files.push((file_name, file));
+            }
+            (metadata, layer_files)
+        };
+
+        info!(
+            "exporting {} layers at disk_consistent_lsn {}",
+            layer_files.len(),
+            metadata.disk_consistent_lsn()
+        );
+
+        let mut builder = Builder::new(writer);
+        let metadata_bytes = metadata.to_bytes()?;
+        let header = new_tar_header(METADATA_FILE_NAME, metadata_bytes.len() as u64)?;
+        builder.append(&header, &metadata_bytes[..]).await?;
+        for (file_name, file) in layer_files {
+            let mut file = tokio::fs::File::from_std(file);
+            builder
+                .append_file(&file_name, &mut file)
+                .await
+                .with_context(|| format!("write layer {file_name} to the archive"))?;
+        }
+        let mut writer = builder.into_inner().await?;
+        writer.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Unpacks an archive written by [`Timeline::export_snapshot`] into the empty
+/// directory `target_dir`, and returns the timeline metadata from it.
+pub async fn unpack_snapshot(
+    reader: impl AsyncRead + Unpin,
+    target_dir: &Path,
+) -> anyhow::Result<TimelineMetadata> {
+    let mut metadata = None;
+    let mut num_layers = 0;
+
+    let mut entries = Archive::new(reader).entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(file_name) = path.to_str().filter(|_| path.components().count() == 1) else {
+            bail!("unexpected path {} in the snapshot", path.display());
+        };
+        ensure!(
+            entry.header().entry_type().is_file(),
+            "entry {file_name} in the snapshot is not a regular file"
+        );
+
+        if file_name == METADATA_FILE_NAME {
+            let mut bytes = Vec::new();
+            tokio::io::copy(&mut entry, &mut bytes).await?;
+            let parsed = TimelineMetadata::from_bytes(&bytes).context("parse metadata")?;
+            ensure!(
+                parsed.ancestor_timeline().is_none(),
+                "the snapshot is of a timeline with an ancestor"
+            );
+            ensure!(metadata.is_none(), "duplicate metadata in the snapshot");
+            metadata = Some(parsed);
+            continue;
+        }
+
+        LayerFileName::from_str(file_name)
+            .map_err(|e| anyhow::anyhow!("unexpected file in the snapshot: {e}"))?;
+        let layer_path = target_dir.join(file_name);
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&layer_path)
+            .await
+            .with_context(|| format!("create layer file {}", layer_path.display()))?;
+        tokio::io::copy(&mut entry, &mut file).await?;
+        file.sync_all().await?;
+        num_layers += 1;
+    }
+
+    let Some(metadata) = metadata else {
+        bail!("no metadata in the snapshot");
+    };
+    let metadata_path = target_dir.join(METADATA_FILE_NAME);
+    let mut file = tokio::fs::File::create(&metadata_path).await?;
+    file.write_all(&metadata.to_bytes()?).await?;
+    file.sync_all().await?;
+    crashsafe::fsync(target_dir)?;
+
+    info!(
+        "unpacked {num_layers} layers at disk_consistent_lsn {}",
+        metadata.disk_consistent_lsn()
+    );
+    Ok(metadata)
+}
+
+fn new_tar_header(path: &str, size: u64) -> anyhow::Result<Header> {
+    let mut header = Header::new_gnu();
+    header.set_size(size);
+    header.set_path(path)?;
+    header.set_mode(0b110000000); // -rw-------
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    );
+    header.set_cksum();
+    Ok(header)
+}
@@ -58,6 +58,8 @@ pub struct WalReceiverConf {
     pub auth_token: Option<Arc<String>>,
     pub availability_zone: Option<String>,
     pub ingest_batch_size: u64,
+    /// Ask the safekeepers to compress the streamed WAL.
+    pub compression: bool,
 }
 
 pub struct WalReceiver {
@@ -17,7 +17,7 @@ use crate::metrics::{
     WALRECEIVER_ACTIVE_MANAGERS, WALRECEIVER_BROKER_UPDATES, WALRECEIVER_CANDIDATES_ADDED,
     WALRECEIVER_CANDIDATES_REMOVED, WALRECEIVER_SWITCHES,
 };
-use crate::task_mgr::TaskKind;
+use crate::task_mgr::{self, TaskKind};
 use crate::tenant::{debug_assert_current_span_has_tenant_and_timeline_id, Timeline};
 use anyhow::Context;
 use chrono::{NaiveDateTime, Utc};
@@ -32,9 +32,7 @@ use tokio::select;
 use tracing::*;
 
 use postgres_connection::{parse_host_port, PgConnectionConfig};
-use utils::backoff::{
-    exponential_backoff, DEFAULT_BASE_BACKOFF_SECONDS, DEFAULT_MAX_BACKOFF_SECONDS,
-};
+use utils::backoff::{self, Backoff, RetryOptions};
 use utils::{
     id::{NodeId, TenantTimelineId},
     lsn::Lsn,
@@ -86,7 +84,13 @@ pub(super) async fn connection_manager_loop_step(
     // Subscribe to the broker updates. Stream shares underlying TCP connection
     // with other streams on this client (other connection managers). When
     // object goes out of scope, stream finishes in drop() automatically.
-    let mut broker_subscription = subscribe_for_timeline_updates(broker_client, id).await;
+    let mut broker_subscription = match subscribe_for_timeline_updates(broker_client, id).await {
+        Some(subscription) => subscription,
+        None => {
+            debug!("shutdown requested, stopping wal connection manager loop");
+            return ControlFlow::Break(());
+        }
+    };
     debug!("Subscribed for broker timeline updates");
 
     loop {
@@ -166,6 +170,10 @@ pub(super) async fn connection_manager_loop_step(
                                     warn!("timeline transitioned back to Loading state, that should not happen");
                                     return ControlFlow::Continue(());
                                 }
+                                TimelineState::Suspended => {
+                                    debug!("timeline suspended, waiting for it to be resumed");
+                                    return ControlFlow::Continue(());
+                                }
                             }
                         }
                         Err(_sender_dropped_error) => return ControlFlow::Break(()),
@@ -173,6 +181,10 @@ pub(super) async fn connection_manager_loop_step(
                 }
             } => match new_event {
                 ControlFlow::Continue(()) => {
+                    // Not receiving WAL until the timeline becomes active again
+                    if let Some(wal_connection) = connection_manager_state.wal_connection.take() {
+                        wal_connection.connection_task.shutdown().await;
+                    }
                     return ControlFlow::Continue(());
                 }
                 ControlFlow::Break(()) => {
@@ -206,46 +218,46 @@ pub(super) async fn connection_manager_loop_step(
 }
 
 /// Endlessly try to subscribe for broker updates for a given timeline.
+/// Returns [`None`] if the task is requested to shut down meanwhile.
 async fn subscribe_for_timeline_updates(
     broker_client: &mut BrokerClientChannel,
     id: TenantTimelineId,
-) -> Streaming<SafekeeperTimelineInfo> {
-    let mut attempt = 0;
-    loop {
-        exponential_backoff(
-            attempt,
-            DEFAULT_BASE_BACKOFF_SECONDS,
-            DEFAULT_MAX_BACKOFF_SECONDS,
-        )
-        .await;
-        attempt += 1;
-
-        // subscribe to the specific timeline
-        let key = SubscriptionKey::TenantTimelineId(ProtoTenantTimelineId {
-            tenant_id: id.tenant_id.as_ref().to_owned(),
-            timeline_id: id.timeline_id.as_ref().to_owned(),
-        });
-        let request = SubscribeSafekeeperInfoRequest {
-            subscription_key: Some(key),
-        };
-
-        match broker_client.subscribe_safekeeper_info(request).await {
-            Ok(resp) => {
-                return resp.into_inner();
-            }
-            Err(e) => {
-                // Safekeeper nodes can stop pushing timeline updates to the broker, when no new writes happen and
-                // entire WAL is streamed. Keep this noticeable with logging, but do not warn/error.
-                info!("Attempt #{attempt}, failed to subscribe for timeline {id} updates in broker: {e:#}");
-                continue;
-            }
-        }
-    }
+) -> Option<Streaming<SafekeeperTimelineInfo>> {
+    let options = RetryOptions {
+        backoff: Backoff::default(),
+        // Safekeeper nodes can stop pushing timeline updates to the broker, when no new writes happen and
+        // entire WAL is streamed. Keep this noticeable with logging, but do not warn/error.
+        warn_threshold: u32::MAX,
+        max_retries: u32::MAX,
+        deadline: None,
+    };
+    backoff::retry_with_options(
+        || {
+            // subscribe to the specific timeline
+            let key = SubscriptionKey::TenantTimelineId(ProtoTenantTimelineId {
+                tenant_id: id.tenant_id.as_ref().to_owned(),
+                timeline_id: id.timeline_id.as_ref().to_owned(),
+            });
+            let request = SubscribeSafekeeperInfoRequest {
+                subscription_key: Some(key),
+            };
+            // The client is a handle of the shared channel
+            let mut broker_client = broker_client.clone();
+            async move { broker_client.subscribe_safekeeper_info(request).await }
+        },
+        |_| false,
+        &options,
+        &format!("subscribe for timeline {id} updates in broker"),
+        &task_mgr::shutdown_token(),
+    )
+    .await
+    // with endless retries, only cancellation stops them
+    .and_then(Result::ok)
+    .map(|resp| resp.into_inner())
 }
 
-const WALCONNECTION_RETRY_MIN_BACKOFF_SECONDS: f64 = 0.1;
-const WALCONNECTION_RETRY_MAX_BACKOFF_SECONDS: f64 = 15.0;
-const WALCONNECTION_RETRY_BACKOFF_MULTIPLIER: f64 = 1.5;
+const WALCONNECTION_RETRY_BACKOFF: Backoff =
+    Backoff::new(Duration::from_millis(100), Duration::from_secs(15)).with_multiplier(1.5);
 
 /// All data that's needed to run endless broker loop and keep the WAL streaming connection alive, if possible.
 pub(super) struct ConnectionManagerState {
@@ -362,7 +374,8 @@ struct NewCommittedWAL {
 #[derive(Debug, Clone, Copy)]
 struct RetryInfo {
     next_retry_at: Option<NaiveDateTime>,
-    retry_duration_seconds: f64,
+    /// Connections dropped since one processed WAL, see [`WALCONNECTION_RETRY_BACKOFF`].
+    failed_attempts: u32,
 }
 
 /// Data about the timeline to connect to, received from the broker.
@@ -400,6 +413,7 @@ impl ConnectionManagerState {
         let node_id = new_sk.safekeeper_id;
         let connect_timeout = self.conf.wal_connect_timeout;
         let ingest_batch_size = self.conf.ingest_batch_size;
+        let compression = self.conf.compression;
         let timeline = Arc::clone(&self.timeline);
         let ctx = ctx.detached_child(
             TaskKind::WalReceiverConnectionHandler,
@@ -420,6 +434,7 @@ impl ConnectionManagerState {
                     ctx,
                     node_id,
                     ingest_batch_size,
+                    compression,
                 )
                 .await;
 
@@ -482,8 +497,10 @@ impl ConnectionManagerState {
             .entry(wal_connection.sk_id)
             .or_insert(RetryInfo {
                 next_retry_at: None,
-                retry_duration_seconds: WALCONNECTION_RETRY_MIN_BACKOFF_SECONDS,
+                failed_attempts: 0,
             });
+        retry.failed_attempts = retry.failed_attempts.saturating_add(1);
+        let retry_duration = WALCONNECTION_RETRY_BACKOFF.delay(retry.failed_attempts);
 
         let now = Utc::now().naive_utc();
 
@@ -494,7 +511,7 @@ impl ConnectionManagerState {
             wal_connection
                 .started_at
                 .checked_add_signed(chrono::Duration::milliseconds(
-                    (retry.retry_duration_seconds * 1000.0) as i64,
+                    retry_duration.as_millis() as i64
                 ));
 
         if let Some(next) = &retry.next_retry_at {
@@ -505,15 +522,6 @@ impl ConnectionManagerState {
                 );
             }
         }
-
-        let next_retry_duration =
-            retry.retry_duration_seconds * WALCONNECTION_RETRY_BACKOFF_MULTIPLIER;
-        // Clamp the next retry duration to the maximum allowed.
-        let next_retry_duration = next_retry_duration.min(WALCONNECTION_RETRY_MAX_BACKOFF_SECONDS);
-        // Clamp the next retry duration to the minimum allowed.
-        let next_retry_duration = next_retry_duration.max(WALCONNECTION_RETRY_MIN_BACKOFF_SECONDS);
-
-        retry.retry_duration_seconds = next_retry_duration;
     }
 
     /// Returns time needed to wait to have a new candidate for WAL streaming.
@@ -547,6 +555,24 @@ impl ConnectionManagerState {
             info!("New SK node was added: {new_safekeeper_id}");
             WALRECEIVER_CANDIDATES_ADDED.inc();
         }
+        self.update_standby_horizon();
+    }
+
+    /// Passes the oldest LSN of the replicas, over all the safekeepers they stream
+    /// from, to the GC of the timeline.
+    fn update_standby_horizon(&self) {
+        let standby_horizon = self
+            .wal_stream_candidates
+            .values()
+            .map(|info| Lsn(info.timeline.standby_horizon))
+            .filter(|lsn| lsn.is_valid())
+            .min()
+            .unwrap_or(Lsn::INVALID);
+        let old = self.timeline.standby_horizon.load();
+        if old != standby_horizon {
+            debug!("standby horizon changed from {old} to {standby_horizon}");
+            self.timeline.standby_horizon.store(standby_horizon);
+        }
     }
 
     /// Cleans up stale broker records and checks the rest for the new connection candidate.
@@ -775,6 +801,7 @@ impl ConnectionManagerState {
                 }
                 match wal_stream_connection_config(
                     self.id,
+                    self.timeline.conf.id,
                     info.safekeeper_connstr.as_ref(),
                     match &self.conf.auth_token {
                         None => None,
@@ -816,6 +843,7 @@ impl ConnectionManagerState {
                 self.wal_connection_retries.remove(&node_id);
                 WALRECEIVER_CANDIDATES_REMOVED.inc();
             }
+            self.update_standby_horizon();
         }
     }
 
@@ -883,6 +911,7 @@ fn wal_stream_connection_config(
         tenant_id,
         timeline_id,
     }: TenantTimelineId,
+    pageserver_id: NodeId,
     listen_pg_addr_str: &str,
     auth_token: Option<&str>,
     availability_zone: Option<&str>,
@@ -895,6 +924,8 @@ fn wal_stream_connection_config(
             "-c".to_owned(),
             format!("timeline_id={}", timeline_id),
             format!("tenant_id={}", tenant_id),
+            // Lets the safekeeper tell the pageservers of the timeline apart
+            format!("pageserver_id={}", pageserver_id),
         ])
         .set_password(auth_token.map(|s| s.to_owned()));
 
@@ -930,6 +961,7 @@ mod tests {
                 local_start_lsn: 0,
                 safekeeper_connstr: safekeeper_connstr.to_owned(),
                 availability_zone: None,
+                standby_horizon: 0,
             },
             latest_update,
         }
@@ -1120,7 +1152,7 @@ mod tests {
             NodeId(0),
             RetryInfo {
                 next_retry_at: now.checked_add_signed(chrono::Duration::hours(1)),
-                retry_duration_seconds: WALCONNECTION_RETRY_MAX_BACKOFF_SECONDS,
+                failed_attempts: 100,
             },
         )]);
 
@@ -1361,6 +1393,7 @@ mod tests {
                 auth_token: None,
                 availability_zone: None,
                 ingest_batch_size: 1,
+                compression: false,
             },
             wal_connection: None,
             wal_stream_candidates: HashMap::new(),
@@ -1438,4 +1471,28 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn standby_horizon_of_all_safekeepers() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("standby_horizon_of_all_safekeepers")?;
+        let mut state = dummy_state(&harness).await;
+        let now = Utc::now().naive_utc();
+
+        let update = |safekeeper_id, standby_horizon| SafekeeperTimelineInfo {
+            safekeeper_id,
+            standby_horizon,
+            ..dummy_broker_sk_timeline(0, DUMMY_SAFEKEEPER_HOST, now).timeline
+        };
+        state.register_timeline_update(update(1, 0x3000));
+        // no replicas on this one
+        state.register_timeline_update(update(2, 0));
+        state.register_timeline_update(update(3, 0x2000));
+        assert_eq!(state.timeline.standby_horizon.load(), Lsn(0x2000));
+
+        // the replica disconnected
+        state.register_timeline_update(update(3, 0));
+        assert_eq!(state.timeline.standby_horizon.load(), Lsn(0x3000));
+
+        Ok(())
+    }
 }
@@ -9,7 +9,7 @@ use std::{
 };
 
 use anyhow::{anyhow, Context};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use chrono::{NaiveDateTime, Utc};
 use fail::fail_point;
 use futures::StreamExt;
@@ -37,6 +37,7 @@ use crate::{
 use postgres_backend::is_expected_io_error;
 use postgres_connection::PgConnectionConfig;
 use postgres_ffi::waldecoder::WalStreamDecoder;
+use safekeeper_api::{negotiate_replication_protocol, WalCompression};
 use utils::pageserver_feedback::PageserverFeedback;
 use utils::{id::NodeId, lsn::Lsn};
 
@@ -116,6 +117,7 @@ pub(super) async fn handle_walreceiver_connection(
     ctx: RequestContext,
     node: NodeId,
     ingest_batch_size: u64,
+    compression: bool,
 ) -> Result<(), WalReceiverError> {
     debug_assert_current_span_has_tenant_and_timeline_id();
 
@@ -221,6 +223,16 @@ pub(super) async fn handle_walreceiver_connection(
         return Ok(());
     }
 
+    // A safekeeper may be pushing WAL to us while the broker is unreachable.
+    // It ends the push once the broker is back, wait for that.
+    let _ingest_guard = select! {
+        _ = cancellation.cancelled() => {
+            debug!("walreceiver interrupted while waiting for the WAL push to end");
+            return Ok(());
+        }
+        guard = timeline.wal_ingest_lock.lock() => guard,
+    };
+
     //
     // Start streaming the WAL, from where we left off previously.
     //
@@ -246,7 +258,24 @@ pub(super) async fn handle_walreceiver_connection(
 
     info!("last_record_lsn {last_rec_lsn} starting replication from {startpoint}, safekeeper is at {end_of_wal}...");
 
-    let query = format!("START_REPLICATION PHYSICAL {startpoint}");
+    let proto_version = negotiate_replication_protocol(identify.proto_version);
+    // Compression needs version 2, older safekeepers stream uncompressed WAL.
+    let compression = (compression && proto_version >= 2).then_some(WalCompression::Zstd);
+    let mut options = Vec::new();
+    if proto_version > 1 {
+        options.push(format!("proto_version '{proto_version}'"));
+    }
+    if let Some(compression) = compression {
+        options.push(format!("compression '{compression}'"));
+    }
+    let query = if options.is_empty() {
+        format!("START_REPLICATION PHYSICAL {startpoint}")
+    } else {
+        format!(
+            "START_REPLICATION PHYSICAL {startpoint} ({})",
+            options.join(", ")
+        )
+    };
 
     let copy_stream = replication_client.copy_both_simple(&query).await?;
     let mut physical_stream = pin!(ReplicationStream::new(copy_stream));
@@ -266,6 +295,16 @@ pub(super) async fn handle_walreceiver_connection(
     } {
         let replication_message = replication_message?;
 
+        // With compression, the data of each XLogData is a zstd frame of the WAL.
+        let wal = match &replication_message {
+            ReplicationMessage::XLogData(xlog_data) if compression.is_some() => Bytes::from(
+                zstd::stream::decode_all(xlog_data.data().as_ref())
+                    .context("failed to decompress WAL")?,
+            ),
+            ReplicationMessage::XLogData(xlog_data) => xlog_data.data().clone(),
+            _ => Bytes::new(),
+        };
+
         let now = Utc::now().naive_utc();
         let last_rec_lsn_before_msg = last_rec_lsn;
 
@@ -287,10 +326,9 @@ pub(super) async fn handle_walreceiver_connection(
 
                 connection_status.latest_connection_update = now;
                 connection_status.commit_lsn = Some(Lsn::from(xlog_data.wal_end()));
-                connection_status.streaming_lsn = Some(Lsn::from(
-                    xlog_data.wal_start() + xlog_data.data().len() as u64,
-                ));
-                if !xlog_data.data().is_empty() {
+                connection_status.streaming_lsn =
+                    Some(Lsn::from(xlog_data.wal_start() + wal.len() as u64));
+                if !wal.is_empty() {
                     connection_status.latest_wal_update = now;
                 }
             }
@@ -309,12 +347,13 @@ pub(super) async fn handle_walreceiver_connection(
             ReplicationMessage::XLogData(xlog_data) => {
                 // Pass the WAL data to the decoder, and see if we can decode
                 // more records as a result.
-                let data = xlog_data.data();
+                let data = &wal;
                 let startlsn = Lsn::from(xlog_data.wal_start());
                 let endlsn = startlsn + data.len() as u64;
 
                 trace!("received XLogData between {startlsn} and {endlsn}");
 
+                timeline.usage_counters.record_ingest(data.len() as u64);
                 waldecoder.feed_bytes(data);
 
                 {
@@ -470,6 +509,9 @@ struct IdentifySystem {
     timeline: u32,
     xlogpos: PgLsn,
     dbname: Option<String>,
+    /// Highest replication protocol version the safekeeper speaks, absent if
+    /// it predates the versioning.
+    proto_version: Option<u32>,
 }
 
 /// There was a problem parsing the response to
@@ -500,6 +542,7 @@ async fn identify_system(client: &mut Client) -> anyhow::Result<IdentifySystem>
             timeline: get_parse(first_row, 1)?,
             xlogpos: get_parse(first_row, 2)?,
             dbname: get_parse(first_row, 3).ok(),
+            proto_version: get_parse(first_row, 4).ok(),
         })
     } else {
         Err(IdentifyError.into())
@@ -0,0 +1,212 @@
+//! Finding the tenants that load the pageserver the most, for the
+//! `/v1/debug/top_tenants` endpoint.
+//!
+//! Every timeline counts the GetPage requests it serves and the WAL bytes it
+//! ingests in its [`UsageCounters`]. [`sampling_task`] samples the counters of
+//! all tenants every [`SAMPLE_PERIOD`] and keeps the samples of the last
+//! [`WINDOW`]. The usage of a tenant over the window is the difference between
+//! its current counters and its oldest sample; a tenant loaded during the window
+//! counts everything since it was loaded.
+//!
+//! The physical size is not windowed, it is the current size of the layer files.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use pageserver_api::models::{TopTenantInfo, TopTenants, TopTenantsBy};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+use utils::id::TenantId;
+
+use crate::tenant::{mgr, Tenant};
+
+pub const SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+pub const WINDOW: Duration = Duration::from_secs(300);
+
+/// Keeping one more sample than fits in the window, so the oldest one is a full window old.
+const MAX_SAMPLES: usize = (WINDOW.as_secs() / SAMPLE_PERIOD.as_secs()) as usize + 1;
+
+/// Cumulative usage counters of a timeline.
+#[derive(Default)]
+pub struct UsageCounters {
+    reads: AtomicU64,
+    ingested_bytes: AtomicU64,
+}
+
+impl UsageCounters {
+    pub fn record_read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_ingest(&self, bytes: u64) {
+        self.ingested_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Usage {
+    reads: u64,
+    ingested_bytes: u64,
+}
+
+struct Sample {
+    taken_at: Instant,
+    usage: HashMap<TenantId, Usage>,
+}
+
+static SAMPLES: Lazy<Mutex<VecDeque<Sample>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+fn tenant_usage(tenant: &Tenant) -> Usage {
+    let mut usage = Usage::default();
+    for timeline in tenant.list_timelines() {
+        usage.reads += timeline.usage_counters.reads.load(Ordering::Relaxed);
+        usage.ingested_bytes += timeline
+            .usage_counters
+            .ingested_bytes
+            .load(Ordering::Relaxed);
+    }
+    usage
+}
+
+async fn all_tenants() -> Vec<Arc<Tenant>> {
+    let tenant_ids = match mgr::list_tenants().await {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            warn!("failed to list tenants: {e:#}");
+            return Vec::new();
+        }
+    };
+    let mut tenants = Vec::with_capacity(tenant_ids.len());
+    for (tenant_id, _) in tenant_ids {
+        // The tenant may have been detached since it was listed
+        if let Ok(tenant) = mgr::get_tenant(tenant_id, false).await {
+            tenants.push(tenant);
+        }
+    }
+    tenants
+}
+
+/// Samples the usage counters of all tenants every [`SAMPLE_PERIOD`].
+pub async fn sampling_task(cancel: CancellationToken) -> anyhow::Result<()> {
+    loop {
+        let usage = all_tenants()
+            .await
+            .iter()
+            .map(|tenant| (tenant.tenant_id(), tenant_usage(tenant)))
+            .collect();
+        {
+            let mut samples = SAMPLES.lock().unwrap();
+            samples.push_back(Sample {
+                taken_at: Instant::now(),
+                usage,
+            });
+            while samples.len() > MAX_SAMPLES {
+                samples.pop_front();
+            }
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(SAMPLE_PERIOD) => {}
+        }
+    }
+}
+
+/// Returns the `k` heaviest tenants by the given dimension.
+pub async fn top_tenants(by: TopTenantsBy, k: usize) -> TopTenants {
+    let mut current = Vec::new();
+    for tenant in all_tenants().await {
+        let mut physical_size = 0;
+        for timeline in tenant.list_timelines() {
+            physical_size += timeline.layer_size_sum().await;
+        }
+        current.push((tenant.tenant_id(), tenant_usage(&tenant), physical_size));
+    }
+
+    let samples = SAMPLES.lock().unwrap();
+    let (window, baseline) = match samples.front() {
+        Some(oldest) => (oldest.taken_at.elapsed(), Some(&oldest.usage)),
+        None => (Duration::ZERO, None),
+    };
+    TopTenants {
+        by,
+        window_secs: window.as_secs(),
+        tenants: rank(by, k, current, baseline),
+    }
+}
+
+fn rank(
+    by: TopTenantsBy,
+    k: usize,
+    current: Vec<(TenantId, Usage, u64)>,
+    baseline: Option<&HashMap<TenantId, Usage>>,
+) -> Vec<TopTenantInfo> {
+    let mut tenants = current
+        .into_iter()
+        .map(|(id, usage, physical_size)| {
+            let before = baseline
+                .and_then(|baseline| baseline.get(&id))
+                .copied()
+                .unwrap_or_default();
+            // The counters of deleted timelines are gone, so the usage can go down
+            TopTenantInfo {
+                id,
+                reads: usage.reads.saturating_sub(before.reads),
+                ingested_bytes: usage.ingested_bytes.saturating_sub(before.ingested_bytes),
+                physical_size,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    tenants.sort_by_key(|tenant| {
+        std::cmp::Reverse(match by {
+            TopTenantsBy::Reads => tenant.reads,
+            TopTenantsBy::Ingest => tenant.ingested_bytes,
+            TopTenantsBy::PhysicalSize => tenant.physical_size,
+        })
+    });
+    tenants.truncate(k);
+    tenants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_by_usage_over_window() {
+        let ids = [
+            TenantId::generate(),
+            TenantId::generate(),
+            TenantId::generate(),
+        ];
+        let usage = |reads, ingested_bytes| Usage {
+            reads,
+            ingested_bytes,
+        };
+        let current = vec![
+            (ids[0], usage(1000, 500), 10),
+            (ids[1], usage(300, 9000), 30),
+            // Loaded during the window, counts everything since it was loaded
+            (ids[2], usage(400, 0), 20),
+        ];
+        let baseline = HashMap::from([(ids[0], usage(900, 100)), (ids[1], usage(0, 1000))]);
+
+        let ranked = |by, k| {
+            rank(by, k, current.clone(), Some(&baseline))
+                .into_iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranked(TopTenantsBy::Reads, 3), [ids[2], ids[1], ids[0]]);
+        assert_eq!(ranked(TopTenantsBy::Ingest, 3), [ids[1], ids[0], ids[2]]);
+        assert_eq!(ranked(TopTenantsBy::PhysicalSize, 2), [ids[1], ids[2]]);
+
+        let top = rank(TopTenantsBy::Reads, 1, current, Some(&baseline));
+        assert_eq!(top[0].reads, 400);
+        assert_eq!(top[0].ingested_bytes, 0);
+    }
+}
@@ -18,6 +18,7 @@ use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{RwLock, RwLockWriteGuard};
+use utils::checksummed_writer::{preallocate_file, SyncFile};
 
 ///
 /// A virtual file descriptor. You can use this just like std::fs::File, but internally
@@ -241,6 +242,11 @@ impl VirtualFile {
         self.with_file("fsync", |file| file.sync_all())?
     }
 
+    /// Call File::sync_data() on the underlying File.
+    pub fn sync_data(&self) -> Result<(), Error> {
+        self.with_file("fdatasync", |file| file.sync_data())?
+    }
+
     pub fn metadata(&self) -> Result<fs::Metadata, Error> {
         self.with_file("metadata", |file| file.metadata())?
     }
@@ -416,6 +422,20 @@ impl FileExt for VirtualFile {
     }
 }
 
+impl SyncFile for VirtualFile {
+    fn sync_data(&self) -> Result<(), Error> {
+        VirtualFile::sync_data(self)
+    }
+
+    fn sync_all(&self) -> Result<(), Error> {
+        VirtualFile::sync_all(self)
+    }
+
+    fn preallocate(&self, len: u64) -> Result<(), Error> {
+        self.with_file("fallocate", |file| preallocate_file(file, len))?
+    }
+}
+
 impl OpenFiles {
     fn new(num_slots: usize) -> OpenFiles {
         let mut slots = Box::new(Vec::with_capacity(num_slots));
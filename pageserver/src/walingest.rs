@@ -31,6 +31,7 @@ use bytes::{Buf, Bytes, BytesMut};
 use tracing::*;
 
 use crate::context::RequestContext;
+use crate::metrics::WAL_INGEST_SKIPPED_BLOCKS;
 use crate::pgdatadir_mapping::*;
 use crate::tenant::PageReconstructError;
 use crate::tenant::Timeline;
@@ -50,6 +51,8 @@ use utils::lsn::Lsn;
 pub struct WalIngest {
     checkpoint: CheckPoint,
     checkpoint_modified: bool,
+    /// See [`Timeline::ingests_relation_data`].
+    ingest_relation_data: bool,
 }
 
 impl WalIngest {
@@ -64,9 +67,15 @@ impl WalIngest {
         let checkpoint = CheckPoint::decode(&checkpoint_bytes)?;
         trace!("CheckPoint.nextXid = {}", checkpoint.nextXid.value);
 
+        let ingest_relation_data = timeline.ingests_relation_data();
+        if !ingest_relation_data {
+            timeline.mark_relation_data_skipped(startpoint)?;
+        }
+
         Ok(WalIngest {
             checkpoint,
             checkpoint_modified: false,
+            ingest_relation_data,
         })
     }
 
@@ -86,6 +95,8 @@ impl WalIngest {
         decoded: &mut DecodedWALRecord,
         ctx: &RequestContext,
     ) -> anyhow::Result<()> {
+        utils::failpoint_async!("wal-ingest-record", |msg| anyhow::bail!(msg));
+
         let pg_version = modification.tline.pg_version;
 
         modification.set_lsn(lsn)?;
@@ -101,8 +112,9 @@ impl WalIngest {
 
         // Heap AM records need some special handling, because they modify VM pages
         // without registering them with the standard mechanism.
-        if decoded.xl_rmid == pg_constants::RM_HEAP_ID
-            || decoded.xl_rmid == pg_constants::RM_HEAP2_ID
+        if self.ingest_relation_data
+            && (decoded.xl_rmid == pg_constants::RM_HEAP_ID
+                || decoded.xl_rmid == pg_constants::RM_HEAP2_ID)
         {
             self.ingest_heapam_record(&mut buf, modification, decoded, ctx)
                 .await?;
@@ -288,6 +300,8 @@ impl WalIngest {
                 let mut checkpoint_bytes = [0u8; SIZEOF_CHECKPOINT];
                 buf.copy_to_slice(&mut checkpoint_bytes);
                 let xlog_checkpoint = CheckPoint::decode(&checkpoint_bytes)?;
+                // The computes start after the checkpoint from now on
+                modification.tline.basebackup_cache.clear();
                 trace!(
                     "xlog_checkpoint.oldestXid={}, checkpoint.oldestXid={}",
                     xlog_checkpoint.oldestXid,
@@ -311,6 +325,11 @@ impl WalIngest {
                 // we could peek into the message and only pause if it contains
                 // a particular string, for example, but this is enough for now.
                 utils::failpoint_sleep_millis_async!("wal-ingest-logical-message-sleep");
+
+                let xlrec =
+                    XlLogicalMessage::decode(&mut buf).context("decode logical message record")?;
+                self.ingest_logical_message(&xlrec, &buf, modification, ctx)
+                    .await?;
             }
         } else if decoded.xl_rmid == pg_constants::RM_CSNLOG_ID {
             let info = decoded.xl_info & !pg_constants::XLR_INFO_MASK;
@@ -338,10 +357,23 @@ impl WalIngest {
         }
 
         // Iterate through all the blocks that the record modifies, and
-        // "put" a separate copy of the record for each block.
+        // "put" a separate copy of the record for each block. If the relation data
+        // isn't ingested, only keep track of the relation sizes.
         for blk in decoded.blocks.iter() {
-            self.ingest_decoded_block(modification, lsn, decoded, blk, ctx)
-                .await?;
+            if self.ingest_relation_data {
+                self.ingest_decoded_block(modification, lsn, decoded, blk, ctx)
+                    .await?;
+            } else {
+                let rel = RelTag {
+                    spcnode: blk.rnode_spcnode,
+                    dbnode: blk.rnode_dbnode,
+                    relnode: blk.rnode_relnode,
+                    forknum: blk.forknum,
+                };
+                self.handle_rel_extend(modification, rel, blk.blkno, ctx)
+                    .await?;
+                WAL_INGEST_SKIPPED_BLOCKS.inc();
+            }
         }
 
         // If checkpoint data was updated, store the new version in the repository
@@ -628,6 +660,11 @@ impl WalIngest {
             };
 
             modification.put_rel_creation(dst_rel, nblocks, ctx).await?;
+            if !self.ingest_relation_data {
+                // the source pages may be missing, and the copies would never be read
+                num_rels_copied += 1;
+                continue;
+            }
 
             // Copy content
             debug!("copying rel {} to {}, {} blocks", src_rel, dst_rel, nblocks);
@@ -1064,6 +1101,47 @@ impl WalIngest {
         Ok(())
     }
 
+    /// Handles a logical message. The compute WAL-logs changes to the files that
+    /// hold replication slot state as logical messages with the prefix
+    /// `neon-file:<path>`, and the new contents of the file as the message. An
+    /// empty message means that the file was removed.
+    async fn ingest_logical_message(
+        &mut self,
+        xlrec: &XlLogicalMessage,
+        buf: &Bytes,
+        modification: &mut DatadirModification<'_>,
+        ctx: &RequestContext,
+    ) -> Result<()> {
+        let Some(prefix) = buf.get(..xlrec.prefix_size) else {
+            warn!(
+                "truncated logical message record, prefix size {}",
+                xlrec.prefix_size
+            );
+            return Ok(());
+        };
+        // The prefix is null-terminated
+        let prefix = prefix.strip_suffix(&[0]).unwrap_or(prefix);
+        let Some(path) = prefix.strip_prefix(b"neon-file:") else {
+            return Ok(());
+        };
+        let path = std::str::from_utf8(path).context("non-UTF8 aux file path")?;
+        if !is_valid_aux_file_path(path) {
+            warn!("ignoring logical message for unexpected aux file path {path:?}");
+            return Ok(());
+        }
+        let Some(end) = xlrec.prefix_size.checked_add(xlrec.message_size) else {
+            warn!(
+                "invalid logical message record, prefix size {}, message size {}",
+                xlrec.prefix_size, xlrec.message_size
+            );
+            return Ok(());
+        };
+        let content = buf
+            .get(xlrec.prefix_size..end)
+            .context("truncated logical message record")?;
+        modification.put_aux_file(path, content, ctx).await
+    }
+
     async fn ingest_relmap_page(
         &mut self,
         modification: &mut DatadirModification<'_>,
@@ -1219,9 +1297,11 @@ impl WalIngest {
             //info!("extending {} {} to {}", rel, old_nblocks, new_nblocks);
             modification.put_rel_extend(rel, new_nblocks, ctx).await?;
 
-            // fill the gap with zeros
-            for gap_blknum in old_nblocks..blknum {
-                modification.put_rel_page_image(rel, gap_blknum, ZERO_PAGE.clone())?;
+            // fill the gap with zeros, if the pages are stored at all
+            if self.ingest_relation_data {
+                for gap_blknum in old_nblocks..blknum {
+                    modification.put_rel_page_image(rel, gap_blknum, ZERO_PAGE.clone())?;
+                }
             }
         }
         Ok(())
@@ -1322,6 +1402,7 @@ mod tests {
     use crate::tenant::Timeline;
     use postgres_ffi::v14::xlog_utils::SIZEOF_CHECKPOINT;
     use postgres_ffi::RELSEG_SIZE;
+    use tokio_util::sync::CancellationToken;
     use utils::id::RegionId;
 
     use crate::DEFAULT_PG_VERSION;
@@ -1865,4 +1946,87 @@ mod tests {
 
         Ok(())
     }
+
+    /// Test that the relations of a timeline that doesn't ingest its relation data
+    /// are extended without page versions, and that compaction still creates image
+    /// layers for it.
+    #[tokio::test]
+    async fn test_skipped_relation_data_compaction() -> Result<()> {
+        let mut harness = TenantHarness::create("test_skipped_relation_data_compaction")?;
+        let mut conf = harness.conf.clone();
+        conf.ingest_regions = vec![RegionId(1)];
+        harness.conf = Box::leak(Box::new(conf));
+        harness.tenant_conf.image_creation_threshold = 1;
+        let (tenant, ctx) = harness.load().await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(8), DEFAULT_PG_VERSION, RegionId(0), &ctx)
+            .await?;
+        assert!(!tline.ingests_relation_data());
+        let mut walingest = init_walingest_test(&tline, &ctx).await?;
+
+        let mut lsn = 0x10;
+        for blknum in [0, 5, 9] {
+            lsn += 0x10;
+            let mut m = tline.begin_modification(Lsn(lsn));
+            walingest
+                .handle_rel_extend(&mut m, TESTREL_A, blknum, &ctx)
+                .await?;
+            m.commit().await?;
+
+            tline.freeze_and_flush().await?;
+            tline.compact(&CancellationToken::new(), &ctx).await?;
+        }
+
+        assert_eq!(
+            tline
+                .get_rel_size(TESTREL_A, Version::Lsn(Lsn(lsn)), false, &ctx)
+                .await?,
+            10
+        );
+        let guard = tline.layers.read().await;
+        assert!(guard
+            .layer_map()
+            .iter_historic_layers()
+            .any(|layer| !layer.is_delta()));
+
+        Ok(())
+    }
+
+    /// Test that a timeline whose relation data was skipped keeps refusing the
+    /// page reads after its region is ingested again, and so do its branches.
+    #[tokio::test]
+    async fn test_skipped_relation_data_marker() -> Result<()> {
+        let mut harness = TenantHarness::create("test_skipped_relation_data_marker")?;
+        let conf = harness.conf;
+        let mut skipping_conf = conf.clone();
+        skipping_conf.ingest_regions = vec![RegionId(1)];
+        harness.conf = Box::leak(Box::new(skipping_conf));
+        {
+            let (tenant, ctx) = harness.load().await;
+            let tline = tenant
+                .create_test_timeline(TIMELINE_ID, Lsn(8), DEFAULT_PG_VERSION, RegionId(0), &ctx)
+                .await?;
+            init_walingest_test(&tline, &ctx).await?;
+            tline.freeze_and_flush().await?;
+            assert!(harness
+                .conf
+                .timeline_relation_data_skipped_path(&harness.tenant_id, &TIMELINE_ID)
+                .is_file());
+
+            let child = tenant
+                .branch_timeline_test(&tline, NEW_TIMELINE_ID, Some(Lsn(0x10)), RegionId(0), &ctx)
+                .await?;
+            assert!(!child.ingests_relation_data());
+        }
+
+        harness.conf = conf;
+        let (tenant, _ctx) = harness.load().await;
+        let tline = tenant.get_timeline(TIMELINE_ID, true)?;
+        assert!(tline.region_ingested());
+        assert!(!tline.ingests_relation_data());
+        let child = tenant.get_timeline(NEW_TIMELINE_ID, true)?;
+        assert!(!child.ingests_relation_data());
+
+        Ok(())
+    }
 }
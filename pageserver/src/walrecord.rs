@@ -2,7 +2,7 @@
 //! Functions for parsing WAL records.
 //!
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use bytes::{Buf, Bytes};
 use postgres_ffi::pg_constants;
 use postgres_ffi::BLCKSZ;
@@ -102,12 +102,19 @@ pub struct DecodedBkpBlock {
     /* Buffer holding the rmgr-specific data associated with this block */
     has_data: bool,
     data_len: u16,
+    data_offset: u32,
 }
 
 impl DecodedBkpBlock {
     pub fn new() -> DecodedBkpBlock {
         Default::default()
     }
+
+    /// The rmgr-specific data of the block, in the record it was decoded from.
+    pub fn data<'a>(&self, record: &'a [u8]) -> &'a [u8] {
+        let offset = self.data_offset as usize;
+        &record[offset..offset + self.data_len as usize]
+    }
 }
 
 #[derive(Default)]
@@ -313,7 +320,7 @@ impl XlHeapUpdate {
             old_offnum: buf.get_u16_le(),
             old_infobits_set: buf.get_u8(),
             flags: buf.get_u8(),
-            t_cid: buf.get_u32(),
+            t_cid: buf.get_u32_le(),
             new_xmax: buf.get_u32_le(),
             new_offnum: buf.get_u16_le(),
         }
@@ -517,6 +524,35 @@ impl XlMultiXactTruncate {
     }
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct XlLogicalMessage {
+    pub db_id: Oid,
+    pub transactional: bool,
+    pub prefix_size: usize,
+    pub message_size: usize,
+}
+
+impl XlLogicalMessage {
+    pub fn decode(buf: &mut Bytes) -> Result<XlLogicalMessage> {
+        ensure_remaining(buf, 24)?;
+        let db_id = buf.get_u32_le();
+        let transactional = buf.get_u8() != 0;
+        buf.advance(3); // padding, not initialized by postgres
+        Ok(XlLogicalMessage {
+            db_id,
+            transactional,
+            prefix_size: buf.get_u64_le() as usize,
+            message_size: buf.get_u64_le() as usize,
+        })
+    }
+}
+
+fn ensure_remaining(buf: &Bytes, len: usize) -> Result<()> {
+    ensure!(buf.remaining() >= len, "record is truncated");
+    Ok(())
+}
+
 /// Main routine to decode a WAL record and figure out which blocks are modified
 //
 // See xlogrecord.h for details
@@ -565,13 +601,20 @@ pub fn decode_wal_record(
         xlogrec.xl_info
     );
 
+    ensure!(
+        xlogrec.xl_tot_len as usize >= XLOG_SIZE_OF_XLOG_RECORD,
+        "invalid xl_tot_len {}",
+        xlogrec.xl_tot_len
+    );
     let remaining: usize = xlogrec.xl_tot_len as usize - XLOG_SIZE_OF_XLOG_RECORD;
+    ensure!(
+        buf.remaining() == remaining,
+        "record length {} doesn't match xl_tot_len {}",
+        record.len(),
+        xlogrec.xl_tot_len
+    );
 
-    if buf.remaining() != remaining {
-        //TODO error
-    }
-
-    let mut max_block_id = 0;
+    let mut max_block_id = None;
     let mut blocks_total_len: u32 = 0;
     let mut main_data_len = 0;
     let mut datatotal: u32 = 0;
@@ -586,23 +629,29 @@ pub fn decode_wal_record(
         match block_id {
             pg_constants::XLR_BLOCK_ID_DATA_SHORT => {
                 /* XLogRecordDataHeaderShort */
+                ensure_remaining(&buf, 1)?;
                 main_data_len = buf.get_u8() as u32;
                 datatotal += main_data_len;
             }
 
             pg_constants::XLR_BLOCK_ID_DATA_LONG => {
                 /* XLogRecordDataHeaderLong */
+                ensure_remaining(&buf, 4)?;
                 main_data_len = buf.get_u32_le();
-                datatotal += main_data_len;
+                datatotal = datatotal
+                    .checked_add(main_data_len)
+                    .context("invalid main data length")?;
             }
 
             pg_constants::XLR_BLOCK_ID_ORIGIN => {
                 // RepOriginId is uint16
+                ensure_remaining(&buf, 2)?;
                 buf.advance(2);
             }
 
             pg_constants::XLR_BLOCK_ID_TOPLEVEL_XID => {
                 // TransactionId is uint32
+                ensure_remaining(&buf, 4)?;
                 buf.advance(4);
             }
 
@@ -610,17 +659,12 @@ pub fn decode_wal_record(
                 /* XLogRecordBlockHeader */
                 let mut blk = DecodedBkpBlock::new();
 
-                if block_id <= max_block_id {
-                    // TODO
-                    //report_invalid_record(state,
-                    //			  "out-of-order block_id %u at %X/%X",
-                    //			  block_id,
-                    //			  (uint32) (state->ReadRecPtr >> 32),
-                    //			  (uint32) state->ReadRecPtr);
-                    //    goto err;
+                if max_block_id.map_or(false, |max| block_id <= max) {
+                    bail!("out-of-order block_id {block_id}");
                 }
-                max_block_id = block_id;
+                max_block_id = Some(block_id);
 
+                ensure_remaining(&buf, 3)?;
                 let fork_flags: u8 = buf.get_u8();
                 blk.forknum = fork_flags & pg_constants::BKPBLOCK_FORK_MASK;
                 blk.flags = fork_flags;
@@ -629,21 +673,27 @@ pub fn decode_wal_record(
                 blk.will_init = (fork_flags & pg_constants::BKPBLOCK_WILL_INIT) != 0;
                 blk.data_len = buf.get_u16_le();
 
-                /* TODO cross-check that the HAS_DATA flag is set iff data_length > 0 */
+                if blk.has_data != (blk.data_len > 0) {
+                    bail!(
+                        "BKPBLOCK_HAS_DATA is {}, but data length is {}",
+                        blk.has_data,
+                        blk.data_len
+                    );
+                }
 
                 datatotal += blk.data_len as u32;
                 blocks_total_len += blk.data_len as u32;
 
                 if blk.has_image {
+                    ensure_remaining(&buf, 5)?;
                     blk.bimg_len = buf.get_u16_le();
                     blk.hole_offset = buf.get_u16_le();
                     blk.bimg_info = buf.get_u8();
 
-                    blk.apply_image = if pg_version == 14 {
-                        (blk.bimg_info & postgres_ffi::v14::bindings::BKPIMAGE_APPLY) != 0
-                    } else {
-                        assert_eq!(pg_version, 15);
-                        (blk.bimg_info & postgres_ffi::v15::bindings::BKPIMAGE_APPLY) != 0
+                    blk.apply_image = match pg_version {
+                        14 => (blk.bimg_info & postgres_ffi::v14::bindings::BKPIMAGE_APPLY) != 0,
+                        15 => (blk.bimg_info & postgres_ffi::v15::bindings::BKPIMAGE_APPLY) != 0,
+                        _ => bail!("unknown pg_version {pg_version}"),
                     };
 
                     let blk_img_is_compressed =
@@ -653,8 +703,12 @@ pub fn decode_wal_record(
                         debug!("compressed block image , pg_version = {}", pg_version);
                     }
 
+                    if blk.bimg_len > BLCKSZ {
+                        bail!("block image length {} exceeds BLCKSZ", blk.bimg_len);
+                    }
                     if blk_img_is_compressed {
                         if blk.bimg_info & pg_constants::BKPIMAGE_HAS_HOLE != 0 {
+                            ensure_remaining(&buf, 2)?;
                             blk.hole_length = buf.get_u16_le();
                         } else {
                             blk.hole_length = 0;
@@ -665,94 +719,57 @@ pub fn decode_wal_record(
                     datatotal += blk.bimg_len as u32;
                     blocks_total_len += blk.bimg_len as u32;
 
-                    /*
-                     * cross-check that hole_offset > 0, hole_length > 0 and
-                     * bimg_len < BLCKSZ if the HAS_HOLE flag is set.
-                     */
+                    // The cross-checks of xlogreader.c.
                     if blk.bimg_info & pg_constants::BKPIMAGE_HAS_HOLE != 0
                         && (blk.hole_offset == 0 || blk.hole_length == 0 || blk.bimg_len == BLCKSZ)
                     {
-                        // TODO
-                        /*
-                        report_invalid_record(state,
-                                      "pg_constants::BKPIMAGE_HAS_HOLE set, but hole offset %u length %u block image length %u at %X/%X",
-                                      (unsigned int) blk->hole_offset,
-                                      (unsigned int) blk->hole_length,
-                                      (unsigned int) blk->bimg_len,
-                                      (uint32) (state->ReadRecPtr >> 32), (uint32) state->ReadRecPtr);
-                        goto err;
-                                     */
+                        bail!(
+                            "BKPIMAGE_HAS_HOLE set, but hole offset {} length {} image length {}",
+                            blk.hole_offset,
+                            blk.hole_length,
+                            blk.bimg_len
+                        );
                     }
-
-                    /*
-                     * cross-check that hole_offset == 0 and hole_length == 0 if
-                     * the HAS_HOLE flag is not set.
-                     */
                     if blk.bimg_info & pg_constants::BKPIMAGE_HAS_HOLE == 0
                         && (blk.hole_offset != 0 || blk.hole_length != 0)
                     {
-                        // TODO
-                        /*
-                        report_invalid_record(state,
-                                      "pg_constants::BKPIMAGE_HAS_HOLE not set, but hole offset %u length %u at %X/%X",
-                                      (unsigned int) blk->hole_offset,
-                                      (unsigned int) blk->hole_length,
-                                      (uint32) (state->ReadRecPtr >> 32), (uint32) state->ReadRecPtr);
-                        goto err;
-                                     */
+                        bail!(
+                            "BKPIMAGE_HAS_HOLE not set, but hole offset {} length {}",
+                            blk.hole_offset,
+                            blk.hole_length
+                        );
                     }
-
-                    /*
-                     * cross-check that bimg_len < BLCKSZ if the IS_COMPRESSED
-                     * flag is set.
-                     */
-                    if !blk_img_is_compressed && blk.bimg_len == BLCKSZ {
-                        // TODO
-                        /*
-                        report_invalid_record(state,
-                                      "pg_constants::BKPIMAGE_IS_COMPRESSED set, but block image length %u at %X/%X",
-                                      (unsigned int) blk->bimg_len,
-                                      (uint32) (state->ReadRecPtr >> 32), (uint32) state->ReadRecPtr);
-                        goto err;
-                                     */
+                    if blk_img_is_compressed && blk.bimg_len == BLCKSZ {
+                        bail!(
+                            "block image is compressed, but its length is {}",
+                            blk.bimg_len
+                        );
                     }
-
-                    /*
-                     * cross-check that bimg_len = BLCKSZ if neither HAS_HOLE nor
-                     * IS_COMPRESSED flag is set.
-                     */
                     if blk.bimg_info & pg_constants::BKPIMAGE_HAS_HOLE == 0
                         && !blk_img_is_compressed
                         && blk.bimg_len != BLCKSZ
                     {
-                        // TODO
-                        /*
-                        report_invalid_record(state,
-                                      "neither pg_constants::BKPIMAGE_HAS_HOLE nor pg_constants::BKPIMAGE_IS_COMPRESSED set, but block image length is %u at %X/%X",
-                                      (unsigned int) blk->data_len,
-                                      (uint32) (state->ReadRecPtr >> 32), (uint32) state->ReadRecPtr);
-                        goto err;
-                                     */
+                        bail!(
+                            "block image has no hole and isn't compressed, but its length is {}",
+                            blk.bimg_len
+                        );
                     }
                 }
                 if fork_flags & pg_constants::BKPBLOCK_SAME_REL == 0 {
+                    ensure_remaining(&buf, 12)?;
                     rnode_spcnode = buf.get_u32_le();
                     rnode_dbnode = buf.get_u32_le();
                     rnode_relnode = buf.get_u32_le();
                     got_rnode = true;
                 } else if !got_rnode {
-                    // TODO
-                    /*
-                    report_invalid_record(state,
-                                    "pg_constants::BKPBLOCK_SAME_REL set but no previous rel at %X/%X",
-                                    (uint32) (state->ReadRecPtr >> 32), (uint32) state->ReadRecPtr);
-                    goto err;           */
+                    bail!("BKPBLOCK_SAME_REL set but no previous rel");
                 }
 
                 blk.rnode_spcnode = rnode_spcnode;
                 blk.rnode_dbnode = rnode_dbnode;
                 blk.rnode_relnode = rnode_relnode;
 
+                ensure_remaining(&buf, 4)?;
                 blk.blkno = buf.get_u32_le();
                 trace!(
                     "this record affects {}/{}/{} blk {}",
@@ -765,11 +782,16 @@ pub fn decode_wal_record(
                 decoded.blocks.push(blk);
             }
 
-            _ => {
-                // TODO: invalid block_id
-            }
+            _ => bail!("invalid block_id {block_id}"),
         }
     }
+    // The block and main data must take exactly the rest of the record
+    ensure!(
+        buf.remaining() == datatotal as usize,
+        "record data length {} doesn't match the headers, which add up to {}",
+        buf.remaining(),
+        datatotal
+    );
 
     // 3. Decode blocks.
     let mut ptr = record.len() - buf.remaining();
@@ -779,6 +801,7 @@ pub fn decode_wal_record(
             ptr += blk.bimg_len as usize;
         }
         if blk.has_data {
+            blk.data_offset = ptr as u32;
             ptr += blk.data_len as usize;
         }
     }
@@ -789,9 +812,12 @@ pub fn decode_wal_record(
     let main_data_offset = (xlogrec.xl_tot_len - main_data_len) as usize;
 
     // 4. Decode main_data
-    if main_data_len > 0 {
-        assert_eq!(buf.remaining(), main_data_len as usize);
-    }
+    ensure!(
+        buf.remaining() == main_data_len as usize,
+        "main data length {} doesn't match its header {}",
+        buf.remaining(),
+        main_data_len
+    );
 
     decoded.xl_xid = xlogrec.xl_xid;
     decoded.xl_info = xlogrec.xl_info;
@@ -880,3 +906,127 @@ fn describe_postgres_wal_record(record: &Bytes) -> Result<String, DeserializeErr
 
     Ok(String::from(result))
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+
+    use super::*;
+
+    /// A record modifying a block with some data, and with main data.
+    fn test_record() -> Bytes {
+        let block_data = [1u8; 40];
+        let main_data = [2u8; 20];
+        let mut body = BytesMut::new();
+        body.put_u8(0); // block_id
+        body.put_u8(pg_constants::BKPBLOCK_HAS_DATA);
+        body.put_u16_le(block_data.len() as u16);
+        body.put_u32_le(1663); // spcnode
+        body.put_u32_le(16384); // dbnode
+        body.put_u32_le(16385); // relnode
+        body.put_u32_le(7); // blkno
+        body.put_u8(pg_constants::XLR_BLOCK_ID_DATA_SHORT);
+        body.put_u8(main_data.len() as u8);
+        body.put_slice(&block_data);
+        body.put_slice(&main_data);
+
+        let mut record = BytesMut::new();
+        record.put_u32_le((XLOG_SIZE_OF_XLOG_RECORD + body.len()) as u32); // xl_tot_len
+        record.put_u32_le(730); // xl_xid
+        record.put_u64_le(0x16B3D30); // xl_prev
+        record.put_u8(0); // xl_info
+        record.put_u8(pg_constants::RM_HEAP_ID);
+        record.put_u16_le(0); // padding
+        record.put_u32_le(0); // xl_crc
+        record.put_slice(&body);
+        record.freeze()
+    }
+
+    #[test]
+    fn test_decode_wal_record() {
+        let record = test_record();
+        let mut decoded = DecodedWALRecord::default();
+        decode_wal_record(record.clone(), &mut decoded, 15).unwrap();
+        assert_eq!(decoded.xl_xid, 730);
+        assert_eq!(decoded.xl_rmid, pg_constants::RM_HEAP_ID);
+        assert_eq!(decoded.blocks.len(), 1);
+        assert_eq!(decoded.blocks[0].rnode_relnode, 16385);
+        assert_eq!(decoded.blocks[0].blkno, 7);
+        assert_eq!(decoded.blocks[0].data(&record), &[1u8; 40]);
+        assert_eq!(decoded.main_data_offset, record.len() - 20);
+    }
+
+    #[test]
+    fn test_decode_malformed_wal_record() {
+        let record = test_record();
+        let mut decoded = DecodedWALRecord::default();
+        for len in 0..record.len() {
+            assert!(decode_wal_record(record.slice(..len), &mut decoded, 15).is_err());
+        }
+        // Corrupted records may happen to be valid, but must not panic
+        for i in 0..record.len() {
+            let mut corrupted = record.to_vec();
+            corrupted[i] ^= 0xFF;
+            let _ = decode_wal_record(corrupted.into(), &mut decoded, 15);
+        }
+    }
+
+    #[test]
+    fn test_decode_logical_message() {
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(5); // db_id
+        buf.put_u32_le(1); // transactional
+        buf.put_u64_le(10); // prefix_size
+        buf.put_u64_le(u64::MAX); // message_size
+        let buf = buf.freeze();
+
+        let xlrec = XlLogicalMessage::decode(&mut buf.clone()).unwrap();
+        assert_eq!(xlrec.db_id, 5);
+        assert!(xlrec.transactional);
+        assert_eq!(xlrec.prefix_size, 10);
+        assert_eq!(xlrec.message_size, usize::MAX);
+        for len in 0..buf.len() {
+            assert!(XlLogicalMessage::decode(&mut buf.slice(..len)).is_err());
+        }
+
+        // The padding after the transactional flag may hold garbage
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(5); // db_id
+        buf.put_slice(&[0, 0xAA, 0xBB, 0xCC]); // transactional, padding
+        buf.put_u64_le(10); // prefix_size
+        buf.put_u64_le(20); // message_size
+        let xlrec = XlLogicalMessage::decode(&mut buf.freeze()).unwrap();
+        assert!(!xlrec.transactional);
+        assert_eq!(xlrec.prefix_size, 10);
+        assert_eq!(xlrec.message_size, 20);
+    }
+
+    #[test]
+    fn test_decode_generated_wal() {
+        use postgres_ffi::wal_generator::{WalGenerator, WalGeneratorConfig};
+        use postgres_ffi::waldecoder::WalStreamDecoder;
+
+        for pg_version in [14, 15] {
+            let config = WalGeneratorConfig {
+                pg_version,
+                ..Default::default()
+            };
+            let region = config.regions[0];
+            let mut decoder = WalStreamDecoder::new(config.start_lsn, pg_version);
+            let mut generator = WalGenerator::new(config, 1).unwrap();
+            decoder.feed_bytes(&generator.generate(region, 1024 * 1024));
+
+            let mut decoded = DecodedWALRecord::default();
+            while let Some((_, record)) = decoder.poll_decode().unwrap() {
+                decode_wal_record(record, &mut decoded, pg_version).unwrap();
+                if decoded.xl_rmid == pg_constants::RM_HEAP_ID {
+                    assert!(!decoded.blocks.is_empty());
+                    assert!(decoded
+                        .blocks
+                        .iter()
+                        .all(|blk| blk.rnode_relnode == decoded.blocks[0].rnode_relnode));
+                }
+            }
+        }
+    }
+}
@@ -18,11 +18,15 @@
 //! any WAL records, so that even if an attacker hijacks the Postgres
 //! process, he cannot escape out of it.
 //!
+//! The simplest and most common Postgres WAL records are replayed by the
+//! pageserver itself, without a round trip to the postgres process, see
+//! [`native`].
+//!
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::{BufMut, Bytes, BytesMut};
 use nix::poll::*;
 use serde::Serialize;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
 use std::ops::{Deref, DerefMut};
@@ -39,8 +43,8 @@ use utils::crashsafe::path_with_suffix_extension;
 use utils::{bin_ser::BeSer, id::TenantId, lsn::Lsn, nonblock::set_nonblock};
 
 use crate::metrics::{
-    WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_RECORDS_HISTOGRAM, WAL_REDO_RECORD_COUNTER, WAL_REDO_TIME,
-    WAL_REDO_WAIT_TIME,
+    WAL_REDO_BATCH_SIZE_HISTOGRAM, WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_RECORDS_HISTOGRAM,
+    WAL_REDO_RECORD_COUNTER, WAL_REDO_TIME, WAL_REDO_WAIT_TIME,
 };
 use crate::pgdatadir_mapping::{key_to_rel_block, key_to_slru_block};
 use crate::repository::Key;
@@ -56,6 +60,8 @@ use postgres_ffi::v14::nonrelfile_utils::{
 };
 use postgres_ffi::BLCKSZ;
 
+mod native;
+
 ///
 /// `RelTag` + block number (`blknum`) gives us a unique id of the page in the cluster.
 ///
@@ -87,6 +93,37 @@ pub trait WalRedoManager: Send + Sync {
         records: Vec<(Lsn, NeonWalRecord)>,
         pg_version: u32,
     ) -> Result<Bytes, WalRedoError>;
+
+    /// Apply the WAL records of many pages, see [`WalRedoManager::request_redo`].
+    ///
+    /// The results are in the same order as `requests`. A failure to reconstruct one
+    /// page doesn't fail the others. By default, the requests are applied one at a
+    /// time.
+    fn request_redo_batch(
+        &self,
+        requests: Vec<WalRedoRequest>,
+        pg_version: u32,
+    ) -> Vec<Result<Bytes, WalRedoError>> {
+        requests
+            .into_iter()
+            .map(|r| self.request_redo(r.key, r.lsn, r.base_img, r.records, pg_version))
+            .collect()
+    }
+}
+
+/// The arguments of one [`WalRedoManager::request_redo`] call in a batch.
+pub struct WalRedoRequest {
+    pub key: Key,
+    pub lsn: Lsn,
+    pub base_img: Option<(Lsn, Bytes)>,
+    pub records: Vec<(Lsn, NeonWalRecord)>,
+}
+
+/// A page to reconstruct in the WAL redo process.
+struct RedoPage<'a> {
+    tag: BufferTag,
+    base_img: Option<&'a Bytes>,
+    records: &'a [(Lsn, NeonWalRecord)],
 }
 
 struct ProcessInput {
@@ -119,21 +156,6 @@ pub struct PostgresRedoManager {
     stderr: Mutex<Option<ChildStderr>>,
 }
 
-/// Can this request be served by neon redo functions
-/// or we need to pass it to wal-redo postgres process?
-fn can_apply_in_neon(rec: &NeonWalRecord) -> bool {
-    // Currently, we don't have bespoken Rust code to replay any
-    // Postgres WAL records. But everything else is handled in neon.
-    #[allow(clippy::match_like_matches_macro)]
-    match rec {
-        NeonWalRecord::Postgres {
-            will_init: _,
-            rec: _,
-        } => false,
-        _ => true,
-    }
-}
-
 /// An error happened in WAL redo
 #[derive(Debug, thiserror::Error)]
 pub enum WalRedoError {
@@ -173,14 +195,14 @@ impl WalRedoManager for PostgresRedoManager {
 
         let base_img_lsn = base_img.as_ref().map(|p| p.0).unwrap_or(Lsn::INVALID);
         let mut img = base_img.map(|p| p.1);
-        let mut batch_neon = can_apply_in_neon(&records[0].1);
+        let mut batch_neon = self.can_apply_in_neon(key, &records[0].1, pg_version);
         let mut batch_start = 0;
         for (i, record) in records.iter().enumerate().skip(1) {
-            let rec_neon = can_apply_in_neon(&record.1);
+            let rec_neon = self.can_apply_in_neon(key, &record.1, pg_version);
 
             if rec_neon != batch_neon {
                 let result = if batch_neon {
-                    self.apply_batch_neon(key, lsn, img, &records[batch_start..i])
+                    self.apply_batch_neon(key, lsn, img, &records[batch_start..i], pg_version)
                 } else {
                     self.apply_batch_postgres(
                         key,
@@ -200,7 +222,7 @@ impl WalRedoManager for PostgresRedoManager {
         }
         // last batch
         if batch_neon {
-            self.apply_batch_neon(key, lsn, img, &records[batch_start..])
+            self.apply_batch_neon(key, lsn, img, &records[batch_start..], pg_version)
         } else {
             self.apply_batch_postgres(
                 key,
@@ -213,6 +235,81 @@ impl WalRedoManager for PostgresRedoManager {
             )
         }
     }
+
+    ///
+    /// Request the WAL redo manager to apply the WAL records of many pages
+    ///
+    /// The requests of the blocks of a relation within the same chunk of
+    /// `wal_redo_max_batch_size` blocks are sent to the WAL redo process together, and
+    /// their pages are read back together, if they only need Postgres records to be
+    /// applied. The other requests, and the requests of a batch that failed, are
+    /// applied one at a time.
+    ///
+    fn request_redo_batch(
+        &self,
+        requests: Vec<WalRedoRequest>,
+        pg_version: u32,
+    ) -> Vec<Result<Bytes, WalRedoError>> {
+        let max_batch_size = self.conf.wal_redo_max_batch_size.get() as u32;
+        let mut results: Vec<Option<Result<Bytes, WalRedoError>>> =
+            requests.iter().map(|_| None).collect();
+
+        let mut one_at_a_time = Vec::new();
+        let mut batches: BTreeMap<(RelTag, u32), Vec<(usize, BufferTag, WalRedoRequest)>> =
+            BTreeMap::new();
+        for (idx, request) in requests.into_iter().enumerate() {
+            let batchable = max_batch_size > 1
+                && !request.records.is_empty()
+                && !request
+                    .records
+                    .iter()
+                    .any(|(_, rec)| self.can_apply_in_neon(request.key, rec, pg_version));
+            match key_to_rel_block(request.key) {
+                Ok((rel, blknum)) if batchable => batches
+                    .entry((rel, blknum / max_batch_size))
+                    .or_default()
+                    .push((idx, BufferTag { rel, blknum }, request)),
+                _ => one_at_a_time.push((idx, request)),
+            }
+        }
+
+        for (_, batch) in batches {
+            if batch.len() == 1 {
+                one_at_a_time.extend(batch.into_iter().map(|(idx, _, request)| (idx, request)));
+                continue;
+            }
+            let pages: Vec<RedoPage> = batch
+                .iter()
+                .map(|(_, tag, request)| RedoPage {
+                    tag: *tag,
+                    base_img: request.base_img.as_ref().map(|(_, img)| img),
+                    records: &request.records,
+                })
+                .collect();
+            match self.apply_pages_postgres(&pages, pg_version) {
+                Ok(imgs) => {
+                    for ((idx, _, _), img) in batch.iter().zip(imgs) {
+                        results[*idx] = Some(Ok(img));
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "batched WAL redo of {} pages failed, applying them one at a time: {e}",
+                        batch.len()
+                    );
+                    one_at_a_time.extend(batch.into_iter().map(|(idx, _, request)| (idx, request)));
+                }
+            }
+        }
+
+        for (idx, r) in one_at_a_time {
+            results[idx] = Some(self.request_redo(r.key, r.lsn, r.base_img, r.records, pg_version));
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every request is either batched or applied alone"))
+            .collect()
+    }
 }
 
 impl PostgresRedoManager {
@@ -230,7 +327,20 @@ impl PostgresRedoManager {
         }
     }
 
-    /// Launch process pre-emptively. Should not be needed except for benchmarking.
+    /// Whether the record is applied by the pageserver itself, rather than by the
+    /// WAL redo process. Everything but the Postgres records is, and the Postgres
+    /// records of the types in [`native`] too.
+    fn can_apply_in_neon(&self, key: Key, rec: &NeonWalRecord, pg_version: u32) -> bool {
+        match rec {
+            NeonWalRecord::Postgres { rec, .. } => {
+                self.conf.wal_redo_native && native::can_apply(key, rec, pg_version)
+            }
+            _ => true,
+        }
+    }
+
+    /// Launch process pre-emptively. Should not be needed except for benchmarking
+    /// and [`check_wal_redo`].
     pub fn launch_process(&self, pg_version: u32) -> anyhow::Result<()> {
         let mut proc = self.stdin.lock().unwrap();
         if proc.is_none() {
@@ -255,76 +365,36 @@ impl PostgresRedoManager {
     ) -> Result<Bytes, WalRedoError> {
         let (rel, blknum) = key_to_rel_block(key).or(Err(WalRedoError::InvalidRecord))?;
         const MAX_RETRY_ATTEMPTS: u32 = 1;
-        let start_time = Instant::now();
+        // Relational WAL records are applied using wal-redo-postgres
+        let page = RedoPage {
+            tag: BufferTag { rel, blknum },
+            base_img: base_img.as_ref(),
+            records,
+        };
+        let nbytes = records_len(records);
         let mut n_attempts = 0u32;
         loop {
-            let mut proc = self.stdin.lock().unwrap();
-            let lock_time = Instant::now();
-
-            // launch the WAL redo process on first use
-            if proc.is_none() {
-                self.launch(&mut proc, pg_version)?;
-            }
-            WAL_REDO_WAIT_TIME.observe(lock_time.duration_since(start_time).as_secs_f64());
-
-            // Relational WAL records are applied using wal-redo-postgres
-            let buf_tag = BufferTag { rel, blknum };
             let result = self
-                .apply_wal_records(proc, buf_tag, &base_img, records, wal_redo_timeout)
-                .map_err(WalRedoError::IoError);
-
-            let end_time = Instant::now();
-            let duration = end_time.duration_since(lock_time);
-
-            let len = records.len();
-            let nbytes = records.iter().fold(0, |acumulator, record| {
-                acumulator
-                    + match &record.1 {
-                        NeonWalRecord::Postgres { rec, .. } => rec.len(),
-                        _ => unreachable!("Only PostgreSQL records are accepted in this batch"),
-                    }
-            });
-
-            WAL_REDO_TIME.observe(duration.as_secs_f64());
-            WAL_REDO_RECORDS_HISTOGRAM.observe(len as f64);
-            WAL_REDO_BYTES_HISTOGRAM.observe(nbytes as f64);
-
-            debug!(
-				"postgres applied {} WAL records ({} bytes) in {} us to reconstruct page image at LSN {}",
-				len,
-				nbytes,
-				duration.as_micros(),
-				lsn
-			);
-
-            // If something went wrong, don't try to reuse the process. Kill it, and
-            // next request will launch a new one.
-            if result.is_err() {
+                .redo_pages_postgres(std::slice::from_ref(&page), wal_redo_timeout, pg_version)
+                .map(|mut imgs| imgs.pop().expect("one page image per page"));
+
+            if result.is_ok() {
+                debug!(
+                    "postgres applied {} WAL records ({} bytes) to reconstruct page image at LSN {}",
+                    records.len(),
+                    nbytes,
+                    lsn
+                );
+            } else {
                 error!(
-                "error applying {} WAL records {}..{} ({} bytes) to base image with LSN {} to reconstruct page image at LSN {}",
-                records.len(),
-				records.first().map(|p| p.0).unwrap_or(Lsn(0)),
-				records.last().map(|p| p.0).unwrap_or(Lsn(0)),
-                nbytes,
-				base_img_lsn,
-                lsn
-            );
-                // self.stdin only holds stdin & stderr as_raw_fd().
-                // Dropping it as part of take() doesn't close them.
-                // The owning objects (ChildStdout and ChildStderr) are stored in
-                // self.stdout and self.stderr, respsectively.
-                // We intentionally keep them open here to avoid a race between
-                // currently running `apply_wal_records()` and a `launch()` call
-                // after we return here.
-                // The currently running `apply_wal_records()` must not read from
-                // the newly launched process.
-                // By keeping self.stdout and self.stderr open here, `launch()` will
-                // get other file descriptors for the new child's stdout and stderr,
-                // and hence the current `apply_wal_records()` calls will observe
-                //  `output.stdout.as_raw_fd() != stdout_fd` .
-                if let Some(proc) = self.stdin.lock().unwrap().take() {
-                    proc.child.kill_and_wait();
-                }
+                    "error applying {} WAL records {}..{} ({} bytes) to base image with LSN {} to reconstruct page image at LSN {}",
+                    records.len(),
+                    records.first().map(|p| p.0).unwrap_or(Lsn(0)),
+                    records.last().map(|p| p.0).unwrap_or(Lsn(0)),
+                    nbytes,
+                    base_img_lsn,
+                    lsn
+                );
             }
             n_attempts += 1;
             if n_attempts > MAX_RETRY_ATTEMPTS || result.is_ok() {
@@ -333,6 +403,80 @@ impl PostgresRedoManager {
         }
     }
 
+    ///
+    /// Reconstruct many pages with one round trip to wal-redo postgres. Unlike
+    /// [`Self::apply_batch_postgres`], a failure isn't retried, as the caller applies
+    /// the pages one at a time then.
+    ///
+    fn apply_pages_postgres(
+        &self,
+        pages: &[RedoPage],
+        pg_version: u32,
+    ) -> Result<Vec<Bytes>, WalRedoError> {
+        self.redo_pages_postgres(pages, self.conf.wal_redo_timeout, pg_version)
+    }
+
+    ///
+    /// Send pages to reconstruct to wal-redo postgres, launching it on first use,
+    /// and record the metrics of the round trip.
+    ///
+    fn redo_pages_postgres(
+        &self,
+        pages: &[RedoPage],
+        wal_redo_timeout: Duration,
+        pg_version: u32,
+    ) -> Result<Vec<Bytes>, WalRedoError> {
+        let start_time = Instant::now();
+        let mut proc = self.stdin.lock().unwrap();
+        let lock_time = Instant::now();
+
+        // launch the WAL redo process on first use
+        if proc.is_none() {
+            self.launch(&mut proc, pg_version)?;
+        }
+        WAL_REDO_WAIT_TIME.observe(lock_time.duration_since(start_time).as_secs_f64());
+
+        let result = self
+            .apply_wal_records(proc, pages, wal_redo_timeout)
+            .map_err(WalRedoError::IoError);
+
+        let duration = lock_time.elapsed();
+        WAL_REDO_TIME.observe(duration.as_secs_f64());
+        WAL_REDO_BATCH_SIZE_HISTOGRAM.observe(pages.len() as f64);
+        for page in pages {
+            WAL_REDO_RECORDS_HISTOGRAM.observe(page.records.len() as f64);
+            WAL_REDO_BYTES_HISTOGRAM.observe(records_len(page.records) as f64);
+        }
+
+        debug!(
+            "postgres reconstructed {} pages in {} us",
+            pages.len(),
+            duration.as_micros()
+        );
+
+        // If something went wrong, don't try to reuse the process. Kill it, and
+        // next request will launch a new one.
+        if result.is_err() {
+            // self.stdin only holds stdin & stderr as_raw_fd().
+            // Dropping it as part of take() doesn't close them.
+            // The owning objects (ChildStdout and ChildStderr) are stored in
+            // self.stdout and self.stderr, respsectively.
+            // We intentionally keep them open here to avoid a race between
+            // currently running `apply_wal_records()` and a `launch()` call
+            // after we return here.
+            // The currently running `apply_wal_records()` must not read from
+            // the newly launched process.
+            // By keeping self.stdout and self.stderr open here, `launch()` will
+            // get other file descriptors for the new child's stdout and stderr,
+            // and hence the current `apply_wal_records()` calls will observe
+            //  `output.stdout.as_raw_fd() != stdout_fd` .
+            if let Some(proc) = self.stdin.lock().unwrap().take() {
+                proc.child.kill_and_wait();
+            }
+        }
+        result
+    }
+
     ///
     /// Process a batch of WAL records using bespoken Neon code.
     ///
@@ -342,6 +486,7 @@ impl PostgresRedoManager {
         lsn: Lsn,
         base_img: Option<Bytes>,
         records: &[(Lsn, NeonWalRecord)],
+        pg_version: u32,
     ) -> Result<Bytes, WalRedoError> {
         let start_time = Instant::now();
 
@@ -349,15 +494,18 @@ impl PostgresRedoManager {
         if let Some(fpi) = base_img {
             // If full-page image is provided, then use it...
             page.extend_from_slice(&fpi[..]);
+        } else if records.first().map_or(false, |(_, rec)| rec.will_init()) {
+            // ... or the first record initializes the page with a full-page image
+            page.resize(BLCKSZ as usize, 0);
         } else {
-            // All the current WAL record types that we can handle require a base image.
+            // All the other WAL record types that we can handle require a base image.
             error!("invalid neon WAL redo request with no base image");
             return Err(WalRedoError::InvalidRequest);
         }
 
         // Apply all the WAL records in the batch
         for (record_lsn, record) in records.iter() {
-            self.apply_record_neon(key, &mut page, *record_lsn, record)?;
+            self.apply_record_neon(key, &mut page, *record_lsn, record, pg_version)?;
         }
         // Success!
         let end_time = Instant::now();
@@ -378,16 +526,16 @@ impl PostgresRedoManager {
         &self,
         key: Key,
         page: &mut BytesMut,
-        _record_lsn: Lsn,
+        record_lsn: Lsn,
         record: &NeonWalRecord,
+        pg_version: u32,
     ) -> Result<(), WalRedoError> {
         match record {
-            NeonWalRecord::Postgres {
-                will_init: _,
-                rec: _,
-            } => {
-                error!("tried to pass postgres wal record to neon WAL redo");
-                return Err(WalRedoError::InvalidRequest);
+            NeonWalRecord::Postgres { will_init: _, rec } => {
+                if let Err(e) = native::apply(key, page, record_lsn, rec, pg_version) {
+                    error!("failed to replay Postgres WAL record at {record_lsn}: {e:#}");
+                    return Err(WalRedoError::InvalidRecord);
+                }
             }
             NeonWalRecord::ClearVisibilityMapFlags {
                 new_heap_blkno,
@@ -702,6 +850,13 @@ impl<C: CommandExt> CloseFileDescriptors for C {
     }
 }
 
+/// Checks that a WAL redo process can be started, for the readiness probe.
+/// The process is killed right away.
+pub fn check_wal_redo(conf: &'static PageServerConf) -> anyhow::Result<()> {
+    let manager = PostgresRedoManager::new(conf, TenantId::generate());
+    manager.launch_process(crate::DEFAULT_PG_VERSION)
+}
+
 impl PostgresRedoManager {
     //
     // Start postgres binary in special WAL redo mode.
@@ -811,18 +966,16 @@ impl PostgresRedoManager {
         Ok(())
     }
 
-    // Apply given WAL records ('records') over an old page image. Returns
-    // new page image.
+    // Apply given WAL records over the old page images of 'pages'. Returns
+    // the new page images, in the same order.
     //
     #[instrument(skip_all, fields(tenant_id=%self.tenant_id, pid=%input.as_ref().unwrap().child.id()))]
     fn apply_wal_records(
         &self,
         mut input: MutexGuard<Option<ProcessInput>>,
-        tag: BufferTag,
-        base_img: &Option<Bytes>,
-        records: &[(Lsn, NeonWalRecord)],
+        pages: &[RedoPage],
         wal_redo_timeout: Duration,
-    ) -> Result<Bytes, std::io::Error> {
+    ) -> Result<Vec<Bytes>, std::io::Error> {
         // Serialize all the messages to send the WAL redo process first.
         //
         // This could be problematic if there are millions of records to replay,
@@ -832,27 +985,29 @@ impl PostgresRedoManager {
         // Most requests start with a before-image with BLCKSZ bytes, followed by
         // by some other WAL records. Start with a buffer that can hold that
         // comfortably.
-        let mut writebuf: Vec<u8> = Vec::with_capacity((BLCKSZ as usize) * 3);
-        build_begin_redo_for_block_msg(tag, &mut writebuf);
-        if let Some(img) = base_img {
-            build_push_page_msg(tag, img, &mut writebuf);
-        }
-        for (lsn, rec) in records.iter() {
-            if let NeonWalRecord::Postgres {
-                will_init: _,
-                rec: postgres_rec,
-            } = rec
-            {
-                build_apply_record_msg(*lsn, postgres_rec, &mut writebuf);
-            } else {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "tried to pass neon wal record to postgres WAL redo",
-                ));
+        let mut writebuf: Vec<u8> = Vec::with_capacity((BLCKSZ as usize) * 3 * pages.len());
+        for page in pages {
+            build_begin_redo_for_block_msg(page.tag, &mut writebuf);
+            if let Some(img) = page.base_img {
+                build_push_page_msg(page.tag, img, &mut writebuf);
             }
+            for (lsn, rec) in page.records.iter() {
+                if let NeonWalRecord::Postgres {
+                    will_init: _,
+                    rec: postgres_rec,
+                } = rec
+                {
+                    build_apply_record_msg(*lsn, postgres_rec, &mut writebuf);
+                } else {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        "tried to pass neon wal record to postgres WAL redo",
+                    ));
+                }
+            }
+            build_get_page_msg(page.tag, &mut writebuf);
+            WAL_REDO_RECORD_COUNTER.inc_by(page.records.len() as u64);
         }
-        build_get_page_msg(tag, &mut writebuf);
-        WAL_REDO_RECORD_COUNTER.inc_by(records.len() as u64);
 
         let proc = input.as_mut().unwrap();
         let mut nwrite = 0usize;
@@ -919,8 +1074,10 @@ impl PostgresRedoManager {
                 ));
             }
         }
-        let request_no = proc.n_requests;
-        proc.n_requests += 1;
+        // Each page is a request of its own to the process, with its own response
+        let first_request_no = proc.n_requests;
+        proc.n_requests += pages.len();
+        let last_request_no = proc.n_requests - 1;
         drop(input);
 
         // To improve walredo performance we separate sending requests and receiving
@@ -953,7 +1110,7 @@ impl PostgresRedoManager {
             ));
         }
         let n_processed_responses = output.n_processed_responses;
-        while n_processed_responses + output.pending_responses.len() <= request_no {
+        while n_processed_responses + output.pending_responses.len() <= last_request_no {
             // We expect the WAL redo process to respond with an 8k page image. We read it
             // into this buffer.
             let mut resultbuf = vec![0; BLCKSZ.into()];
@@ -1046,9 +1203,13 @@ impl PostgresRedoManager {
         // T2: does the while loop below
         // pending_responses now looks like this: Front Back
         // n_processed_responses now has value 25
-        let res = output.pending_responses[request_no - n_processed_responses]
-            .take()
-            .expect("we own this request_no, nobody else is supposed to take it");
+        let res = (first_request_no..=last_request_no)
+            .map(|request_no| {
+                output.pending_responses[request_no - n_processed_responses]
+                    .take()
+                    .expect("we own this request_no, nobody else is supposed to take it")
+            })
+            .collect();
         while let Some(front) = output.pending_responses.front() {
             if front.is_none() {
                 output.pending_responses.pop_front();
@@ -1166,6 +1327,17 @@ impl NoLeakChildCommandExt for Command {
 // process. See pgxn/neon_walredo/walredoproc.c for
 // explanation of the protocol.
 
+/// Total size of a batch of Postgres WAL records for wal-redo postgres.
+fn records_len(records: &[(Lsn, NeonWalRecord)]) -> usize {
+    records
+        .iter()
+        .map(|(_, rec)| match rec {
+            NeonWalRecord::Postgres { rec, .. } => rec.len(),
+            _ => unreachable!("Only PostgreSQL records are accepted in this batch"),
+        })
+        .sum()
+}
+
 fn build_begin_redo_for_block_msg(tag: BufferTag, buf: &mut Vec<u8>) {
     let len = 4 + 1 + 4 * 4;
 
@@ -1208,7 +1380,7 @@ fn build_get_page_msg(tag: BufferTag, buf: &mut Vec<u8>) {
 
 #[cfg(test)]
 mod tests {
-    use super::{PostgresRedoManager, WalRedoManager};
+    use super::{PostgresRedoManager, WalRedoManager, WalRedoRequest};
     use crate::repository::Key;
     use crate::{config::PageServerConf, walrecord::NeonWalRecord};
     use bytes::Bytes;
@@ -1242,6 +1414,35 @@ mod tests {
         assert_eq!(&expected, &*page);
     }
 
+    #[test]
+    fn short_v14_redo_batch() {
+        let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
+
+        // The first record has a full-page image, which would be restored natively
+        let h = RedoHarness::with_native_redo(false).unwrap();
+
+        // both requests of the same block go to the WAL redo process together
+        let request = || WalRedoRequest {
+            key: Key {
+                field1: 0,
+                field2: 1663,
+                field3: 13010,
+                field4: 1259,
+                field5: 0,
+                field6: 0,
+            },
+            lsn: Lsn::from_str("0/16E2408").unwrap(),
+            base_img: None,
+            records: short_records(),
+        };
+        let pages = h.manager.request_redo_batch(vec![request(), request()], 14);
+
+        assert_eq!(pages.len(), 2);
+        for page in pages {
+            assert_eq!(&expected, &*page.unwrap());
+        }
+    }
+
     #[test]
     fn short_v14_fails_for_wrong_key_but_returns_zero_page() {
         let h = RedoHarness::new().unwrap();
@@ -1298,8 +1499,13 @@ mod tests {
 
     impl RedoHarness {
         fn new() -> anyhow::Result<Self> {
+            Self::with_native_redo(true)
+        }
+
+        fn with_native_redo(wal_redo_native: bool) -> anyhow::Result<Self> {
             let repo_dir = tempfile::tempdir()?;
-            let conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            let mut conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            conf.wal_redo_native = wal_redo_native;
             let conf = Box::leak(Box::new(conf));
             let tenant_id = TenantId::generate();
 
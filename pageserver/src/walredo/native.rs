@@ -0,0 +1,744 @@
+//!
+//! Replay of simple Postgres WAL records in the pageserver itself.
+//!
+//! Most page reconstructions apply a few records of a handful of types: the
+//! full-page images logged by the first change of a page after a checkpoint,
+//! including the ones logged for hint bits, HOT updates of heap tuples and
+//! insertions into btree leaf pages. Replaying them is cheap, and sending them
+//! to the WAL redo process costs more than the replay itself. The records of
+//! these types are replayed here instead, by ports of their redo routines in
+//! Postgres, and all the other records go to the WAL redo process.
+//!
+//! The ports must produce the same page as Postgres, byte for byte. A record
+//! that doesn't look like what they expect, e.g. a HOT update with a compressed
+//! full-page image or a btree insertion that splits a posting list, isn't
+//! replayed here, and goes to the WAL redo process too.
+//!
+use anyhow::{bail, ensure, Context};
+use bytes::{Bytes, BytesMut};
+use postgres_ffi::{page_get_lsn, page_is_new, page_set_lsn, pg_constants, BLCKSZ};
+use postgres_ffi::{transaction_id_precedes, TransactionId};
+use utils::lsn::Lsn;
+
+use crate::metrics::WAL_REDO_NATIVE_RECORDS;
+use crate::pgdatadir_mapping::key_to_rel_block;
+use crate::repository::Key;
+use crate::walrecord::{decode_wal_record, DecodedBkpBlock, DecodedWALRecord, XlHeapUpdate};
+
+const PAGE_SIZE: usize = BLCKSZ as usize;
+
+// From bufpage.h and itemid.h
+const SIZE_OF_PAGE_HEADER: usize = pg_constants::SIZE_OF_PAGE_HEADER as usize;
+const SIZE_OF_ITEM_ID: usize = 4;
+const PD_ALL_VISIBLE: u16 = 0x0004;
+const LP_UNUSED: u32 = 0;
+const LP_NORMAL: u32 = 1;
+
+// From htup_details.h
+const SIZE_OF_HEAP_TUPLE_HEADER: usize = 23;
+const MAX_HEAP_TUPLES_PER_PAGE: u16 = ((PAGE_SIZE - SIZE_OF_PAGE_HEADER)
+    / (maxalign(SIZE_OF_HEAP_TUPLE_HEADER) + SIZE_OF_ITEM_ID))
+    as u16;
+const HEAP_XMAX_KEYSHR_LOCK: u16 = 0x0010;
+const HEAP_COMBOCID: u16 = 0x0020;
+const HEAP_XMAX_EXCL_LOCK: u16 = 0x0040;
+const HEAP_XMAX_LOCK_ONLY: u16 = 0x0080;
+const HEAP_XMAX_COMMITTED: u16 = 0x0400;
+const HEAP_XMAX_INVALID: u16 = 0x0800;
+const HEAP_XMAX_IS_MULTI: u16 = 0x1000;
+const HEAP_MOVED: u16 = 0xC000;
+const HEAP_XMAX_BITS: u16 = HEAP_XMAX_COMMITTED
+    | HEAP_XMAX_INVALID
+    | HEAP_XMAX_IS_MULTI
+    | HEAP_XMAX_KEYSHR_LOCK
+    | HEAP_XMAX_EXCL_LOCK
+    | HEAP_XMAX_LOCK_ONLY;
+const HEAP_KEYS_UPDATED: u16 = 0x2000;
+const HEAP_HOT_UPDATED: u16 = 0x4000;
+
+// From heapam_xlog.h
+const SIZE_OF_HEAP_UPDATE: usize = 18;
+const SIZE_OF_HEAP_HEADER: usize = 5;
+const XLHL_XMAX_IS_MULTI: u8 = 0x01;
+const XLHL_XMAX_LOCK_ONLY: u8 = 0x02;
+const XLHL_XMAX_EXCL_LOCK: u8 = 0x04;
+const XLHL_XMAX_KEYSHR_LOCK: u8 = 0x08;
+const XLHL_KEYS_UPDATED: u8 = 0x10;
+
+/// The types of the records replayed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+enum RecordType {
+    FullPageImage,
+    HeapHotUpdate,
+    BtreeInsertLeaf,
+}
+
+/// Checks whether the Postgres WAL record can be replayed here, on the page of `key`.
+pub(super) fn can_apply(key: Key, rec: &Bytes, pg_version: u32) -> bool {
+    decode(key, rec, pg_version).is_some()
+}
+
+/// Replays the Postgres WAL record, ending at `lsn`, on the page of `key`. Only
+/// records for which [`can_apply`] is true can be replayed.
+pub(super) fn apply(
+    key: Key,
+    page: &mut BytesMut,
+    lsn: Lsn,
+    rec: &Bytes,
+    pg_version: u32,
+) -> anyhow::Result<()> {
+    let (record_type, decoded, block_id) =
+        decode(key, rec, pg_version).context("record type can't be replayed natively")?;
+    let blk = &decoded.blocks[block_id];
+
+    if record_type == RecordType::FullPageImage {
+        restore_image(page, &decoded.record, blk, lsn)?;
+    } else {
+        ensure!(
+            page.len() == PAGE_SIZE,
+            "page image has length {} instead of {PAGE_SIZE}",
+            page.len()
+        );
+        // Like XLogReadBufferForRedo, skip the changes the page already has
+        if page_get_lsn(page) >= lsn {
+            return Ok(());
+        }
+        match record_type {
+            RecordType::HeapHotUpdate => redo_heap_hot_update(page, &decoded, blk)?,
+            RecordType::BtreeInsertLeaf => redo_btree_insert_leaf(page, &decoded, blk)?,
+            RecordType::FullPageImage => unreachable!(),
+        }
+        page_set_lsn(page, lsn);
+    }
+
+    WAL_REDO_NATIVE_RECORDS
+        .with_label_values(&[record_type.into()])
+        .inc();
+    Ok(())
+}
+
+/// Decodes the record, if it's of a type replayed here, and finds the block of
+/// `key` in it.
+fn decode(key: Key, rec: &Bytes, pg_version: u32) -> Option<(RecordType, DecodedWALRecord, usize)> {
+    let (rel, blknum) = key_to_rel_block(key).ok()?;
+    let mut decoded = DecodedWALRecord::default();
+    decode_wal_record(rec.clone(), &mut decoded, pg_version).ok()?;
+    let block_id = decoded.blocks.iter().position(|blk| {
+        blk.rnode_spcnode == rel.spcnode
+            && blk.rnode_dbnode == rel.dbnode
+            && blk.rnode_relnode == rel.relnode
+            && blk.forknum == rel.forknum
+            && blk.blkno == blknum
+    })?;
+    let blk = &decoded.blocks[block_id];
+
+    // Whatever the record does to a block with a full-page image to apply, the
+    // redo routine just restores the image.
+    let record_type = if blk.apply_image {
+        if postgres_ffi::bkpimage_is_compressed(blk.bimg_info, pg_version).ok()? {
+            return None;
+        }
+        RecordType::FullPageImage
+    } else {
+        let info = decoded.xl_info & pg_constants::XLR_RMGR_INFO_MASK;
+        let single_block = decoded.blocks.len() == 1 && !blk.data(rec).is_empty();
+        match decoded.xl_rmid {
+            // A HOT update keeps the new tuple on the page of the old one, so
+            // the record has only that block.
+            pg_constants::RM_HEAP_ID
+                if info & pg_constants::XLOG_HEAP_OPMASK == pg_constants::XLOG_HEAP_HOT_UPDATE
+                    && info & pg_constants::XLOG_HEAP_INIT_PAGE == 0
+                    && single_block =>
+            {
+                RecordType::HeapHotUpdate
+            }
+            pg_constants::RM_BTREE_ID
+                if info == pg_constants::XLOG_BTREE_INSERT_LEAF && single_block =>
+            {
+                RecordType::BtreeInsertLeaf
+            }
+            _ => return None,
+        }
+    };
+    Some((record_type, decoded, block_id))
+}
+
+/// Port of RestoreBlockImage and of the rest of XLogReadBufferForRedoExtended
+/// for a restored image.
+fn restore_image(
+    page: &mut BytesMut,
+    record: &[u8],
+    blk: &DecodedBkpBlock,
+    lsn: Lsn,
+) -> anyhow::Result<()> {
+    let image_start = blk.bimg_offset as usize;
+    let image = record
+        .get(image_start..image_start + blk.bimg_len as usize)
+        .context("block image is out of the record")?;
+    let hole_offset = blk.hole_offset as usize;
+    ensure!(
+        hole_offset <= image.len() && image.len() + blk.hole_length as usize == PAGE_SIZE,
+        "invalid block image of length {} with hole at {} of length {}",
+        image.len(),
+        blk.hole_offset,
+        blk.hole_length
+    );
+
+    page.clear();
+    page.extend_from_slice(&image[..hole_offset]);
+    page.resize(hole_offset + blk.hole_length as usize, 0);
+    page.extend_from_slice(&image[hole_offset..]);
+    // The page may be uninitialized. If so, we can't set the LSN because that
+    // would corrupt the page.
+    if !page_is_new(page) {
+        page_set_lsn(page, lsn);
+    }
+    Ok(())
+}
+
+/// Port of heap_xlog_update, for a HOT update. The fork of Postgres logs the
+/// command id of the update, which is set in both tuples.
+fn redo_heap_hot_update(
+    page: &mut [u8],
+    decoded: &DecodedWALRecord,
+    blk: &DecodedBkpBlock,
+) -> anyhow::Result<()> {
+    let mut main_data = decoded.record.slice(decoded.main_data_offset..);
+    ensure!(
+        main_data.len() >= SIZE_OF_HEAP_UPDATE,
+        "xl_heap_update is truncated"
+    );
+    let xlrec = XlHeapUpdate::decode(&mut main_data);
+    let xid = decoded.xl_xid;
+    let mut page = Page::new(page)?;
+
+    // Mark the old tuple as updated by the new one
+    let (old_start, old_len) = page.normal_item(xlrec.old_offnum)?;
+    ensure!(
+        old_len >= SIZE_OF_HEAP_TUPLE_HEADER,
+        "old tuple of length {old_len} is too short"
+    );
+    let old_tuple = page.0[old_start..old_start + old_len].to_vec();
+    {
+        let htup = &mut page.0[old_start..old_start + old_len];
+        let mut infomask = get_u16(htup, 20) & !(HEAP_XMAX_BITS | HEAP_MOVED);
+        let mut infomask2 = get_u16(htup, 18) & !HEAP_KEYS_UPDATED;
+        infomask2 |= HEAP_HOT_UPDATED;
+        fix_infomask_from_infobits(xlrec.old_infobits_set, &mut infomask, &mut infomask2);
+        // HeapTupleHeaderSetCmax, which isn't a combo command id here
+        infomask &= !HEAP_COMBOCID;
+        put_u32(htup, 4, xlrec.old_xmax);
+        put_u32(htup, 8, xlrec.t_cid);
+        put_tid(htup, blk.blkno, xlrec.new_offnum);
+        put_u16(htup, 18, infomask2);
+        put_u16(htup, 20, infomask);
+    }
+    page.set_prunable(xid);
+    if xlrec.flags & pg_constants::XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED != 0 {
+        page.clear_all_visible();
+    }
+
+    // Rebuild the new tuple from the record, and the prefix and suffix shared
+    // with the old tuple
+    ensure!(
+        xlrec.new_offnum <= page.max_offset() + 1,
+        "invalid max offset number"
+    );
+    let mut data = blk.data(&decoded.record);
+    let prefix_len = if xlrec.flags & pg_constants::XLH_UPDATE_PREFIX_FROM_OLD != 0 {
+        take_u16(&mut data)?
+    } else {
+        0
+    };
+    let suffix_len = if xlrec.flags & pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD != 0 {
+        take_u16(&mut data)?
+    } else {
+        0
+    };
+    ensure!(
+        data.len() >= SIZE_OF_HEAP_HEADER,
+        "xl_heap_header is truncated"
+    );
+    let (t_infomask2, t_infomask, t_hoff) = (get_u16(data, 0), get_u16(data, 2), data[4]);
+    let data = &data[SIZE_OF_HEAP_HEADER..];
+
+    let old_hoff = old_tuple[22] as usize;
+    ensure!(
+        old_hoff + prefix_len <= old_len && suffix_len <= old_len,
+        "prefix or suffix is longer than the old tuple"
+    );
+    let mut htup = vec![0u8; SIZE_OF_HEAP_TUPLE_HEADER];
+    if prefix_len > 0 {
+        // the null bitmap, padding and oid, then the prefix and the rest
+        let bitmap_len = (t_hoff as usize)
+            .checked_sub(SIZE_OF_HEAP_TUPLE_HEADER)
+            .filter(|len| *len <= data.len())
+            .context("invalid t_hoff")?;
+        htup.extend_from_slice(&data[..bitmap_len]);
+        htup.extend_from_slice(&old_tuple[old_hoff..old_hoff + prefix_len]);
+        htup.extend_from_slice(&data[bitmap_len..]);
+    } else {
+        htup.extend_from_slice(data);
+    }
+    htup.extend_from_slice(&old_tuple[old_len - suffix_len..]);
+    put_u16(&mut htup, 18, t_infomask2);
+    // HeapTupleHeaderSetCmin, which isn't a combo command id either
+    put_u16(&mut htup, 20, t_infomask & !HEAP_COMBOCID);
+    htup[22] = t_hoff;
+    put_u32(&mut htup, 0, xid);
+    put_u32(&mut htup, 4, xlrec.new_xmax);
+    put_u32(&mut htup, 8, xlrec.t_cid);
+    put_tid(&mut htup, blk.blkno, xlrec.new_offnum);
+
+    page.add_item(&htup, xlrec.new_offnum, true, true)?;
+    if xlrec.flags & pg_constants::XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED != 0 {
+        page.clear_all_visible();
+    }
+    Ok(())
+}
+
+/// Port of btree_xlog_insert, for a retail insertion into a leaf page.
+fn redo_btree_insert_leaf(
+    page: &mut [u8],
+    decoded: &DecodedWALRecord,
+    blk: &DecodedBkpBlock,
+) -> anyhow::Result<()> {
+    // xl_btree_insert
+    let main_data = &decoded.record[decoded.main_data_offset..];
+    ensure!(main_data.len() >= 2, "xl_btree_insert is truncated");
+    let offnum = get_u16(main_data, 0);
+
+    let mut page = Page::new(page)?;
+    page.add_item(blk.data(&decoded.record), offnum, false, false)
+}
+
+/// Port of fix_infomask_from_infobits.
+fn fix_infomask_from_infobits(infobits: u8, infomask: &mut u16, infomask2: &mut u16) {
+    *infomask &=
+        !(HEAP_XMAX_IS_MULTI | HEAP_XMAX_LOCK_ONLY | HEAP_XMAX_KEYSHR_LOCK | HEAP_XMAX_EXCL_LOCK);
+    *infomask2 &= !HEAP_KEYS_UPDATED;
+
+    if infobits & XLHL_XMAX_IS_MULTI != 0 {
+        *infomask |= HEAP_XMAX_IS_MULTI;
+    }
+    if infobits & XLHL_XMAX_LOCK_ONLY != 0 {
+        *infomask |= HEAP_XMAX_LOCK_ONLY;
+    }
+    if infobits & XLHL_XMAX_EXCL_LOCK != 0 {
+        *infomask |= HEAP_XMAX_EXCL_LOCK;
+    }
+    // note HEAP_XMAX_SHR_LOCK isn't considered here
+    if infobits & XLHL_XMAX_KEYSHR_LOCK != 0 {
+        *infomask |= HEAP_XMAX_KEYSHR_LOCK;
+    }
+    if infobits & XLHL_KEYS_UPDATED != 0 {
+        *infomask2 |= HEAP_KEYS_UPDATED;
+    }
+}
+
+/// A slotted page, with its header checked like PageAddItemExtended does.
+struct Page<'a>(&'a mut [u8]);
+
+impl<'a> Page<'a> {
+    fn new(page: &'a mut [u8]) -> anyhow::Result<Self> {
+        let page = Page(page);
+        let (lower, upper, special) = (page.lower(), page.upper(), page.special());
+        ensure!(
+            lower >= SIZE_OF_PAGE_HEADER
+                && lower <= upper
+                && upper <= special
+                && special <= PAGE_SIZE,
+            "corrupted page pointers: lower = {lower}, upper = {upper}, special = {special}"
+        );
+        Ok(page)
+    }
+
+    fn lower(&self) -> usize {
+        get_u16(self.0, 12) as usize
+    }
+
+    fn upper(&self) -> usize {
+        get_u16(self.0, 14) as usize
+    }
+
+    fn special(&self) -> usize {
+        get_u16(self.0, 16) as usize
+    }
+
+    fn max_offset(&self) -> u16 {
+        ((self.lower() - SIZE_OF_PAGE_HEADER) / SIZE_OF_ITEM_ID) as u16
+    }
+
+    fn item_id_pos(offnum: u16) -> usize {
+        SIZE_OF_PAGE_HEADER + (offnum as usize - 1) * SIZE_OF_ITEM_ID
+    }
+
+    /// Returns lp_off, lp_flags and lp_len of the line pointer.
+    fn item_id(&self, offnum: u16) -> (usize, u32, usize) {
+        let lp = u32::from_le_bytes(self.0[Self::item_id_pos(offnum)..][..4].try_into().unwrap());
+        (
+            (lp & 0x7FFF) as usize,
+            (lp >> 15) & 0x3,
+            (lp >> 17) as usize,
+        )
+    }
+
+    /// Returns the position and length of the item of a normal line pointer.
+    fn normal_item(&self, offnum: u16) -> anyhow::Result<(usize, usize)> {
+        ensure!(
+            offnum >= 1 && offnum <= self.max_offset(),
+            "invalid lp {offnum}"
+        );
+        let (off, flags, len) = self.item_id(offnum);
+        ensure!(
+            flags == LP_NORMAL && off + len <= PAGE_SIZE,
+            "invalid lp {offnum}"
+        );
+        Ok((off, len))
+    }
+
+    /// Port of PageAddItemExtended, with the offset number given.
+    fn add_item(
+        &mut self,
+        item: &[u8],
+        offnum: u16,
+        overwrite: bool,
+        is_heap: bool,
+    ) -> anyhow::Result<()> {
+        let limit = self.max_offset() + 1;
+        ensure!(offnum >= 1, "invalid item offset");
+        let mut need_shuffle = false;
+        if overwrite {
+            if offnum < limit {
+                let (_, flags, len) = self.item_id(offnum);
+                ensure!(
+                    flags == LP_UNUSED && len == 0,
+                    "will not overwrite a used ItemId"
+                );
+            }
+        } else if offnum < limit {
+            need_shuffle = true;
+        }
+        ensure!(offnum <= limit, "specified item offset is too large");
+        ensure!(
+            !is_heap || offnum <= MAX_HEAP_TUPLES_PER_PAGE,
+            "can't put more than MaxHeapTuplesPerPage items in a heap page"
+        );
+
+        let lower = if offnum == limit || need_shuffle {
+            self.lower() + SIZE_OF_ITEM_ID
+        } else {
+            self.lower()
+        };
+        let upper = match self.upper().checked_sub(maxalign(item.len())) {
+            Some(upper) if lower <= upper => upper,
+            _ => bail!("failed to add item of size {}", item.len()),
+        };
+
+        let pos = Self::item_id_pos(offnum);
+        if need_shuffle {
+            let end = Self::item_id_pos(limit);
+            self.0.copy_within(pos..end, pos + SIZE_OF_ITEM_ID);
+        }
+        let lp = upper as u32 | (LP_NORMAL << 15) | ((item.len() as u32) << 17);
+        self.0[pos..pos + SIZE_OF_ITEM_ID].copy_from_slice(&lp.to_le_bytes());
+        self.0[upper..upper + item.len()].copy_from_slice(item);
+        put_u16(self.0, 12, lower as u16);
+        put_u16(self.0, 14, upper as u16);
+        Ok(())
+    }
+
+    /// Port of PageSetPrunable.
+    fn set_prunable(&mut self, xid: TransactionId) {
+        let prune_xid = get_u32(self.0, 20);
+        if prune_xid == pg_constants::INVALID_TRANSACTION_ID
+            || transaction_id_precedes(xid, prune_xid)
+        {
+            put_u32(self.0, 20, xid);
+        }
+    }
+
+    fn clear_all_visible(&mut self) {
+        let flags = get_u16(self.0, 10);
+        put_u16(self.0, 10, flags & !PD_ALL_VISIBLE);
+    }
+}
+
+const fn maxalign(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+fn take_u16(data: &mut &[u8]) -> anyhow::Result<usize> {
+    ensure!(data.len() >= 2, "record data is truncated");
+    let value = get_u16(data, 0);
+    *data = &data[2..];
+    Ok(value as usize)
+}
+
+fn get_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn get_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn put_u16(buf: &mut [u8], pos: usize, value: u16) {
+    buf[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], pos: usize, value: u32) {
+    buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Sets t_ctid of the tuple.
+fn put_tid(htup: &mut [u8], blkno: u32, offnum: u16) {
+    put_u16(htup, 12, (blkno >> 16) as u16);
+    put_u16(htup, 14, blkno as u16);
+    put_u16(htup, 16, offnum);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+    use postgres_ffi::XLOG_SIZE_OF_XLOG_RECORD;
+
+    use super::*;
+
+    const XID: TransactionId = 730;
+
+    fn key() -> Key {
+        Key {
+            field1: 0,
+            field2: 1663,
+            field3: 16384,
+            field4: 16385,
+            field5: 0,
+            field6: 7,
+        }
+    }
+
+    /// A record modifying the block of `key()`, with a full-page image of it and
+    /// the offset of the hole of the image, if any.
+    fn test_record(
+        rmid: u8,
+        info: u8,
+        image: Option<(&[u8], u16)>,
+        block_data: &[u8],
+        main_data: &[u8],
+    ) -> Bytes {
+        let mut body = BytesMut::new();
+        body.put_u8(0); // block_id
+        let mut fork_flags = 0;
+        if image.is_some() {
+            fork_flags |= pg_constants::BKPBLOCK_HAS_IMAGE;
+        }
+        if !block_data.is_empty() {
+            fork_flags |= pg_constants::BKPBLOCK_HAS_DATA;
+        }
+        body.put_u8(fork_flags);
+        body.put_u16_le(block_data.len() as u16);
+        if let Some((image, hole_offset)) = image {
+            body.put_u16_le(image.len() as u16);
+            body.put_u16_le(hole_offset);
+            body.put_u8(
+                pg_constants::BKPIMAGE_HAS_HOLE | postgres_ffi::v14::bindings::BKPIMAGE_APPLY,
+            );
+        }
+        body.put_u32_le(1663); // spcnode
+        body.put_u32_le(16384); // dbnode
+        body.put_u32_le(16385); // relnode
+        body.put_u32_le(7); // blkno
+        body.put_u8(pg_constants::XLR_BLOCK_ID_DATA_SHORT);
+        body.put_u8(main_data.len() as u8);
+        if let Some((image, _)) = image {
+            body.put_slice(image);
+        }
+        body.put_slice(block_data);
+        body.put_slice(main_data);
+
+        let mut record = BytesMut::new();
+        record.put_u32_le((XLOG_SIZE_OF_XLOG_RECORD + body.len()) as u32); // xl_tot_len
+        record.put_u32_le(XID); // xl_xid
+        record.put_u64_le(0x16B3D30); // xl_prev
+        record.put_u8(info);
+        record.put_u8(rmid);
+        record.put_u16_le(0); // padding
+        record.put_u32_le(0); // xl_crc
+        record.put_slice(&body);
+        record.freeze()
+    }
+
+    fn empty_page(special_size: usize, lsn: Lsn) -> BytesMut {
+        let mut page = BytesMut::zeroed(PAGE_SIZE);
+        put_u16(&mut page, 12, SIZE_OF_PAGE_HEADER as u16);
+        put_u16(&mut page, 14, (PAGE_SIZE - special_size) as u16);
+        put_u16(&mut page, 16, (PAGE_SIZE - special_size) as u16);
+        put_u16(&mut page, 18, PAGE_SIZE as u16 | 4); // pd_pagesize_version
+        page_set_lsn(&mut page, lsn);
+        page
+    }
+
+    /// A heap page with a tuple of the data at offset 1.
+    fn heap_page(data: &[u8], lsn: Lsn) -> BytesMut {
+        let mut page = empty_page(0, lsn);
+        let mut htup = vec![0u8; SIZE_OF_HEAP_TUPLE_HEADER + 1];
+        put_u32(&mut htup, 0, 700); // xmin
+        put_tid(&mut htup, 7, 1);
+        put_u16(&mut htup, 18, 1); // one attribute
+        put_u16(&mut htup, 20, HEAP_XMAX_INVALID);
+        htup[22] = htup.len() as u8; // t_hoff
+        htup.extend_from_slice(data);
+        Page::new(&mut page)
+            .unwrap()
+            .add_item(&htup, 1, true, true)
+            .unwrap();
+        page
+    }
+
+    fn item(page: &mut BytesMut, offnum: u16) -> Vec<u8> {
+        let page = Page::new(page).unwrap();
+        let (start, len) = page.normal_item(offnum).unwrap();
+        page.0[start..start + len].to_vec()
+    }
+
+    #[test]
+    fn test_restore_image() {
+        let expected = heap_page(b"hello, world", Lsn(0x16B3D30));
+        let lower = get_u16(&expected, 12) as usize;
+        let upper = get_u16(&expected, 14) as usize;
+        let mut image = expected[..lower].to_vec();
+        image.extend_from_slice(&expected[upper..]);
+        let rec = test_record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_INSERT,
+            Some((&image, lower as u16)),
+            &[],
+            &[1, 0, 0], // xl_heap_insert
+        );
+
+        assert!(can_apply(key(), &rec, 14));
+        // The image is of another block
+        let other_key = Key { field6: 8, ..key() };
+        assert!(!can_apply(other_key, &rec, 14));
+
+        let mut page = BytesMut::new();
+        apply(key(), &mut page, Lsn(0x16B3D30), &rec, 14).unwrap();
+        assert_eq!(page, expected);
+    }
+
+    #[test]
+    fn test_heap_hot_update() {
+        let old_lsn = Lsn(0x16B3D30);
+        let lsn = Lsn(0x16B3E00);
+        let mut page = heap_page(b"hello, old world", old_lsn);
+
+        // "hello, " and " world" are taken from the old tuple
+        let mut data = Vec::new();
+        data.put_u16_le(7); // prefix length
+        data.put_u16_le(6); // suffix length
+        data.put_u16_le(0x8001); // t_infomask2, a heap-only tuple
+        data.put_u16_le(HEAP_XMAX_INVALID | 0x2000); // t_infomask
+        data.put_u8(24); // t_hoff
+        data.put_u8(0); // padding
+        data.put_slice(b"new");
+        let mut xlrec = Vec::new();
+        xlrec.put_u32_le(XID); // old_xmax
+        xlrec.put_u16_le(1); // old_offnum
+        xlrec.put_u8(0); // old_infobits_set
+        xlrec.put_u8(
+            pg_constants::XLH_UPDATE_PREFIX_FROM_OLD | pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD,
+        );
+        xlrec.put_u32_le(3); // t_cid
+        xlrec.put_u32_le(0); // new_xmax
+        xlrec.put_u16_le(2); // new_offnum
+        let rec = test_record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_HOT_UPDATE,
+            None,
+            &data,
+            &xlrec,
+        );
+        assert!(can_apply(key(), &rec, 14));
+
+        apply(key(), &mut page, lsn, &rec, 14).unwrap();
+        assert_eq!(page_get_lsn(&page), lsn);
+        assert_eq!(get_u32(&page, 20), XID); // pd_prune_xid
+
+        let old = item(&mut page, 1);
+        assert_eq!(get_u32(&old, 4), XID); // xmax
+        assert_eq!(get_u32(&old, 8), 3); // cmax
+        assert_eq!(&old[12..18], &[0, 0, 7, 0, 2, 0]); // t_ctid
+        assert_eq!(get_u16(&old, 18), 1 | HEAP_HOT_UPDATED);
+        assert_eq!(get_u16(&old, 20), 0);
+
+        let new = item(&mut page, 2);
+        assert_eq!(get_u32(&new, 0), XID); // xmin
+        assert_eq!(get_u32(&new, 4), 0); // xmax
+        assert_eq!(get_u32(&new, 8), 3); // cmin
+        assert_eq!(&new[12..18], &[0, 0, 7, 0, 2, 0]); // t_ctid
+        assert_eq!(get_u16(&new, 18), 0x8001);
+        assert_eq!(&new[24..], b"hello, new world");
+
+        // The page already has the update
+        let updated = page.clone();
+        apply(key(), &mut page, lsn, &rec, 14).unwrap();
+        assert_eq!(page, updated);
+    }
+
+    #[test]
+    fn test_btree_insert_leaf() {
+        let lsn = Lsn(0x16B3E00);
+        let mut page = empty_page(16, Lsn(0x16B3D30));
+        {
+            let mut page = Page::new(&mut page).unwrap();
+            page.add_item(b"bbbbbbbb", 1, false, false).unwrap();
+            page.add_item(b"cccccccc", 2, false, false).unwrap();
+        }
+
+        // Insert before the existing items
+        let rec = test_record(
+            pg_constants::RM_BTREE_ID,
+            pg_constants::XLOG_BTREE_INSERT_LEAF,
+            None,
+            b"aaaaaaaa",
+            &[1, 0], // xl_btree_insert
+        );
+        assert!(can_apply(key(), &rec, 14));
+        apply(key(), &mut page, lsn, &rec, 14).unwrap();
+
+        assert_eq!(page_get_lsn(&page), lsn);
+        assert_eq!(item(&mut page, 1), b"aaaaaaaa");
+        assert_eq!(item(&mut page, 2), b"bbbbbbbb");
+        assert_eq!(item(&mut page, 3), b"cccccccc");
+    }
+
+    #[test]
+    fn test_unsupported_records() {
+        // A non-HOT update
+        let rec = test_record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_UPDATE,
+            None,
+            &[0; 8],
+            &[0; SIZE_OF_HEAP_UPDATE],
+        );
+        assert!(!can_apply(key(), &rec, 14));
+
+        // A record of a relation other than a heap or a btree
+        let rec = test_record(0x0F, 0, None, &[0; 8], &[0; 2]);
+        assert!(!can_apply(key(), &rec, 14));
+
+        // A malformed btree insertion
+        let rec = test_record(
+            pg_constants::RM_BTREE_ID,
+            pg_constants::XLOG_BTREE_INSERT_LEAF,
+            None,
+            b"aaaaaaaa",
+            &[5, 0],
+        );
+        let mut page = empty_page(16, Lsn(0x16B3D30));
+        assert!(apply(key(), &mut page, Lsn(0x16B3E00), &rec, 14).is_err());
+    }
+}
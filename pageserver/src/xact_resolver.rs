@@ -0,0 +1,520 @@
+//! Client of the transaction resolver of sunstorm, which decides the outcome of the
+//! transactions spanning regions.
+//!
+//! The outcome of a transaction spanning regions reaches the WAL of a region some
+//! time after the resolver decided it, so until then, the CLOG of the region shows
+//! the transaction in progress, even though it's committed or aborted. With
+//! `xact_resolver_endpoint` configured, the page service asks the resolver for the
+//! outcomes of the transactions in progress on the CLOG pages it serves, and sets
+//! them on the pages.
+//!
+//! The final outcomes are cached. Most of the transactions in progress are local
+//! to the region, and the resolver keeps answering that they are in progress, so
+//! that answer is cached too, for [`IN_PROGRESS_TTL`], not to ask again on every
+//! read of the page. If the resolver fails, or doesn't answer within
+//! `xact_resolver_timeout`, the page is served as it is in the WAL, like without a
+//! resolver.
+//!
+//! The coordination of the transactions across regions also makes them wait for
+//! each other across regions, see [`wait_graph`] for the detection of the deadlocks.
+
+pub mod wait_graph;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure};
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::OnceCell;
+use pageserver_api::models::{XactOutcome, XactOutcomesRequest, XactOutcomesResponse};
+use postgres_ffi::v14::nonrelfile_utils::{transaction_id_get_status, transaction_id_set_status};
+use postgres_ffi::{pg_constants, transaction_id_precedes, v14, v15, TransactionId};
+use reqwest::Url;
+use tracing::*;
+use utils::id::{RegionId, TenantTimelineId};
+use utils::lsn::Lsn;
+
+use crate::context::RequestContext;
+use crate::metrics::{XACT_RESOLVER_CACHE_HITS, XACT_RESOLVER_REQUESTS};
+use crate::tenant::Timeline;
+
+/// Number of outcomes kept in the cache, the oldest ones are evicted first.
+const CACHE_CAPACITY: usize = 64 * 1024;
+
+/// How long the resolver isn't asked again about a transaction it said was in
+/// progress.
+const IN_PROGRESS_TTL: Duration = Duration::from_secs(1);
+
+static XACT_RESOLVER: OnceCell<XactResolver> = OnceCell::new();
+
+/// Sets up the client of the resolver at `endpoint`. This must be called once at
+/// page server startup, if a resolver is configured.
+pub fn init(endpoint: Url, timeout: Duration) {
+    if XACT_RESOLVER
+        .set(XactResolver::new(endpoint, timeout))
+        .is_err()
+    {
+        panic!("transaction resolver already initialized");
+    }
+}
+
+/// The client of the resolver, if one is configured.
+pub fn get() -> Option<&'static XactResolver> {
+    XACT_RESOLVER.get()
+}
+
+pub struct XactResolver {
+    client: reqwest::Client,
+    endpoint: Url,
+    timeout: Duration,
+    cache: Mutex<OutcomeCache>,
+}
+
+impl XactResolver {
+    fn new(endpoint: Url, timeout: Duration) -> Self {
+        XactResolver {
+            client: reqwest::Client::new(),
+            endpoint,
+            timeout,
+            cache: Mutex::new(OutcomeCache::new(CACHE_CAPACITY)),
+        }
+    }
+
+    /// Sets the outcomes of the transactions in progress on a CLOG page of the
+    /// timeline at `lsn`. If they can't be resolved, the page is returned as it is.
+    pub async fn resolve_clog_page(
+        &self,
+        timeline: &Timeline,
+        segno: u32,
+        blkno: u32,
+        lsn: Lsn,
+        page: Bytes,
+        ctx: &RequestContext,
+    ) -> Bytes {
+        match self
+            .try_resolve_clog_page(timeline, segno, blkno, lsn, &page, ctx)
+            .await
+        {
+            Ok(Some(resolved)) => resolved,
+            Ok(None) => page,
+            Err(e) => {
+                warn!("could not resolve the transactions of CLOG page {segno}/{blkno}: {e:#}");
+                page
+            }
+        }
+    }
+
+    async fn try_resolve_clog_page(
+        &self,
+        timeline: &Timeline,
+        segno: u32,
+        blkno: u32,
+        lsn: Lsn,
+        page: &Bytes,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let checkpoint = timeline.get_checkpoint(lsn, ctx).await?;
+        let next_xid = next_xid(&checkpoint, timeline.pg_version)?;
+        let xids = in_progress_xids(segno, blkno, next_xid, page);
+        if xids.is_empty() {
+            return Ok(None);
+        }
+
+        let ttid = TenantTimelineId::new(timeline.tenant_id, timeline.timeline_id);
+        let outcomes = self.outcomes(ttid, timeline.region_id, &xids).await?;
+        let mut resolved = BytesMut::from(&page[..]);
+        let count = set_outcomes(&mut resolved, &xids, &outcomes);
+        debug!(
+            "resolved {count} of the {} transactions in progress on CLOG page {segno}/{blkno}",
+            xids.len()
+        );
+        Ok((count > 0).then(|| resolved.freeze()))
+    }
+
+    /// The outcomes of transactions of `region` of a timeline, in the order of `xids`,
+    /// from the cache, or else from the resolver.
+    pub async fn outcomes(
+        &self,
+        ttid: TenantTimelineId,
+        region: RegionId,
+        xids: &[TransactionId],
+    ) -> anyhow::Result<Vec<XactOutcome>> {
+        let now = Instant::now();
+        let cached: Vec<Option<XactOutcome>> = {
+            let cache = self.cache.lock().unwrap();
+            xids.iter()
+                .map(|xid| cache.get((ttid, region, *xid), now))
+                .collect()
+        };
+        let missing: Vec<TransactionId> = xids
+            .iter()
+            .zip(&cached)
+            .filter(|(_, outcome)| outcome.is_none())
+            .map(|(xid, _)| *xid)
+            .collect();
+        XACT_RESOLVER_CACHE_HITS.inc_by((xids.len() - missing.len()) as u64);
+
+        let mut fetched = HashMap::new();
+        if !missing.is_empty() {
+            let outcomes = self.fetch(ttid, region, missing.clone()).await?;
+            let now = Instant::now();
+            let mut cache = self.cache.lock().unwrap();
+            for (xid, outcome) in missing.into_iter().zip(outcomes) {
+                cache.insert((ttid, region, xid), outcome, now);
+                fetched.insert(xid, outcome);
+            }
+        }
+
+        Ok(xids
+            .iter()
+            .zip(cached)
+            .map(|(xid, outcome)| outcome.unwrap_or_else(|| fetched[xid]))
+            .collect())
+    }
+
+    async fn fetch(
+        &self,
+        ttid: TenantTimelineId,
+        region: RegionId,
+        xids: Vec<TransactionId>,
+    ) -> anyhow::Result<Vec<XactOutcome>> {
+        let count = xids.len();
+        let request = self
+            .client
+            .post(self.endpoint.clone())
+            .json(&XactOutcomesRequest {
+                tenant_id: ttid.tenant_id,
+                timeline_id: ttid.timeline_id,
+                region,
+                xids,
+            });
+        let response = tokio::time::timeout(self.timeout, async {
+            let response = request.send().await?.error_for_status()?;
+            response.json::<XactOutcomesResponse>().await
+        })
+        .await;
+
+        let response = match response {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                XACT_RESOLVER_REQUESTS.with_label_values(&["error"]).inc();
+                return Err(anyhow::Error::new(e).context("transaction resolver request failed"));
+            }
+            Err(_) => {
+                XACT_RESOLVER_REQUESTS.with_label_values(&["timeout"]).inc();
+                anyhow::bail!(
+                    "transaction resolver didn't answer within {:?}",
+                    self.timeout
+                );
+            }
+        };
+        XACT_RESOLVER_REQUESTS.with_label_values(&["ok"]).inc();
+        ensure!(
+            response.outcomes.len() == count,
+            "transaction resolver returned {} outcomes for {count} transactions",
+            response.outcomes.len()
+        );
+        Ok(response.outcomes)
+    }
+}
+
+/// A transaction of a region of a timeline. The xids of the timelines are unrelated.
+type OutcomeKey = (TenantTimelineId, RegionId, TransactionId);
+
+/// The outcomes of transactions, by timeline, region and xid. The outcomes which
+/// aren't final expire after [`IN_PROGRESS_TTL`].
+struct OutcomeCache {
+    capacity: usize,
+    outcomes: HashMap<OutcomeKey, (XactOutcome, Option<Instant>)>,
+    /// The keys of `outcomes`, the oldest first.
+    order: VecDeque<OutcomeKey>,
+}
+
+impl OutcomeCache {
+    fn new(capacity: usize) -> Self {
+        OutcomeCache {
+            capacity,
+            outcomes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: OutcomeKey, now: Instant) -> Option<XactOutcome> {
+        match self.outcomes.get(&key) {
+            Some((_, Some(expires_at))) if *expires_at <= now => None,
+            Some((outcome, _)) => Some(*outcome),
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: OutcomeKey, outcome: XactOutcome, now: Instant) {
+        let expires_at = (!outcome.is_final()).then(|| now + IN_PROGRESS_TTL);
+        if let Some((old, _)) = self.outcomes.get(&key) {
+            if !old.is_final() {
+                self.outcomes.insert(key, (outcome, expires_at));
+            }
+            return;
+        }
+        self.outcomes.insert(key, (outcome, expires_at));
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.outcomes.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The first xid that isn't assigned yet, from the checkpoint of a timeline of the
+/// Postgres version.
+fn next_xid(checkpoint: &[u8], pg_version: u32) -> anyhow::Result<TransactionId> {
+    let next_xid = match pg_version {
+        14 => v14::CheckPoint::decode(checkpoint)?.nextXid.value,
+        15 => v15::CheckPoint::decode(checkpoint)?.nextXid.value,
+        _ => bail!("Unknown version {}", pg_version),
+    };
+    Ok(next_xid as TransactionId)
+}
+
+/// The xids of the transactions in progress on a CLOG page, before `next_xid`, the
+/// first xid that isn't assigned yet.
+fn in_progress_xids(
+    segno: u32,
+    blkno: u32,
+    next_xid: TransactionId,
+    page: &[u8],
+) -> Vec<TransactionId> {
+    let pageno = segno * pg_constants::SLRU_PAGES_PER_SEGMENT + blkno;
+    let first_xid = pageno.wrapping_mul(pg_constants::CLOG_XACTS_PER_PAGE);
+    (0..pg_constants::CLOG_XACTS_PER_PAGE)
+        .map(|i| first_xid.wrapping_add(i))
+        .filter(|xid| *xid >= pg_constants::FIRST_NORMAL_TRANSACTION_ID)
+        .take_while(|xid| transaction_id_precedes(*xid, next_xid))
+        .filter(|xid| {
+            transaction_id_get_status(*xid, page) == pg_constants::TRANSACTION_STATUS_IN_PROGRESS
+        })
+        .collect()
+}
+
+/// Sets the final outcomes of the transactions on a CLOG page. Returns how many
+/// were set.
+fn set_outcomes(page: &mut BytesMut, xids: &[TransactionId], outcomes: &[XactOutcome]) -> usize {
+    let mut count = 0;
+    for (xid, outcome) in xids.iter().zip(outcomes) {
+        let status = match outcome {
+            XactOutcome::InProgress => continue,
+            XactOutcome::Committed => pg_constants::TRANSACTION_STATUS_COMMITTED,
+            XactOutcome::Aborted => pg_constants::TRANSACTION_STATUS_ABORTED,
+        };
+        transaction_id_set_status(*xid, status, page);
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Server};
+    use postgres_ffi::BLCKSZ;
+
+    use super::*;
+
+    #[test]
+    fn resolve_page() {
+        let mut page = BytesMut::zeroed(BLCKSZ as usize);
+        transaction_id_set_status(3, pg_constants::TRANSACTION_STATUS_COMMITTED, &mut page);
+        transaction_id_set_status(5, pg_constants::TRANSACTION_STATUS_ABORTED, &mut page);
+
+        // xids 0 to 2 are special, and 8 isn't assigned yet
+        let xids = in_progress_xids(0, 0, 8, &page);
+        assert_eq!(xids, vec![4, 6, 7]);
+
+        let outcomes = [
+            XactOutcome::Committed,
+            XactOutcome::InProgress,
+            XactOutcome::Aborted,
+        ];
+        assert_eq!(set_outcomes(&mut page, &xids, &outcomes), 2);
+        assert_eq!(in_progress_xids(0, 0, 8, &page), vec![6]);
+        assert_eq!(
+            transaction_id_get_status(7, &page),
+            pg_constants::TRANSACTION_STATUS_ABORTED
+        );
+
+        // the xids of a page of another segment
+        let first_xid =
+            (pg_constants::SLRU_PAGES_PER_SEGMENT + 1) * pg_constants::CLOG_XACTS_PER_PAGE;
+        let page = BytesMut::zeroed(BLCKSZ as usize);
+        assert_eq!(
+            in_progress_xids(1, 1, first_xid + 2, &page),
+            vec![first_xid, first_xid + 1]
+        );
+    }
+
+    fn key(tenant: &TenantTimelineId, region: u8, xid: TransactionId) -> OutcomeKey {
+        (*tenant, RegionId(region), xid)
+    }
+
+    #[test]
+    fn cache_evicts_the_oldest() {
+        let now = Instant::now();
+        let t = TenantTimelineId::generate();
+        let mut cache = OutcomeCache::new(2);
+        cache.insert(key(&t, 1, 10), XactOutcome::Committed, now);
+        cache.insert(key(&t, 2, 10), XactOutcome::Aborted, now);
+        cache.insert(key(&t, 1, 10), XactOutcome::Committed, now);
+        assert_eq!(cache.get(key(&t, 2, 10), now), Some(XactOutcome::Aborted));
+
+        cache.insert(key(&t, 1, 11), XactOutcome::Committed, now);
+        assert_eq!(cache.get(key(&t, 1, 10), now), None);
+        assert_eq!(cache.get(key(&t, 2, 10), now), Some(XactOutcome::Aborted));
+        assert_eq!(cache.get(key(&t, 1, 11), now), Some(XactOutcome::Committed));
+    }
+
+    #[test]
+    fn cache_expires_in_progress() {
+        let now = Instant::now();
+        let later = now + IN_PROGRESS_TTL;
+        let t = TenantTimelineId::generate();
+        let mut cache = OutcomeCache::new(10);
+        cache.insert(key(&t, 1, 10), XactOutcome::InProgress, now);
+        cache.insert(key(&t, 1, 11), XactOutcome::Committed, now);
+        assert_eq!(
+            cache.get(key(&t, 1, 10), now),
+            Some(XactOutcome::InProgress)
+        );
+        assert_eq!(cache.get(key(&t, 1, 10), later), None);
+        assert_eq!(
+            cache.get(key(&t, 1, 11), later),
+            Some(XactOutcome::Committed)
+        );
+
+        // a final outcome replaces the one in progress, but not the other way round
+        cache.insert(key(&t, 1, 10), XactOutcome::Aborted, later);
+        cache.insert(key(&t, 1, 11), XactOutcome::InProgress, later);
+        assert_eq!(cache.get(key(&t, 1, 10), later), Some(XactOutcome::Aborted));
+        assert_eq!(
+            cache.get(key(&t, 1, 11), later),
+            Some(XactOutcome::Committed)
+        );
+    }
+
+    #[test]
+    fn cache_is_per_tenant() {
+        let now = Instant::now();
+        let t1 = TenantTimelineId::generate();
+        let t2 = TenantTimelineId::generate();
+        let mut cache = OutcomeCache::new(10);
+        // the same xid is a different transaction in each tenant
+        cache.insert(key(&t1, 1, 10), XactOutcome::Committed, now);
+        assert_eq!(cache.get(key(&t2, 1, 10), now), None);
+        cache.insert(key(&t2, 1, 10), XactOutcome::Aborted, now);
+        assert_eq!(
+            cache.get(key(&t1, 1, 10), now),
+            Some(XactOutcome::Committed)
+        );
+        assert_eq!(cache.get(key(&t2, 1, 10), now), Some(XactOutcome::Aborted));
+    }
+
+    #[test]
+    fn next_xid_of_version() {
+        for pg_version in [14, 15] {
+            let checkpoint = match pg_version {
+                14 => {
+                    let mut checkpoint = v14::CheckPoint::default();
+                    checkpoint.nextXid.value = 1234;
+                    checkpoint.encode().unwrap()
+                }
+                _ => {
+                    let mut checkpoint = v15::CheckPoint::default();
+                    checkpoint.nextXid.value = 1234;
+                    checkpoint.encode().unwrap()
+                }
+            };
+            assert_eq!(next_xid(&checkpoint, pg_version).unwrap(), 1234);
+        }
+        assert!(next_xid(&[], 13).is_err());
+    }
+
+    /// Starts a resolver which answers that the even xids are committed, and the odd
+    /// ones in progress, except that all the xids of `aborting` are aborted. Returns
+    /// its endpoint and the xids it was asked about.
+    fn serve_resolver(aborting: TenantTimelineId) -> (Url, Arc<Mutex<Vec<TransactionId>>>) {
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let asked_ = Arc::clone(&asked);
+        let make_service = make_service_fn(move |_| {
+            let asked = Arc::clone(&asked_);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let asked = Arc::clone(&asked);
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await?;
+                        let request: XactOutcomesRequest = serde_json::from_slice(&body).unwrap();
+                        asked.lock().unwrap().extend(&request.xids);
+                        let aborting = request.tenant_id == aborting.tenant_id
+                            && request.timeline_id == aborting.timeline_id;
+                        let outcomes = request
+                            .xids
+                            .iter()
+                            .map(|xid| match xid % 2 {
+                                _ if aborting => XactOutcome::Aborted,
+                                0 => XactOutcome::Committed,
+                                _ => XactOutcome::InProgress,
+                            })
+                            .collect();
+                        let response = XactOutcomesResponse { outcomes };
+                        let body = serde_json::to_vec(&response).unwrap();
+                        Ok::<_, hyper::Error>(hyper::Response::new(Body::from(body)))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = Url::parse(&format!("http://{}/v1/xact_outcomes", server.local_addr()));
+        tokio::spawn(server);
+        (endpoint.unwrap(), asked)
+    }
+
+    #[tokio::test]
+    async fn outcomes_are_cached() {
+        let t1 = TenantTimelineId::generate();
+        let t2 = TenantTimelineId::generate();
+        let (endpoint, asked) = serve_resolver(t2);
+        let resolver = XactResolver::new(endpoint, Duration::from_secs(10));
+        let outcomes = resolver.outcomes(t1, RegionId(1), &[10, 11]).await.unwrap();
+        assert_eq!(outcomes, [XactOutcome::Committed, XactOutcome::InProgress]);
+        assert_eq!(std::mem::take(&mut *asked.lock().unwrap()), [10, 11]);
+
+        // only the new xid is asked about
+        let outcomes = resolver
+            .outcomes(t1, RegionId(1), &[10, 11, 12])
+            .await
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                XactOutcome::Committed,
+                XactOutcome::InProgress,
+                XactOutcome::Committed
+            ]
+        );
+        assert_eq!(std::mem::take(&mut *asked.lock().unwrap()), [12]);
+
+        // the cache is per region
+        resolver.outcomes(t1, RegionId(2), &[10]).await.unwrap();
+        assert_eq!(std::mem::take(&mut *asked.lock().unwrap()), [10]);
+
+        // ... and per tenant, the same xid of another tenant has its own outcome
+        let outcomes = resolver.outcomes(t2, RegionId(1), &[10]).await.unwrap();
+        assert_eq!(outcomes, [XactOutcome::Aborted]);
+        assert_eq!(std::mem::take(&mut *asked.lock().unwrap()), [10]);
+        let outcomes = resolver.outcomes(t1, RegionId(1), &[10]).await.unwrap();
+        assert_eq!(outcomes, [XactOutcome::Committed]);
+        assert!(asked.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! The wait-for graph of the transactions of a tenant across the regions, to detect
+//! the deadlocks that no region sees on its own.
+//!
+//! A transaction waiting for a lock held by a transaction of another region can be
+//! part of a cycle going through several regions, which the deadlock detector of
+//! each compute misses. The transaction coordination layer of each region reports
+//! the wait-for edges of its transactions, with `PUT .../wait_graph/:region`, each
+//! report replacing the previous one of the region, and a detector service reads the
+//! graph of all the regions, with its cycles, with `GET .../wait_graph`. A report
+//! expires after [`REPORT_TTL`], so that the edges of a region which stopped
+//! reporting don't make up deadlocks.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use pageserver_api::models::{GlobalXid, WaitEdge, WaitGraphInfo};
+use utils::id::RegionId;
+
+/// How long the edges of a report are kept if the region doesn't report again.
+pub const REPORT_TTL: Duration = Duration::from_secs(30);
+
+/// The wait-for graph of a tenant, by the region which reported the edges.
+#[derive(Default)]
+pub struct WaitGraph {
+    reports: Mutex<HashMap<RegionId, Report>>,
+}
+
+struct Report {
+    reported_at: Instant,
+    edges: Vec<WaitEdge>,
+}
+
+impl WaitGraph {
+    /// Replaces the edges of the transactions of `region`, which must all be the
+    /// waiters.
+    pub fn report(&self, region: RegionId, edges: Vec<WaitEdge>) -> anyhow::Result<()> {
+        self.report_at(region, edges, Instant::now())
+    }
+
+    fn report_at(
+        &self,
+        region: RegionId,
+        edges: Vec<WaitEdge>,
+        now: Instant,
+    ) -> anyhow::Result<()> {
+        if let Some(edge) = edges.iter().find(|edge| edge.waiter.region != region) {
+            bail!(
+                "region {region} reported a wait of transaction {} of region {}",
+                edge.waiter.xid,
+                edge.waiter.region
+            );
+        }
+        let report = Report {
+            reported_at: now,
+            edges,
+        };
+        self.reports.lock().unwrap().insert(region, report);
+        Ok(())
+    }
+
+    /// The edges of the unexpired reports, and the cycles they make.
+    pub fn info(&self) -> WaitGraphInfo {
+        self.info_at(Instant::now())
+    }
+
+    fn info_at(&self, now: Instant) -> WaitGraphInfo {
+        let mut edges: Vec<WaitEdge> = {
+            let mut reports = self.reports.lock().unwrap();
+            reports.retain(|_, report| now.duration_since(report.reported_at) < REPORT_TTL);
+            reports
+                .values()
+                .flat_map(|report| report.edges.iter().copied())
+                .collect()
+        };
+        edges.sort_by_key(|edge| (edge.waiter, edge.holder));
+        edges.dedup();
+        let deadlocks = find_cycles(&edges);
+        WaitGraphInfo { edges, deadlocks }
+    }
+}
+
+/// The cycles found by a depth-first search of the graph, each from its smallest
+/// transaction. Of cycles which share edges, only some may be found, but there's
+/// at least one in each group of transactions that wait for each other.
+fn find_cycles(edges: &[WaitEdge]) -> Vec<Vec<GlobalXid>> {
+    let mut graph: BTreeMap<GlobalXid, Vec<GlobalXid>> = BTreeMap::new();
+    for edge in edges {
+        graph.entry(edge.waiter).or_default().push(edge.holder);
+    }
+
+    enum Visit {
+        OnPath,
+        Done,
+    }
+    let mut visits = HashMap::new();
+    let mut cycles = BTreeSet::new();
+    for &start in graph.keys() {
+        if visits.contains_key(&start) {
+            continue;
+        }
+        // the path from `start`, with the index of the next holder to visit of each
+        let mut path = vec![(start, 0)];
+        visits.insert(start, Visit::OnPath);
+        while let Some((xid, next)) = path.last_mut() {
+            let xid = *xid;
+            let holders = graph.get(&xid).map(Vec::as_slice).unwrap_or_default();
+            let Some(&holder) = holders.get(*next) else {
+                visits.insert(xid, Visit::Done);
+                path.pop();
+                continue;
+            };
+            *next += 1;
+            match visits.get(&holder) {
+                None => {
+                    visits.insert(holder, Visit::OnPath);
+                    path.push((holder, 0));
+                }
+                Some(Visit::OnPath) => {
+                    let from = path.iter().position(|(x, _)| *x == holder).unwrap();
+                    let cycle: Vec<GlobalXid> = path[from..].iter().map(|(x, _)| *x).collect();
+                    cycles.insert(rotate_to_smallest(cycle));
+                }
+                Some(Visit::Done) => {}
+            }
+        }
+    }
+    cycles.into_iter().collect()
+}
+
+fn rotate_to_smallest(mut cycle: Vec<GlobalXid>) -> Vec<GlobalXid> {
+    if let Some((smallest, _)) = cycle.iter().enumerate().min_by_key(|(_, xid)| **xid) {
+        cycle.rotate_left(smallest);
+    }
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xid(region: u8, xid: u32) -> GlobalXid {
+        GlobalXid {
+            region: RegionId(region),
+            xid,
+        }
+    }
+
+    fn edge(waiter: GlobalXid, holder: GlobalXid) -> WaitEdge {
+        WaitEdge { waiter, holder }
+    }
+
+    #[test]
+    fn deadlock_across_regions() {
+        let graph = WaitGraph::default();
+        let start = Instant::now();
+
+        // 1/10 -> 2/20 -> 3/30 -> 1/10, and 2/21 waits for the cycle
+        graph
+            .report_at(RegionId(2), vec![edge(xid(2, 20), xid(3, 30))], start)
+            .unwrap();
+        graph
+            .report_at(RegionId(3), vec![edge(xid(3, 30), xid(1, 10))], start)
+            .unwrap();
+        assert!(graph.info_at(start).deadlocks.is_empty());
+
+        let edges = vec![edge(xid(1, 10), xid(2, 20)), edge(xid(1, 11), xid(2, 21))];
+        graph.report_at(RegionId(1), edges, start).unwrap();
+        graph
+            .report_at(
+                RegionId(2),
+                vec![edge(xid(2, 20), xid(3, 30)), edge(xid(2, 21), xid(2, 20))],
+                start,
+            )
+            .unwrap();
+        let info = graph.info_at(start);
+        assert_eq!(info.edges.len(), 5);
+        assert_eq!(
+            info.deadlocks,
+            vec![vec![xid(1, 10), xid(2, 20), xid(3, 30)]]
+        );
+
+        // a report replaces the previous one of the region
+        graph.report_at(RegionId(3), vec![], start).unwrap();
+        assert!(graph.info_at(start).deadlocks.is_empty());
+    }
+
+    #[test]
+    fn reports_expire() {
+        let graph = WaitGraph::default();
+        let start = Instant::now();
+        graph
+            .report_at(RegionId(1), vec![edge(xid(1, 10), xid(2, 20))], start)
+            .unwrap();
+        graph
+            .report_at(
+                RegionId(2),
+                vec![edge(xid(2, 20), xid(1, 10))],
+                start + REPORT_TTL / 2,
+            )
+            .unwrap();
+        assert_eq!(graph.info_at(start + REPORT_TTL / 2).deadlocks.len(), 1);
+
+        let info = graph.info_at(start + REPORT_TTL);
+        assert_eq!(info.edges, vec![edge(xid(2, 20), xid(1, 10))]);
+        assert!(info.deadlocks.is_empty());
+    }
+
+    #[test]
+    fn waiters_of_the_reporting_region() {
+        let graph = WaitGraph::default();
+        graph
+            .report(RegionId(1), vec![edge(xid(2, 20), xid(1, 10))])
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,249 @@
+//! GetPage latency while WAL ingest and compaction run concurrently.
+//!
+//! A pageserver runs in the process, see [`TestPageserverBuilder`]. Each benchmark
+//! creates a timeline holding a relation of [`REL_BLOCKS`] page images, and then
+//! requests random blocks of it at the latest LSN over a pagestream connection,
+//! while:
+//!
+//! * `idle`: nothing else happens on the timeline.
+//! * `ingest`: a task keeps writing new images of random blocks, flushing layers
+//!   at a small checkpoint distance.
+//! * `ingest_compaction`: like `ingest`, and another task compacts the timeline in
+//!   a loop.
+//!
+//! The images are written to the timeline directly rather than decoded from WAL, so
+//! that serving the pages doesn't depend on the WAL redo process.
+//!
+//! Criterion reports the mean time of a request. As the tail latency is what
+//! compaction affects the most, the percentiles of all the requests of a benchmark,
+//! warm-up included, are printed after it.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion};
+use pageserver::context::{DownloadBehavior, RequestContext};
+use pageserver::task_mgr::TaskKind;
+use pageserver::tenant::{mgr, Timeline};
+use pageserver_api::models::{
+    PagestreamGetPageRequest, TenantCreateRequest, TimelineCreateRequest,
+};
+use pageserver_api::reltag::RelTag;
+use pageserver_test_support::{print_latency_percentiles, TestPageserver, TestPageserverBuilder};
+use postgres_ffi::{pg_constants::DEFAULTTABLESPACE_OID, BLCKSZ};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use utils::id::{RegionId, TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+const REL: RelTag = RelTag {
+    forknum: 0,
+    spcnode: DEFAULTTABLESPACE_OID,
+    dbnode: 111111,
+    relnode: 222222,
+};
+
+/// 80 MiB of pages, more than the page cache of the default config.
+const REL_BLOCKS: u32 = 10240;
+
+/// The ingest task writes this many pages per millisecond, 32 MiB/s.
+const INGEST_PAGES_PER_MS: usize = 4;
+
+/// Small enough for layers to be flushed every few seconds of ingest, and for
+/// compaction to find enough L0 layers to work on.
+const CHECKPOINT_DISTANCE: u64 = 16 * 1024 * 1024;
+const COMPACTION_THRESHOLD: usize = 3;
+const COMPACTION_TARGET_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Workload {
+    Idle,
+    Ingest,
+    IngestCompaction,
+}
+
+impl Workload {
+    fn name(self) -> &'static str {
+        match self {
+            Workload::Idle => "idle",
+            Workload::Ingest => "ingest",
+            Workload::IngestCompaction => "ingest_compaction",
+        }
+    }
+}
+
+fn bench_getpage(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let pageserver = rt
+        .block_on(TestPageserverBuilder::new("bench_getpage").start())
+        .unwrap();
+
+    let mut group = c.benchmark_group("getpage");
+    for workload in [Workload::Idle, Workload::Ingest, Workload::IngestCompaction] {
+        let (tenant_id, timeline) = rt.block_on(create_timeline(pageserver)).unwrap();
+        let cancel = CancellationToken::new();
+        let tasks = start_workload(&rt, workload, &timeline, &cancel);
+
+        let mut client = rt
+            .block_on(pageserver.pagestream(tenant_id, timeline.timeline_id))
+            .unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut latencies = Vec::new();
+        group.bench_function(workload.name(), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let request = PagestreamGetPageRequest {
+                            latest: true,
+                            lsn: timeline.get_last_record_lsn(),
+                            region: RegionId(0),
+                            rel: REL,
+                            blkno: rng.gen_range(0..REL_BLOCKS),
+                        };
+                        let started_at = Instant::now();
+                        client.get_page(request).await.unwrap();
+                        let latency = started_at.elapsed();
+                        latencies.push(latency);
+                        total += latency;
+                    }
+                    total
+                })
+            })
+        });
+
+        cancel.cancel();
+        for task in tasks {
+            rt.block_on(task).unwrap().unwrap();
+        }
+        let name = format!("getpage/{}", workload.name());
+        print_latency_percentiles(&name, "requests", &mut latencies);
+    }
+    group.finish();
+}
+
+/// Creates a tenant with a timeline, and fills the relation of the benchmark.
+async fn create_timeline(pageserver: &TestPageserver) -> anyhow::Result<(TenantId, Arc<Timeline>)> {
+    let client = pageserver.http_client();
+    let tenant_id = client
+        .tenant_create(
+            &TenantCreateRequest::builder(TenantId::generate())
+                .checkpoint_distance(CHECKPOINT_DISTANCE)
+                .compaction_target_size(COMPACTION_TARGET_SIZE)
+                .compaction_threshold(COMPACTION_THRESHOLD)
+                // the benchmark compacts the timeline itself
+                .compaction_period(Duration::ZERO)
+                .gc_period(Duration::ZERO)
+                .build()?,
+        )
+        .await?;
+    let timeline_id = TimelineId::generate();
+    client
+        .timeline_create(
+            tenant_id,
+            &TimelineCreateRequest::builder(timeline_id).build()?,
+        )
+        .await?;
+    let timeline = mgr::get_tenant(tenant_id, true)
+        .await?
+        .get_timeline(timeline_id, true)?;
+
+    let ctx = RequestContext::new(
+        TaskKind::WalReceiverConnectionHandler,
+        DownloadBehavior::Error,
+    );
+    let mut lsn = next_lsn(&timeline);
+    let mut modification = timeline.begin_modification(lsn);
+    modification
+        .put_relmap_file(REL.spcnode, REL.dbnode, Bytes::from_static(&[0; 512]), &ctx)
+        .await?;
+    modification.commit().await?;
+
+    lsn = next_lsn(&timeline);
+    let mut modification = timeline.begin_modification(lsn);
+    modification.put_rel_creation(REL, 0, &ctx).await?;
+    modification.commit().await?;
+
+    for blocks in (0..REL_BLOCKS).collect::<Vec<_>>().chunks(256) {
+        lsn = next_lsn(&timeline);
+        let mut modification = timeline.begin_modification(lsn);
+        for &blkno in blocks {
+            modification.put_rel_page_image(REL, blkno, page_image(lsn, blkno))?;
+        }
+        modification
+            .put_rel_extend(REL, blocks[blocks.len() - 1] + 1, &ctx)
+            .await?;
+        modification.commit().await?;
+    }
+    timeline.freeze_and_flush().await?;
+
+    Ok((tenant_id, timeline))
+}
+
+fn start_workload(
+    rt: &Runtime,
+    workload: Workload,
+    timeline: &Arc<Timeline>,
+    cancel: &CancellationToken,
+) -> Vec<JoinHandle<anyhow::Result<()>>> {
+    let mut tasks = Vec::new();
+    if workload == Workload::Ingest || workload == Workload::IngestCompaction {
+        tasks.push(rt.spawn(ingest(Arc::clone(timeline), cancel.clone())));
+    }
+    if workload == Workload::IngestCompaction {
+        tasks.push(rt.spawn(compact(Arc::clone(timeline), cancel.clone())));
+    }
+    tasks
+}
+
+async fn ingest(timeline: Arc<Timeline>, cancel: CancellationToken) -> anyhow::Result<()> {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut interval = tokio::time::interval(Duration::from_millis(1));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    while !cancel.is_cancelled() {
+        interval.tick().await;
+        let lsn = next_lsn(&timeline);
+        let mut modification = timeline.begin_modification(lsn);
+        for _ in 0..INGEST_PAGES_PER_MS {
+            let blkno = rng.gen_range(0..REL_BLOCKS);
+            modification.put_rel_page_image(REL, blkno, page_image(lsn, blkno))?;
+        }
+        modification.commit().await?;
+        timeline.check_checkpoint_distance().await?;
+    }
+    Ok(())
+}
+
+async fn compact(timeline: Arc<Timeline>, cancel: CancellationToken) -> anyhow::Result<()> {
+    let ctx = RequestContext::new(TaskKind::Compaction, DownloadBehavior::Error);
+    while !cancel.is_cancelled() {
+        timeline.compact(&cancel, &ctx).await?;
+        tokio::select! {
+            _ = cancel.cancelled() => {}
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The LSN of the next write, right after the last one.
+fn next_lsn(timeline: &Timeline) -> Lsn {
+    Lsn(timeline.get_last_record_lsn().0 + 8)
+}
+
+fn page_image(lsn: Lsn, blkno: u32) -> Bytes {
+    let mut page = vec![0u8; BLCKSZ as usize];
+    page[0..8].copy_from_slice(&lsn.0.to_le_bytes());
+    page[8..12].copy_from_slice(&blkno.to_le_bytes());
+    Bytes::from(page)
+}
+
+criterion_group!(benches, bench_getpage);
+criterion_main!(benches);
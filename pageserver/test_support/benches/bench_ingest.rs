@@ -0,0 +1,183 @@
+//! Throughput of WAL ingest into the open in-memory layer of a timeline.
+//!
+//! A pageserver runs in the process, see [`TestPageserverBuilder`]. Each benchmark
+//! writes decoded WAL records of random blocks of a relation to a timeline, the way
+//! the WAL receiver does, committing them every `ingest_batch_size` records, for a
+//! few batch sizes. Most records are small, like heap inserts, and every
+//! [`FULL_PAGE_IMAGE_EVERY`]th one is a full page image. The layers are flushed when
+//! they reach the checkpoint distance, as during the WAL receiver's ingest.
+//!
+//! Criterion reports the throughput in bytes of WAL record payloads. Compare
+//! two commits with `--save-baseline` and `--baseline`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pageserver::context::{DownloadBehavior, RequestContext};
+use pageserver::task_mgr::TaskKind;
+use pageserver::tenant::{mgr, Timeline};
+use pageserver::walrecord::NeonWalRecord;
+use pageserver_api::models::{TenantCreateRequest, TimelineCreateRequest};
+use pageserver_api::reltag::RelTag;
+use pageserver_test_support::{TestPageserver, TestPageserverBuilder};
+use postgres_ffi::{pg_constants::DEFAULTTABLESPACE_OID, BLCKSZ};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+const REL: RelTag = RelTag {
+    forknum: 0,
+    spcnode: DEFAULTTABLESPACE_OID,
+    dbnode: 111111,
+    relnode: 222222,
+};
+
+const REL_BLOCKS: u32 = 10240;
+
+/// Number of records ingested per iteration.
+const RECORDS_PER_ITER: usize = 1000;
+
+/// The size of a small record, about that of a heap insert of a narrow row.
+const RECORD_SIZE: usize = 100;
+
+const FULL_PAGE_IMAGE_EVERY: usize = 20;
+
+fn bench_ingest(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let pageserver = rt
+        .block_on(TestPageserverBuilder::new("bench_ingest").start())
+        .unwrap();
+    let timeline = rt.block_on(create_timeline(pageserver)).unwrap();
+
+    let records = make_records();
+    let bytes_per_iter: usize = records.iter().map(|(_, rec)| rec_len(rec)).sum();
+
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Bytes(bytes_per_iter as u64));
+    for batch_size in [1, 10, 100] {
+        group.bench_with_input(
+            BenchmarkId::new("ingest_batch_size", batch_size),
+            &batch_size,
+            |b, batch_size| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let mut total = Duration::ZERO;
+                        for _ in 0..iters {
+                            let started_at = Instant::now();
+                            ingest(&timeline, &records, *batch_size).await.unwrap();
+                            total += started_at.elapsed();
+                        }
+                        total
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Creates a tenant with a timeline holding an empty relation.
+async fn create_timeline(pageserver: &TestPageserver) -> anyhow::Result<Arc<Timeline>> {
+    let client = pageserver.http_client();
+    let tenant_id = client
+        .tenant_create(
+            &TenantCreateRequest::builder(TenantId::generate())
+                .compaction_period(Duration::ZERO)
+                .gc_period(Duration::ZERO)
+                .build()?,
+        )
+        .await?;
+    let timeline_id = TimelineId::generate();
+    client
+        .timeline_create(
+            tenant_id,
+            &TimelineCreateRequest::builder(timeline_id).build()?,
+        )
+        .await?;
+    let timeline = mgr::get_tenant(tenant_id, true)
+        .await?
+        .get_timeline(timeline_id, true)?;
+
+    let ctx = RequestContext::new(
+        TaskKind::WalReceiverConnectionHandler,
+        DownloadBehavior::Error,
+    );
+    let mut modification = timeline.begin_modification(next_lsn(&timeline));
+    modification
+        .put_relmap_file(REL.spcnode, REL.dbnode, Bytes::from_static(&[0; 512]), &ctx)
+        .await?;
+    modification.commit().await?;
+
+    let mut modification = timeline.begin_modification(next_lsn(&timeline));
+    modification.put_rel_creation(REL, 0, &ctx).await?;
+    modification.commit().await?;
+
+    let mut modification = timeline.begin_modification(next_lsn(&timeline));
+    modification.put_rel_extend(REL, REL_BLOCKS, &ctx).await?;
+    modification.commit().await?;
+
+    Ok(timeline)
+}
+
+/// The records ingested in each iteration, with the blocks they modify.
+fn make_records() -> Vec<(u32, NeonWalRecord)> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..RECORDS_PER_ITER)
+        .map(|i| {
+            let blkno = rng.gen_range(0..REL_BLOCKS);
+            let (will_init, len) = if i % FULL_PAGE_IMAGE_EVERY == 0 {
+                (true, BLCKSZ as usize)
+            } else {
+                (false, RECORD_SIZE)
+            };
+            let mut rec = vec![0u8; len];
+            rng.fill(&mut rec[..]);
+            let rec = NeonWalRecord::Postgres {
+                will_init,
+                rec: Bytes::from(rec),
+            };
+            (blkno, rec)
+        })
+        .collect()
+}
+
+fn rec_len(rec: &NeonWalRecord) -> usize {
+    match rec {
+        NeonWalRecord::Postgres { rec, .. } => rec.len(),
+        _ => 0,
+    }
+}
+
+/// Ingests `records` at consecutive LSNs, committing every `batch_size` of them.
+async fn ingest(
+    timeline: &Arc<Timeline>,
+    records: &[(u32, NeonWalRecord)],
+    batch_size: usize,
+) -> anyhow::Result<()> {
+    for batch in records.chunks(batch_size) {
+        let mut lsn = next_lsn(timeline);
+        let mut modification = timeline.begin_modification(lsn);
+        for (blkno, rec) in batch {
+            modification.set_lsn(lsn)?;
+            modification.put_rel_wal_record(REL, *blkno, rec.clone())?;
+            lsn += 8;
+        }
+        modification.commit().await?;
+        timeline.check_checkpoint_distance().await?;
+    }
+    Ok(())
+}
+
+/// The LSN of the next write, right after the last one.
+fn next_lsn(timeline: &Timeline) -> Lsn {
+    Lsn(timeline.get_last_record_lsn().0 + 8)
+}
+
+criterion_group!(benches, bench_ingest);
+criterion_main!(benches);
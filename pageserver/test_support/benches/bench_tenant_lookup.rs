@@ -0,0 +1,208 @@
+//! Latency of looking up a tenant in the tenants map while tenants are created and
+//! detached concurrently.
+//!
+//! A pageserver with [`TENANTS`] tenants runs in the process, see
+//! [`TestPageserverBuilder`]. Each benchmark looks up random tenants, while
+//! [`READERS`] other tasks do the same, as the management API handlers and the
+//! page service connections do, and another task keeps creating and detaching
+//! tenants:
+//!
+//! * `tenants_map`: the lookups go through [`mgr::get_tenant`].
+//! * `single_lock`: the lookups go through a map behind a single `RwLock`, which is
+//!   held for writing while a tenant's files are created, like the tenants map did
+//!   before it was sharded. This is the baseline.
+//!
+//! Criterion reports the mean time of a lookup. The percentiles of all the lookups of
+//! a benchmark, warm-up included, are printed after it; the p99 is what the writers
+//! hurt.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pageserver::tenant::{mgr, Tenant};
+use pageserver_api::models::TenantCreateRequest;
+use pageserver_test_support::{print_latency_percentiles, TestPageserver, TestPageserverBuilder};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use utils::id::TenantId;
+
+const TENANTS: usize = 200;
+
+const READERS: usize = 32;
+
+type SingleLockMap = Arc<RwLock<HashMap<TenantId, Arc<Tenant>>>>;
+
+#[derive(Clone, Copy)]
+enum Map {
+    TenantsMap,
+    SingleLock,
+}
+
+impl Map {
+    fn name(self) -> &'static str {
+        match self {
+            Map::TenantsMap => "tenants_map",
+            Map::SingleLock => "single_lock",
+        }
+    }
+}
+
+fn bench_tenant_lookup(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let pageserver = rt
+        .block_on(TestPageserverBuilder::new("bench_tenant_lookup").start())
+        .unwrap();
+    let tenant_ids = rt.block_on(create_tenants(pageserver)).unwrap();
+    let single_lock = rt.block_on(single_lock_map(&tenant_ids)).unwrap();
+
+    let mut group = c.benchmark_group("tenant_lookup");
+    for map in [Map::TenantsMap, Map::SingleLock] {
+        let cancel = CancellationToken::new();
+        let mut tasks = Vec::new();
+        for seed in 0..READERS {
+            tasks.push(rt.spawn(read_loop(
+                map,
+                Arc::clone(&single_lock),
+                tenant_ids.clone(),
+                seed as u64 + 1,
+                cancel.clone(),
+            )));
+        }
+        tasks.push(rt.spawn(write_loop(
+            map,
+            pageserver,
+            Arc::clone(&single_lock),
+            cancel.clone(),
+        )));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut latencies = Vec::new();
+        group.bench_function(map.name(), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        let tenant_id = *tenant_ids.choose(&mut rng).unwrap();
+                        let started_at = Instant::now();
+                        lookup(map, &single_lock, tenant_id).await;
+                        let latency = started_at.elapsed();
+                        latencies.push(latency);
+                        total += latency;
+                    }
+                    total
+                })
+            })
+        });
+
+        cancel.cancel();
+        for task in tasks {
+            rt.block_on(task).unwrap().unwrap();
+        }
+        let name = format!("tenant_lookup/{}", map.name());
+        print_latency_percentiles(&name, "lookups", &mut latencies);
+    }
+    group.finish();
+}
+
+async fn create_tenants(pageserver: &TestPageserver) -> anyhow::Result<Vec<TenantId>> {
+    let mut tenant_ids = Vec::with_capacity(TENANTS);
+    for _ in 0..TENANTS {
+        tenant_ids.push(create_tenant(pageserver).await?);
+    }
+    // the tenants are activated in the background
+    for tenant_id in &tenant_ids {
+        while mgr::get_tenant(*tenant_id, true).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    Ok(tenant_ids)
+}
+
+async fn create_tenant(pageserver: &TestPageserver) -> anyhow::Result<TenantId> {
+    let request = TenantCreateRequest::builder(TenantId::generate())
+        .compaction_period(Duration::ZERO)
+        .gc_period(Duration::ZERO)
+        .build()?;
+    Ok(pageserver.http_client().tenant_create(&request).await?)
+}
+
+async fn single_lock_map(tenant_ids: &[TenantId]) -> anyhow::Result<SingleLockMap> {
+    let mut map = HashMap::new();
+    for tenant_id in tenant_ids {
+        map.insert(*tenant_id, mgr::get_tenant(*tenant_id, true).await?);
+    }
+    Ok(Arc::new(RwLock::new(map)))
+}
+
+async fn lookup(map: Map, single_lock: &SingleLockMap, tenant_id: TenantId) {
+    match map {
+        Map::TenantsMap => {
+            mgr::get_tenant(tenant_id, true).await.unwrap();
+        }
+        Map::SingleLock => {
+            single_lock.read().await.get(&tenant_id).cloned().unwrap();
+        }
+    }
+}
+
+async fn read_loop(
+    map: Map,
+    single_lock: SingleLockMap,
+    tenant_ids: Vec<TenantId>,
+    seed: u64,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    while !cancel.is_cancelled() {
+        lookup(map, &single_lock, *tenant_ids.choose(&mut rng).unwrap()).await;
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+/// Creates and detaches tenants one after another. For the baseline, the write lock of
+/// the map is held while creating a directory with a file in it, the way tenant
+/// creation held the lock of the tenants map.
+async fn write_loop(
+    map: Map,
+    pageserver: &'static TestPageserver,
+    single_lock: SingleLockMap,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let scratch_dir = pageserver.workdir().join("bench_tenant_lookup");
+    std::fs::create_dir_all(&scratch_dir)?;
+    while !cancel.is_cancelled() {
+        match map {
+            Map::TenantsMap => {
+                let tenant_id = create_tenant(pageserver).await?;
+                pageserver.http_client().tenant_detach(tenant_id).await?;
+            }
+            Map::SingleLock => {
+                let _guard = single_lock.write().await;
+                create_files(&scratch_dir.join(TenantId::generate().to_string()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn create_files(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir(dir)?;
+    let file = std::fs::File::create(dir.join("config"))?;
+    file.sync_all()?;
+    std::fs::File::open(dir)?.sync_all()?;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+criterion_group!(benches, bench_tenant_lookup);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Reporting the latencies measured by the benchmarks, besides criterion's
+//! throughput numbers.
+
+use std::time::Duration;
+
+/// Prints the percentiles of `latencies`, of the operations called `ops`, as
+/// `{name}: {count} {ops}, p50 ...`. Sorts `latencies`.
+pub fn print_latency_percentiles(name: &str, ops: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort_unstable();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize];
+    println!(
+        "{name}: {} {ops}, p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(0.999),
+        latencies[latencies.len() - 1],
+    );
+}
@@ -0,0 +1,317 @@
+//! Running a pageserver inside the test process.
+//!
+//! [`TestPageserverBuilder::start`] starts the HTTP API and the page service of
+//! a pageserver on random local ports, with its repository in a scratch
+//! directory under `tmp_check/` and, optionally, remote storage in a local
+//! directory. Tests talk to it with the typed clients returned by
+//! [`TestPageserver::http_client`] and [`TestPageserver::pagestream`], the same
+//! way the control plane and the computes do, without the Python test framework.
+//!
+//! The pageserver keeps its state in globals, such as the tenants map and the
+//! runtimes of [`pageserver::task_mgr`], so a process can run only one, until
+//! the process exits. The tests of one binary share it through
+//! [`TestPageserver::shared`], each creating tenants of its own. The background
+//! tasks which aren't needed to serve requests, like the consumption metrics
+//! collection or the disk usage based eviction, are not started, and nothing
+//! is expected to listen on the storage broker endpoint.
+//!
+//! Creating timelines runs `initdb` and serving pages runs the WAL redo
+//! process, from `pg_install/` at the repository root, as in the unit tests.
+
+mod latency;
+mod pagestream;
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Context};
+use metrics::launch_timestamp::LaunchTimestamp;
+use pageserver::config::defaults::DEFAULT_CONFIG_FILE;
+use pageserver::config::reload::ConfigReloader;
+use pageserver::config::PageServerConf;
+use pageserver::context::{DownloadBehavior, RequestContext};
+use pageserver::task_mgr::{
+    self, TaskKind, BACKGROUND_RUNTIME, COMPUTE_REQUEST_RUNTIME, MGMT_REQUEST_RUNTIME,
+    WALRECEIVER_RUNTIME,
+};
+use pageserver::tenant::mgr;
+use pageserver::{page_cache, page_service, virtual_file, InitializationOrder};
+use postgres_backend::AuthType;
+use remote_storage::GenericRemoteStorage;
+use tokio::sync::OnceCell;
+use utils::completion;
+use utils::id::{TenantId, TimelineId};
+use utils::{logging, tcp_listener};
+
+pub use latency::print_latency_percentiles;
+pub use pageserver_client::mgmt_api::Client as HttpClient;
+pub use pagestream::PagestreamClient;
+
+static PAGESERVER: OnceCell<TestPageserver> = OnceCell::const_new();
+
+static LOG_HANDLE: once_cell::sync::OnceCell<()> = once_cell::sync::OnceCell::new();
+
+pub struct TestPageserverBuilder {
+    test_name: String,
+    remote_storage: bool,
+    config_overrides: Vec<String>,
+}
+
+impl TestPageserverBuilder {
+    /// The repository goes to `tmp_check/test_{test_name}`, removed first if it
+    /// exists.
+    pub fn new(test_name: &str) -> Self {
+        TestPageserverBuilder {
+            test_name: test_name.to_string(),
+            remote_storage: false,
+            config_overrides: Vec::new(),
+        }
+    }
+
+    /// Keeps the remote storage in the `remote_storage/` directory of the
+    /// repository.
+    pub fn remote_storage(mut self, enabled: bool) -> Self {
+        self.remote_storage = enabled;
+        self
+    }
+
+    /// Overrides a config option given as a TOML document, like `-c` of the
+    /// pageserver binary.
+    pub fn config_override(mut self, option: &str) -> Self {
+        self.config_overrides.push(option.to_string());
+        self
+    }
+
+    /// Starts the pageserver of the process, failing if it's already running.
+    pub async fn start(self) -> anyhow::Result<&'static TestPageserver> {
+        let mut started = false;
+        let pageserver = PAGESERVER
+            .get_or_try_init(|| {
+                started = true;
+                start(self)
+            })
+            .await?;
+        ensure!(started, "a pageserver is already running in this process");
+        Ok(pageserver)
+    }
+}
+
+pub struct TestPageserver {
+    pub conf: &'static PageServerConf,
+    http_addr: SocketAddr,
+    pg_addr: SocketAddr,
+}
+
+impl TestPageserver {
+    /// The pageserver of the process, started with the default options if it
+    /// isn't running yet.
+    pub async fn shared() -> &'static TestPageserver {
+        PAGESERVER
+            .get_or_init(|| async {
+                start(TestPageserverBuilder::new("shared_pageserver"))
+                    .await
+                    .expect("failed to start the pageserver")
+            })
+            .await
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.conf.workdir
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+    }
+
+    pub fn pg_addr(&self) -> SocketAddr {
+        self.pg_addr
+    }
+
+    /// A client of the HTTP API. Like any connections, it should not outlive
+    /// the runtime of the test which created it.
+    pub fn http_client(&self) -> HttpClient {
+        HttpClient::new(format!("http://{}", self.http_addr), None)
+    }
+
+    /// Opens a pagestream connection to the timeline.
+    pub async fn pagestream(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<PagestreamClient> {
+        let connstr = format!("postgresql://no_user@{}", self.pg_addr);
+        PagestreamClient::connect(&connstr, tenant_id, timeline_id).await
+    }
+}
+
+/// A barrier which is already released.
+fn released_barrier() -> completion::Barrier {
+    let (completion, barrier) = completion::channel();
+    drop(completion);
+    barrier
+}
+
+async fn start(builder: TestPageserverBuilder) -> anyhow::Result<TestPageserver> {
+    LOG_HANDLE.get_or_init(|| {
+        // The test may have set up logging itself
+        let _ = logging::init(
+            logging::LogFormat::Test,
+            logging::TracingErrorLayerEnablement::EnableWithRustLogFilter,
+        );
+    });
+
+    let repo_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    let workdir = repo_root
+        .join("tmp_check")
+        .join(format!("test_{}", builder.test_name));
+    let _ = std::fs::remove_dir_all(&workdir);
+    std::fs::create_dir_all(&workdir)?;
+    let workdir = workdir.canonicalize()?;
+
+    let http_listener = tcp_listener::bind("127.0.0.1:0")?;
+    let pg_listener = tcp_listener::bind("127.0.0.1:0")?;
+    let http_addr = http_listener.local_addr()?;
+    let pg_addr = pg_listener.local_addr()?;
+
+    let mut options = vec![
+        "id = 1".to_string(),
+        format!("listen_http_addr = '{http_addr}'"),
+        format!("listen_pg_addr = '{pg_addr}'"),
+        format!(
+            "pg_distrib_dir = '{}'",
+            repo_root.join("pg_install").display()
+        ),
+    ];
+    if builder.remote_storage {
+        let remote_storage_dir = workdir.join("remote_storage");
+        std::fs::create_dir_all(&remote_storage_dir)?;
+        options.push(format!(
+            "remote_storage = {{ local_path = '{}' }}",
+            remote_storage_dir.display()
+        ));
+    }
+    options.extend(builder.config_overrides);
+
+    let mut toml = DEFAULT_CONFIG_FILE
+        .parse::<toml_edit::Document>()
+        .context("could not parse built-in config file")?;
+    for option in &options {
+        let doc = toml_edit::Document::from_str(option)
+            .with_context(|| format!("Option '{option}' could not be parsed as a toml document"))?;
+        for (key, item) in doc.iter() {
+            toml.insert(key, item.clone());
+        }
+    }
+    let cfg_file_path = workdir.join("pageserver.toml");
+    std::fs::write(&cfg_file_path, toml.to_string())?;
+    let conf: &'static PageServerConf = Box::leak(Box::new(
+        PageServerConf::parse_and_validate(&toml, &workdir)
+            .context("Failed to parse pageserver configuration")?,
+    ));
+    ensure!(
+        conf.http_auth_type == AuthType::Trust && conf.pg_auth_type == AuthType::Trust,
+        "authentication is not supported"
+    );
+    std::fs::create_dir_all(conf.tenants_path())?;
+
+    virtual_file::init(conf.max_file_descriptors);
+    page_cache::init(conf.page_cache_size, conf.max_page_cache_size);
+    page_service::fair_limiter::init(conf.get_page_concurrency_limit);
+    pageserver::preinitialize_metrics();
+
+    // The broker client needs to be created inside a tokio runtime, and must
+    // outlive the runtime of the test
+    let broker_client = WALRECEIVER_RUNTIME
+        .spawn(async {
+            storage_broker::connect(conf.broker_endpoint.clone(), conf.broker_keepalive_interval)
+        })
+        .await??;
+
+    let remote_storage = conf
+        .remote_storage_config
+        .as_ref()
+        .map(GenericRemoteStorage::from_config)
+        .transpose()?;
+
+    // Nothing to wait for before loading the tenants or starting background jobs
+    let order = InitializationOrder {
+        initial_tenant_load: None,
+        initial_logical_size_can_start: released_barrier(),
+        initial_logical_size_attempt: None,
+        background_jobs_can_start: released_barrier(),
+    };
+    BACKGROUND_RUNTIME
+        .spawn(mgr::init_tenant_mgr(
+            conf,
+            broker_client.clone(),
+            remote_storage.clone(),
+            order,
+        ))
+        .await??;
+
+    let router = {
+        let _rt_guard = MGMT_REQUEST_RUNTIME.enter();
+        let launch_ts = Box::leak(Box::new(LaunchTimestamp::generate()));
+        pageserver::http::make_router(
+            conf,
+            launch_ts,
+            None,
+            broker_client.clone(),
+            remote_storage,
+            Arc::default(),
+            ConfigReloader::new(&cfg_file_path, Vec::new(), conf),
+        )?
+        .build()
+        .map_err(|err| anyhow!(err))?
+    };
+    task_mgr::spawn(
+        MGMT_REQUEST_RUNTIME.handle(),
+        TaskKind::HttpEndpointListener,
+        None,
+        None,
+        "http endpoint listener",
+        true,
+        async move {
+            pageserver::http::server::serve(
+                http_listener,
+                router,
+                &conf.http_listener,
+                None,
+                task_mgr::shutdown_token(),
+            )
+            .await
+        },
+    );
+
+    let libpq_ctx =
+        RequestContext::todo_child(TaskKind::LibpqEndpointListener, DownloadBehavior::Error);
+    task_mgr::spawn(
+        COMPUTE_REQUEST_RUNTIME.handle(),
+        TaskKind::LibpqEndpointListener,
+        None,
+        None,
+        "libpq endpoint listener",
+        true,
+        async move {
+            page_service::libpq_listener_main(
+                conf,
+                broker_client,
+                None,
+                page_service::Listener::Tcp(pg_listener),
+                conf.pg_auth_type,
+                None,
+                conf.pg_listener.connection_limit(),
+                libpq_ctx,
+            )
+            .await
+        },
+    );
+
+    Ok(TestPageserver {
+        conf,
+        http_addr,
+        pg_addr,
+    })
+}
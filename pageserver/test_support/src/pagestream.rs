@@ -0,0 +1,112 @@
+//! Typed client of the pagestream protocol, which the computes use to get
+//! pages.
+
+use std::pin::Pin;
+
+use anyhow::{bail, Context};
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use pageserver_api::models::{
+    PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamExistsRequest, PagestreamFeMessage,
+    PagestreamGetLatestLsnRequest, PagestreamGetPageRequest, PagestreamNblocksRequest,
+};
+use tokio_postgres::NoTls;
+use tracing::debug;
+use utils::id::{RegionId, TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+pub struct PagestreamClient {
+    copy_both: Pin<Box<tokio_postgres::CopyBothDuplex<Bytes>>>,
+    /// Reused for serializing the requests.
+    request_buf: BytesMut,
+    // The connection closes when the client is dropped
+    _client: tokio_postgres::Client,
+}
+
+impl PagestreamClient {
+    pub(crate) async fn connect(
+        connstr: &str,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<Self> {
+        let (client, connection) = tokio_postgres::connect(connstr, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("pagestream connection closed: {e}");
+            }
+        });
+        let copy_both = client
+            .copy_both_simple(&format!("pagestream {tenant_id} {timeline_id}"))
+            .await?;
+        Ok(PagestreamClient {
+            copy_both: Box::pin(copy_both),
+            request_buf: BytesMut::new(),
+            _client: client,
+        })
+    }
+
+    /// Sends a request and waits for its response. Errors reported by the
+    /// pageserver are returned as [`PagestreamBeMessage::Error`].
+    pub async fn request(
+        &mut self,
+        request: &PagestreamFeMessage,
+    ) -> anyhow::Result<PagestreamBeMessage> {
+        request.serialize_into(&mut self.request_buf);
+        self.copy_both
+            .send(self.request_buf.split().freeze())
+            .await?;
+        let response = self
+            .copy_both
+            .next()
+            .await
+            .context("pagestream connection closed")??;
+        PagestreamBeMessage::deserialize(response)
+    }
+
+    pub async fn exists(&mut self, request: PagestreamExistsRequest) -> anyhow::Result<bool> {
+        match self.request(&PagestreamFeMessage::Exists(request)).await? {
+            PagestreamBeMessage::Exists(resp) => Ok(resp.exists),
+            resp => unexpected(resp),
+        }
+    }
+
+    pub async fn nblocks(&mut self, request: PagestreamNblocksRequest) -> anyhow::Result<u32> {
+        match self.request(&PagestreamFeMessage::Nblocks(request)).await? {
+            PagestreamBeMessage::Nblocks(resp) => Ok(resp.n_blocks),
+            resp => unexpected(resp),
+        }
+    }
+
+    pub async fn get_page(&mut self, request: PagestreamGetPageRequest) -> anyhow::Result<Bytes> {
+        match self.request(&PagestreamFeMessage::GetPage(request)).await? {
+            PagestreamBeMessage::GetPage(resp) => Ok(resp.page),
+            resp => unexpected(resp),
+        }
+    }
+
+    pub async fn db_size(&mut self, request: PagestreamDbSizeRequest) -> anyhow::Result<i64> {
+        match self.request(&PagestreamFeMessage::DbSize(request)).await? {
+            PagestreamBeMessage::DbSize(resp) => Ok(resp.db_size),
+            resp => unexpected(resp),
+        }
+    }
+
+    /// The last record LSN of the timeline of the region.
+    pub async fn latest_lsn(&mut self, region: RegionId) -> anyhow::Result<Lsn> {
+        let request = PagestreamGetLatestLsnRequest { region };
+        match self
+            .request(&PagestreamFeMessage::GetLatestLsn(request))
+            .await?
+        {
+            PagestreamBeMessage::GetLatestLsn(resp) => Ok(resp.lsn),
+            resp => unexpected(resp),
+        }
+    }
+}
+
+fn unexpected<T>(response: PagestreamBeMessage) -> anyhow::Result<T> {
+    match response {
+        PagestreamBeMessage::Error(resp) => bail!("pageserver error: {}", resp.message),
+        resp => bail!("unexpected response {resp:?}"),
+    }
+}
@@ -0,0 +1,31 @@
+use pageserver_api::models::{TenantConfig, TenantCreateRequest};
+use pageserver_test_support::TestPageserver;
+use utils::id::{TenantId, TimelineId};
+
+#[tokio::test]
+async fn test_tenant_create() {
+    let pageserver = TestPageserver::shared().await;
+    let client = pageserver.http_client();
+    client.status().await.unwrap();
+
+    let tenant_id = client
+        .tenant_create(&TenantCreateRequest {
+            new_tenant_id: TenantId::generate(),
+            config: TenantConfig::default(),
+        })
+        .await
+        .unwrap();
+    let tenants = client.tenant_list().await.unwrap();
+    assert!(tenants.iter().any(|tenant| tenant.id == tenant_id));
+    assert!(client.timeline_list(tenant_id).await.unwrap().is_empty());
+
+    // no such timeline
+    assert!(pageserver
+        .pagestream(tenant_id, TimelineId::generate())
+        .await
+        .is_err());
+    assert!(client
+        .timeline_detail(tenant_id, TimelineId::generate())
+        .await
+        .is_err());
+}
@@ -55,6 +55,8 @@ char	   *neon_tenant;
 int32		max_cluster_size;
 char	   *page_server_connstring;
 char	   *neon_auth_token;
+char	   *neon_lsn_token;
+bool		pagestream_error_codes = true;
 
 int			readahead_buffer_size = 128;
 int			flush_every_n_requests = 8;
@@ -64,13 +66,22 @@ int			max_reconnect_attempts = 60;
 
 bool	(*old_redo_read_buffer_filter) (XLogReaderState *record, uint8 block_id) = NULL;
 
+/*
+ * Set when the pageserver rejected the error_codes option of pagestream, not
+ * to ask for the error codes again for the life of the backend.
+ */
+static bool pagestream_error_codes_rejected = false;
+
 static bool pageserver_flush(void);
 
 static bool
 pageserver_connect(int elevel)
 {
 	char	   *query;
+	char	   *lsn_token_option = "";
+	bool		error_codes;
 	int			ret;
+	PGresult   *res;
 	const char *keywords[3];
 	const char *values[3];
 	int			n;
@@ -117,10 +128,25 @@ pageserver_connect(int elevel)
 		return false;
 	}
 
+	/*
+	 * A hot standby tells the pageserver so, to have the LSNs it reads at kept
+	 * from GC. With an LSN token, the pageserver waits for the writes of the
+	 * compute that handed it out before serving us. The error codes tell the
+	 * errors worth retrying, unless the pageserver doesn't know them.
+	 */
+	if (neon_lsn_token && neon_lsn_token[0])
+		lsn_token_option = psprintf(" lsn_token=%s", neon_lsn_token);
+	error_codes = pagestream_error_codes && !pagestream_error_codes_rejected;
 	if (IsMultiRegion())
-		query = psprintf("multipagestream %s", neon_tenant);
+		query = psprintf("multipagestream %s%s%s%s", neon_tenant,
+						 error_codes ? " error_codes" : "",
+						 RecoveryInProgress() ? " readonly" : "",
+						 lsn_token_option);
 	else
-		query = psprintf("pagestream %s %s", neon_tenant, neon_timeline);
+		query = psprintf("pagestream %s %s%s%s%s", neon_tenant, neon_timeline,
+						 error_codes ? " error_codes" : "",
+						 RecoveryInProgress() ? " readonly" : "",
+						 lsn_token_option);
 
 	ret = PQsendQuery(pageserver_conn, query);
 	if (ret != 1)
@@ -167,6 +193,38 @@ pageserver_connect(int elevel)
 		}
 	}
 
+	/* e.g. the pageserver couldn't catch up with the LSN token in time */
+	res = PQgetResult(pageserver_conn);
+	if (PQresultStatus(res) != PGRES_COPY_BOTH)
+	{
+		char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+
+		PQclear(res);
+		PQfinish(pageserver_conn);
+		pageserver_conn = NULL;
+		FreeWaitEventSet(pageserver_conn_wes);
+		pageserver_conn_wes = NULL;
+
+		/*
+		 * A pageserver that predates the error codes rejects the option, with
+		 * one of these errors depending on its version. Start over without it.
+		 */
+		if (error_codes &&
+			(strstr(msg, "option 'error_codes'") != NULL ||
+			 strstr(msg, "invalid param number") != NULL))
+		{
+			neon_log(LOG, "pageserver doesn't support pagestream error codes, reconnecting without them: %s",
+					 msg);
+			pfree(msg);
+			pagestream_error_codes_rejected = true;
+			return pageserver_connect(elevel);
+		}
+
+		neon_log(elevel, "could not start pagestream with pageserver: %s", msg);
+		return false;
+	}
+	PQclear(res);
+
 	if (IsMultiRegion())
 		neon_log(LOG, "libpagestore: multi-region enabled");
 	neon_log(LOG, "libpagestore: connected to '%s'", page_server_connstring);
@@ -434,6 +492,27 @@ pg_init_libpagestore(void)
 							   0,	/* no flags required */
 							   check_neon_id, NULL, NULL);
 
+	DefineCustomStringVariable("neon.lsn_token",
+							   "LSN token of the writes to read, from neon_lsn_token() in another region",
+							   "The pageserver waits until it has ingested the WAL of the "
+							   "token before serving the connections of the backend.",
+							   &neon_lsn_token,
+							   "",
+							   PGC_BACKEND,
+							   0,	/* no flags required */
+							   NULL, NULL, NULL);
+
+	DefineCustomBoolVariable("neon.pagestream_error_codes",
+							 "Ask the pageserver for the codes of the page request errors",
+							 "The codes tell the errors worth retrying. A pageserver that "
+							 "doesn't support them is detected at connection time, and "
+							 "the connection is retried without them.",
+							 &pagestream_error_codes,
+							 true,
+							 PGC_SIGHUP,
+							 0,	/* no flags required */
+							 NULL, NULL, NULL);
+
 	DefineCustomIntVariable("neon.max_cluster_size",
 							"cluster size limit",
 							NULL,
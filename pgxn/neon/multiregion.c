@@ -13,7 +13,9 @@
 #include "multiregion.h"
 
 #include "access/remotexact.h"
+#include "access/xlog.h"
 #include "catalog/catalog.h"
+#include "lib/stringinfo.h"
 #include "libpq-fe.h"
 #include "libpq/pqformat.h"
 #include "libpq/libpq.h"
@@ -62,6 +64,36 @@ get_all_region_lsns(void)
 	return region_lsns;
 }
 
+/*
+ * The LSN token of the backend, "<region>:<lsn>,...": the end of its last commit
+ * in the current region, and the LSNs it read at in the other regions. A compute
+ * of another region reads the writes of the commit once it presents the token to
+ * its pageserver, with neon.lsn_token.
+ */
+char *
+get_lsn_token(void)
+{
+	StringInfoData token;
+	XLogRecPtr	commit_lsn = XactLastCommitEnd;
+	int			i;
+
+	if (commit_lsn == InvalidXLogRecPtr && !RecoveryInProgress())
+		commit_lsn = GetXLogInsertRecPtr();
+
+	initStringInfo(&token);
+	for (i = 0; i < MAX_REGIONS; i++)
+	{
+		XLogRecPtr	lsn = i == current_region ? commit_lsn : region_lsns[i];
+
+		if (lsn == InvalidXLogRecPtr)
+			continue;
+		if (token.len > 0)
+			appendStringInfoChar(&token, ',');
+		appendStringInfo(&token, "%d:%X/%X", i, LSN_FORMAT_ARGS(lsn));
+	}
+	return token.data;
+}
+
 void
 clear_region_lsns(void)
 {
@@ -17,5 +17,6 @@
 extern XLogRecPtr get_region_lsn(int region);
 extern XLogRecPtr *get_all_region_lsns(void);
 extern void clear_region_lsns(void);
+extern char *get_lsn_token(void);
 
 #endif
\ No newline at end of file
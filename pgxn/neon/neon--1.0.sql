@@ -22,6 +22,12 @@ AS 'MODULE_PATHNAME', 'backpressure_throttling_time'
 LANGUAGE C STRICT
 PARALLEL UNSAFE;
 
+CREATE FUNCTION neon_lsn_token()
+RETURNS text
+AS 'MODULE_PATHNAME', 'neon_lsn_token'
+LANGUAGE C STRICT
+PARALLEL UNSAFE;
+
 CREATE FUNCTION local_cache_pages()
 RETURNS SETOF RECORD
 AS 'MODULE_PATHNAME', 'local_cache_pages'
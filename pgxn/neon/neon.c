@@ -20,12 +20,14 @@
 #include "funcapi.h"
 #include "access/htup_details.h"
 #include "utils/pg_lsn.h"
+#include "utils/builtins.h"
 #include "utils/guc.h"
 
 #include "neon.h"
 #include "walproposer.h"
 #include "pagestore_client.h"
 #include "control_plane_connector.h"
+#include "multiregion.h"
 
 PG_MODULE_MAGIC;
 void		_PG_init(void);
@@ -49,6 +51,7 @@ _PG_init(void)
 PG_FUNCTION_INFO_V1(pg_cluster_size);
 PG_FUNCTION_INFO_V1(backpressure_lsns);
 PG_FUNCTION_INFO_V1(backpressure_throttling_time);
+PG_FUNCTION_INFO_V1(neon_lsn_token);
 
 Datum
 pg_cluster_size(PG_FUNCTION_ARGS)
@@ -94,3 +97,13 @@ backpressure_throttling_time(PG_FUNCTION_ARGS)
 {
 	PG_RETURN_UINT64(BackpressureThrottlingTime());
 }
+
+/*
+ * The LSN token of the last commit of the backend, for a compute of another
+ * region to read its writes, with neon.lsn_token.
+ */
+Datum
+neon_lsn_token(PG_FUNCTION_ARGS)
+{
+	PG_RETURN_TEXT_P(cstring_to_text(get_lsn_token()));
+}
@@ -177,9 +177,18 @@ typedef struct
 	XLogRecPtr lsn;
 } NeonGetLatestLsnResponse;
 
+/* Kinds of errors in NeonErrorResponse, see PagestreamErrorCode */
+typedef enum
+{
+	NEON_ERROR_OTHER = 0,
+	NEON_ERROR_WAIT_LSN_TIMEOUT = 1,
+	NEON_ERROR_SHUTTING_DOWN = 2,
+} NeonErrorCode;
+
 typedef struct
 {
 	NeonMessageTag tag;
+	uint8		code;			/* NeonErrorCode */
 	char		message[FLEXIBLE_ARRAY_MEMBER]; /* null-terminated error
 												 * message */
 }			NeonErrorResponse;
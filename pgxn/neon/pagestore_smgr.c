@@ -1100,6 +1100,11 @@ nm_unpack_response(StringInfo s)
 				msg_resp = palloc0(sizeof(NeonErrorResponse) + msglen + 1);
 				msg_resp->tag = tag;
 				memcpy(msg_resp->message, msgtext, msglen + 1);
+				/* Only sent with the error_codes option of pagestream */
+				if (s->cursor < s->len)
+					msg_resp->code = pq_getmsgbyte(s);
+				else
+					msg_resp->code = NEON_ERROR_OTHER;
 				pq_getmsgend(s);
 
 				resp = (NeonResponse *) msg_resp;
@@ -1288,6 +1293,7 @@ nm_to_string(NeonMessage * msg)
 
 				/* FIXME: escape double-quotes in the message */
 				appendStringInfoString(&s, "{\"type\": \"NeonErrorResponse\"");
+				appendStringInfo(&s, ", \"code\": %u", msg_resp->code);
 				appendStringInfo(&s, ", \"message\": \"%s\"}", msg_resp->message);
 				appendStringInfoChar(&s, '}');
 				break;
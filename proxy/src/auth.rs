@@ -4,7 +4,7 @@ pub mod backend;
 pub use backend::BackendType;
 
 mod credentials;
-pub use credentials::ClientCredentials;
+pub use credentials::{parse_region_param, ClientCredentials};
 
 mod password_hack;
 pub use password_hack::parse_endpoint_param;
@@ -1,6 +1,7 @@
 mod classic;
 mod hacks;
 mod link;
+mod token;
 
 pub use link::LinkAuthError;
 
@@ -9,8 +10,8 @@ use crate::{
     console::{
         self,
         provider::{CachedNodeInfo, ConsoleReqExtra},
-        Api,
     },
+    proxy::wake_compute,
     stream, url,
 };
 use futures::TryFutureExt;
@@ -22,6 +23,9 @@ use tracing::info;
 pub struct AuthSuccess<T> {
     /// Did we send [`pq_proto::BeMessage::AuthenticationOk`] to client?
     pub reported_auth_ok: bool,
+    /// Did the proxy check the client's credentials itself? Otherwise only the
+    /// compute does, when a connection is opened with them.
+    pub verified_by_proxy: bool,
     /// Something to be considered a positive result.
     pub value: T,
 }
@@ -33,6 +37,7 @@ impl<T> AuthSuccess<T> {
     pub fn map<R>(self, f: impl FnOnce(T) -> R) -> AuthSuccess<R> {
         AuthSuccess {
             reported_auth_ok: self.reported_auth_ok,
+            verified_by_proxy: self.verified_by_proxy,
             value: f(self.value),
         }
     }
@@ -160,6 +165,17 @@ impl BackendType<'_, ClientCredentials<'_>> {
             Test(_) => Some("test".to_owned()),
         }
     }
+
+    /// Get the key of the compute node in the requested region, if any,
+    /// see [`ClientCredentials::compute_key`].
+    pub fn get_compute_key(&self) -> Option<String> {
+        use BackendType::*;
+
+        match self {
+            Console(_, creds) | Postgres(_, creds) => creds.compute_key(),
+            Link(_) | Test(_) => self.get_endpoint(),
+        }
+    }
     /// Authenticate the client via the requested backend, possibly using credentials.
     #[tracing::instrument(fields(allow_cleartext = allow_cleartext), skip_all)]
     pub async fn authenticate(
@@ -179,7 +195,11 @@ impl BackendType<'_, ClientCredentials<'_>> {
                 );
 
                 let api = api.as_ref();
-                auth_quirks(api, extra, creds, client, allow_cleartext).await?
+                if api.token_exchange() {
+                    token::authenticate(api, extra, creds, client, allow_cleartext).await?
+                } else {
+                    auth_quirks(api, extra, creds, client, allow_cleartext).await?
+                }
             }
             Postgres(api, creds) => {
                 info!(
@@ -217,8 +237,8 @@ impl BackendType<'_, ClientCredentials<'_>> {
         use BackendType::*;
 
         match self {
-            Console(api, creds) => api.wake_compute(extra, creds).map_ok(Some).await,
-            Postgres(api, creds) => api.wake_compute(extra, creds).map_ok(Some).await,
+            Console(api, creds) => wake_compute(api.as_ref(), extra, creds).map_ok(Some).await,
+            Postgres(api, creds) => wake_compute(api.as_ref(), extra, creds).map_ok(Some).await,
             Link(_) => Ok(None),
             Test(x) => x.wake_compute().map(Some),
         }
@@ -5,6 +5,7 @@ use crate::{
         self,
         provider::{CachedNodeInfo, ConsoleReqExtra},
     },
+    proxy::wake_compute,
     stream,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -27,12 +28,13 @@ pub async fn cleartext_hack(
         .authenticate()
         .await?;
 
-    let mut node = api.wake_compute(extra, creds).await?;
+    let mut node = wake_compute(api, extra, creds).await?;
     node.config.password(password);
 
     // Report tentative success; compute node will check the password anyway.
     Ok(AuthSuccess {
         reported_auth_ok: false,
+        verified_by_proxy: false,
         value: node,
     })
 }
@@ -55,12 +57,13 @@ pub async fn password_hack(
     info!(project = &payload.endpoint, "received missing parameter");
     creds.project = Some(payload.endpoint);
 
-    let mut node = api.wake_compute(extra, creds).await?;
+    let mut node = wake_compute(api, extra, creds).await?;
     node.config.password(payload.password);
 
     // Report tentative success; compute node will check the password anyway.
     Ok(AuthSuccess {
         reported_auth_ok: false,
+        verified_by_proxy: false,
         value: node,
     })
 }
@@ -104,6 +104,7 @@ pub(super) async fn authenticate(
 
     Ok(AuthSuccess {
         reported_auth_ok: true,
+        verified_by_proxy: true,
         value: NodeInfo {
             config,
             aux: db_info.aux.into(),
@@ -0,0 +1,153 @@
+use super::{classic, AuthSuccess};
+use crate::{
+    auth::{self, AuthErrorImpl, AuthFlow, ClientCredentials},
+    console::provider::{neon, CachedNodeInfo, ConsoleReqExtra},
+    proxy::wake_compute,
+    stream,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+/// Instead of keeping the roles' secrets, the console validates the client's
+/// password, and hands out a short-lived token scoped to the endpoint and role.
+/// The proxy then logs into the compute node with the token, so the role's
+/// password never has to be shipped to the proxy, nor kept by it.
+///
+/// The exchange needs the password in cleartext: unless `allow_cleartext`, the
+/// clients that pass the endpoint name by other means than the password go through
+/// the SCRAM flow with the role's secret instead.
+pub async fn authenticate(
+    api: &neon::Api,
+    extra: &ConsoleReqExtra<'_>,
+    creds: &mut ClientCredentials<'_>,
+    client: &mut stream::PqStream<impl AsyncRead + AsyncWrite + Unpin>,
+    allow_cleartext: bool,
+) -> auth::Result<AuthSuccess<CachedNodeInfo>> {
+    let Some(password) = read_password(creds, client, allow_cleartext).await? else {
+        return classic::authenticate(api, extra, creds, client).await;
+    };
+    let password = std::str::from_utf8(&password)
+        .map_err(|_| AuthErrorImpl::MalformedPassword("password is not valid utf-8"))?;
+
+    info!("exchanging the client's credentials for a token");
+    let token = match api.exchange_token(extra, creds, password).await? {
+        Some(token) => token,
+        None => return Err(auth::AuthError::auth_failed(creds.user)),
+    };
+
+    let mut node = wake_compute(api, extra, creds).await?;
+    node.config.password(token.as_bytes());
+
+    // Report tentative success; compute node will check the token anyway.
+    Ok(AuthSuccess {
+        reported_auth_ok: false,
+        verified_by_proxy: false,
+        value: node,
+    })
+}
+
+/// Asks the client for its password in cleartext, if allowed. Returns [`None`] if not.
+async fn read_password(
+    creds: &mut ClientCredentials<'_>,
+    client: &mut stream::PqStream<impl AsyncRead + AsyncWrite + Unpin>,
+    allow_cleartext: bool,
+) -> auth::Result<Option<Vec<u8>>> {
+    // Clients without SNI pass the endpoint along with the password, in cleartext
+    // in any case, see [`super::hacks::password_hack`].
+    if creds.project.is_none() {
+        let payload = AuthFlow::new(client)
+            .begin(auth::PasswordHack)
+            .await?
+            .authenticate()
+            .await?;
+        info!(project = &payload.endpoint, "received missing parameter");
+        creds.project = Some(payload.endpoint);
+        return Ok(Some(payload.password));
+    }
+
+    if !allow_cleartext {
+        return Ok(None);
+    }
+    let password = AuthFlow::new(client)
+        .begin(auth::CleartextPassword)
+        .await?
+        .authenticate()
+        .await?;
+    Ok(Some(password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A password message, as sent by the client.
+    fn password_message(password: &[u8]) -> Vec<u8> {
+        let mut msg = vec![b'p'];
+        msg.extend_from_slice(&(4 + password.len() as u32 + 1).to_be_bytes());
+        msg.extend_from_slice(password);
+        msg.push(0);
+        msg
+    }
+
+    /// The `AuthenticationCleartextPassword` request of the proxy.
+    const CLEARTEXT_REQUEST: [u8; 9] = [b'R', 0, 0, 0, 8, 0, 0, 0, 3];
+
+    fn creds(project: Option<&str>) -> ClientCredentials<'static> {
+        ClientCredentials {
+            user: "john_doe",
+            project: project.map(str::to_owned),
+            region: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn cleartext_allowed() -> anyhow::Result<()> {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(&password_message(b"secret")).await?;
+
+        let mut creds = creds(Some("endpoint"));
+        let mut stream = stream::PqStream::new(server);
+        let password = read_password(&mut creds, &mut stream, true).await?;
+        assert_eq!(password.as_deref(), Some(&b"secret"[..]));
+
+        let mut request = [0; CLEARTEXT_REQUEST.len()];
+        client.read_exact(&mut request).await?;
+        assert_eq!(request, CLEARTEXT_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cleartext_not_allowed() -> anyhow::Result<()> {
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let mut creds = creds(Some("endpoint"));
+        let mut stream = stream::PqStream::new(server);
+        assert!(read_password(&mut creds, &mut stream, false)
+            .await?
+            .is_none());
+
+        // the client wasn't asked for its password
+        drop(stream);
+        let mut sent = Vec::new();
+        client.read_to_end(&mut sent).await?;
+        assert!(sent.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn password_hack_without_sni() -> anyhow::Result<()> {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client
+            .write_all(&password_message(b"project=endpoint;secret"))
+            .await?;
+
+        // the endpoint name comes with the password, in cleartext anyway
+        let mut creds = creds(None);
+        let mut stream = stream::PqStream::new(server);
+        let password = read_password(&mut creds, &mut stream, false).await?;
+        assert_eq!(password.as_deref(), Some(&b"secret"[..]));
+        assert_eq!(creds.project(), Some("endpoint"));
+        Ok(())
+    }
+}
@@ -6,6 +6,12 @@ use pq_proto::StartupMessageParams;
 use std::collections::HashSet;
 use thiserror::Error;
 use tracing::info;
+use utils::id::RegionId;
+use utils::region::RegionError;
+
+/// Prefix of the SNI label which names the region of the endpoint,
+/// as in `<endpoint>.region-<id or name>.<common name>`.
+const REGION_LABEL_PREFIX: &str = "region-";
 
 #[derive(Debug, Error, PartialEq, Eq, Clone)]
 pub enum ClientCredsParseError {
@@ -27,6 +33,34 @@ pub enum ClientCredsParseError {
 
     #[error("Project name ('{0}') must contain only alphanumeric characters and hyphen.")]
     MalformedProjectName(String),
+
+    #[error(
+        "Inconsistent region inferred from \
+         SNI ('{}') and region option ('{}').",
+        .domain, .option,
+    )]
+    InconsistentRegions { domain: String, option: String },
+
+    #[error(
+        "Region option is given more than once ('{}' and '{}').",
+        .first, .second,
+    )]
+    DuplicateRegionOptions { first: String, second: String },
+
+    #[error("Region ('{0}') must be a number from 0 to 255, or a region name.")]
+    MalformedRegion(String),
+
+    #[error("Region ('{0}') is not known.")]
+    UnknownRegion(String),
+}
+
+impl From<RegionError> for ClientCredsParseError {
+    fn from(e: RegionError) -> Self {
+        match e {
+            RegionError::Malformed(region) => ClientCredsParseError::MalformedRegion(region),
+            RegionError::Unknown(region) => ClientCredsParseError::UnknownRegion(region),
+        }
+    }
 }
 
 impl UserFacingError for ClientCredsParseError {}
@@ -38,6 +72,9 @@ pub struct ClientCredentials<'a> {
     pub user: &'a str,
     // TODO: this is a severe misnomer! We should think of a new name ASAP.
     pub project: Option<String>,
+    /// The region of a multi-region endpoint to connect to; the console picks one
+    /// if it isn't given.
+    pub region: Option<RegionId>,
 }
 
 impl ClientCredentials<'_> {
@@ -45,6 +82,15 @@ impl ClientCredentials<'_> {
     pub fn project(&self) -> Option<&str> {
         self.project.as_deref()
     }
+
+    /// Identifies the compute node of the endpoint in the region, e.g. for caching.
+    pub fn compute_key(&self) -> Option<String> {
+        let project = self.project()?;
+        Some(match self.region {
+            Some(region) => format!("{project}@{region}"),
+            None => project.to_owned(),
+        })
+    }
 }
 
 impl<'a> ClientCredentials<'a> {
@@ -53,6 +99,7 @@ impl<'a> ClientCredentials<'a> {
         ClientCredentials {
             user: "",
             project: None,
+            region: None,
         }
     }
 
@@ -81,29 +128,30 @@ impl<'a> ClientCredentials<'a> {
             })
             .map(|name| name.to_string());
 
-        let project_from_domain = if let Some(sni_str) = sni {
-            if let Some(cn) = common_names {
-                let common_name_from_sni = sni_str.split_once('.').map(|(_, domain)| domain);
-
-                let project = common_name_from_sni
-                    .and_then(|domain| {
-                        if cn.contains(domain) {
-                            subdomain_from_sni(sni_str, domain)
-                        } else {
-                            None
-                        }
-                    })
-                    .ok_or_else(|| UnknownCommonName {
-                        cn: common_name_from_sni.unwrap_or("").into(),
-                    })?;
-
-                Some(project)
-            } else {
-                None
+        // So might be the region, by id or by name, but only once.
+        let mut region_options = params
+            .options_raw()
+            .into_iter()
+            .flatten()
+            .filter_map(parse_region_param);
+        let region_option = region_options.next().map(|region| region.to_string());
+        if let Some(second) = region_options.next() {
+            return Err(DuplicateRegionOptions {
+                first: region_option.unwrap_or_default(),
+                second: second.to_owned(),
+            });
+        }
+        let region_option_id = region_option.as_deref().map(str::parse).transpose()?;
+
+        let (project_from_domain, region_from_domain) = match (sni, common_names) {
+            (Some(sni), Some(cn)) => {
+                let (project, region) = parse_sni(sni, &cn)?;
+                (Some(project), region)
             }
-        } else {
-            None
+            _ => (None, None),
         };
+        let region_from_domain_id: Option<RegionId> =
+            region_from_domain.as_deref().map(str::parse).transpose()?;
 
         let project = match (project_option, project_from_domain) {
             // Invariant: if we have both project name variants, they should match.
@@ -118,20 +166,58 @@ impl<'a> ClientCredentials<'a> {
         }
         .transpose()?;
 
-        info!(user, project = project.as_deref(), "credentials");
+        // Invariant: if we have both, they should name the same region.
+        let region = match (region_option_id, region_from_domain_id) {
+            (Some(option), Some(domain)) if option != domain => {
+                return Err(InconsistentRegions {
+                    domain: region_from_domain.unwrap_or_default(),
+                    option: region_option.unwrap_or_default(),
+                });
+            }
+            (a, b) => a.or(b),
+        };
 
-        Ok(Self { user, project })
+        info!(
+            user,
+            project = project.as_deref(),
+            region = region.map(|r| format!("{r:#}")),
+            "credentials"
+        );
+
+        Ok(Self {
+            user,
+            project,
+            region,
+        })
     }
 }
 
-fn project_name_valid(name: &str) -> bool {
-    name.chars().all(|c| c.is_alphanumeric() || c == '-')
+pub fn parse_region_param(bytes: &str) -> Option<&str> {
+    bytes.strip_prefix("region=")
 }
 
-fn subdomain_from_sni(sni: &str, common_name: &str) -> Option<String> {
-    sni.strip_suffix(common_name)?
-        .strip_suffix('.')
-        .map(str::to_owned)
+/// Splits the SNI hostname into the project name and the region, if there's one:
+/// `<project>.<common name>` or `<project>.region-<id or name>.<common name>`.
+fn parse_sni(
+    sni: &str,
+    common_names: &HashSet<String>,
+) -> Result<(String, Option<String>), ClientCredsParseError> {
+    let (project, domain) = sni.split_once('.').unwrap_or((sni, ""));
+    if common_names.contains(domain) {
+        return Ok((project.to_owned(), None));
+    }
+    if let Some((label, cn)) = domain.split_once('.') {
+        if let Some(region) = label.strip_prefix(REGION_LABEL_PREFIX) {
+            if common_names.contains(cn) {
+                return Ok((project.to_owned(), Some(region.to_owned())));
+            }
+        }
+    }
+    Err(ClientCredsParseError::UnknownCommonName { cn: domain.into() })
+}
+
+fn project_name_valid(name: &str) -> bool {
+    name.chars().all(|c| c.is_alphanumeric() || c == '-')
 }
 
 #[cfg(test)]
@@ -288,6 +374,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_region() -> anyhow::Result<()> {
+        let common_names = Some(["localhost".into()].into());
+        let options = StartupMessageParams::new([("user", "john_doe")]);
+
+        let sni = Some("foo.region-2.localhost");
+        let creds = ClientCredentials::parse(&options, sni, common_names.clone())?;
+        assert_eq!(creds.project.as_deref(), Some("foo"));
+        assert_eq!(creds.region, Some(RegionId(2)));
+        assert_eq!(creds.compute_key().as_deref(), Some("foo@2"));
+
+        let sni = Some("foo.localhost");
+        let creds = ClientCredentials::parse(&options, sni, common_names.clone())?;
+        assert_eq!(creds.region, None);
+        assert_eq!(creds.compute_key().as_deref(), Some("foo"));
+
+        let options =
+            StartupMessageParams::new([("user", "john_doe"), ("options", "endpoint=foo region=1")]);
+        let creds = ClientCredentials::parse(&options, None, None)?;
+        assert_eq!(creds.project.as_deref(), Some("foo"));
+        assert_eq!(creds.region, Some(RegionId(1)));
+
+        let sni = Some("foo.region-2.localhost");
+        let err =
+            ClientCredentials::parse(&options, sni, common_names.clone()).expect_err("should fail");
+        assert!(matches!(err, InconsistentRegions { .. }), "{err:?}");
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "endpoint=foo region=1 region=2"),
+        ]);
+        let err = ClientCredentials::parse(&options, None, None).expect_err("should fail");
+        assert_eq!(
+            err,
+            DuplicateRegionOptions {
+                first: "1".into(),
+                second: "2".into(),
+            }
+        );
+
+        let options = StartupMessageParams::new([("user", "john_doe")]);
+        let sni = Some("foo.region-x.localhost");
+        let err =
+            ClientCredentials::parse(&options, sni, common_names.clone()).expect_err("should fail");
+        assert_eq!(err, UnknownRegion("x".into()));
+
+        let sni = Some("foo.region-300.localhost");
+        let err = ClientCredentials::parse(&options, sni, common_names).expect_err("should fail");
+        assert_eq!(err, MalformedRegion("300".into()));
+
+        Ok(())
+    }
+
     #[test]
     fn parse_inconsistent_sni() {
         let options = StartupMessageParams::new([("user", "john_doe")]);
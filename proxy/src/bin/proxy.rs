@@ -3,6 +3,8 @@ use proxy::auth;
 use proxy::console;
 use proxy::http;
 use proxy::metrics;
+use proxy::pooler::Pooler;
+use proxy::rate_limiter::EndpointRateLimiter;
 
 use anyhow::bail;
 use proxy::config::{self, ProxyConfig};
@@ -13,6 +15,7 @@ use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 use tracing::info;
 use tracing::warn;
+use utils::region::{RegionConfig, RegionRegistry};
 use utils::{project_git_version, sentry_init::init_sentry};
 
 project_git_version!(GIT_VERSION);
@@ -76,9 +79,30 @@ struct ProxyCliArgs {
     /// cache for `wake_compute` api method (use `size=0` to disable)
     #[clap(long, default_value = config::CacheOptions::DEFAULT_OPTIONS_NODE_INFO)]
     wake_compute_cache: String,
+    /// exchange the client's credentials for a short-lived token at the console, and log
+    /// into the compute with the token instead of the password (console backend only)
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    auth_token_exchange: bool,
     /// Allow self-signed certificates for compute nodes (for testing)
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     allow_self_signed_compute: bool,
+    /// maximum number of compute connections per endpoint shared by the pooled sessions,
+    /// which connect to the endpoint name with the `-pooler` suffix (use `0` to disable)
+    #[clap(long, default_value_t = 0)]
+    pooler_max_conns_per_endpoint: usize,
+    /// how long an idle compute connection of the pooled sessions is kept open
+    #[clap(long, default_value = "5m")]
+    pooler_idle_timeout: String,
+    /// maximum number of new client connections per second per endpoint (use `0` to disable)
+    #[clap(long, default_value_t = 0)]
+    rate_limit_conns_per_sec: u32,
+    /// maximum number of open client connections per endpoint (use `0` to disable)
+    #[clap(long, default_value_t = 0)]
+    rate_limit_max_concurrent_conns: usize,
+    /// names and metadata of the regions, which can be named in the SNI and in the `region`
+    /// option, as JSON array, e.g. `[{"id": 1, "name": "us-east-2"}]`
+    #[clap(long)]
+    regions: Option<String>,
 }
 
 #[tokio::main]
@@ -91,6 +115,10 @@ async fn main() -> anyhow::Result<()> {
     ::metrics::set_build_info_metric(GIT_VERSION);
 
     let args = ProxyCliArgs::parse();
+    if let Some(regions) = &args.regions {
+        let regions: Vec<RegionConfig> = serde_json::from_str(regions)?;
+        utils::region::init(RegionRegistry::new(regions)?)?;
+    }
     let config = build_config(&args)?;
 
     info!("Authentication backend: {}", config.auth_backend);
@@ -140,6 +168,14 @@ async fn main() -> anyhow::Result<()> {
         maintenance_tasks.spawn(metrics::task_main(metrics_config));
     }
 
+    if let Some(pooler) = &config.pooler {
+        maintenance_tasks.spawn(proxy::pooler::task_main(pooler));
+    }
+
+    if let Some(rate_limiter) = &config.rate_limiter {
+        maintenance_tasks.spawn(proxy::rate_limiter::task_main(rate_limiter));
+    }
+
     let maintenance = loop {
         // get one complete task
         match futures::future::select(
@@ -202,12 +238,14 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             info!("Using NodeInfoCache (wake_compute) with size={size} ttl={ttl:?}");
             let caches = Box::leak(Box::new(console::caches::ApiCaches {
                 node_info: console::caches::NodeInfoCache::new("node_info_cache", size, ttl),
+                wake_compute_locks: Default::default(),
             }));
 
             let url = args.auth_endpoint.parse()?;
             let endpoint = http::Endpoint::new(url, http::new_client());
 
-            let api = console::provider::neon::Api::new(endpoint, caches);
+            let api = console::provider::neon::Api::new(endpoint, caches)
+                .with_token_exchange(args.auth_token_exchange);
             auth::BackendType::Console(Cow::Owned(api), ())
         }
         AuthBackend::Postgres => {
@@ -221,11 +259,36 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         }
     };
 
+    let pooler = match args.pooler_max_conns_per_endpoint {
+        0 => None,
+        max_conns_per_endpoint => {
+            let idle_timeout = humantime::parse_duration(&args.pooler_idle_timeout)?;
+            info!("Pooling up to {max_conns_per_endpoint} compute connections per endpoint");
+            Some(Pooler::new(max_conns_per_endpoint, idle_timeout))
+        }
+    };
+
+    let rate_limiter = match (
+        args.rate_limit_conns_per_sec,
+        args.rate_limit_max_concurrent_conns,
+    ) {
+        (0, 0) => None,
+        (conns_per_sec, max_concurrent_conns) => {
+            info!("Limiting connections per endpoint to {conns_per_sec}/s, {max_concurrent_conns} open (0 is unlimited)");
+            Some(EndpointRateLimiter::new(
+                conns_per_sec,
+                max_concurrent_conns,
+            ))
+        }
+    };
+
     let config = Box::leak(Box::new(ProxyConfig {
         tls_config,
         auth_backend,
         metric_collection,
         allow_self_signed_compute: args.allow_self_signed_compute,
+        pooler,
+        rate_limiter,
     }));
 
     Ok(config)
@@ -111,7 +111,8 @@ impl<'a> Session<'a> {
 impl Session<'_> {
     /// Store the cancel token for the given session.
     /// This enables query cancellation in `crate::proxy::prepare_client_connection`.
-    pub fn enable_query_cancellation(self, cancel_closure: CancelClosure) -> CancelKeyData {
+    /// A pooled session calls it again whenever it moves to another compute connection.
+    pub fn enable_query_cancellation(&self, cancel_closure: CancelClosure) -> CancelKeyData {
         info!("enabling query cancellation for this session");
         self.cancel_map
             .0
@@ -120,6 +121,12 @@ impl Session<'_> {
 
         self.key
     }
+
+    /// Forget the cancel token, e.g. once a pooled session has returned its compute
+    /// connection to the pool, so that it can't cancel the queries of other sessions.
+    pub fn disable_query_cancellation(&self) {
+        self.cancel_map.0.write().insert(self.key, None);
+    }
 }
 
 #[cfg(test)]
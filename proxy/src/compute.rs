@@ -1,5 +1,5 @@
 use crate::{
-    auth::parse_endpoint_param,
+    auth::{parse_endpoint_param, parse_region_param},
     cancellation::CancelClosure,
     console::errors::WakeComputeError,
     error::{io_error, UserFacingError},
@@ -217,12 +217,15 @@ impl ConnCfg {
     }
 }
 
+/// Socket connected to a compute node.
+pub type ComputeStream = tokio_postgres::maybe_tls_stream::MaybeTlsStream<
+    tokio::net::TcpStream,
+    postgres_native_tls::TlsStream<tokio::net::TcpStream>,
+>;
+
 pub struct PostgresConnection {
     /// Socket connected to a compute node.
-    pub stream: tokio_postgres::maybe_tls_stream::MaybeTlsStream<
-        tokio::net::TcpStream,
-        postgres_native_tls::TlsStream<tokio::net::TcpStream>,
-    >,
+    pub stream: ComputeStream,
     /// PostgreSQL connection parameters.
     pub params: std::collections::HashMap<String, String>,
     /// Query cancellation token.
@@ -278,7 +281,7 @@ fn filtered_options(params: &StartupMessageParams) -> Option<String> {
     #[allow(unstable_name_collisions)]
     let options: String = params
         .options_raw()?
-        .filter(|opt| parse_endpoint_param(opt).is_none())
+        .filter(|opt| parse_endpoint_param(opt).is_none() && parse_region_param(opt).is_none())
         .intersperse(" ") // TODO: use impl from std once it's stabilized
         .collect();
 
@@ -313,5 +316,8 @@ mod tests {
 
         let params = StartupMessageParams::new([("options", "project = foo")]);
         assert_eq!(filtered_options(&params).as_deref(), Some("project = foo"));
+
+        let params = StartupMessageParams::new([("options", "endpoint=foo region=1 -c x=y")]);
+        assert_eq!(filtered_options(&params).as_deref(), Some("-c x=y"));
     }
 }
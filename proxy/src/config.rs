@@ -1,4 +1,4 @@
-use crate::auth;
+use crate::{auth, pooler::Pooler, rate_limiter::EndpointRateLimiter};
 use anyhow::{bail, ensure, Context, Ok};
 use rustls::sign;
 use std::{
@@ -13,6 +13,10 @@ pub struct ProxyConfig {
     pub auth_backend: auth::BackendType<'static, ()>,
     pub metric_collection: Option<MetricCollectionConfig>,
     pub allow_self_signed_compute: bool,
+    /// Pool of compute connections for the pooled sessions, `None` if disabled.
+    pub pooler: Option<Pooler>,
+    /// Limits of the client connections per endpoint, `None` if disabled.
+    pub rate_limiter: Option<EndpointRateLimiter>,
 }
 
 #[derive(Debug)]
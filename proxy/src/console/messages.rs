@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// Generic error response with human-readable description.
@@ -22,6 +22,40 @@ impl fmt::Debug for GetRoleSecret {
     }
 }
 
+/// Request of the client's credentials for a [`ExchangeToken`].
+/// Sent to the `/proxy_exchange_token` API method.
+#[derive(Serialize)]
+pub struct ExchangeTokenRequest<'a> {
+    pub project: &'a str,
+    pub role: &'a str,
+    pub password: &'a str,
+}
+
+// Manually implement debug to omit sensitive info.
+impl fmt::Debug for ExchangeTokenRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExchangeTokenRequest")
+            .field("project", &self.project)
+            .field("role", &self.role)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Response which holds a short-lived token, scoped to the client's endpoint
+/// and role, which the compute node accepts in place of the role's password.
+/// Returned by the `/proxy_exchange_token` API method.
+#[derive(Deserialize)]
+pub struct ExchangeToken {
+    pub token: Box<str>,
+}
+
+// Manually implement debug to omit sensitive info.
+impl fmt::Debug for ExchangeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExchangeToken").finish_non_exhaustive()
+    }
+}
+
 /// Response which holds compute node's `host:port` pair.
 /// Returned by the `/proxy_wake_compute` API method.
 #[derive(Debug, Deserialize)]
@@ -8,7 +8,9 @@ use crate::{
     compute, scram,
 };
 use async_trait::async_trait;
-use std::sync::Arc;
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 pub mod errors {
     use crate::{
@@ -220,4 +222,98 @@ pub trait Api {
 pub struct ApiCaches {
     /// Cache for the `wake_compute` API method.
     pub node_info: NodeInfoCache,
+    /// Queues the concurrent `wake_compute` calls of an endpoint which miss the cache.
+    pub wake_compute_locks: ApiLocks,
+}
+
+static WAKE_COMPUTE_QUEUED: Lazy<metrics::IntCounter> = Lazy::new(|| {
+    metrics::register_int_counter!(
+        "proxy_wake_compute_queued_total",
+        "Number of wake_compute calls which waited for a concurrent one of the same endpoint.",
+    )
+    .unwrap()
+});
+
+/// Per-endpoint locks of the console requests. When a cold endpoint gets a burst of
+/// connections, the first of them wakes the compute while the rest wait for the lock,
+/// and then find the compute node info in the cache, instead of all of them waking it.
+#[derive(Default)]
+pub struct ApiLocks {
+    locks: parking_lot::Mutex<HashMap<Arc<str>, Arc<Semaphore>>>,
+}
+
+impl ApiLocks {
+    /// Waits for the concurrent holders of the endpoint's lock.
+    pub async fn lock(&self, key: &str) -> ApiLockGuard<'_> {
+        let semaphore = {
+            let mut locks = self.locks.lock();
+            let semaphore = locks
+                .entry(key.into())
+                .or_insert_with(|| Arc::new(Semaphore::new(1)));
+            Arc::clone(semaphore)
+        };
+        let permit = match Arc::clone(&semaphore).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                WAKE_COMPUTE_QUEUED.inc();
+                semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed")
+            }
+        };
+        ApiLockGuard {
+            locks: self,
+            key: key.into(),
+            permit: Some(permit),
+        }
+    }
+}
+
+pub struct ApiLockGuard<'a> {
+    locks: &'a ApiLocks,
+    key: Arc<str>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ApiLockGuard<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.locks.lock();
+        drop(self.permit.take());
+        // Forget the lock unless somebody else is waiting for it; new references
+        // are only handed out under the map's lock.
+        if let Some(semaphore) = locks.get(&self.key) {
+            if Arc::strong_count(semaphore) == 1 {
+                locks.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn api_locks() {
+        let locks = Arc::new(ApiLocks::default());
+
+        let guard = locks.lock("ep-foo").await;
+        // other endpoints aren't blocked
+        drop(locks.lock("ep-bar").await);
+
+        let waiter = tokio::spawn({
+            let locks = Arc::clone(&locks);
+            async move {
+                let _guard = locks.lock("ep-foo").await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(locks.locks.lock().is_empty());
+    }
 }
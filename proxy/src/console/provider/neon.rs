@@ -1,7 +1,9 @@
 //! Production console backend.
 
 use super::{
-    super::messages::{ConsoleError, GetRoleSecret, WakeCompute},
+    super::messages::{
+        ConsoleError, ExchangeToken, ExchangeTokenRequest, GetRoleSecret, WakeCompute,
+    },
     errors::{ApiError, GetAuthInfoError, WakeComputeError},
     ApiCaches, AuthInfo, CachedNodeInfo, ConsoleReqExtra, NodeInfo,
 };
@@ -16,12 +18,29 @@ use tracing::{error, info, info_span, warn, Instrument};
 pub struct Api {
     endpoint: http::Endpoint,
     caches: &'static ApiCaches,
+    /// Exchange the client's credentials for a token, see [`Api::exchange_token`].
+    token_exchange: bool,
 }
 
 impl Api {
     /// Construct an API object containing the auth parameters.
     pub fn new(endpoint: http::Endpoint, caches: &'static ApiCaches) -> Self {
-        Self { endpoint, caches }
+        Self {
+            endpoint,
+            caches,
+            token_exchange: false,
+        }
+    }
+
+    /// Authenticate the clients by exchanging their credentials for a token,
+    /// instead of performing SCRAM with the role's secret.
+    pub fn with_token_exchange(mut self, token_exchange: bool) -> Self {
+        self.token_exchange = token_exchange;
+        self
+    }
+
+    pub fn token_exchange(&self) -> bool {
+        self.token_exchange
     }
 
     pub fn url(&self) -> &str {
@@ -71,12 +90,58 @@ impl Api {
         .await
     }
 
+    /// Let the console validate the client's password, and return a short-lived
+    /// token scoped to the endpoint and role, to be presented to the compute node
+    /// instead of the password. Returns [`None`] if the console rejects the credentials.
+    #[tracing::instrument(skip_all)]
+    pub async fn exchange_token(
+        &self,
+        extra: &ConsoleReqExtra<'_>,
+        creds: &ClientCredentials<'_>,
+        password: &str,
+    ) -> Result<Option<Box<str>>, GetAuthInfoError> {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        async {
+            let body = ExchangeTokenRequest {
+                project: creds.project().expect("impossible"),
+                role: creds.user,
+                password,
+            };
+            let request = self
+                .endpoint
+                .post("proxy_exchange_token")
+                .header("X-Request-ID", &request_id)
+                .query(&[("session_id", extra.session_id)])
+                .query(&[("application_name", extra.application_name)])
+                .json(&body)
+                .build()?;
+
+            info!(url = request.url().as_str(), "sending http request");
+            let start = Instant::now();
+            let response = self.endpoint.execute(request).await?;
+            info!(duration = ?start.elapsed(), "received http response");
+            match parse_body::<ExchangeToken>(response).await {
+                Ok(body) => Ok(Some(body.token)),
+                // The console doesn't tell apart unknown roles and wrong passwords.
+                Err(e) => match e.http_status_code() {
+                    Some(http::StatusCode::UNAUTHORIZED | http::StatusCode::FORBIDDEN) => Ok(None),
+                    _otherwise => Err(e.into()),
+                },
+            }
+        }
+        .map_err(crate::error::log_error)
+        .instrument(info_span!("http", id = request_id))
+        .await
+    }
+
     async fn do_wake_compute(
         &self,
         extra: &ConsoleReqExtra<'_>,
         creds: &ClientCredentials<'_>,
     ) -> Result<NodeInfo, WakeComputeError> {
         let project = creds.project().expect("impossible");
+        // The console wakes the compute of a multi-region endpoint in this region
+        let region = creds.region.map(|region| region.to_string());
         let request_id = uuid::Uuid::new_v4().to_string();
         async {
             let request = self
@@ -87,6 +152,7 @@ impl Api {
                 .query(&[
                     ("application_name", extra.application_name),
                     ("project", Some(project)),
+                    ("region_id", region.as_deref()),
                 ])
                 .build()?;
 
@@ -139,7 +205,9 @@ impl super::Api for Api {
         extra: &ConsoleReqExtra<'_>,
         creds: &ClientCredentials,
     ) -> Result<CachedNodeInfo, WakeComputeError> {
-        let key = creds.project().expect("impossible");
+        // The computes of the regions of an endpoint are separate entries
+        let key = creds.compute_key().expect("impossible");
+        let key = key.as_str();
 
         // Every time we do a wakeup http request, the compute node will stay up
         // for some time (highly depends on the console's scale-to-zero policy);
@@ -150,6 +218,17 @@ impl super::Api for Api {
             return Ok(cached);
         }
 
+        // When lots of clients connect to a suspended compute at once, only the
+        // first one wakes it; the rest wait for it, and then find its node info.
+        let _guard = self.caches.wake_compute_locks.lock(key).await;
+        if let Some(cached) = self.caches.node_info.get(key) {
+            info!(
+                key = key,
+                "found compute node info cached by a concurrent wakeup"
+            );
+            return Ok(cached);
+        }
+
         let node = self.do_wake_compute(extra, creds).await?;
         let (_, cached) = self.caches.node_info.insert(key.into(), node);
         info!(key = key, "created a cache entry for compute node info");
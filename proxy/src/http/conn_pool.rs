@@ -234,6 +234,16 @@ async fn connect_to_compute(
             )
         })
         .transpose()?;
+    // The pool bounds the connections, only the rate of new ones needs a limit.
+    if let (
+        Some(limiter),
+        auth::BackendType::Console(_, creds) | auth::BackendType::Postgres(_, creds),
+    ) = (&config.rate_limiter, &creds)
+    {
+        if let Some(endpoint) = creds.project() {
+            drop(limiter.acquire(endpoint)?);
+        }
+    }
     let extra = console::ConsoleReqExtra {
         session_id: uuid::Uuid::new_v4(),
         application_name: Some(APP_NAME),
@@ -22,9 +22,9 @@ use super::conn_pool::ConnInfo;
 use super::conn_pool::GlobalConnPool;
 
 #[derive(serde::Deserialize)]
-struct QueryData {
-    query: String,
-    params: Vec<serde_json::Value>,
+pub(super) struct QueryData {
+    pub query: String,
+    pub params: Vec<serde_json::Value>,
 }
 
 #[derive(serde::Deserialize)]
@@ -54,7 +54,7 @@ static HEADER_VALUE_TRUE: HeaderValue = HeaderValue::from_static("true");
 // Convert json non-string types to strings, so that they can be passed to Postgres
 // as parameters.
 //
-fn json_to_pg_text(json: Vec<Value>) -> Result<Vec<Option<String>>, serde_json::Error> {
+pub(super) fn json_to_pg_text(json: Vec<Value>) -> Result<Vec<Option<String>>, serde_json::Error> {
     json.iter()
         .map(|value| {
             match value {
@@ -120,6 +120,21 @@ fn get_conn_info(
         .ok_or(anyhow::anyhow!("missing connection string"))?
         .to_str()?;
 
+    let host_header = headers
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(':').next());
+
+    parse_conn_info(connection_string, host_header, sni_hostname)
+}
+
+/// Parse and check the connection string of a client connecting to `host_header`
+/// over TLS with `sni_hostname`.
+pub(super) fn parse_conn_info(
+    connection_string: &str,
+    host_header: Option<&str>,
+    sni_hostname: Option<String>,
+) -> Result<ConnInfo, anyhow::Error> {
     let connection_url = Url::parse(connection_string)?;
 
     let protocol = connection_url.scheme();
@@ -154,11 +169,6 @@ fn get_conn_info(
         .host_str()
         .ok_or(anyhow::anyhow!("no host"))?;
 
-    let host_header = headers
-        .get("host")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.split(':').next());
-
     if hostname != sni_hostname {
         return Err(anyhow::anyhow!("mismatched SNI hostname and hostname"));
     } else if let Some(h) = host_header {
@@ -311,33 +321,10 @@ async fn query_to_json<T: GenericClient>(
 
     // grab the command tag and number of rows affected
     let command_tag = row_stream.command_tag().unwrap_or_default();
-    let mut command_tag_split = command_tag.split(' ');
-    let command_tag_name = command_tag_split.next().unwrap_or_default();
-    let command_tag_count = if command_tag_name == "INSERT" {
-        // INSERT returns OID first and then number of rows
-        command_tag_split.nth(1)
-    } else {
-        // other commands return number of rows (if any)
-        command_tag_split.next()
-    }
-    .and_then(|s| s.parse::<i64>().ok());
+    let (command_tag_name, command_tag_count) = parse_command_tag(&command_tag);
 
     let fields = if !rows.is_empty() {
-        rows[0]
-            .columns()
-            .iter()
-            .map(|c| {
-                json!({
-                    "name": Value::String(c.name().to_owned()),
-                    "dataTypeID": Value::Number(c.type_().oid().into()),
-                    "tableID": c.table_oid(),
-                    "columnID": c.column_id(),
-                    "dataTypeSize": c.type_size(),
-                    "dataTypeModifier": c.type_modifier(),
-                    "format": "text",
-                })
-            })
-            .collect::<Vec<_>>()
+        fields_to_json(&rows[0])
     } else {
         Vec::new()
     };
@@ -358,6 +345,56 @@ async fn query_to_json<T: GenericClient>(
     }))
 }
 
+//
+// The SQLSTATE of a failed query, if it failed in postgres
+//
+pub(super) fn error_code(e: &anyhow::Error) -> Value {
+    match e.downcast_ref::<tokio_postgres::Error>() {
+        Some(e) => match e.code() {
+            Some(e) => serde_json::to_value(e.code()).unwrap(),
+            None => Value::Null,
+        },
+        None => Value::Null,
+    }
+}
+
+//
+// Split the command tag into the command name and the number of rows affected
+//
+pub(super) fn parse_command_tag(command_tag: &str) -> (&str, Option<i64>) {
+    let mut command_tag_split = command_tag.split(' ');
+    let command_tag_name = command_tag_split.next().unwrap_or_default();
+    let command_tag_count = if command_tag_name == "INSERT" {
+        // INSERT returns OID first and then number of rows
+        command_tag_split.nth(1)
+    } else {
+        // other commands return number of rows (if any)
+        command_tag_split.next()
+    }
+    .and_then(|s| s.parse::<i64>().ok());
+    (command_tag_name, command_tag_count)
+}
+
+//
+// Describe the columns of a row, like node-postgres does
+//
+pub(super) fn fields_to_json(row: &Row) -> Vec<Value> {
+    row.columns()
+        .iter()
+        .map(|c| {
+            json!({
+                "name": Value::String(c.name().to_owned()),
+                "dataTypeID": Value::Number(c.type_().oid().into()),
+                "tableID": c.table_oid(),
+                "columnID": c.column_id(),
+                "dataTypeSize": c.type_size(),
+                "dataTypeModifier": c.type_modifier(),
+                "format": "text",
+            })
+        })
+        .collect()
+}
+
 //
 // Convert postgres row with text-encoded values to JSON object
 //
@@ -17,7 +17,7 @@ use hyper::{
 };
 use hyper_tungstenite::{tungstenite::Message, HyperWebsocket, WebSocketStream};
 use pin_project_lite::pin_project;
-use serde_json::{json, Value};
+use serde_json::json;
 
 use std::{
     convert::Infallible,
@@ -39,7 +39,7 @@ use utils::http::{error::ApiError, json::json_response};
 // Tracking issue: https://github.com/rust-lang/rust/issues/98407.
 use sync_wrapper::SyncWrapper;
 
-use super::{conn_pool::GlobalConnPool, sql_over_http};
+use super::{conn_pool::GlobalConnPool, sql_over_http, sql_over_ws};
 
 pin_project! {
     /// This is a wrapper around a [`WebSocketStream`] that
@@ -180,8 +180,25 @@ async fn ws_handler(
         .and_then(|h| h.split(':').next())
         .map(|s| s.to_string());
 
+    // SQL over WebSocket, see [`sql_over_ws`].
+    if hyper_tungstenite::is_upgrade_request(&request) && request.uri().path() == "/sql" {
+        info!(session_id = ?session_id, "performing websocket upgrade for sql-over-websocket");
+
+        let (response, websocket) = hyper_tungstenite::upgrade(&mut request, None)
+            .map_err(|e| ApiError::BadRequest(e.into()))?;
+
+        tokio::spawn(
+            async move {
+                if let Err(e) = sql_over_ws::serve(websocket, host, sni_hostname, conn_pool).await {
+                    error!(session_id = ?session_id, "error in sql-over-websocket: {e:#}");
+                }
+            }
+            .instrument(info_span!("sql-over-websocket")),
+        );
+
+        Ok(response)
     // Check if the request is a websocket upgrade request.
-    if hyper_tungstenite::is_upgrade_request(&request) {
+    } else if hyper_tungstenite::is_upgrade_request(&request) {
         info!(session_id = ?session_id, "performing websocket upgrade");
 
         let (response, websocket) = hyper_tungstenite::upgrade(&mut request, None)
@@ -214,13 +231,7 @@ async fn ws_handler(
             Ok(r) => r,
             Err(e) => {
                 let message = format!("{:?}", e);
-                let code = match e.downcast_ref::<tokio_postgres::Error>() {
-                    Some(e) => match e.code() {
-                        Some(e) => serde_json::to_value(e.code()).unwrap(),
-                        None => Value::Null,
-                    },
-                    None => Value::Null,
-                };
+                let code = sql_over_http::error_code(&e);
                 error!(
                     ?code,
                     "sql-over-http per-client task finished with an error: {e:#}"
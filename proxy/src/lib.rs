@@ -16,7 +16,9 @@ pub mod http;
 pub mod logging;
 pub mod metrics;
 pub mod parse;
+pub mod pooler;
 pub mod proxy;
+pub mod rate_limiter;
 pub mod sasl;
 pub mod scram;
 pub mod stream;
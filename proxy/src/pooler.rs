@@ -0,0 +1,748 @@
+//! Transaction-mode pooling of compute connections.
+//!
+//! Serverless workloads open thousands of short-lived connections, and each of them
+//! would otherwise hold a connection to the compute, running into its
+//! `max_connections`. The clients which connect to the endpoint name with the
+//! [`POOLER_SUFFIX`], e.g. `ep-foo-123456-pooler.<region>.<domain>` or
+//! `options=endpoint=ep-foo-123456-pooler`, share at most
+//! `--pooler-max-conns-per-endpoint` compute connections per endpoint instead.
+//!
+//! A session holds a compute connection only for the duration of a transaction:
+//! from the first message the client sends, until the compute reports that it's idle
+//! with `ReadyForQuery`, and no other queries of the client are pending. Then the
+//! connection goes back to the pool, for the next transaction of any session of the
+//! same user, database and options. When all the connections of the endpoint are
+//! busy, the sessions wait for one, for up to [`ACQUIRE_TIMEOUT`].
+//!
+//! The proxy doesn't check the password of the cleartext, password hack and token
+//! exchange flows itself, the compute does when a connection is opened with it. So
+//! the first transaction of such a session runs on a new connection, opened with the
+//! credentials of the client, and only the next ones reuse the idle connections.
+//!
+//! Like with other transaction-mode poolers, the sessions must not rely on any state
+//! outside of a transaction: plain `SET`, session advisory locks, `LISTEN`, temporary
+//! tables and named prepared statements stay on the connection which happened to run
+//! them. The cancel key a client gets cancels the query of its current transaction.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::FutureExt;
+use metrics::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use pq_proto::{BeMessage as Be, ProtocolError, StartupMessageParams};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use crate::auth::{self, ClientCredentials};
+use crate::cancellation::{self, CancelClosure};
+use crate::compute::ComputeStream;
+use crate::console::messages::MetricsAuxInfo;
+use crate::proxy::NUM_BYTES_PROXIED_COUNTER;
+
+/// Suffix of the endpoint name which asks for a pooled session.
+pub const POOLER_SUFFIX: &str = "-pooler";
+
+/// How long a session waits for a compute connection when all of them are busy.
+pub const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the idle connections are checked for the idle timeout.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Error reported to the client when it can't get a compute connection.
+pub const ERR_NO_CONNECTION: &str = "couldn't get a connection to the compute node";
+
+/// Upper limit of a message of the compute, that of Postgres.
+pub const MAX_SERVER_MESSAGE_SIZE: usize = 1 << 30;
+
+/// Upper limit of a message of the client, not to buffer whatever length it claims.
+pub const MAX_CLIENT_MESSAGE_SIZE: usize = 64 << 20;
+
+/// The buffer of a [`MessageStream`] grows by this much at most per read, rather
+/// than by the length a message claims up front.
+const READ_CHUNK_SIZE: usize = 64 << 10;
+
+const MSG_QUERY: u8 = b'Q';
+const MSG_SYNC: u8 = b'S';
+const MSG_FUNCTION_CALL: u8 = b'F';
+const MSG_TERMINATE: u8 = b'X';
+const MSG_READY_FOR_QUERY: u8 = b'Z';
+const MSG_NOTICE_RESPONSE: u8 = b'N';
+const MSG_PARAMETER_STATUS: u8 = b'S';
+const MSG_NOTIFICATION_RESPONSE: u8 = b'A';
+const TX_STATUS_IDLE: u8 = b'I';
+
+static POOLER_COMPUTE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proxy_pooler_compute_connections",
+        "Number of open compute connections of the pooled sessions, idle or in use.",
+    )
+    .unwrap()
+});
+
+static POOLER_ACQUIRES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_pooler_acquires_total",
+        "Number of compute connections taken from the pools, by outcome.",
+        &["outcome"],
+    )
+    .unwrap()
+});
+
+/// Strips the [`POOLER_SUFFIX`] from the endpoint name of the credentials, and tells
+/// whether it was there.
+pub fn strip_pooler_suffix(creds: &mut auth::BackendType<'_, ClientCredentials<'_>>) -> bool {
+    let project = match creds {
+        auth::BackendType::Console(_, creds) | auth::BackendType::Postgres(_, creds) => {
+            &mut creds.project
+        }
+        auth::BackendType::Link(_) | auth::BackendType::Test(_) => return false,
+    };
+    match project
+        .as_deref()
+        .and_then(|p| p.strip_suffix(POOLER_SUFFIX))
+    {
+        Some(endpoint) => {
+            *project = Some(endpoint.to_owned());
+            true
+        }
+        None => false,
+    }
+}
+
+/// The compute connections of a pool are shared by the sessions of the same user,
+/// database and options.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    user: String,
+    dbname: String,
+    options: String,
+}
+
+impl PoolKey {
+    pub fn new(params: &StartupMessageParams) -> Self {
+        let user = params.get("user").unwrap_or_default();
+        PoolKey {
+            user: user.to_owned(),
+            dbname: params.get("database").unwrap_or(user).to_owned(),
+            options: params.get("options").unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+/// The connection pools of all endpoints.
+pub struct Pooler {
+    max_conns_per_endpoint: usize,
+    idle_timeout: Duration,
+    endpoints: Mutex<HashMap<String, Arc<EndpointPool<ServerConn>>>>,
+}
+
+impl Pooler {
+    pub fn new(max_conns_per_endpoint: usize, idle_timeout: Duration) -> Self {
+        Pooler {
+            max_conns_per_endpoint,
+            idle_timeout,
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn endpoint_pool(&self, endpoint: &str) -> Arc<EndpointPool<ServerConn>> {
+        let mut endpoints = self.endpoints.lock();
+        let pool = endpoints.entry(endpoint.to_owned()).or_insert_with(|| {
+            Arc::new(EndpointPool::new(
+                self.max_conns_per_endpoint,
+                self.idle_timeout,
+                ACQUIRE_TIMEOUT,
+            ))
+        });
+        Arc::clone(pool)
+    }
+
+    /// Closes the connections which have been idle for too long, so that they don't
+    /// keep the computes from suspending, and forgets the unused pools.
+    fn close_idle(&self) {
+        let mut endpoints = self.endpoints.lock();
+        endpoints.retain(|_, pool| {
+            pool.close_expired();
+            // New references are only handed out under the lock
+            Arc::strong_count(pool) > 1 || !pool.is_unused()
+        });
+    }
+}
+
+/// Closes the idle connections of the [`Pooler`] after the idle timeout.
+pub async fn task_main(pooler: &'static Pooler) -> anyhow::Result<Infallible> {
+    scopeguard::defer! {
+        info!("pooler idle connection reaper has shut down");
+    }
+
+    let mut ticker = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        pooler.close_idle();
+    }
+}
+
+/// A connection taken from an [`EndpointPool`]. Dropping it closes the connection,
+/// unless it's given back with [`EndpointPool::release`].
+pub struct Pooled<C> {
+    pub conn: C,
+    _permit: Permit,
+}
+
+/// A slot for an open connection of an [`EndpointPool`].
+struct Permit(#[allow(dead_code)] OwnedSemaphorePermit);
+
+impl Permit {
+    fn new(permit: OwnedSemaphorePermit) -> Self {
+        POOLER_COMPUTE_CONNECTIONS.inc();
+        Permit(permit)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        POOLER_COMPUTE_CONNECTIONS.dec();
+    }
+}
+
+struct IdleConn<C> {
+    conn: Pooled<C>,
+    since: Instant,
+}
+
+/// The connections of an endpoint, at most `max_conns` of them, idle or in use.
+pub struct EndpointPool<C> {
+    max_conns: usize,
+    idle_timeout: Duration,
+    acquire_timeout: Duration,
+    permits: Arc<Semaphore>,
+    /// The idle connections, the most recently used last.
+    idle: Mutex<HashMap<PoolKey, Vec<IdleConn<C>>>>,
+    /// Notified when a connection is released.
+    released: Notify,
+}
+
+impl<C> EndpointPool<C> {
+    fn new(max_conns: usize, idle_timeout: Duration, acquire_timeout: Duration) -> Self {
+        EndpointPool {
+            max_conns,
+            idle_timeout,
+            acquire_timeout,
+            permits: Arc::new(Semaphore::new(max_conns)),
+            idle: Mutex::new(HashMap::new()),
+            released: Notify::new(),
+        }
+    }
+
+    /// Takes an idle connection for `key`, or opens a new one with `connect` if the
+    /// pool isn't full. Otherwise closes an idle connection of another key to make
+    /// room, or waits for a connection to be released.
+    pub async fn acquire<F>(&self, key: &PoolKey, connect: F) -> anyhow::Result<Pooled<C>>
+    where
+        F: Future<Output = anyhow::Result<C>>,
+    {
+        self.acquire_impl(key, connect, true).await
+    }
+
+    /// Opens a new connection with `connect`, like [`Self::acquire`] but without
+    /// reusing an idle connection, closing one of any key to make room if needed.
+    pub async fn acquire_new<F>(&self, key: &PoolKey, connect: F) -> anyhow::Result<Pooled<C>>
+    where
+        F: Future<Output = anyhow::Result<C>>,
+    {
+        self.acquire_impl(key, connect, false).await
+    }
+
+    async fn acquire_impl<F>(
+        &self,
+        key: &PoolKey,
+        connect: F,
+        reuse: bool,
+    ) -> anyhow::Result<Pooled<C>>
+    where
+        F: Future<Output = anyhow::Result<C>>,
+    {
+        let deadline = tokio::time::Instant::now() + self.acquire_timeout;
+        let permit = loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if reuse {
+                if let Some(conn) = self.take_idle(key) {
+                    POOLER_ACQUIRES.with_label_values(&["reused"]).inc();
+                    return Ok(conn);
+                }
+            }
+            if let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() {
+                break permit;
+            }
+            if self.close_longest_idle(reuse.then_some(key)) {
+                continue;
+            }
+            tokio::select! {
+                permit = Arc::clone(&self.permits).acquire_owned() => {
+                    break permit.expect("the semaphore is never closed");
+                }
+                _ = released => {}
+                _ = tokio::time::sleep_until(deadline) => {
+                    POOLER_ACQUIRES.with_label_values(&["timed_out"]).inc();
+                    bail!("timed out waiting for a free compute connection");
+                }
+            }
+        };
+
+        // The permit is returned if the connection fails
+        let permit = Permit::new(permit);
+        let conn = connect.await?;
+        POOLER_ACQUIRES.with_label_values(&["opened"]).inc();
+        Ok(Pooled {
+            conn,
+            _permit: permit,
+        })
+    }
+
+    /// Gives back a connection which is idle, for the next transaction of `key`.
+    pub fn release(&self, key: PoolKey, conn: Pooled<C>) {
+        self.idle.lock().entry(key).or_default().push(IdleConn {
+            conn,
+            since: Instant::now(),
+        });
+        self.released.notify_waiters();
+    }
+
+    fn take_idle(&self, key: &PoolKey) -> Option<Pooled<C>> {
+        let mut idle = self.idle.lock();
+        let conns = idle.get_mut(key)?;
+        conns.retain(|conn| conn.since.elapsed() < self.idle_timeout);
+        let conn = conns.pop();
+        if conns.is_empty() {
+            idle.remove(key);
+        }
+        conn.map(|idle| idle.conn)
+    }
+
+    /// Closes the longest idle connection of a key other than `except`, if there's one.
+    fn close_longest_idle(&self, except: Option<&PoolKey>) -> bool {
+        let mut idle = self.idle.lock();
+        let Some(other) = idle
+            .iter()
+            .filter(|(other, _)| Some(*other) != except)
+            .min_by_key(|(_, conns)| conns[0].since)
+            .map(|(other, _)| other.clone())
+        else {
+            return false;
+        };
+        let conns = idle.get_mut(&other).unwrap();
+        conns.remove(0);
+        if conns.is_empty() {
+            idle.remove(&other);
+        }
+        true
+    }
+
+    fn close_expired(&self) {
+        let mut idle = self.idle.lock();
+        idle.retain(|_, conns| {
+            conns.retain(|conn| conn.since.elapsed() < self.idle_timeout);
+            !conns.is_empty()
+        });
+    }
+
+    fn is_unused(&self) -> bool {
+        self.idle.lock().is_empty() && self.permits.available_permits() == self.max_conns
+    }
+}
+
+/// A compute connection of a pool, after the startup.
+pub struct ServerConn {
+    pub stream: MessageStream<ComputeStream>,
+    /// The parameters the compute reported at the startup.
+    pub params: HashMap<String, String>,
+    pub cancel_closure: CancelClosure,
+}
+
+impl EndpointPool<ServerConn> {
+    /// Takes a connection like [`Self::acquire`], or [`Self::acquire_new`] unless
+    /// `reuse`. The asynchronous messages the compute sent while the connection was
+    /// idle are discarded, not to leak into the session, and the idle connections the
+    /// compute closed in the meantime are skipped.
+    pub async fn acquire_conn(
+        &self,
+        key: &PoolKey,
+        connector: &mut impl Connect,
+        reuse: bool,
+    ) -> anyhow::Result<Pooled<ServerConn>> {
+        loop {
+            let mut conn = self.acquire_impl(key, connector.connect(), reuse).await?;
+            match conn.conn.stream.discard_async_messages() {
+                Ok(()) => return Ok(conn),
+                Err(e) => info!("closing an idle compute connection: {e}"),
+            }
+        }
+    }
+}
+
+/// Opens the compute connections of a pooled session.
+#[async_trait]
+pub trait Connect {
+    async fn connect(&mut self) -> anyhow::Result<ServerConn>;
+}
+
+/// Stream of protocol messages after the startup, each a type byte followed by the
+/// length of the rest of the message, including the length itself.
+pub struct MessageStream<S> {
+    stream: S,
+    buf: BytesMut,
+    max_message_size: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MessageStream<S> {
+    /// Wraps a stream, with the data which has already been read from it. The longer
+    /// messages fail the reads.
+    pub fn new(stream: S, buf: BytesMut, max_message_size: usize) -> Self {
+        MessageStream {
+            stream,
+            buf,
+            max_message_size,
+        }
+    }
+
+    /// Reads the next message, `None` if the peer closed the stream. Cancel safe.
+    pub async fn read_message(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            if let Some(msg) = self.parse_message()? {
+                return Ok(Some(msg));
+            }
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    fn parse_message(&mut self) -> io::Result<Option<Bytes>> {
+        let Some(len) = self.buf.get(1..5) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        if !(4..=self.max_message_size).contains(&len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid message length {len}"),
+            ));
+        }
+        if self.buf.len() < 1 + len {
+            self.buf
+                .reserve((1 + len - self.buf.len()).min(READ_CHUNK_SIZE));
+            return Ok(None);
+        }
+        Ok(Some(self.buf.split_to(1 + len).freeze()))
+    }
+
+    /// Drops the asynchronous messages which have already arrived, the notices,
+    /// parameter changes and notifications of an idle connection. Fails if the peer
+    /// closed the stream, or sent anything else.
+    pub fn discard_async_messages(&mut self) -> io::Result<()> {
+        while let Some(msg) = self.read_message().now_or_never() {
+            let msg = msg?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            if !matches!(
+                msg[0],
+                MSG_NOTICE_RESPONSE | MSG_PARAMETER_STATUS | MSG_NOTIFICATION_RESPONSE
+            ) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unexpected message '{}' on an idle connection",
+                        msg[0] as char
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn write_message(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.stream.write_all(msg).await?;
+        self.stream.flush().await
+    }
+
+    async fn write_error(&mut self, error: &str) -> io::Result<()> {
+        let mut buf = BytesMut::new();
+        Be::write(&mut buf, &Be::ErrorResponse(error, None))
+            .map_err(ProtocolError::into_io_error)?;
+        self.write_message(&buf).await
+    }
+}
+
+/// A compute connection in use by a session.
+struct Attached {
+    conn: Pooled<ServerConn>,
+    /// Number of the client's queries and syncs the compute hasn't answered with
+    /// `ReadyForQuery` yet.
+    pending: usize,
+}
+
+/// Relays the messages of a pooled session between the client and the compute
+/// connections it takes from `pool` for its transactions, until the client
+/// terminates the session.
+pub async fn relay<S: AsyncRead + AsyncWrite + Unpin>(
+    mut client: MessageStream<S>,
+    pool: &EndpointPool<ServerConn>,
+    key: &PoolKey,
+    connector: &mut impl Connect,
+    session: &cancellation::Session<'_>,
+    aux: &MetricsAuxInfo,
+) -> anyhow::Result<()> {
+    let m_sent = NUM_BYTES_PROXIED_COUNTER.with_label_values(&aux.traffic_labels("tx"));
+    let m_recv = NUM_BYTES_PROXIED_COUNTER.with_label_values(&aux.traffic_labels("rx"));
+
+    let mut attached: Option<Attached> = None;
+    loop {
+        let current = match &mut attached {
+            Some(current) => current,
+            None => {
+                // Take a connection once the client starts its next transaction
+                let Some(msg) = client.read_message().await? else {
+                    return Ok(());
+                };
+                if msg[0] == MSG_TERMINATE {
+                    return Ok(());
+                }
+                let conn = match pool.acquire_conn(key, connector, true).await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("couldn't get a compute connection: {e:#}");
+                        client.write_error(ERR_NO_CONNECTION).await?;
+                        return Err(e);
+                    }
+                };
+                session.enable_query_cancellation(conn.conn.cancel_closure.clone());
+                let current = attached.insert(Attached { conn, pending: 0 });
+                current.send(&msg).await?;
+                m_recv.inc_by(msg.len() as u64);
+                current
+            }
+        };
+
+        let mut release = false;
+        tokio::select! {
+            msg = client.read_message() => match msg? {
+                // The connection is in the middle of a transaction, so it's closed
+                None => return Ok(()),
+                Some(msg) if msg[0] == MSG_TERMINATE => return Ok(()),
+                Some(msg) => {
+                    current.send(&msg).await?;
+                    m_recv.inc_by(msg.len() as u64);
+                }
+            },
+            msg = current.conn.conn.stream.read_message() => {
+                let msg = msg?.context("compute closed the connection")?;
+                client.write_message(&msg).await?;
+                m_sent.inc_by(msg.len() as u64);
+                if msg[0] == MSG_READY_FOR_QUERY {
+                    current.pending = current.pending.saturating_sub(1);
+                    release = current.pending == 0 && msg.get(5) == Some(&TX_STATUS_IDLE);
+                }
+            }
+        }
+
+        if release {
+            session.disable_query_cancellation();
+            let Attached { conn, .. } = attached.take().unwrap();
+            pool.release(key.clone(), conn);
+        }
+    }
+}
+
+impl Attached {
+    async fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        if matches!(msg[0], MSG_QUERY | MSG_SYNC | MSG_FUNCTION_CALL) {
+            self.pending += 1;
+        }
+        self.conn.conn.stream.write_message(msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(user: &str) -> PoolKey {
+        PoolKey::new(&StartupMessageParams::new([("user", user)]))
+    }
+
+    fn pool(max_conns: usize) -> EndpointPool<u32> {
+        EndpointPool::new(
+            max_conns,
+            Duration::from_secs(60),
+            Duration::from_millis(100),
+        )
+    }
+
+    #[tokio::test]
+    async fn reuse_and_limit() -> anyhow::Result<()> {
+        let pool = pool(2);
+        let c1 = pool.acquire(&key("a"), async { Ok(1) }).await?;
+        let c2 = pool.acquire(&key("a"), async { Ok(2) }).await?;
+
+        // the pool is full
+        let err = pool.acquire(&key("a"), async { Ok(3) }).await;
+        assert!(err.is_err());
+
+        // the released connection is reused
+        pool.release(key("a"), c1);
+        let c1 = pool.acquire(&key("a"), async { Ok(3) }).await?;
+        assert_eq!(c1.conn, 1);
+
+        // closing a connection makes room for a new one
+        drop(c2);
+        let c3 = pool.acquire(&key("a"), async { Ok(3) }).await?;
+        assert_eq!(c3.conn, 3);
+
+        // an idle connection of another user is closed to make room
+        pool.release(key("a"), c1);
+        let c4 = pool.acquire(&key("b"), async { Ok(4) }).await?;
+        assert_eq!(c4.conn, 4);
+        assert!(pool.take_idle(&key("a")).is_none());
+
+        // failing to connect gives back the slot
+        drop(c4);
+        assert!(pool
+            .acquire(&key("b"), async { anyhow::bail!("no") })
+            .await
+            .is_err());
+        pool.acquire(&key("b"), async { Ok(5) }).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn acquire_new() -> anyhow::Result<()> {
+        let pool = pool(1);
+        let c1 = pool.acquire(&key("a"), async { Ok(1) }).await?;
+        pool.release(key("a"), c1);
+
+        // the idle connection of the same key is closed to make room, not reused
+        let c2 = pool.acquire_new(&key("a"), async { Ok(2) }).await?;
+        assert_eq!(c2.conn, 2);
+        pool.release(key("a"), c2);
+        assert_eq!(pool.acquire(&key("a"), async { Ok(3) }).await?.conn, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiters_get_released_connections() -> anyhow::Result<()> {
+        let pool = Arc::new(pool(1));
+        let c1 = pool.acquire(&key("a"), async { Ok(1) }).await?;
+
+        let waiter = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move {
+                pool.acquire(&key("a"), async { Ok(2) })
+                    .await
+                    .map(|c| c.conn)
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        pool.release(key("a"), c1);
+        assert_eq!(waiter.await??, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let pool = EndpointPool::new(1, Duration::ZERO, Duration::ZERO);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let c1 = rt
+            .block_on(pool.acquire(&key("a"), async { Ok(1) }))
+            .unwrap();
+        pool.release(key("a"), c1);
+        pool.close_expired();
+        assert!(pool.is_unused());
+    }
+
+    #[tokio::test]
+    async fn message_framing() -> anyhow::Result<()> {
+        let (client, mut server) = tokio::io::duplex(1024);
+        // a query and the beginning of a sync, after a message that has been read
+        let mut stream = MessageStream::new(
+            client,
+            BytesMut::from(&b"Q\0\0\0\x0bse"[..]),
+            MAX_CLIENT_MESSAGE_SIZE,
+        );
+        server.write_all(b"lect\0S\0\0").await?;
+
+        let msg = stream.read_message().await?.unwrap();
+        assert_eq!(&msg[..], b"Q\0\0\0\x0bselect\0");
+
+        server.write_all(b"\0\x04").await?;
+        let msg = stream.read_message().await?.unwrap();
+        assert_eq!(&msg[..], b"S\0\0\0\x04");
+
+        drop(server);
+        assert!(stream.read_message().await?.is_none());
+
+        let (client, _server) = tokio::io::duplex(1024);
+        let mut stream = MessageStream::new(
+            client,
+            BytesMut::from(&b"Q\0\0\0\x02"[..]),
+            MAX_CLIENT_MESSAGE_SIZE,
+        );
+        assert!(stream.read_message().await.is_err());
+
+        // too long
+        let (client, _server) = tokio::io::duplex(1024);
+        let mut stream = MessageStream::new(client, BytesMut::from(&b"Q\0\x20\0\0"[..]), 1 << 20);
+        assert!(stream.read_message().await.is_err());
+
+        // the buffer isn't grown to the length claimed up front
+        let (client, server) = tokio::io::duplex(1024);
+        let mut stream = MessageStream::new(client, BytesMut::from(&b"Q\0\x08\0\0"[..]), 1 << 20);
+        drop(server);
+        assert!(stream.read_message().await.is_err());
+        assert!(stream.buf.capacity() < 1 << 19);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discard_async_messages() -> anyhow::Result<()> {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut stream = MessageStream::new(client, BytesMut::new(), MAX_SERVER_MESSAGE_SIZE);
+
+        // nothing has arrived
+        stream.discard_async_messages()?;
+
+        // a notice, a parameter change and a notification
+        server.write_all(b"N\0\0\0\x05x").await?;
+        server.write_all(b"S\0\0\0\x06a\0").await?;
+        server.write_all(b"A\0\0\0\x04").await?;
+        stream.discard_async_messages()?;
+        assert!(stream.buf.is_empty());
+
+        // anything else means the connection is broken
+        server.write_all(b"E\0\0\0\x04").await?;
+        assert!(stream.discard_async_messages().is_err());
+
+        drop(server);
+        let (client, server) = tokio::io::duplex(1024);
+        let mut stream = MessageStream::new(client, BytesMut::new(), MAX_SERVER_MESSAGE_SIZE);
+        drop(server);
+        assert!(stream.discard_async_messages().is_err());
+        Ok(())
+    }
+}
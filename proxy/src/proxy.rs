@@ -7,6 +7,8 @@ use crate::{
     compute::{self, PostgresConnection},
     config::{ProxyConfig, TlsConfig},
     console::{self, errors::WakeComputeError, messages::MetricsAuxInfo, Api},
+    pooler::{self, Connect, Pooler},
+    rate_limiter::EndpointRateLimiter,
     stream::{PqStream, Stream},
 };
 use anyhow::{bail, Context};
@@ -17,7 +19,7 @@ use metrics::{
 };
 use once_cell::sync::Lazy;
 use pq_proto::{BeMessage as Be, FeStartupPacket, StartupMessageParams};
-use std::{error::Error, io, ops::ControlFlow, sync::Arc};
+use std::{collections::HashMap, error::Error, io, ops::ControlFlow, sync::Arc};
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     time,
@@ -72,7 +74,7 @@ static NUM_CONNECTION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
-static NUM_BYTES_PROXIED_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
+pub(crate) static NUM_BYTES_PROXIED_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "proxy_io_bytes_per_client",
         "Number of bytes sent/received between client and backend.",
@@ -211,7 +213,7 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
     };
 
     // Extract credentials which we're going to use for auth.
-    let creds = {
+    let mut creds = {
         let hostname = mode.hostname(stream.get_ref());
         let common_names = tls.and_then(|tls| tls.common_names.clone());
         let result = config
@@ -226,12 +228,44 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
         }
     };
 
+    // Sessions to the endpoint name with the pooler suffix share compute connections.
+    let pooler = if pooler::strip_pooler_suffix(&mut creds) {
+        if config.pooler.is_none() {
+            info!("connection pooling is disabled, connecting to compute directly");
+        }
+        config.pooler.as_ref()
+    } else {
+        None
+    };
+
+    // Held until the client disconnects. The pooled sessions count towards the
+    // limits of the endpoint too, as they cost a console request all the same.
+    // Without an endpoint in the startup message or the SNI, the client names it
+    // along with its password, so it's limited after authentication.
+    let mut late_rate_limiter = None;
+    let _conn_guard = match (&config.rate_limiter, &creds) {
+        (
+            Some(limiter),
+            auth::BackendType::Console(_, creds) | auth::BackendType::Postgres(_, creds),
+        ) => match creds.project().map(|endpoint| limiter.acquire(endpoint)) {
+            Some(Ok(guard)) => Some(guard),
+            Some(Err(e)) => stream.throw_error(e).await?,
+            None => {
+                late_rate_limiter = Some(limiter);
+                None
+            }
+        },
+        _ => None,
+    };
+
     let client = Client::new(
         stream,
         creds,
         &params,
         session_id,
         mode.allow_self_signed_compute(config),
+        pooler,
+        late_rate_limiter,
     );
     cancel_map
         .with_session(|session| client.connect_to_db(session, mode.allow_cleartext()))
@@ -470,6 +504,34 @@ where
     }
 }
 
+/// Wake up the compute node, retrying with a backoff while the console fails
+/// with a transient error.
+#[tracing::instrument(skip_all)]
+pub async fn wake_compute(
+    api: &impl Api,
+    extra: &console::ConsoleReqExtra<'_>,
+    creds: &auth::ClientCredentials<'_>,
+) -> Result<console::CachedNodeInfo, WakeComputeError> {
+    let mut num_retries = 0;
+    loop {
+        let wake_res = api.wake_compute(extra, creds).await;
+        match handle_try_wake(wake_res, num_retries) {
+            Err(e) => {
+                error!(error = ?e, num_retries, retriable = false, "couldn't wake compute node");
+                return Err(e);
+            }
+            Ok(ControlFlow::Continue(e)) => {
+                warn!(error = ?e, num_retries, retriable = true, "couldn't wake compute node");
+            }
+            Ok(ControlFlow::Break(node_info)) => return Ok(node_info),
+        }
+
+        let wait_duration = retry_after(num_retries);
+        num_retries += 1;
+        time::sleep(wait_duration).await;
+    }
+}
+
 /// Attempts to wake up the compute node.
 /// * Returns Ok(Continue(e)) if there was an error waking but retries are acceptable
 /// * Returns Ok(Break(node)) if the wakeup succeeded
@@ -553,14 +615,15 @@ pub fn retry_after(num_retries: u32) -> time::Duration {
 /// Finish client connection initialization: confirm auth success, send params, etc.
 #[tracing::instrument(skip_all)]
 async fn prepare_client_connection(
-    node: &compute::PostgresConnection,
+    params: &HashMap<String, String>,
+    cancel_closure: &cancellation::CancelClosure,
     reported_auth_ok: bool,
-    session: cancellation::Session<'_>,
+    session: &cancellation::Session<'_>,
     stream: &mut PqStream<impl AsyncRead + AsyncWrite + Unpin>,
 ) -> anyhow::Result<()> {
     // Register compute's query cancellation token and produce a new, unique one.
     // The new token (cancel_key_data) will be sent to the client.
-    let cancel_key_data = session.enable_query_cancellation(node.cancel_closure.clone());
+    let cancel_key_data = session.enable_query_cancellation(cancel_closure.clone());
 
     // Report authentication success if we haven't done this already.
     // Note that we do this only (for the most part) after we've connected
@@ -572,7 +635,7 @@ async fn prepare_client_connection(
     // Forward all postgres connection params to the client.
     // Right now the implementation is very hacky and inefficent (ideally,
     // we don't need an intermediate hashmap), but at least it should be correct.
-    for (name, value) in &node.params {
+    for (name, value) in params {
         // TODO: Theoretically, this could result in a big pile of params...
         stream.write_message_noflush(&Be::ParameterStatus {
             name: name.as_bytes(),
@@ -634,6 +697,11 @@ struct Client<'a, S> {
     session_id: uuid::Uuid,
     /// Allow self-signed certificates (for testing).
     allow_self_signed_compute: bool,
+    /// Pool of compute connections, if the client asked for a pooled session.
+    pooler: Option<&'static Pooler>,
+    /// Limits the endpoint once the authentication has named it, if the credentials
+    /// didn't.
+    rate_limiter: Option<&'static EndpointRateLimiter>,
 }
 
 impl<'a, S> Client<'a, S> {
@@ -644,6 +712,8 @@ impl<'a, S> Client<'a, S> {
         params: &'a StartupMessageParams,
         session_id: uuid::Uuid,
         allow_self_signed_compute: bool,
+        pooler: Option<&'static Pooler>,
+        rate_limiter: Option<&'static EndpointRateLimiter>,
     ) -> Self {
         Self {
             stream,
@@ -651,6 +721,8 @@ impl<'a, S> Client<'a, S> {
             params,
             session_id,
             allow_self_signed_compute,
+            pooler,
+            rate_limiter,
         }
     }
 }
@@ -671,6 +743,8 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
             params,
             session_id,
             allow_self_signed_compute,
+            pooler,
+            rate_limiter,
         } = self;
 
         let extra = console::ConsoleReqExtra {
@@ -686,19 +760,52 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
             Err(e) => return stream.throw_error(e).await,
         };
 
+        // Held until the client disconnects, like the guard taken before authentication.
+        let _conn_guard = match rate_limiter.zip(creds.get_endpoint()) {
+            Some((limiter, endpoint)) => match limiter.acquire(&endpoint) {
+                Ok(guard) => Some(guard),
+                Err(e) => return stream.throw_error(e).await,
+            },
+            None => None,
+        };
+
         let AuthSuccess {
             reported_auth_ok,
+            verified_by_proxy,
             value: mut node_info,
         } = auth_result;
 
         node_info.allow_self_signed_compute = allow_self_signed_compute;
 
         let aux = node_info.aux.clone();
+        if let Some(pooler) = pooler {
+            // The regions of an endpoint have separate computes, and pools
+            let endpoint = creds.get_compute_key().unwrap_or_default();
+            let mut connector = ComputeConnector::new(node_info, params, &extra, &creds);
+            return connect_to_pool(
+                pooler,
+                &endpoint,
+                &mut connector,
+                reported_auth_ok,
+                verified_by_proxy,
+                session,
+                stream,
+            )
+            .await;
+        }
+
         let mut node = connect_to_compute(&TcpMechanism { params }, node_info, &extra, &creds)
             .or_else(|e| stream.throw_error(e))
             .await?;
 
-        prepare_client_connection(&node, reported_auth_ok, session, &mut stream).await?;
+        prepare_client_connection(
+            &node.params,
+            &node.cancel_closure,
+            reported_auth_ok,
+            &session,
+            &mut stream,
+        )
+        .await?;
         // Before proxy passing, forward to compute whatever data is left in the
         // PqStream input buffer. Normally there is none, but our serverless npm
         // driver in pipeline mode sends startup, password and first query
@@ -708,3 +815,105 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
         proxy_pass(stream, node.stream, &aux).await
     }
 }
+
+/// Opens the compute connections of a pooled session.
+struct ComputeConnector<'a> {
+    /// The compute node the client has been authenticated for, until the first
+    /// connection has been opened.
+    node_info: Option<console::CachedNodeInfo>,
+    /// Connection params of the compute node, for the connections after the first.
+    config: compute::ConnCfg,
+    aux: Arc<MetricsAuxInfo>,
+    allow_self_signed_compute: bool,
+    params: &'a StartupMessageParams,
+    extra: &'a console::ConsoleReqExtra<'a>,
+    creds: &'a auth::BackendType<'a, auth::ClientCredentials<'a>>,
+}
+
+impl<'a> ComputeConnector<'a> {
+    fn new(
+        node_info: console::CachedNodeInfo,
+        params: &'a StartupMessageParams,
+        extra: &'a console::ConsoleReqExtra<'a>,
+        creds: &'a auth::BackendType<'a, auth::ClientCredentials<'a>>,
+    ) -> Self {
+        Self {
+            config: node_info.config.clone(),
+            aux: node_info.aux.clone(),
+            allow_self_signed_compute: node_info.allow_self_signed_compute,
+            node_info: Some(node_info),
+            params,
+            extra,
+            creds,
+        }
+    }
+}
+
+#[async_trait]
+impl Connect for ComputeConnector<'_> {
+    async fn connect(&mut self) -> anyhow::Result<pooler::ServerConn> {
+        let node_info = self.node_info.take().unwrap_or_else(|| {
+            console::CachedNodeInfo::new_uncached(console::NodeInfo {
+                config: self.config.clone(),
+                aux: self.aux.clone(),
+                allow_self_signed_compute: self.allow_self_signed_compute,
+            })
+        });
+        let mechanism = TcpMechanism {
+            params: self.params,
+        };
+        let node = connect_to_compute(&mechanism, node_info, self.extra, self.creds).await?;
+        Ok(pooler::ServerConn {
+            stream: pooler::MessageStream::new(
+                node.stream,
+                Default::default(),
+                pooler::MAX_SERVER_MESSAGE_SIZE,
+            ),
+            params: node.params,
+            cancel_closure: node.cancel_closure,
+        })
+    }
+}
+
+/// Finish the startup of a pooled session with the params of a compute connection
+/// from the pool, then relay its transactions, see [`pooler`].
+///
+/// Unless `verified_by_proxy`, the startup takes a new connection, for the compute
+/// to check the credentials of the client before it gets an idle one.
+#[tracing::instrument(skip_all)]
+async fn connect_to_pool<S: AsyncRead + AsyncWrite + Unpin>(
+    pooler: &Pooler,
+    endpoint: &str,
+    connector: &mut ComputeConnector<'_>,
+    reported_auth_ok: bool,
+    verified_by_proxy: bool,
+    session: cancellation::Session<'_>,
+    mut stream: PqStream<S>,
+) -> anyhow::Result<()> {
+    let pool = pooler.endpoint_pool(endpoint);
+    let key = pooler::PoolKey::new(connector.params);
+    let conn = match pool.acquire_conn(&key, connector, verified_by_proxy).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("couldn't get a compute connection: {e:#}");
+            return stream.throw_error_str(pooler::ERR_NO_CONNECTION).await;
+        }
+    };
+
+    prepare_client_connection(
+        &conn.conn.params,
+        &conn.conn.cancel_closure,
+        reported_auth_ok,
+        &session,
+        &mut stream,
+    )
+    .await?;
+    session.disable_query_cancellation();
+    pool.release(key.clone(), conn);
+
+    info!("relaying the transactions of the pooled session...");
+    let (stream, read_buf) = stream.into_inner();
+    let aux = connector.aux.clone();
+    let client = pooler::MessageStream::new(stream, read_buf, pooler::MAX_CLIENT_MESSAGE_SIZE);
+    pooler::relay(client, &pool, &key, connector, &session, &aux).await
+}
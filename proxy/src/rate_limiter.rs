@@ -0,0 +1,218 @@
+//! Per-endpoint limits of the client connections.
+//!
+//! Every connection wakes the compute through the console and takes a backend of
+//! the compute, so a credential stuffing attack, or a fleet of clients reconnecting
+//! in a loop, could overload both. The limiter bounds, per endpoint:
+//!
+//! * the rate of new connections, with a [`TokenBucket`] holding up to a second's
+//!   worth of `--rate-limit-conns-per-sec` connections,
+//! * the number of connections open at the same time, with
+//!   `--rate-limit-max-concurrent-conns`.
+//!
+//! The connections over the limits are refused with a [`RateLimitError`], before
+//! authentication, so they cost neither a console request nor a compute backend.
+//! The endpoint of a client using the password hack is only known once it has sent
+//! its password, so it's limited after authentication, before taking a backend.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use metrics::{register_int_counter_vec, IntCounterVec};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::info;
+use utils::token_bucket::TokenBucket;
+
+use crate::error::UserFacingError;
+
+/// How often the limiter forgets the endpoints it doesn't need to track anymore.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+static RATE_LIMITED_CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_rate_limited_connections_total",
+        "Number of client connections refused by the per-endpoint limits, by reason.",
+        &["reason"],
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RateLimitError {
+    #[error("too many connection attempts to endpoint {0}, try again later")]
+    TooManyConnectionAttempts(String),
+
+    #[error("too many concurrent connections to endpoint {0}")]
+    TooManyConcurrentConnections(String),
+}
+
+impl UserFacingError for RateLimitError {}
+
+impl RateLimitError {
+    fn metric_label(&self) -> &'static str {
+        match self {
+            RateLimitError::TooManyConnectionAttempts(_) => "rate",
+            RateLimitError::TooManyConcurrentConnections(_) => "concurrency",
+        }
+    }
+}
+
+pub struct EndpointRateLimiter {
+    /// New connections per second, 0 for no limit.
+    conns_per_sec: u32,
+    /// Open connections, 0 for no limit.
+    max_concurrent_conns: usize,
+    endpoints: Mutex<HashMap<String, EndpointState>>,
+}
+
+struct EndpointState {
+    /// Holds up to `conns_per_sec` new connections.
+    bucket: TokenBucket,
+    open_conns: usize,
+}
+
+impl EndpointRateLimiter {
+    pub fn new(conns_per_sec: u32, max_concurrent_conns: usize) -> Self {
+        EndpointRateLimiter {
+            conns_per_sec,
+            max_concurrent_conns,
+            endpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admits a new connection to `endpoint`, which counts as open until the
+    /// returned guard is dropped.
+    pub fn acquire(&self, endpoint: &str) -> Result<ConnectionGuard<'_>, RateLimitError> {
+        self.acquire_at(endpoint, Instant::now()).map_err(|e| {
+            RATE_LIMITED_CONNECTIONS
+                .with_label_values(&[e.metric_label()])
+                .inc();
+            e
+        })
+    }
+
+    fn acquire_at(
+        &self,
+        endpoint: &str,
+        now: Instant,
+    ) -> Result<ConnectionGuard<'_>, RateLimitError> {
+        let mut endpoints = self.endpoints.lock();
+        if !endpoints.contains_key(endpoint) {
+            let state = EndpointState {
+                bucket: TokenBucket::new_at(self.conns_per_sec, self.conns_per_sec, now),
+                open_conns: 0,
+            };
+            endpoints.insert(endpoint.to_owned(), state);
+        }
+        let state = endpoints.get_mut(endpoint).unwrap();
+
+        if self.max_concurrent_conns > 0 && state.open_conns >= self.max_concurrent_conns {
+            return Err(RateLimitError::TooManyConcurrentConnections(
+                endpoint.to_owned(),
+            ));
+        }
+        if state.bucket.try_acquire_at(1, now).is_err() {
+            return Err(RateLimitError::TooManyConnectionAttempts(
+                endpoint.to_owned(),
+            ));
+        }
+        state.open_conns += 1;
+
+        Ok(ConnectionGuard {
+            limiter: self,
+            endpoint: endpoint.to_owned(),
+        })
+    }
+
+    /// Forgets the endpoints without open connections, whose bucket is full again,
+    /// as they are in the same state as the endpoints the limiter has never seen.
+    fn cleanup(&self, now: Instant) {
+        let mut endpoints = self.endpoints.lock();
+        endpoints.retain(|_, state| state.open_conns > 0 || !state.bucket.is_full_at(now));
+    }
+}
+
+/// Periodically forgets the endpoints the limiter doesn't need to track anymore.
+pub async fn task_main(limiter: &'static EndpointRateLimiter) -> anyhow::Result<Infallible> {
+    scopeguard::defer! {
+        info!("rate limiter cleanup has shut down");
+    }
+
+    let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        limiter.cleanup(Instant::now());
+    }
+}
+
+/// An open connection admitted by [`EndpointRateLimiter::acquire`].
+pub struct ConnectionGuard<'a> {
+    limiter: &'a EndpointRateLimiter,
+    endpoint: String,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        let mut endpoints = self.limiter.endpoints.lock();
+        if let Some(state) = endpoints.get_mut(&self.endpoint) {
+            state.open_conns -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_rate() {
+        let limiter = EndpointRateLimiter::new(2, 0);
+        let start = Instant::now();
+
+        // a burst of up to a second's worth of connections
+        drop(limiter.acquire_at("ep-foo", start).unwrap());
+        drop(limiter.acquire_at("ep-foo", start).unwrap());
+        assert_eq!(
+            limiter.acquire_at("ep-foo", start).err(),
+            Some(RateLimitError::TooManyConnectionAttempts("ep-foo".into()))
+        );
+        // other endpoints have their own limit
+        drop(limiter.acquire_at("ep-bar", start).unwrap());
+
+        // the bucket refills over time
+        let later = start + Duration::from_millis(500);
+        drop(limiter.acquire_at("ep-foo", later).unwrap());
+        assert!(limiter.acquire_at("ep-foo", later).is_err());
+
+        // the endpoints with a full bucket are forgotten
+        limiter.cleanup(start + Duration::from_secs(1));
+        assert_eq!(limiter.endpoints.lock().len(), 1);
+        limiter.cleanup(start + Duration::from_secs(2));
+        assert!(limiter.endpoints.lock().is_empty());
+    }
+
+    #[test]
+    fn concurrent_connections() {
+        let limiter = EndpointRateLimiter::new(0, 2);
+        let now = Instant::now();
+
+        let first = limiter.acquire_at("ep-foo", now).unwrap();
+        let _second = limiter.acquire_at("ep-foo", now).unwrap();
+        assert_eq!(
+            limiter.acquire_at("ep-foo", now).err(),
+            Some(RateLimitError::TooManyConcurrentConnections(
+                "ep-foo".into()
+            ))
+        );
+
+        drop(first);
+        let _third = limiter.acquire_at("ep-foo", now).unwrap();
+
+        // endpoints with open connections are kept
+        limiter.cleanup(now);
+        assert_eq!(limiter.endpoints.lock().len(), 1);
+    }
+}
@@ -12,6 +12,7 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::task::JoinError;
 use toml_edit::Document;
 
+use std::env::VarError;
 use std::fs::{self, File};
 use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
@@ -22,32 +23,38 @@ use tokio::sync::mpsc;
 
 use tracing::*;
 use utils::pid_file;
+use utils::region::{RegionConfig, RegionRegistry};
 
 use metrics::set_build_info_metric;
+use safekeeper::chaos::ChaosConfig;
 use safekeeper::defaults::{
     DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR, DEFAULT_MAX_OFFLOADER_LAG_BYTES,
-    DEFAULT_PG_LISTEN_ADDR,
+    DEFAULT_PG_LISTEN_ADDR, DEFAULT_TIMELINE_TOMBSTONE_TTL,
 };
+use safekeeper::wal_forward::WalForwardTarget;
 use safekeeper::wal_service;
 use safekeeper::GlobalTimelines;
 use safekeeper::SafeKeeperConf;
+use safekeeper::TIMELINE_TASKS;
 use safekeeper::{broker, WAL_SERVICE_RUNTIME};
-use safekeeper::{control_file, BROKER_RUNTIME};
+use safekeeper::{control_file, disk_usage, BROKER_RUNTIME};
 use safekeeper::{http, WAL_REMOVER_RUNTIME};
 use safekeeper::{remove_wal, WAL_BACKUP_RUNTIME};
-use safekeeper::{wal_backup, HTTP_RUNTIME};
+use safekeeper::{wal_backup, wal_forward, wal_push, HTTP_RUNTIME};
 use storage_broker::DEFAULT_ENDPOINT;
 use utils::auth::{JwtAuth, Scope};
 use utils::{
-    id::NodeId,
+    id::{NodeId, RegionId},
     logging::{self, LogFormat},
     project_git_version,
     sentry_init::init_sentry,
-    tcp_listener,
+    tcp_listener, unix_listener,
 };
 
 const PID_FILE_NAME: &str = "safekeeper.pid";
 const ID_FILE_NAME: &str = "safekeeper.id";
+/// How long the shutdown waits for the tasks of the timelines.
+const TIMELINE_TASKS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 project_git_version!(GIT_VERSION);
 
@@ -76,6 +83,10 @@ struct Args {
     /// only tenant scoped auth tokens. Pointless if auth is disabled.
     #[arg(long, default_value = None, verbatim_doc_comment)]
     listen_pg_tenant_only: Option<String>,
+    /// Path of a Unix domain socket to additionally receive/send WAL on, for
+    /// the computes and pageservers on the same host.
+    #[arg(long, default_value = None, verbatim_doc_comment)]
+    listen_pg_socket: Option<PathBuf>,
     /// Listen http endpoint for management and metrics in the form host:port.
     #[arg(long, default_value = DEFAULT_HTTP_LISTEN_ADDR)]
     listen_http: String,
@@ -115,6 +126,34 @@ struct Args {
     /// Safekeeper won't be elected for WAL offloading if it is lagging for more than this value in bytes
     #[arg(long, default_value_t = DEFAULT_MAX_OFFLOADER_LAG_BYTES)]
     max_offloader_lag: u64,
+    /// Limit of the data directory size in bytes. Close to it, new timelines
+    /// are refused; at it, the timelines whose WAL isn't being consumed stop
+    /// accepting WAL. Unlimited if not set.
+    #[arg(long, verbatim_doc_comment)]
+    max_disk_usage: Option<u64>,
+    /// How long to keep the tombstone of a timeline deleted with one, as a human
+    /// readable duration. Long enough for the computes that missed the deletion
+    /// to be gone.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = DEFAULT_TIMELINE_TOMBSTONE_TTL, verbatim_doc_comment)]
+    timeline_tombstone_ttl: Duration,
+    /// Push WAL to the pageservers while the broker is unreachable. The
+    /// pageservers are learned from the broker while it is reachable; the JWT
+    /// token to connect to them is taken from PAGESERVER_AUTH_TOKEN env var.
+    #[arg(long, verbatim_doc_comment)]
+    wal_push: bool,
+    /// Comma separated safekeepers of another region to forward the committed
+    /// WAL of the timelines to, as <node_id>=<host:port>. The JWT token to
+    /// connect to them is taken from SAFEKEEPER_AUTH_TOKEN env var.
+    #[arg(long, value_delimiter = ',', verbatim_doc_comment)]
+    wal_forward_to: Vec<WalForwardTarget>,
+    /// Before loading the timelines, validate the page headers and the record
+    /// CRCs of their WAL on disk, reporting committed WAL which is damaged and
+    /// garbage after the last valid record (a torn tail).
+    #[arg(long, verbatim_doc_comment)]
+    verify_wal_on_startup: bool,
+    /// Zero out the torn tails found by --verify-wal-on-startup.
+    #[arg(long, requires = "verify_wal_on_startup")]
+    truncate_torn_wal: bool,
     /// Number of max parallel WAL segments to be offloaded to remote storage.
     #[arg(long, default_value = "5")]
     wal_backup_parallel_jobs: usize,
@@ -122,12 +161,32 @@ struct Args {
     /// WAL backup horizon.
     #[arg(long)]
     disable_wal_backup: bool,
+    /// Also offload the in-progress WAL segment up to commit_lsn, at most once
+    /// per this interval, as a human readable duration. Only full segments
+    /// are offloaded if not set.
+    #[arg(long, value_parser = humantime::parse_duration, verbatim_doc_comment)]
+    partial_backup_interval: Option<Duration>,
     /// Path to a .pem public key which is used to check JWT tokens.
     #[arg(long)]
     auth_validation_public_key_path: Option<PathBuf>,
     /// Format for logging, either 'plain' or 'json'.
     #[arg(long, default_value = "plain")]
     log_format: String,
+    /// Inject faults for testing: delay, drop or reset the accepted connections
+    /// and delay fsyncs, as TOML inline table, e.g.
+    ///   {connection_delay = "200ms", drop_probability = 0.1, reset_probability = 0.1, reset_after = "10s", fsync_delay = "20ms"}
+    /// Never use in production.
+    #[arg(long, value_parser = ChaosConfig::parse, verbatim_doc_comment)]
+    chaos: Option<ChaosConfig>,
+    /// Names and metadata of the regions, as TOML inline array of tables, e.g.
+    ///   [{id = 0, name = "global"}, {id = 1, name = "us-east-2", metadata = {cloud = "aws"}}]
+    /// Should match the regions of the pageservers.
+    #[arg(long, value_parser = parse_regions, verbatim_doc_comment)]
+    regions: Option<RegionRegistry>,
+    /// Region the safekeeper runs in, by id or name. Added to all its metrics, as
+    /// the `node_region` label, and to its log lines.
+    #[arg(long)]
+    region: Option<String>,
     /// Run everything in single threaded current thread runtime, might be
     /// useful for debugging.
     #[arg(long)]
@@ -170,6 +229,14 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(regions) = args.regions {
+        utils::region::init(regions)?;
+    }
+    if let Some(region) = &args.region {
+        let region = region.parse::<RegionId>().context("invalid --region")?;
+        utils::region::tag_process(region)?;
+    }
+
     let auth = match args.auth_validation_public_key_path.as_ref() {
         None => {
             info!("auth is disabled");
@@ -183,11 +250,30 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    let pageserver_auth_token = match std::env::var("PAGESERVER_AUTH_TOKEN") {
+        Ok(token) => {
+            info!("loaded JWT token for authentication with pageservers");
+            Some(token)
+        }
+        Err(VarError::NotPresent) => None,
+        Err(e) => return Err(e).context("failed to read PAGESERVER_AUTH_TOKEN"),
+    };
+
+    let safekeeper_auth_token = match std::env::var("SAFEKEEPER_AUTH_TOKEN") {
+        Ok(token) => {
+            info!("loaded JWT token for authentication with safekeepers");
+            Some(token)
+        }
+        Err(VarError::NotPresent) => None,
+        Err(e) => return Err(e).context("failed to read SAFEKEEPER_AUTH_TOKEN"),
+    };
+
     let conf = SafeKeeperConf {
         workdir,
         my_id: id,
         listen_pg_addr: args.listen_pg,
         listen_pg_addr_tenant_only: args.listen_pg_tenant_only,
+        listen_pg_socket: args.listen_pg_socket,
         listen_http_addr: args.listen_http,
         advertise_pg_addr: args.advertise_pg,
         availability_zone: args.availability_zone,
@@ -197,10 +283,20 @@ async fn main() -> anyhow::Result<()> {
         heartbeat_timeout: args.heartbeat_timeout,
         remote_storage: args.remote_storage,
         max_offloader_lag_bytes: args.max_offloader_lag,
+        max_disk_usage: args.max_disk_usage,
+        timeline_tombstone_ttl: args.timeline_tombstone_ttl,
+        wal_push: args.wal_push,
+        pageserver_auth_token,
+        wal_forward_to: args.wal_forward_to,
+        safekeeper_auth_token,
+        verify_wal_on_startup: args.verify_wal_on_startup,
+        truncate_torn_wal: args.truncate_torn_wal,
         wal_backup_enabled: !args.disable_wal_backup,
         backup_parallel_jobs: args.wal_backup_parallel_jobs,
+        partial_backup_interval: args.partial_backup_interval,
         auth,
         current_thread_runtime: args.current_thread_runtime,
+        chaos: args.chaos,
     };
 
     // initialize sentry if SENTRY_DSN is provided
@@ -250,6 +346,24 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
             None
         };
 
+    let pg_listener_unix = if let Some(listen_pg_socket) = &conf.listen_pg_socket {
+        info!(
+            "starting safekeeper WAL service on {}",
+            listen_pg_socket.display()
+        );
+        let listener = unix_listener::bind(listen_pg_socket).map_err(|e| {
+            error!(
+                "failed to bind to socket {}: {}",
+                listen_pg_socket.display(),
+                e
+            );
+            e
+        })?;
+        Some(listener)
+    } else {
+        None
+    };
+
     info!(
         "starting safekeeper HTTP service on {}",
         conf.listen_http_addr
@@ -287,7 +401,7 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         .unwrap_or_else(|| WAL_SERVICE_RUNTIME.handle())
         .spawn(wal_service::task_main(
             conf_,
-            pg_listener,
+            wal_service::Listener::Tcp(pg_listener),
             Some(Scope::SafekeeperData),
         ))
         // wrap with task name for error reporting
@@ -301,7 +415,7 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
             .unwrap_or_else(|| WAL_SERVICE_RUNTIME.handle())
             .spawn(wal_service::task_main(
                 conf_,
-                pg_listener_tenant_only,
+                wal_service::Listener::Tcp(pg_listener_tenant_only),
                 Some(Scope::Tenant),
             ))
             // wrap with task name for error reporting
@@ -309,6 +423,21 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         tasks_handles.push(Box::pin(wal_service_handle));
     }
 
+    if let Some(pg_listener_unix) = pg_listener_unix {
+        let conf_ = conf.clone();
+        let wal_service_handle = current_thread_rt
+            .as_ref()
+            .unwrap_or_else(|| WAL_SERVICE_RUNTIME.handle())
+            .spawn(wal_service::task_main(
+                conf_,
+                wal_service::Listener::Unix(pg_listener_unix),
+                Some(Scope::SafekeeperData),
+            ))
+            // wrap with task name for error reporting
+            .map(|res| ("WAL service unix socket main".to_owned(), res));
+        tasks_handles.push(Box::pin(wal_service_handle));
+    }
+
     let conf_ = conf.clone();
     let http_handle = current_thread_rt
         .as_ref()
@@ -333,6 +462,34 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         .map(|res| ("WAL remover".to_owned(), res));
     tasks_handles.push(Box::pin(wal_remover_handle));
 
+    let conf_ = conf.clone();
+    let disk_usage_handle = current_thread_rt
+        .as_ref()
+        .unwrap_or_else(|| WAL_REMOVER_RUNTIME.handle())
+        .spawn(disk_usage::task_main(conf_))
+        .map(|res| ("disk usage".to_owned(), res));
+    tasks_handles.push(Box::pin(disk_usage_handle));
+
+    if conf.wal_push {
+        let conf_ = conf.clone();
+        let wal_push_handle = current_thread_rt
+            .as_ref()
+            .unwrap_or_else(|| BROKER_RUNTIME.handle())
+            .spawn(wal_push::task_main(conf_).instrument(info_span!("WAL push")))
+            .map(|res| ("WAL push".to_owned(), res));
+        tasks_handles.push(Box::pin(wal_push_handle));
+    }
+
+    if !conf.wal_forward_to.is_empty() {
+        let conf_ = conf.clone();
+        let wal_forward_handle = current_thread_rt
+            .as_ref()
+            .unwrap_or_else(|| BROKER_RUNTIME.handle())
+            .spawn(wal_forward::task_main(conf_).instrument(info_span!("WAL forward")))
+            .map(|res| ("WAL forward".to_owned(), res));
+        tasks_handles.push(Box::pin(wal_forward_handle));
+    }
+
     let conf_ = conf.clone();
     let wal_backup_handle = current_thread_rt
         .as_ref()
@@ -365,6 +522,13 @@ async fn start_safekeeper(conf: SafeKeeperConf) -> Result<()> {
         _ = sigterm_stream.recv() => info!("received SIGTERM, terminating")
 
     };
+    // Let the timeline tasks, e.g. WAL pushes to pageservers, finish cleanly
+    if tokio::time::timeout(TIMELINE_TASKS_SHUTDOWN_TIMEOUT, TIMELINE_TASKS.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("timeline tasks didn't shut down in {TIMELINE_TASKS_SHUTDOWN_TIMEOUT:?}");
+    }
     std::process::exit(0);
 }
 
@@ -426,6 +590,17 @@ fn parse_remote_storage(storage_conf: &str) -> anyhow::Result<RemoteStorageConfi
     })
 }
 
+// Parse the region registry from TOML array.
+fn parse_regions(regions: &str) -> anyhow::Result<RegionRegistry> {
+    // an array is not a valid document either, so wrap it in a key
+    #[derive(serde::Deserialize)]
+    struct Wrapper {
+        regions: Vec<RegionConfig>,
+    }
+    let wrapper: Wrapper = toml_edit::de::from_str(&format!("regions = {regions}"))?;
+    RegionRegistry::new(wrapper.regions)
+}
+
 #[test]
 fn verify_cli() {
     use clap::CommandFactory;
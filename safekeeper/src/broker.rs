@@ -1,4 +1,8 @@
 //! Communication with the broker, providing safekeeper peers and pageserver coordination.
+//!
+//! Besides exchanging the timeline state with the peers, the safekeeper
+//! subscribes to the pageserver announcements, remembering which pageservers
+//! want the WAL of which timelines, for [`crate::wal_push`].
 
 use anyhow::anyhow;
 use anyhow::bail;
@@ -9,15 +13,22 @@ use anyhow::Result;
 
 use storage_broker::parse_proto_ttid;
 
+use storage_broker::proto::subscribe_pageserver_info_request::SubscriptionKey as ProtoPageserverSubscriptionKey;
 use storage_broker::proto::subscribe_safekeeper_info_request::SubscriptionKey as ProtoSubscriptionKey;
+use storage_broker::proto::SubscribePageserverInfoRequest;
 use storage_broker::proto::SubscribeSafekeeperInfoRequest;
 use storage_broker::Request;
 
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
 use std::time::Duration;
 use std::time::Instant;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tracing::*;
+use utils::id::NodeId;
+use utils::id::TenantTimelineId;
 
 use crate::metrics::BROKER_ITERATION_TIMELINES;
 use crate::metrics::BROKER_PULLED_UPDATES;
@@ -29,6 +40,81 @@ use crate::SafeKeeperConf;
 const RETRY_INTERVAL_MSEC: u64 = 1000;
 const PUSH_INTERVAL_MSEC: u64 = 1000;
 
+/// When we last received a message from the broker, or started trying to.
+static LAST_MESSAGE_AT: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+
+/// Whether the broker hasn't been heard from for longer than the heartbeat
+/// timeout. As we receive our own updates, it means that either the broker is
+/// unreachable or we have no active timelines.
+pub fn is_unreachable(conf: &SafeKeeperConf) -> bool {
+    LAST_MESSAGE_AT.lock().elapsed() > conf.heartbeat_timeout
+}
+
+/// A pageserver wanting the WAL of a timeline.
+struct PageserverEntry {
+    connstr: String,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Pageservers {
+    timelines: HashMap<TenantTimelineId, HashMap<NodeId, PageserverEntry>>,
+    last_pruned: Option<Instant>,
+}
+
+impl Pageservers {
+    /// Returns whether the pageserver is new for the timeline.
+    fn record(
+        &mut self,
+        ttid: TenantTimelineId,
+        id: NodeId,
+        connstr: String,
+        now: Instant,
+    ) -> bool {
+        self.timelines
+            .entry(ttid)
+            .or_default()
+            .insert(
+                id,
+                PageserverEntry {
+                    connstr,
+                    last_seen: now,
+                },
+            )
+            .is_none()
+    }
+
+    /// Forgets the pageservers which haven't announced a timeline for `ttl`.
+    fn prune(&mut self, ttl: Duration, now: Instant) {
+        self.timelines.retain(|_, pageservers| {
+            pageservers.retain(|_, entry| now.duration_since(entry.last_seen) <= ttl);
+            !pageservers.is_empty()
+        });
+        self.last_pruned = Some(now);
+    }
+}
+
+/// Pageservers by the timelines they want WAL of, as last announced through
+/// the broker. Pruned only while the announcements are being received, so
+/// that the pageservers are remembered while the broker is unreachable.
+static PAGESERVERS: Lazy<Mutex<Pageservers>> = Lazy::new(|| Mutex::new(Pageservers::default()));
+
+/// Returns the ids and connection strings of the pageservers wanting the WAL
+/// of the timeline.
+pub fn get_pageservers(ttid: &TenantTimelineId) -> Vec<(NodeId, String)> {
+    PAGESERVERS
+        .lock()
+        .timelines
+        .get(ttid)
+        .map(|pageservers| {
+            pageservers
+                .iter()
+                .map(|(id, entry)| (*id, entry.connstr.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 /// Push once in a while data about all active timelines to the broker.
 async fn push_loop(conf: SafeKeeperConf) -> anyhow::Result<()> {
     let mut client =
@@ -99,6 +185,7 @@ async fn pull_loop(conf: SafeKeeperConf) -> Result<()> {
             .as_ref()
             .ok_or_else(|| anyhow!("missing tenant_timeline_id"))?;
         let ttid = parse_proto_ttid(proto_ttid)?;
+        *LAST_MESSAGE_AT.lock() = Instant::now();
         if let Ok(tli) = GlobalTimelines::get(ttid) {
             // Note that we also receive *our own* info. That's
             // important, as it is used as an indication of live
@@ -119,12 +206,52 @@ async fn pull_loop(conf: SafeKeeperConf) -> Result<()> {
     bail!("end of stream");
 }
 
+/// Subscribe to the pageserver announcements, remembering the pageservers.
+async fn pull_pageservers_loop(conf: SafeKeeperConf) -> Result<()> {
+    let mut client = storage_broker::connect(conf.broker_endpoint, conf.broker_keepalive_interval)?;
+
+    let request = SubscribePageserverInfoRequest {
+        subscription_key: Some(ProtoPageserverSubscriptionKey::All(())),
+    };
+
+    let mut stream = client
+        .subscribe_pageserver_info(request)
+        .await
+        .context("subscribe_pageserver_info request failed")?
+        .into_inner();
+
+    while let Some(msg) = stream.message().await? {
+        let proto_ttid = msg
+            .tenant_timeline_id
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing tenant_timeline_id"))?;
+        let ttid = parse_proto_ttid(proto_ttid)?;
+        let now = Instant::now();
+        let mut pageservers = PAGESERVERS.lock();
+        let pageserver_id = NodeId(msg.pageserver_id);
+        if pageservers.record(ttid, pageserver_id, msg.pageserver_connstr, now) {
+            info!("pageserver {} wants WAL of {}", pageserver_id, ttid);
+        }
+        // Pageservers announce their timelines every second, so missing
+        // a heartbeat timeout worth of announcements means it's gone.
+        let prune_due = pageservers
+            .last_pruned
+            .map_or(true, |at| now.duration_since(at) > conf.heartbeat_timeout);
+        if prune_due {
+            pageservers.prune(conf.heartbeat_timeout, now);
+        }
+    }
+    bail!("end of stream");
+}
+
 pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
     info!("started, broker endpoint {:?}", conf.broker_endpoint);
+    Lazy::force(&LAST_MESSAGE_AT);
 
     let mut ticker = tokio::time::interval(Duration::from_millis(RETRY_INTERVAL_MSEC));
     let mut push_handle: Option<JoinHandle<Result<(), Error>>> = None;
     let mut pull_handle: Option<JoinHandle<Result<(), Error>>> = None;
+    let mut pull_pageservers_handle: Option<JoinHandle<Result<(), Error>>> = None;
 
     // Selecting on JoinHandles requires some squats; is there a better way to
     // reap tasks individually?
@@ -153,6 +280,15 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
                     };
                     pull_handle = None;
                 },
+                res = async { pull_pageservers_handle.as_mut().unwrap().await }, if pull_pageservers_handle.is_some() => {
+                    match res {
+                        Ok(res_internal) => if let Err(err_inner) = res_internal {
+                            warn!("pageservers pull task failed: {:?}", err_inner);
+                        }
+                        Err(err_outer) => { warn!("pageservers pull task panicked: {:?}", err_outer) }
+                    };
+                    pull_pageservers_handle = None;
+                },
                 _ = ticker.tick() => {
                     if push_handle.is_none() {
                         push_handle = Some(tokio::spawn(push_loop(conf.clone())));
@@ -160,7 +296,39 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
                     if pull_handle.is_none() {
                         pull_handle = Some(tokio::spawn(pull_loop(conf.clone())));
                     }
+                    if pull_pageservers_handle.is_none() {
+                        pull_pageservers_handle = Some(tokio::spawn(pull_pageservers_loop(conf.clone())));
+                    }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::id::{TenantId, TimelineId};
+
+    #[test]
+    fn test_pageservers_prune() {
+        let ttid = TenantTimelineId::new(TenantId::generate(), TimelineId::generate());
+        let ttl = Duration::from_secs(5);
+        let start = Instant::now();
+        let mut pageservers = Pageservers::default();
+        assert!(pageservers.record(ttid, NodeId(1), "ps1".to_owned(), start));
+        assert!(pageservers.record(ttid, NodeId(2), "ps2".to_owned(), start));
+        assert!(!pageservers.record(ttid, NodeId(2), "ps2".to_owned(), start + ttl));
+
+        pageservers.prune(ttl, start + ttl);
+        assert_eq!(pageservers.timelines[&ttid].len(), 2);
+
+        // the first one stopped announcing the timeline
+        pageservers.prune(ttl, start + ttl + Duration::from_secs(1));
+        let left = &pageservers.timelines[&ttid];
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[&NodeId(2)].connstr, "ps2");
+
+        pageservers.prune(ttl, start + ttl * 3);
+        assert!(pageservers.timelines.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Fault injection for testing, to exercise the retries of the walproposer and
+//! the pageserver's WAL receiver without external network tooling.
+//!
+//! Enabled with `--chaos`, taking an inline TOML table:
+//!
+//! ```text
+//! --chaos '{connection_delay = "200ms", drop_probability = 0.1, reset_probability = 0.1, reset_after = "10s", fsync_delay = "20ms"}'
+//! ```
+//!
+//! Each accepted connection is first delayed by a random time up to
+//! `connection_delay`. Then it is dropped without being served with
+//! `drop_probability`, or served and reset at a random time up to
+//! `reset_after` with `reset_probability`. Each fsync of the WAL is delayed by a
+//! random time up to `fsync_delay`, even with `--no-sync`.
+
+use std::time::Duration;
+
+use anyhow::ensure;
+use rand::Rng;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    #[serde(with = "humantime_serde")]
+    pub connection_delay: Option<Duration>,
+    pub drop_probability: f64,
+    pub reset_probability: f64,
+    #[serde(with = "humantime_serde")]
+    pub reset_after: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub fsync_delay: Option<Duration>,
+}
+
+/// What happens to an accepted connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionFate {
+    Serve,
+    Drop,
+    ResetAfter(Duration),
+}
+
+/// Reset time used when `reset_after` isn't set.
+const DEFAULT_RESET_AFTER: Duration = Duration::from_secs(10);
+
+fn random_up_to(max: Duration) -> Duration {
+    max.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+impl ChaosConfig {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        // an inline table is not a valid document, so wrap it in a key
+        #[derive(Deserialize)]
+        struct Wrapper {
+            chaos: ChaosConfig,
+        }
+        let wrapper: Wrapper = toml_edit::de::from_str(&format!("chaos = {s}"))?;
+        wrapper.chaos.validate()?;
+        Ok(wrapper.chaos)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, p) in [
+            ("drop_probability", self.drop_probability),
+            ("reset_probability", self.reset_probability),
+        ] {
+            ensure!((0.0..=1.0).contains(&p), "{name} must be within [0, 1]");
+        }
+        ensure!(
+            self.drop_probability + self.reset_probability <= 1.0,
+            "drop_probability and reset_probability must add up to at most 1"
+        );
+        Ok(())
+    }
+
+    /// Sleeps for the random delay of an accepted connection.
+    pub async fn delay_connection(&self) {
+        if let Some(max) = self.connection_delay {
+            tokio::time::sleep(random_up_to(max)).await;
+        }
+    }
+
+    pub fn connection_fate(&self) -> ConnectionFate {
+        let roll = rand::thread_rng().gen::<f64>();
+        if roll < self.drop_probability {
+            ConnectionFate::Drop
+        } else if roll < self.drop_probability + self.reset_probability {
+            ConnectionFate::ResetAfter(random_up_to(
+                self.reset_after.unwrap_or(DEFAULT_RESET_AFTER),
+            ))
+        } else {
+            ConnectionFate::Serve
+        }
+    }
+
+    /// Sleeps for the random delay of an fsync.
+    pub async fn delay_fsync(&self) {
+        if let Some(max) = self.fsync_delay {
+            tokio::time::sleep(random_up_to(max)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = ChaosConfig::parse(
+            r#"{connection_delay = "200ms", drop_probability = 0.1, fsync_delay = "1s"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ChaosConfig {
+                connection_delay: Some(Duration::from_millis(200)),
+                drop_probability: 0.1,
+                fsync_delay: Some(Duration::from_secs(1)),
+                ..Default::default()
+            }
+        );
+
+        assert!(ChaosConfig::parse("{drop_probability = 1.5}").is_err());
+        assert!(ChaosConfig::parse("{drop_probability = 0.6, reset_probability = 0.6}").is_err());
+        assert!(ChaosConfig::parse("{unknown = 1}").is_err());
+    }
+
+    #[test]
+    fn connection_fate() {
+        let drop_all = ChaosConfig {
+            drop_probability: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(drop_all.connection_fate(), ConnectionFate::Drop);
+
+        let reset_all = ChaosConfig {
+            reset_probability: 1.0,
+            reset_after: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        match reset_all.connection_fate() {
+            ConnectionFate::ResetAfter(after) => assert!(after <= Duration::from_secs(1)),
+            fate => panic!("unexpected fate {fate:?}"),
+        }
+
+        assert_eq!(
+            ChaosConfig::default().connection_fate(),
+            ConnectionFate::Serve
+        );
+    }
+}
@@ -4,6 +4,7 @@ use anyhow::{bail, ensure, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
+use tracing::info;
 
 use std::io::Read;
 use std::ops::Deref;
@@ -12,15 +13,15 @@ use std::time::Instant;
 
 use crate::control_file_upgrade::upgrade_control_file;
 use crate::metrics::PERSIST_CONTROL_FILE_SECONDS;
-use crate::safekeeper::{SafeKeeperState, SK_FORMAT_VERSION, SK_MAGIC};
-use utils::{bin_ser::LeSer, id::TenantTimelineId};
+use crate::safekeeper::{SafeKeeperState, SK_FORMAT_VERSION, SK_MAGIC, SK_MIN_READER_VERSION};
+use utils::{bin_ser::LeSer, crashsafe, id::TenantTimelineId};
 
 use crate::SafeKeeperConf;
 
 use std::convert::TryInto;
 
 // contains persistent metadata for safekeeper
-const CONTROL_FILE_NAME: &str = "safekeeper.control";
+pub const CONTROL_FILE_NAME: &str = "safekeeper.control";
 // needed to atomically update the state using `rename`
 const CONTROL_FILE_NAME_PARTIAL: &str = "safekeeper.control.partial";
 pub const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
@@ -49,18 +50,57 @@ pub struct FileStorage {
 }
 
 impl FileStorage {
-    /// Initialize storage by loading state from disk.
+    /// Initialize storage by loading state from disk. A control file in an older
+    /// format is rewritten in the current one.
     pub fn restore_new(ttid: &TenantTimelineId, conf: &SafeKeeperConf) -> Result<FileStorage> {
         let timeline_dir = conf.timeline_dir(ttid);
 
-        let state = Self::load_control_file_conf(conf, ttid)?;
+        let (state, version) = Self::read_control_file(timeline_dir.join(CONTROL_FILE_NAME))?;
 
-        Ok(FileStorage {
+        let storage = FileStorage {
             timeline_dir,
             conf: conf.clone(),
             state,
             last_persist_at: Instant::now(),
-        })
+        };
+        // A newer file is left alone, so that its extra fields survive until the
+        // state changes
+        if version < SK_FORMAT_VERSION {
+            storage.upgrade_in_place(version)?;
+        }
+        Ok(storage)
+    }
+
+    /// Writes the loaded state back in the current format. Synchronous, like the
+    /// loading of the timelines.
+    fn upgrade_in_place(&self, old_version: u32) -> Result<()> {
+        let control_partial_path = self.timeline_dir.join(CONTROL_FILE_NAME_PARTIAL);
+        let control_path = self.timeline_dir.join(CONTROL_FILE_NAME);
+        let buf = Self::serialize(&self.state)?;
+
+        let mut control_partial =
+            std::fs::File::create(&control_partial_path).with_context(|| {
+                format!(
+                    "failed to create partial control file at: {}",
+                    control_partial_path.display()
+                )
+            })?;
+        std::io::Write::write_all(&mut control_partial, &buf)?;
+        if !self.conf.no_sync {
+            control_partial.sync_all()?;
+        }
+        std::fs::rename(&control_partial_path, &control_path)?;
+        if !self.conf.no_sync {
+            crashsafe::fsync_file_and_parent(&control_path)?;
+        }
+
+        info!(
+            "upgraded control file {} from version {} to {}",
+            control_path.display(),
+            old_version,
+            SK_FORMAT_VERSION
+        );
+        Ok(())
     }
 
     /// Create file storage for a new timeline, but don't persist it yet.
@@ -82,7 +122,8 @@ impl FileStorage {
     }
 
     /// Check the magic/version in the on-disk data and deserialize it, if possible.
-    fn deser_sk_state(buf: &mut &[u8]) -> Result<SafeKeeperState> {
+    /// Returns the state and the version it was stored in.
+    fn deser_sk_state(buf: &mut &[u8]) -> Result<(SafeKeeperState, u32)> {
         // Read the version independent part
         let magic = ReadBytesExt::read_u32::<LittleEndian>(buf)?;
         if magic != SK_MAGIC {
@@ -93,12 +134,43 @@ impl FileStorage {
             );
         }
         let version = ReadBytesExt::read_u32::<LittleEndian>(buf)?;
+        if version < 8 {
+            // before the minimal reader version was stored
+            return Ok((upgrade_control_file(buf, version)?, version));
+        }
+
+        let min_reader_version = ReadBytesExt::read_u32::<LittleEndian>(buf)?;
         if version == SK_FORMAT_VERSION {
             let res = SafeKeeperState::des(buf)?;
-            return Ok(res);
+            return Ok((res, version));
+        }
+        if version > SK_FORMAT_VERSION {
+            ensure!(
+                min_reader_version <= SK_FORMAT_VERSION,
+                "control file version {version} can be read by versions {min_reader_version} and newer, this is version {SK_FORMAT_VERSION}"
+            );
+            info!(
+                "reading newer control file version {version}, ignoring the fields added after version {SK_FORMAT_VERSION}"
+            );
+            let res = SafeKeeperState::des_prefix(buf)?;
+            return Ok((res, version));
         }
         // try to upgrade
-        upgrade_control_file(buf, version)
+        Ok((upgrade_control_file(buf, version)?, version))
+    }
+
+    /// Serializes the state in the current format, with the checksum.
+    pub(crate) fn serialize(s: &SafeKeeperState) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::new();
+        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MAGIC)?;
+        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_FORMAT_VERSION)?;
+        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MIN_READER_VERSION)?;
+        s.ser_into(&mut buf)?;
+
+        // calculate checksum before resize
+        let checksum = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        Ok(buf)
     }
 
     /// Load control file for given ttid at path specified by conf.
@@ -112,6 +184,11 @@ impl FileStorage {
 
     /// Read in the control file.
     pub fn load_control_file<P: AsRef<Path>>(control_file_path: P) -> Result<SafeKeeperState> {
+        Ok(Self::read_control_file(control_file_path)?.0)
+    }
+
+    /// Read in the control file, and return the version it was stored in too.
+    fn read_control_file<P: AsRef<Path>>(control_file_path: P) -> Result<(SafeKeeperState, u32)> {
         let mut control_file = std::fs::OpenOptions::new()
             .read(true)
             .write(true)
@@ -142,14 +219,12 @@ impl FileStorage {
             )
         );
 
-        let state = FileStorage::deser_sk_state(&mut &buf[..buf.len() - CHECKSUM_SIZE])
-            .with_context(|| {
-                format!(
-                    "while reading control file {}",
-                    control_file_path.as_ref().display(),
-                )
-            })?;
-        Ok(state)
+        FileStorage::deser_sk_state(&mut &buf[..buf.len() - CHECKSUM_SIZE]).with_context(|| {
+            format!(
+                "while reading control file {}",
+                control_file_path.as_ref().display(),
+            )
+        })
     }
 }
 
@@ -177,14 +252,7 @@ impl Storage for FileStorage {
                 &control_partial_path.display()
             )
         })?;
-        let mut buf: Vec<u8> = Vec::new();
-        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MAGIC)?;
-        WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_FORMAT_VERSION)?;
-        s.ser_into(&mut buf)?;
-
-        // calculate checksum before resize
-        let checksum = crc32c::crc32c(&buf);
-        buf.extend_from_slice(&checksum.to_le_bytes());
+        let buf = Self::serialize(s)?;
 
         control_partial.write_all(&buf).await.with_context(|| {
             format!(
@@ -332,4 +400,95 @@ mod test {
             Ok(_) => panic!("expected error"),
         }
     }
+
+    /// Writes a control file with the given header and body, like an older or
+    /// a newer safekeeper would.
+    async fn write_raw(
+        conf: &SafeKeeperConf,
+        ttid: &TenantTimelineId,
+        version: u32,
+        min_reader_version: Option<u32>,
+        body: &[u8],
+    ) {
+        fs::create_dir_all(conf.timeline_dir(ttid))
+            .await
+            .expect("failed to create timeline dir");
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&SK_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&version.to_le_bytes());
+        if let Some(min_reader_version) = min_reader_version {
+            buf.extend_from_slice(&min_reader_version.to_le_bytes());
+        }
+        buf.extend_from_slice(body);
+        let checksum = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        fs::write(conf.timeline_dir(ttid).join(CONTROL_FILE_NAME), &buf)
+            .await
+            .expect("failed to write control file");
+    }
+
+    async fn stored_version(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> u32 {
+        let data = fs::read(conf.timeline_dir(ttid).join(CONTROL_FILE_NAME))
+            .await
+            .unwrap();
+        u32::from_le_bytes(data[4..8].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_in_place() {
+        let conf = stub_conf();
+        let ttid = TenantTimelineId::generate();
+        let mut state = SafeKeeperState::empty();
+        state.commit_lsn = Lsn(42);
+        write_raw(&conf, &ttid, 7, None, &state.ser().unwrap()).await;
+
+        let storage = FileStorage::restore_new(&ttid, &conf).expect("failed to upgrade state");
+        assert_eq!(storage.commit_lsn, Lsn(42));
+        assert_eq!(stored_version(&conf, &ttid).await, SK_FORMAT_VERSION);
+
+        let (_, state) = load_from_control_file(&conf, &ttid)
+            .await
+            .expect("failed to read upgraded state");
+        assert_eq!(state.commit_lsn, Lsn(42));
+    }
+
+    #[tokio::test]
+    async fn test_read_newer_version() {
+        let conf = stub_conf();
+        let ttid = TenantTimelineId::generate();
+        let mut state = SafeKeeperState::empty();
+        state.commit_lsn = Lsn(42);
+        let mut body = state.ser().unwrap();
+        // a field appended by the newer version
+        body.extend_from_slice(&[1, 2, 3, 4]);
+
+        write_raw(
+            &conf,
+            &ttid,
+            SK_FORMAT_VERSION + 1,
+            Some(SK_FORMAT_VERSION),
+            &body,
+        )
+        .await;
+        let (_, state) = load_from_control_file(&conf, &ttid)
+            .await
+            .expect("failed to read newer state");
+        assert_eq!(state.commit_lsn, Lsn(42));
+        // not downgraded on load
+        assert_eq!(stored_version(&conf, &ttid).await, SK_FORMAT_VERSION + 1);
+
+        // the newer version changed the layout incompatibly
+        write_raw(
+            &conf,
+            &ttid,
+            SK_FORMAT_VERSION + 1,
+            Some(SK_FORMAT_VERSION + 1),
+            &body,
+        )
+        .await;
+        match load_from_control_file(&conf, &ttid).await {
+            Err(err) => assert!(format!("{err:#}").contains("can be read by versions")),
+            Ok(_) => panic!("expected error"),
+        }
+    }
 }
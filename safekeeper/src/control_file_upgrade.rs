@@ -138,6 +138,46 @@ pub struct SafeKeeperStateV4 {
     pub peers: PersistedPeers,
 }
 
+/// Versions 5 to 8, before the elections were appended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafeKeeperStateV8 {
+    #[serde(with = "hex")]
+    pub tenant_id: TenantId,
+    #[serde(with = "hex")]
+    pub timeline_id: TimelineId,
+    pub acceptor_state: AcceptorState,
+    pub server: ServerInfo,
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    pub timeline_start_lsn: Lsn,
+    pub local_start_lsn: Lsn,
+    pub commit_lsn: Lsn,
+    pub backup_lsn: Lsn,
+    pub peer_horizon_lsn: Lsn,
+    pub remote_consistent_lsn: Lsn,
+    pub peers: PersistedPeers,
+}
+
+impl From<SafeKeeperStateV8> for SafeKeeperState {
+    fn from(oldstate: SafeKeeperStateV8) -> Self {
+        SafeKeeperState {
+            tenant_id: oldstate.tenant_id,
+            timeline_id: oldstate.timeline_id,
+            acceptor_state: oldstate.acceptor_state,
+            server: oldstate.server,
+            proposer_uuid: oldstate.proposer_uuid,
+            timeline_start_lsn: oldstate.timeline_start_lsn,
+            local_start_lsn: oldstate.local_start_lsn,
+            commit_lsn: oldstate.commit_lsn,
+            backup_lsn: oldstate.backup_lsn,
+            peer_horizon_lsn: oldstate.peer_horizon_lsn,
+            remote_consistent_lsn: oldstate.remote_consistent_lsn,
+            peers: oldstate.peers,
+            elections: Vec::new(),
+        }
+    }
+}
+
 pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState> {
     // migrate to storing full term history
     if version == 1 {
@@ -167,6 +207,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState>
             peer_horizon_lsn: oldstate.truncate_lsn,
             remote_consistent_lsn: Lsn(0),
             peers: PersistedPeers(vec![]),
+            elections: Vec::new(),
         });
     // migrate to hexing some ids
     } else if version == 2 {
@@ -190,6 +231,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState>
             peer_horizon_lsn: oldstate.truncate_lsn,
             remote_consistent_lsn: Lsn(0),
             peers: PersistedPeers(vec![]),
+            elections: Vec::new(),
         });
     // migrate to moving tenant_id/timeline_id to the top and adding some lsns
     } else if version == 3 {
@@ -213,6 +255,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState>
             peer_horizon_lsn: oldstate.truncate_lsn,
             remote_consistent_lsn: Lsn(0),
             peers: PersistedPeers(vec![]),
+            elections: Vec::new(),
         });
     // migrate to having timeline_start_lsn
     } else if version == 4 {
@@ -236,10 +279,11 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState>
             peer_horizon_lsn: oldstate.peer_horizon_lsn,
             remote_consistent_lsn: Lsn(0),
             peers: PersistedPeers(vec![]),
+            elections: Vec::new(),
         });
     } else if version == 5 {
         info!("reading safekeeper control file version {}", version);
-        let mut oldstate = SafeKeeperState::des(&buf[..buf.len()])?;
+        let mut oldstate = SafeKeeperState::from(SafeKeeperStateV8::des_prefix(buf)?);
         if oldstate.timeline_start_lsn != Lsn(0) {
             return Ok(oldstate);
         }
@@ -250,9 +294,17 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<SafeKeeperState>
         oldstate.local_start_lsn = Lsn(1);
 
         return Ok(oldstate);
+    } else if version == 7 {
+        // Version 8 only added the minimal reader version to the header
+        info!("reading safekeeper control file version {}", version);
+        return Ok(SafeKeeperStateV8::des_prefix(buf)?.into());
+    } else if version == 8 {
+        // Version 9 appended the elections
+        info!("reading safekeeper control file version {}", version);
+        return Ok(SafeKeeperStateV8::des_prefix(buf)?.into());
     } else if version == 6 {
         info!("reading safekeeper control file version {}", version);
-        let mut oldstate = SafeKeeperState::des(&buf[..buf.len()])?;
+        let mut oldstate = SafeKeeperState::from(SafeKeeperStateV8::des_prefix(buf)?);
         if oldstate.server.pg_version != 0 {
             return Ok(oldstate);
         }
@@ -0,0 +1,147 @@
+//! Creating a timeline from a prefix of another timeline of the same tenant,
+//! for branching.
+//!
+//! The new timeline gets the state of the source timeline up to `until_lsn`,
+//! and the WAL of the segment containing `until_lsn`. It starts at
+//! `until_lsn`, so that a compute started on the new branch can write to it
+//! right away, without the safekeepers learning about the branch from the
+//! pageserver first. The operation should be done on every safekeeper of the
+//! timeline's set.
+
+use anyhow::{bail, ensure, Context, Result};
+use postgres_ffi::{XLogFileName, PG_TLI};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+use utils::checksummed_writer::SyncPolicy;
+use utils::id::{TenantTimelineId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::control_file::{self, CONTROL_FILE_NAME};
+use crate::pull_timeline::{create_temp_timeline_dir, load_temp_timeline};
+use crate::safekeeper::{AcceptorState, SafeKeeperState};
+use crate::timeline::TimelineError;
+use crate::wal_storage::{self, Storage, WalReader};
+use crate::GlobalTimelines;
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    #[serde_as(as = "DisplayFromStr")]
+    pub target_timeline_id: TimelineId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub until_lsn: Lsn,
+}
+
+pub async fn handle_request(source_ttid: TenantTimelineId, request: Request) -> Result<()> {
+    let ttid = TenantTimelineId::new(source_ttid.tenant_id, request.target_timeline_id);
+    if GlobalTimelines::get(ttid).is_ok() {
+        bail!("Timeline {} already exists", ttid.timeline_id);
+    }
+    if GlobalTimelines::get_tombstone(&ttid).is_some() {
+        bail!(TimelineError::Deleted(ttid));
+    }
+    let source = GlobalTimelines::get(source_ttid)?;
+    let conf = &GlobalTimelines::get_global_config();
+
+    let (mem_state, state) = source.get_state().await;
+    let until_lsn = request.until_lsn;
+    ensure!(
+        until_lsn <= mem_state.commit_lsn,
+        "until_lsn {} is not committed yet, commit_lsn is {}",
+        until_lsn,
+        mem_state.commit_lsn
+    );
+    ensure!(
+        until_lsn >= state.timeline_start_lsn,
+        "until_lsn {} is before the start of the timeline {}",
+        until_lsn,
+        state.timeline_start_lsn
+    );
+
+    info!(
+        "copying timeline {} to {} until {}",
+        source_ttid, ttid, until_lsn
+    );
+
+    // The WAL of the segment containing until_lsn, up to it. The segment may
+    // be already removed locally, then it's read from the remote storage.
+    let wal_seg_size = state.server.wal_seg_size as usize;
+    let segment_start = until_lsn.segment_lsn(wal_seg_size);
+    let mut wal = vec![0u8; (until_lsn.0 - segment_start.0) as usize];
+    let mut reader = WalReader::new(
+        conf.workdir.clone(),
+        conf.timeline_dir(&source_ttid),
+        &state,
+        segment_start,
+        conf.is_wal_backup_enabled(),
+    )?;
+    let mut read = 0;
+    while read < wal.len() {
+        let n = reader.read(&mut wal[read..]).await?;
+        ensure!(
+            n > 0,
+            "unexpected end of WAL at {}",
+            segment_start + read as u64
+        );
+        read += n;
+    }
+
+    let mut new_state = SafeKeeperState::new(
+        &ttid,
+        state.server.clone(),
+        vec![],
+        until_lsn,
+        segment_start,
+    );
+    new_state.timeline_start_lsn = until_lsn;
+    new_state.peer_horizon_lsn = until_lsn;
+    new_state.acceptor_state = AcceptorState {
+        term: state.acceptor_state.term,
+        term_history: state.acceptor_state.term_history.up_to(until_lsn),
+    };
+
+    let tli_dir = create_temp_timeline_dir(conf, &ttid).await?;
+    let tli_dir_path = tli_dir.path().to_owned();
+
+    let segment_name = XLogFileName(
+        PG_TLI,
+        segment_start.segment_number(wal_seg_size),
+        wal_seg_size,
+    );
+    let segment_path = tli_dir_path.join(segment_name + ".partial");
+    let mut segment = tokio::fs::File::create(&segment_path)
+        .await
+        .with_context(|| format!("failed to create {}", segment_path.display()))?;
+    segment.write_all(&wal).await?;
+    segment.flush().await?;
+    // Like the segments created by the safekeeper, fill it up with zeros
+    segment.set_len(wal_seg_size as u64).await?;
+
+    let control_path = tli_dir_path.join(CONTROL_FILE_NAME);
+    let mut control = tokio::fs::File::create(&control_path)
+        .await
+        .with_context(|| format!("failed to create {}", control_path.display()))?;
+    control
+        .write_all(&control_file::FileStorage::serialize(&new_state)?)
+        .await?;
+    control.flush().await?;
+
+    let policy = SyncPolicy::All.unless(conf.no_sync);
+    policy.sync_async(&segment).await?;
+    policy.sync_async(&control).await?;
+
+    // until_lsn must be the end of a record, for the new timeline to continue from it
+    let wal_store =
+        wal_storage::PhysicalStorage::new(&ttid, tli_dir_path.clone(), conf, &new_state)?;
+    ensure!(
+        wal_store.flush_lsn() == until_lsn,
+        "until_lsn {} is not at a record boundary, the last record before it ends at {}",
+        until_lsn,
+        wal_store.flush_lsn()
+    );
+
+    load_temp_timeline(conf, ttid, &tli_dir_path).await?;
+    Ok(())
+}
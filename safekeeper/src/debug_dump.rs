@@ -20,6 +20,7 @@ use utils::lsn::Lsn;
 
 use crate::safekeeper::SafeKeeperState;
 use crate::safekeeper::SafekeeperMemState;
+use crate::safekeeper::Term;
 use crate::safekeeper::TermHistory;
 use crate::SafeKeeperConf;
 
@@ -70,6 +71,8 @@ pub struct Config {
     pub listen_http_addr: String,
     pub no_sync: bool,
     pub max_offloader_lag_bytes: u64,
+    pub max_disk_usage: Option<u64>,
+    pub verify_wal_on_startup: bool,
     pub wal_backup_enabled: bool,
 }
 
@@ -89,6 +92,7 @@ pub struct Timeline {
 pub struct Memory {
     pub is_cancelled: bool,
     pub peers_info_len: usize,
+    pub peers: Vec<Peer>,
     pub walsenders: Vec<WalSenderState>,
     pub wal_backup_active: bool,
     pub active: bool,
@@ -104,6 +108,17 @@ pub struct Memory {
     pub file_open: bool,
 }
 
+/// What we know about the timeline on other safekeeper, from the broker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Peer {
+    pub sk_id: NodeId,
+    pub last_log_term: Term,
+    pub flush_lsn: Lsn,
+    pub commit_lsn: Lsn,
+    pub local_start_lsn: Lsn,
+    pub last_heard_secs_ago: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DiskContent {
     pub files: Vec<FileInfo>,
@@ -261,6 +276,8 @@ fn build_config(config: SafeKeeperConf) -> Config {
         listen_http_addr: config.listen_http_addr,
         no_sync: config.no_sync,
         max_offloader_lag_bytes: config.max_offloader_lag_bytes,
+        max_disk_usage: config.max_disk_usage,
+        verify_wal_on_startup: config.verify_wal_on_startup,
         wal_backup_enabled: config.wal_backup_enabled,
     }
 }
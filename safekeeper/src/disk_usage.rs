@@ -0,0 +1,95 @@
+//! Keeping the data directory below [`SafeKeeperConf::max_disk_usage`].
+//!
+//! [`task_main`] measures the data directory every [`CHECK_INTERVAL`]. Once it
+//! takes more than [`NEW_TIMELINES_THRESHOLD`] of the limit, new timelines are
+//! refused. Once it reaches the limit, the timelines retaining more than
+//! `max_offloader_lag_bytes` of WAL stop accepting WAL until the usage goes
+//! down. Their WAL isn't being consumed by the pageserver, the backup or the
+//! peers, and it's better to stall their computes than to run out of space for
+//! all the timelines on the safekeeper.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tracing::*;
+
+use crate::metrics::DATA_DIR_SIZE;
+use crate::SafeKeeperConf;
+
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Fraction of the limit after which new timelines are refused.
+pub const NEW_TIMELINES_THRESHOLD: f64 = 0.9;
+
+/// Size of the data directory at the last check.
+static DISK_USAGE: AtomicU64 = AtomicU64::new(0);
+
+pub async fn task_main(conf: SafeKeeperConf) -> Result<()> {
+    loop {
+        let workdir = conf.workdir.clone();
+        match tokio::task::spawn_blocking(move || dir_size(&workdir)).await? {
+            Ok(size) => {
+                DISK_USAGE.store(size, Ordering::Relaxed);
+                DATA_DIR_SIZE.set(size as i64);
+            }
+            Err(e) => warn!(
+                "failed to measure the size of {}: {}",
+                conf.workdir.display(),
+                e
+            ),
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            // Removed since it was listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        size += if metadata.is_dir() {
+            match dir_size(&entry.path()) {
+                Ok(size) => size,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Returns an error if there is no room for a new timeline.
+pub fn check_new_timeline(conf: &SafeKeeperConf) -> Result<()> {
+    if let Some(max_disk_usage) = conf.max_disk_usage {
+        let usage = DISK_USAGE.load(Ordering::Relaxed);
+        if usage as f64 >= max_disk_usage as f64 * NEW_TIMELINES_THRESHOLD {
+            bail!(
+                "refusing to create a timeline, disk usage {} is close to the limit {}",
+                usage,
+                max_disk_usage
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether a timeline retaining this much WAL must stop accepting more.
+pub fn should_throttle(conf: &SafeKeeperConf, retained_wal_bytes: u64) -> bool {
+    match conf.max_disk_usage {
+        Some(max_disk_usage) => {
+            DISK_USAGE.load(Ordering::Relaxed) >= max_disk_usage
+                && retained_wal_bytes > conf.max_offloader_lag_bytes
+        }
+        None => false,
+    }
+}
@@ -20,9 +20,10 @@ use postgres_backend::{self, PostgresBackend};
 use postgres_ffi::PG_TLI;
 use pq_proto::{BeMessage, FeStartupPacket, RowDescriptor, INT4_OID, TEXT_OID};
 use regex::Regex;
+use safekeeper_api::{WalCompression, REPLICATION_PROTOCOL_VERSION};
 use utils::auth::{Claims, Scope};
 use utils::{
-    id::{TenantId, TenantTimelineId, TimelineId},
+    id::{NodeId, TenantId, TenantTimelineId, TimelineId},
     lsn::Lsn,
 };
 
@@ -34,6 +35,8 @@ pub struct SafekeeperPostgresHandler {
     pub tenant_id: Option<TenantId>,
     pub timeline_id: Option<TimelineId>,
     pub ttid: TenantTimelineId,
+    /// Node id of the pageserver on the other side, if it passed one.
+    pub pageserver_id: Option<NodeId>,
     /// Unique connection id is logged in spans for observability.
     pub conn_id: ConnectionId,
     /// Auth scope allowed on the connections. None if auth is not configured.
@@ -45,10 +48,17 @@ pub struct SafekeeperPostgresHandler {
 /// Parsed Postgres command.
 enum SafekeeperPostgresCommand {
     StartWalPush,
-    StartReplication { start_lsn: Lsn, term: Option<Term> },
+    StartReplication {
+        start_lsn: Lsn,
+        term: Option<Term>,
+        proto_version: u32,
+        compression: Option<WalCompression>,
+    },
     IdentifySystem,
     TimelineStatus,
-    JSONCtrl { cmd: AppendLogicalMessage },
+    JSONCtrl {
+        cmd: AppendLogicalMessage,
+    },
 }
 
 fn parse_cmd(cmd: &str) -> anyhow::Result<SafekeeperPostgresCommand> {
@@ -56,8 +66,9 @@ fn parse_cmd(cmd: &str) -> anyhow::Result<SafekeeperPostgresCommand> {
         Ok(SafekeeperPostgresCommand::StartWalPush)
     } else if cmd.starts_with("START_REPLICATION") {
         let re = Regex::new(
-            // We follow postgres START_REPLICATION LOGICAL options to pass term.
-            r"START_REPLICATION(?: SLOT [^ ]+)?(?: PHYSICAL)? ([[:xdigit:]]+/[[:xdigit:]]+)(?: \(term='(\d+)'\))?",
+            // We follow postgres START_REPLICATION LOGICAL options to pass term,
+            // protocol version and compression.
+            r"START_REPLICATION(?: SLOT [^ ]+)?(?: PHYSICAL)? ([[:xdigit:]]+/[[:xdigit:]]+)(?: \((.*)\))?",
         )
         .unwrap();
         let caps = re
@@ -65,12 +76,41 @@ fn parse_cmd(cmd: &str) -> anyhow::Result<SafekeeperPostgresCommand> {
             .context(format!("failed to parse START_REPLICATION command {}", cmd))?;
         let start_lsn =
             Lsn::from_str(&caps[1]).context("parse start LSN from START_REPLICATION command")?;
-        let term = if let Some(m) = caps.get(2) {
-            Some(m.as_str().parse::<u64>().context("invalid term")?)
-        } else {
-            None
-        };
-        Ok(SafekeeperPostgresCommand::StartReplication { start_lsn, term })
+        let mut term = None;
+        let mut proto_version = 1;
+        let mut compression = None;
+        if let Some(options) = caps.get(2) {
+            let option_re = Regex::new(r"^\s*(\w+)\s*=?\s*'([^']*)'\s*$").unwrap();
+            for option in options.as_str().split(',') {
+                let option_caps = option_re
+                    .captures(option)
+                    .with_context(|| format!("invalid START_REPLICATION option {option}"))?;
+                let value = &option_caps[2];
+                match &option_caps[1] {
+                    "term" => term = Some(value.parse::<u64>().context("invalid term")?),
+                    "proto_version" => {
+                        proto_version = value.parse::<u32>().context("invalid proto_version")?
+                    }
+                    "compression" => compression = Some(value.parse::<WalCompression>()?),
+                    name => anyhow::bail!("unknown START_REPLICATION option {name}"),
+                }
+            }
+        }
+        if !(1..=REPLICATION_PROTOCOL_VERSION).contains(&proto_version) {
+            anyhow::bail!(
+                "unsupported replication protocol version {proto_version}, \
+                 the highest supported is {REPLICATION_PROTOCOL_VERSION}"
+            );
+        }
+        if compression.is_some() && proto_version < 2 {
+            anyhow::bail!("compression requires replication protocol version 2");
+        }
+        Ok(SafekeeperPostgresCommand::StartReplication {
+            start_lsn,
+            term,
+            proto_version,
+            compression,
+        })
     } else if cmd.starts_with("IDENTIFY_SYSTEM") {
         Ok(SafekeeperPostgresCommand::IdentifySystem)
     } else if cmd.starts_with("TIMELINE_STATUS") {
@@ -100,7 +140,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
     for SafekeeperPostgresHandler
 {
     // tenant_id and timeline_id are passed in connection string params
-    fn startup(
+    async fn startup(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         sm: &FeStartupPacket,
@@ -122,6 +162,12 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
                                 format!("Failed to parse {value} as timeline id")
                             })?);
                         }
+                        Some(("pageserver_id", value)) => {
+                            self.pageserver_id =
+                                Some(NodeId(value.parse().with_context(|| {
+                                    format!("Failed to parse {value} as pageserver id")
+                                })?));
+                        }
                         Some(("availability_zone", client_az)) => {
                             if let Some(metrics) = self.io_metrics.as_ref() {
                                 metrics.set_client_az(client_az)
@@ -147,7 +193,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
         }
     }
 
-    fn check_auth_jwt(
+    async fn check_auth_jwt(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
         jwt_response: &[u8],
@@ -225,8 +271,13 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
                     .instrument(info_span!("WAL receiver", ttid = %span_ttid))
                     .await
             }
-            SafekeeperPostgresCommand::StartReplication { start_lsn, term } => {
-                self.handle_start_replication(pgb, start_lsn, term)
+            SafekeeperPostgresCommand::StartReplication {
+                start_lsn,
+                term,
+                proto_version,
+                compression,
+            } => {
+                self.handle_start_replication(pgb, start_lsn, term, proto_version, compression)
                     .instrument(info_span!("WAL sender", ttid = %span_ttid))
                     .await
             }
@@ -252,6 +303,7 @@ impl SafekeeperPostgresHandler {
             tenant_id: None,
             timeline_id: None,
             ttid: TenantTimelineId::empty(),
+            pageserver_id: None,
             conn_id,
             claims: None,
             allowed_auth_scope,
@@ -331,6 +383,8 @@ impl SafekeeperPostgresHandler {
         let tli = PG_TLI.to_string();
         let tli_bytes = tli.as_bytes();
         let sysid_bytes = sysid.as_bytes();
+        let proto_version = REPLICATION_PROTOCOL_VERSION.to_string();
+        let proto_version_bytes = proto_version.as_bytes();
 
         pgb.write_message_noflush(&BeMessage::RowDescription(&[
             RowDescriptor {
@@ -357,12 +411,20 @@ impl SafekeeperPostgresHandler {
                 typlen: -1,
                 ..Default::default()
             },
+            // Not in Postgres: the highest replication protocol version we speak.
+            RowDescriptor {
+                name: b"proto_version",
+                typoid: INT4_OID,
+                typlen: 4,
+                ..Default::default()
+            },
         ]))?
         .write_message_noflush(&BeMessage::DataRow(&[
             Some(sysid_bytes),
             Some(tli_bytes),
             Some(lsn_bytes),
             None,
+            Some(proto_version_bytes),
         ]))?
         .write_message_noflush(&BeMessage::CommandComplete(b"IDENTIFY_SYSTEM"))?;
         Ok(())
@@ -375,3 +437,73 @@ impl SafekeeperPostgresHandler {
         self.appname == Some("wal_proposer_recovery".to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type StartReplication = (Lsn, Option<Term>, u32, Option<WalCompression>);
+
+    fn parse_start_replication(cmd: &str) -> anyhow::Result<StartReplication> {
+        match parse_cmd(cmd)? {
+            SafekeeperPostgresCommand::StartReplication {
+                start_lsn,
+                term,
+                proto_version,
+                compression,
+            } => Ok((start_lsn, term, proto_version, compression)),
+            _ => anyhow::bail!("not START_REPLICATION"),
+        }
+    }
+
+    #[test]
+    fn start_replication_options() {
+        let lsn = Lsn(0x16B9188);
+        assert_eq!(
+            parse_start_replication("START_REPLICATION PHYSICAL 0/16B9188").unwrap(),
+            (lsn, None, 1, None)
+        );
+        // What Postgres walreceiver sends.
+        assert_eq!(
+            parse_start_replication("START_REPLICATION 0/16B9188 TIMELINE 1").unwrap(),
+            (lsn, None, 1, None)
+        );
+        assert_eq!(
+            parse_start_replication("START_REPLICATION 0/16B9188 (term='3')").unwrap(),
+            (lsn, Some(3), 1, None)
+        );
+        assert_eq!(
+            parse_start_replication(&format!(
+                "START_REPLICATION PHYSICAL 0/16B9188 (term '3', proto_version '{}')",
+                REPLICATION_PROTOCOL_VERSION
+            ))
+            .unwrap(),
+            (lsn, Some(3), REPLICATION_PROTOCOL_VERSION, None)
+        );
+
+        assert!(parse_start_replication(&format!(
+            "START_REPLICATION PHYSICAL 0/16B9188 (proto_version='{}')",
+            REPLICATION_PROTOCOL_VERSION + 1
+        ))
+        .is_err());
+        assert!(
+            parse_start_replication("START_REPLICATION 0/16B9188 (proto_version='0')").is_err()
+        );
+        assert_eq!(
+            parse_start_replication(
+                "START_REPLICATION PHYSICAL 0/16B9188 (proto_version '2', compression 'zstd')"
+            )
+            .unwrap(),
+            (lsn, None, 2, Some(WalCompression::Zstd))
+        );
+        // Compression needs version 2
+        assert!(
+            parse_start_replication("START_REPLICATION 0/16B9188 (compression 'zstd')").is_err()
+        );
+        assert!(parse_start_replication(
+            "START_REPLICATION 0/16B9188 (proto_version '2', compression 'lz4')"
+        )
+        .is_err());
+        assert!(parse_start_replication("START_REPLICATION 0/16B9188 (slot_name '1')").is_err());
+    }
+}
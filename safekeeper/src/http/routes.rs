@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use hyper::{Body, Request, Response, StatusCode, Uri};
 
 use once_cell::sync::Lazy;
@@ -17,7 +18,8 @@ use utils::http::endpoint::request_span;
 
 use crate::safekeeper::ServerInfo;
 use crate::safekeeper::Term;
-use crate::{debug_dump, pull_timeline};
+use crate::wal_forward::WalForwardStatus;
+use crate::{copy_timeline, debug_dump, pull_timeline};
 
 use crate::timelines_global_map::TimelineDeleteForceResult;
 use crate::GlobalTimelines;
@@ -28,7 +30,7 @@ use utils::{
         endpoint::{self, auth_middleware, check_permission_with},
         error::ApiError,
         json::{json_request, json_response},
-        request::{ensure_no_body, parse_request_param},
+        request::{ensure_no_body, parse_query_param, parse_request_param},
         RequestExt, RouterBuilder,
     },
     id::{NodeId, TenantId, TenantTimelineId, TimelineId},
@@ -75,6 +77,27 @@ pub struct AcceptorStateStatus {
     pub term_history: Vec<TermSwitchApiEntry>,
 }
 
+/// Term switch together with the election of the walproposer in the term, if
+/// this safekeeper took part in it. Used only for the API response.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TermHistoryApiEntry {
+    pub term: Term,
+    #[serde_as(as = "DisplayFromStr")]
+    pub start_lsn: Lsn,
+    pub proposer_uuid: Option<String>,
+    pub proposer_addr: Option<String>,
+    pub voted_at: Option<DateTime<Utc>>,
+    pub elected_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TermHistoryStatus {
+    pub term: Term,
+    pub epoch: Term,
+    pub history: Vec<TermHistoryApiEntry>,
+}
+
 /// Info about timeline on safekeeper ready for reporting.
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
@@ -99,6 +122,10 @@ pub struct TimelineStatus {
     pub peer_horizon_lsn: Lsn,
     #[serde_as(as = "DisplayFromStr")]
     pub remote_consistent_lsn: Lsn,
+    /// Forwarding of the WAL to the safekeepers of another region, if this
+    /// safekeeper forwards the timeline.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wal_forwards: Vec<WalForwardStatus>,
 }
 
 fn check_permission(request: &Request<Body>, tenant_id: Option<TenantId>) -> Result<(), ApiError> {
@@ -149,6 +176,44 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
         backup_lsn: inmem.backup_lsn,
         peer_horizon_lsn: inmem.peer_horizon_lsn,
         remote_consistent_lsn: tli.get_walsenders().get_remote_consistent_lsn(),
+        wal_forwards: tli.wal_forward.lock().unwrap().get_status(inmem.commit_lsn),
+    };
+    json_response(StatusCode::OK, status)
+}
+
+/// Term history of the timeline, with the walproposers elected in the terms.
+async fn timeline_term_history_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
+    let (_, state) = tli.get_state().await;
+    let flush_lsn = tli.get_flush_lsn().await;
+
+    let history = state
+        .acceptor_state
+        .term_history
+        .0
+        .iter()
+        .map(|ts| {
+            let election = state.elections.iter().find(|e| e.term == ts.term);
+            TermHistoryApiEntry {
+                term: ts.term,
+                start_lsn: ts.lsn,
+                proposer_uuid: election.map(|e| hex::encode(e.proposer_uuid)),
+                proposer_addr: election.map(|e| e.proposer_addr.clone()),
+                voted_at: election.and_then(|e| e.voted_at).map(DateTime::from),
+                elected_at: election.map(|e| DateTime::from(e.elected_at)),
+            }
+        })
+        .collect();
+    let status = TermHistoryStatus {
+        term: state.acceptor_state.term,
+        epoch: state.acceptor_state.get_epoch(flush_lsn),
+        history,
     };
     json_response(StatusCode::OK, status)
 }
@@ -191,6 +256,22 @@ async fn timeline_pull_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, resp)
 }
 
+/// Create a new timeline from a prefix of the given one, for branching.
+async fn timeline_copy_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let source_ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "source_timeline_id")?,
+    );
+    check_permission(&request, Some(source_ttid.tenant_id))?;
+
+    let data: copy_timeline::Request = json_request(&mut request).await?;
+
+    copy_timeline::handle_request(source_ttid, data)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    json_response(StatusCode::CREATED, ())
+}
+
 /// Download a file from the timeline directory.
 // TODO: figure out a better way to copy files between safekeepers
 async fn timeline_files_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
@@ -231,10 +312,12 @@ async fn timeline_delete_force_handler(
         parse_request_param(&request, "timeline_id")?,
     );
     check_permission(&request, Some(ttid.tenant_id))?;
+    // With a tombstone, the timeline can't be created again
+    let tombstone = parse_query_param(&request, "tombstone")?.unwrap_or(false);
     ensure_no_body(&mut request).await?;
     // FIXME: `delete_force` can fail from both internal errors and bad requests. Add better
     // error handling here when we're able to.
-    let resp = GlobalTimelines::delete_force(&ttid)
+    let resp = GlobalTimelines::delete_force(&ttid, tombstone)
         .await
         .map_err(ApiError::InternalServerError)?;
     json_response(StatusCode::OK, resp)
@@ -285,6 +368,7 @@ async fn record_safekeeper_info(mut request: Request<Body>) -> Result<Response<B
         backup_lsn: sk_info.backup_lsn.0,
         local_start_lsn: sk_info.local_start_lsn.0,
         availability_zone: None,
+        standby_horizon: 0,
     };
 
     let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
@@ -387,6 +471,10 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
         .get("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             request_span(r, timeline_status_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/term_history",
+            |r| request_span(r, timeline_term_history_handler),
+        )
         .delete("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             request_span(r, timeline_delete_force_handler)
         })
@@ -396,6 +484,10 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
         .post("/v1/pull_timeline", |r| {
             request_span(r, timeline_pull_handler)
         })
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:source_timeline_id/copy",
+            |r| request_span(r, timeline_copy_handler),
+        )
         .get(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/file/:filename",
             |r| request_span(r, timeline_files_handler),
@@ -404,6 +496,8 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
         .post("/v1/record_safekeeper_info/:tenant_id/:timeline_id", |r| {
             request_span(r, record_safekeeper_info)
         })
+        .get("/v1/debug/dump", |r| request_span(r, dump_debug_handler))
+        // The old path, pull_timeline fetches the files list from it
         .get("/v1/debug_dump", |r| request_span(r, dump_debug_handler))
 }
 
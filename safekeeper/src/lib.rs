@@ -7,12 +7,18 @@ use std::time::Duration;
 use storage_broker::Uri;
 
 use utils::id::{NodeId, TenantId, TenantTimelineId};
+use utils::task_scope::TaskScope;
+
+use crate::chaos::ChaosConfig;
 
 mod auth;
 pub mod broker;
+pub mod chaos;
 pub mod control_file;
 pub mod control_file_upgrade;
+pub mod copy_timeline;
 pub mod debug_dump;
+pub mod disk_usage;
 pub mod handler;
 pub mod http;
 pub mod json_ctrl;
@@ -24,8 +30,11 @@ pub mod safekeeper;
 pub mod send_wal;
 pub mod timeline;
 pub mod wal_backup;
+pub mod wal_forward;
+pub mod wal_push;
 pub mod wal_service;
 pub mod wal_storage;
+pub mod wal_verify;
 
 mod timelines_global_map;
 use std::sync::Arc;
@@ -40,6 +49,7 @@ pub mod defaults {
 
     pub const DEFAULT_HEARTBEAT_TIMEOUT: &str = "5000ms";
     pub const DEFAULT_MAX_OFFLOADER_LAG_BYTES: u64 = 128 * (1 << 20);
+    pub const DEFAULT_TIMELINE_TOMBSTONE_TTL: &str = "7days";
 }
 
 #[derive(Debug, Clone)]
@@ -54,6 +64,7 @@ pub struct SafeKeeperConf {
     pub my_id: NodeId,
     pub listen_pg_addr: String,
     pub listen_pg_addr_tenant_only: Option<String>,
+    pub listen_pg_socket: Option<PathBuf>,
     pub listen_http_addr: String,
     pub advertise_pg_addr: Option<String>,
     pub availability_zone: Option<String>,
@@ -63,10 +74,33 @@ pub struct SafeKeeperConf {
     pub heartbeat_timeout: Duration,
     pub remote_storage: Option<RemoteStorageConfig>,
     pub max_offloader_lag_bytes: u64,
+    /// See [`disk_usage`].
+    pub max_disk_usage: Option<u64>,
+    /// How long the tombstones of the deleted timelines are kept.
+    pub timeline_tombstone_ttl: Duration,
+    /// Push WAL to the pageservers while the broker is unreachable, see [`wal_push`].
+    pub wal_push: bool,
+    /// JWT token to connect to the pageservers with, if they require auth.
+    pub pageserver_auth_token: Option<String>,
+    /// The safekeepers of another region to forward the committed WAL to, see
+    /// [`wal_forward`].
+    pub wal_forward_to: Vec<wal_forward::WalForwardTarget>,
+    /// JWT token to connect to the safekeepers of `wal_forward_to` with, if
+    /// they require auth.
+    pub safekeeper_auth_token: Option<String>,
+    /// Verify the WAL of the timelines on startup, see [`wal_verify`].
+    pub verify_wal_on_startup: bool,
+    /// Zero out the torn tails found by the verification.
+    pub truncate_torn_wal: bool,
     pub backup_parallel_jobs: usize,
     pub wal_backup_enabled: bool,
+    /// How often to offload the in-progress segment, see [`wal_backup`].
+    /// Only full segments are offloaded if not set.
+    pub partial_backup_interval: Option<Duration>,
     pub auth: Option<Arc<JwtAuth>>,
     pub current_thread_runtime: bool,
+    /// Fault injection for testing, see [`chaos`].
+    pub chaos: Option<ChaosConfig>,
 }
 
 impl SafeKeeperConf {
@@ -78,6 +112,11 @@ impl SafeKeeperConf {
         self.tenant_dir(&ttid.tenant_id)
             .join(ttid.timeline_id.to_string())
     }
+
+    /// WAL is offloaded to the remote storage, and kept on disk until it is.
+    pub fn is_wal_backup_enabled(&self) -> bool {
+        self.remote_storage.is_some() && self.wal_backup_enabled
+    }
 }
 
 impl SafeKeeperConf {
@@ -88,6 +127,7 @@ impl SafeKeeperConf {
             no_sync: false,
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_pg_addr_tenant_only: None,
+            listen_pg_socket: None,
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
             advertise_pg_addr: None,
             availability_zone: None,
@@ -99,14 +139,28 @@ impl SafeKeeperConf {
             broker_keepalive_interval: Duration::from_secs(5),
             wal_backup_enabled: true,
             backup_parallel_jobs: 1,
+            partial_backup_interval: None,
             auth: None,
             heartbeat_timeout: Duration::new(5, 0),
             max_offloader_lag_bytes: defaults::DEFAULT_MAX_OFFLOADER_LAG_BYTES,
+            max_disk_usage: None,
+            timeline_tombstone_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            wal_push: false,
+            pageserver_auth_token: None,
+            wal_forward_to: vec![],
+            safekeeper_auth_token: None,
+            verify_wal_on_startup: false,
+            truncate_torn_wal: false,
             current_thread_runtime: false,
+            chaos: None,
         }
     }
 }
 
+/// The tasks of all the timelines, each timeline has a child scope of it, see
+/// [`timeline::Timeline::tasks`].
+pub static TIMELINE_TASKS: Lazy<TaskScope> = Lazy::new(|| TaskScope::new("timelines"));
+
 // Tokio runtimes.
 pub static WAL_SERVICE_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
     tokio::runtime::Builder::new_multi_thread()
@@ -11,7 +11,8 @@ use futures::Future;
 use metrics::{
     core::{AtomicU64, Collector, Desc, GenericCounter, GenericGaugeVec, Opts},
     proto::MetricFamily,
-    register_int_counter, register_int_counter_vec, Gauge, IntCounter, IntCounterVec, IntGaugeVec,
+    register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Gauge, IntCounter, IntCounterVec, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
@@ -20,11 +21,20 @@ use utils::pageserver_feedback::PageserverFeedback;
 use utils::{id::TenantTimelineId, lsn::Lsn};
 
 use crate::{
+    remove_wal::WalRetention,
     safekeeper::{SafeKeeperState, SafekeeperMemState},
+    timeline::PeerInfo,
     GlobalTimelines,
 };
 
 // Global metrics across all timelines.
+pub static DATA_DIR_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "safekeeper_data_dir_size_bytes",
+        "Size of the data directory, measured periodically"
+    )
+    .expect("Failed to register safekeeper_data_dir_size_bytes gauge")
+});
 pub static WRITE_WAL_BYTES: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "safekeeper_write_wal_bytes",
@@ -81,6 +91,14 @@ pub static BROKER_PUSHED_UPDATES: Lazy<IntCounter> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_broker_pushed_updates_total counter")
 });
+pub static WAL_BACKUP_LAUNCHER_WAKEUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_wal_backup_launcher_wakeups_total",
+        "Number of wake-ups of the WAL backup launcher, sent, deduplicated, rate limited or dropped",
+        &["result"]
+    )
+    .expect("Failed to register safekeeper_wal_backup_launcher_wakeups_total counter")
+});
 pub static BROKER_PULLED_UPDATES: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "safekeeper_broker_pulled_updates_total",
@@ -119,6 +137,13 @@ pub static BACKED_UP_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_backed_up_segments_total counter")
 });
+pub static BACKED_UP_PARTIAL_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_backed_up_partial_segments_total",
+        "Number of uploads of in-progress WAL segments"
+    )
+    .expect("Failed to register safekeeper_backed_up_partial_segments_total counter")
+});
 pub static BACKUP_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_backup_errors_total",
@@ -146,6 +171,40 @@ pub static BROKER_ITERATION_TIMELINES: Lazy<Histogram> = Lazy::new(|| {
     .expect("Failed to register safekeeper_broker_iteration_timelines histogram vec")
 });
 
+// Results of the WAL verification on startup, per timeline.
+pub static WAL_VERIFIED_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_wal_verified_bytes",
+        "WAL verified on startup, up to the end of the last valid record",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("Failed to register safekeeper_wal_verified_bytes gauge vec")
+});
+pub static WAL_TORN_TAIL_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_wal_torn_tail_bytes",
+        "Non-zero bytes found after the last valid WAL record on startup",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("Failed to register safekeeper_wal_torn_tail_bytes gauge vec")
+});
+pub static WAL_VERIFICATION_FAILED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_wal_verification_failed",
+        "Reports 1 if committed WAL failed the verification on startup, 0 otherwise",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("Failed to register safekeeper_wal_verification_failed gauge vec")
+});
+pub static WAL_FORWARD_LAG_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_wal_forward_lag_bytes",
+        "Committed WAL not yet acknowledged by the target safekeeper of the WAL forwarding",
+        &["tenant_id", "timeline_id", "target"]
+    )
+    .expect("Failed to register safekeeper_wal_forward_lag_bytes gauge vec")
+});
+
 pub const LABEL_UNKNOWN: &str = "unknown";
 
 /// Labels for traffic metrics.
@@ -318,6 +377,9 @@ pub struct FullTimelineInfo {
 
     pub flush_lsn: Lsn,
     pub remote_consistent_lsn: Lsn,
+    pub wal_retention: WalRetention,
+    /// Other safekeepers of the timeline we heard from recently.
+    pub peers: Vec<PeerInfo>,
 
     pub wal_storage: WalStorageMetrics,
 }
@@ -337,6 +399,9 @@ pub struct TimelineCollector {
     wal_backup_active: GenericGaugeVec<AtomicU64>,
     connected_computes: IntGaugeVec,
     disk_usage: GenericGaugeVec<AtomicU64>,
+    retained_wal_bytes: GenericGaugeVec<AtomicU64>,
+    peer_flush_lag_bytes: IntGaugeVec,
+    peer_last_heard_seconds: GaugeVec,
     acceptor_term: GenericGaugeVec<AtomicU64>,
     written_wal_bytes: GenericGaugeVec<AtomicU64>,
     written_wal_seconds: GaugeVec,
@@ -475,6 +540,36 @@ impl TimelineCollector {
         .unwrap();
         descs.extend(disk_usage.desc().into_iter().cloned());
 
+        let retained_wal_bytes = GenericGaugeVec::new(
+            Opts::new(
+                "safekeeper_retained_wal_bytes",
+                "WAL that can't be removed yet, by who holds it back: pageserver, backup or peers",
+            ),
+            &["tenant_id", "timeline_id", "held_by"],
+        )
+        .unwrap();
+        descs.extend(retained_wal_bytes.desc().into_iter().cloned());
+
+        let peer_flush_lag_bytes = IntGaugeVec::new(
+            Opts::new(
+                "safekeeper_peer_flush_lag_bytes",
+                "How far flush_lsn of the peer safekeeper is behind ours, negative if it is ahead",
+            ),
+            &["tenant_id", "timeline_id", "peer_id"],
+        )
+        .unwrap();
+        descs.extend(peer_flush_lag_bytes.desc().into_iter().cloned());
+
+        let peer_last_heard_seconds = GaugeVec::new(
+            Opts::new(
+                "safekeeper_peer_last_heard_seconds",
+                "Time since the last update from the peer safekeeper through the broker",
+            ),
+            &["tenant_id", "timeline_id", "peer_id"],
+        )
+        .unwrap();
+        descs.extend(peer_last_heard_seconds.desc().into_iter().cloned());
+
         let acceptor_term = GenericGaugeVec::new(
             Opts::new("safekeeper_acceptor_term", "Current consensus term"),
             &["tenant_id", "timeline_id"],
@@ -540,6 +635,9 @@ impl TimelineCollector {
             wal_backup_active,
             connected_computes,
             disk_usage,
+            retained_wal_bytes,
+            peer_flush_lag_bytes,
+            peer_last_heard_seconds,
             acceptor_term,
             written_wal_bytes,
             written_wal_seconds,
@@ -571,6 +669,9 @@ impl Collector for TimelineCollector {
         self.wal_backup_active.reset();
         self.connected_computes.reset();
         self.disk_usage.reset();
+        self.retained_wal_bytes.reset();
+        self.peer_flush_lag_bytes.reset();
+        self.peer_last_heard_seconds.reset();
         self.acceptor_term.reset();
         self.written_wal_bytes.reset();
         self.written_wal_seconds.reset();
@@ -659,6 +760,22 @@ impl Collector for TimelineCollector {
                     .with_label_values(labels)
                     .set(disk_usage_bytes);
             }
+
+            let (held_by, horizon_lsn) = tli.wal_retention.held_by();
+            self.retained_wal_bytes
+                .with_label_values(&[tenant_id.as_str(), timeline_id.as_str(), held_by])
+                .set(tli.flush_lsn.0.saturating_sub(horizon_lsn.0));
+
+            for peer in &tli.peers {
+                let peer_id = peer.sk_id.to_string();
+                let labels = &[tenant_id.as_str(), timeline_id.as_str(), peer_id.as_str()];
+                self.peer_flush_lag_bytes
+                    .with_label_values(labels)
+                    .set(tli.flush_lsn.0 as i64 - peer.flush_lsn.0 as i64);
+                self.peer_last_heard_seconds
+                    .with_label_values(labels)
+                    .set(peer.ts.elapsed().as_secs_f64());
+            }
         }
 
         // collect MetricFamilys.
@@ -675,6 +792,9 @@ impl Collector for TimelineCollector {
         mfs.extend(self.wal_backup_active.collect());
         mfs.extend(self.connected_computes.collect());
         mfs.extend(self.disk_usage.collect());
+        mfs.extend(self.retained_wal_bytes.collect());
+        mfs.extend(self.peer_flush_lag_bytes.collect());
+        mfs.extend(self.peer_last_heard_seconds.collect());
         mfs.extend(self.acceptor_term.collect());
         mfs.extend(self.written_wal_bytes.collect());
         mfs.extend(self.written_wal_seconds.collect());
@@ -697,8 +817,10 @@ async fn collect_timeline_metrics() -> Vec<FullTimelineInfo> {
     let mut res = vec![];
     let timelines = GlobalTimelines::get_all();
 
+    let conf = GlobalTimelines::get_global_config();
+
     for tli in timelines {
-        if let Some(info) = tli.info_for_metrics().await {
+        if let Some(info) = tli.info_for_metrics(&conf).await {
             res.push(info);
         }
     }
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tracing::info;
 use utils::id::{TenantId, TenantTimelineId, TimelineId};
@@ -8,10 +10,11 @@ use utils::id::{TenantId, TenantTimelineId, TimelineId};
 use serde_with::{serde_as, DisplayFromStr};
 
 use crate::{
-    control_file, debug_dump,
+    control_file, debug_dump, disk_usage,
     http::routes::TimelineStatus,
+    timeline::{Timeline, TimelineError},
     wal_storage::{self, Storage},
-    GlobalTimelines,
+    GlobalTimelines, SafeKeeperConf,
 };
 
 /// Info about timeline on safekeeper ready for reporting.
@@ -34,13 +37,15 @@ pub struct Response {
 
 /// Find the most advanced safekeeper and pull timeline from it.
 pub async fn handle_request(request: Request) -> Result<Response> {
-    let existing_tli = GlobalTimelines::get(TenantTimelineId::new(
-        request.tenant_id,
-        request.timeline_id,
-    ));
+    let ttid = TenantTimelineId::new(request.tenant_id, request.timeline_id);
+    let existing_tli = GlobalTimelines::get(ttid);
     if existing_tli.is_ok() {
         bail!("Timeline {} already exists", request.timeline_id);
     }
+    // Don't download the whole timeline just to fail on loading it
+    if GlobalTimelines::get_tombstone(&ttid).is_some() {
+        bail!(TimelineError::Deleted(ttid));
+    }
 
     let client = reqwest::Client::new();
     let http_hosts = request.http_hosts.clone();
@@ -154,23 +159,7 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
         host
     );
 
-    // Creating temp directory for a new timeline. It needs to be
-    // located on the same filesystem as the rest of the timelines.
-
-    // conf.workdir is usually /storage/safekeeper/data
-    // will try to transform it into /storage/safekeeper/tmp
-    let temp_base = conf
-        .workdir
-        .parent()
-        .ok_or(anyhow::anyhow!("workdir has no parent"))?
-        .join("tmp");
-
-    tokio::fs::create_dir_all(&temp_base).await?;
-
-    let tli_dir = tempfile::Builder::new()
-        .suffix("_temptli")
-        .prefix(&format!("{}_{}_", ttid.tenant_id, ttid.timeline_id))
-        .tempdir_in(temp_base)?;
+    let tli_dir = create_temp_timeline_dir(conf, &ttid).await?;
     let tli_dir_path = tli_dir.path().to_owned();
 
     // Note: some time happens between fetching list of files and fetching files themselves.
@@ -215,17 +204,54 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
     );
     assert!(status.commit_lsn <= status.flush_lsn);
 
-    // Move timeline dir to the correct location
+    load_temp_timeline(conf, ttid, &tli_dir_path).await?;
+
+    Ok(Response {
+        safekeeper_host: host,
+    })
+}
+
+/// Creates temp directory for a new timeline. It needs to be located on the
+/// same filesystem as the rest of the timelines, to move it into place with a rename.
+pub(crate) async fn create_temp_timeline_dir(
+    conf: &SafeKeeperConf,
+    ttid: &TenantTimelineId,
+) -> Result<tempfile::TempDir> {
+    disk_usage::check_new_timeline(conf)?;
+
+    // conf.workdir is usually /storage/safekeeper/data
+    // will try to transform it into /storage/safekeeper/tmp
+    let temp_base = conf
+        .workdir
+        .parent()
+        .ok_or(anyhow::anyhow!("workdir has no parent"))?
+        .join("tmp");
+
+    tokio::fs::create_dir_all(&temp_base).await?;
+
+    Ok(tempfile::Builder::new()
+        .suffix("_temptli")
+        .prefix(&format!("{}_{}_", ttid.tenant_id, ttid.timeline_id))
+        .tempdir_in(temp_base)?)
+}
+
+/// Moves the timeline prepared in the temp directory to the correct location
+/// and loads it.
+pub(crate) async fn load_temp_timeline(
+    conf: &SafeKeeperConf,
+    ttid: TenantTimelineId,
+    tmp_path: &Path,
+) -> Result<Arc<Timeline>> {
     let timeline_path = conf.timeline_dir(&ttid);
 
     info!(
         "Moving timeline {} from {} to {}",
         ttid,
-        tli_dir_path.display(),
+        tmp_path.display(),
         timeline_path.display()
     );
     tokio::fs::create_dir_all(conf.tenant_dir(&ttid.tenant_id)).await?;
-    tokio::fs::rename(tli_dir_path, &timeline_path).await?;
+    tokio::fs::rename(tmp_path, &timeline_path).await?;
 
     let tli = GlobalTimelines::load_timeline(ttid).context("Failed to load timeline after copy")?;
 
@@ -235,7 +261,5 @@ async fn pull_timeline(status: TimelineStatus, host: String) -> Result<Response>
         tli.get_flush_lsn().await
     );
 
-    Ok(Response {
-        safekeeper_host: host,
-    })
+    Ok(tli)
 }
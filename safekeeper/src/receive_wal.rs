@@ -2,13 +2,16 @@
 //! Gets messages from the network, passes them down to consensus module and
 //! sends replies back.
 
+use crate::disk_usage;
 use crate::handler::SafekeeperPostgresHandler;
 use crate::safekeeper::AcceptorProposerMessage;
+use crate::safekeeper::PgUuid;
 use crate::safekeeper::ProposerAcceptorMessage;
 use crate::safekeeper::ServerInfo;
 use crate::timeline::Timeline;
 use crate::wal_service::ConnectionId;
 use crate::GlobalTimelines;
+use crate::SafeKeeperConf;
 use anyhow::{anyhow, Context};
 use bytes::BytesMut;
 use postgres_backend::CopyStreamHandlerEnd;
@@ -73,6 +76,7 @@ impl SafekeeperPostgresHandler {
         let peer_addr = *pgb.get_peer_addr();
         let network_reader = NetworkReader {
             ttid: self.ttid,
+            conf: self.conf.clone(),
             conn_id: self.conn_id,
             pgb_reader: &mut pgb_reader,
             peer_addr,
@@ -116,6 +120,7 @@ impl SafekeeperPostgresHandler {
 
 struct NetworkReader<'a, IO> {
     ttid: TenantTimelineId,
+    conf: SafeKeeperConf,
     conn_id: ConnectionId,
     pgb_reader: &'a mut PostgresBackendReader<IO>,
     peer_addr: SocketAddr,
@@ -133,7 +138,7 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
     ) -> Result<(), CopyStreamHandlerEnd> {
         // Receive information about server to create timeline, if not yet.
         let next_msg = read_message(self.pgb_reader).await?;
-        let tli = match next_msg {
+        let (tli, proposer_uuid) = match next_msg {
             ProposerAcceptorMessage::Greeting(ref greeting) => {
                 info!(
                     "start handshake with walproposer {} sysid {} timeline {}",
@@ -144,7 +149,10 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
                     system_id: greeting.system_id,
                     wal_seg_size: greeting.wal_seg_size,
                 };
-                GlobalTimelines::create(self.ttid, server_info, Lsn::INVALID, Lsn::INVALID).await?
+                let tli =
+                    GlobalTimelines::create(self.ttid, server_info, Lsn::INVALID, Lsn::INVALID)
+                        .await?;
+                (tli, greeting.proposer_id)
             }
             _ => {
                 return Err(CopyStreamHandlerEnd::Other(anyhow::anyhow!(
@@ -155,9 +163,11 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
 
         *self.acceptor_handle = Some(WalAcceptor::spawn(
             tli.clone(),
+            self.conf,
             msg_rx,
             reply_tx,
             self.conn_id,
+            (proposer_uuid, self.peer_addr),
         ));
 
         // Forward all messages to WalAcceptor
@@ -216,23 +226,30 @@ const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
 /// Takes messages from msg_rx, processes and pushes replies to reply_tx.
 struct WalAcceptor {
     tli: Arc<Timeline>,
+    conf: SafeKeeperConf,
     msg_rx: Receiver<ProposerAcceptorMessage>,
     reply_tx: Sender<AcceptorProposerMessage>,
+    /// Identity of the walproposer, recorded when it gets elected.
+    proposer: (PgUuid, SocketAddr),
 }
 
 impl WalAcceptor {
     /// Spawn thread with WalAcceptor running, return handle to it.
     fn spawn(
         tli: Arc<Timeline>,
+        conf: SafeKeeperConf,
         msg_rx: Receiver<ProposerAcceptorMessage>,
         reply_tx: Sender<AcceptorProposerMessage>,
         conn_id: ConnectionId,
+        proposer: (PgUuid, SocketAddr),
     ) -> JoinHandle<anyhow::Result<()>> {
         task::spawn(async move {
             let mut wa = WalAcceptor {
                 tli,
+                conf,
                 msg_rx,
                 reply_tx,
+                proposer,
             };
 
             let span_ttid = wa.tli.ttid; // satisfy borrow checker
@@ -264,6 +281,8 @@ impl WalAcceptor {
             let mut next_msg = opt_msg.unwrap();
 
             let reply_msg = if matches!(next_msg, ProposerAcceptorMessage::AppendRequest(_)) {
+                self.wait_for_disk_space().await;
+
                 // loop through AppendRequest's while it's readily available to
                 // write as many WAL as possible without fsyncing
                 //
@@ -297,7 +316,14 @@ impl WalAcceptor {
                     .await?
             } else {
                 // process message other than AppendRequest
-                self.tli.process_msg(&next_msg).await?
+                let reply = self.tli.process_msg(&next_msg).await?;
+                if let ProposerAcceptorMessage::Elected(ref elected) = next_msg {
+                    let (proposer_uuid, proposer_addr) = self.proposer;
+                    self.tli
+                        .record_election(elected.term, proposer_uuid, proposer_addr.to_string())
+                        .await?;
+                }
+                reply
             };
 
             if let Some(reply) = reply_msg {
@@ -309,6 +335,35 @@ impl WalAcceptor {
             }
         }
     }
+
+    /// Holds off the WAL while the disk is full and the WAL of this timeline
+    /// isn't being consumed, see [`disk_usage`].
+    async fn wait_for_disk_space(&self) {
+        if self.conf.max_disk_usage.is_none() {
+            return;
+        }
+        let mut throttled = false;
+        loop {
+            let retained_wal_bytes = self
+                .tli
+                .get_retained_wal_bytes(self.conf.is_wal_backup_enabled())
+                .await;
+            if !disk_usage::should_throttle(&self.conf, retained_wal_bytes) {
+                break;
+            }
+            if !throttled {
+                warn!(
+                    "disk usage limit reached, not accepting WAL while {} bytes of it are retained",
+                    retained_wal_bytes
+                );
+                throttled = true;
+            }
+            tokio::time::sleep(disk_usage::CHECK_INTERVAL).await;
+        }
+        if throttled {
+            info!("accepting WAL again");
+        }
+    }
 }
 
 struct ComputeConnectionGuard {
@@ -318,8 +373,7 @@ struct ComputeConnectionGuard {
 impl Drop for ComputeConnectionGuard {
     fn drop(&mut self) {
         let tli = self.timeline.clone();
-        // tokio forbids to call blocking_send inside the runtime, and see
-        // comments in on_compute_disconnect why we call blocking_send.
+        // drop can't await, unregister the connection in a task
         tokio::spawn(async move {
             if let Err(e) = tli.on_compute_disconnect().await {
                 error!("failed to unregister compute connection: {}", e);
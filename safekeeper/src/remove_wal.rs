@@ -1,12 +1,66 @@
 //! Thread removing old WAL.
+//!
+//! A WAL segment is removed only once everyone who may still read it from this
+//! safekeeper has moved past it, see [`WalRetention`].
 
 use std::time::Duration;
 
+use postgres_ffi::XLogSegNo;
+use serde::Serialize;
 use tokio::time::sleep;
 use tracing::*;
+use utils::lsn::Lsn;
 
 use crate::{GlobalTimelines, SafeKeeperConf};
 
+/// The LSNs holding back the removal of the WAL of a timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WalRetention {
+    /// The pageserver has uploaded the layers with the WAL up to here, so it
+    /// won't ask for it again.
+    pub remote_consistent_lsn: Lsn,
+    /// The slowest of the pageservers streaming the timeline from this
+    /// safekeeper, see [`crate::send_wal::WalSenders::get_subscribers_horizon`].
+    /// `None` if there are none.
+    pub subscribers_lsn: Option<Lsn>,
+    /// The WAL up to here is offloaded to the remote storage. `None` if WAL
+    /// backup is disabled.
+    pub backup_lsn: Option<Lsn>,
+    /// All the peer safekeepers have the WAL up to here.
+    pub peer_horizon_lsn: Lsn,
+}
+
+impl WalRetention {
+    /// The WAL before this LSN is not needed anymore.
+    pub fn horizon_lsn(&self) -> Lsn {
+        self.held_by().1
+    }
+
+    /// The oldest segment that must be kept.
+    pub fn horizon_segno(&self, wal_seg_size: usize) -> XLogSegNo {
+        self.horizon_lsn().segment_number(wal_seg_size)
+    }
+
+    /// Who holds the horizon back, and at which LSN.
+    pub fn held_by(&self) -> (&'static str, Lsn) {
+        let mut held_by = ("pageserver", self.remote_consistent_lsn);
+        if let Some(subscribers_lsn) = self.subscribers_lsn {
+            if subscribers_lsn < held_by.1 {
+                held_by = ("subscribers", subscribers_lsn);
+            }
+        }
+        if let Some(backup_lsn) = self.backup_lsn {
+            if backup_lsn < held_by.1 {
+                held_by = ("backup", backup_lsn);
+            }
+        }
+        if self.peer_horizon_lsn < held_by.1 {
+            held_by = ("peers", self.peer_horizon_lsn);
+        }
+        held_by
+    }
+}
+
 pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
     let wal_removal_interval = Duration::from_millis(5000);
     loop {
@@ -24,7 +78,7 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
                 warn!("failed to persist control file: {e}");
             }
             if let Err(e) = tli
-                .remove_old_wal(conf.wal_backup_enabled)
+                .remove_old_wal(conf.is_wal_backup_enabled())
                 .instrument(info_span!("", tenant = %ttid.tenant_id, timeline = %ttid.timeline_id))
                 .await
             {
@@ -34,3 +88,32 @@ pub async fn task_main(conf: SafeKeeperConf) -> anyhow::Result<()> {
         sleep(wal_removal_interval).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retention_horizon() {
+        let mut retention = WalRetention {
+            remote_consistent_lsn: Lsn(0x3000),
+            subscribers_lsn: None,
+            backup_lsn: Some(Lsn(0x2000)),
+            peer_horizon_lsn: Lsn(0x4000),
+        };
+        assert_eq!(retention.held_by(), ("backup", Lsn(0x2000)));
+
+        // Without WAL backup, the backup_lsn doesn't matter
+        retention.backup_lsn = None;
+        assert_eq!(retention.held_by(), ("pageserver", Lsn(0x3000)));
+
+        // A lagging pageserver among several holds the WAL
+        retention.subscribers_lsn = Some(Lsn(0x2800));
+        assert_eq!(retention.held_by(), ("subscribers", Lsn(0x2800)));
+
+        retention.peer_horizon_lsn = Lsn(0x1000);
+        assert_eq!(retention.horizon_lsn(), Lsn(0x1000));
+        assert_eq!(retention.held_by().0, "peers");
+        assert_eq!(retention.horizon_segno(0x800), 2);
+    }
+}
@@ -4,18 +4,19 @@ use anyhow::{bail, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use postgres_ffi::{TimeLineID, XLogSegNo, MAX_SEND_SIZE};
+use postgres_ffi::{TimeLineID, MAX_SEND_SIZE};
 use serde::{Deserialize, Serialize};
 use std::cmp::max;
 use std::cmp::min;
 use std::fmt;
 use std::io::Read;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use storage_broker::proto::SafekeeperTimelineInfo;
 
 use tracing::*;
 
 use crate::control_file;
+use crate::remove_wal::WalRetention;
 use crate::send_wal::HotStandbyFeedback;
 
 use crate::wal_storage;
@@ -28,8 +29,14 @@ use utils::{
 };
 
 pub const SK_MAGIC: u32 = 0xcafeceefu32;
-pub const SK_FORMAT_VERSION: u32 = 7;
-const SK_PROTOCOL_VERSION: u32 = 2;
+pub const SK_FORMAT_VERSION: u32 = 9;
+/// Oldest control file format version which can read the files written with
+/// [`SK_FORMAT_VERSION`]. Since version 8, fields are only ever appended to
+/// [`SafeKeeperState`], so older versions can read newer files by ignoring
+/// the fields they don't know about. Bump this when changing the layout in any
+/// other way.
+pub const SK_MIN_READER_VERSION: u32 = 8;
+pub const SK_PROTOCOL_VERSION: u32 = 2;
 pub const UNKNOWN_SERVER_VERSION: u32 = 0;
 
 /// Consensus logical timestamp.
@@ -103,6 +110,22 @@ impl fmt::Debug for TermHistory {
 /// Unique id of proposer. Not needed for correctness, used for monitoring.
 pub type PgUuid = [u8; 16];
 
+/// How many of the latest elections are kept in [`SafeKeeperState::elections`].
+const MAX_TERM_ELECTIONS: usize = 100;
+
+/// Walproposer elected in a term, as seen by this safekeeper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermElection {
+    pub term: Term,
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    /// Address the walproposer connected from.
+    pub proposer_addr: String,
+    /// When we voted for the term, unless we restarted after that.
+    pub voted_at: Option<SystemTime>,
+    pub elected_at: SystemTime,
+}
+
 /// Persistent consensus state of the acceptor.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AcceptorState {
@@ -205,6 +228,9 @@ pub struct SafeKeeperState {
     // obviously can be stale. (Currently not saved at all, but let's provision
     // place to have less file version upgrades).
     pub peers: PersistedPeers,
+    /// Latest elections of walproposers we took part in, oldest first. Not
+    /// needed for correctness, exists for debugging.
+    pub elections: Vec<TermElection>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -247,6 +273,7 @@ impl SafeKeeperState {
                     .map(|p| (*p, PersistedPeerInfo::new()))
                     .collect(),
             ),
+            elections: Vec::new(),
         }
     }
 
@@ -269,7 +296,7 @@ impl SafeKeeperState {
 // protocol messages
 
 /// Initial Proposer -> Acceptor message
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ProposerGreeting {
     /// proposer-acceptor protocol version
     pub protocol_version: u32,
@@ -287,27 +314,27 @@ pub struct ProposerGreeting {
 /// (acceptor voted for).
 #[derive(Debug, Serialize)]
 pub struct AcceptorGreeting {
-    term: u64,
-    node_id: NodeId,
+    pub term: u64,
+    pub node_id: NodeId,
 }
 
 /// Vote request sent from proposer to safekeepers
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct VoteRequest {
-    term: Term,
+    pub term: Term,
 }
 
 /// Vote itself, sent from safekeeper to proposer
 #[derive(Debug, Serialize)]
 pub struct VoteResponse {
-    term: Term, // safekeeper's current term; if it is higher than proposer's, the compute is out of date.
-    vote_given: u64, // fixme u64 due to padding
+    pub term: Term, // safekeeper's current term; if it is higher than proposer's, the compute is out of date.
+    pub vote_given: u64, // fixme u64 due to padding
     // Safekeeper flush_lsn (end of WAL) + history of term switches allow
     // proposer to choose the most advanced one.
-    flush_lsn: Lsn,
-    truncate_lsn: Lsn,
-    term_history: TermHistory,
-    timeline_start_lsn: Lsn,
+    pub flush_lsn: Lsn,
+    pub truncate_lsn: Lsn,
+    pub term_history: TermHistory,
+    pub timeline_start_lsn: Lsn,
 }
 
 /*
@@ -329,7 +356,7 @@ pub struct AppendRequest {
     pub h: AppendRequestHeader,
     pub wal_data: Bytes,
 }
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppendRequestHeader {
     // safekeeper's current term; if it is higher than proposer's, the compute is out of date.
     pub term: Term,
@@ -448,6 +475,43 @@ impl ProposerAcceptorMessage {
             _ => bail!("unknown proposer-acceptor message tag: {}", tag,),
         }
     }
+
+    /// Serialize proposer message, the reverse of [`ProposerAcceptorMessage::parse`],
+    /// for the safekeepers which act as a proposer, see [`crate::wal_forward`].
+    pub fn serialize(&self, buf: &mut BytesMut) -> Result<()> {
+        match self {
+            ProposerAcceptorMessage::Greeting(msg) => {
+                buf.put_u64_le('g' as u64);
+                buf.put_slice(&msg.ser()?);
+            }
+            ProposerAcceptorMessage::VoteRequest(msg) => {
+                buf.put_u64_le('v' as u64);
+                buf.put_slice(&msg.ser()?);
+            }
+            ProposerAcceptorMessage::Elected(msg) => {
+                buf.put_u64_le('e' as u64);
+                buf.put_u64_le(msg.term);
+                buf.put_u64_le(msg.start_streaming_at.into());
+                buf.put_u32_le(msg.term_history.0.len() as u32);
+                for e in &msg.term_history.0 {
+                    buf.put_u64_le(e.term);
+                    buf.put_u64_le(e.lsn.into());
+                }
+                buf.put_u64_le(msg.timeline_start_lsn.into());
+            }
+            ProposerAcceptorMessage::AppendRequest(msg) => {
+                buf.put_u64_le('a' as u64);
+                buf.put_slice(&msg.h.ser()?);
+                buf.put_slice(&msg.wal_data);
+            }
+            ProposerAcceptorMessage::NoFlushAppendRequest(_)
+            | ProposerAcceptorMessage::FlushWAL => {
+                bail!("{self:?} is not sent over the wire")
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Acceptor -> Proposer messages
@@ -495,6 +559,64 @@ impl AcceptorProposerMessage {
 
         Ok(())
     }
+
+    /// Parse acceptor message, the reverse of [`AcceptorProposerMessage::serialize`].
+    pub fn parse(mut msg_bytes: Bytes) -> Result<AcceptorProposerMessage> {
+        if msg_bytes.remaining() < 8 {
+            bail!("acceptor-proposer message misses tag");
+        }
+        let tag = msg_bytes.get_u64_le() as u8 as char;
+        match tag {
+            'g' => {
+                if msg_bytes.remaining() < 16 {
+                    bail!("AcceptorGreeting message is not complete");
+                }
+                Ok(AcceptorProposerMessage::Greeting(AcceptorGreeting {
+                    term: msg_bytes.get_u64_le(),
+                    node_id: NodeId(msg_bytes.get_u64_le()),
+                }))
+            }
+            'v' => {
+                if msg_bytes.remaining() < 32 {
+                    bail!("VoteResponse message is not complete");
+                }
+                let term = msg_bytes.get_u64_le();
+                let vote_given = msg_bytes.get_u64_le();
+                let flush_lsn = msg_bytes.get_u64_le().into();
+                let truncate_lsn = msg_bytes.get_u64_le().into();
+                let term_history = TermHistory::from_bytes(&mut msg_bytes)?;
+                if msg_bytes.remaining() < 8 {
+                    bail!("VoteResponse message is not complete");
+                }
+                let timeline_start_lsn = msg_bytes.get_u64_le().into();
+                Ok(AcceptorProposerMessage::VoteResponse(VoteResponse {
+                    term,
+                    vote_given,
+                    flush_lsn,
+                    truncate_lsn,
+                    term_history,
+                    timeline_start_lsn,
+                }))
+            }
+            'a' => {
+                if msg_bytes.remaining() < 48 {
+                    bail!("AppendResponse message is not complete");
+                }
+                Ok(AcceptorProposerMessage::AppendResponse(AppendResponse {
+                    term: msg_bytes.get_u64_le(),
+                    flush_lsn: msg_bytes.get_u64_le().into(),
+                    commit_lsn: msg_bytes.get_u64_le().into(),
+                    hs_feedback: HotStandbyFeedback {
+                        ts: msg_bytes.get_i64_le(),
+                        xmin: msg_bytes.get_u64_le(),
+                        catalog_xmin: msg_bytes.get_u64_le(),
+                    },
+                    pageserver_feedback: PageserverFeedback::parse(msg_bytes),
+                }))
+            }
+            _ => bail!("unknown acceptor-proposer message tag: {}", tag),
+        }
+    }
 }
 
 /// Safekeeper implements consensus to reliably persist WAL across nodes.
@@ -514,6 +636,9 @@ pub struct SafeKeeper<CTRL: control_file::Storage, WAL: wal_storage::Storage> {
     pub wal_store: WAL,
 
     node_id: NodeId, // safekeeper's node id
+
+    /// The latest term we voted for since the start, and when.
+    last_vote: Option<(Term, SystemTime)>,
 }
 
 impl<CTRL, WAL> SafeKeeper<CTRL, WAL>
@@ -546,6 +671,7 @@ where
             state,
             wal_store,
             node_id,
+            last_vote: None,
         })
     }
 
@@ -698,6 +824,7 @@ where
 
             resp.term = self.state.acceptor_state.term;
             resp.vote_given = true as u64;
+            self.last_vote = Some((msg.term, SystemTime::now()));
         }
         info!("processed VoteRequest for term {}: {:?}", msg.term, &resp);
         Ok(Some(AcceptorProposerMessage::VoteResponse(resp)))
@@ -797,6 +924,39 @@ where
         Ok(None)
     }
 
+    /// Remembers the walproposer elected in the current term. Called after it
+    /// sent ProposerElected, by the connection which knows its identity.
+    pub async fn record_election(
+        &mut self,
+        term: Term,
+        proposer_uuid: PgUuid,
+        proposer_addr: String,
+    ) -> Result<()> {
+        if term != self.state.acceptor_state.term
+            || self.state.elections.last().map(|e| e.term) == Some(term)
+        {
+            return Ok(());
+        }
+        let voted_at = match self.last_vote {
+            Some((vote_term, voted_at)) if vote_term == term => Some(voted_at),
+            _ => None,
+        };
+
+        let mut state = self.state.clone();
+        state.elections.push(TermElection {
+            term,
+            proposer_uuid,
+            proposer_addr,
+            voted_at,
+            elected_at: SystemTime::now(),
+        });
+        if state.elections.len() > MAX_TERM_ELECTIONS {
+            let excess = state.elections.len() - MAX_TERM_ELECTIONS;
+            state.elections.drain(..excess);
+        }
+        self.state.persist(&state).await
+    }
+
     /// Advance commit_lsn taking into account what we have locally.
     ///
     /// Note: it is assumed that 'WAL we have is from the right term' check has
@@ -988,27 +1148,32 @@ where
         Ok(())
     }
 
-    /// Get oldest segno we still need to keep. We hold WAL till it is consumed
-    /// by all of 1) pageserver (remote_consistent_lsn) 2) peers 3) s3
-    /// offloading.
+    /// Get what holds the WAL on disk. We hold WAL till it is consumed by all
+    /// of 1) pageservers (remote_consistent_lsn and the subscribers of this
+    /// safekeeper) 2) peers 3) s3 offloading, if enabled.
     /// While it is safe to use inmem values for determining horizon,
     /// we use persistent to make possible normal states less surprising.
-    pub fn get_horizon_segno(&self, wal_backup_enabled: bool) -> XLogSegNo {
-        let mut horizon_lsn = min(
-            self.state.remote_consistent_lsn,
-            self.state.peer_horizon_lsn,
-        );
-        if wal_backup_enabled {
-            horizon_lsn = min(horizon_lsn, self.state.backup_lsn);
+    pub fn get_wal_retention(
+        &self,
+        wal_backup_enabled: bool,
+        subscribers_lsn: Option<Lsn>,
+    ) -> WalRetention {
+        WalRetention {
+            remote_consistent_lsn: self.state.remote_consistent_lsn,
+            subscribers_lsn,
+            backup_lsn: wal_backup_enabled.then_some(self.state.backup_lsn),
+            peer_horizon_lsn: self.state.peer_horizon_lsn,
         }
-        horizon_lsn.segment_number(self.state.server.wal_seg_size as usize)
     }
 }
 
+#[cfg(test)]
+mod simulation;
+
 #[cfg(test)]
 mod tests {
     use futures::future::BoxFuture;
-    use postgres_ffi::WAL_SEGMENT_SIZE;
+    use postgres_ffi::{XLogSegNo, WAL_SEGMENT_SIZE};
 
     use super::*;
     use crate::wal_storage::Storage;
@@ -1169,4 +1334,151 @@ mod tests {
         sk.wal_store.truncate_wal(Lsn(3)).await.unwrap(); // imitate the complete record at 3 %)
         assert_eq!(sk.get_epoch(), 1);
     }
+
+    #[tokio::test]
+    async fn test_record_election() {
+        let storage = InMemoryState {
+            persisted_state: test_sk_state(),
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(storage, wal_store, NodeId(0)).unwrap();
+
+        let vote_request = ProposerAcceptorMessage::VoteRequest(VoteRequest { term: 1 });
+        sk.process_msg(&vote_request).await.unwrap();
+        let addr = "127.0.0.1:5432".to_owned();
+        sk.record_election(1, [1; 16], addr.clone()).await.unwrap();
+        // recorded once per term
+        sk.record_election(1, [2; 16], addr.clone()).await.unwrap();
+        // not the current term
+        sk.record_election(2, [2; 16], addr.clone()).await.unwrap();
+
+        let elections = &sk.state.persisted_state.elections;
+        assert_eq!(elections.len(), 1);
+        assert_eq!(elections[0].term, 1);
+        assert_eq!(elections[0].proposer_uuid, [1; 16]);
+        assert!(elections[0].voted_at.is_some());
+
+        for term in 2..=(MAX_TERM_ELECTIONS as Term + 10) {
+            let vote_request = ProposerAcceptorMessage::VoteRequest(VoteRequest { term });
+            sk.process_msg(&vote_request).await.unwrap();
+            sk.record_election(term, [1; 16], addr.clone())
+                .await
+                .unwrap();
+        }
+        let elections = &sk.state.persisted_state.elections;
+        assert_eq!(elections.len(), MAX_TERM_ELECTIONS);
+        assert_eq!(
+            elections.last().unwrap().term,
+            MAX_TERM_ELECTIONS as Term + 10
+        );
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let roundtrip = |msg: ProposerAcceptorMessage| {
+            let mut buf = BytesMut::new();
+            msg.serialize(&mut buf).unwrap();
+            ProposerAcceptorMessage::parse(buf.freeze()).unwrap()
+        };
+
+        let greeting = ProposerGreeting {
+            protocol_version: SK_PROTOCOL_VERSION,
+            pg_version: 150002,
+            proposer_id: [7; 16],
+            system_id: 42,
+            timeline_id: TimelineId::from([1u8; 16]),
+            tenant_id: TenantId::from([2u8; 16]),
+            tli: 1,
+            wal_seg_size: WAL_SEGMENT_SIZE as u32,
+        };
+        match roundtrip(ProposerAcceptorMessage::Greeting(greeting)) {
+            ProposerAcceptorMessage::Greeting(msg) => {
+                assert_eq!(msg.pg_version, 150002);
+                assert_eq!(msg.proposer_id, [7; 16]);
+                assert_eq!(msg.tenant_id, TenantId::from([2u8; 16]));
+                assert_eq!(msg.wal_seg_size, WAL_SEGMENT_SIZE as u32);
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        let elected = ProposerElected {
+            term: 3,
+            start_streaming_at: Lsn(0x2000),
+            term_history: TermHistory(vec![
+                TermSwitchEntry {
+                    term: 1,
+                    lsn: Lsn(0x1000),
+                },
+                TermSwitchEntry {
+                    term: 3,
+                    lsn: Lsn(0x2000),
+                },
+            ]),
+            timeline_start_lsn: Lsn(0x1000),
+        };
+        match roundtrip(ProposerAcceptorMessage::Elected(elected)) {
+            ProposerAcceptorMessage::Elected(msg) => {
+                assert_eq!(msg.term, 3);
+                assert_eq!(msg.start_streaming_at, Lsn(0x2000));
+                assert_eq!(msg.term_history.0.len(), 2);
+                assert_eq!(msg.timeline_start_lsn, Lsn(0x1000));
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        let append_request = AppendRequest {
+            h: AppendRequestHeader {
+                term: 3,
+                epoch_start_lsn: Lsn(0x2000),
+                begin_lsn: Lsn(0x2000),
+                end_lsn: Lsn(0x2004),
+                commit_lsn: Lsn(0x2000),
+                truncate_lsn: Lsn(0x1000),
+                proposer_uuid: [7; 16],
+            },
+            wal_data: Bytes::from_static(b"abcd"),
+        };
+        match roundtrip(ProposerAcceptorMessage::AppendRequest(append_request)) {
+            ProposerAcceptorMessage::AppendRequest(msg) => {
+                assert_eq!(msg.h.end_lsn, Lsn(0x2004));
+                assert_eq!(msg.h.truncate_lsn, Lsn(0x1000));
+                assert_eq!(&msg.wal_data[..], b"abcd");
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        let vote_response = VoteResponse {
+            term: 3,
+            vote_given: 1,
+            flush_lsn: Lsn(0x2004),
+            truncate_lsn: Lsn(0x1000),
+            term_history: TermHistory(vec![TermSwitchEntry {
+                term: 1,
+                lsn: Lsn(0x1000),
+            }]),
+            timeline_start_lsn: Lsn(0x1000),
+        };
+        let mut buf = BytesMut::new();
+        AcceptorProposerMessage::VoteResponse(vote_response)
+            .serialize(&mut buf)
+            .unwrap();
+        match AcceptorProposerMessage::parse(buf.freeze()).unwrap() {
+            AcceptorProposerMessage::VoteResponse(msg) => {
+                assert_eq!(msg.vote_given, 1);
+                assert_eq!(msg.flush_lsn, Lsn(0x2004));
+                assert_eq!(msg.term_history.0[0].lsn, Lsn(0x1000));
+                assert_eq!(msg.timeline_start_lsn, Lsn(0x1000));
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+
+        let mut buf = BytesMut::new();
+        AcceptorProposerMessage::AppendResponse(AppendResponse::term_only(5))
+            .serialize(&mut buf)
+            .unwrap();
+        match AcceptorProposerMessage::parse(buf.freeze()).unwrap() {
+            AcceptorProposerMessage::AppendResponse(msg) => assert_eq!(msg.term, 5),
+            msg => panic!("unexpected {msg:?}"),
+        }
+    }
 }
@@ -0,0 +1,867 @@
+//! Deterministic simulation of the proposer-acceptor protocol.
+//!
+//! Runs several [`SafeKeeper`] state machines with in-memory storage and model
+//! walproposers over a virtual network, driven by a seeded random schedule of
+//! message deliveries, connection breaks, safekeeper crashes and walproposer
+//! restarts. After every step it checks that no WAL acknowledged by a quorum
+//! (committed) is lost: each newly elected walproposer starts from WAL
+//! containing all committed WAL, and the committed WAL of every safekeeper
+//! matches it.
+//!
+//! The network delivers the messages of a connection in order, like TCP, but
+//! may interleave the connections arbitrarily. A lost message is modeled as a
+//! broken connection, dropping everything in flight on it. A crashed
+//! safekeeper loses the WAL it hasn't flushed and its in-memory state.
+//!
+//! The model walproposer follows walproposer.c: it picks a term above the ones
+//! a quorum greeted it with, takes the most advanced voter as the donor, finds
+//! the divergence point with each safekeeper's term history and streams WAL
+//! from it. Recovery from the donor is modeled as taking a snapshot of its WAL
+//! along with the vote.
+
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+
+use anyhow::{ensure, Result};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use postgres_ffi::{XLogSegNo, WAL_SEGMENT_SIZE};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::*;
+
+/// Where the WAL of the simulated timeline starts.
+const START_LSN: Lsn = Lsn(0x100);
+
+/// Knobs of a simulation run, with relative weights of the events.
+#[derive(Debug, Clone)]
+struct SimConfig {
+    num_safekeepers: usize,
+    steps: usize,
+    /// Deliver messages of different connections out of order.
+    reorder: bool,
+    max_proposers: usize,
+    deliver_weight: u32,
+    append_weight: u32,
+    connect_weight: u32,
+    break_connection_weight: u32,
+    crash_safekeeper_weight: u32,
+    new_proposer_weight: u32,
+    kill_proposer_weight: u32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            num_safekeepers: 3,
+            steps: 400,
+            reorder: true,
+            max_proposers: 2,
+            deliver_weight: 60,
+            append_weight: 15,
+            connect_weight: 10,
+            break_connection_weight: 3,
+            crash_safekeeper_weight: 2,
+            new_proposer_weight: 2,
+            kill_proposer_weight: 1,
+        }
+    }
+}
+
+/// Control file storage which is durable as soon as persisted.
+struct SimControlFile {
+    state: SafeKeeperState,
+}
+
+#[async_trait::async_trait]
+impl control_file::Storage for SimControlFile {
+    async fn persist(&mut self, s: &SafeKeeperState) -> Result<()> {
+        self.state = s.clone();
+        Ok(())
+    }
+
+    fn last_persist_at(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl std::ops::Deref for SimControlFile {
+    type Target = SafeKeeperState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+/// WAL storage keeping the WAL since [`START_LSN`] in memory, which loses the
+/// unflushed part on crash.
+#[derive(Default)]
+struct SimWalStore {
+    /// Set by the first truncation, which places the start of the WAL.
+    initialized: bool,
+    wal: Vec<u8>,
+    flushed: usize,
+}
+
+impl SimWalStore {
+    fn end_lsn(&self, len: usize) -> Lsn {
+        START_LSN + len as u64
+    }
+
+    fn offset(lsn: Lsn) -> Result<usize> {
+        ensure!(lsn >= START_LSN, "LSN {lsn} is before the start of WAL");
+        Ok((lsn.0 - START_LSN.0) as usize)
+    }
+
+    fn flushed_wal(&self) -> &[u8] {
+        &self.wal[..self.flushed]
+    }
+
+    fn crash(&mut self) {
+        self.wal.truncate(self.flushed);
+    }
+}
+
+#[async_trait::async_trait]
+impl wal_storage::Storage for SimWalStore {
+    fn flush_lsn(&self) -> Lsn {
+        if self.initialized {
+            self.end_lsn(self.flushed)
+        } else {
+            Lsn(0)
+        }
+    }
+
+    async fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
+        ensure!(self.initialized, "write before truncation");
+        ensure!(
+            Self::offset(startpos)? == self.wal.len(),
+            "write at {startpos}, but WAL ends at {}",
+            self.end_lsn(self.wal.len())
+        );
+        self.wal.extend_from_slice(buf);
+        Ok(())
+    }
+
+    async fn truncate_wal(&mut self, end_pos: Lsn) -> Result<()> {
+        let offset = Self::offset(end_pos)?;
+        if !self.initialized {
+            ensure!(offset == 0, "truncation of empty WAL to {end_pos}");
+            self.initialized = true;
+        }
+        ensure!(
+            offset <= self.wal.len(),
+            "truncation to {end_pos} beyond the end of WAL"
+        );
+        self.wal.truncate(offset);
+        self.flushed = offset;
+        Ok(())
+    }
+
+    async fn flush_wal(&mut self) -> Result<()> {
+        self.flushed = self.wal.len();
+        Ok(())
+    }
+
+    fn remove_up_to(&self, _segno_up_to: XLogSegNo) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get_metrics(&self) -> crate::metrics::WalStorageMetrics {
+        crate::metrics::WalStorageMetrics::default()
+    }
+}
+
+type SimSafeKeeper = SafeKeeper<SimControlFile, SimWalStore>;
+
+fn sim_safekeeper_state() -> SafeKeeperState {
+    let mut state = SafeKeeperState::empty();
+    state.server.wal_seg_size = WAL_SEGMENT_SIZE as u32;
+    state.tenant_id = TenantId::from([1u8; 16]);
+    state.timeline_id = TimelineId::from([1u8; 16]);
+    state
+}
+
+enum Message {
+    ToSafekeeper(ProposerAcceptorMessage),
+    /// Responses to votes carry the snapshot of the flushed WAL, to be used if
+    /// the safekeeper becomes the donor.
+    ToProposer(AcceptorProposerMessage, Option<Vec<u8>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Direction {
+    ToSafekeeper,
+    ToProposer,
+}
+
+/// Connection between a walproposer and a safekeeper.
+type ConnKey = (u64, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnState {
+    Greeting,
+    Voting,
+    /// ProposerElected is sent, streaming WAL from `next_lsn`.
+    Streaming {
+        next_lsn: Lsn,
+        flush_lsn: Option<Lsn>,
+    },
+}
+
+struct Elected {
+    term_history: TermHistory,
+    epoch_start_lsn: Lsn,
+    /// WAL since [`START_LSN`].
+    wal: Vec<u8>,
+    commit_lsn: Lsn,
+}
+
+impl Elected {
+    fn end_lsn(&self) -> Lsn {
+        START_LSN + self.wal.len() as u64
+    }
+}
+
+struct Proposer {
+    id: u64,
+    /// Zero until chosen after a quorum of greetings.
+    term: Term,
+    conns: BTreeMap<usize, ConnState>,
+    greeting_terms: BTreeMap<usize, Term>,
+    votes: BTreeMap<usize, (VoteResponse, Vec<u8>)>,
+    elected: Option<Elected>,
+}
+
+struct Sim {
+    config: SimConfig,
+    rng: StdRng,
+    safekeepers: Vec<SimSafeKeeper>,
+    proposers: Vec<Proposer>,
+    next_proposer_id: u64,
+    /// In flight messages with their sequence numbers.
+    network: BTreeMap<(ConnKey, Direction), VecDeque<(u64, Message)>>,
+    next_msg_seq: u64,
+    /// WAL since [`START_LSN`] committed by any walproposer.
+    committed: Vec<u8>,
+    elections: usize,
+}
+
+/// Start of the part of a safekeeper's WAL to overwrite with the proposer's,
+/// after the last term their histories share.
+fn start_streaming_at(
+    prop_history: &TermHistory,
+    prop_end_lsn: Lsn,
+    sk_history: &TermHistory,
+    sk_flush_lsn: Lsn,
+) -> Result<Lsn> {
+    let common = prop_history
+        .0
+        .iter()
+        .zip(&sk_history.0)
+        .take_while(|(p, s)| p.term == s.term)
+        .count();
+    if common == 0 {
+        return Ok(prop_history.0[0].lsn);
+    }
+    let i = common - 1;
+    ensure!(
+        prop_history.0[i].lsn == sk_history.0[i].lsn,
+        "term {} begins at {} in the proposer history, but at {} in the safekeeper one",
+        prop_history.0[i].term,
+        prop_history.0[i].lsn,
+        sk_history.0[i].lsn
+    );
+    let sk_end = sk_history.0.get(i + 1).map_or(sk_flush_lsn, |e| e.lsn);
+    let prop_end = prop_history.0.get(i + 1).map_or(prop_end_lsn, |e| e.lsn);
+    Ok(min(sk_end, prop_end))
+}
+
+impl Sim {
+    fn new(config: SimConfig, seed: u64) -> Result<Sim> {
+        let mut safekeepers = Vec::new();
+        for i in 0..config.num_safekeepers {
+            let control_file = SimControlFile {
+                state: sim_safekeeper_state(),
+            };
+            safekeepers.push(SafeKeeper::new(
+                control_file,
+                SimWalStore::default(),
+                NodeId(i as u64),
+            )?);
+        }
+        Ok(Sim {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            safekeepers,
+            proposers: Vec::new(),
+            next_proposer_id: 1,
+            network: BTreeMap::new(),
+            next_msg_seq: 0,
+            committed: Vec::new(),
+            elections: 0,
+        })
+    }
+
+    fn quorum(&self) -> usize {
+        self.config.num_safekeepers / 2 + 1
+    }
+
+    fn send(&mut self, conn: ConnKey, direction: Direction, msg: Message) {
+        let seq = self.next_msg_seq;
+        self.next_msg_seq += 1;
+        self.network
+            .entry((conn, direction))
+            .or_default()
+            .push_back((seq, msg));
+    }
+
+    fn send_to_sk(&mut self, conn: ConnKey, msg: ProposerAcceptorMessage) {
+        self.send(conn, Direction::ToSafekeeper, Message::ToSafekeeper(msg));
+    }
+
+    fn proposer_mut(&mut self, id: u64) -> Option<&mut Proposer> {
+        self.proposers.iter_mut().find(|p| p.id == id)
+    }
+
+    fn break_connection(&mut self, (proposer_id, sk): ConnKey) {
+        self.network
+            .retain(|((p, s), _), _| !(*p == proposer_id && *s == sk));
+        if let Some(proposer) = self.proposer_mut(proposer_id) {
+            proposer.conns.remove(&sk);
+        }
+    }
+
+    fn kill_proposer(&mut self, proposer_id: u64) {
+        self.network.retain(|((p, _), _), _| *p != proposer_id);
+        self.proposers.retain(|p| p.id != proposer_id);
+    }
+
+    fn new_proposer(&mut self) {
+        let id = self.next_proposer_id;
+        self.next_proposer_id += 1;
+        self.proposers.push(Proposer {
+            id,
+            term: 0,
+            conns: BTreeMap::new(),
+            greeting_terms: BTreeMap::new(),
+            votes: BTreeMap::new(),
+            elected: None,
+        });
+    }
+
+    fn connect(&mut self, proposer_id: u64, sk: usize) {
+        let proposer = self.proposer_mut(proposer_id).unwrap();
+        proposer.conns.insert(sk, ConnState::Greeting);
+        proposer.greeting_terms.remove(&sk);
+        let greeting = ProposerGreeting {
+            protocol_version: SK_PROTOCOL_VERSION,
+            pg_version: 150000,
+            proposer_id: [proposer_id as u8; 16],
+            system_id: 0,
+            timeline_id: TimelineId::from([1u8; 16]),
+            tenant_id: TenantId::from([1u8; 16]),
+            tli: 1,
+            wal_seg_size: WAL_SEGMENT_SIZE as u32,
+        };
+        self.send_to_sk(
+            (proposer_id, sk),
+            ProposerAcceptorMessage::Greeting(greeting),
+        );
+    }
+
+    async fn crash_safekeeper(&mut self, sk: usize) -> Result<()> {
+        self.network.retain(|((_, s), _), _| *s != sk);
+        for proposer in &mut self.proposers {
+            proposer.conns.remove(&sk);
+        }
+        let old = &mut self.safekeepers[sk];
+        let mut wal_store = std::mem::take(&mut old.wal_store);
+        wal_store.crash();
+        let control_file = SimControlFile {
+            state: old.state.state.clone(),
+        };
+        self.safekeepers[sk] = SafeKeeper::new(control_file, wal_store, NodeId(sk as u64))?;
+        Ok(())
+    }
+
+    /// Sends the WAL the safekeeper doesn't have yet, with the current commit_lsn.
+    fn send_append(&mut self, proposer_id: u64, sk: usize) {
+        let proposer = self.proposer_mut(proposer_id).unwrap();
+        let (Some(elected), Some(ConnState::Streaming { next_lsn, .. })) =
+            (&proposer.elected, proposer.conns.get_mut(&sk))
+        else {
+            return;
+        };
+        let begin_lsn = *next_lsn;
+        let end_lsn = elected.end_lsn();
+        *next_lsn = end_lsn;
+        let wal_data = Bytes::copy_from_slice(
+            &elected.wal[(begin_lsn.0 - START_LSN.0) as usize..elected.wal.len()],
+        );
+        let msg = AppendRequest {
+            h: AppendRequestHeader {
+                term: proposer.term,
+                epoch_start_lsn: elected.epoch_start_lsn,
+                begin_lsn,
+                end_lsn,
+                commit_lsn: elected.commit_lsn,
+                truncate_lsn: Lsn(0),
+                proposer_uuid: [proposer_id as u8; 16],
+            },
+            wal_data,
+        };
+        self.send_to_sk(
+            (proposer_id, sk),
+            ProposerAcceptorMessage::AppendRequest(msg),
+        );
+    }
+
+    /// Appends a few records to the WAL of the walproposer and streams them.
+    fn append(&mut self, proposer_id: u64) {
+        let n_records = self.rng.gen_range(1..=4);
+        let mut records = Vec::new();
+        for _ in 0..n_records {
+            let len = self.rng.gen_range(1..=64);
+            records.extend((0..len).map(|_| self.rng.gen::<u8>()));
+        }
+        let proposer = self.proposer_mut(proposer_id).unwrap();
+        let Some(elected) = &mut proposer.elected else {
+            return;
+        };
+        elected.wal.extend(records);
+        let sks = proposer.conns.keys().copied().collect::<Vec<_>>();
+        for sk in sks {
+            self.send_append(proposer_id, sk);
+        }
+    }
+
+    fn send_vote_request(&mut self, proposer_id: u64, sk: usize) {
+        let proposer = self.proposer_mut(proposer_id).unwrap();
+        proposer.conns.insert(sk, ConnState::Voting);
+        let term = proposer.term;
+        self.send_to_sk(
+            (proposer_id, sk),
+            ProposerAcceptorMessage::VoteRequest(VoteRequest { term }),
+        );
+    }
+
+    fn send_elected(&mut self, proposer_id: u64, sk: usize) -> Result<()> {
+        let proposer = self.proposer_mut(proposer_id).unwrap();
+        let elected = proposer.elected.as_ref().unwrap();
+        let (vote, _) = &proposer.votes[&sk];
+        let start = start_streaming_at(
+            &elected.term_history,
+            elected.end_lsn(),
+            &vote.term_history,
+            vote.flush_lsn,
+        )?;
+        let msg = ProposerElected {
+            term: proposer.term,
+            start_streaming_at: start,
+            term_history: elected.term_history.clone(),
+            timeline_start_lsn: START_LSN,
+        };
+        proposer.conns.insert(
+            sk,
+            ConnState::Streaming {
+                next_lsn: start,
+                flush_lsn: None,
+            },
+        );
+        self.send_to_sk((proposer_id, sk), ProposerAcceptorMessage::Elected(msg));
+        self.send_append(proposer_id, sk);
+        Ok(())
+    }
+
+    /// Becomes the leader after a quorum of votes, taking the WAL of the most
+    /// advanced voter.
+    fn elect(&mut self, proposer_id: u64) -> Result<()> {
+        let proposer = self
+            .proposers
+            .iter_mut()
+            .find(|p| p.id == proposer_id)
+            .unwrap();
+        let (_, (donor, donor_wal)) = proposer
+            .votes
+            .iter()
+            .max_by_key(|(_, (vote, _))| {
+                let epoch = vote.term_history.0.last().map_or(0, |e| e.term);
+                (epoch, vote.flush_lsn)
+            })
+            .unwrap();
+        let epoch_start_lsn = max(donor.flush_lsn, START_LSN);
+        let mut term_history = donor.term_history.clone();
+        term_history.0.push(TermSwitchEntry {
+            term: proposer.term,
+            lsn: epoch_start_lsn,
+        });
+        let wal = donor_wal.clone();
+        ensure!(
+            wal.len() >= self.committed.len() && wal[..self.committed.len()] == self.committed[..],
+            "proposer {} elected in term {} with WAL up to {} lost committed WAL up to {}",
+            proposer_id,
+            proposer.term,
+            START_LSN + wal.len() as u64,
+            START_LSN + self.committed.len() as u64
+        );
+        proposer.elected = Some(Elected {
+            term_history,
+            epoch_start_lsn,
+            wal,
+            commit_lsn: Lsn(0),
+        });
+        self.elections += 1;
+
+        // voters which disconnected since are still counted
+        let voters = proposer
+            .votes
+            .keys()
+            .filter(|sk| proposer.conns.get(sk) == Some(&ConnState::Voting))
+            .copied()
+            .collect::<Vec<_>>();
+        for sk in voters {
+            self.send_elected(proposer_id, sk)?;
+        }
+        Ok(())
+    }
+
+    /// Advances commit_lsn to the position flushed by a quorum.
+    fn update_commit_lsn(&mut self, proposer_id: u64) -> Result<()> {
+        let quorum = self.quorum();
+        let proposer = self
+            .proposers
+            .iter_mut()
+            .find(|p| p.id == proposer_id)
+            .unwrap();
+        let elected = proposer.elected.as_mut().unwrap();
+        let mut flushed = proposer
+            .conns
+            .values()
+            .filter_map(|conn| match conn {
+                ConnState::Streaming {
+                    flush_lsn: Some(lsn),
+                    ..
+                } => Some(*lsn),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if flushed.len() < quorum {
+            return Ok(());
+        }
+        flushed.sort_unstable_by(|a, b| b.cmp(a));
+        let candidate = flushed[quorum - 1];
+        // nothing is committed in the term until its start is
+        if candidate < elected.epoch_start_lsn || candidate <= elected.commit_lsn {
+            return Ok(());
+        }
+        ensure!(
+            candidate <= elected.end_lsn(),
+            "flushed {candidate} beyond the end of the proposer WAL"
+        );
+        elected.commit_lsn = candidate;
+
+        let new_committed = &elected.wal[..(candidate.0 - START_LSN.0) as usize];
+        let common = min(new_committed.len(), self.committed.len());
+        ensure!(
+            new_committed[..common] == self.committed[..common],
+            "proposer {} committed WAL up to {} diverging from the previously committed",
+            proposer_id,
+            candidate
+        );
+        if new_committed.len() > self.committed.len() {
+            self.committed = new_committed.to_vec();
+        }
+        Ok(())
+    }
+
+    async fn deliver_to_safekeeper(&mut self, conn: ConnKey, msg: ProposerAcceptorMessage) {
+        let sk = &mut self.safekeepers[conn.1];
+        let is_vote = matches!(msg, ProposerAcceptorMessage::VoteRequest(_));
+        match sk.process_msg(&msg).await {
+            Ok(Some(reply)) => {
+                let snapshot = is_vote.then(|| sk.wal_store.flushed_wal().to_vec());
+                self.send(
+                    conn,
+                    Direction::ToProposer,
+                    Message::ToProposer(reply, snapshot),
+                );
+            }
+            Ok(None) => {}
+            // the safekeeper closes the connection on errors
+            Err(_) => self.break_connection(conn),
+        }
+    }
+
+    async fn deliver_to_proposer(
+        &mut self,
+        (proposer_id, sk): ConnKey,
+        msg: AcceptorProposerMessage,
+        snapshot: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let quorum = self.quorum();
+        let Some(proposer) = self.proposer_mut(proposer_id) else {
+            return Ok(());
+        };
+        match msg {
+            AcceptorProposerMessage::Greeting(greeting) => {
+                if proposer.term != 0 {
+                    if greeting.term > proposer.term {
+                        self.kill_proposer(proposer_id);
+                    } else {
+                        self.send_vote_request(proposer_id, sk);
+                    }
+                    return Ok(());
+                }
+                proposer.greeting_terms.insert(sk, greeting.term);
+                if proposer.greeting_terms.len() >= quorum {
+                    proposer.term = proposer.greeting_terms.values().max().unwrap() + 1;
+                    let greeted = proposer
+                        .greeting_terms
+                        .keys()
+                        .filter(|sk| proposer.conns.get(sk) == Some(&ConnState::Greeting))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for sk in greeted {
+                        self.send_vote_request(proposer_id, sk);
+                    }
+                }
+            }
+            AcceptorProposerMessage::VoteResponse(vote) => {
+                let elected = proposer.elected.is_some();
+                // walproposer exits if refused by a safekeeper with a higher
+                // term, or before getting a quorum
+                if vote.vote_given == 0 && (vote.term > proposer.term || !elected) {
+                    self.kill_proposer(proposer_id);
+                    return Ok(());
+                }
+                proposer.votes.insert(sk, (vote, snapshot.unwrap()));
+                if elected {
+                    self.send_elected(proposer_id, sk)?;
+                } else if proposer.votes.len() >= quorum {
+                    self.elect(proposer_id)?;
+                }
+            }
+            AcceptorProposerMessage::AppendResponse(resp) => {
+                if resp.term > proposer.term {
+                    self.kill_proposer(proposer_id);
+                    return Ok(());
+                }
+                if let Some(ConnState::Streaming { flush_lsn, .. }) = proposer.conns.get_mut(&sk) {
+                    *flush_lsn = Some(resp.flush_lsn);
+                }
+                self.update_commit_lsn(proposer_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn deliver(&mut self) -> Result<()> {
+        let key = if self.config.reorder {
+            let n = self.rng.gen_range(0..self.network.len());
+            *self.network.keys().nth(n).unwrap()
+        } else {
+            *self
+                .network
+                .iter()
+                .min_by_key(|(_, queue)| queue[0].0)
+                .unwrap()
+                .0
+        };
+        let queue = self.network.get_mut(&key).unwrap();
+        let (_, msg) = queue.pop_front().unwrap();
+        if queue.is_empty() {
+            self.network.remove(&key);
+        }
+        let (conn, _) = key;
+        match msg {
+            Message::ToSafekeeper(msg) => self.deliver_to_safekeeper(conn, msg).await,
+            Message::ToProposer(msg, snapshot) => {
+                self.deliver_to_proposer(conn, msg, snapshot).await?
+            }
+        }
+        Ok(())
+    }
+
+    /// The committed WAL of each safekeeper must be the committed WAL.
+    fn check_safekeepers(&self) -> Result<()> {
+        for (i, sk) in self.safekeepers.iter().enumerate() {
+            let commit_lsn = sk.inmem.commit_lsn;
+            if commit_lsn <= START_LSN {
+                continue;
+            }
+            let len = (commit_lsn.0 - START_LSN.0) as usize;
+            let wal = sk.wal_store.flushed_wal();
+            ensure!(
+                len <= self.committed.len() && len <= wal.len(),
+                "safekeeper {i} commit_lsn {commit_lsn} is beyond the committed or its WAL"
+            );
+            ensure!(
+                wal[..len] == self.committed[..len],
+                "safekeeper {i} committed WAL diverges from the committed"
+            );
+        }
+        Ok(())
+    }
+
+    async fn step(&mut self) -> Result<()> {
+        let c = &self.config;
+        let weights = [
+            if self.network.is_empty() {
+                0
+            } else {
+                c.deliver_weight
+            },
+            c.append_weight,
+            c.connect_weight,
+            c.break_connection_weight,
+            c.crash_safekeeper_weight,
+            if self.proposers.len() < c.max_proposers {
+                c.new_proposer_weight
+            } else {
+                0
+            },
+            c.kill_proposer_weight,
+        ];
+        let mut roll = self.rng.gen_range(0..weights.iter().sum::<u32>());
+        let event = weights
+            .iter()
+            .position(|w| {
+                if roll < *w {
+                    true
+                } else {
+                    roll -= w;
+                    false
+                }
+            })
+            .unwrap();
+
+        let proposer_id = if self.proposers.is_empty() {
+            None
+        } else {
+            Some(self.proposers[self.rng.gen_range(0..self.proposers.len())].id)
+        };
+        let sk = self.rng.gen_range(0..self.safekeepers.len());
+        match (event, proposer_id) {
+            (0, _) => self.deliver().await?,
+            (1, Some(id)) => self.append(id),
+            (2, Some(id)) => {
+                if !self.proposer_mut(id).unwrap().conns.contains_key(&sk) {
+                    self.connect(id, sk);
+                }
+            }
+            (3, Some(id)) => self.break_connection((id, sk)),
+            (4, _) => self.crash_safekeeper(sk).await?,
+            (5, _) => self.new_proposer(),
+            (6, Some(id)) => self.kill_proposer(id),
+            _ => {}
+        }
+        self.check_safekeepers()
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        for _ in 0..self.config.steps {
+            self.step().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the schedules with the given seeds, returning the total number of
+/// elections and the committed WAL bytes.
+async fn run_schedules(config: &SimConfig, seeds: std::ops::Range<u64>) -> (usize, usize) {
+    let (mut elections, mut committed) = (0, 0);
+    for seed in seeds {
+        // the safekeeper asserts some of the invariants itself
+        let _guard = scopeguard::guard_on_unwind((), |_| {
+            eprintln!("schedule with seed {seed} panicked");
+        });
+        let mut sim = Sim::new(config.clone(), seed).unwrap();
+        if let Err(e) = sim.run().await {
+            panic!("schedule with seed {seed} failed: {e:#}");
+        }
+        elections += sim.elections;
+        committed += sim.committed.len();
+    }
+    (elections, committed)
+}
+
+#[test]
+fn test_divergence_point() {
+    let history = |entries: &[(Term, u64)]| {
+        TermHistory(
+            entries
+                .iter()
+                .map(|&(term, lsn)| TermSwitchEntry {
+                    term,
+                    lsn: Lsn(lsn),
+                })
+                .collect(),
+        )
+    };
+    let prop = history(&[(1, 0x100), (3, 0x200), (5, 0x300)]);
+    // empty safekeeper
+    assert_eq!(
+        start_streaming_at(&prop, Lsn(0x400), &history(&[]), Lsn(0)).unwrap(),
+        Lsn(0x100)
+    );
+    // wrote more WAL in term 1 than the proposer has
+    assert_eq!(
+        start_streaming_at(&prop, Lsn(0x400), &history(&[(1, 0x100)]), Lsn(0x250)).unwrap(),
+        Lsn(0x200)
+    );
+    // has WAL of term 2 after term 1, which is not in the proposer history
+    assert_eq!(
+        start_streaming_at(
+            &prop,
+            Lsn(0x400),
+            &history(&[(1, 0x100), (2, 0x180)]),
+            Lsn(0x250)
+        )
+        .unwrap(),
+        Lsn(0x180)
+    );
+    // lagging behind in the proposer's term
+    assert_eq!(
+        start_streaming_at(&prop, Lsn(0x400), &prop, Lsn(0x350)).unwrap(),
+        Lsn(0x350)
+    );
+}
+
+#[tokio::test]
+async fn test_no_committed_wal_loss() {
+    let config = SimConfig::default();
+    let (elections, committed) = run_schedules(&config, 0..2000).await;
+    // make sure the schedules are not degenerate
+    assert!(elections > 0);
+    assert!(committed > 0);
+}
+
+#[tokio::test]
+async fn test_no_committed_wal_loss_in_order() {
+    let config = SimConfig {
+        num_safekeepers: 5,
+        reorder: false,
+        max_proposers: 3,
+        ..Default::default()
+    };
+    let (elections, committed) = run_schedules(&config, 0..500).await;
+    assert!(elections > 0);
+    assert!(committed > 0);
+}
+
+#[tokio::test]
+async fn test_no_committed_wal_loss_frequent_failures() {
+    let config = SimConfig {
+        break_connection_weight: 15,
+        crash_safekeeper_weight: 10,
+        kill_proposer_weight: 5,
+        new_proposer_weight: 10,
+        ..Default::default()
+    };
+    let (elections, committed) = run_schedules(&config, 0..1000).await;
+    assert!(elections > 0);
+    assert!(committed > 0);
+}
@@ -15,18 +15,20 @@ use postgres_backend::{CopyStreamHandlerEnd, PostgresBackendReader, QueryError};
 use postgres_ffi::get_current_timestamp;
 use postgres_ffi::{TimestampTz, MAX_SEND_SIZE};
 use pq_proto::{BeMessage, WalSndKeepAlive, XLogDataBody};
+use safekeeper_api::WalCompression;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use tokio::io::{AsyncRead, AsyncWrite};
-use utils::id::TenantTimelineId;
+use utils::id::{NodeId, TenantTimelineId};
 use utils::lsn::AtomicLsn;
 use utils::pageserver_feedback::PageserverFeedback;
 
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::watch::Receiver;
 use tokio::time::timeout;
 use tracing::*;
@@ -38,6 +40,14 @@ const STANDBY_STATUS_UPDATE_TAG_BYTE: u8 = b'r';
 // neon extension of replication protocol
 const NEON_STATUS_UPDATE_TAG_BYTE: u8 = b'z';
 
+/// zstd level of the compressed WAL stream. The lowest levels keep up with
+/// the WAL of busy computes, and give most of the gain on WAL.
+const WAL_COMPRESSION_LEVEL: i32 = 1;
+
+/// How long a pageserver which stopped streaming is still remembered as a
+/// subscriber of the timeline, holding back the WAL removal.
+const SUBSCRIBER_TTL: Duration = Duration::from_secs(60 * 60);
+
 type FullTransactionId = u64;
 
 /// Hot standby feedback received from replica
@@ -112,6 +122,8 @@ impl WalSenders {
         addr: SocketAddr,
         conn_id: ConnectionId,
         appname: Option<String>,
+        pageserver_id: Option<NodeId>,
+        proto_version: u32,
     ) -> WalSenderGuard {
         let slots = &mut self.mutex.lock().slots;
         let walsender_state = WalSenderState {
@@ -119,6 +131,8 @@ impl WalSenders {
             addr,
             conn_id,
             appname,
+            pageserver_id,
+            proto_version,
             feedback: ReplicationFeedback::Pageserver(PageserverFeedback::empty()),
         };
         // find empty slot or create new one
@@ -141,6 +155,16 @@ impl WalSenders {
         self.mutex.lock().slots.iter().flatten().cloned().collect()
     }
 
+    /// Whether the pageserver is streaming WAL from us.
+    pub fn is_streaming_to(self: &Arc<WalSenders>, pageserver_id: NodeId) -> bool {
+        self.mutex
+            .lock()
+            .slots
+            .iter()
+            .flatten()
+            .any(|ws| ws.pageserver_id == Some(pageserver_id))
+    }
+
     /// Get aggregated pageserver feedback.
     pub fn get_ps_feedback(self: &Arc<WalSenders>) -> PageserverFeedback {
         self.mutex.lock().agg_ps_feedback
@@ -152,10 +176,30 @@ impl WalSenders {
         (shared.agg_ps_feedback, shared.agg_hs_feedback)
     }
 
+    /// Get the oldest LSN the replicas streaming from us have applied, see
+    /// [`WalSendersShared::standby_horizon`].
+    pub fn get_standby_horizon(self: &Arc<WalSenders>) -> Lsn {
+        self.mutex.lock().standby_horizon()
+    }
+
+    /// Get the oldest remote_consistent_lsn of the pageservers subscribed to
+    /// the timeline, see [`WalSendersShared::subscribers_horizon`].
+    pub fn get_subscribers_horizon(self: &Arc<WalSenders>) -> Option<Lsn> {
+        self.mutex.lock().subscribers_horizon(Instant::now())
+    }
+
     /// Record new pageserver feedback, update aggregated values.
     fn record_ps_feedback(self: &Arc<WalSenders>, id: WalSenderId, feedback: &PageserverFeedback) {
         let mut shared = self.mutex.lock();
-        shared.get_slot_mut(id).feedback = ReplicationFeedback::Pageserver(*feedback);
+        let slot = shared.get_slot_mut(id);
+        slot.feedback = ReplicationFeedback::Pageserver(*feedback);
+        if let Some(pageserver_id) = slot.pageserver_id {
+            shared.record_subscriber_feedback(
+                pageserver_id,
+                feedback.remote_consistent_lsn,
+                Instant::now(),
+            );
+        }
         shared.update_ps_feedback();
         self.update_remote_consistent_lsn(shared.agg_ps_feedback.remote_consistent_lsn);
     }
@@ -217,6 +261,12 @@ impl WalSenders {
     /// Unregister walsender.
     fn unregister(self: &Arc<WalSenders>, id: WalSenderId) {
         let mut shared = self.mutex.lock();
+        if let Some(pageserver_id) = shared.get_slot(id).pageserver_id {
+            // Counting from the disconnection
+            if let Some(subscriber) = shared.subscribers.get_mut(&pageserver_id) {
+                subscriber.last_seen = Instant::now();
+            }
+        }
         shared.slots[id] = None;
         shared.update_hs_feedback();
     }
@@ -228,6 +278,18 @@ struct WalSendersShared {
     // aggregated over all walsenders value
     agg_ps_feedback: PageserverFeedback,
     slots: Vec<Option<WalSenderState>>,
+    /// Pageservers which identified themselves on connection, streaming now
+    /// or recently. Several pageservers, e.g. one per region, can stream the
+    /// same timeline, and each of them needs the WAL until it's uploaded
+    /// layers with it.
+    subscribers: HashMap<NodeId, Subscriber>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Subscriber {
+    remote_consistent_lsn: Lsn,
+    /// When the pageserver last sent feedback or disconnected.
+    last_seen: Instant,
 }
 
 impl WalSendersShared {
@@ -236,9 +298,61 @@ impl WalSendersShared {
             agg_hs_feedback: HotStandbyFeedback::empty(),
             agg_ps_feedback: PageserverFeedback::empty(),
             slots: Vec::new(),
+            subscribers: HashMap::new(),
         }
     }
 
+    fn record_subscriber_feedback(
+        &mut self,
+        pageserver_id: NodeId,
+        remote_consistent_lsn: Lsn,
+        now: Instant,
+    ) {
+        let subscriber = self.subscribers.entry(pageserver_id).or_insert(Subscriber {
+            remote_consistent_lsn,
+            last_seen: now,
+        });
+        subscriber.remote_consistent_lsn =
+            max(subscriber.remote_consistent_lsn, remote_consistent_lsn);
+        subscriber.last_seen = now;
+    }
+
+    /// The oldest remote_consistent_lsn of the subscribers, forgetting the
+    /// ones which have been disconnected for longer than [`SUBSCRIBER_TTL`].
+    /// `None` if there are no subscribers.
+    fn subscribers_horizon(&mut self, now: Instant) -> Option<Lsn> {
+        let slots = &self.slots;
+        self.subscribers.retain(|pageserver_id, subscriber| {
+            slots
+                .iter()
+                .flatten()
+                .any(|ws| ws.pageserver_id == Some(*pageserver_id))
+                || now.duration_since(subscriber.last_seen) < SUBSCRIBER_TTL
+        });
+        self.subscribers
+            .values()
+            .map(|subscriber| subscriber.remote_consistent_lsn)
+            .min()
+    }
+
+    /// The oldest apply LSN of the replicas, [`Lsn::INVALID`] if there are none.
+    /// The pageservers keep the page versions from this LSN on, which the
+    /// queries of the replicas still read, while the primary keeps the tuple
+    /// versions they need from vacuum with the hot standby feedback.
+    fn standby_horizon(&self) -> Lsn {
+        self.slots
+            .iter()
+            .flatten()
+            .filter_map(|ws| match ws.feedback {
+                ReplicationFeedback::Standby(sf) if sf.reply.apply_lsn.is_valid() => {
+                    Some(sf.reply.apply_lsn)
+                }
+                _ => None,
+            })
+            .min()
+            .unwrap_or(Lsn::INVALID)
+    }
+
     /// Get content of provided id slot, it must exist.
     fn get_slot(&self, id: WalSenderId) -> &WalSenderState {
         self.slots[id].as_ref().expect("walsender doesn't exist")
@@ -322,6 +436,10 @@ pub struct WalSenderState {
     conn_id: ConnectionId,
     // postgres application_name
     appname: Option<String>,
+    /// Id of the pageserver, if it is one and it passed its id.
+    pageserver_id: Option<NodeId>,
+    /// Negotiated version of the replication protocol.
+    proto_version: u32,
     feedback: ReplicationFeedback,
 }
 
@@ -361,9 +479,11 @@ impl SafekeeperPostgresHandler {
         pgb: &mut PostgresBackend<IO>,
         start_pos: Lsn,
         term: Option<Term>,
+        proto_version: u32,
+        compression: Option<WalCompression>,
     ) -> Result<(), QueryError> {
         if let Err(end) = self
-            .handle_start_replication_guts(pgb, start_pos, term)
+            .handle_start_replication_guts(pgb, start_pos, term, proto_version, compression)
             .await
         {
             // Log the result and probably send it to the client, closing the stream.
@@ -377,6 +497,8 @@ impl SafekeeperPostgresHandler {
         pgb: &mut PostgresBackend<IO>,
         start_pos: Lsn,
         term: Option<Term>,
+        proto_version: u32,
+        compression: Option<WalCompression>,
     ) -> Result<(), CopyStreamHandlerEnd> {
         let appname = self.appname.clone();
         let tli =
@@ -388,6 +510,8 @@ impl SafekeeperPostgresHandler {
             *pgb.get_peer_addr(),
             self.conn_id,
             self.appname.clone(),
+            self.pageserver_id,
+            proto_version,
         ));
 
         let commit_lsn_watch_rx = tli.get_commit_lsn_watch_rx();
@@ -419,8 +543,8 @@ impl SafekeeperPostgresHandler {
         }
 
         info!(
-            "starting streaming from {:?} till {:?}, available WAL ends at {}",
-            start_pos, stop_pos, end_pos
+            "starting streaming from {:?} till {:?}, available WAL ends at {}, protocol version {}, compression {:?}",
+            start_pos, stop_pos, end_pos, proto_version, compression
         );
 
         // switch to copy
@@ -447,6 +571,7 @@ impl SafekeeperPostgresHandler {
             end_pos,
             stop_pos,
             term,
+            compression,
             commit_lsn_watch_rx,
             ws_guard: ws_guard.clone(),
             wal_reader,
@@ -487,6 +612,8 @@ struct WalSender<'a, IO> {
     /// in. Streaming is stopped if local term changes to a different (higher)
     /// value.
     term: Option<Term>,
+    /// Compression of the data of XLogData messages.
+    compression: Option<WalCompression>,
     commit_lsn_watch_rx: Receiver<Lsn>,
     ws_guard: Arc<WalSenderGuard>,
     wal_reader: WalReader,
@@ -543,13 +670,24 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> WalSender<'_, IO> {
             };
             let send_buf = &send_buf[..send_size];
 
+            // compress it if asked to
+            let compressed;
+            let data = match self.compression {
+                Some(WalCompression::Zstd) => {
+                    compressed = zstd::bulk::compress(send_buf, WAL_COMPRESSION_LEVEL)
+                        .context("failed to compress WAL")?;
+                    &compressed[..]
+                }
+                None => send_buf,
+            };
+
             // and send it
             self.pgb
                 .write_message(&BeMessage::XLogData(XLogDataBody {
                     wal_start: self.start_pos.0,
                     wal_end: self.end_pos.0,
                     timestamp: get_current_timestamp(),
-                    data: send_buf,
+                    data,
                 }))
                 .await?;
 
@@ -717,6 +855,8 @@ mod tests {
             addr: mock_addr(),
             conn_id: 1,
             appname: None,
+            pageserver_id: None,
+            proto_version: 1,
             feedback,
         };
         wss.slots.push(Some(walsender_state))
@@ -735,6 +875,29 @@ mod tests {
         })
     }
 
+    fn standby_reply(apply_lsn: Lsn) -> ReplicationFeedback {
+        ReplicationFeedback::Standby(StandbyFeedback {
+            reply: StandbyReply {
+                apply_lsn,
+                ..StandbyReply::empty()
+            },
+            hs_feedback: HotStandbyFeedback::empty(),
+        })
+    }
+
+    #[test]
+    fn test_standby_horizon() {
+        let mut wss = WalSendersShared::new();
+        push_feedback(&mut wss, ps_feedback(0, Lsn(0x1000)));
+        assert_eq!(wss.standby_horizon(), Lsn::INVALID);
+
+        push_feedback(&mut wss, standby_reply(Lsn(0x3000)));
+        push_feedback(&mut wss, standby_reply(Lsn(0x2000)));
+        // not replied yet
+        push_feedback(&mut wss, standby_reply(Lsn::INVALID));
+        assert_eq!(wss.standby_horizon(), Lsn(0x2000));
+    }
+
     // test that hs aggregation works as expected
     #[test]
     fn test_hs_feedback_no_valid() {
@@ -776,4 +939,25 @@ mod tests {
         assert_eq!(wss.agg_ps_feedback.current_timeline_size, 4);
         assert_eq!(wss.agg_ps_feedback.last_received_lsn, Lsn(84));
     }
+
+    #[test]
+    fn test_subscribers_horizon() {
+        let mut wss = WalSendersShared::new();
+        let now = Instant::now();
+        assert_eq!(wss.subscribers_horizon(now), None);
+
+        wss.record_subscriber_feedback(NodeId(1), Lsn(0x3000), now);
+        wss.record_subscriber_feedback(NodeId(2), Lsn(0x1000), now);
+        wss.record_subscriber_feedback(NodeId(2), Lsn(0x2000), now);
+        assert_eq!(wss.subscribers_horizon(now), Some(Lsn(0x2000)));
+
+        // A connected subscriber is never forgotten
+        push_feedback(&mut wss, ps_feedback(0, Lsn(0x3000)));
+        wss.slots[0].as_mut().unwrap().pageserver_id = Some(NodeId(2));
+        let later = now + SUBSCRIBER_TTL;
+        assert_eq!(wss.subscribers_horizon(later), Some(Lsn(0x2000)));
+
+        wss.slots[0] = None;
+        assert_eq!(wss.subscribers_horizon(later), None);
+    }
 }
@@ -5,7 +5,7 @@ use anyhow::{anyhow, bail, Result};
 use postgres_ffi::XLogSegNo;
 use tokio::fs;
 
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::{Mutex, MutexGuard};
@@ -18,45 +18,51 @@ use utils::http::error::ApiError;
 use utils::{
     id::{NodeId, TenantTimelineId},
     lsn::Lsn,
+    task_scope::TaskScope,
 };
 
 use storage_broker::proto::SafekeeperTimelineInfo;
 use storage_broker::proto::TenantTimelineId as ProtoTenantTimelineId;
 
 use crate::safekeeper::{
-    AcceptorProposerMessage, ProposerAcceptorMessage, SafeKeeper, SafeKeeperState,
+    AcceptorProposerMessage, PgUuid, ProposerAcceptorMessage, SafeKeeper, SafeKeeperState,
     SafekeeperMemState, ServerInfo, Term,
 };
 use crate::send_wal::WalSenders;
 use crate::{control_file, safekeeper::UNKNOWN_SERVER_VERSION};
 
 use crate::metrics::FullTimelineInfo;
+use crate::wal_backup::LauncherWakeup;
+use crate::wal_forward::WalForwardState;
 use crate::wal_storage::Storage as wal_storage_iface;
-use crate::SafeKeeperConf;
 use crate::{debug_dump, wal_storage};
+use crate::{SafeKeeperConf, TIMELINE_TASKS};
 
 /// Things safekeeper should know about timeline state on peers.
 #[derive(Debug, Clone)]
 pub struct PeerInfo {
     pub sk_id: NodeId,
+    /// Postgres address the peer advertises.
+    pub pg_connstr: String,
     /// Term of the last entry.
-    _last_log_term: Term,
+    pub last_log_term: Term,
     /// LSN of the last record.
-    _flush_lsn: Lsn,
+    pub flush_lsn: Lsn,
     pub commit_lsn: Lsn,
     /// Since which LSN safekeeper has WAL. TODO: remove this once we fill new
     /// sk since backup_lsn.
     pub local_start_lsn: Lsn,
     /// When info was received.
-    ts: Instant,
+    pub ts: Instant,
 }
 
 impl PeerInfo {
     fn from_sk_info(sk_info: &SafekeeperTimelineInfo, ts: Instant) -> PeerInfo {
         PeerInfo {
             sk_id: NodeId(sk_info.safekeeper_id),
-            _last_log_term: sk_info.last_log_term,
-            _flush_lsn: Lsn(sk_info.flush_lsn),
+            pg_connstr: sk_info.safekeeper_connstr.clone(),
+            last_log_term: sk_info.last_log_term,
+            flush_lsn: Lsn(sk_info.flush_lsn),
             commit_lsn: Lsn(sk_info.commit_lsn),
             local_start_lsn: Lsn(sk_info.local_start_lsn),
             ts,
@@ -102,6 +108,9 @@ pub struct SharedState {
     active: bool,
     num_computes: u32,
     last_removed_segno: XLogSegNo,
+    /// Whether the in-progress segment is offloaded too, see
+    /// [`SafeKeeperConf::partial_backup_interval`].
+    partial_backup_enabled: bool,
 }
 
 impl SharedState {
@@ -141,6 +150,7 @@ impl SharedState {
             active: false,
             num_computes: 0,
             last_removed_segno: 0,
+            partial_backup_enabled: conf.partial_backup_interval.is_some(),
         })
     }
 
@@ -161,14 +171,13 @@ impl SharedState {
             active: false,
             num_computes: 0,
             last_removed_segno: 0,
+            partial_backup_enabled: conf.partial_backup_interval.is_some(),
         })
     }
 
+    /// `remote_consistent_lsn` is the oldest of the pageservers we know of.
     fn is_active(&self, remote_consistent_lsn: Lsn) -> bool {
-        self.is_wal_backup_required()
-            // FIXME: add tracking of relevant pageservers and check them here individually,
-            // otherwise migration won't work (we suspend too early).
-            || remote_consistent_lsn < self.sk.inmem.commit_lsn
+        self.is_wal_backup_required() || remote_consistent_lsn < self.sk.inmem.commit_lsn
     }
 
     /// Mark timeline active/inactive and return whether s3 offloading requires
@@ -185,8 +194,11 @@ impl SharedState {
     /// Should we run s3 offloading in current state?
     fn is_wal_backup_required(&self) -> bool {
         let seg_size = self.get_wal_seg_size();
+        if self.partial_backup_enabled {
+            return self.num_computes > 0 || self.sk.inmem.commit_lsn > self.sk.inmem.backup_lsn;
+        }
         self.num_computes > 0 ||
-        // Currently only the whole segment is offloaded, so compare segment numbers.
+        // Otherwise only the whole segment is offloaded, so compare segment numbers.
                (self.sk.inmem.commit_lsn.segment_number(seg_size) >
                 self.sk.inmem.backup_lsn.segment_number(seg_size))
     }
@@ -224,6 +236,7 @@ impl SharedState {
         ttid: &TenantTimelineId,
         conf: &SafeKeeperConf,
         remote_consistent_lsn: Lsn,
+        standby_horizon: Lsn,
     ) -> SafekeeperTimelineInfo {
         SafekeeperTimelineInfo {
             safekeeper_id: conf.my_id.0,
@@ -244,6 +257,7 @@ impl SharedState {
             backup_lsn: self.sk.inmem.backup_lsn.0,
             local_start_lsn: self.sk.state.local_start_lsn.0,
             availability_zone: conf.availability_zone.clone(),
+            standby_horizon: standby_horizon.0,
         }
     }
 }
@@ -258,6 +272,8 @@ pub enum TimelineError {
     Invalid(TenantTimelineId),
     #[error("Timeline {0} is already exists")]
     AlreadyExists(TenantTimelineId),
+    #[error("Timeline {0} was deleted")]
+    Deleted(TenantTimelineId),
     #[error("Timeline {0} is not initialized, wal_seg_size is zero")]
     UninitializedWalSegSize(TenantTimelineId),
     #[error("Timeline {0} is not initialized, pg_version is unknown")]
@@ -271,6 +287,9 @@ impl From<TimelineError> for ApiError {
             TimelineError::NotFound(ttid) => {
                 ApiError::NotFound(anyhow!("timeline {} not found", ttid).into())
             }
+            TimelineError::Deleted(ttid) => {
+                ApiError::Conflict(format!("timeline {} was deleted", ttid))
+            }
             _ => ApiError::InternalServerError(anyhow!("{}", te)),
         }
     }
@@ -281,10 +300,10 @@ impl From<TimelineError> for ApiError {
 pub struct Timeline {
     pub ttid: TenantTimelineId,
 
-    /// Sending here asks for wal backup launcher attention (start/stop
-    /// offloading). Sending ttid instead of concrete command allows to do
-    /// sending without timeline lock.
-    pub wal_backup_launcher_tx: Sender<TenantTimelineId>,
+    /// Asks for wal backup launcher attention (start/stop offloading). The
+    /// launcher gets the ttid instead of a concrete command, which allows to wake
+    /// it up without timeline lock.
+    pub wal_backup_wakeup: LauncherWakeup,
 
     /// Used to broadcast commit_lsn updates to all background jobs.
     commit_lsn_watch_tx: watch::Sender<Lsn>,
@@ -296,12 +315,14 @@ pub struct Timeline {
     mutex: Mutex<SharedState>,
     walsenders: Arc<WalSenders>,
 
-    /// Cancellation channel. Delete/cancel will send `true` here as a cancellation signal.
-    cancellation_tx: watch::Sender<bool>,
+    /// Background tasks of the timeline. Delete/cancel cancels it, and the timeline
+    /// should not be used after that. The tasks should stop soon after the
+    /// cancellation.
+    tasks: TaskScope,
 
-    /// Timeline should not be used after cancellation. Background tasks should
-    /// monitor this channel and stop eventually after receiving `true` from this channel.
-    cancellation_rx: watch::Receiver<bool>,
+    /// Forwarding of the WAL to the safekeepers of another region, see
+    /// [`crate::wal_forward`].
+    pub wal_forward: std::sync::Mutex<WalForwardState>,
 
     /// Directory where timeline state is stored.
     pub timeline_dir: PathBuf,
@@ -320,17 +341,16 @@ impl Timeline {
         let rcl = shared_state.sk.state.remote_consistent_lsn;
         let (commit_lsn_watch_tx, commit_lsn_watch_rx) =
             watch::channel(shared_state.sk.state.commit_lsn);
-        let (cancellation_tx, cancellation_rx) = watch::channel(false);
 
         Ok(Timeline {
             ttid,
-            wal_backup_launcher_tx,
+            wal_backup_wakeup: LauncherWakeup::new(ttid, wal_backup_launcher_tx),
             commit_lsn_watch_tx,
             commit_lsn_watch_rx,
             mutex: Mutex::new(shared_state),
             walsenders: WalSenders::new(rcl),
-            cancellation_rx,
-            cancellation_tx,
+            tasks: TIMELINE_TASKS.child(format!("timeline {ttid}")),
+            wal_forward: Default::default(),
             timeline_dir: conf.timeline_dir(&ttid),
         })
     }
@@ -345,18 +365,17 @@ impl Timeline {
         local_start_lsn: Lsn,
     ) -> Result<Timeline> {
         let (commit_lsn_watch_tx, commit_lsn_watch_rx) = watch::channel(Lsn::INVALID);
-        let (cancellation_tx, cancellation_rx) = watch::channel(false);
         let state = SafeKeeperState::new(&ttid, server_info, vec![], commit_lsn, local_start_lsn);
 
         Ok(Timeline {
             ttid,
-            wal_backup_launcher_tx,
+            wal_backup_wakeup: LauncherWakeup::new(ttid, wal_backup_launcher_tx),
             commit_lsn_watch_tx,
             commit_lsn_watch_rx,
             mutex: Mutex::new(SharedState::create_new(&conf, &ttid, state)?),
             walsenders: WalSenders::new(Lsn(0)),
-            cancellation_rx,
-            cancellation_tx,
+            tasks: TIMELINE_TASKS.child(format!("timeline {ttid}")),
+            wal_forward: Default::default(),
             timeline_dir: conf.timeline_dir(&ttid),
         })
     }
@@ -403,7 +422,7 @@ impl Timeline {
     }
 
     /// Delete timeline from disk completely, by removing timeline directory. Background
-    /// timeline activities will stop eventually.
+    /// timeline activities will stop eventually, see [`Timeline::tasks`].
     pub async fn delete_from_disk(
         &self,
         shared_state: &mut MutexGuard<'_, SharedState>,
@@ -424,7 +443,7 @@ impl Timeline {
     /// time.
     fn cancel(&self, shared_state: &mut MutexGuard<'_, SharedState>) {
         info!("timeline {} is cancelled", self.ttid);
-        let _ = self.cancellation_tx.send(true);
+        self.tasks.cancel();
         // Close associated FDs. Nobody will be able to touch timeline data once
         // it is cancelled, so WAL storage won't be opened again.
         shared_state.sk.wal_store.close();
@@ -432,7 +451,13 @@ impl Timeline {
 
     /// Returns if timeline is cancelled.
     pub fn is_cancelled(&self) -> bool {
-        *self.cancellation_rx.borrow()
+        self.tasks.is_cancelled()
+    }
+
+    /// The scope of the background tasks of the timeline, which are cancelled when
+    /// the timeline is, and waited for when it's deleted.
+    pub fn tasks(&self) -> &TaskScope {
+        &self.tasks
     }
 
     /// Take a writing mutual exclusive lock on timeline shared_state.
@@ -441,7 +466,13 @@ impl Timeline {
     }
 
     fn update_status(&self, shared_state: &mut SharedState) -> bool {
-        shared_state.update_status(self.get_walsenders().get_remote_consistent_lsn(), self.ttid)
+        // Stay active until all the pageservers streaming from us caught up
+        let walsenders = self.get_walsenders();
+        let mut remote_consistent_lsn = walsenders.get_remote_consistent_lsn();
+        if let Some(subscribers_lsn) = walsenders.get_subscribers_horizon() {
+            remote_consistent_lsn = min(remote_consistent_lsn, subscribers_lsn);
+        }
+        shared_state.update_status(remote_consistent_lsn, self.ttid)
     }
 
     /// Register compute connection, starting timeline-related activity if it is
@@ -459,8 +490,7 @@ impl Timeline {
         }
         // Wake up wal backup launcher, if offloading not started yet.
         if is_wal_backup_action_pending {
-            // Can fail only if channel to a static thread got closed, which is not normal at all.
-            self.wal_backup_launcher_tx.send(self.ttid).await?;
+            self.wal_backup_wakeup.wake()?;
         }
         Ok(())
     }
@@ -476,8 +506,7 @@ impl Timeline {
         }
         // Wake up wal backup launcher, if it is time to stop the offloading.
         if is_wal_backup_action_pending {
-            // Can fail only if channel to a static thread got closed, which is not normal at all.
-            self.wal_backup_launcher_tx.send(self.ttid).await?;
+            self.wal_backup_wakeup.wake()?;
         }
         Ok(())
     }
@@ -519,6 +548,7 @@ impl Timeline {
             return false;
         }
 
+        self.wal_backup_wakeup.received();
         self.write_shared_state().await.wal_backup_attend()
     }
 
@@ -556,6 +586,24 @@ impl Timeline {
         Ok(rmsg)
     }
 
+    /// Remembers the walproposer elected in the current term, see
+    /// [`SafeKeeper::record_election`].
+    pub async fn record_election(
+        &self,
+        term: Term,
+        proposer_uuid: PgUuid,
+        proposer_addr: String,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            bail!(TimelineError::Cancelled(self.ttid));
+        }
+        let mut shared_state = self.write_shared_state().await;
+        shared_state
+            .sk
+            .record_election(term, proposer_uuid, proposer_addr)
+            .await
+    }
+
     /// Returns wal_seg_size.
     pub async fn get_wal_seg_size(&self) -> usize {
         self.write_shared_state().await.get_wal_seg_size()
@@ -601,6 +649,7 @@ impl Timeline {
             &self.ttid,
             conf,
             self.walsenders.get_remote_consistent_lsn(),
+            self.walsenders.get_standby_horizon(),
         )
     }
 
@@ -625,7 +674,7 @@ impl Timeline {
         self.commit_lsn_watch_tx.send(commit_lsn)?;
         // Wake up wal backup launcher, if it is time to stop the offloading.
         if is_wal_backup_action_pending {
-            self.wal_backup_launcher_tx.send(self.ttid).await?;
+            self.wal_backup_wakeup.wake()?;
         }
         Ok(())
     }
@@ -655,6 +704,18 @@ impl Timeline {
         self.write_shared_state().await.sk.wal_store.flush_lsn()
     }
 
+    /// Returns the amount of WAL that can't be removed yet.
+    pub async fn get_retained_wal_bytes(&self, wal_backup_enabled: bool) -> u64 {
+        let subscribers_lsn = self.walsenders.get_subscribers_horizon();
+        let state = self.write_shared_state().await;
+        let horizon_lsn = state
+            .sk
+            .get_wal_retention(wal_backup_enabled, subscribers_lsn)
+            .horizon_lsn();
+        let flush_lsn = state.sk.wal_store.flush_lsn();
+        flush_lsn.0.saturating_sub(horizon_lsn.0)
+    }
+
     /// Delete WAL segments from disk that are no longer needed. This is determined
     /// based on pageserver's remote_consistent_lsn and local backup_lsn/peer_lsn.
     pub async fn remove_old_wal(&self, wal_backup_enabled: bool) -> Result<()> {
@@ -663,9 +724,13 @@ impl Timeline {
         }
 
         let horizon_segno: XLogSegNo;
+        let subscribers_lsn = self.walsenders.get_subscribers_horizon();
         let remover = {
             let shared_state = self.write_shared_state().await;
-            horizon_segno = shared_state.sk.get_horizon_segno(wal_backup_enabled);
+            horizon_segno = shared_state
+                .sk
+                .get_wal_retention(wal_backup_enabled, subscribers_lsn)
+                .horizon_segno(shared_state.get_wal_seg_size());
             if horizon_segno <= 1 || horizon_segno <= shared_state.last_removed_segno {
                 return Ok(()); // nothing to do
             }
@@ -698,12 +763,13 @@ impl Timeline {
 
     /// Gather timeline data for metrics. If the timeline is not active, returns
     /// None, we do not collect these.
-    pub async fn info_for_metrics(&self) -> Option<FullTimelineInfo> {
+    pub async fn info_for_metrics(&self, conf: &SafeKeeperConf) -> Option<FullTimelineInfo> {
         if self.is_cancelled() {
             return None;
         }
 
         let ps_feedback = self.walsenders.get_ps_feedback();
+        let subscribers_lsn = self.walsenders.get_subscribers_horizon();
         let state = self.write_shared_state().await;
         if state.active {
             Some(FullTimelineInfo {
@@ -718,6 +784,18 @@ impl Timeline {
                 persisted_state: state.sk.state.clone(),
                 flush_lsn: state.sk.wal_store.flush_lsn(),
                 remote_consistent_lsn: self.get_walsenders().get_remote_consistent_lsn(),
+                wal_retention: state
+                    .sk
+                    .get_wal_retention(conf.is_wal_backup_enabled(), subscribers_lsn),
+                peers: state
+                    .peers_info
+                    .0
+                    .iter()
+                    // Our own info comes through the broker as well
+                    .filter(|p| p.sk_id != conf.my_id)
+                    .filter(|p| p.ts.elapsed() <= conf.heartbeat_timeout)
+                    .cloned()
+                    .collect(),
                 wal_storage: state.sk.wal_store.get_metrics(),
             })
         } else {
@@ -735,6 +813,19 @@ impl Timeline {
         debug_dump::Memory {
             is_cancelled: self.is_cancelled(),
             peers_info_len: state.peers_info.0.len(),
+            peers: state
+                .peers_info
+                .0
+                .iter()
+                .map(|p| debug_dump::Peer {
+                    sk_id: p.sk_id,
+                    last_log_term: p.last_log_term,
+                    flush_lsn: p.flush_lsn,
+                    commit_lsn: p.commit_lsn,
+                    local_start_lsn: p.local_start_lsn,
+                    last_heard_secs_ago: p.ts.elapsed().as_secs_f64(),
+                })
+                .collect(),
             walsenders: self.walsenders.get_all(),
             wal_backup_active: state.wal_backup_active,
             active: state.active,
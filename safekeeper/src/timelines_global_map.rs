@@ -2,23 +2,55 @@
 //! All timelines should always be present in this map, this is done by loading them
 //! all from the disk on startup and keeping them in memory.
 
+use crate::disk_usage;
 use crate::safekeeper::ServerInfo;
 use crate::timeline::{Timeline, TimelineError};
+use crate::wal_verify;
 use crate::SafeKeeperConf;
 use anyhow::{bail, Context, Result};
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::Sender;
 use tracing::*;
+use utils::checksummed_writer::SyncPolicy;
+use utils::crashsafe;
 use utils::id::{TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
+/// Timelines deleted with a tombstone, in the workdir.
+const TOMBSTONES_FILE_NAME: &str = "timeline_tombstones.json";
+
+/// Marks a deleted timeline, so that a walproposer that missed the deletion
+/// can't create it again.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub ttid: TenantTimelineId,
+    pub deleted_at: SystemTime,
+}
+
+/// Drops the tombstones older than `ttl`: the walproposers that missed the
+/// deletion are long gone by then.
+fn prune_tombstones(
+    tombstones: &mut HashMap<TenantTimelineId, Tombstone>,
+    ttl: Duration,
+    now: SystemTime,
+) {
+    tombstones.retain(|_, tombstone| {
+        // A tombstone from the future, after a clock jump, is kept
+        now.duration_since(tombstone.deleted_at)
+            .map_or(true, |age| age < ttl)
+    });
+}
+
 struct GlobalTimelinesState {
     timelines: HashMap<TenantTimelineId, Arc<Timeline>>,
+    tombstones: HashMap<TenantTimelineId, Tombstone>,
     wal_backup_launcher_tx: Option<Sender<TenantTimelineId>>,
     conf: Option<SafeKeeperConf>,
 }
@@ -39,9 +71,13 @@ impl GlobalTimelinesState {
         )
     }
 
-    /// Insert timeline into the map. Returns error if timeline with the same id already exists.
+    /// Insert timeline into the map. Returns error if timeline with the same id already exists,
+    /// or was deleted with a tombstone.
     fn try_insert(&mut self, timeline: Arc<Timeline>) -> Result<()> {
         let ttid = timeline.ttid;
+        if self.tombstones.contains_key(&ttid) {
+            bail!(TimelineError::Deleted(ttid));
+        }
         if self.timelines.contains_key(&ttid) {
             bail!(TimelineError::AlreadyExists(ttid));
         }
@@ -49,6 +85,20 @@ impl GlobalTimelinesState {
         Ok(())
     }
 
+    fn load_tombstones(&mut self) -> Result<()> {
+        let path = self.get_conf().workdir.join(TOMBSTONES_FILE_NAME);
+        let tombstones: Vec<Tombstone> = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        self.tombstones = tombstones.into_iter().map(|t| (t.ttid, t)).collect();
+        let ttl = self.get_conf().timeline_tombstone_ttl;
+        prune_tombstones(&mut self.tombstones, ttl, SystemTime::now());
+        Ok(())
+    }
+
     /// Get timeline from the map. Returns error if timeline doesn't exist.
     fn get(&self, ttid: &TenantTimelineId) -> Result<Arc<Timeline>, TimelineError> {
         self.timelines
@@ -61,11 +111,16 @@ impl GlobalTimelinesState {
 static TIMELINES_STATE: Lazy<Mutex<GlobalTimelinesState>> = Lazy::new(|| {
     Mutex::new(GlobalTimelinesState {
         timelines: HashMap::new(),
+        tombstones: HashMap::new(),
         wal_backup_launcher_tx: None,
         conf: None,
     })
 });
 
+/// Serializes the rewrites of the tombstones file, which are done out of
+/// [`TIMELINES_STATE`], not to block the access to all the timelines on the fsyncs.
+static TOMBSTONES_FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
 /// A zero-sized struct used to manage access to the global timelines map.
 pub struct GlobalTimelines;
 
@@ -79,6 +134,7 @@ impl GlobalTimelines {
         assert!(state.wal_backup_launcher_tx.is_none());
         state.wal_backup_launcher_tx = Some(wal_backup_launcher_tx);
         state.conf = Some(conf);
+        state.load_tombstones()?;
 
         // Iterate through all directories and load tenants for all directories
         // named as a valid tenant_id.
@@ -136,6 +192,18 @@ impl GlobalTimelines {
                         TimelineId::from_str(timeline_dir_entry.file_name().to_str().unwrap_or(""))
                     {
                         let ttid = TenantTimelineId::new(tenant_id, timeline_id);
+                        if state.tombstones.contains_key(&ttid) {
+                            // The deletion was interrupted, finish it
+                            info!("removing the directory of deleted timeline {}", ttid);
+                            delete_dir(state.get_conf().timeline_dir(&ttid))?;
+                            continue;
+                        }
+                        if state.get_conf().verify_wal_on_startup {
+                            let _enter = info_span!("verify_wal", ttid = %ttid).entered();
+                            if let Err(e) = wal_verify::verify_timeline(state.get_conf(), &ttid) {
+                                error!("failed to verify WAL: {:?}", e);
+                            }
+                        }
                         match Timeline::load_timeline(
                             state.get_conf().clone(),
                             ttid,
@@ -169,7 +237,13 @@ impl GlobalTimelines {
 
     /// Load timeline from disk to the memory.
     pub fn load_timeline(ttid: TenantTimelineId) -> Result<Arc<Timeline>> {
-        let (conf, wal_backup_launcher_tx) = TIMELINES_STATE.lock().unwrap().get_dependencies();
+        let (conf, wal_backup_launcher_tx) = {
+            let state = TIMELINES_STATE.lock().unwrap();
+            if state.tombstones.contains_key(&ttid) {
+                bail!(TimelineError::Deleted(ttid));
+            }
+            state.get_dependencies()
+        };
 
         match Timeline::load_timeline(conf, ttid, wal_backup_launcher_tx) {
             Ok(timeline) => {
@@ -213,6 +287,7 @@ impl GlobalTimelines {
             }
             state.get_dependencies()
         };
+        disk_usage::check_new_timeline(&conf)?;
 
         info!("creating new timeline {}", ttid);
 
@@ -257,7 +332,7 @@ impl GlobalTimelines {
             // We are done with bootstrap, release the lock, return the timeline.
             // {} block forces release before .await
         }
-        timeline.wal_backup_launcher_tx.send(timeline.ttid).await?;
+        timeline.wal_backup_wakeup.wake()?;
         Ok(timeline)
     }
 
@@ -301,10 +376,69 @@ impl GlobalTimelines {
             .collect()
     }
 
+    /// Returns the tombstone of the timeline, if it was deleted with one.
+    pub fn get_tombstone(ttid: &TenantTimelineId) -> Option<Tombstone> {
+        TIMELINES_STATE
+            .lock()
+            .unwrap()
+            .tombstones
+            .get(ttid)
+            .copied()
+    }
+
+    /// Durably rewrites the tombstones file with the tombstones in memory, pruning
+    /// the expired ones.
+    fn persist_tombstones() -> Result<()> {
+        let _file_lock = TOMBSTONES_FILE_LOCK.lock().unwrap();
+        // Taken under the file lock, so that a rewrite never loses the tombstones
+        // of an earlier one
+        let (path, no_sync, tombstones) = {
+            let mut state = TIMELINES_STATE.lock().unwrap();
+            let conf = state.get_conf();
+            let (path, no_sync) = (conf.workdir.join(TOMBSTONES_FILE_NAME), conf.no_sync);
+            let ttl = conf.timeline_tombstone_ttl;
+            prune_tombstones(&mut state.tombstones, ttl, SystemTime::now());
+            let tombstones = state.tombstones.values().copied().collect::<Vec<_>>();
+            (path, no_sync, tombstones)
+        };
+
+        let temp_path = crashsafe::path_with_suffix_extension(&path, "partial");
+        let mut file = std::fs::File::create(&temp_path)
+            .with_context(|| format!("failed to create {}", temp_path.display()))?;
+        file.write_all(&serde_json::to_vec(&tombstones)?)?;
+        SyncPolicy::All.unless(no_sync).sync(&file)?;
+        std::fs::rename(&temp_path, &path)?;
+        if !no_sync {
+            crashsafe::fsync_file_and_parent(&path)?;
+        }
+        Ok(())
+    }
+
     /// Cancels timeline, then deletes the corresponding data directory.
-    pub async fn delete_force(ttid: &TenantTimelineId) -> Result<TimelineDeleteForceResult> {
-        let tli_res = TIMELINES_STATE.lock().unwrap().get(ttid);
-        match tli_res {
+    ///
+    /// With `tombstone`, the timeline can't be created again: a walproposer
+    /// that still tries to write to it gets an error, even after a restart.
+    pub async fn delete_force(
+        ttid: &TenantTimelineId,
+        tombstone: bool,
+    ) -> Result<TimelineDeleteForceResult> {
+        let tli_res = {
+            let mut state = TIMELINES_STATE.lock().unwrap();
+            if tombstone {
+                // Before the deletion, so that the timeline can't be recreated
+                // concurrently
+                state.tombstones.entry(*ttid).or_insert(Tombstone {
+                    ttid: *ttid,
+                    deleted_at: SystemTime::now(),
+                });
+            }
+            state.get(ttid)
+        };
+        if tombstone {
+            // Also on a retry, the tombstone may be in memory only
+            Self::persist_tombstones()?;
+        }
+        let result = match tli_res {
             Ok(timeline) => {
                 // Take a lock and finish the deletion holding this mutex.
                 let mut shared_state = timeline.write_shared_state().await;
@@ -312,6 +446,9 @@ impl GlobalTimelines {
                 info!("deleting timeline {}", ttid);
                 let (dir_existed, was_active) =
                     timeline.delete_from_disk(&mut shared_state).await?;
+                // The tasks may need the state to stop
+                drop(shared_state);
+                timeline.tasks().shutdown().await;
 
                 // Remove timeline from the map.
                 // FIXME: re-enable it once we fix the issue with recreation of deleted timelines
@@ -337,7 +474,12 @@ impl GlobalTimelines {
                     was_active: false,
                 })
             }
+        };
+        // The tombstone prevents the recreation the FIXME above is about
+        if tombstone && result.is_ok() {
+            TIMELINES_STATE.lock().unwrap().timelines.remove(ttid);
         }
+        result
     }
 
     /// Deactivates and deletes all timelines for the tenant. Returns map of all timelines which
@@ -354,7 +496,7 @@ impl GlobalTimelines {
 
         let mut deleted = HashMap::new();
         for tli in &to_delete {
-            match Self::delete_force(&tli.ttid).await {
+            match Self::delete_force(&tli.ttid, false).await {
                 Ok(result) => {
                     deleted.insert(tli.ttid, result);
                 }
@@ -412,3 +554,32 @@ fn delete_dir(path: PathBuf) -> Result<bool> {
         Err(e) => Err(e.into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_tombstones() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let tombstone = |deleted_at| Tombstone {
+            ttid: TenantTimelineId::generate(),
+            deleted_at,
+        };
+        let (old, recent, future) = (
+            tombstone(now - 8 * day),
+            tombstone(now - day),
+            tombstone(now + day),
+        );
+        let mut tombstones = [old, recent, future]
+            .into_iter()
+            .map(|t| (t.ttid, t))
+            .collect::<HashMap<_, _>>();
+
+        prune_tombstones(&mut tombstones, 7 * day, now);
+        assert!(!tombstones.contains_key(&old.ttid));
+        assert!(tombstones.contains_key(&recent.ttid));
+        assert!(tombstones.contains_key(&future.ttid));
+    }
+}
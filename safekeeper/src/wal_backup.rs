@@ -1,39 +1,145 @@
+//! Offloading WAL to the remote storage.
+//!
+//! Full segments are uploaded under their usual names, e.g.
+//! `000000010000000000000001`, by the safekeeper elected for offloading
+//! among the peers. With [`SafeKeeperConf::partial_backup_interval`], the
+//! in-progress segment is uploaded too, as `000000010000000000000002.partial`
+//! holding the segment from its start up to commit_lsn at the time of the
+//! upload: the size of the object tells where its WAL ends. Each upload
+//! replaces the previous one, and the object is removed once the full segment
+//! is uploaded. `backup_lsn` then points into the in-progress segment.
+
 use anyhow::{Context, Result};
 
 use futures::stream::FuturesOrdered;
 use futures::StreamExt;
-use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use utils::id::NodeId;
+use utils::task_scope::TaskScope;
 
-use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use postgres_ffi::v14::xlog_utils::XLogSegNoOffsetToRecPtr;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::{XLogSegNo, PG_TLI};
 use remote_storage::{GenericRemoteStorage, RemotePath};
 use tokio::fs::File;
+use tokio::io::AsyncReadExt;
 
 use tokio::select;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
 use tokio::sync::watch;
-use tokio::time::sleep;
+use tokio::time::{sleep, sleep_until};
 use tracing::*;
 
+use utils::backoff::Backoff;
+use utils::rate_limit::RateLimit;
 use utils::{id::TenantTimelineId, lsn::Lsn};
 
-use crate::metrics::{BACKED_UP_SEGMENTS, BACKUP_ERRORS};
+use crate::metrics::{
+    BACKED_UP_PARTIAL_SEGMENTS, BACKED_UP_SEGMENTS, BACKUP_ERRORS, WAL_BACKUP_LAUNCHER_WAKEUPS,
+};
 use crate::timeline::{PeerInfo, Timeline};
+use crate::wal_storage::wal_file_paths;
 use crate::{GlobalTimelines, SafeKeeperConf};
 
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+
+/// Delays before retrying a failed upload, with jitter, as all the timelines of the
+/// safekeeper are likely to fail at the same time.
+const UPLOAD_FAILURE_BACKOFF: Backoff =
+    Backoff::new(Duration::from_millis(20), Duration::from_secs(5)).with_jitter(0.2);
+
+/// Minimum interval between two wake-ups of the launcher sent for a timeline. The
+/// launcher gets to the more frequent ones on its next tick.
+const MIN_WAKEUP_INTERVAL: Duration = Duration::from_millis(CHECK_TASKS_INTERVAL_MSEC);
+
+/// Timelines whose wake-up of the launcher was rate limited or dropped, for the
+/// launcher to get to on its next tick.
+static DEFERRED_WAKEUPS: Lazy<Mutex<HashSet<TenantTimelineId>>> = Lazy::new(Default::default);
+
+/// Wakes up the WAL backup launcher for a timeline, without blocking.
+///
+/// A wake-up already queued for the timeline covers the next ones, as the launcher
+/// looks at the state of the timeline when it gets to it, so when many computes
+/// reconnect at once, the timeline is queued once. The wake-ups are sent at most
+/// once per [`MIN_WAKEUP_INTERVAL`] for each timeline, and when the channel is full
+/// anyway, the wake-up is dropped rather than blocking the connection. The launcher
+/// gets to the rate limited and dropped wake-ups on its next tick.
+pub struct LauncherWakeup {
+    ttid: TenantTimelineId,
+    tx: Sender<TenantTimelineId>,
+    queued: AtomicBool,
+    /// When the last wake-up was sent.
+    last_sent: Mutex<Option<Instant>>,
+}
 
-const UPLOAD_FAILURE_RETRY_MIN_MS: u64 = 10;
-const UPLOAD_FAILURE_RETRY_MAX_MS: u64 = 5000;
+impl LauncherWakeup {
+    pub fn new(ttid: TenantTimelineId, tx: Sender<TenantTimelineId>) -> Self {
+        LauncherWakeup {
+            ttid,
+            tx,
+            queued: AtomicBool::new(false),
+            last_sent: Mutex::new(None),
+        }
+    }
+
+    pub fn wake(&self) -> Result<()> {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            WAL_BACKUP_LAUNCHER_WAKEUPS
+                .with_label_values(&["deduplicated"])
+                .inc();
+            return Ok(());
+        }
+        let mut last_sent = self.last_sent.lock().unwrap();
+        if last_sent.is_some_and(|at| at.elapsed() < MIN_WAKEUP_INTERVAL) {
+            self.defer();
+            WAL_BACKUP_LAUNCHER_WAKEUPS
+                .with_label_values(&["rate_limited"])
+                .inc();
+            return Ok(());
+        }
+        match self.tx.try_send(self.ttid) {
+            Ok(()) => {
+                *last_sent = Some(Instant::now());
+                WAL_BACKUP_LAUNCHER_WAKEUPS
+                    .with_label_values(&["sent"])
+                    .inc();
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.defer();
+                WAL_BACKUP_LAUNCHER_WAKEUPS
+                    .with_label_values(&["dropped"])
+                    .inc();
+                static LOG_RATE_LIMIT: Lazy<Mutex<RateLimit>> =
+                    Lazy::new(|| Mutex::new(RateLimit::new(Duration::from_secs(10))));
+                LOG_RATE_LIMIT.lock().unwrap().call(|| {
+                    warn!("WAL backup launcher channel is full, dropping wake-ups");
+                });
+                Ok(())
+            }
+            // Can fail only if channel to a static thread got closed, which is not normal at all.
+            Err(TrySendError::Closed(_)) => anyhow::bail!("WAL backup launcher channel closed"),
+        }
+    }
+
+    /// Leaves the wake-up to the next tick of the launcher. The timeline stays queued
+    /// until the launcher gets to it, so the wake-ups in between are deduplicated.
+    fn defer(&self) {
+        DEFERRED_WAKEUPS.lock().unwrap().insert(self.ttid);
+    }
+
+    /// Called by the launcher when it gets to the timeline, before it looks at its state,
+    /// so that the changes after that wake it up again.
+    pub fn received(&self) {
+        self.queued.store(false, Ordering::Release);
+    }
+}
 
 /// Check whether wal backup is required for timeline. If yes, mark that launcher is
 /// aware of current status and return the timeline.
@@ -47,9 +153,10 @@ async fn is_wal_backup_required(ttid: TenantTimelineId) -> Option<Arc<Timeline>>
     }
 }
 
+/// The backup task runs in a child scope of the timeline's, so that it's stopped
+/// when the timeline is deleted, as well as when another safekeeper is elected.
 struct WalBackupTaskHandle {
-    shutdown_tx: Sender<()>,
-    handle: JoinHandle<()>,
+    scope: TaskScope,
 }
 
 struct WalBackupTimelineEntry {
@@ -59,12 +166,9 @@ struct WalBackupTimelineEntry {
 
 async fn shut_down_task(ttid: TenantTimelineId, entry: &mut WalBackupTimelineEntry) {
     if let Some(wb_handle) = entry.handle.take() {
-        // Tell the task to shutdown. Error means task exited earlier, that's ok.
-        let _ = wb_handle.shutdown_tx.send(()).await;
-        // Await the task itself. TODO: restart panicked tasks earlier.
-        if let Err(e) = wb_handle.handle.await {
-            warn!("WAL backup task for {} panicked: {}", ttid, e);
-        }
+        // Panics are logged by the scope. TODO: restart panicked tasks earlier.
+        debug!("shutting down WAL backup task for {}", ttid);
+        wb_handle.scope.shutdown().await;
     }
 }
 
@@ -76,7 +180,7 @@ async fn shut_down_task(ttid: TenantTimelineId, entry: &mut WalBackupTimelineEnt
 /// So we deterministically choose among the reasonably caught up candidates.
 /// TODO: take into account failed attempts to deal with hypothetical situation
 /// where s3 is unreachable only for some sks.
-fn determine_offloader(
+pub(crate) fn determine_offloader(
     alive_peers: &[PeerInfo],
     wal_backup_lsn: Lsn,
     ttid: TenantTimelineId,
@@ -138,24 +242,22 @@ async fn update_task(
         if elected_me {
             info!("elected for backup {}: {}", ttid, election_dbg_str);
 
-            let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
             let timeline_dir = conf.timeline_dir(&ttid);
 
-            let handle = tokio::spawn(
+            let scope = entry.timeline.tasks().child("WAL backup");
+            scope.spawn("WAL backup", |cancel| {
                 backup_task_main(
                     ttid,
                     timeline_dir,
                     conf.workdir.clone(),
                     conf.backup_parallel_jobs,
-                    shutdown_rx,
+                    conf.partial_backup_interval,
+                    cancel,
                 )
-                .instrument(info_span!("WAL backup task", ttid = %ttid)),
-            );
-
-            entry.handle = Some(WalBackupTaskHandle {
-                shutdown_tx,
-                handle,
+                .instrument(info_span!("WAL backup task", ttid = %ttid))
             });
+
+            entry.handle = Some(WalBackupTaskHandle { scope });
         } else {
             info!("stepping down from backup {}: {}", ttid, election_dbg_str);
             shut_down_task(ttid, entry).await;
@@ -196,30 +298,20 @@ pub async fn wal_backup_launcher_task_main(
             ttid = wal_backup_launcher_rx.recv() => {
                 // channel is never expected to get closed
                 let ttid = ttid.unwrap();
-                if conf.remote_storage.is_none() || !conf.wal_backup_enabled {
+                if !conf.is_wal_backup_enabled() {
                     continue; /* just drain the channel and do nothing */
                 }
-                let timeline = is_wal_backup_required(ttid).await;
-                // do we need to do anything at all?
-                if timeline.is_some() != tasks.contains_key(&ttid) {
-                    if let Some(timeline) = timeline {
-                        // need to start the task
-                        let entry = tasks.entry(ttid).or_insert(WalBackupTimelineEntry {
-                            timeline,
-                            handle: None,
-                        });
-                        update_task(&conf, ttid, entry).await;
-                    } else {
-                        // need to stop the task
-                        info!("stopping WAL backup task for {}", ttid);
-                        let mut entry = tasks.remove(&ttid).unwrap();
-                        shut_down_task(ttid, &mut entry).await;
-                    }
-                }
+                attend(&conf, ttid, &mut tasks).await;
             }
             // For each timeline needing offloading, check if this safekeeper
             // should do the job and start/stop the task accordingly.
             _ = ticker.tick() => {
+                let deferred = std::mem::take(&mut *DEFERRED_WAKEUPS.lock().unwrap());
+                if conf.is_wal_backup_enabled() {
+                    for ttid in deferred {
+                        attend(&conf, ttid, &mut tasks).await;
+                    }
+                }
                 for (ttid, entry) in tasks.iter_mut() {
                     update_task(&conf, *ttid, entry).await;
                 }
@@ -228,12 +320,38 @@ pub async fn wal_backup_launcher_task_main(
     }
 }
 
+/// Starts or stops the offloading of a timeline, after a wake-up of the launcher.
+async fn attend(
+    conf: &SafeKeeperConf,
+    ttid: TenantTimelineId,
+    tasks: &mut HashMap<TenantTimelineId, WalBackupTimelineEntry>,
+) {
+    let timeline = is_wal_backup_required(ttid).await;
+    // do we need to do anything at all?
+    if timeline.is_some() != tasks.contains_key(&ttid) {
+        if let Some(timeline) = timeline {
+            // need to start the task
+            let entry = tasks.entry(ttid).or_insert(WalBackupTimelineEntry {
+                timeline,
+                handle: None,
+            });
+            update_task(conf, ttid, entry).await;
+        } else {
+            // need to stop the task
+            info!("stopping WAL backup task for {}", ttid);
+            let mut entry = tasks.remove(&ttid).unwrap();
+            shut_down_task(ttid, &mut entry).await;
+        }
+    }
+}
+
 struct WalBackupTask {
     timeline: Arc<Timeline>,
     timeline_dir: PathBuf,
     workspace_dir: PathBuf,
     wal_seg_size: usize,
     parallel_jobs: usize,
+    partial_backup_interval: Option<Duration>,
     commit_lsn_watch_rx: watch::Receiver<Lsn>,
 }
 
@@ -243,7 +361,8 @@ async fn backup_task_main(
     timeline_dir: PathBuf,
     workspace_dir: PathBuf,
     parallel_jobs: usize,
-    mut shutdown_rx: Receiver<()>,
+    partial_backup_interval: Option<Duration>,
+    cancel: CancellationToken,
 ) {
     info!("started");
     let res = GlobalTimelines::get(ttid);
@@ -260,6 +379,7 @@ async fn backup_task_main(
         timeline_dir,
         workspace_dir,
         parallel_jobs,
+        partial_backup_interval,
     };
 
     // task is spinned up only when wal_seg_size already initialized
@@ -268,7 +388,7 @@ async fn backup_task_main(
     let mut canceled = false;
     select! {
         _ = wb.run() => {}
-        _ = shutdown_rx.recv() => {
+        _ = cancel.cancelled() => {
             canceled = true;
         }
     }
@@ -278,25 +398,37 @@ async fn backup_task_main(
 impl WalBackupTask {
     async fn run(&mut self) {
         let mut backup_lsn = Lsn(0);
+        // When this task uploaded the in-progress segment last time.
+        let mut partial_uploaded_at: Option<Instant> = None;
 
         let mut retry_attempt = 0u32;
         // offload loop
         loop {
             if retry_attempt == 0 {
-                // wait for new WAL to arrive
-                if let Err(e) = self.commit_lsn_watch_rx.changed().await {
+                // wait for new WAL to arrive, or for the in-progress segment
+                // upload if it is pending
+                let commit_lsn = *self.commit_lsn_watch_rx.borrow();
+                let partial_due_at = match self.partial_backup_interval {
+                    Some(interval) if commit_lsn > backup_lsn => {
+                        Some(partial_uploaded_at.map_or_else(Instant::now, |at| at + interval))
+                    }
+                    _ => None,
+                };
+                let res = match partial_due_at {
+                    Some(at) => select! {
+                        res = self.commit_lsn_watch_rx.changed() => res,
+                        _ = sleep_until(at.into()) => Ok(()),
+                    },
+                    None => self.commit_lsn_watch_rx.changed().await,
+                };
+                if let Err(e) = res {
                     // should never happen, as we hold Arc to timeline.
                     error!("commit_lsn watch shut down: {:?}", e);
                     return;
                 }
             } else {
                 // or just sleep if we errored previously
-                let mut retry_delay = UPLOAD_FAILURE_RETRY_MAX_MS;
-                if let Some(backoff_delay) = UPLOAD_FAILURE_RETRY_MIN_MS.checked_shl(retry_attempt)
-                {
-                    retry_delay = min(retry_delay, backoff_delay);
-                }
-                sleep(Duration::from_millis(retry_delay)).await;
+                sleep(UPLOAD_FAILURE_BACKOFF.delay(retry_attempt)).await;
             }
 
             let commit_lsn = *self.commit_lsn_watch_rx.borrow();
@@ -305,47 +437,137 @@ impl WalBackupTask {
             // don't have much local WAL and others already uploaded
             // segments we don't even have.
             if backup_lsn.segment_number(self.wal_seg_size)
-                >= commit_lsn.segment_number(self.wal_seg_size)
+                < commit_lsn.segment_number(self.wal_seg_size)
             {
-                retry_attempt = 0;
-                continue; /* nothing to do, common case as we wake up on every commit_lsn bump */
+                // Perhaps peers advanced the position, check shmem value.
+                backup_lsn = self.timeline.get_wal_backup_lsn().await;
             }
-            // Perhaps peers advanced the position, check shmem value.
-            backup_lsn = self.timeline.get_wal_backup_lsn().await;
             if backup_lsn.segment_number(self.wal_seg_size)
-                >= commit_lsn.segment_number(self.wal_seg_size)
-            {
-                retry_attempt = 0;
-                continue;
-            }
-
-            match backup_lsn_range(
-                &self.timeline,
-                &mut backup_lsn,
-                commit_lsn,
-                self.wal_seg_size,
-                &self.timeline_dir,
-                &self.workspace_dir,
-                self.parallel_jobs,
-            )
-            .await
+                < commit_lsn.segment_number(self.wal_seg_size)
             {
-                Ok(()) => {
-                    retry_attempt = 0;
-                }
-                Err(e) => {
+                let prev_backup_lsn = backup_lsn;
+                if let Err(e) = backup_lsn_range(
+                    &self.timeline,
+                    &mut backup_lsn,
+                    commit_lsn,
+                    self.wal_seg_size,
+                    &self.timeline_dir,
+                    &self.workspace_dir,
+                    self.parallel_jobs,
+                )
+                .await
+                {
                     error!(
                         "failed while offloading range {}-{}: {:?}",
                         backup_lsn, commit_lsn, e
                     );
 
                     retry_attempt = retry_attempt.saturating_add(1);
+                    continue;
+                }
+                if self.partial_backup_interval.is_some()
+                    && prev_backup_lsn.segment_offset(self.wal_seg_size) != 0
+                {
+                    // The full segment replaces the partial one uploaded before
+                    let seg_no = prev_backup_lsn.segment_number(self.wal_seg_size);
+                    if let Err(e) = self.delete_partial_segment(seg_no).await {
+                        warn!("failed to delete partial segment {}: {:?}", seg_no, e);
+                    }
                 }
             }
+
+            let partial_due = match (self.partial_backup_interval, partial_uploaded_at) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(interval), Some(at)) => at.elapsed() >= interval,
+            };
+            if partial_due && commit_lsn > backup_lsn {
+                if let Err(e) = self.backup_partial_segment(commit_lsn).await {
+                    error!(
+                        "failed while offloading partial segment up to {}: {:?}",
+                        commit_lsn, e
+                    );
+
+                    retry_attempt = retry_attempt.saturating_add(1);
+                    continue;
+                }
+                backup_lsn = commit_lsn;
+                partial_uploaded_at = Some(Instant::now());
+            }
+            retry_attempt = 0;
+        }
+    }
+
+    /// Uploads the in-progress segment from its start up to `end_lsn`,
+    /// replacing the one uploaded before, and advances backup_lsn to it.
+    async fn backup_partial_segment(&self, end_lsn: Lsn) -> Result<()> {
+        let seg_no = end_lsn.segment_number(self.wal_seg_size);
+        let size = end_lsn.segment_offset(self.wal_seg_size);
+        let (wal_file_path, wal_file_partial_path) =
+            wal_file_paths(&self.timeline_dir, seg_no, self.wal_seg_size)?;
+        // The segment may be already completed locally
+        let segment_file_path = if wal_file_partial_path.exists() {
+            wal_file_partial_path
+        } else {
+            wal_file_path
+        };
+        let remote_path = remote_partial_segment_path(
+            &self.timeline_dir,
+            &self.workspace_dir,
+            seg_no,
+            self.wal_seg_size,
+        )?;
+
+        let res = backup_object(&segment_file_path, &remote_path, size).await;
+        if res.is_ok() {
+            BACKED_UP_PARTIAL_SEGMENTS.inc();
+        } else {
+            BACKUP_ERRORS.inc();
         }
+        res?;
+        debug!("Backup of {} up to {} done", remote_path, end_lsn);
+
+        self.timeline
+            .set_wal_backup_lsn(end_lsn)
+            .await
+            .context("setting wal_backup_lsn")
+    }
+
+    async fn delete_partial_segment(&self, seg_no: XLogSegNo) -> Result<()> {
+        let remote_path = remote_partial_segment_path(
+            &self.timeline_dir,
+            &self.workspace_dir,
+            seg_no,
+            self.wal_seg_size,
+        )?;
+        get_remote_storage().delete(&remote_path).await
     }
 }
 
+/// Remote path of the in-progress segment upload.
+fn remote_partial_segment_path(
+    timeline_dir: &Path,
+    workspace_dir: &Path,
+    seg_no: XLogSegNo,
+    wal_seg_size: usize,
+) -> Result<RemotePath> {
+    let (_, wal_file_partial_path) = wal_file_paths(timeline_dir, seg_no, wal_seg_size)?;
+    remote_path(&wal_file_partial_path, workspace_dir)
+}
+
+/// Remote path mirroring the path of the file in the workspace.
+fn remote_path(file_path: &Path, workspace_dir: &Path) -> Result<RemotePath> {
+    file_path
+        .strip_prefix(workspace_dir)
+        .context("Failed to strip workspace dir prefix")
+        .and_then(RemotePath::new)
+        .with_context(|| {
+            format!(
+                "Failed to resolve remote part of path {file_path:?} for base {workspace_dir:?}",
+            )
+        })
+}
+
 pub async fn backup_lsn_range(
     timeline: &Arc<Timeline>,
     backup_lsn: &mut Lsn,
@@ -412,15 +634,7 @@ async fn backup_single_segment(
     workspace_dir: &Path,
 ) -> Result<Segment> {
     let segment_file_path = seg.file_path(timeline_dir)?;
-    let remote_segment_path = segment_file_path
-        .strip_prefix(workspace_dir)
-        .context("Failed to strip workspace dir prefix")
-        .and_then(RemotePath::new)
-        .with_context(|| {
-            format!(
-                "Failed to resolve remote part of path {segment_file_path:?} for base {workspace_dir:?}",
-            )
-        })?;
+    let remote_segment_path = remote_path(&segment_file_path, workspace_dir)?;
 
     let res = backup_object(&segment_file_path, &remote_segment_path, seg.size()).await;
     if res.is_ok() {
@@ -469,9 +683,9 @@ fn get_segments(start: Lsn, end: Lsn, seg_size: usize) -> Vec<Segment> {
 
     let res: Vec<Segment> = (first_seg..last_seg)
         .map(|s| {
-            let start_lsn = XLogSegNoOffsetToRecPtr(s, 0, seg_size);
-            let end_lsn = XLogSegNoOffsetToRecPtr(s + 1, 0, seg_size);
-            Segment::new(s, Lsn::from(start_lsn), Lsn::from(end_lsn))
+            let start_lsn = Lsn::from_segment_number(s, seg_size);
+            let end_lsn = Lsn::from_segment_number(s + 1, seg_size);
+            Segment::new(s, start_lsn, end_lsn)
         })
         .collect();
     res
@@ -479,12 +693,17 @@ fn get_segments(start: Lsn, end: Lsn, seg_size: usize) -> Vec<Segment> {
 
 static REMOTE_STORAGE: OnceCell<Option<GenericRemoteStorage>> = OnceCell::new();
 
-async fn backup_object(source_file: &Path, target_file: &RemotePath, size: usize) -> Result<()> {
-    let storage = REMOTE_STORAGE
+fn get_remote_storage() -> &'static GenericRemoteStorage {
+    REMOTE_STORAGE
         .get()
         .expect("failed to get remote storage")
         .as_ref()
-        .unwrap();
+        .unwrap()
+}
+
+/// Uploads the first `size` bytes of the file.
+async fn backup_object(source_file: &Path, target_file: &RemotePath, size: usize) -> Result<()> {
+    let storage = get_remote_storage();
 
     let file = tokio::io::BufReader::new(File::open(&source_file).await.with_context(|| {
         format!(
@@ -494,7 +713,7 @@ async fn backup_object(source_file: &Path, target_file: &RemotePath, size: usize
     })?);
 
     storage
-        .upload_storage_object(Box::new(file), size, target_file)
+        .upload_storage_object(Box::new(file.take(size as u64)), size, target_file)
         .await
 }
 
@@ -519,3 +738,53 @@ pub async fn read_object(
 
     Ok(download.download_stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launcher_wakeups() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let ttid = TenantTimelineId::generate();
+        let wakeup = LauncherWakeup::new(ttid, tx.clone());
+        let is_deferred = |ttid| DEFERRED_WAKEUPS.lock().unwrap().contains(&ttid);
+
+        // the wake-ups before the launcher gets to the timeline are deduplicated
+        wakeup.wake().unwrap();
+        wakeup.wake().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), ttid);
+        assert!(rx.try_recv().is_err());
+
+        // the next wake-up within the minimum interval is left to the next tick
+        wakeup.received();
+        wakeup.wake().unwrap();
+        assert!(rx.try_recv().is_err());
+        assert!(is_deferred(ttid));
+        // ... and covers the next ones until then
+        wakeup.wake().unwrap();
+        assert!(rx.try_recv().is_err());
+        DEFERRED_WAKEUPS.lock().unwrap().remove(&ttid);
+        wakeup.received();
+
+        // after the interval, it's sent again
+        *wakeup.last_sent.lock().unwrap() = Instant::now().checked_sub(MIN_WAKEUP_INTERVAL);
+        wakeup.wake().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), ttid);
+        wakeup.received();
+
+        // a full channel drops the wake-up instead of blocking
+        let other = LauncherWakeup::new(TenantTimelineId::generate(), tx);
+        other.wake().unwrap();
+        *wakeup.last_sent.lock().unwrap() = None;
+        wakeup.wake().unwrap();
+        assert!(is_deferred(ttid));
+        assert_eq!(rx.try_recv().unwrap(), other.ttid);
+        assert!(rx.try_recv().is_err());
+
+        drop(rx);
+        wakeup.received();
+        *wakeup.last_sent.lock().unwrap() = None;
+        assert!(wakeup.wake().is_err());
+    }
+}
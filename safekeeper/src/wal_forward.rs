@@ -0,0 +1,581 @@
+//! Forwarding committed WAL to the safekeepers of another region.
+//!
+//! With [`SafeKeeperConf::wal_forward_to`] set, the committed WAL of every
+//! timeline is replicated asynchronously to the safekeeper set of another
+//! region, which gives sunstorm a replication path at the WAL level, besides
+//! the replication of the layers by the pageservers. The WAL is sent to each
+//! target safekeeper with the ordinary walproposer protocol (`START_WAL_PUSH`),
+//! so the targets need nothing special: the forwarder greets the targets, gets
+//! elected by them and appends the WAL from where each target's WAL ends.
+//!
+//! The targets make up one safekeeper set, so the forwarder is elected by all of
+//! them in the same term, like a walproposer: a target past that term, or taken
+//! over in it by another proposer, moves all the targets on to the next term.
+//! The forwarded WAL is the committed WAL of the timeline whichever term it is
+//! forwarded in, so the term history given to the targets attributes all of it to
+//! the current term, which keeps the histories of the targets identical. On a
+//! reconnect, the forwarder keeps the term it was elected in, as long as the
+//! target is still in it.
+//!
+//! Only one safekeeper of the timeline forwards, elected the same way as the
+//! WAL offloader (see [`wal_backup::determine_offloader`]) among the peers
+//! which aren't targets, recognized by their node id. The WAL which the
+//! forwarder has already removed, e.g. after a change of forwarder, is read
+//! back from the remote storage, so it must be enabled unless the targets
+//! keep up. The WAL acknowledged by each target and the lag behind
+//! commit_lsn are reported in the timeline status and in the
+//! `safekeeper_wal_forward_lag_bytes` metric. The state of the forwarding is
+//! kept in the timeline, and goes away with it.
+
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use postgres_ffi::{MAX_SEND_SIZE, PG_TLI};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::task::AbortHandle;
+use tracing::*;
+use utils::id::{NodeId, TenantTimelineId};
+use utils::lsn::Lsn;
+
+use crate::metrics::WAL_FORWARD_LAG_BYTES;
+use crate::safekeeper::{
+    AcceptorProposerMessage, AppendRequest, AppendRequestHeader, PgUuid, ProposerAcceptorMessage,
+    ProposerElected, ProposerGreeting, Term, TermHistory, TermSwitchEntry, VoteRequest,
+    SK_PROTOCOL_VERSION,
+};
+use crate::timeline::Timeline;
+use crate::wal_storage::WalReader;
+use crate::{wal_backup, GlobalTimelines, SafeKeeperConf};
+
+/// How often to look for timelines to forward, and to retry failed forwards.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to send an empty AppendRequest while there is no new WAL, to get
+/// the acknowledgements of the target and to keep its commit_lsn up to date.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A safekeeper of another region to forward the WAL to, given as
+/// `<node_id>=<host:port>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WalForwardTarget {
+    /// Node id of the target, which keeps it out of the election of the
+    /// forwarder if the regions share the broker.
+    pub node_id: NodeId,
+    /// Postgres address of the target.
+    pub pg_connstr: String,
+}
+
+impl FromStr for WalForwardTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (node_id, pg_connstr) = s
+            .split_once('=')
+            .context("expected <node_id>=<host:port>")?;
+        let node_id = node_id
+            .parse()
+            .with_context(|| format!("invalid node id {node_id:?}"))?;
+        ensure!(!pg_connstr.is_empty(), "empty address of node {node_id}");
+        Ok(WalForwardTarget {
+            node_id: NodeId(node_id),
+            pg_connstr: pg_connstr.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for WalForwardTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.node_id, self.pg_connstr)
+    }
+}
+
+/// Forwarding of the WAL of a timeline to a target safekeeper.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalForwardStatus {
+    /// Node id of the target safekeeper.
+    pub node_id: NodeId,
+    /// Postgres address of the target safekeeper.
+    pub target: String,
+    /// The end of the WAL acknowledged by the target.
+    #[serde_as(as = "DisplayFromStr")]
+    pub forwarded_lsn: Lsn,
+    /// Bytes of committed WAL the target hasn't acknowledged yet.
+    pub lag_bytes: u64,
+}
+
+/// The forwarding of the WAL of a timeline, kept in [`Timeline`].
+#[derive(Debug, Default)]
+pub struct WalForwardState {
+    /// The term this safekeeper is elected in by the targets, or is getting
+    /// elected in.
+    term: Term,
+    /// The targets which elected this safekeeper in `term`.
+    elected_by: HashSet<NodeId>,
+    /// The WAL acknowledged by the targets, while this safekeeper forwards the
+    /// timeline.
+    forwarded_lsns: HashMap<WalForwardTarget, Lsn>,
+}
+
+impl WalForwardState {
+    /// The term to get elected in by a target which is at `target_term`: the
+    /// current term, unless the target is past it or was taken over in it by
+    /// another proposer, which moves all the targets on to the next term.
+    fn term_for(&mut self, target: NodeId, target_term: Term) -> Term {
+        let taken_over = target_term == self.term && !self.elected_by.contains(&target);
+        if target_term > self.term || taken_over {
+            self.term = max(target_term, self.term) + 1;
+            self.elected_by.clear();
+        }
+        self.term
+    }
+
+    fn record_elected(&mut self, target: NodeId, term: Term) {
+        if term == self.term {
+            self.elected_by.insert(target);
+        }
+    }
+
+    /// The WAL acknowledged by all the targets, which the targets may remove as
+    /// far as the forwarder is concerned.
+    fn forwarded_horizon(&self, targets: &[WalForwardTarget]) -> Lsn {
+        targets
+            .iter()
+            .map(|target| {
+                self.forwarded_lsns
+                    .get(target)
+                    .copied()
+                    .unwrap_or(Lsn::INVALID)
+            })
+            .min()
+            .unwrap_or(Lsn::INVALID)
+    }
+
+    /// The forwarding of the timeline, by target, if this safekeeper forwards it.
+    pub fn get_status(&self, commit_lsn: Lsn) -> Vec<WalForwardStatus> {
+        let mut status: Vec<WalForwardStatus> = self
+            .forwarded_lsns
+            .iter()
+            .map(|(target, forwarded_lsn)| WalForwardStatus {
+                node_id: target.node_id,
+                target: target.pg_connstr.clone(),
+                forwarded_lsn: *forwarded_lsn,
+                lag_bytes: commit_lsn.0.saturating_sub(forwarded_lsn.0),
+            })
+            .collect();
+        status.sort_by_key(|s| s.node_id);
+        status
+    }
+}
+
+fn record_forwarded(
+    tli: &Timeline,
+    target: &WalForwardTarget,
+    forwarded_lsn: Lsn,
+    commit_lsn: Lsn,
+) {
+    tli.wal_forward
+        .lock()
+        .unwrap()
+        .forwarded_lsns
+        .insert(target.clone(), forwarded_lsn);
+    WAL_FORWARD_LAG_BYTES
+        .with_label_values(&[
+            &tli.ttid.tenant_id.to_string(),
+            &tli.ttid.timeline_id.to_string(),
+            &target.pg_connstr,
+        ])
+        .set(commit_lsn.0.saturating_sub(forwarded_lsn.0) as i64);
+}
+
+fn forget_forwarded(ttid: &TenantTimelineId, target: &WalForwardTarget) {
+    let _ = WAL_FORWARD_LAG_BYTES.remove_label_values(&[
+        &ttid.tenant_id.to_string(),
+        &ttid.timeline_id.to_string(),
+        &target.pg_connstr,
+    ]);
+}
+
+pub async fn task_main(conf: SafeKeeperConf) -> Result<()> {
+    let mut forwards: HashMap<(TenantTimelineId, NodeId), AbortHandle> = HashMap::new();
+    loop {
+        let mut forwarded = HashSet::new();
+        for tli in GlobalTimelines::get_all() {
+            if tli.is_cancelled() {
+                continue;
+            }
+            if !is_forwarder(&conf, &tli).await {
+                tli.wal_forward.lock().unwrap().forwarded_lsns.clear();
+                continue;
+            }
+            forwarded.insert(tli.ttid);
+            for target in &conf.wal_forward_to {
+                let key = (tli.ttid, target.node_id);
+                if forwards
+                    .get(&key)
+                    .is_some_and(|handle| !handle.is_finished())
+                {
+                    continue;
+                }
+                let conf = conf.clone();
+                let forward_tli = tli.clone();
+                let target = target.clone();
+                let span = info_span!("forward", ttid = %key.0, target = %target);
+                let handle = tli.tasks().spawn("WAL forward", |cancel| {
+                    async move {
+                        let forward = forward_wal(&conf, &forward_tli, &target);
+                        let cancelled = cancel.cancelled();
+                        let res = tokio::select! {
+                            res = forward => res,
+                            _ = cancelled => Err(anyhow!("timeline is cancelled")),
+                        };
+                        if let Err(e) = res {
+                            warn!("WAL forward failed: {e:#}");
+                        }
+                    }
+                    .instrument(span)
+                });
+                forwards.insert(key, handle);
+            }
+        }
+        forwards.retain(|(ttid, node_id), handle| {
+            if forwarded.contains(ttid) {
+                return true;
+            }
+            handle.abort();
+            if let Some(target) = conf.wal_forward_to.iter().find(|t| t.node_id == *node_id) {
+                forget_forwarded(ttid, target);
+            }
+            false
+        });
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Whether this safekeeper is the one to forward the WAL of the timeline.
+async fn is_forwarder(conf: &SafeKeeperConf, tli: &Arc<Timeline>) -> bool {
+    let (_, state) = tli.get_state().await;
+    if state.local_start_lsn == Lsn::INVALID {
+        return false; // no WAL yet
+    }
+    // Unlike for the offloading, any safekeeper can forward, as the WAL it has
+    // already removed is in the remote storage. The targets publish the timeline
+    // to the broker too if the regions share it, and mustn't be elected.
+    let mut peers = tli.get_peers(conf).await;
+    peers.retain(|peer| {
+        !conf
+            .wal_forward_to
+            .iter()
+            .any(|target| target.node_id == peer.sk_id)
+    });
+    let (forwarder, _) = wal_backup::determine_offloader(&peers, Lsn::MAX, tli.ttid, conf);
+    forwarder == Some(conf.my_id)
+}
+
+/// Forwards committed WAL of the timeline to the target safekeeper, from where
+/// the target's WAL ends, until the connection breaks or the forwarder moves
+/// on to another term.
+async fn forward_wal(
+    conf: &SafeKeeperConf,
+    tli: &Arc<Timeline>,
+    target: &WalForwardTarget,
+) -> Result<()> {
+    let ttid = tli.ttid;
+    let mut pg_config =
+        tokio_postgres::Config::from_str(&format!("postgresql://no_user@{}", target.pg_connstr))
+            .context("invalid safekeeper address")?;
+    pg_config.application_name("wal_forward").options(&format!(
+        "-c tenant_id={} timeline_id={}",
+        ttid.tenant_id, ttid.timeline_id
+    ));
+    if let Some(token) = &conf.safekeeper_auth_token {
+        pg_config.password(token);
+    }
+    let (client, connection) = pg_config
+        .connect(tokio_postgres::NoTls)
+        .await
+        .context("failed to connect to the safekeeper")?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("safekeeper connection closed: {e}");
+        }
+    });
+    let duplex = client.copy_both_simple::<Bytes>("START_WAL_PUSH").await?;
+    let (mut sink, mut stream) = Box::pin(duplex).split();
+
+    let (_, state) = tli.get_state().await;
+    let proposer_uuid = proposer_uuid(conf.my_id);
+    let greeting = ProposerGreeting {
+        protocol_version: SK_PROTOCOL_VERSION,
+        pg_version: state.server.pg_version,
+        proposer_id: proposer_uuid,
+        system_id: state.server.system_id,
+        timeline_id: ttid.timeline_id,
+        tenant_id: ttid.tenant_id,
+        tli: PG_TLI,
+        wal_seg_size: state.server.wal_seg_size,
+    };
+    send_msg(&mut sink, ProposerAcceptorMessage::Greeting(greeting)).await?;
+    let target_term = match recv_msg(&mut stream).await? {
+        AcceptorProposerMessage::Greeting(greeting) => {
+            ensure!(
+                greeting.node_id == target.node_id,
+                "target is node {}, not {}",
+                greeting.node_id,
+                target.node_id
+            );
+            greeting.term
+        }
+        msg => bail!("unexpected {msg:?} instead of greeting"),
+    };
+
+    // Get elected in the term of the targets. The target votes only once per
+    // term, so nobody else has written in it if the target elected this
+    // safekeeper in it before.
+    let (term, reelected) = {
+        let mut forward = tli.wal_forward.lock().unwrap();
+        let term = forward.term_for(target.node_id, target_term);
+        (term, forward.elected_by.contains(&target.node_id))
+    };
+    // Refused if reelected, but the response has the target's WAL position all
+    // the same.
+    send_msg(
+        &mut sink,
+        ProposerAcceptorMessage::VoteRequest(VoteRequest { term }),
+    )
+    .await?;
+    let vote = match recv_msg(&mut stream).await? {
+        AcceptorProposerMessage::VoteResponse(vote) => vote,
+        msg => bail!("unexpected {msg:?} instead of vote"),
+    };
+    if reelected {
+        ensure!(
+            vote.term == term,
+            "another proposer took over the target in term {}",
+            vote.term
+        );
+    } else {
+        ensure!(
+            vote.vote_given != 0,
+            "vote for term {term} refused, the target is at term {}",
+            vote.term
+        );
+        tli.wal_forward
+            .lock()
+            .unwrap()
+            .record_elected(target.node_id, term);
+    }
+
+    let commit_lsn = *tli.get_commit_lsn_watch_rx().borrow();
+    let start_lsn = if vote.flush_lsn == Lsn::INVALID {
+        state.timeline_start_lsn
+    } else {
+        vote.flush_lsn
+    };
+    // Possible right after a change of forwarder, retrying helps.
+    ensure!(
+        start_lsn <= commit_lsn,
+        "target has WAL up to {start_lsn}, ahead of commit_lsn {commit_lsn}"
+    );
+    // The same on all the targets, whatever WAL they have.
+    let epoch_start_lsn = state.timeline_start_lsn;
+    let elected = ProposerElected {
+        term,
+        start_streaming_at: start_lsn,
+        term_history: TermHistory(vec![TermSwitchEntry {
+            term,
+            lsn: epoch_start_lsn,
+        }]),
+        timeline_start_lsn: state.timeline_start_lsn,
+    };
+    send_msg(&mut sink, ProposerAcceptorMessage::Elected(elected)).await?;
+
+    let mut reader = WalReader::new(
+        conf.workdir.clone(),
+        conf.timeline_dir(&ttid),
+        &state,
+        start_lsn,
+        conf.is_wal_backup_enabled(),
+    )?;
+    info!("forwarding WAL from {start_lsn} in term {term}");
+
+    let send = send_wal(
+        conf,
+        tli,
+        &mut sink,
+        &mut reader,
+        term,
+        epoch_start_lsn,
+        start_lsn,
+    );
+    let recv = recv_acks(tli, target, &mut stream, term);
+    tokio::select! {
+        res = send => res,
+        res = recv => res,
+    }
+}
+
+/// Sends the WAL up to commit_lsn as it grows, from `start_lsn`, until the
+/// targets move on to another term.
+async fn send_wal<S>(
+    conf: &SafeKeeperConf,
+    tli: &Arc<Timeline>,
+    sink: &mut S,
+    reader: &mut WalReader,
+    term: Term,
+    epoch_start_lsn: Lsn,
+    start_lsn: Lsn,
+) -> Result<()>
+where
+    S: Sink<Bytes, Error = tokio_postgres::Error> + Unpin,
+{
+    let proposer_uuid = proposer_uuid(conf.my_id);
+    let mut commit_lsn_rx = tli.get_commit_lsn_watch_rx();
+    let mut buf = vec![0u8; MAX_SEND_SIZE];
+    let mut pos = start_lsn;
+    loop {
+        let truncate_lsn = {
+            let forward = tli.wal_forward.lock().unwrap();
+            ensure!(
+                forward.term == term,
+                "the targets moved on to term {}",
+                forward.term
+            );
+            forward.forwarded_horizon(&conf.wal_forward_to)
+        };
+        let commit_lsn = *commit_lsn_rx.borrow();
+        let len = min(commit_lsn.0.saturating_sub(pos.0) as usize, buf.len());
+        let n = if len > 0 {
+            let n = reader.read(&mut buf[..len]).await?;
+            ensure!(n > 0, "unexpected end of WAL at {pos}");
+            n
+        } else {
+            0
+        };
+        let append = AppendRequest {
+            h: AppendRequestHeader {
+                term,
+                epoch_start_lsn,
+                begin_lsn: pos,
+                end_lsn: pos + n as u64,
+                commit_lsn,
+                truncate_lsn,
+                proposer_uuid,
+            },
+            wal_data: Bytes::copy_from_slice(&buf[..n]),
+        };
+        send_msg(sink, ProposerAcceptorMessage::AppendRequest(append)).await?;
+        pos += n as u64;
+        if n == 0 {
+            let changed = commit_lsn_rx.changed();
+            if let Ok(Err(_)) = tokio::time::timeout(HEARTBEAT_INTERVAL, changed).await {
+                bail!("timeline is gone");
+            }
+        }
+    }
+}
+
+/// Records the WAL acknowledged by the target, until another proposer takes
+/// it over.
+async fn recv_acks<S>(
+    tli: &Arc<Timeline>,
+    target: &WalForwardTarget,
+    stream: &mut S,
+    term: Term,
+) -> Result<()>
+where
+    S: Stream<Item = Result<Bytes, tokio_postgres::Error>> + Unpin,
+{
+    let commit_lsn_rx = tli.get_commit_lsn_watch_rx();
+    loop {
+        match recv_msg(stream).await? {
+            AcceptorProposerMessage::AppendResponse(resp) => {
+                ensure!(
+                    resp.term <= term,
+                    "another proposer took over the target in term {}",
+                    resp.term
+                );
+                record_forwarded(tli, target, resp.flush_lsn, *commit_lsn_rx.borrow());
+            }
+            msg => bail!("unexpected {msg:?} instead of append response"),
+        }
+    }
+}
+
+/// The walproposer UUID of this safekeeper, as it shows up in the elections
+/// of the targets.
+fn proposer_uuid(my_id: NodeId) -> PgUuid {
+    let mut uuid = [0u8; 16];
+    uuid[..8].copy_from_slice(&my_id.0.to_be_bytes());
+    uuid
+}
+
+async fn send_msg<S>(sink: &mut S, msg: ProposerAcceptorMessage) -> Result<()>
+where
+    S: Sink<Bytes, Error = tokio_postgres::Error> + Unpin,
+{
+    let mut buf = BytesMut::new();
+    msg.serialize(&mut buf)?;
+    sink.send(buf.freeze()).await?;
+    Ok(())
+}
+
+async fn recv_msg<S>(stream: &mut S) -> Result<AcceptorProposerMessage>
+where
+    S: Stream<Item = Result<Bytes, tokio_postgres::Error>> + Unpin,
+{
+    let bytes = stream
+        .next()
+        .await
+        .context("target closed the connection")??;
+    AcceptorProposerMessage::parse(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_follow_one_term() {
+        let mut state = WalForwardState::default();
+        let (a, b) = (NodeId(1), NodeId(2));
+
+        // The first target moves the targets to the term after its own, and the
+        // target behind is elected in the same term.
+        assert_eq!(state.term_for(a, 3), 4);
+        state.record_elected(a, 4);
+        assert_eq!(state.term_for(b, 1), 4);
+        state.record_elected(b, 4);
+
+        // Reconnecting keeps the term.
+        assert_eq!(state.term_for(a, 4), 4);
+
+        // A target taken over in the term moves all of them on.
+        state.elected_by.remove(&b);
+        assert_eq!(state.term_for(b, 4), 5);
+        assert!(state.elected_by.is_empty());
+        assert_eq!(state.term_for(a, 4), 5);
+
+        // An election of the old term doesn't count in the new one.
+        state.record_elected(a, 4);
+        assert!(state.elected_by.is_empty());
+    }
+
+    #[test]
+    fn parse_target() {
+        let target: WalForwardTarget = "7=sk-7.region-b:5454".parse().unwrap();
+        assert_eq!(target.node_id, NodeId(7));
+        assert_eq!(target.pg_connstr, "sk-7.region-b:5454");
+        assert_eq!(target.to_string(), "7=sk-7.region-b:5454");
+        assert!("sk-7.region-b:5454".parse::<WalForwardTarget>().is_err());
+        assert!("x=sk-7.region-b:5454".parse::<WalForwardTarget>().is_err());
+    }
+}
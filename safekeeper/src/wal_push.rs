@@ -0,0 +1,175 @@
+//! Pushing WAL to a pageserver while the broker is unreachable.
+//!
+//! Normally the pageserver learns about the safekeepers of a timeline from the
+//! broker, and streams WAL from one of them. While the broker is down, the
+//! ingestion stops and the computes eventually stall on backpressure. With
+//! [`SafeKeeperConf::wal_push`] set, once the broker hasn't been heard from for
+//! the heartbeat timeout, the safekeeper pushes the WAL of its active timelines
+//! to the pageservers which announced wanting it through the broker before it
+//! went down (see [`broker::get_pageservers`]) and don't stream from this
+//! safekeeper, with the `push_wal` page service command.
+//!
+//! The pageserver accepts a push only if nothing else is ingesting WAL into the
+//! timeline, so all the safekeepers of the timeline can try, and one of them
+//! wins. A push ends once the broker is reachable again, letting the
+//! pageserver's WAL receiver take over.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bytes::Bytes;
+use futures::SinkExt;
+use postgres_ffi::v14::xlog_utils::normalize_lsn;
+use postgres_ffi::MAX_SEND_SIZE;
+use tokio::task::AbortHandle;
+use tokio_postgres::SimpleQueryMessage;
+use tracing::*;
+use utils::id::{NodeId, TenantTimelineId};
+use utils::lsn::Lsn;
+
+use crate::timeline::Timeline;
+use crate::wal_storage::WalReader;
+use crate::{broker, GlobalTimelines, SafeKeeperConf};
+
+/// How often to look for timelines to push, and to check whether a push
+/// should end while there is no new WAL.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub async fn task_main(conf: SafeKeeperConf) -> Result<()> {
+    let mut pushes: HashMap<(TenantTimelineId, NodeId), AbortHandle> = HashMap::new();
+    loop {
+        pushes.retain(|_, handle| !handle.is_finished());
+        if broker::is_unreachable(&conf) {
+            for tli in GlobalTimelines::get_all() {
+                if !tli.is_active().await {
+                    continue;
+                }
+                for (pageserver_id, connstr) in broker::get_pageservers(&tli.ttid) {
+                    let key = (tli.ttid, pageserver_id);
+                    if pushes.contains_key(&key)
+                        || tli.get_walsenders().is_streaming_to(pageserver_id)
+                    {
+                        continue;
+                    }
+                    let conf = conf.clone();
+                    let push_tli = tli.clone();
+                    let span = info_span!("push", ttid = %key.0, pageserver_id = %pageserver_id);
+                    let handle = tli.tasks().spawn("WAL push", |cancel| {
+                        async move {
+                            let push = push_wal(&conf, &push_tli, pageserver_id, &connstr);
+                            let cancelled = cancel.cancelled();
+                            let res = tokio::select! {
+                                res = push => res,
+                                _ = cancelled => Err(anyhow!("timeline is cancelled")),
+                            };
+                            match res {
+                                Ok(()) => info!("WAL push ended"),
+                                Err(e) => warn!("WAL push failed: {e:#}"),
+                            }
+                        }
+                        .instrument(span)
+                    });
+                    pushes.insert(key, handle);
+                }
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Pushes committed WAL of the timeline to the pageserver, from where its last
+/// record ends, until the push should end.
+async fn push_wal(
+    conf: &SafeKeeperConf,
+    tli: &Arc<Timeline>,
+    pageserver_id: NodeId,
+    connstr: &str,
+) -> Result<()> {
+    let mut pg_config =
+        tokio_postgres::Config::from_str(connstr).context("invalid pageserver connstr")?;
+    if let Some(token) = &conf.pageserver_auth_token {
+        pg_config.password(token);
+    }
+    let (client, connection) = pg_config
+        .connect(tokio_postgres::NoTls)
+        .await
+        .context("failed to connect to the pageserver")?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("pageserver connection closed: {e}");
+        }
+    });
+
+    let ttid = tli.ttid;
+    let messages = client
+        .simple_query(&format!(
+            "get_last_record_rlsn {} {}",
+            ttid.tenant_id, ttid.timeline_id
+        ))
+        .await?;
+    let last_record_lsn = match messages.first() {
+        Some(SimpleQueryMessage::Row(row)) => {
+            Lsn::from_str(row.get("last_lsn").context("missing last_lsn")?)?
+        }
+        _ => bail!("unexpected response to get_last_record_rlsn"),
+    };
+
+    // The pageserver expects the same starting point as for streaming: the end
+    // of the last record, skipping the padding and the page header.
+    let wal_seg_size = tli.get_wal_seg_size().await;
+    let start_lsn = normalize_lsn(
+        last_record_lsn + last_record_lsn.calc_padding(8u32),
+        wal_seg_size,
+    );
+    let (mem_state, state) = tli.get_state().await;
+    ensure!(
+        start_lsn <= mem_state.commit_lsn,
+        "pageserver is at {start_lsn}, ahead of commit_lsn {}",
+        mem_state.commit_lsn
+    );
+    let mut reader = WalReader::new(
+        conf.workdir.clone(),
+        conf.timeline_dir(&ttid),
+        &state,
+        start_lsn,
+        conf.is_wal_backup_enabled(),
+    )?;
+
+    info!("pushing WAL from {start_lsn}");
+    let query = format!(
+        "push_wal {} {} {}",
+        ttid.tenant_id, ttid.timeline_id, start_lsn
+    );
+    let mut sink = pin!(client.copy_in::<_, Bytes>(query.as_str()).await?);
+    let mut commit_lsn_rx = tli.get_commit_lsn_watch_rx();
+    let mut buf = vec![0u8; MAX_SEND_SIZE];
+    let mut pos = start_lsn;
+    loop {
+        if !broker::is_unreachable(conf) {
+            info!("broker is reachable again");
+            break;
+        }
+        if tli.is_cancelled() || tli.get_walsenders().is_streaming_to(pageserver_id) {
+            break;
+        }
+        let commit_lsn = *commit_lsn_rx.borrow();
+        if pos >= commit_lsn {
+            let _ = tokio::time::timeout(CHECK_INTERVAL, commit_lsn_rx.changed()).await;
+            continue;
+        }
+
+        let len = min((commit_lsn.0 - pos.0) as usize, buf.len());
+        let n = reader.read(&mut buf[..len]).await?;
+        ensure!(n > 0, "unexpected end of WAL at {pos}");
+        sink.send(Bytes::copy_from_slice(&buf[..n])).await?;
+        pos += n as u64;
+    }
+    sink.as_mut().finish().await?;
+    info!("pushed WAL up to {pos}");
+    Ok(())
+}
@@ -4,57 +4,123 @@
 //!
 use anyhow::{Context, Result};
 use postgres_backend::QueryError;
-use std::{future, time::Duration};
-use tokio::net::TcpStream;
+use std::{future, net::SocketAddr, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_io_timeout::TimeoutReader;
 use tracing::*;
-use utils::{auth::Scope, measured_stream::MeasuredStream};
+use utils::{auth::Scope, measured_stream::MeasuredStream, unix_listener::UNIX_PEER_ADDR};
 
+use crate::chaos::ConnectionFate;
 use crate::handler::SafekeeperPostgresHandler;
 use crate::metrics::TrafficMetrics;
 use crate::SafeKeeperConf;
 use postgres_backend::{AuthType, PostgresBackend};
 
-/// Accept incoming TCP connections and spawn them into a background thread.
+/// Socket the WAL service accepts connections on.
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    /// For the computes and pageservers on the same host.
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Accept incoming connections and spawn a task for each of them.
 pub async fn task_main(
     conf: SafeKeeperConf,
-    pg_listener: std::net::TcpListener,
+    pg_listener: Listener,
     allowed_auth_scope: Option<Scope>,
 ) -> anyhow::Result<()> {
-    // Tokio's from_std won't do this for us, per its comment.
-    pg_listener.set_nonblocking(true)?;
-
-    let listener = tokio::net::TcpListener::from_std(pg_listener)?;
     let mut connection_count: ConnectionCount = 0;
 
-    loop {
-        let (socket, peer_addr) = listener.accept().await.context("accept")?;
-        debug!("accepted connection from {}", peer_addr);
-        let conf = conf.clone();
-        let conn_id = issue_connection_id(&mut connection_count);
-
-        tokio::spawn(async move {
-            if let Err(err) = handle_socket(socket, conf, conn_id, allowed_auth_scope)
-                .instrument(info_span!("", cid = %conn_id))
-                .await
-            {
-                error!("connection handler exited: {}", err);
+    match pg_listener {
+        Listener::Tcp(pg_listener) => {
+            // Tokio's from_std won't do this for us, per its comment.
+            pg_listener.set_nonblocking(true)?;
+            let listener = tokio::net::TcpListener::from_std(pg_listener)?;
+            loop {
+                let (socket, peer_addr) = listener.accept().await.context("accept")?;
+                debug!("accepted connection from {}", peer_addr);
+                if let Err(err) = socket.set_nodelay(true) {
+                    error!("failed to set TCP_NODELAY for {}: {}", peer_addr, err);
+                    continue;
+                }
+                let conn_id = issue_connection_id(&mut connection_count);
+                spawn_connection(socket, peer_addr, conf.clone(), conn_id, allowed_auth_scope);
+            }
+        }
+        Listener::Unix(pg_listener) => {
+            pg_listener.set_nonblocking(true)?;
+            let listener = tokio::net::UnixListener::from_std(pg_listener)?;
+            loop {
+                let (socket, _) = listener.accept().await.context("accept")?;
+                debug!("accepted connection on unix socket");
+                let conn_id = issue_connection_id(&mut connection_count);
+                spawn_connection(
+                    socket,
+                    UNIX_PEER_ADDR,
+                    conf.clone(),
+                    conn_id,
+                    allowed_auth_scope,
+                );
             }
-        });
+        }
     }
 }
 
-/// This is run by `task_main` above, inside a background thread.
-///
-async fn handle_socket(
-    socket: TcpStream,
+fn spawn_connection<IO>(
+    socket: IO,
+    peer_addr: SocketAddr,
     conf: SafeKeeperConf,
     conn_id: ConnectionId,
     allowed_auth_scope: Option<Scope>,
-) -> Result<(), QueryError> {
-    socket.set_nodelay(true)?;
-    let peer_addr = socket.peer_addr()?;
+) where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(
+        async move {
+            let fate = match &conf.chaos {
+                Some(chaos) => {
+                    chaos.delay_connection().await;
+                    chaos.connection_fate()
+                }
+                None => ConnectionFate::Serve,
+            };
+            let handler = handle_socket(socket, peer_addr, conf, conn_id, allowed_auth_scope);
+            let res = match fate {
+                ConnectionFate::Serve => handler.await,
+                ConnectionFate::Drop => {
+                    info!("chaos: dropping the connection");
+                    return;
+                }
+                ConnectionFate::ResetAfter(after) => {
+                    match tokio::time::timeout(after, handler).await {
+                        Ok(res) => res,
+                        Err(_) => {
+                            info!("chaos: resetting the connection after {:?}", after);
+                            return;
+                        }
+                    }
+                }
+            };
+            if let Err(err) = res {
+                error!("connection handler exited: {}", err);
+            }
+        }
+        .instrument(info_span!("", cid = %conn_id)),
+    );
+}
 
+/// This is run by `task_main` above, in a task of its own, and serves the connection with
+/// the async [`PostgresBackend`], like the page service.
+async fn handle_socket<IO>(
+    socket: IO,
+    peer_addr: SocketAddr,
+    conf: SafeKeeperConf,
+    conn_id: ConnectionId,
+    allowed_auth_scope: Option<Scope>,
+) -> Result<(), QueryError>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     // Set timeout on reading from the socket. It prevents hanged up connection
     // if client suddenly disappears. Note that TCP_KEEPALIVE is not enabled by
     // default, and tokio doesn't provide ability to set it out of the box.
@@ -30,6 +30,7 @@ use postgres_ffi::waldecoder::WalStreamDecoder;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::XLOG_BLCKSZ;
 use pq_proto::SystemId;
+use utils::checksummed_writer::{preallocate_file, SyncPolicy};
 use utils::{id::TenantTimelineId, lsn::Lsn};
 
 #[async_trait::async_trait]
@@ -189,20 +190,15 @@ impl PhysicalStorage {
         )
     }
 
-    /// Call fdatasync if config requires so.
-    async fn fdatasync_file(&mut self, file: &mut File) -> Result<()> {
-        if !self.conf.no_sync {
-            self.metrics
-                .observe_flush_seconds(time_io_closure(file.sync_data()).await?);
+    /// Syncs the file according to `policy`, unless the config disables syncing.
+    async fn sync_file(&mut self, file: &File, policy: SyncPolicy) -> Result<()> {
+        if let Some(chaos) = &self.conf.chaos {
+            chaos.delay_fsync().await;
         }
-        Ok(())
-    }
-
-    /// Call fsync if config requires so.
-    async fn fsync_file(&mut self, file: &mut File) -> Result<()> {
-        if !self.conf.no_sync {
+        let policy = policy.unless(self.conf.no_sync);
+        if policy != SyncPolicy::NoSync {
             self.metrics
-                .observe_flush_seconds(time_io_closure(file.sync_all()).await?);
+                .observe_flush_seconds(time_io_closure(policy.sync_async(file)).await?);
         }
         Ok(())
     }
@@ -232,8 +228,12 @@ impl PhysicalStorage {
                 .await
                 .with_context(|| format!("Failed to open log file {:?}", &wal_file_path))?;
 
+            // Allocated at once, so that a full disk fails here rather than halfway
+            // through the zeroes, and the segment is contiguous
+            preallocate_file(&file, self.wal_seg_size as u64)
+                .with_context(|| format!("Failed to preallocate log file {:?}", &wal_file_path))?;
             write_zeroes(&mut file, self.wal_seg_size).await?;
-            self.fsync_file(&mut file).await?;
+            self.sync_file(&file, SyncPolicy::All).await?;
             Ok((file, true))
         }
     }
@@ -257,7 +257,7 @@ impl PhysicalStorage {
 
         if xlogoff + buf.len() == self.wal_seg_size {
             // If we reached the end of a WAL segment, flush and close it.
-            self.fdatasync_file(&mut file).await?;
+            self.sync_file(&file, SyncPolicy::Data).await?;
 
             // Rename partial file to completed file
             let (wal_file_path, wal_file_partial_path) =
@@ -279,8 +279,8 @@ impl PhysicalStorage {
     async fn write_exact(&mut self, pos: Lsn, mut buf: &[u8]) -> Result<()> {
         if self.write_lsn != pos {
             // need to flush the file before discarding it
-            if let Some(mut file) = self.file.take() {
-                self.fdatasync_file(&mut file).await?;
+            if let Some(file) = self.file.take() {
+                self.sync_file(&file, SyncPolicy::Data).await?;
             }
 
             self.write_lsn = pos;
@@ -369,8 +369,8 @@ impl Storage for PhysicalStorage {
             return Ok(());
         }
 
-        if let Some(mut unflushed_file) = self.file.take() {
-            self.fdatasync_file(&mut unflushed_file).await?;
+        if let Some(unflushed_file) = self.file.take() {
+            self.sync_file(&unflushed_file, SyncPolicy::Data).await?;
             self.file = Some(unflushed_file);
         } else {
             // We have unflushed data (write_lsn != flush_lsn), but no file.
@@ -412,8 +412,8 @@ impl Storage for PhysicalStorage {
         }
 
         // Close previously opened file, if any
-        if let Some(mut unflushed_file) = self.file.take() {
-            self.fdatasync_file(&mut unflushed_file).await?;
+        if let Some(unflushed_file) = self.file.take() {
+            self.sync_file(&unflushed_file, SyncPolicy::Data).await?;
         }
 
         let xlogoff = end_pos.segment_offset(self.wal_seg_size);
@@ -427,7 +427,7 @@ impl Storage for PhysicalStorage {
         // Fill end with zeroes
         file.seek(SeekFrom::Start(xlogoff as u64)).await?;
         write_zeroes(&mut file, self.wal_seg_size - xlogoff).await?;
-        self.fdatasync_file(&mut file).await?;
+        self.sync_file(&file, SyncPolicy::Data).await?;
 
         if !is_partial {
             // Make segment partial once again
@@ -727,7 +727,7 @@ async fn write_zeroes(file: &mut File, mut count: usize) -> Result<()> {
 }
 
 /// Helper returning full path to WAL segment file and its .partial brother.
-fn wal_file_paths(
+pub(crate) fn wal_file_paths(
     timeline_dir: &Path,
     segno: XLogSegNo,
     wal_seg_size: usize,
@@ -0,0 +1,447 @@
+//! Verification of the WAL on disk on startup.
+//!
+//! With [`SafeKeeperConf::verify_wal_on_startup`], before a timeline is loaded
+//! its WAL is decoded from the first record of the oldest segment on disk,
+//! validating the page headers and the record CRCs, up to the end of the last
+//! valid record. If that is before the persisted `commit_lsn`, committed WAL is
+//! damaged or missing, and the verification fails. The timeline is loaded
+//! anyway: such WAL can only be repaired manually, from the peers.
+//!
+//! Anything but zeroes after the last valid record is a torn tail, left by
+//! writes interrupted by a crash. The safekeeper continues writing WAL from the
+//! end of the last valid record, so the tail is harmless, but with
+//! [`SafeKeeperConf::truncate_torn_wal`] it is zeroed out the same way
+//! `truncate_wal` does.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use postgres_ffi::pg_constants::XLP_FIRST_IS_CONTRECORD;
+use postgres_ffi::v14::bindings::XLogPageHeaderData;
+use postgres_ffi::v14::xlog_utils::{
+    IsPartialXLogFileName, IsXLogFileName, XLogFromFileName, XLOG_SIZE_OF_XLOG_LONG_PHD,
+    XLOG_SIZE_OF_XLOG_SHORT_PHD,
+};
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::{xlog_page_magic, XLogSegNo, XLOG_BLCKSZ};
+use tracing::*;
+use utils::checksummed_writer::{verify_checksum, ChecksummedWriter, SyncPolicy};
+use utils::id::TenantTimelineId;
+use utils::lsn::Lsn;
+
+use crate::control_file;
+use crate::metrics::{WAL_TORN_TAIL_BYTES, WAL_VERIFICATION_FAILED, WAL_VERIFIED_BYTES};
+use crate::wal_storage::wal_file_paths;
+use crate::SafeKeeperConf;
+
+const READ_BUF_SIZE: usize = 128 * 1024;
+
+/// Result of decoding the WAL of a timeline.
+#[derive(Debug)]
+pub struct Verification {
+    /// Start of the first decoded record.
+    pub start_lsn: Lsn,
+    /// End of the last valid record.
+    pub end_lsn: Lsn,
+    /// Why decoding stopped at `end_lsn`. Decoding of intact WAL stops at the
+    /// zeroes after it, or at the end of the last segment.
+    pub stop_reason: Option<String>,
+    /// Bytes from `end_lsn` up to the last non-zero byte on disk.
+    pub torn_tail_bytes: u64,
+}
+
+/// Verifies the WAL of the timeline, truncating the torn tail if configured,
+/// and reports the result in the log and the metrics.
+pub fn verify_timeline(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Result<()> {
+    let started_at = Instant::now();
+    let state = control_file::FileStorage::load_control_file_conf(conf, ttid)?;
+    if state.commit_lsn == Lsn(0) {
+        // No WAL was written yet
+        return Ok(());
+    }
+    let timeline_dir = conf.timeline_dir(ttid);
+    let wal_seg_size = state.server.wal_seg_size as usize;
+    let Some(verification) = verify_wal(
+        &timeline_dir,
+        wal_seg_size,
+        state.server.pg_version / 10000,
+        state.local_start_lsn,
+    )?
+    else {
+        info!("no WAL to verify on disk");
+        return Ok(());
+    };
+
+    let tenant_id = ttid.tenant_id.to_string();
+    let timeline_id = ttid.timeline_id.to_string();
+    let labels = [tenant_id.as_str(), timeline_id.as_str()];
+    let failed = verification.end_lsn < state.commit_lsn;
+    WAL_VERIFIED_BYTES
+        .with_label_values(&labels)
+        .set((verification.end_lsn.0 - verification.start_lsn.0) as i64);
+    WAL_TORN_TAIL_BYTES
+        .with_label_values(&labels)
+        .set(verification.torn_tail_bytes as i64);
+    WAL_VERIFICATION_FAILED
+        .with_label_values(&labels)
+        .set(failed as i64);
+
+    let stop_reason = verification
+        .stop_reason
+        .as_deref()
+        .unwrap_or("end of the last segment");
+    if failed {
+        error!(
+            "committed WAL is damaged: valid WAL ends at {}, before commit_lsn {}: {}",
+            verification.end_lsn, state.commit_lsn, stop_reason
+        );
+    } else if verification.torn_tail_bytes > 0 {
+        if conf.truncate_torn_wal {
+            truncate_torn_tail(
+                &timeline_dir,
+                wal_seg_size,
+                verification.end_lsn,
+                conf.no_sync,
+            )?;
+            warn!(
+                "truncated torn tail of {} bytes after {}: {}",
+                verification.torn_tail_bytes, verification.end_lsn, stop_reason
+            );
+        } else {
+            warn!(
+                "found torn tail of {} bytes after {}: {}",
+                verification.torn_tail_bytes, verification.end_lsn, stop_reason
+            );
+        }
+    }
+    info!(
+        "verified WAL from {} to {} in {:?}",
+        verification.start_lsn,
+        verification.end_lsn,
+        started_at.elapsed()
+    );
+    Ok(())
+}
+
+/// Decodes the WAL in `timeline_dir` from the first record of the oldest
+/// segment, or from `local_start_lsn` if that is in or after it. Returns `None`
+/// if there is no WAL on disk.
+pub fn verify_wal(
+    timeline_dir: &Path,
+    wal_seg_size: usize,
+    pg_version: u32,
+    local_start_lsn: Lsn,
+) -> Result<Option<Verification>> {
+    let segments = list_segments(timeline_dir, wal_seg_size)?;
+    let Some(&last_segno) = segments.last() else {
+        return Ok(None);
+    };
+    let oldest_start = Lsn::from_segment_number(segments[0], wal_seg_size);
+    let start_lsn = if local_start_lsn >= oldest_start {
+        local_start_lsn
+    } else {
+        let page_magic = xlog_page_magic(pg_version)?;
+        let mut first_record = None;
+        for &segno in &segments {
+            if let Some(mut segment) = open_segment(timeline_dir, segno, wal_seg_size)? {
+                first_record = find_first_record(&mut segment, segno, wal_seg_size, page_magic)?;
+                if first_record.is_some() {
+                    break;
+                }
+            }
+        }
+        match first_record {
+            Some(lsn) => lsn,
+            None => return Ok(None),
+        }
+    };
+
+    let mut decoder = WalStreamDecoder::new(start_lsn, pg_version);
+    let mut end_lsn = start_lsn;
+    let mut stop_reason = None;
+    let mut pos = start_lsn;
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    'segments: loop {
+        let segno = pos.segment_number(wal_seg_size);
+        let Some(mut segment) = open_segment(timeline_dir, segno, wal_seg_size)? else {
+            break;
+        };
+        segment.seek(SeekFrom::Start(pos.segment_offset(wal_seg_size) as u64))?;
+        loop {
+            let n = segment.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            pos += n as u64;
+            decoder.feed_bytes(&buf[..n]);
+            loop {
+                match decoder.poll_decode() {
+                    Ok(Some((lsn, _))) => end_lsn = lsn,
+                    Ok(None) => break,
+                    Err(e) => {
+                        stop_reason = Some(e.to_string());
+                        break 'segments;
+                    }
+                }
+            }
+        }
+        if pos.segment_number(wal_seg_size) == segno {
+            stop_reason = Some(format!(
+                "segment {segno} is shorter than {wal_seg_size} bytes"
+            ));
+            break;
+        }
+    }
+
+    let torn_tail_end = last_nonzero_lsn(timeline_dir, wal_seg_size, end_lsn, last_segno)?;
+    let torn_tail_bytes = torn_tail_end.map_or(0, |lsn| lsn.0 + 1 - end_lsn.0);
+    Ok(Some(Verification {
+        start_lsn,
+        end_lsn,
+        stop_reason,
+        torn_tail_bytes,
+    }))
+}
+
+/// Zeroes out the WAL after `end_lsn` and removes the segments after it,
+/// leaving the segment of `end_lsn` partial. The zeroes are read back and checked
+/// before the segment is renamed.
+pub fn truncate_torn_tail(
+    timeline_dir: &Path,
+    wal_seg_size: usize,
+    end_lsn: Lsn,
+    no_sync: bool,
+) -> Result<()> {
+    let end_segno = end_lsn.segment_number(wal_seg_size);
+    for segno in list_segments(timeline_dir, wal_seg_size)? {
+        if segno > end_segno {
+            let (wal_file_path, wal_file_partial_path) =
+                wal_file_paths(timeline_dir, segno, wal_seg_size)?;
+            remove_if_exists(&wal_file_path)?;
+            remove_if_exists(&wal_file_partial_path)?;
+        }
+    }
+
+    let (wal_file_path, wal_file_partial_path) =
+        wal_file_paths(timeline_dir, end_segno, wal_seg_size)?;
+    let is_partial = wal_file_partial_path.exists();
+    let path = if is_partial {
+        &wal_file_partial_path
+    } else if wal_file_path.exists() {
+        &wal_file_path
+    } else {
+        return Ok(());
+    };
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let xlogoff = end_lsn.segment_offset(wal_seg_size);
+    file.seek(SeekFrom::Start(xlogoff as u64))?;
+    let tail_len = (wal_seg_size - xlogoff) as u64;
+    let mut writer = ChecksummedWriter::new(file);
+    io::copy(&mut io::repeat(0).take(tail_len), &mut writer)?;
+    let (mut file, crc) = writer.finish(SyncPolicy::Data.unless(no_sync))?;
+    file.seek(SeekFrom::Start(xlogoff as u64))?;
+    verify_checksum(&mut file, tail_len, crc)
+        .with_context(|| format!("failed to verify the zeroed tail of {}", path.display()))?;
+    if !is_partial {
+        fs::rename(wal_file_path, wal_file_partial_path)?;
+    }
+    Ok(())
+}
+
+/// Finds the start of the first record in the segment, skipping the
+/// continuation of a record from the previous segment. The page headers are
+/// the same in all the Postgres versions, but for `page_magic`.
+fn find_first_record(
+    segment: &mut File,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+    page_magic: u16,
+) -> Result<Option<Lsn>> {
+    let mut page = [0u8; XLOG_BLCKSZ];
+    for page_offset in (0..wal_seg_size).step_by(XLOG_BLCKSZ) {
+        segment.seek(SeekFrom::Start(page_offset as u64))?;
+        if let Err(e) = segment.read_exact(&mut page) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Ok(None);
+            }
+            return Err(e.into());
+        }
+        let hdr = XLogPageHeaderData::from_bytes(&mut &page[..])?;
+        if hdr.xlp_magic != page_magic {
+            // Not written
+            return Ok(None);
+        }
+        let hdr_size = if page_offset == 0 {
+            XLOG_SIZE_OF_XLOG_LONG_PHD
+        } else {
+            XLOG_SIZE_OF_XLOG_SHORT_PHD
+        };
+        let page_lsn = Lsn::from_segment_number(segno, wal_seg_size) + page_offset as u64;
+        if hdr.xlp_info & XLP_FIRST_IS_CONTRECORD == 0 {
+            return Ok(Some(page_lsn + hdr_size as u64));
+        }
+        let rem_len = Lsn(hdr.xlp_rem_len as u64).align().0 as usize;
+        if rem_len < XLOG_BLCKSZ - hdr_size {
+            return Ok(Some(page_lsn + (hdr_size + rem_len) as u64));
+        }
+        // The whole page is taken by the continuation
+    }
+    Ok(None)
+}
+
+/// Returns the position of the last non-zero byte after `from`, up to the end
+/// of the segment `last_segno`.
+fn last_nonzero_lsn(
+    timeline_dir: &Path,
+    wal_seg_size: usize,
+    from: Lsn,
+    last_segno: XLogSegNo,
+) -> Result<Option<Lsn>> {
+    let mut last_nonzero = None;
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    for segno in from.segment_number(wal_seg_size)..=last_segno {
+        let Some(mut segment) = open_segment(timeline_dir, segno, wal_seg_size)? else {
+            continue;
+        };
+        let mut pos = if segno == from.segment_number(wal_seg_size) {
+            from
+        } else {
+            Lsn::from_segment_number(segno, wal_seg_size)
+        };
+        segment.seek(SeekFrom::Start(pos.segment_offset(wal_seg_size) as u64))?;
+        loop {
+            let n = segment.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if let Some(i) = buf[..n].iter().rposition(|b| *b != 0) {
+                last_nonzero = Some(pos + i as u64);
+            }
+            pos += n as u64;
+        }
+    }
+    Ok(last_nonzero)
+}
+
+/// Lists the numbers of the segments in the directory, in ascending order.
+fn list_segments(timeline_dir: &Path, wal_seg_size: usize) -> Result<Vec<XLogSegNo>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(timeline_dir)
+        .with_context(|| format!("failed to list {}", timeline_dir.display()))?
+    {
+        let fname = entry?.file_name();
+        if let Some(fname) = fname.to_str() {
+            if IsXLogFileName(fname) || IsPartialXLogFileName(fname) {
+                segments.push(XLogFromFileName(fname, wal_seg_size).0);
+            }
+        }
+    }
+    segments.sort_unstable();
+    segments.dedup();
+    Ok(segments)
+}
+
+/// Opens the .partial or the full segment file, if present.
+fn open_segment(
+    timeline_dir: &Path,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+) -> Result<Option<File>> {
+    let (wal_file_path, wal_file_partial_path) = wal_file_paths(timeline_dir, segno, wal_seg_size)?;
+    for path in [wal_file_partial_path, wal_file_path] {
+        match File::open(&path) {
+            Ok(file) => return Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+        }
+    }
+    Ok(None)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("failed to remove {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use postgres_ffi::{
+        encode_logical_message, generate_wal_segment, XLogFileName, PG_TLI, WAL_SEGMENT_SIZE,
+    };
+
+    const SEGNO: XLogSegNo = 1;
+
+    /// Writes a partial segment of the Postgres version with a single record,
+    /// followed by `tail`. Returns the start and the end of the record.
+    fn write_segment(dir: &Path, pg_version: u32, tail: &[u8]) -> (Lsn, Lsn) {
+        let seg_start = Lsn::from_segment_number(SEGNO, WAL_SEGMENT_SIZE);
+        let mut segment = generate_wal_segment(SEGNO, 0, pg_version, seg_start)
+            .unwrap()
+            .to_vec();
+        let record = encode_logical_message("prefix", "message");
+        let start_lsn = seg_start + XLOG_SIZE_OF_XLOG_LONG_PHD as u64;
+        let start = start_lsn.segment_offset(WAL_SEGMENT_SIZE);
+        segment[start..start + record.len()].copy_from_slice(&record);
+        let end_lsn = (start_lsn + record.len() as u64).align();
+        let end = end_lsn.segment_offset(WAL_SEGMENT_SIZE);
+        segment[end..end + tail.len()].copy_from_slice(tail);
+        let name = XLogFileName(PG_TLI, SEGNO, WAL_SEGMENT_SIZE) + ".partial";
+        fs::write(dir.join(name), segment).unwrap();
+        (start_lsn, end_lsn)
+    }
+
+    #[test]
+    fn intact_wal() {
+        for pg_version in [14, 15] {
+            let dir = tempfile::tempdir().unwrap();
+            let (start_lsn, end_lsn) = write_segment(dir.path(), pg_version, &[]);
+            let verification = verify_wal(dir.path(), WAL_SEGMENT_SIZE, pg_version, Lsn(0))
+                .unwrap()
+                .unwrap();
+            assert_eq!(verification.start_lsn, start_lsn);
+            assert_eq!(verification.end_lsn, end_lsn);
+            assert_eq!(verification.torn_tail_bytes, 0);
+        }
+    }
+
+    #[test]
+    fn torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let (start_lsn, end_lsn) = write_segment(dir.path(), 14, b"torn");
+        let verification = verify_wal(dir.path(), WAL_SEGMENT_SIZE, 14, start_lsn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(verification.end_lsn, end_lsn);
+        assert_eq!(verification.torn_tail_bytes, 4);
+
+        // A leftover of the next segment is a part of the tail too
+        let next = XLogFileName(PG_TLI, SEGNO + 1, WAL_SEGMENT_SIZE) + ".partial";
+        fs::write(dir.path().join(&next), b"next").unwrap();
+        let verification = verify_wal(dir.path(), WAL_SEGMENT_SIZE, 14, start_lsn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            verification.torn_tail_bytes,
+            end_lsn.remaining_in_segment(WAL_SEGMENT_SIZE) + 4
+        );
+
+        truncate_torn_tail(dir.path(), WAL_SEGMENT_SIZE, end_lsn, true).unwrap();
+        assert!(!dir.path().join(&next).exists());
+        let verification = verify_wal(dir.path(), WAL_SEGMENT_SIZE, 14, start_lsn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(verification.end_lsn, end_lsn);
+        assert_eq!(verification.torn_tail_bytes, 0);
+    }
+}
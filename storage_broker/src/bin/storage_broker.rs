@@ -8,8 +8,9 @@
 //! Message is dropped if subscriber can't consume it, not affecting other
 //! subscribers.
 //!
-//! Only safekeeper message is supported, but it is not hard to add something
-//! else with generics.
+//! Safekeepers publish the state of their timelines, and pageservers publish
+//! the timelines they want to receive WAL of. Each kind of message has its own
+//! registry of publishers and subscribers.
 use clap::{command, Parser};
 use futures_core::Stream;
 use futures_util::StreamExt;
@@ -37,8 +38,12 @@ use utils::signals::ShutdownSignals;
 use metrics::{Encoder, TextEncoder};
 use storage_broker::metrics::{NUM_PUBS, NUM_SUBS_ALL, NUM_SUBS_TIMELINE};
 use storage_broker::proto::broker_service_server::{BrokerService, BrokerServiceServer};
+use storage_broker::proto::subscribe_pageserver_info_request::SubscriptionKey as ProtoPageserverSubscriptionKey;
 use storage_broker::proto::subscribe_safekeeper_info_request::SubscriptionKey as ProtoSubscriptionKey;
-use storage_broker::proto::{SafekeeperTimelineInfo, SubscribeSafekeeperInfoRequest};
+use storage_broker::proto::{
+    PageserverTimelineInfo, SafekeeperTimelineInfo, SubscribePageserverInfoRequest,
+    SubscribeSafekeeperInfoRequest, TenantTimelineId as ProtoTenantTimelineId,
+};
 use storage_broker::{
     parse_proto_ttid, EitherBody, DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_LISTEN_ADDR,
 };
@@ -91,36 +96,60 @@ impl SubscriptionKey {
             }
         }
     }
+
+    pub fn from_proto_pageserver_subscription_key(
+        key: ProtoPageserverSubscriptionKey,
+    ) -> Result<Self, Status> {
+        match key {
+            ProtoPageserverSubscriptionKey::All(_) => Ok(SubscriptionKey::All),
+            ProtoPageserverSubscriptionKey::TenantTimelineId(proto_ttid) => {
+                Ok(SubscriptionKey::Timeline(parse_proto_ttid(&proto_ttid)?))
+            }
+        }
+    }
+}
+
+// Message passed from publishers to subscribers.
+trait Message: Clone + Send + Sync + 'static {
+    fn tenant_timeline_id(&self) -> Option<&ProtoTenantTimelineId>;
+}
+
+impl Message for SafekeeperTimelineInfo {
+    fn tenant_timeline_id(&self) -> Option<&ProtoTenantTimelineId> {
+        self.tenant_timeline_id.as_ref()
+    }
+}
+
+impl Message for PageserverTimelineInfo {
+    fn tenant_timeline_id(&self) -> Option<&ProtoTenantTimelineId> {
+        self.tenant_timeline_id.as_ref()
+    }
 }
 
 // Channel to timeline subscribers.
-struct ChanToTimelineSub {
-    chan: broadcast::Sender<SafekeeperTimelineInfo>,
+struct ChanToTimelineSub<M> {
+    chan: broadcast::Sender<M>,
     // Tracked separately to know when delete the shmem entry. receiver_count()
     // is unhandy for that as unregistering and dropping the receiver side
     // happens at different moments.
     num_subscribers: u64,
 }
 
-struct SharedState {
+// The metrics are shared by the registries of all message kinds, hence they
+// are incremented and decremented rather than set.
+struct SharedState<M> {
     next_pub_id: PubId,
-    num_pubs: i64,
     next_sub_id: SubId,
-    num_subs_to_timelines: i64,
-    chans_to_timeline_subs: HashMap<TenantTimelineId, ChanToTimelineSub>,
-    num_subs_to_all: i64,
-    chan_to_all_subs: broadcast::Sender<SafekeeperTimelineInfo>,
+    chans_to_timeline_subs: HashMap<TenantTimelineId, ChanToTimelineSub<M>>,
+    chan_to_all_subs: broadcast::Sender<M>,
 }
 
-impl SharedState {
+impl<M: Message> SharedState<M> {
     pub fn new(all_keys_chan_size: usize) -> Self {
         SharedState {
             next_pub_id: 0,
-            num_pubs: 0,
             next_sub_id: 0,
-            num_subs_to_timelines: 0,
             chans_to_timeline_subs: HashMap::new(),
-            num_subs_to_all: 0,
             chan_to_all_subs: broadcast::channel(all_keys_chan_size).0,
         }
     }
@@ -129,15 +158,13 @@ impl SharedState {
     pub fn register_publisher(&mut self) -> PubId {
         let pub_id = self.next_pub_id;
         self.next_pub_id += 1;
-        self.num_pubs += 1;
-        NUM_PUBS.set(self.num_pubs);
+        NUM_PUBS.inc();
         pub_id
     }
 
     // Unregister publisher.
     pub fn unregister_publisher(&mut self) {
-        self.num_pubs -= 1;
-        NUM_PUBS.set(self.num_pubs);
+        NUM_PUBS.dec();
     }
 
     // Register new subscriber.
@@ -145,18 +172,16 @@ impl SharedState {
         &mut self,
         sub_key: SubscriptionKey,
         timeline_chan_size: usize,
-    ) -> (SubId, broadcast::Receiver<SafekeeperTimelineInfo>) {
+    ) -> (SubId, broadcast::Receiver<M>) {
         let sub_id = self.next_sub_id;
         self.next_sub_id += 1;
         let sub_rx = match sub_key {
             SubscriptionKey::All => {
-                self.num_subs_to_all += 1;
-                NUM_SUBS_ALL.set(self.num_subs_to_all);
+                NUM_SUBS_ALL.inc();
                 self.chan_to_all_subs.subscribe()
             }
             SubscriptionKey::Timeline(ttid) => {
-                self.num_subs_to_timelines += 1;
-                NUM_SUBS_TIMELINE.set(self.num_subs_to_timelines);
+                NUM_SUBS_TIMELINE.inc();
                 // Create new broadcast channel for this key, or subscriber to
                 // the existing one.
                 let chan_to_timeline_sub =
@@ -176,13 +201,9 @@ impl SharedState {
     // Unregister the subscriber.
     pub fn unregister_subscriber(&mut self, sub_key: SubscriptionKey) {
         match sub_key {
-            SubscriptionKey::All => {
-                self.num_subs_to_all -= 1;
-                NUM_SUBS_ALL.set(self.num_subs_to_all);
-            }
+            SubscriptionKey::All => NUM_SUBS_ALL.dec(),
             SubscriptionKey::Timeline(ttid) => {
-                self.num_subs_to_timelines -= 1;
-                NUM_SUBS_TIMELINE.set(self.num_subs_to_timelines);
+                NUM_SUBS_TIMELINE.dec();
 
                 // Remove from the map, destroying the channel, if we are the
                 // last subscriber to this timeline.
@@ -202,15 +223,31 @@ impl SharedState {
 }
 
 // SharedState wrapper.
-#[derive(Clone)]
-struct Registry {
-    shared_state: Arc<RwLock<SharedState>>,
+struct Registry<M> {
+    shared_state: Arc<RwLock<SharedState<M>>>,
     timeline_chan_size: usize,
 }
 
-impl Registry {
+// Derived Clone would require M: Clone.
+impl<M> Clone for Registry<M> {
+    fn clone(&self) -> Self {
+        Registry {
+            shared_state: self.shared_state.clone(),
+            timeline_chan_size: self.timeline_chan_size,
+        }
+    }
+}
+
+impl<M: Message> Registry<M> {
+    pub fn new(all_keys_chan_size: usize, timeline_chan_size: usize) -> Self {
+        Registry {
+            shared_state: Arc::new(RwLock::new(SharedState::new(all_keys_chan_size))),
+            timeline_chan_size,
+        }
+    }
+
     // Register new publisher in shared state.
-    pub fn register_publisher(&self, remote_addr: SocketAddr) -> Publisher {
+    pub fn register_publisher(&self, remote_addr: SocketAddr) -> Publisher<M> {
         let pub_id = self.shared_state.write().register_publisher();
         info!("publication started id={} addr={:?}", pub_id, remote_addr);
         Publisher {
@@ -220,7 +257,7 @@ impl Registry {
         }
     }
 
-    pub fn unregister_publisher(&self, publisher: &Publisher) {
+    pub fn unregister_publisher(&self, publisher: &Publisher<M>) {
         self.shared_state.write().unregister_publisher();
         info!(
             "publication ended id={} addr={:?}",
@@ -233,7 +270,7 @@ impl Registry {
         &self,
         sub_key: SubscriptionKey,
         remote_addr: SocketAddr,
-    ) -> Subscriber {
+    ) -> Subscriber<M> {
         let (sub_id, sub_rx) = self
             .shared_state
             .write()
@@ -252,7 +289,7 @@ impl Registry {
     }
 
     // Unregister the subscriber
-    pub fn unregister_subscriber(&self, subscriber: &Subscriber) {
+    pub fn unregister_subscriber(&self, subscriber: &Subscriber<M>) {
         self.shared_state
             .write()
             .unregister_subscriber(subscriber.key);
@@ -264,34 +301,61 @@ impl Registry {
 }
 
 // Private subscriber state.
-struct Subscriber {
+struct Subscriber<M: Message> {
     id: SubId,
     key: SubscriptionKey,
     // Subscriber receives messages from publishers here.
-    sub_rx: broadcast::Receiver<SafekeeperTimelineInfo>,
+    sub_rx: broadcast::Receiver<M>,
     // to unregister itself from shared state in Drop
-    registry: Registry,
+    registry: Registry<M>,
     // for logging
     remote_addr: SocketAddr,
 }
 
-impl Drop for Subscriber {
+impl<M: Message> Subscriber<M> {
+    // Transform rx into stream with item = Result, as subscription methods demand.
+    fn into_stream(mut self) -> impl Stream<Item = Result<M, Status>> + Send + 'static {
+        async_stream::try_stream! {
+            let mut warn_interval = time::interval(Duration::from_millis(1000));
+            let mut missed_msgs: u64 = 0;
+            loop {
+                match self.sub_rx.recv().await {
+                    Ok(info) => yield info,
+                    Err(RecvError::Lagged(skipped_msg)) => {
+                        missed_msgs += skipped_msg;
+                        if (futures::poll!(Box::pin(warn_interval.tick()))).is_ready() {
+                            warn!("subscription id={}, key={:?} addr={:?} dropped {} messages, channel is full",
+                                self.id, self.key, self.remote_addr, missed_msgs);
+                            missed_msgs = 0;
+                        }
+                    }
+                    Err(RecvError::Closed) => {
+                        // can't happen, we never drop the channel while there is a subscriber
+                        Err(Status::new(Code::Internal, "channel unexpectantly closed"))?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<M: Message> Drop for Subscriber<M> {
     fn drop(&mut self) {
         self.registry.unregister_subscriber(self);
     }
 }
 
 // Private publisher state
-struct Publisher {
+struct Publisher<M: Message> {
     id: PubId,
-    registry: Registry,
+    registry: Registry<M>,
     // for logging
     remote_addr: SocketAddr,
 }
 
-impl Publisher {
+impl<M: Message> Publisher<M> {
     // Send msg to relevant subscribers.
-    pub fn send_msg(&mut self, msg: &SafekeeperTimelineInfo) -> Result<(), Status> {
+    pub fn send_msg(&mut self, msg: &M) -> Result<(), Status> {
         // send message to subscribers for everything
         let shared_state = self.registry.shared_state.read();
         // Err means there is no subscribers, it is fine.
@@ -299,7 +363,7 @@ impl Publisher {
 
         // send message to per timeline subscribers
         let ttid =
-            parse_proto_ttid(msg.tenant_timeline_id.as_ref().ok_or_else(|| {
+            parse_proto_ttid(msg.tenant_timeline_id().ok_or_else(|| {
                 Status::new(Code::InvalidArgument, "missing tenant_timeline_id")
             })?)?;
         if let Some(subs) = shared_state.chans_to_timeline_subs.get(&ttid) {
@@ -313,14 +377,38 @@ impl Publisher {
     }
 }
 
-impl Drop for Publisher {
+impl<M: Message> Drop for Publisher<M> {
     fn drop(&mut self) {
         self.registry.unregister_publisher(self);
     }
 }
 
+// Forward the published messages to subscribers until the stream is closed.
+async fn publish<M: Message>(
+    registry: &Registry<M>,
+    request: Request<tonic::Streaming<M>>,
+) -> Result<Response<()>, Status> {
+    let remote_addr = request
+        .remote_addr()
+        .expect("TCPConnectInfo inserted by handler");
+    let mut publisher = registry.register_publisher(remote_addr);
+
+    let mut stream = request.into_inner();
+
+    loop {
+        match stream.next().await {
+            Some(Ok(msg)) => publisher.send_msg(&msg)?,
+            Some(Err(e)) => return Err(e), // grpc error from the stream
+            None => break,                 // closed stream
+        }
+    }
+
+    Ok(Response::new(()))
+}
+
 struct Broker {
-    registry: Registry,
+    sk_registry: Registry<SafekeeperTimelineInfo>,
+    ps_registry: Registry<PageserverTimelineInfo>,
 }
 
 #[tonic::async_trait]
@@ -329,22 +417,7 @@ impl BrokerService for Broker {
         &self,
         request: Request<tonic::Streaming<SafekeeperTimelineInfo>>,
     ) -> Result<Response<()>, Status> {
-        let remote_addr = request
-            .remote_addr()
-            .expect("TCPConnectInfo inserted by handler");
-        let mut publisher = self.registry.register_publisher(remote_addr);
-
-        let mut stream = request.into_inner();
-
-        loop {
-            match stream.next().await {
-                Some(Ok(msg)) => publisher.send_msg(&msg)?,
-                Some(Err(e)) => return Err(e), // grpc error from the stream
-                None => break,                 // closed stream
-            }
-        }
-
-        Ok(Response::new(()))
+        publish(&self.sk_registry, request).await
     }
 
     type SubscribeSafekeeperInfoStream =
@@ -362,33 +435,39 @@ impl BrokerService for Broker {
             .subscription_key
             .ok_or_else(|| Status::new(Code::InvalidArgument, "missing subscription key"))?;
         let sub_key = SubscriptionKey::from_proto_subscription_key(proto_key)?;
-        let mut subscriber = self.registry.register_subscriber(sub_key, remote_addr);
+        let subscriber = self.sk_registry.register_subscriber(sub_key, remote_addr);
 
-        // transform rx into stream with item = Result, as method result demands
-        let output = async_stream::try_stream! {
-            let mut warn_interval = time::interval(Duration::from_millis(1000));
-            let mut missed_msgs: u64 = 0;
-            loop {
-                match subscriber.sub_rx.recv().await {
-                    Ok(info) => yield info,
-                    Err(RecvError::Lagged(skipped_msg)) => {
-                        missed_msgs += skipped_msg;
-                        if (futures::poll!(Box::pin(warn_interval.tick()))).is_ready() {
-                            warn!("subscription id={}, key={:?} addr={:?} dropped {} messages, channel is full",
-                                subscriber.id, subscriber.key, subscriber.remote_addr, missed_msgs);
-                            missed_msgs = 0;
-                        }
-                    }
-                    Err(RecvError::Closed) => {
-                        // can't happen, we never drop the channel while there is a subscriber
-                        Err(Status::new(Code::Internal, "channel unexpectantly closed"))?;
-                    }
-                }
-            }
-        };
+        Ok(Response::new(
+            Box::pin(subscriber.into_stream()) as Self::SubscribeSafekeeperInfoStream
+        ))
+    }
+
+    async fn publish_pageserver_info(
+        &self,
+        request: Request<tonic::Streaming<PageserverTimelineInfo>>,
+    ) -> Result<Response<()>, Status> {
+        publish(&self.ps_registry, request).await
+    }
+
+    type SubscribePageserverInfoStream =
+        Pin<Box<dyn Stream<Item = Result<PageserverTimelineInfo, Status>> + Send + 'static>>;
+
+    async fn subscribe_pageserver_info(
+        &self,
+        request: Request<SubscribePageserverInfoRequest>,
+    ) -> Result<Response<Self::SubscribePageserverInfoStream>, Status> {
+        let remote_addr = request
+            .remote_addr()
+            .expect("TCPConnectInfo inserted by handler");
+        let proto_key = request
+            .into_inner()
+            .subscription_key
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "missing subscription key"))?;
+        let sub_key = SubscriptionKey::from_proto_pageserver_subscription_key(proto_key)?;
+        let subscriber = self.ps_registry.register_subscriber(sub_key, remote_addr);
 
         Ok(Response::new(
-            Box::pin(output) as Self::SubscribeSafekeeperInfoStream
+            Box::pin(subscriber.into_stream()) as Self::SubscribePageserverInfoStream
         ))
     }
 }
@@ -448,12 +527,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         })
     });
 
-    let registry = Registry {
-        shared_state: Arc::new(RwLock::new(SharedState::new(args.all_keys_chan_size))),
-        timeline_chan_size: args.timeline_chan_size,
-    };
     let storage_broker_impl = Broker {
-        registry: registry.clone(),
+        sk_registry: Registry::new(args.all_keys_chan_size, args.timeline_chan_size),
+        ps_registry: Registry::new(args.all_keys_chan_size, args.timeline_chan_size),
     };
     let storage_broker_server = BrokerServiceServer::new(storage_broker_impl);
 
@@ -508,7 +584,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use storage_broker::proto::TenantTimelineId as ProtoTenantTimelineId;
     use tokio::sync::broadcast::error::TryRecvError;
     use utils::id::{TenantId, TimelineId};
 
@@ -528,6 +603,7 @@ mod tests {
             safekeeper_connstr: "neon-1-sk-1.local:7676".to_owned(),
             local_start_lsn: 0,
             availability_zone: None,
+            standby_horizon: 0,
         }
     }
 
@@ -543,10 +619,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_registry() {
-        let registry = Registry {
-            shared_state: Arc::new(RwLock::new(SharedState::new(16))),
-            timeline_chan_size: 16,
-        };
+        let registry: Registry<SafekeeperTimelineInfo> = Registry::new(16, 16);
 
         // subscribe to timeline 2
         let ttid_2 = TenantTimelineId {
@@ -58,3 +58,4 @@ class NeonBroker:
         if self.handle is not None:
             self.handle.terminate()
             self.handle.wait()
+            self.handle = None
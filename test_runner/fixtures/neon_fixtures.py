@@ -442,6 +442,15 @@ class NeonEnvBuilder:
         self.num_safekeepers = num_safekeepers
         self.safekeepers_id_start = safekeepers_id_start
         self.safekeepers_enable_fsync = safekeepers_enable_fsync
+        self.safekeepers_max_disk_usage: Optional[int] = None
+        self.safekeepers_push_wal_to_pageserver = False
+        self.safekeepers_verify_wal_on_startup = False
+        self.safekeepers_truncate_torn_wal = False
+        # Ids of the safekeepers to forward the WAL to, by safekeeper id.
+        self.safekeepers_wal_forward_to: Dict[int, List[int]] = {}
+        self.safekeepers_partial_backup_interval: Optional[str] = None
+        # Fault injection settings as TOML inline table, see `safekeeper --help`.
+        self.safekeepers_chaos: Optional[str] = None
         self.auth_enabled = auth_enabled
         self.default_branch_name = default_branch_name
         self.env: Optional[NeonEnv] = None
@@ -847,6 +856,48 @@ class NeonEnv:
                 auth_enabled = true
                 """
                 )
+            if config.safekeepers_max_disk_usage is not None:
+                toml += textwrap.dedent(
+                    f"""
+                max_disk_usage = {config.safekeepers_max_disk_usage}
+                """
+                )
+            if config.safekeepers_push_wal_to_pageserver:
+                toml += textwrap.dedent(
+                    """
+                push_wal_to_pageserver = true
+                """
+                )
+            if id in config.safekeepers_wal_forward_to:
+                toml += textwrap.dedent(
+                    f"""
+                wal_forward_to = {config.safekeepers_wal_forward_to[id]}
+                """
+                )
+            if config.safekeepers_verify_wal_on_startup:
+                toml += textwrap.dedent(
+                    """
+                verify_wal_on_startup = true
+                """
+                )
+            if config.safekeepers_truncate_torn_wal:
+                toml += textwrap.dedent(
+                    """
+                truncate_torn_wal = true
+                """
+                )
+            if config.safekeepers_partial_backup_interval is not None:
+                toml += textwrap.dedent(
+                    f"""
+                partial_backup_interval = "{config.safekeepers_partial_backup_interval}"
+                """
+                )
+            if config.safekeepers_chaos is not None:
+                toml += textwrap.dedent(
+                    f"""
+                chaos = '{config.safekeepers_chaos}'
+                """
+                )
             if (
                 bool(self.remote_storage_users & RemoteStorageUsers.SAFEKEEPER)
                 and self.remote_storage is not None
@@ -2578,6 +2629,7 @@ class SafekeeperTimelineStatus:
     backup_lsn: Lsn
     peer_horizon_lsn: Lsn
     remote_consistent_lsn: Lsn
+    wal_forwards: List[Dict[str, Any]]
 
 
 @dataclass
@@ -2586,6 +2638,10 @@ class SafekeeperMetrics:
     # As a consequence, values may differ from real original int64s.
     flush_lsn_inexact: Dict[Tuple[TenantId, TimelineId], int] = field(default_factory=dict)
     commit_lsn_inexact: Dict[Tuple[TenantId, TimelineId], int] = field(default_factory=dict)
+    # Keyed by (tenant_id, timeline_id, peer safekeeper id)
+    peer_flush_lag_bytes: Dict[Tuple[TenantId, TimelineId, int], int] = field(
+        default_factory=dict
+    )
 
 
 class SafekeeperHttpClient(requests.Session):
@@ -2604,7 +2660,7 @@ class SafekeeperHttpClient(requests.Session):
 
     def debug_dump(self, params: Optional[Dict[str, str]] = None) -> Dict[str, Any]:
         params = params or {}
-        res = self.get(f"http://localhost:{self.port}/v1/debug_dump", params=params)
+        res = self.get(f"http://localhost:{self.port}/v1/debug/dump", params=params)
         res.raise_for_status()
         res_json = res.json()
         assert isinstance(res_json, dict)
@@ -2633,6 +2689,19 @@ class SafekeeperHttpClient(requests.Session):
         res = self.post(f"http://localhost:{self.port}/v1/tenant/timeline", json=body)
         res.raise_for_status()
 
+    def copy_timeline(
+        self,
+        tenant_id: TenantId,
+        source_timeline_id: TimelineId,
+        target_timeline_id: TimelineId,
+        until_lsn: Lsn,
+    ):
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{source_timeline_id}/copy",
+            json={"target_timeline_id": str(target_timeline_id), "until_lsn": str(until_lsn)},
+        )
+        res.raise_for_status()
+
     def timeline_status(
         self, tenant_id: TenantId, timeline_id: TimelineId
     ) -> SafekeeperTimelineStatus:
@@ -2648,8 +2717,18 @@ class SafekeeperHttpClient(requests.Session):
             backup_lsn=Lsn(resj["backup_lsn"]),
             peer_horizon_lsn=Lsn(resj["peer_horizon_lsn"]),
             remote_consistent_lsn=Lsn(resj["remote_consistent_lsn"]),
+            wal_forwards=resj.get("wal_forwards", []),
         )
 
+    def timeline_term_history(self, tenant_id: TenantId, timeline_id: TimelineId) -> Dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/term_history"
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def record_safekeeper_info(self, tenant_id: TenantId, timeline_id: TimelineId, body):
         res = self.post(
             f"http://localhost:{self.port}/v1/record_safekeeper_info/{tenant_id}/{timeline_id}",
@@ -2657,9 +2736,12 @@ class SafekeeperHttpClient(requests.Session):
         )
         res.raise_for_status()
 
-    def timeline_delete_force(self, tenant_id: TenantId, timeline_id: TimelineId) -> Dict[Any, Any]:
+    def timeline_delete_force(
+        self, tenant_id: TenantId, timeline_id: TimelineId, tombstone: bool = False
+    ) -> Dict[Any, Any]:
         res = self.delete(
-            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}"
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}",
+            params={"tombstone": "true"} if tombstone else None,
         )
         res.raise_for_status()
         res_json = res.json()
@@ -2698,6 +2780,14 @@ class SafekeeperHttpClient(requests.Session):
             metrics.commit_lsn_inexact[
                 (TenantId(match.group(1)), TimelineId(match.group(2)))
             ] = int(match.group(3))
+        for match in re.finditer(
+            r'^safekeeper_peer_flush_lag_bytes{peer_id="(\d+)",tenant_id="([0-9a-f]+)",timeline_id="([0-9a-f]+)"} (\S+)$',
+            all_metrics_text,
+            re.MULTILINE,
+        ):
+            metrics.peer_flush_lag_bytes[
+                (TenantId(match.group(2)), TimelineId(match.group(3)), int(match.group(1)))
+            ] = int(match.group(4))
         return metrics
 
 
@@ -2725,7 +2815,7 @@ def pytest_addoption(parser: Parser):
 
 
 SMALL_DB_FILE_NAME_REGEX: re.Pattern = re.compile(  # type: ignore[type-arg]
-    r"config|metadata|.+\.(?:toml|pid|json|sql)"
+    r"config|labels|clean_shutdown|metadata|metadata\.log|.+\.(?:toml|pid|json|sql)"
 )
 
 
@@ -4,7 +4,8 @@ import json
 import time
 from collections import defaultdict
 from dataclasses import dataclass
-from typing import Any, Dict, List, Optional, Tuple
+from pathlib import Path
+from typing import Any, Dict, List, Optional, Tuple, Union
 
 import requests
 
@@ -139,6 +140,36 @@ class PageserverHttpClient(requests.Session):
     def check_status(self):
         self.get(f"http://localhost:{self.port}/v1/status").raise_for_status()
 
+    def check_status_live(self):
+        self.get(f"http://localhost:{self.port}/v1/status/live").raise_for_status()
+
+    def status_ready(self) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/status/ready")
+        # 503 means "not ready yet", and still carries the list of pending checks
+        if res.status_code != 503:
+            self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def reload_config(self) -> Dict[str, Any]:
+        res = self.post(f"http://localhost:{self.port}/v1/reload_config")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def shutdown(self):
+        res = self.post(f"http://localhost:{self.port}/v1/shutdown")
+        self.verbose_error(res)
+
+    def background_tasks(self) -> List[Dict[str, Any]]:
+        res = self.get(f"http://localhost:{self.port}/v1/background_tasks")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
     def configure_failpoints(self, config_strings: Tuple[str, str] | List[Tuple[str, str]]):
         self.is_testing_enabled_or_skip()
 
@@ -159,8 +190,15 @@ class PageserverHttpClient(requests.Session):
         assert res_json is None
         return res_json
 
-    def tenant_list(self) -> List[Dict[Any, Any]]:
-        res = self.get(f"http://localhost:{self.port}/v1/tenant")
+    def tenant_list(
+        self, label: Optional[str] = None, region_id: Optional[Union[int, str]] = None
+    ) -> List[Dict[Any, Any]]:
+        params = {}
+        if label is not None:
+            params["label"] = label
+        if region_id is not None:
+            params["region_id"] = str(region_id)
+        res = self.get(f"http://localhost:{self.port}/v1/tenant", params=params)
         self.verbose_error(res)
         res_json = res.json()
         assert isinstance(res_json, list)
@@ -222,6 +260,29 @@ class PageserverHttpClient(requests.Session):
         res = self.post(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/ignore")
         self.verbose_error(res)
 
+    def tenant_list_ignored(self) -> List[TenantId]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/ignored")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return [TenantId(tenant_id) for tenant_id in res_json]
+
+    def tenant_wait_graph(self, tenant_id: TenantId) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/wait_graph")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def tenant_report_wait_edges(
+        self, tenant_id: TenantId, region: Union[int, str], edges: List[Dict[str, Any]]
+    ):
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/wait_graph/{region}",
+            json={"edges": edges},
+        )
+        self.verbose_error(res)
+
     def tenant_status(self, tenant_id: TenantId) -> Dict[Any, Any]:
         res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id}")
         self.verbose_error(res)
@@ -289,12 +350,15 @@ class PageserverHttpClient(requests.Session):
         tenant_id: TenantId,
         include_non_incremental_logical_size: bool = False,
         include_timeline_dir_layer_file_size_sum: bool = False,
+        label: Optional[str] = None,
     ) -> List[Dict[str, Any]]:
         params = {}
         if include_non_incremental_logical_size:
             params["include-non-incremental-logical-size"] = "true"
         if include_timeline_dir_layer_file_size_sum:
             params["include-timeline-dir-layer-file-size-sum"] = "true"
+        if label is not None:
+            params["label"] = label
 
         res = self.get(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline", params=params
@@ -311,6 +375,9 @@ class PageserverHttpClient(requests.Session):
         new_timeline_id: TimelineId,
         ancestor_timeline_id: Optional[TimelineId] = None,
         ancestor_start_lsn: Optional[Lsn] = None,
+        labels: Optional[Dict[str, str]] = None,
+        region_id: Optional[Union[int, str]] = None,
+        materialize_in_regions: Optional[List[Union[int, str]]] = None,
         **kwargs,
     ) -> Dict[Any, Any]:
         body: Dict[str, Any] = {
@@ -320,6 +387,12 @@ class PageserverHttpClient(requests.Session):
         }
         if pg_version != PgVersion.NOT_SET:
             body["pg_version"] = int(pg_version)
+        if labels is not None:
+            body["labels"] = labels
+        if region_id is not None:
+            body["region_id"] = region_id
+        if materialize_in_regions is not None:
+            body["materialize_in_regions"] = materialize_in_regions
 
         res = self.post(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline", json=body, **kwargs
@@ -335,6 +408,93 @@ class PageserverHttpClient(requests.Session):
         assert isinstance(res_json, dict)
         return res_json
 
+    def timeline_diff(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        from_lsn: Lsn,
+        to_lsn: Optional[Lsn] = None,
+    ) -> Dict[str, Any]:
+        params = {"from_lsn": str(from_lsn)}
+        if to_lsn is not None:
+            params["to_lsn"] = str(to_lsn)
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/diff",
+            params=params,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_computes(self, tenant_id: TenantId, timeline_id: TimelineId) -> List[Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/computes",
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
+    def timeline_snapshot_export(self, tenant_id: TenantId, timeline_id: TimelineId, path: Path):
+        """Write the timeline's snapshot archive to `path`."""
+        with self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/snapshot",
+            stream=True,
+        ) as res:
+            self.verbose_error(res)
+            with open(path, "wb") as f:
+                for chunk in res.iter_content(chunk_size=1024 * 1024):
+                    f.write(chunk)
+
+    def timeline_snapshot_import(self, tenant_id: TenantId, timeline_id: TimelineId, path: Path):
+        """Create a new tenant from the snapshot archive at `path`."""
+        with open(path, "rb") as f:
+            res = self.put(
+                f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/snapshot",
+                data=f,
+            )
+        self.verbose_error(res)
+
+    def set_timeline_labels(
+        self, tenant_id: TenantId, timeline_id: TimelineId, labels: Dict[str, str]
+    ):
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/labels",
+            json=labels,
+        )
+        self.verbose_error(res)
+
+    def timeline_config(self, tenant_id: TenantId, timeline_id: TimelineId) -> Dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/config"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def set_timeline_config(
+        self, tenant_id: TenantId, timeline_id: TimelineId, config: Dict[str, Any]
+    ):
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/config",
+            json=config,
+        )
+        self.verbose_error(res)
+
+    def timeline_suspend(self, tenant_id: TenantId, timeline_id: TimelineId):
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/suspend"
+        )
+        self.verbose_error(res)
+
+    def timeline_resume(self, tenant_id: TenantId, timeline_id: TimelineId):
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/resume"
+        )
+        self.verbose_error(res)
+
     def timeline_detail(
         self,
         tenant_id: TenantId,
@@ -587,6 +747,23 @@ class PageserverHttpClient(requests.Session):
 
         assert res.status_code == 200
 
+    def repair_layer(
+        self, tenant_id: TenantId, timeline_id: TimelineId, layer_name: str
+    ) -> Dict[str, Any]:
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/layer/{layer_name}/repair",
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_check_layer_map(self, tenant_id: TenantId, timeline_id: TimelineId):
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/check_layer_map",
+        )
+        self.verbose_error(res)
+
     def evict_all_layers(self, tenant_id: TenantId, timeline_id: TimelineId):
         info = self.layer_map_info(tenant_id, timeline_id)
         for layer in info.historic_layers:
@@ -600,6 +777,32 @@ class PageserverHttpClient(requests.Session):
         self.verbose_error(res)
         return res.json()
 
+    def page_cache_info(self) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/page_cache")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def top_tenants(self, by: Optional[str] = None, k: Optional[int] = None) -> Dict[str, Any]:
+        params: Dict[str, Any] = {}
+        if by is not None:
+            params["by"] = by
+        if k is not None:
+            params["k"] = k
+        res = self.get(f"http://localhost:{self.port}/v1/debug/top_tenants", params=params)
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def page_cache_resize(self, size: int) -> Dict[str, Any]:
+        res = self.put(f"http://localhost:{self.port}/v1/page_cache", json={"size": size})
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def tenant_break(self, tenant_id: TenantId):
         res = self.put(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/break")
         self.verbose_error(res)
@@ -0,0 +1,75 @@
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.utils import wait_for_upload, wait_until_tenant_active
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import Lsn, TenantId, TimelineId
+from fixtures.utils import query_scalar, wait_until
+
+
+#
+# Checks that the download of the layers at attach is throttled, and that it
+# resumes where it stopped when the pageserver restarts in the middle of it.
+#
+def test_attach_download_resumes(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_attach_download_resumes",
+    )
+    # 1 MiB/s, for the download of the ~10 MB of layers to take a few seconds
+    neon_env_builder.pageserver_config_override = (
+        "attach_download_layers={max_concurrent_downloads = 2, max_bytes_per_second = 1048576}"
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # small checkpoint distance to create more layer files
+            "checkpoint_distance": f"{1024 ** 2}",
+            "compaction_period": "0s",
+            "gc_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    # the download is cut by the restart
+    env.pageserver.allowed_errors.append(".*download the layers of the tenant.*")
+    env.pageserver.allowed_errors.append(".*shutting down in the middle of the layer download.*")
+
+    endpoint = env.endpoints.create_start("main")
+    tenant_id = TenantId(endpoint.safe_psql("show neon.tenant_id")[0][0])
+    timeline_id = TimelineId(endpoint.safe_psql("show neon.timeline_id")[0][0])
+    with endpoint.cursor() as cur:
+        cur.execute(
+            "CREATE TABLE t AS SELECT g AS key, repeat('x', 100) AS value"
+            " FROM generate_series(1, 100000) g"
+        )
+        current_lsn = Lsn(query_scalar(cur, "SELECT pg_current_wal_flush_lsn()"))
+    endpoint.stop()
+
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, current_lsn)
+
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id)
+
+    def some_layers_downloaded():
+        progress = client.tenant_status(tenant_id)["attach_download"]
+        assert progress is not None
+        assert progress["downloaded_layers"] >= 2
+        assert progress["downloaded_layers"] < progress["total_layers"]
+
+    wait_until(50, 0.1, some_layers_downloaded)
+    assert client.tenant_status(tenant_id)["has_in_progress_downloads"]
+
+    env.pageserver.stop(immediate=True)
+    env.pageserver.start()
+
+    wait_until_tenant_active(client, tenant_id, iterations=60)
+    status = client.tenant_status(tenant_id)
+    log.info(f"tenant status after the restart: {status}")
+    assert not status["has_in_progress_downloads"]
+    progress = status["attach_download"]
+    assert progress["resumed_layers"] >= 2
+    assert progress["downloaded_layers"] == progress["total_layers"]
+    assert progress["downloaded_bytes"] == progress["total_bytes"]
+
+    layers = client.layer_map_info(tenant_id, timeline_id).historic_layers
+    assert len(layers) == progress["total_layers"]
+    assert all(not layer.remote for layer in layers)
@@ -165,12 +165,18 @@ def test_fully_custom_config(positive_env: NeonEnv):
         "gc_feedback": True,
         "gc_horizon": 23 * (1024 * 1024),
         "gc_period": "2h 13m",
+        "get_page_weight": 3,
+        "logical_size_reconcile_period": "3h",
         "image_creation_threshold": 7,
         "pitr_interval": "1m",
+        "slru_image_creation_threshold": 5,
         "lagging_wal_timeout": "23m",
+        "layer_compression_level": 3,
         "max_lsn_wal_lag": 230000,
         "min_resident_size_override": 23,
+        "pagestream_rate_limit": 1000,
         "trace_read_requests": True,
+        "wait_lsn_timeout": "13s",
         "walreceiver_connect_timeout": "13m",
     }
 
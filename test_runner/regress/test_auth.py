@@ -62,6 +62,18 @@ def test_pageserver_auth(neon_env_builder: NeonEnvBuilder):
     ):
         tenant_http_client.tenant_create(TenantId.generate())
 
+    # page service commands are authorized by the tenant of the token
+    ps.safe_psql(f"show {env.initial_tenant}", password=tenant_token)
+    with pytest.raises(psycopg2.Error, match="Tenant id mismatch. Permission denied"):
+        ps.safe_psql(f"show {env.initial_tenant}", password=invalid_tenant_token)
+
+    # and the commands that write to the timelines need a pageserver token
+    with pytest.raises(psycopg2.Error, match="requires a pageserver token"):
+        ps.safe_psql(
+            f"import wal {env.initial_tenant} {env.initial_timeline} 0/0 0/0",
+            password=tenant_token,
+        )
+
 
 def test_compute_auth_to_pageserver(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.auth_enabled = True
@@ -0,0 +1,32 @@
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.types import Lsn
+from fixtures.utils import query_scalar
+
+
+#
+# Checks that a compute started again at the same LSN gets the basebackup from the
+# cache, with the same contents.
+#
+def test_basebackup_cache(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    env.neon_cli.create_branch("test_basebackup_cache")
+
+    endpoint = env.endpoints.create_start("test_basebackup_cache")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g AS key FROM generate_series(1, 1000) g")
+    lsn = Lsn(query_scalar(endpoint.connect().cursor(), "SELECT pg_current_wal_flush_lsn()"))
+    # The shutdown checkpoint is after the LSN the read-only computes start at
+    endpoint.stop()
+
+    def lookups(result: str) -> float:
+        value = client.get_metric_value(
+            "pageserver_basebackup_cache_lookups_total", {"result": result}
+        )
+        return value or 0
+
+    misses = lookups("miss")
+    for expected_hits in [0, 1, 2]:
+        with env.endpoints.create_start("test_basebackup_cache", lsn=lsn) as static:
+            assert static.safe_psql("SELECT count(*) FROM t") == [(1000,)]
+        assert lookups("hit") == expected_hits
+    assert lookups("miss") == misses + 1
@@ -96,6 +96,8 @@ def test_gc_aggressive(neon_env_builder: NeonEnvBuilder):
         assert r is not None
         assert r == (num_rows, updates_to_perform)
 
+    env.pageserver.http_client().timeline_check_layer_map(env.initial_tenant, timeline)
+
 
 #
 @pytest.mark.parametrize("remote_storage_kind", [RemoteStorageKind.LOCAL_FS])
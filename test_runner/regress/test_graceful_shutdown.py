@@ -0,0 +1,55 @@
+import json
+
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.utils import wait_until_tenant_active
+from fixtures.types import Lsn
+from fixtures.utils import wait_until
+
+
+def test_graceful_shutdown(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t (id int, t text)",
+            "INSERT INTO t SELECT g, 'some text' FROM generate_series(1, 10000) g",
+        ]
+    )
+    last_flush_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    endpoint.stop()
+
+    # The marker is only written once the initial logical size is calculated
+    def logical_size_calculated():
+        detail = client.timeline_detail(
+            tenant_id, timeline_id, include_non_incremental_logical_size=True
+        )
+        assert detail["current_logical_size"] == detail["current_logical_size_non_incremental"]
+        return detail["current_logical_size"]
+
+    logical_size = wait_until(30, 0.5, logical_size_calculated)
+
+    client.shutdown()
+
+    def shut_down():
+        assert env.pageserver.log_contains("Shut down successfully completed")
+
+    wait_until(30, 0.5, shut_down)
+    env.pageserver.running = False
+
+    marker_path = env.timeline_dir(tenant_id, timeline_id) / "clean_shutdown"
+    marker = json.loads(marker_path.read_text())
+    assert marker["logical_size"] == logical_size
+    # stopping the endpoint writes a shutdown checkpoint after the last flush
+    assert Lsn(marker["disk_consistent_lsn"]) >= last_flush_lsn
+
+    env.pageserver.start()
+    wait_until_tenant_active(client, tenant_id)
+
+    # The logical size is taken from the marker, and the marker is gone
+    assert env.pageserver.log_contains("from the clean shutdown marker")
+    assert client.timeline_detail(tenant_id, timeline_id)["current_logical_size"] == logical_size
+    assert not marker_path.exists()
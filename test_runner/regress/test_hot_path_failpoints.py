@@ -0,0 +1,84 @@
+import time
+
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.utils import wait_for_upload
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import Lsn
+
+
+# Exercise the latency and error actions of the GetPage failpoint.
+def test_get_page_failpoint(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*failpoint page-service-get-page: boom.*")
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g FROM generate_series(1, 10000) g")
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # restart the compute to empty its buffer cache, so that reads go to the pageserver
+    endpoint.stop()
+    endpoint.start()
+
+    client.configure_failpoints(("page-service-get-page", "return(sleep=50)"))
+    started_at = time.time()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 10000
+    assert time.time() - started_at >= 0.05
+    assert env.pageserver.log_contains('failpoint "page-service-get-page": sleeping for')
+
+    endpoint.stop()
+    endpoint.start()
+
+    client.configure_failpoints(("page-service-get-page", "return(error=boom)"))
+    with pytest.raises(Exception):
+        endpoint.safe_psql("SELECT count(*) FROM foo")
+
+    client.configure_failpoints(("page-service-get-page", "off"))
+    endpoint.stop()
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 10000
+
+
+# Layer downloads are retried when the download failpoint returns errors.
+@pytest.mark.parametrize("remote_storage_kind", [RemoteStorageKind.LOCAL_FS])
+def test_download_layer_failpoint(
+    neon_env_builder: NeonEnvBuilder, remote_storage_kind: RemoteStorageKind
+):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=remote_storage_kind,
+        test_name="test_download_layer_failpoint",
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops because they perform on-demand downloads
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g FROM generate_series(1, 10000) g")
+    current_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, current_lsn)
+    endpoint.stop()
+
+    layer = client.layer_map_info(tenant_id, timeline_id).historic_layers[0]
+    client.evict_layer(tenant_id, timeline_id, layer.layer_file_name)
+
+    # the first two attempts fail, the third one succeeds
+    client.configure_failpoints(("download-layer-file", "2*return(error=flaky)"))
+    client.download_layer(tenant_id, timeline_id, layer.layer_file_name)
+    assert env.pageserver.log_contains('failpoint "download-layer-file": returning error "flaky"')
+
+    layer = next(
+        info
+        for info in client.layer_map_info(tenant_id, timeline_id).historic_layers
+        if info.layer_file_name == layer.layer_file_name
+    )
+    assert not layer.remote
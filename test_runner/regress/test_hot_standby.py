@@ -1,6 +1,7 @@
 import time
 
 from fixtures.neon_fixtures import NeonEnv
+from fixtures.types import Lsn
 
 
 def test_hot_standby(neon_simple_env: NeonEnv):
@@ -78,3 +79,16 @@ def test_hot_standby(neon_simple_env: NeonEnv):
                         response = secondary_cursor.fetchone()
                         assert response is not None
                         assert response == responses[query]
+
+                # both computes are attached to the timeline, and the backend of the
+                # standby reads at the LSN it's replayed up to
+                computes = env.pageserver.http_client().timeline_computes(
+                    env.initial_tenant, env.initial_timeline
+                )
+                assert {compute["mode"] for compute in computes} == {"read_write", "read_only"}
+                standby_lsn = max(
+                    Lsn(compute["read_lsn"])
+                    for compute in computes
+                    if compute["mode"] == "read_only"
+                )
+                assert standby_lsn >= Lsn(primary_lsn)
@@ -0,0 +1,62 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import wait_until_tenant_active
+from fixtures.types import TenantId, TimelineId
+
+
+def test_tenant_and_timeline_labels(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id = TenantId.generate()
+    client.tenant_create(tenant_id, conf={"labels": {"project": "p1", "env": "prod"}})
+    assert client.tenant_status(tenant_id)["labels"] == {"project": "p1", "env": "prod"}
+
+    def listed_tenants(label: str):
+        return {TenantId(t["id"]) for t in client.tenant_list(label=label)}
+
+    assert listed_tenants("project=p1") == {tenant_id}
+    assert listed_tenants("project=p1,env=prod") == {tenant_id}
+    assert listed_tenants("project=p1,env=staging") == set()
+    assert env.initial_tenant not in listed_tenants("project=p1")
+
+    # The config endpoint replaces the labels, and keeps them when they are omitted
+    client.set_tenant_config(tenant_id, {"labels": {"project": "p1", "env": "staging"}})
+    assert listed_tenants("env=staging") == {tenant_id}
+    client.set_tenant_config(tenant_id, {"gc_period": "1h"})
+    assert client.tenant_status(tenant_id)["labels"] == {"project": "p1", "env": "staging"}
+
+    main_id = TimelineId.generate()
+    branch_id = TimelineId.generate()
+    client.timeline_create(env.pg_version, tenant_id, main_id, labels={"role": "main"})
+    info = client.timeline_create(
+        env.pg_version, tenant_id, branch_id, ancestor_timeline_id=main_id, labels={"role": "dev"}
+    )
+    assert info["labels"] == {"role": "dev"}
+
+    def listed_timelines(label: str):
+        return {TimelineId(t["timeline_id"]) for t in client.timeline_list(tenant_id, label=label)}
+
+    assert listed_timelines("role=main") == {main_id}
+    assert listed_timelines("role=dev") == {branch_id}
+    assert len(client.timeline_list(tenant_id)) == 2
+
+    client.set_timeline_labels(tenant_id, branch_id, {"role": "main", "owner": "alice"})
+    assert listed_timelines("role=main") == {main_id, branch_id}
+
+    with pytest.raises(PageserverApiException, match="label key"):
+        client.set_timeline_labels(tenant_id, main_id, {"not a key": "value"})
+    with pytest.raises(PageserverApiException, match="invalid label selector"):
+        client.tenant_list(label="project")
+
+    # The labels are persisted
+    env.pageserver.stop()
+    env.pageserver.start()
+    wait_until_tenant_active(client, tenant_id)
+    assert client.tenant_status(tenant_id)["labels"] == {"project": "p1", "env": "staging"}
+    assert listed_timelines("role=main") == {main_id, branch_id}
+    assert client.timeline_detail(tenant_id, branch_id)["labels"] == {
+        "role": "main",
+        "owner": "alice",
+    }
@@ -0,0 +1,77 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.utils import wait_for_last_record_lsn, wait_for_upload
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import Lsn, TenantId, TimelineId
+from fixtures.utils import query_scalar
+
+
+# Corrupts a layer file on disk, and checks that the repair API detects it and
+# replaces it with the copy from remote storage.
+@pytest.mark.parametrize("remote_storage_kind", [RemoteStorageKind.LOCAL_FS])
+def test_layer_repair(
+    neon_env_builder: NeonEnvBuilder,
+    remote_storage_kind: RemoteStorageKind,
+):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=remote_storage_kind,
+        test_name="test_layer_repair",
+    )
+
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops, so that the layers don't change
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    env.pageserver.allowed_errors.append(".*layer file has 1 corrupt blocks.*")
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("main")
+
+    tenant_id = TenantId(endpoint.safe_psql("show neon.tenant_id")[0][0])
+    timeline_id = TimelineId(endpoint.safe_psql("show neon.timeline_id")[0][0])
+
+    with endpoint.cursor() as cur:
+        cur.execute("CREATE TABLE foo (t text)")
+        cur.execute(
+            """
+            INSERT INTO foo
+            SELECT 'long string to consume some space' || g
+            FROM generate_series(1, 100000) g
+            """
+        )
+        current_lsn = Lsn(query_scalar(cur, "SELECT pg_current_wal_flush_lsn()"))
+
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, current_lsn)
+
+    layer_map = client.layer_map_info(tenant_id, timeline_id)
+    layer = max(layer_map.historic_layers, key=lambda layer: layer.layer_file_size)
+    layer_name = layer.layer_file_name
+
+    info = client.repair_layer(tenant_id, timeline_id, layer_name)
+    assert info["outcome"] == "Healthy"
+
+    # Flip a byte in the second block of the file
+    layer_path = env.timeline_dir(tenant_id, timeline_id) / layer_name
+    with open(layer_path, "r+b") as f:
+        f.seek(8192 + 100)
+        byte = f.read(1)
+        f.seek(8192 + 100)
+        f.write(bytes([byte[0] ^ 0xFF]))
+
+    info = client.repair_layer(tenant_id, timeline_id, layer_name)
+    assert info["outcome"] == "Redownloaded"
+    assert info["corrupt_blocks"] == [1]
+
+    info = client.repair_layer(tenant_id, timeline_id, layer_name)
+    assert info["outcome"] == "Healthy"
+
+    # A missing layer is downloaded
+    client.evict_layer(tenant_id, timeline_id, layer_name)
+    info = client.repair_layer(tenant_id, timeline_id, layer_name)
+    assert info["outcome"] == "Downloaded"
+
+    assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 100000
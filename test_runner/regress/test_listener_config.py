@@ -0,0 +1,50 @@
+import tempfile
+
+import psycopg2
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.utils import wait_until
+
+
+def test_page_service_connection_limit(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "pg_listener={max_connections=2}"
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*refusing connection from.*max_connections reached.*")
+
+    first = env.pageserver.connect()
+    second = env.pageserver.connect()
+    with pytest.raises(psycopg2.OperationalError):
+        env.pageserver.connect()
+
+    # Closing a connection frees its slot
+    first.close()
+
+    def connect():
+        env.pageserver.connect().close()
+
+    wait_until(10, 0.5, connect)
+    second.close()
+
+    # The management API is not limited
+    env.pageserver.http_client().check_status()
+
+
+def test_page_service_unix_socket(neon_env_builder: NeonEnvBuilder):
+    # Socket paths are limited to ~100 bytes, too short for the test output directory
+    with tempfile.TemporaryDirectory() as socket_dir:
+        # libpq clients look for the socket at <host>/.s.PGSQL.<port>
+        neon_env_builder.pageserver_config_override = (
+            f"listen_pg_socket='{socket_dir}/.s.PGSQL.5432'"
+        )
+        env = neon_env_builder.init_start()
+
+        with env.pageserver.cursor(host=socket_dir, port=5432) as cur:
+            cur.execute(f"show {env.initial_tenant}")
+            assert cur.fetchone() is not None
+
+        # The TCP listener keeps working
+        with env.pageserver.cursor() as cur:
+            cur.execute(f"show {env.initial_tenant}")
+            assert cur.fetchone() is not None
+
+        env.pageserver.stop()
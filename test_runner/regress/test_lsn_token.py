@@ -0,0 +1,40 @@
+import psycopg2
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.types import Lsn
+
+
+# Checks that a backend presenting the LSN token of a commit is served once the
+# pageserver has ingested its WAL, and not before.
+def test_lsn_token(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "wait_lsn_timeout = '1s'"
+    env = neon_env_builder.init_start()
+
+    endpoint = env.endpoints.create_start("main")
+    with endpoint.cursor() as cur:
+        cur.execute("CREATE EXTENSION neon")
+        cur.execute("CREATE TABLE t AS SELECT generate_series(1, 1000) AS x")
+        cur.execute("SELECT neon_lsn_token(), pg_current_wal_insert_lsn()::text")
+        res = cur.fetchone()
+        assert res is not None
+        token, insert_lsn = res
+
+    region, commit_lsn = token.split(":")
+    assert Lsn(commit_lsn) <= Lsn(insert_lsn)
+
+    # Restart to evict the table from the shared buffers, for the reads to go to
+    # the pageserver
+    endpoint.stop().start()
+    with endpoint.cursor(options=f"-cneon.lsn_token={token}") as cur:
+        cur.execute("SELECT count(*) FROM t")
+        assert cur.fetchone() == (1000,)
+
+    # A token of writes the pageserver doesn't have
+    future_lsn = Lsn(Lsn(insert_lsn).lsn_int + 0x10000000)
+    env.pageserver.allowed_errors.append(".*of the LSN token.*")
+    endpoint.stop().start()
+    with pytest.raises(psycopg2.Error, match="could not start pagestream with pageserver"):
+        with endpoint.cursor(
+            options=f"-cneon.lsn_token={region}:{future_lsn} -cneon.max_reconnect_attempts=0"
+        ) as cur:
+            cur.execute("SELECT count(*) FROM t")
@@ -2,12 +2,14 @@ import subprocess
 from pathlib import Path
 from typing import Optional
 
+import pytest
+import toml  # TODO: replace with tomllib for Python >= 3.11
 from fixtures.neon_fixtures import (
     DEFAULT_BRANCH_NAME,
     NeonEnv,
     NeonEnvBuilder,
 )
-from fixtures.pageserver.http import PageserverHttpClient
+from fixtures.pageserver.http import PageserverApiException, PageserverHttpClient
 from fixtures.pg_version import PgVersion
 from fixtures.types import Lsn, TenantId, TimelineId
 from fixtures.utils import wait_until
@@ -64,6 +66,10 @@ def test_pageserver_init_node_id(
 
 def check_client(pg_version: PgVersion, client: PageserverHttpClient, initial_tenant: TenantId):
     client.check_status()
+    client.check_status_live()
+    ready = client.status_ready()
+    assert ready["ready"], ready["pending"]
+    assert ready["pending"] == []
 
     # check initial tenant is there
     assert initial_tenant in {TenantId(t["id"]) for t in client.tenant_list()}
@@ -190,3 +196,122 @@ def test_pageserver_http_api_client_auth_enabled(neon_env_builder: NeonEnvBuilde
 
     with env.pageserver.http_client(auth_token=pageserver_token) as client:
         check_client(env.pg_version, client, env.initial_tenant)
+
+
+def test_pageserver_page_cache_resize(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "page_cache_size=100;max_page_cache_size=200"
+    env = neon_env_builder.init_start()
+
+    with env.pageserver.http_client() as client:
+        info = client.page_cache_info()
+        assert info["size"] == 100
+        assert info["capacity"] == 200
+        assert info["memory_budget"] is None
+
+        endpoint = env.endpoints.create_start("main")
+        endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+
+        # Requests beyond the capacity are clamped
+        assert client.page_cache_resize(1000)["size"] == 200
+
+        info = client.page_cache_resize(10)
+        assert info["size"] == 10
+        assert sum(t["materialized_pages"] for t in info["tenants"]) <= 10
+
+        # The pageserver keeps serving reads with the smaller cache
+        assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000
+
+
+def test_pageserver_page_cache_snapshot(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    endpoint.stop()
+
+    def materialized_pages() -> int:
+        info = env.pageserver.http_client().page_cache_info()
+        return sum(
+            t["materialized_pages"] for t in info["tenants"] if t["tenant_id"] == str(tenant_id)
+        )
+
+    assert materialized_pages() > 0
+
+    # A clean shutdown saves the materialized pages, which are loaded back on startup
+    env.pageserver.stop()
+    snapshot_path = env.repo_dir / "page_cache.snapshot"
+    assert snapshot_path.exists()
+    env.pageserver.start()
+    assert not snapshot_path.exists()
+    assert materialized_pages() > 0
+
+
+def test_pageserver_reload_config(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    pageserver_toml = env.repo_dir / "pageserver.toml"
+
+    # Nothing changed yet
+    res = client.reload_config()
+    assert res == {"applied": [], "restart_required": []}
+
+    config = toml.load(pageserver_toml)
+    config["log_filter"] = "info"
+    config["concurrent_tenant_size_logical_size_queries"] = "4"
+    config["wait_lsn_timeout"] = "30 s"
+    with pageserver_toml.open("w") as f:
+        toml.dump(config, f)
+
+    res = client.reload_config()
+    assert {c["name"] for c in res["applied"]} == {
+        "log_filter",
+        "concurrent_tenant_size_logical_size_queries",
+    }
+    assert [c["name"] for c in res["restart_required"]] == ["wait_lsn_timeout"]
+
+    # The applied settings are not reported again, the others are until a restart
+    res = client.reload_config()
+    assert res["applied"] == []
+    assert [c["name"] for c in res["restart_required"]] == ["wait_lsn_timeout"]
+
+    # An invalid config is rejected, and nothing is applied
+    config["log_filter"] = "info,pageserver=loud"
+    with pageserver_toml.open("w") as f:
+        toml.dump(config, f)
+    with pytest.raises(PageserverApiException, match="invalid log filter"):
+        client.reload_config()
+
+    del config["log_filter"]
+    with pageserver_toml.open("w") as f:
+        toml.dump(config, f)
+    res = client.reload_config()
+    assert [c["name"] for c in res["applied"]] == ["log_filter"]
+
+    client.check_status_live()
+
+
+def test_pageserver_background_tasks(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    pageserver_toml = env.repo_dir / "pageserver.toml"
+
+    def limits():
+        return {c["class"]: c["limit"] for c in client.background_tasks()}
+
+    default_limits = limits()
+    # the default compaction limit is lower with few CPUs
+    assert 1 <= default_limits.pop("compaction") <= 8
+    assert default_limits == {"gc": 8, "logical_size_reconciliation": 4}
+    for c in client.background_tasks():
+        for task in c["running"] + c["waiting"]:
+            assert task["tenant_id"] == str(env.initial_tenant)
+
+    # The limits can be changed at runtime
+    config = toml.load(pageserver_toml)
+    config["background_task_concurrency"] = {"compaction": 1, "gc": 2}
+    with pageserver_toml.open("w") as f:
+        toml.dump(config, f)
+    res = client.reload_config()
+    assert [c["name"] for c in res["applied"]] == ["background_task_concurrency"]
+    assert limits() == {"compaction": 1, "gc": 2, "logical_size_reconciliation": 4}
@@ -0,0 +1,51 @@
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+
+
+# Modifies a small part of a big table on a branch, and checks that image layer
+# creation on the branch only materializes the modified pages, into sparse
+# image layers.
+def test_sparse_image_layers(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops, we compact manually
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            # keep the whole table in one partition
+            "compaction_target_size": f"{1024 ** 3}",
+            "image_creation_threshold": "1",
+        }
+    )
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    with env.endpoints.create_start("main") as endpoint:
+        endpoint.safe_psql_many(
+            [
+                "CREATE TABLE foo (id int, t text) WITH (fillfactor = 10)",
+                """
+                INSERT INTO foo
+                SELECT g, 'long string to consume some space' FROM generate_series(1, 20000) g
+                """,
+            ]
+        )
+
+    timeline_id = env.neon_cli.create_branch("branch", "main")
+    with env.endpoints.create_start("branch") as endpoint:
+        endpoint.safe_psql("UPDATE foo SET t = 'updated' WHERE id <= 100")
+        wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+        client.timeline_checkpoint(tenant_id, timeline_id)
+
+        layers = client.layer_map_info(tenant_id, timeline_id).historic_layers
+        images = [layer.layer_file_name for layer in layers if layer.kind == "Image"]
+        assert len(images) > 0
+        assert all(name.endswith("-sparse") for name in images), images
+
+        assert endpoint.safe_psql("SELECT count(*) FROM foo WHERE t = 'updated'")[0][0] == 100
+        assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 20000
+
+    # The presence maps are read back correctly after a restart
+    env.pageserver.stop()
+    env.pageserver.start()
+    with env.endpoints.create_start("branch") as endpoint:
+        assert endpoint.safe_psql("SELECT count(*) FROM foo WHERE t = 'updated'")[0][0] == 100
+        assert endpoint.safe_psql("SELECT count(*) FROM foo")[0][0] == 20000
@@ -410,3 +410,69 @@ def test_live_reconfig_get_evictions_low_residence_duration_metric_threshold(
     metric = get_metric()
     assert int(metric.labels["low_threshold_secs"]) == 24 * 60 * 60, "label resets to default"
     assert int(metric.value) == 0, "value resets to default"
+
+
+def test_live_reconfig_gc_period(neon_env_builder: NeonEnvBuilder):
+    """A shorter gc_period applies to the GC loop sleeping with the old one"""
+    env = neon_env_builder.init_start()
+    (tenant_id, timeline_id) = env.neon_cli.create_tenant(conf={"gc_period": "1h"})
+    ps_http = env.pageserver.http_client()
+
+    def gc_count() -> float:
+        value = ps_http.get_metric_value(
+            "pageserver_storage_operations_seconds_count_total",
+            {
+                "operation": "gc",
+                "tenant_id": str(tenant_id),
+                "timeline_id": str(timeline_id),
+            },
+        )
+        return value or 0
+
+    env.neon_cli.config_tenant(tenant_id, {"gc_period": "1s"})
+
+    def gc_ran():
+        assert gc_count() > 0
+
+    # Without the reload, GC would run in up to an hour
+    wait_until(20, 0.5, gc_ran)
+
+
+def test_timeline_config(neon_env_builder: NeonEnvBuilder):
+    """The overrides of a timeline apply to it only, and survive a restart"""
+    env = neon_env_builder.init_start()
+    (tenant_id, main_id) = env.neon_cli.create_tenant(
+        conf={"checkpoint_distance": str(1024**3), "checkpoint_timeout": "1h"}
+    )
+    branch_id = env.neon_cli.create_branch("branch", "main", tenant_id=tenant_id)
+    ps_http = env.pageserver.http_client()
+
+    ps_http.set_timeline_config(tenant_id, branch_id, {"checkpoint_distance": 1024 * 1024})
+    config = ps_http.timeline_config(tenant_id, branch_id)
+    assert config["timeline_specific_overrides"] == {"checkpoint_distance": 1024 * 1024}
+    assert config["effective_config"] == {
+        "checkpoint_distance": 1024 * 1024,
+        "checkpoint_timeout": "1h",
+    }
+    assert ps_http.timeline_config(tenant_id, main_id)["timeline_specific_overrides"] == {}
+
+    env.pageserver.stop()
+    env.pageserver.start()
+    config = ps_http.timeline_config(tenant_id, branch_id)
+    assert config["timeline_specific_overrides"] == {"checkpoint_distance": 1024 * 1024}
+
+    # The branch flushes its layers after a MB of WAL, without waiting for the hour
+    endpoint = env.endpoints.create_start("branch", tenant_id=tenant_id)
+    disk_consistent_lsn = Lsn(ps_http.timeline_detail(tenant_id, branch_id)["disk_consistent_lsn"])
+    endpoint.safe_psql(
+        "CREATE TABLE t AS SELECT g, repeat('x', 100) FROM generate_series(1, 100000) g"
+    )
+
+    def flushed():
+        detail = ps_http.timeline_detail(tenant_id, branch_id)
+        assert Lsn(detail["disk_consistent_lsn"]) > disk_consistent_lsn
+
+    wait_until(20, 0.5, flushed)
+
+    ps_http.set_timeline_config(tenant_id, branch_id, {})
+    assert ps_http.timeline_config(tenant_id, branch_id)["timeline_specific_overrides"] == {}
@@ -528,7 +528,7 @@ def test_detach_while_attaching(
 # * `ignore` the tenant
 # * verify that ignored tenant files are generally unchanged, only an ignored mark had appeared
 # * verify the ignored tenant is gone from pageserver's memory
-# * restart the pageserver and verify that ignored tenant is still not loaded
+# * restart the pageserver and verify that ignored tenant is still not loaded, and listed as ignored
 # * `load` the same tenant
 # * ensure that it's status is `Active` and it's present in pageserver's memory with all timelines
 @pytest.mark.parametrize("remote_storage_kind", [RemoteStorageKind.NOOP, RemoteStorageKind.MOCK_S3])
@@ -570,6 +570,7 @@ def test_ignored_tenant_reattach(
     assert len(tenants_after_ignore) + 1 == len(
         tenants_before_ignore
     ), "Only ignored tenant should be missing"
+    assert pageserver_http.tenant_list_ignored() == [ignored_tenant_id]
 
     # restart the pageserver to ensure we don't load the ignore timeline
     env.pageserver.stop()
@@ -579,6 +580,7 @@ def test_ignored_tenant_reattach(
     assert (
         tenants_after_restart == tenants_after_ignore
     ), "Ignored tenant should not be reloaded after pageserver restart"
+    assert pageserver_http.tenant_list_ignored() == [ignored_tenant_id]
 
     # now, load it from the local files and expect it works
     pageserver_http.tenant_load(tenant_id=ignored_tenant_id)
@@ -587,6 +589,7 @@ def test_ignored_tenant_reattach(
     tenants_after_attach = [tenant["id"] for tenant in pageserver_http.tenant_list()]
     tenants_after_attach.sort()
     assert tenants_after_attach == tenants_before_ignore, "Should have all tenants back"
+    assert pageserver_http.tenant_list_ignored() == []
 
     timelines_after_ignore = [
         timeline["timeline_id"]
@@ -0,0 +1,112 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import TenantId, TimelineId
+
+
+# Checks the home region and the regions of the tenants in the tenant listing,
+# and the filtering of the listing by home region.
+def test_tenant_regions(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id = TenantId.generate()
+    client.tenant_create(tenant_id)
+    status = client.tenant_status(tenant_id)
+    assert "home_region" not in status
+    assert "regions" not in status
+
+    # The root timeline is in region 1, and the tenant is branched into region 2
+    root_id = TimelineId.generate()
+    client.timeline_create(env.pg_version, tenant_id, root_id, region_id=1)
+    client.timeline_create(
+        env.pg_version, tenant_id, TimelineId.generate(), ancestor_timeline_id=root_id, region_id=2
+    )
+    status = client.tenant_status(tenant_id)
+    assert status["home_region"] == 1
+    assert status["regions"] == [1, 2]
+
+    def listed_tenants(region_id):
+        return {TenantId(t["id"]) for t in client.tenant_list(region_id=region_id)}
+
+    assert listed_tenants(1) == {tenant_id}
+    assert listed_tenants(2) == set()
+    assert tenant_id not in listed_tenants(0)
+    assert env.initial_tenant in listed_tenants(0)
+    assert {tenant_id, env.initial_tenant} <= {TenantId(t["id"]) for t in client.tenant_list()}
+
+
+# Checks that a timeline created with materialize_in_regions is branched into the
+# regions right away.
+def test_timeline_materialize_in_regions(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id = TenantId.generate()
+    client.tenant_create(tenant_id)
+    root_id = TimelineId.generate()
+    info = client.timeline_create(
+        env.pg_version,
+        tenant_id,
+        root_id,
+        region_id=1,
+        materialize_in_regions=[2, 3],
+        labels={"app": "shop"},
+    )
+    region_timelines = {
+        t["region_id"]: TimelineId(t["timeline_id"]) for t in info["region_timelines"]
+    }
+    assert set(region_timelines) == {2, 3}
+
+    for region, timeline_id in region_timelines.items():
+        detail = client.timeline_detail(tenant_id, timeline_id)
+        assert detail["region_id"] == region
+        assert TimelineId(detail["ancestor_timeline_id"]) == root_id
+        assert detail["ancestor_lsn"] == info["last_record_lsn"]
+        assert detail["labels"] == {"app": "shop"}
+        assert "region_timelines" not in detail
+
+    status = client.tenant_status(tenant_id)
+    assert status["home_region"] == 1
+    assert status["regions"] == [1, 2, 3]
+
+    # Nothing is created if the request is malformed
+    for regions in [[1], [2, 2]]:
+        with pytest.raises(PageserverApiException, match="materialize"):
+            client.timeline_create(
+                env.pg_version,
+                tenant_id,
+                TimelineId.generate(),
+                region_id=1,
+                materialize_in_regions=regions,
+            )
+    assert len(client.timeline_list(tenant_id)) == 3
+
+    # A creation that fails midway is completed by its retry
+    env.pageserver.allowed_errors.append(".*failpoint: timeline-materialize-in-region.*")
+    client.configure_failpoints(("timeline-materialize-in-region", "1*off->return"))
+    timeline_id = TimelineId.generate()
+    with pytest.raises(PageserverApiException, match="failpoint"):
+        client.timeline_create(
+            env.pg_version,
+            tenant_id,
+            timeline_id,
+            region_id=1,
+            materialize_in_regions=[2, 3],
+        )
+    assert len(client.timeline_list(tenant_id)) == 5
+    client.configure_failpoints(("timeline-materialize-in-region", "off"))
+    info = client.timeline_create(
+        env.pg_version,
+        tenant_id,
+        timeline_id,
+        region_id=1,
+        materialize_in_regions=[2, 3],
+    )
+    assert [t["region_id"] for t in info["region_timelines"]] == [2, 3]
+    assert len(client.timeline_list(tenant_id)) == 6
+    ancestor_lsns = {
+        client.timeline_detail(tenant_id, TimelineId(t["timeline_id"]))["ancestor_lsn"]
+        for t in info["region_timelines"]
+    }
+    assert len(ancestor_lsns) == 1
@@ -0,0 +1,79 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import Lsn
+
+
+def changed_relnodes(diff) -> set[int]:
+    return {r["rel"]["relnode"] for r in diff["relations"] if r["rel"]["forknum"] == 0}
+
+
+def test_timeline_diff(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc, it could move the cutoff past the LSNs we diff from
+            "gc_period": "0s",
+        }
+    )
+    tenant_id = env.initial_tenant
+    main_id = env.initial_timeline
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t1 (id int, t text)",
+            "CREATE TABLE t2 (id int, t text)",
+            "INSERT INTO t1 SELECT g, 'some text' FROM generate_series(1, 10000) g",
+        ]
+    )
+    t1 = endpoint.safe_psql("SELECT pg_relation_filenode('t1')")[0][0]
+    t2 = endpoint.safe_psql("SELECT pg_relation_filenode('t2')")[0][0]
+    lsn0 = wait_for_last_flush_lsn(env, endpoint, tenant_id, main_id)
+
+    endpoint.safe_psql("INSERT INTO t2 SELECT g, 'some text' FROM generate_series(1, 10000) g")
+    lsn1 = wait_for_last_flush_lsn(env, endpoint, tenant_id, main_id)
+
+    endpoint.safe_psql("UPDATE t1 SET t = 'updated' WHERE id <= 100")
+    lsn2 = wait_for_last_flush_lsn(env, endpoint, tenant_id, main_id)
+    # the diff reads the layers only, flush the changes into them
+    client.timeline_checkpoint(tenant_id, main_id)
+
+    diff = client.timeline_diff(tenant_id, main_id, lsn1, lsn2)
+    assert Lsn(diff["from_lsn"]) == lsn1
+    assert t1 in changed_relnodes(diff)
+    assert t2 not in changed_relnodes(diff)
+    assert diff["total_records"] > 0 and diff["total_bytes"] > 0
+
+    diff = client.timeline_diff(tenant_id, main_id, lsn0, lsn1)
+    assert t2 in changed_relnodes(diff)
+    assert t1 not in changed_relnodes(diff)
+    [t2_diff] = [r for r in diff["relations"] if r["rel"]["relnode"] == t2]
+    assert t2_diff["size_changed"]
+    assert t2_diff["changed_blocks"] > 0
+
+    # On a branch, the changes before the branch point come from the ancestor
+    branch_id = env.neon_cli.create_branch("branch", "main", ancestor_start_lsn=lsn1)
+    branch_endpoint = env.endpoints.create_start("branch")
+    branch_endpoint.safe_psql("DELETE FROM t2 WHERE id <= 100")
+    branch_lsn = wait_for_last_flush_lsn(env, branch_endpoint, tenant_id, branch_id)
+    client.timeline_checkpoint(tenant_id, branch_id)
+
+    branch_diff = client.timeline_diff(tenant_id, branch_id, lsn1)
+    assert Lsn(branch_diff["to_lsn"]) >= branch_lsn
+    assert t2 in changed_relnodes(branch_diff)
+    assert t1 not in changed_relnodes(branch_diff)
+
+    # The insert into t2 on main is included too
+    diff = client.timeline_diff(tenant_id, branch_id, lsn0, branch_lsn)
+    assert t2 in changed_relnodes(diff)
+    assert diff["total_records"] > branch_diff["total_records"]
+
+    with pytest.raises(PageserverApiException, match="must be smaller than"):
+        client.timeline_diff(tenant_id, main_id, lsn2, lsn1)
+
+    # The changes that are not flushed yet are not diffed
+    endpoint.safe_psql("UPDATE t2 SET t = 'updated' WHERE id <= 100")
+    lsn3 = wait_for_last_flush_lsn(env, endpoint, tenant_id, main_id)
+    with pytest.raises(PageserverApiException, match="is not flushed"):
+        client.timeline_diff(tenant_id, main_id, lsn2, lsn3)
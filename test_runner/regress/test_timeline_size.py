@@ -29,7 +29,7 @@ from fixtures.pageserver.utils import (
 from fixtures.pg_version import PgVersion
 from fixtures.port_distributor import PortDistributor
 from fixtures.remote_storage import RemoteStorageKind
-from fixtures.types import TenantId, TimelineId
+from fixtures.types import Lsn, TenantId, TimelineId
 from fixtures.utils import get_timeline_dir_size, wait_until
 
 
@@ -66,6 +66,64 @@ def test_timeline_size(neon_simple_env: NeonEnv):
             assert res["current_logical_size"] == res["current_logical_size_non_incremental"]
 
 
+def test_timeline_size_reconciliation(neon_simple_env: NeonEnv):
+    """
+    The logical size maintained from the ingested WAL is periodically checked
+    against a calculation from the layers, and the LSN of the check is reported.
+    """
+    env = neon_simple_env
+    tenant_id, timeline_id = env.neon_cli.create_tenant(
+        conf={"logical_size_reconcile_period": "1s"}
+    )
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE foo (t text)",
+            "INSERT INTO foo SELECT 'long string to consume some space' || g FROM generate_series(1, 10000) g",
+        ]
+    )
+    last_flush_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    def reconciled_after_insert():
+        res = client.timeline_detail(tenant_id, timeline_id)
+        assert res["current_logical_size_is_exact"]
+        calculated_at = res["current_logical_size_calculated_at"]
+        assert calculated_at is not None
+        assert Lsn(calculated_at) >= last_flush_lsn
+
+    wait_until(30, 1, reconciled_after_insert)
+
+    res = client.timeline_detail(tenant_id, timeline_id, include_non_incremental_logical_size=True)
+    assert res["current_logical_size"] == res["current_logical_size_non_incremental"]
+
+
+def test_timeline_slru_size(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    new_timeline_id = env.neon_cli.create_branch("test_timeline_slru_size", "empty")
+
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("test_timeline_slru_size")
+
+    res = client.timeline_detail(env.initial_tenant, new_timeline_id)
+    initial_slru_size = res["current_slru_size"]
+    assert initial_slru_size > 0
+    assert initial_slru_size % 8192 == 0
+
+    # Commit some transactions. That updates the CLOG, but nothing truncates
+    # the SLRUs this early, so the size can only grow.
+    with closing(endpoint.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            for i in range(100):
+                cur.execute(f"INSERT INTO foo VALUES ('row {i}')")
+    wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, new_timeline_id)
+
+    res = client.timeline_detail(env.initial_tenant, new_timeline_id)
+    assert res["current_slru_size"] >= initial_slru_size
+
+
 def test_timeline_size_createdropdb(neon_simple_env: NeonEnv):
     env = neon_simple_env
     new_timeline_id = env.neon_cli.create_branch("test_timeline_size_createdropdb", "empty")
@@ -0,0 +1,59 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import Lsn
+
+
+# Export a timeline, replace the tenant with an import of the snapshot, and check
+# that the data is still there.
+def test_timeline_snapshot(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE foo (id int, t text)",
+            "INSERT INTO foo SELECT g, 'some text' FROM generate_series(1, 100000) g",
+        ]
+    )
+    last_flush_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    endpoint.stop()
+
+    snapshot_path = env.repo_dir / "snapshot.tar"
+    client.timeline_snapshot_export(tenant_id, timeline_id, snapshot_path)
+    layers_before = {
+        layer.layer_file_name
+        for layer in client.layer_map_info(tenant_id, timeline_id).historic_layers
+    }
+
+    client.tenant_detach(tenant_id)
+    client.timeline_snapshot_import(tenant_id, timeline_id, snapshot_path)
+
+    layers_after = {
+        layer.layer_file_name
+        for layer in client.layer_map_info(tenant_id, timeline_id).historic_layers
+    }
+    assert layers_after == layers_before
+    detail = client.timeline_detail(tenant_id, timeline_id)
+    assert Lsn(detail["disk_consistent_lsn"]) >= last_flush_lsn
+
+    # importing over an existing tenant fails
+    with pytest.raises(PageserverApiException, match="already exists"):
+        client.timeline_snapshot_import(tenant_id, timeline_id, snapshot_path)
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*), sum(id) FROM foo")[0] == (100000, 5000050000)
+
+
+def test_timeline_snapshot_with_ancestor(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    branch_id = env.neon_cli.create_branch("branch", "main")
+    with pytest.raises(PageserverApiException, match="has ancestor"):
+        client.timeline_snapshot_export(
+            env.initial_tenant, branch_id, env.repo_dir / "snapshot.tar"
+        )
@@ -0,0 +1,34 @@
+import time
+
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.pageserver.utils import wait_for_last_record_lsn
+from fixtures.types import Lsn
+
+
+# Checks that the WAL of a suspended timeline isn't ingested until it's resumed.
+def test_timeline_suspend(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_timeline_suspend")
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("test_timeline_suspend")
+    endpoint.safe_psql("CREATE TABLE t (x int)")
+    lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, lsn)
+
+    client.timeline_suspend(tenant_id, timeline_id)
+    client.timeline_suspend(tenant_id, timeline_id)
+    assert client.timeline_detail(tenant_id, timeline_id)["state"] == "Suspended"
+
+    # Generates WAL without reading any page from the pageserver
+    endpoint.safe_psql("SELECT pg_logical_emit_message(true, 'test', repeat('x', 10000))")
+    new_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+    time.sleep(2)
+    detail = client.timeline_detail(tenant_id, timeline_id)
+    assert Lsn(detail["last_record_lsn"]) < new_lsn
+
+    client.timeline_resume(tenant_id, timeline_id)
+    assert client.timeline_detail(tenant_id, timeline_id)["state"] == "Active"
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, new_lsn)
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(0,)]
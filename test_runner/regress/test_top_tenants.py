@@ -0,0 +1,43 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import TenantId
+
+
+def test_top_tenants(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    busy_tenant, timeline_id = env.neon_cli.create_tenant()
+    idle_tenant, _ = env.neon_cli.create_tenant()
+
+    endpoint = env.endpoints.create_start("main", tenant_id=busy_tenant)
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE t (id int, t text)",
+            "INSERT INTO t SELECT g, 'some text' FROM generate_series(1, 100000) g",
+        ]
+    )
+    wait_for_last_flush_lsn(env, endpoint, busy_tenant, timeline_id)
+    # Restart to empty the shared buffers, so that the reads go to the pageserver
+    endpoint.stop()
+    endpoint.start()
+    endpoint.safe_psql("SELECT count(*) FROM t")
+
+    def top_ids(by: str, k: int = 20):
+        return [TenantId(t["id"]) for t in client.top_tenants(by=by, k=k)["tenants"]]
+
+    for by in ["reads", "ingest", "physical_size"]:
+        top = top_ids(by)
+        assert top[0] == busy_tenant, f"busy tenant is not the heaviest by {by}"
+        assert idle_tenant in top
+
+    top = client.top_tenants(by="reads", k=1)
+    assert top["by"] == "reads"
+    [busy] = top["tenants"]
+    assert busy["reads"] > 0
+    assert busy["ingested_bytes"] > 0
+    assert busy["physical_size"] > 0
+
+    with pytest.raises(PageserverApiException, match="expected one of"):
+        client.top_tenants(by="writes")
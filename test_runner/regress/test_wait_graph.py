@@ -0,0 +1,39 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.pageserver.http import PageserverApiException
+
+
+def xid(region: int, xid: int):
+    return {"region": region, "xid": xid}
+
+
+def edge(waiter, holder):
+    return {"waiter": waiter, "holder": holder}
+
+
+# Checks that the wait-for edges reported by the regions make up the wait graph
+# of the tenant, with the deadlocks across the regions.
+def test_wait_graph(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    tenant_id = env.initial_tenant
+
+    graph = client.tenant_wait_graph(tenant_id)
+    assert graph == {"edges": [], "deadlocks": []}
+
+    client.tenant_report_wait_edges(tenant_id, 1, [edge(xid(1, 10), xid(2, 20))])
+    client.tenant_report_wait_edges(tenant_id, 2, [edge(xid(2, 20), xid(1, 11))])
+    assert client.tenant_wait_graph(tenant_id)["deadlocks"] == []
+
+    # A report replaces the previous one of the region
+    client.tenant_report_wait_edges(tenant_id, 2, [edge(xid(2, 20), xid(1, 10))])
+    graph = client.tenant_wait_graph(tenant_id)
+    assert len(graph["edges"]) == 2
+    assert graph["deadlocks"] == [[xid(1, 10), xid(2, 20)]]
+
+    # A region can only report the waits of its own transactions
+    with pytest.raises(PageserverApiException, match="reported a wait of transaction"):
+        client.tenant_report_wait_edges(tenant_id, 1, [edge(xid(2, 21), xid(1, 10))])
+
+    client.tenant_report_wait_edges(tenant_id, 1, [])
+    assert client.tenant_wait_graph(tenant_id)["deadlocks"] == []
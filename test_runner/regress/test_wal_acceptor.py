@@ -1,6 +1,7 @@
 import os
 import pathlib
 import random
+import re
 import shutil
 import signal
 import subprocess
@@ -17,6 +18,7 @@ import psycopg2
 import pytest
 from fixtures.broker import NeonBroker
 from fixtures.log_helper import log
+from fixtures.metrics import parse_metrics
 from fixtures.neon_fixtures import (
     Endpoint,
     NeonEnv,
@@ -27,8 +29,10 @@ from fixtures.neon_fixtures import (
     Safekeeper,
     SafekeeperHttpClient,
     SafekeeperPort,
+    wait_for_last_flush_lsn,
 )
 from fixtures.pageserver.utils import (
+    last_record_lsn,
     timeline_delete_wait_completed,
     wait_for_last_record_lsn,
     wait_for_upload,
@@ -36,12 +40,13 @@ from fixtures.pageserver.utils import (
 from fixtures.pg_version import PgVersion
 from fixtures.port_distributor import PortDistributor
 from fixtures.remote_storage import (
+    LocalFsStorage,
     RemoteStorageKind,
     RemoteStorageUsers,
     available_remote_storages,
 )
 from fixtures.types import Lsn, TenantId, TimelineId
-from fixtures.utils import get_dir_size, query_scalar, start_in_background
+from fixtures.utils import get_dir_size, query_scalar, start_in_background, wait_until
 
 
 def wait_lsn_force_checkpoint(
@@ -310,6 +315,26 @@ def test_broker(neon_env_builder: NeonEnvBuilder):
             )
         time.sleep(1)
 
+    # Each safekeeper reports how far behind it are the others, which is
+    # nothing once the writes are done.
+    def peers_caught_up():
+        for sk in env.safekeepers:
+            lags = sk.http_client().get_metrics().peer_flush_lag_bytes
+            peers = [sk_id for (_, _, sk_id) in lags.keys()]
+            assert sorted(peers) == sorted(p.id for p in env.safekeepers if p.id != sk.id)
+            assert all(lag == 0 for lag in lags.values()), f"lags on {sk.id}: {lags}"
+
+    wait_until(30, 1, peers_caught_up)
+
+    # The debug dump shows the peers, and the compute streaming from us
+    dump = clients[0].debug_dump(
+        {"dump_memory": "true", "tenant_id": str(tenant_id), "timeline_id": str(timeline_id)}
+    )
+    [timeline] = dump["timelines"]
+    peers = timeline["memory"]["peers"]
+    assert sorted(p["sk_id"] for p in peers) == sorted(sk.id for sk in env.safekeepers)
+    assert timeline["memory"]["num_computes"] == 1
+
 
 # Test that old WAL consumed by peers and pageserver is removed from safekeepers.
 @pytest.mark.parametrize("auth_enabled", [False, True])
@@ -493,6 +518,55 @@ def test_wal_backup(neon_env_builder: NeonEnvBuilder, remote_storage_kind: Remot
     )
 
 
+# Check that the in-progress segment is offloaded as <segment>.partial, and
+# replaced by the full segment once it is complete.
+def test_partial_wal_backup(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_partial_wal_backup",
+    )
+    neon_env_builder.remote_storage_users = RemoteStorageUsers.SAFEKEEPER
+    neon_env_builder.safekeepers_partial_backup_interval = "1s"
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_partial_wal_backup")
+
+    endpoint = env.endpoints.create_start("test_partial_wal_backup")
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t values (1, 'payload')")
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = env.remote_storage.root / str(tenant_id) / str(timeline_id)
+    seg_size = 16 * 1024 * 1024
+    http_cli = env.safekeepers[0].http_client()
+
+    def segment_name(segno: int) -> str:
+        return f"{1:08X}{segno // 256:08X}{segno % 256:08X}"
+
+    def partial_segment_offloaded() -> int:
+        tli_status = http_cli.timeline_status(tenant_id, timeline_id)
+        log.info(f"sk status is {tli_status}")
+        assert tli_status.backup_lsn == tli_status.commit_lsn
+        segno = int(tli_status.commit_lsn) // seg_size
+        partial_segment = remote_timeline_dir / f"{segment_name(segno)}.partial"
+        # The size of the object tells where its WAL ends
+        assert partial_segment.stat().st_size == int(tli_status.commit_lsn) % seg_size
+        return segno
+
+    segno = wait_until(30, 1, partial_segment_offloaded)
+
+    # roughly fills one segment
+    endpoint.safe_psql("insert into t select generate_series(1,250000), 'payload'")
+
+    def full_segment_offloaded():
+        assert (remote_timeline_dir / segment_name(segno)).exists()
+        assert not (remote_timeline_dir / f"{segment_name(segno)}.partial").exists()
+
+    wait_until(30, 1, full_segment_offloaded)
+    wait_until(30, 1, partial_segment_offloaded)
+
+
 @pytest.mark.parametrize("remote_storage_kind", available_remote_storages())
 def test_s3_wal_replay(neon_env_builder: NeonEnvBuilder, remote_storage_kind: RemoteStorageKind):
     neon_env_builder.num_safekeepers = 3
@@ -869,6 +943,41 @@ def test_timeline_status(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     assert debug_dump_1["config"]["id"] == env.safekeepers[0].id
 
 
+# Check that the term history reports the walproposer elected in each term.
+def test_term_history(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_term_history")
+
+    endpoint = env.endpoints.create_start("test_term_history")
+    endpoint.safe_psql("create table t(i int)")
+
+    def last_elections():
+        elections = []
+        for sk in env.safekeepers:
+            status = sk.http_client().timeline_term_history(tenant_id, timeline_id)
+            log.info(f"term history on safekeeper {sk.id}: {status}")
+            last = status["history"][-1]
+            # the safekeepers which missed the election have no proposer recorded
+            if last["term"] == status["term"] and last["elected_at"] is not None:
+                assert last["proposer_uuid"] is not None
+                assert last["proposer_addr"] is not None
+                elections.append(last)
+        return elections
+
+    elections_before = last_elections()
+    assert len(elections_before) >= 2
+
+    endpoint.stop().start()
+    endpoint.safe_psql("insert into t values(10)")
+
+    elections_after = last_elections()
+    assert len(elections_after) >= 2
+    term_before = max(e["term"] for e in elections_before)
+    assert all(e["term"] > term_before for e in elections_after)
+
+
 class DummyConsumer(object):
     def __call__(self, msg):
         pass
@@ -1357,6 +1466,122 @@ def test_delete_force(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
             cur.execute("INSERT INTO t (key) VALUES (123)")
 
 
+# Close to the disk usage limit, the safekeeper refuses new timelines, but keeps
+# accepting the WAL of the timelines whose WAL is consumed.
+def test_safekeeper_max_disk_usage(neon_env_builder: NeonEnvBuilder):
+    max_disk_usage = 24 * 1024 * 1024
+    neon_env_builder.safekeepers_max_disk_usage = max_disk_usage
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_safekeeper_max_disk_usage")
+
+    endpoint = env.endpoints.create_start("test_safekeeper_max_disk_usage")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    # Fill more than one 16MB WAL segment
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,200000), 'payload'")
+
+    sk_http = env.safekeepers[0].http_client()
+
+    def over_threshold():
+        metrics = sk_http.get_metrics_str()
+        size = re.search(r"^safekeeper_data_dir_size_bytes (\S+)$", metrics, re.MULTILINE)
+        assert size is not None
+        assert float(size.group(1)) >= max_disk_usage * 0.9
+
+    wait_until(30, 1, over_threshold)
+
+    pg_version = sk_http.timeline_status(tenant_id, timeline_id).pg_version
+    with pytest.raises(sk_http.HTTPError):
+        sk_http.timeline_create(tenant_id, TimelineId.generate(), pg_version, Lsn("0/1000000"))
+
+    # The WAL of the existing timeline is consumed, so it keeps accepting it
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(200001,201000), 'payload'")
+
+
+# Deletion with a tombstone prevents the timeline from being created again, also
+# after a safekeeper restart.
+def test_delete_force_tombstone(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_delete_force_tombstone")
+    endpoint = env.endpoints.create_start("test_delete_force_tombstone")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key)")
+    pg_version = endpoint.safe_psql("SHOW server_version_num")[0][0]
+    endpoint.stop_and_destroy()
+
+    sk = env.safekeepers[0]
+    sk_data_dir = Path(sk.data_dir())
+    sk_http = sk.http_client()
+    assert sk_http.timeline_delete_force(tenant_id, timeline_id, tombstone=True)["dir_existed"]
+    assert not (sk_data_dir / str(tenant_id) / str(timeline_id)).exists()
+    assert (sk_data_dir / "timeline_tombstones.json").exists()
+
+    for restart in [False, True]:
+        if restart:
+            sk.stop()
+            sk.start()
+        with pytest.raises(sk_http.HTTPError, match="404"):
+            sk_http.timeline_status(tenant_id, timeline_id)
+        with pytest.raises(sk_http.HTTPError):
+            sk_http.timeline_create(tenant_id, timeline_id, int(pg_version), Lsn("0/1000000"))
+        assert not (sk_data_dir / str(tenant_id) / str(timeline_id)).exists()
+
+    # Repeated deletion succeeds
+    assert not sk_http.timeline_delete_force(tenant_id, timeline_id, tombstone=True)["dir_existed"]
+
+    # Other timelines of the tenant are not affected
+    env.endpoints.create_start("main").safe_psql("CREATE TABLE t(key int primary key)")
+
+
+# Branch creation with the safekeeper timeline copied from the parent at the branch point.
+def test_copy_timeline(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+
+    timeline_id = env.neon_cli.create_branch("test_copy_timeline_parent")
+    endpoint = env.endpoints.create_start("test_copy_timeline_parent")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+    branch_lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    def all_committed():
+        for sk in env.safekeepers:
+            assert sk.http_client().timeline_status(tenant_id, timeline_id).commit_lsn >= branch_lsn
+
+    wait_until(30, 0.5, all_committed)
+
+    # More WAL on the parent, not visible on the branch
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1001,2000), 'payload'")
+
+    branch_id = env.neon_cli.create_branch(
+        "test_copy_timeline_child", "test_copy_timeline_parent", ancestor_start_lsn=branch_lsn
+    )
+    for sk in env.safekeepers:
+        sk_http = sk.http_client()
+        sk_http.copy_timeline(tenant_id, timeline_id, branch_id, branch_lsn)
+        status = sk_http.timeline_status(tenant_id, branch_id)
+        assert status.flush_lsn == branch_lsn
+        assert status.commit_lsn == branch_lsn
+        assert status.timeline_start_lsn == branch_lsn
+
+        # Only once
+        with pytest.raises(sk_http.HTTPError):
+            sk_http.copy_timeline(tenant_id, timeline_id, branch_id, branch_lsn)
+
+    # The branch accepts writes right away
+    branch_endpoint = env.endpoints.create_start("test_copy_timeline_child")
+    branch_endpoint.safe_psql("INSERT INTO t VALUES (0, 'branch')")
+    assert branch_endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 1001
+
+    # Can't copy beyond the committed WAL
+    with pytest.raises(env.safekeepers[0].http_client().HTTPError):
+        env.safekeepers[0].http_client().copy_timeline(
+            tenant_id, timeline_id, TimelineId.generate(), Lsn("FF/0")
+        )
+
+
 def test_pull_timeline(neon_env_builder: NeonEnvBuilder):
     def safekeepers_guc(env: NeonEnv, sk_names: List[int]) -> str:
         return ",".join([f"localhost:{sk.port.pg}" for sk in env.safekeepers if sk.id in sk_names])
@@ -1447,3 +1672,150 @@ def test_pull_timeline(neon_env_builder: NeonEnvBuilder):
 
     execute_payload(endpoint)
     show_statuses(env.safekeepers, tenant_id, timeline_id)
+
+
+# Without the broker, a restarted pageserver doesn't know which safekeepers to
+# stream WAL from. Check that the safekeepers push WAL to it instead, having
+# learned about the pageserver from the broker before it went down.
+def test_wal_push_without_broker(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.safekeepers_push_wal_to_pageserver = True
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_push_without_broker")
+
+    endpoint = env.endpoints.create_start("test_wal_push_without_broker")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+
+    def pageserver_announced():
+        for sk in env.safekeepers:
+            with open(os.path.join(sk.data_dir(), "safekeeper.log")) as f:
+                assert f"pageserver 1 wants WAL of {tenant_id}/{timeline_id}" in f.read()
+
+    wait_until(30, 1, pageserver_announced)
+
+    env.broker.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,10000), 'payload'")
+    flush_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+    pageserver_http = env.pageserver.http_client()
+
+    def caught_up():
+        assert last_record_lsn(pageserver_http, tenant_id, timeline_id) >= flush_lsn
+
+    wait_until(30, 1, caught_up)
+
+    # Once the broker is back, the pageserver streams WAL again
+    env.broker.try_start()
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(10001,20000), 'payload'")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 20000
+
+
+# Check that a safekeeper forwards the committed WAL to a safekeeper standing in
+# for the safekeepers of another region, which the compute doesn't know about.
+def test_wal_forward(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 2
+    neon_env_builder.safekeepers_wal_forward_to = {1: [2]}
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_forward")
+
+    endpoint = env.endpoints.create("test_wal_forward")
+    endpoint.active_safekeepers = [1]
+    endpoint.start()
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,10000), 'payload'")
+    flush_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+
+    source = env.safekeepers[0].http_client()
+    target = env.safekeepers[1].http_client()
+
+    def forwarded():
+        assert target.timeline_status(tenant_id, timeline_id).flush_lsn >= flush_lsn
+        status = source.timeline_status(tenant_id, timeline_id)
+        assert len(status.wal_forwards) == 1
+        assert Lsn(status.wal_forwards[0]["forwarded_lsn"]) >= flush_lsn
+
+    wait_until(30, 1, forwarded)
+
+    # The forwarding goes on as WAL is committed, and catches up
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(10001,20000), 'payload'")
+    flush_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+
+    def caught_up():
+        status = source.timeline_status(tenant_id, timeline_id)
+        assert status.commit_lsn >= flush_lsn
+        assert status.wal_forwards[0]["lag_bytes"] == 0
+        assert target.timeline_status(tenant_id, timeline_id).flush_lsn >= flush_lsn
+
+    wait_until(30, 1, caught_up)
+
+
+# Check that the WAL verification on startup finds and truncates a torn tail.
+def test_wal_verify_torn_tail(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.safekeepers_verify_wal_on_startup = True
+    neon_env_builder.safekeepers_truncate_torn_wal = True
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_verify_torn_tail")
+
+    endpoint = env.endpoints.create_start("test_wal_verify_torn_tail")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+    endpoint.stop()
+
+    sk = env.safekeepers[0]
+    flush_lsn = sk.http_client().timeline_status(tenant_id, timeline_id).flush_lsn
+    sk.stop()
+
+    # Imitate a write interrupted by a crash after the last record
+    tli_dir = os.path.join(sk.data_dir(), str(tenant_id), str(timeline_id))
+    partial_segments = [f for f in os.listdir(tli_dir) if f.endswith(".partial")]
+    assert len(partial_segments) == 1
+    segment_path = os.path.join(tli_dir, partial_segments[0])
+    offset = int(flush_lsn) % (16 * 1024 * 1024)
+    with open(segment_path, "r+b") as f:
+        f.seek(offset)
+        f.write(b"torn")
+
+    sk.start()
+    metrics = parse_metrics(sk.http_client().get_metrics_str())
+    labels = {"tenant_id": str(tenant_id), "timeline_id": str(timeline_id)}
+    assert metrics.query_one("safekeeper_wal_torn_tail_bytes", labels).value == 4
+    assert metrics.query_one("safekeeper_wal_verification_failed", labels).value == 0
+    with open(segment_path, "rb") as f:
+        f.seek(offset)
+        assert f.read(4) == b"\0\0\0\0"
+
+    endpoint.start()
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1001,2000), 'payload'")
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 2000
+
+
+# Check that the walproposer and the pageserver's WAL receiver keep up with
+# the safekeepers delaying, dropping and resetting their connections.
+def test_wal_service_chaos(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.num_safekeepers = 3
+    neon_env_builder.safekeepers_chaos = (
+        '{connection_delay = "100ms", drop_probability = 0.2, '
+        'reset_probability = 0.2, reset_after = "5s", fsync_delay = "5ms"}'
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*wal receiver task finished with an error.*")
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_wal_service_chaos")
+
+    endpoint = env.endpoints.create_start("test_wal_service_chaos")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    for i in range(10):
+        endpoint.safe_psql(
+            f"INSERT INTO t SELECT generate_series({i * 1000 + 1}, {(i + 1) * 1000}), 'payload'"
+        )
+        time.sleep(1)
+
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000
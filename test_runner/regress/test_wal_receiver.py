@@ -1,7 +1,9 @@
+import os
 import time
 
+import pytest
 from fixtures.log_helper import log
-from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder
+from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder, wait_for_last_flush_lsn
 from fixtures.types import Lsn, TenantId
 
 
@@ -99,6 +101,57 @@ def test_pageserver_lsn_wait_error_safekeeper_stop(neon_env_builder: NeonEnvBuil
                 ), f"Should have safekeeper {safekeeper.id} printed in walreceiver state after 2nd WAL wait timeout"
 
 
+# Checks that the wait_lsn_timeout of a tenant overrides the pageserver-wide one, and
+# that the timed out waits are counted.
+def test_tenant_wait_lsn_timeout(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    # The pageserver-wide timeout is 60s, the test would time out itself with it
+    tenant_id, timeline_id = env.neon_cli.create_tenant(conf={"wait_lsn_timeout": "1s"})
+    expected_timeout_error = f"Timed out while waiting for WAL record at LSN {future_lsn} to arrive"
+    env.pageserver.allowed_errors.append(f".*{expected_timeout_error}.*")
+
+    def timeouts() -> float:
+        return client.get_timeline_metric(
+            tenant_id, timeline_id, "pageserver_wait_lsn_timeouts_total"
+        )
+
+    assert timeouts() == 0
+    with pytest.raises(Exception, match=expected_timeout_error):
+        trigger_wait_lsn_timeout(env, tenant_id)
+    assert timeouts() >= 1
+    waits = client.get_timeline_metric(
+        tenant_id, timeline_id, "pageserver_timeline_wait_lsn_seconds_count"
+    )
+    assert waits >= 1
+
+
+# Checks that the WAL compressed by the safekeepers is ingested correctly.
+def test_walreceiver_compression(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "walreceiver_compression = true"
+    env = neon_env_builder.init_start()
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql(
+        "INSERT INTO t SELECT i, repeat('payload', 10) FROM generate_series(1, 100000) as i"
+    )
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # Read the pages back from the pageserver
+    endpoint.stop_and_destroy()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    assert endpoint.safe_psql("SELECT count(*), sum(key) FROM t") == [(100000, 5000050000)]
+
+    compressed = False
+    for sk in env.safekeepers:
+        with open(os.path.join(sk.data_dir(), "safekeeper.log")) as f:
+            compressed |= "compression Some(Zstd)" in f.read()
+    assert compressed, "no safekeeper streamed compressed WAL"
+
+
 def insert_test_elements(env: NeonEnv, tenant_id: TenantId, start: int, count: int):
     first_element_id = start
     last_element_id = first_element_id + count